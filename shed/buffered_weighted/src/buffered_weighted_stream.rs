@@ -11,6 +11,7 @@ use std::fmt;
 use std::pin::Pin;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Instant;
 
 use futures_util::stream::Fuse;
 use futures_util::stream::FuturesOrdered;
@@ -22,6 +23,9 @@ use pin_project::pin_project;
 use crate::global_weight::GlobalWeight;
 use crate::memory_bound::MemoryBound;
 use crate::peekable_fused::PeekableFused;
+use crate::weight_estimator::CompletionStats;
+
+type CompletionCallback = Box<dyn FnMut(&CompletionStats) + Send>;
 
 /// Stream for the [`buffered_weighted`](crate::StreamExt::buffered_weighted) method.
 #[must_use = "streams do nothing unless polled"]
@@ -36,6 +40,7 @@ where
     in_progress_queue: FuturesOrdered<FutureWithWeight<<St::Item as WeightedFuture>::Future>>,
     global_weight: GlobalWeight,
     bound: MemoryBound,
+    on_completion: Option<CompletionCallback>,
 }
 
 impl<St> fmt::Debug for BufferedWeighted<St>
@@ -49,6 +54,7 @@ where
             .field("in_progress_queue", &self.in_progress_queue)
             .field("global_weight", &self.global_weight)
             .field("bound", &self.bound)
+            .field("on_completion", &self.on_completion.is_some())
             .finish()
     }
 }
@@ -64,9 +70,21 @@ where
             in_progress_queue: FuturesOrdered::new(),
             global_weight: GlobalWeight::new(max_weight),
             bound: MemoryBound::new(bound),
+            on_completion: None,
         }
     }
 
+    /// Call `callback` with the [`CompletionStats`] of every future once its
+    /// output is returned by this stream, e.g. to feed a
+    /// [`WeightEstimator`](crate::WeightEstimator).
+    pub fn with_completion_callback<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&CompletionStats) + Send + 'static,
+    {
+        self.on_completion = Some(Box::new(callback));
+        self
+    }
+
     /// Returns the maximum weight of futures allowed to be run by this adaptor.
     pub fn max_weight(&self) -> usize {
         self.global_weight.max()
@@ -105,9 +123,29 @@ where
     ///
     /// Note that this may discard intermediate state of this combinator, so
     /// care should be taken to avoid losing resources when this is called.
+    /// See [`into_inner_with_pending`](Self::into_inner_with_pending) to keep
+    /// the futures that were not started.
     pub fn into_inner(self) -> St {
         self.stream.into_inner().into_inner()
     }
+
+    /// Returns the weighted futures pulled from the underlying stream but not
+    /// started yet, e.g. because the maximum weight was reached, in the order
+    /// they will be started. Each item holds the weight of its future.
+    ///
+    /// This lets shutdown logic report or persist the work that was not
+    /// started, along with the items still in the underlying stream.
+    pub fn pending(&self) -> impl Iterator<Item = &St::Item> + '_ {
+        self.stream.peeked().into_iter()
+    }
+
+    /// Consumes this combinator, returning the weighted futures that were
+    /// not started yet, see [`pending`](Self::pending), and the underlying
+    /// sink or stream. The futures that were started are dropped.
+    pub fn into_inner_with_pending(self) -> (Vec<St::Item>, St) {
+        let (pending, stream) = self.stream.into_parts();
+        (pending.into_iter().collect(), stream.into_inner())
+    }
 }
 
 impl<St> Stream for BufferedWeighted<St>
@@ -139,15 +177,22 @@ where
                 _ => unreachable!("we just peeked at this item"),
             };
             this.global_weight.add_weight(weight);
+            let measurement = this.on_completion.as_ref().map(|_| Measurement {
+                started: Instant::now(),
+                start_rss: this.bound.rss_bytes(),
+            });
             this.in_progress_queue
-                .push_back(FutureWithWeight::new(weight, future));
+                .push_back(FutureWithWeight::new(weight, future, measurement));
         }
 
         // Attempt to pull the next value from the in_progress_queue.
         match this.in_progress_queue.poll_next_unpin(cx) {
             Poll::Pending => return Poll::Pending,
-            Poll::Ready(Some((weight, output))) => {
+            Poll::Ready(Some((weight, stats, output))) => {
                 this.global_weight.sub_weight(weight);
+                if let (Some(on_completion), Some(stats)) = (this.on_completion.as_mut(), stats) {
+                    on_completion(&stats);
+                }
                 return Poll::Ready(Some(output));
             }
             Poll::Ready(None) => {}
@@ -216,11 +261,23 @@ struct FutureWithWeight<Fut> {
     #[pin]
     future: Fut,
     weight: usize,
+    measurement: Option<Measurement>,
+}
+
+/// State captured when a future is scheduled, to compute its
+/// [`CompletionStats`] once it completes.
+struct Measurement {
+    started: Instant,
+    start_rss: Option<u64>,
 }
 
 impl<Fut> FutureWithWeight<Fut> {
-    pub fn new(weight: usize, future: Fut) -> Self {
-        Self { future, weight }
+    pub fn new(weight: usize, future: Fut, measurement: Option<Measurement>) -> Self {
+        Self {
+            future,
+            weight,
+            measurement,
+        }
     }
 }
 
@@ -228,13 +285,25 @@ impl<Fut> Future for FutureWithWeight<Fut>
 where
     Fut: Future,
 {
-    type Output = (usize, Fut::Output);
+    type Output = (usize, Option<CompletionStats>, Fut::Output);
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
 
         match this.future.poll(cx) {
             Poll::Pending => Poll::Pending,
-            Poll::Ready(output) => Poll::Ready((*this.weight, output)),
+            Poll::Ready(output) => {
+                // Measure on completion rather than when the output is returned,
+                // as ordering may delay the latter.
+                let stats = this.measurement.take().map(|measurement| CompletionStats {
+                    weight: *this.weight,
+                    duration: measurement.started.elapsed(),
+                    memory_delta: measurement.start_rss.and_then(|start_rss| {
+                        let rss = MemoryBound::current_rss_bytes()?;
+                        Some(rss as i64 - start_rss as i64)
+                    }),
+                });
+                Poll::Ready((*this.weight, stats, output))
+            }
         }
     }
 }
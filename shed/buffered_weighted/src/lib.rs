@@ -88,6 +88,37 @@
 //! assert_eq!(buffered.next().await, None);
 //! # Ok::<(), &'static str>(()) }).unwrap();
 //! ```
+//!
+//! ## 2. Tuning weights
+//!
+//! Weights are often guesses. [`BufferedWeighted::with_completion_callback`] reports the
+//! [`CompletionStats`] of every future, i.e. its declared weight, how long it ran and, for
+//! [`buffered_weighted_bounded`](StreamExt::buffered_weighted_bounded), how much the RSS of the
+//! process changed while it ran. These can be fed into a [`WeightEstimator`] to pick the weights
+//! of subsequent futures of the same class.
+//!
+//! ```rust
+//! # futures::executor::block_on(async {
+//! use std::sync::Arc;
+//!
+//! use buffered_weighted::StreamExt as _;
+//! use buffered_weighted::WeightEstimator;
+//! use futures::future;
+//! use futures::stream;
+//! use futures::StreamExt as _;
+//!
+//! let estimator = Arc::new(WeightEstimator::new(10));
+//! let weight = estimator.estimate(&"fetch");
+//! let buffered = stream::iter(vec![(weight, future::ready(1)), (weight, future::ready(2))])
+//!     .buffered_weighted(20)
+//!     .with_completion_callback({
+//!         let estimator = estimator.clone();
+//!         move |stats| estimator.record("fetch", stats)
+//!     });
+//! assert_eq!(buffered.collect::<Vec<_>>().await, vec![1, 2]);
+//! assert_eq!(estimator.estimate(&"fetch"), 10);
+//! # });
+//! ```
 
 mod buffered_weighted_stream;
 mod global_weight;
@@ -95,9 +126,12 @@ mod memory_bound;
 mod peekable_fused;
 #[cfg(test)]
 mod tests;
+mod weight_estimator;
 
 pub use crate::buffered_weighted_stream::BufferedWeighted;
 pub use crate::memory_bound::MemoryBound;
+pub use crate::weight_estimator::CompletionStats;
+pub use crate::weight_estimator::WeightEstimator;
 
 /// Traits to aid in type definitions.
 ///
@@ -44,4 +44,23 @@ impl MemoryBound {
         // Memory bound not supported on this platform.
         true
     }
+
+    /// Returns the current RSS bytes of the process if this memory bound is
+    /// enforced, so that memory use is only measured for bounded streams.
+    pub(crate) fn rss_bytes(&self) -> Option<u64> {
+        self.bound.and_then(|_| Self::current_rss_bytes())
+    }
+
+    /// Returns the current RSS bytes of the process.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn current_rss_bytes() -> Option<u64> {
+        let stats = Process::myself().ok()?.stat().ok()?;
+        Some(stats.rss * procfs::page_size())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn current_rss_bytes() -> Option<u64> {
+        // Memory bound not supported on this platform.
+        None
+    }
 }
@@ -83,6 +83,17 @@ impl<St: FusedStream> PeekableFused<St> {
         self.stream
     }
 
+    /// Consumes this combinator, returning the peeked item, if any, and the
+    /// underlying sink or stream.
+    pub fn into_parts(self) -> (Option<St::Item>, St) {
+        (self.peeked, self.stream)
+    }
+
+    /// Returns the item that was peeked but not returned yet, if any.
+    pub fn peeked(&self) -> Option<&St::Item> {
+        self.peeked.as_ref()
+    }
+
     /// Returns whether the combinator is done.
     pub fn is_done(&self) -> bool {
         self.stream.is_terminated()
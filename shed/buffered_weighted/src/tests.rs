@@ -8,8 +8,11 @@
  */
 
 use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
 
+use futures::future;
 use futures::future::BoxFuture;
 use futures::stream;
 use futures::Future;
@@ -22,7 +25,9 @@ use tokio_stream::wrappers::UnboundedReceiverStream;
 
 use crate::traits::WeightedFuture;
 use crate::BufferedWeighted;
+use crate::CompletionStats;
 use crate::StreamExt as _;
+use crate::WeightEstimator;
 
 #[derive(Clone, Debug, Arbitrary)]
 struct TestState {
@@ -160,6 +165,110 @@ fn test_examples() {
     test_future_queue_impl::<()>(state);
 }
 
+#[tokio::test]
+async fn test_completion_callback() {
+    let completed = Arc::new(Mutex::new(Vec::new()));
+    let futures = vec![(3, 30), (1, 10), (2, 20)]
+        .into_iter()
+        .map(|(weight, delay)| {
+            (weight, async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                weight
+            })
+        });
+
+    let outputs = stream::iter(futures)
+        .buffered_weighted(10)
+        .with_completion_callback({
+            let completed = completed.clone();
+            move |stats| completed.lock().unwrap().push(*stats)
+        })
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(outputs, vec![3, 1, 2]);
+
+    // Futures are reported in the order their outputs are returned, and
+    // memory is not measured for unbounded streams.
+    let completed = completed.lock().unwrap();
+    assert_eq!(
+        completed
+            .iter()
+            .map(|stats| stats.weight)
+            .collect::<Vec<_>>(),
+        vec![3, 1, 2]
+    );
+    for stats in completed.iter() {
+        assert!(stats.duration >= Duration::from_millis(stats.weight as u64 * 10));
+        assert_eq!(stats.memory_delta, None);
+    }
+}
+
+#[test]
+fn test_pending() {
+    let futures = vec![3, 3, 2]
+        .into_iter()
+        .map(|weight| (weight, future::pending::<()>()));
+    let mut buffered = stream::iter(futures).buffered_weighted(4);
+    assert_eq!(buffered.pending().count(), 0);
+
+    // The first future is started, and the second one is pulled from the
+    // stream but doesn't fit.
+    assert_eq!(buffered.next().now_or_never(), None);
+    assert_eq!(buffered.current_weight(), 3);
+    assert_eq!(
+        buffered
+            .pending()
+            .map(|(weight, _)| *weight)
+            .collect::<Vec<_>>(),
+        vec![3]
+    );
+
+    let (pending, stream) = buffered.into_inner_with_pending();
+    assert_eq!(
+        pending
+            .iter()
+            .map(|(weight, _)| *weight)
+            .collect::<Vec<_>>(),
+        vec![3]
+    );
+    assert_eq!(stream.count().now_or_never(), Some(1));
+}
+
+#[test]
+fn test_weight_estimator() {
+    let estimator = WeightEstimator::new(5).with_smoothing(0.5);
+    assert_eq!(estimator.estimate(&"a"), 5);
+    assert_eq!(estimator.estimate_duration(&"a"), None);
+
+    let stats = |weight, memory_delta| CompletionStats {
+        weight,
+        duration: Duration::from_secs(weight as u64),
+        memory_delta,
+    };
+
+    estimator.record("a", &stats(10, None));
+    assert_eq!(estimator.estimate(&"a"), 10);
+    assert_eq!(
+        estimator.estimate_duration(&"a"),
+        Some(Duration::from_secs(10))
+    );
+
+    estimator.record("a", &stats(10, Some(30)));
+    assert_eq!(estimator.estimate(&"a"), 20);
+    assert_eq!(
+        estimator.estimate_duration(&"a"),
+        Some(Duration::from_secs(10))
+    );
+
+    // Memory being freed doesn't make for a negative weight.
+    estimator.record("a", &stats(10, Some(-100)));
+    assert_eq!(estimator.estimate(&"a"), 10);
+
+    estimator.record("b", &stats(2, None));
+    assert_eq!(estimator.estimate(&"b"), 2);
+    assert_eq!(estimator.estimate(&"a"), 10);
+}
+
 proptest! {
     #[test]
     fn proptest_future_queue(state: TestState) {
@@ -0,0 +1,114 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Measurements taken for a single future run by
+/// [`BufferedWeighted`](crate::BufferedWeighted), passed to the callback set with
+/// [`with_completion_callback`](crate::BufferedWeighted::with_completion_callback).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompletionStats {
+    /// The weight the future was declared with.
+    pub weight: usize,
+    /// Time between the future being scheduled and it completing.
+    pub duration: Duration,
+    /// Change in RSS bytes of the process between the future being scheduled
+    /// and it completing. Only measured by
+    /// [`buffered_weighted_bounded`](crate::StreamExt::buffered_weighted_bounded) on
+    /// platforms where memory bounds are supported. Other futures running
+    /// concurrently contribute to this delta too.
+    pub memory_delta: Option<i64>,
+}
+
+impl CompletionStats {
+    /// The weight the future turned out to have: its memory delta if one was
+    /// measured, or otherwise its declared weight.
+    pub fn observed_weight(&self) -> usize {
+        match self.memory_delta {
+            Some(delta) => usize::try_from(delta.max(0)).unwrap_or(usize::MAX),
+            None => self.weight,
+        }
+    }
+}
+
+/// Estimates the weight of futures per class of work from the
+/// [`CompletionStats`] of previously completed futures of the same class,
+/// so that callers can tune the weights of subsequent items.
+///
+/// Estimates are exponentially weighted moving averages of the observed
+/// weights, so recent completions count more than older ones.
+#[derive(Debug)]
+pub struct WeightEstimator<K> {
+    default_weight: usize,
+    smoothing: f64,
+    classes: Mutex<HashMap<K, ClassEstimate>>,
+}
+
+#[derive(Debug)]
+struct ClassEstimate {
+    weight: f64,
+    duration: Duration,
+}
+
+impl<K> WeightEstimator<K>
+where
+    K: Eq + Hash,
+{
+    /// Create an estimator returning `default_weight` for classes without any
+    /// recorded completion.
+    pub fn new(default_weight: usize) -> Self {
+        Self {
+            default_weight,
+            smoothing: 0.2,
+            classes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set how much each new completion moves the estimate towards its
+    /// observed weight, between 0 (exclusive) and 1. Defaults to 0.2.
+    pub fn with_smoothing(mut self, smoothing: f64) -> Self {
+        self.smoothing = smoothing.clamp(f64::EPSILON, 1.0);
+        self
+    }
+
+    /// Record the completion of a future of the given class.
+    pub fn record(&self, class: K, stats: &CompletionStats) {
+        let observed = stats.observed_weight() as f64;
+        let mut classes = self.classes.lock().expect("lock poisoned");
+        classes
+            .entry(class)
+            .and_modify(|estimate| {
+                estimate.weight += self.smoothing * (observed - estimate.weight);
+                estimate.duration = estimate.duration.mul_f64(1.0 - self.smoothing)
+                    + stats.duration.mul_f64(self.smoothing);
+            })
+            .or_insert(ClassEstimate {
+                weight: observed,
+                duration: stats.duration,
+            });
+    }
+
+    /// The estimated weight of the next future of the given class.
+    pub fn estimate(&self, class: &K) -> usize {
+        let classes = self.classes.lock().expect("lock poisoned");
+        classes.get(class).map_or(self.default_weight, |estimate| {
+            estimate.weight.round() as usize
+        })
+    }
+
+    /// The estimated duration of the next future of the given class, if any
+    /// completion of that class was recorded.
+    pub fn estimate_duration(&self, class: &K) -> Option<Duration> {
+        let classes = self.classes.lock().expect("lock poisoned");
+        classes.get(class).map(|estimate| estimate.duration)
+    }
+}
@@ -0,0 +1,186 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::ptr;
+use std::sync::atomic::compiler_fence;
+use std::sync::atomic::Ordering;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use bytes::Bytes;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::Entity;
+
+/// Provider of the keys used to decrypt the encrypted configs returned by a
+/// `Source`, e.g. a client of a local KMS agent, registered on a
+/// `ConfigStore` with `ConfigStore::register_decryptor`.
+///
+/// Each encrypted config names the key it was encrypted with, so keys can be
+/// rotated by encrypting configs with a new key while the decryptor still
+/// knows the old keys, until every config has been re-encrypted.
+pub trait ConfigDecryptor: Send + Sync {
+    /// Decrypt the `ciphertext` of a config encrypted with the key `key_id`,
+    /// returning the plaintext config.
+    ///
+    /// The returned buffer is zeroed once the config has been deserialized.
+    /// Only that buffer is, not the copies made while deserializing the
+    /// config, e.g. by versioned or raw config handles, nor the deserialized
+    /// config itself.
+    fn decrypt(&self, key_id: &str, ciphertext: &str) -> Result<Vec<u8>>;
+}
+
+impl<F> ConfigDecryptor for F
+where
+    F: Fn(&str, &str) -> Result<Vec<u8>> + Send + Sync,
+{
+    fn decrypt(&self, key_id: &str, ciphertext: &str) -> Result<Vec<u8>> {
+        self(key_id, ciphertext)
+    }
+}
+
+/// Encrypted config, as returned by a `Source` in the contents of an
+/// `Entity` serialized as `{"encrypted_config": {"key_id": ..., "ciphertext":
+/// ...}}`. Contents in any other format are not encrypted.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptedConfig {
+    /// Identifier of the key the config is encrypted with.
+    pub key_id: String,
+    /// Encrypted config, in an encoding understood by the decryptor, e.g.
+    /// base64.
+    pub ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Envelope {
+    encrypted_config: EncryptedConfig,
+}
+
+impl EncryptedConfig {
+    /// Parse the contents of an `Entity`, returning `None` if they aren't an
+    /// encrypted config.
+    pub fn parse(contents: &[u8]) -> Option<Self> {
+        serde_json::from_slice::<Envelope>(contents)
+            .ok()
+            .map(|envelope| envelope.encrypted_config)
+    }
+
+    /// Serialize the encrypted config, as the contents of an `Entity`.
+    pub fn to_bytes(&self) -> Result<Bytes> {
+        let envelope = Envelope {
+            encrypted_config: self.clone(),
+        };
+        let bytes =
+            serde_json::to_vec(&envelope).context("Failed to serialize encrypted config")?;
+        Ok(bytes.into())
+    }
+}
+
+/// Buffer holding a decrypted config, which is zeroed when dropped. Copies
+/// of its contents are not.
+struct Plaintext(Vec<u8>);
+
+impl AsRef<[u8]> for Plaintext {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for Plaintext {
+    fn drop(&mut self) {
+        for byte in self.0.iter_mut() {
+            // SAFETY: the pointer comes from a mutable reference. The write is
+            // volatile so that it isn't optimized away as a dead store.
+            unsafe { ptr::write_volatile(byte, 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+/// Decrypt the contents of the entity at `path` if they are an encrypted
+/// config. The buffer of the decrypted contents is zeroed once the last
+/// reference to it is dropped, i.e. after they have been deserialized.
+pub(crate) fn decrypt_entity(
+    decryptor: Option<&dyn ConfigDecryptor>,
+    path: &str,
+    entity: Entity,
+) -> Result<Entity> {
+    let encrypted = match entity.contents.as_deref().and_then(EncryptedConfig::parse) {
+        Some(encrypted) => encrypted,
+        None => return Ok(entity),
+    };
+    let decryptor = decryptor
+        .ok_or_else(|| anyhow!("No decryptor registered for encrypted config at {}", path))?;
+    let plaintext = Plaintext(
+        decryptor
+            .decrypt(&encrypted.key_id, &encrypted.ciphertext)
+            .with_context(|| {
+                format!(
+                    "Failed to decrypt config at {} with key {}",
+                    path, encrypted.key_id
+                )
+            })?,
+    );
+    Ok(Entity {
+        contents: Some(Bytes::from_owner(plaintext)),
+        ..entity
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ModificationTime;
+
+    fn entity(contents: Bytes) -> Entity {
+        Entity {
+            contents: Some(contents),
+            mod_time: ModificationTime::Unset,
+            version: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_decrypt_entity() -> Result<()> {
+        let decryptor = |key_id: &str, ciphertext: &str| -> Result<Vec<u8>> {
+            match key_id {
+                "old" | "new" => Ok(ciphertext.chars().rev().collect::<String>().into_bytes()),
+                _ => Err(anyhow!("unknown key")),
+            }
+        };
+        let encrypted = EncryptedConfig {
+            key_id: "new".to_owned(),
+            ciphertext: "}1 :\"eulav\"{".to_owned(),
+        };
+        assert_eq!(
+            EncryptedConfig::parse(&encrypted.to_bytes()?),
+            Some(encrypted.clone())
+        );
+
+        let decrypted = decrypt_entity(Some(&decryptor), "path", entity(encrypted.to_bytes()?))?;
+        assert_eq!(decrypted.contents.unwrap(), r#"{"value": 1}"#);
+
+        let encrypted = EncryptedConfig {
+            key_id: "revoked".to_owned(),
+            ..encrypted
+        };
+        assert!(decrypt_entity(Some(&decryptor), "path", entity(encrypted.to_bytes()?)).is_err());
+        assert!(decrypt_entity(None, "path", entity(encrypted.to_bytes()?)).is_err());
+
+        // Configs that aren't encrypted are returned as they are.
+        let plain = Bytes::from_static(br#"{"encrypted_config": 1, "value": 1}"#);
+        let decrypted = decrypt_entity(None, "path", entity(plain.clone()))?;
+        assert_eq!(decrypted.contents.unwrap(), plain);
+        Ok(())
+    }
+}
@@ -13,6 +13,7 @@
 //! set of configs identitied by their paths that are periodically refreshed.
 //! The configs are provided by the implementors of the Source trait.
 
+mod encrypted;
 #[cfg(fbcode_build)]
 mod facebook;
 mod file_source;
@@ -25,16 +26,22 @@ mod store;
 pub mod test_source;
 #[cfg(test)]
 mod tests;
+mod versioned;
 
 use std::fmt::Debug;
 
 use anyhow::Result;
 use bytes::Bytes;
 use chrono::NaiveDateTime;
+pub use encrypted::ConfigDecryptor;
+pub use encrypted::EncryptedConfig;
 pub use handle::ConfigHandle;
 pub use handle::ConfigUpdateWatcher;
 pub use store::ConfigStore;
 pub use test_source::TestSource;
+pub use versioned::migrate_config;
+pub use versioned::Migration;
+pub use versioned::VersionedConfig;
 
 /// Trait to be implemented by sources of configuration that the `ConfigStore`
 /// will use
@@ -15,6 +15,7 @@ use std::str;
 use std::sync::Arc;
 use std::sync::Condvar;
 use std::sync::Mutex;
+use std::sync::RwLock;
 use std::sync::Weak;
 use std::thread;
 use std::time::Duration;
@@ -28,10 +29,15 @@ use slog::info;
 use slog::warn;
 use slog::Logger;
 
+use crate::encrypted::decrypt_entity;
+use crate::encrypted::ConfigDecryptor;
 use crate::file_source::FileSource;
 use crate::handle::ConfigHandle;
 use crate::refreshable_entities::Refreshable;
 use crate::refreshable_entities::RegisteredConfigEntity;
+use crate::versioned::migrate_config;
+use crate::versioned::VersionedConfig;
+use crate::Entity;
 use crate::Source;
 
 /// A wrapper around the configerator APIs to provide an easily mocked way of reading JSON configs
@@ -42,6 +48,7 @@ pub struct ConfigStore {
     clients: Arc<Mutex<HashMap<String, ClientList>>>,
     kick: Arc<Condvar>,
     logger: Option<Logger>,
+    decryptor: Arc<RwLock<Option<Arc<dyn ConfigDecryptor>>>>,
 }
 
 type ClientList = Vec<Weak<dyn Refreshable + Sync + Send>>;
@@ -65,6 +72,7 @@ impl ConfigStore {
             clients: Arc::new(Mutex::new(HashMap::new())),
             kick: Arc::new(Condvar::new()),
             logger: logger.into_option_logger(),
+            decryptor: Arc::new(RwLock::new(None)),
         };
 
         if let Some(poll_interval) = poll_interval.into() {
@@ -132,6 +140,48 @@ impl ConfigStore {
         self.get_config_handle_with_deserializer(path, deserialize_thrift_simple_json)
     }
 
+    /// Like `get_config_handle_DEPRECATED`, but configs written in an older
+    /// format version are migrated to `T::VERSION` before being deserialized.
+    /// See `VersionedConfig` for how versions and migrations are declared.
+    #[allow(non_snake_case)]
+    pub fn get_versioned_config_handle_DEPRECATED<T>(&self, path: String) -> Result<ConfigHandle<T>>
+    where
+        T: Send + Sync + DeserializeOwned + VersionedConfig + 'static,
+    {
+        fn deserialize_versioned_json<T>(s: Bytes) -> Result<T>
+        where
+            T: DeserializeOwned + VersionedConfig,
+        {
+            let v = migrate_config::<T>(serde_json::from_slice(&s)?)?;
+            Ok(serde_json::from_value(v)?)
+        }
+        self.get_config_handle_with_deserializer(path, deserialize_versioned_json)
+    }
+
+    /// Like `get_config_handle`, but configs written in an older format
+    /// version are migrated to `T::VERSION` before being deserialized.
+    /// See `VersionedConfig` for how versions and migrations are declared.
+    pub fn get_versioned_config_handle<T>(&self, path: String) -> Result<ConfigHandle<T>>
+    where
+        for<'a> T: Send
+            + Sync
+            + Deserialize<SimpleJsonProtocolDeserializer<Cursor<&'a [u8]>>>
+            + VersionedConfig
+            + 'static,
+    {
+        fn deserialize_versioned_thrift_simple_json<T>(s: Bytes) -> Result<T>
+        where
+            for<'a> T:
+                Deserialize<SimpleJsonProtocolDeserializer<Cursor<&'a [u8]>>> + VersionedConfig,
+        {
+            let v = migrate_config::<T>(serde_json::from_slice(&s)?)?;
+            let s = serde_json::to_vec(&v)?;
+            let v = fbthrift::simplejson_protocol::deserialize(s.as_slice())?;
+            Ok(v)
+        }
+        self.get_config_handle_with_deserializer(path, deserialize_versioned_thrift_simple_json)
+    }
+
     /// Fetch a self-updating config handle for the config at `path`, as a raw, non-deserialized
     /// string. This is usually not what you want if you need to use the config (since you won't
     /// get the benefits of a cached deserialization), so prefer using `get_config_handle`. That
@@ -145,6 +195,15 @@ impl ConfigStore {
         self.get_config_handle_with_deserializer(path, deserialize_raw)
     }
 
+    /// Register the provider used to decrypt the encrypted configs returned
+    /// by the source, see `EncryptedConfig`, replacing any previously
+    /// registered one. Configs are decrypted before being deserialized, and
+    /// the buffer returned by the decryptor is zeroed afterwards, see
+    /// `ConfigDecryptor::decrypt` for the copies that aren't.
+    pub fn register_decryptor(&self, decryptor: Arc<dyn ConfigDecryptor>) {
+        *self.decryptor.write().expect("lock poisoned") = Some(decryptor);
+    }
+
     /// By default configs are updated once in `poll_interval`. Call this to force update them.
     /// Meant to be used in tests
     pub fn force_update_configs(&self) {
@@ -160,7 +219,7 @@ impl ConfigStore {
         T: Send + Sync + 'static,
     {
         let entity = {
-            let entity = self.source.config_for_path(&path)?;
+            let entity = self.config_for_path(&path)?;
             Arc::new(RegisteredConfigEntity::new(
                 path.clone(),
                 entity,
@@ -178,9 +237,15 @@ impl ConfigStore {
         Ok(ConfigHandle::from_registered(entity))
     }
 
+    /// Get the config at `path` from the source, decrypting it if needed.
+    fn config_for_path(&self, path: &str) -> Result<Entity> {
+        let entity = self.source.config_for_path(path)?;
+        let decryptor = self.decryptor.read().expect("lock poisoned").clone();
+        decrypt_entity(decryptor.as_deref(), path, entity)
+    }
+
     fn refresh_client(&self, client: Arc<dyn Refreshable + Sync + Send>) {
         let res = self
-            .source
             .config_for_path(client.get_path())
             .and_then(|entity| client.refresh(entity));
         if let Some(ref logger) = self.logger {
@@ -13,14 +13,20 @@ use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
+use anyhow::anyhow;
 use anyhow::Result;
+use serde_json::json;
+use serde_json::Value;
 use serde_derive::Deserialize;
 use tokio::time::timeout;
 
 use crate::ConfigHandle;
 use crate::ConfigStore;
+use crate::EncryptedConfig;
 use crate::ModificationTime;
+use crate::Migration;
 use crate::TestSource;
+use crate::VersionedConfig;
 
 const SLEEP_TIME_MS: u64 = 50;
 
@@ -339,3 +345,131 @@ fn test_config_update_watcher_for_static_configs() {
     // is not supported and results in an error.
     assert!(result.watcher().is_err());
 }
+
+#[derive(Debug, Deserialize, Eq, PartialEq)]
+struct VersionedTestConfig {
+    version: u64,
+    values: Vec<i64>,
+}
+
+impl VersionedConfig for VersionedTestConfig {
+    const VERSION: u64 = 1;
+    const MIGRATIONS: &'static [(u64, Migration)] = &[(0, migrate_single_value)];
+}
+
+fn migrate_single_value(mut config: Value) -> Result<Value> {
+    let value = config["value"].take();
+    config["values"] = json!([value]);
+    Ok(config)
+}
+
+#[test]
+fn test_versioned_config_handle() {
+    let test_source = Arc::new(TestSource::new());
+    test_source.insert_config(
+        "versioned",
+        r#"{ "value": 1 }"#,
+        ModificationTime::UnixTimestamp(1),
+    );
+
+    let store = ConfigStore::new(test_source.clone(), None, None);
+    let handle = store
+        .get_versioned_config_handle_DEPRECATED::<VersionedTestConfig>("versioned".to_owned())
+        .expect("Failed to get handle");
+    assert_eq!(
+        *handle.get(),
+        VersionedTestConfig {
+            version: 1,
+            values: vec![1],
+        }
+    );
+
+    // A config written in a newer format than the binary understands is
+    // rejected and the last good config is kept.
+    test_source.insert_config(
+        "versioned",
+        r#"{ "version": 2, "values": [2] }"#,
+        ModificationTime::UnixTimestamp(2),
+    );
+    test_source.insert_to_refresh("versioned".to_owned());
+    store.force_update_configs();
+    assert_eq!(
+        *handle.get(),
+        VersionedTestConfig {
+            version: 1,
+            values: vec![1],
+        }
+    );
+
+    test_source.insert_config(
+        "versioned",
+        r#"{ "version": 1, "values": [3, 4] }"#,
+        ModificationTime::UnixTimestamp(3),
+    );
+    test_source.insert_to_refresh("versioned".to_owned());
+    store.force_update_configs();
+    assert_eq!(
+        *handle.get(),
+        VersionedTestConfig {
+            version: 1,
+            values: vec![3, 4],
+        }
+    );
+}
+
+/// Decryptor "encrypting" with the key `k1` by reversing the config, and with
+/// `k2` by reversing and uppercasing it.
+fn test_decryptor(key_id: &str, ciphertext: &str) -> Result<Vec<u8>> {
+    let reversed = ciphertext.chars().rev().collect::<String>();
+    match key_id {
+        "k1" => Ok(reversed.into_bytes()),
+        "k2" => Ok(reversed.to_lowercase().into_bytes()),
+        _ => Err(anyhow!("unknown key {}", key_id)),
+    }
+}
+
+fn insert_encrypted_config(
+    test_source: &TestSource,
+    path: &str,
+    key_id: &str,
+    ciphertext: &str,
+    mod_time: u64,
+) {
+    let encrypted = EncryptedConfig {
+        key_id: key_id.to_owned(),
+        ciphertext: ciphertext.to_owned(),
+    };
+    test_source.insert_config(
+        path,
+        std::str::from_utf8(&encrypted.to_bytes().unwrap()).unwrap(),
+        ModificationTime::UnixTimestamp(mod_time),
+    );
+    test_source.insert_to_refresh(path.to_owned());
+}
+
+#[test]
+fn test_encrypted_config_handle() {
+    let test_source = Arc::new(TestSource::new());
+    insert_encrypted_config(&test_source, "secret", "k1", r#"}1 :"eulav"{"#, 1);
+
+    let store = ConfigStore::new(test_source.clone(), None, None);
+    assert!(
+        get_test_handle(&store, "secret").is_err(),
+        "encrypted configs need a decryptor"
+    );
+
+    store.register_decryptor(Arc::new(test_decryptor));
+    let handle = get_test_handle(&store, "secret").expect("Failed to get handle");
+    assert_eq!(*handle.get(), TestConfig { value: 1 });
+
+    // The config is re-encrypted with a rotated key.
+    insert_encrypted_config(&test_source, "secret", "k2", r#"}2 :"EULAV"{"#, 2);
+    store.force_update_configs();
+    assert_eq!(*handle.get(), TestConfig { value: 2 });
+
+    // A config that can't be decrypted is rejected and the last good config
+    // is kept.
+    insert_encrypted_config(&test_source, "secret", "k3", r#"}3 :"eulav"{"#, 3);
+    store.force_update_configs();
+    assert_eq!(*handle.get(), TestConfig { value: 2 });
+}
@@ -0,0 +1,119 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde_json::Value;
+
+/// A function migrating the JSON representation of a config from one version
+/// of its format to the next one.
+pub type Migration = fn(Value) -> Result<Value>;
+
+/// Trait to be implemented by config structs whose format is versioned. When
+/// such a config is parsed, any older version found in the source is migrated
+/// to `VERSION` before being deserialized, so config format changes can be
+/// rolled out independently from the binaries that read them.
+pub trait VersionedConfig {
+    /// Version of the config format this struct deserializes from.
+    const VERSION: u64;
+
+    /// Name of the top-level field holding the version of the config. Configs
+    /// without this field are considered to be at version 0.
+    const VERSION_FIELD: &'static str = "version";
+
+    /// Registered migrations, as pairs of the version migrated from and the
+    /// function migrating it to the next version.
+    const MIGRATIONS: &'static [(u64, Migration)] = &[];
+}
+
+/// Migrate the JSON representation of a config to `T::VERSION`, applying
+/// all registered migrations in order. Fails if the config is newer than
+/// `T::VERSION` or if a migration is missing.
+pub fn migrate_config<T: VersionedConfig>(mut value: Value) -> Result<Value> {
+    let mut version = match value.get(T::VERSION_FIELD) {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| anyhow!("Invalid config version {}", version))?,
+    };
+
+    if version > T::VERSION {
+        bail!(
+            "Config version {} is newer than the supported version {}",
+            version,
+            T::VERSION
+        );
+    }
+
+    while version < T::VERSION {
+        let migration = T::MIGRATIONS
+            .iter()
+            .find_map(|(from, migration)| (*from == version).then_some(migration))
+            .ok_or_else(|| anyhow!("No migration registered from config version {}", version))?;
+        value = migration(value)
+            .with_context(|| format!("While migrating config from version {}", version))?;
+        version += 1;
+
+        match value.as_object_mut() {
+            Some(object) => {
+                object.insert(T::VERSION_FIELD.to_owned(), version.into());
+            }
+            None => bail!("Config version {} is not a JSON object", version),
+        }
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    struct TestConfig;
+
+    impl VersionedConfig for TestConfig {
+        const VERSION: u64 = 2;
+        const MIGRATIONS: &'static [(u64, Migration)] = &[(0, v0_to_v1), (1, v1_to_v2)];
+    }
+
+    fn v0_to_v1(mut value: Value) -> Result<Value> {
+        let object = value.as_object_mut().context("Not an object")?;
+        let old = object.remove("value").unwrap_or_default();
+        object.insert("values".to_owned(), json!([old]));
+        Ok(value)
+    }
+
+    fn v1_to_v2(mut value: Value) -> Result<Value> {
+        value["enabled"] = json!(true);
+        Ok(value)
+    }
+
+    #[test]
+    fn test_migrate_config() -> Result<()> {
+        let expected = json!({ "version": 2, "values": [1], "enabled": true });
+
+        assert_eq!(
+            migrate_config::<TestConfig>(json!({ "value": 1 }))?,
+            expected
+        );
+        assert_eq!(
+            migrate_config::<TestConfig>(json!({ "version": 1, "values": [1] }))?,
+            expected
+        );
+        assert_eq!(migrate_config::<TestConfig>(expected.clone())?, expected);
+        assert!(migrate_config::<TestConfig>(json!({ "version": 3 })).is_err());
+        assert!(migrate_config::<TestConfig>(json!({ "version": "1" })).is_err());
+
+        Ok(())
+    }
+}
@@ -14,6 +14,12 @@
 //! See [the trace event profiling tool documentation][2] for details on
 //! working with Chrome traces.
 //!
+//! Traces can also be written event by event with [TraceWriter], and events
+//! submitted from hot paths without blocking on IO with [TraceSink].
+//!
+//! The threads of the events are identified by ids assigned by this crate,
+//! see [current_tid], and can be named with [Trace::add_thread_names].
+//!
 //! [1]: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU/preview
 //! [2]: http://dev.chromium.org/developers/how-tos/trace-event-profiling-tool
 
@@ -24,8 +30,6 @@ use std::fs::File;
 use std::io::Read;
 use std::io::Write;
 use std::path::Path;
-#[cfg(not(target_os = "linux"))]
-use std::thread::ThreadId;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -38,6 +42,20 @@ use serde::Deserialize;
 use serde::Serialize;
 use serde_json::Value;
 
+mod threads;
+mod validate;
+mod writer;
+
+pub use crate::threads::current_tid;
+pub use crate::threads::thread_name_event;
+pub use crate::threads::thread_names;
+pub use crate::validate::Diagnostic;
+pub use crate::validate::DiagnosticKind;
+pub use crate::writer::TraceSink;
+pub use crate::writer::TraceSinkStats;
+pub use crate::writer::TraceSinkWorker;
+pub use crate::writer::TraceWriter;
+
 /// Type alias for the [Event::args] field.
 pub type Args = HashMap<String, Value>;
 
@@ -116,7 +134,8 @@ pub struct Event {
     pub ph: Phase,
     /// The process ID for the process that output this event.
     pub pid: u64,
-    /// The thread ID for the thread that output this event.
+    /// The thread ID for the thread that output this event, see
+    /// [current_tid].
     pub tid: u64,
     #[serde(
         skip_serializing_if = "Option::is_none",
@@ -183,7 +202,7 @@ impl Event {
             name: name.to_string(),
             ph: phase,
             pid: getpid(),
-            tid: gettid(),
+            tid: current_tid(),
             ..Default::default()
         }
     }
@@ -408,34 +427,7 @@ json_methods_impl!(Event);
 json_methods_impl!(Trace);
 
 fn getpid() -> u64 {
-    unsafe { libc::getpid() as u64 }
-}
-
-/// Get an integer ID for the current thread on Linux. This is the
-/// system-level thread ID assigned by the kernel's scheduler.
-#[cfg(target_os = "linux")]
-fn gettid() -> u64 {
-    // The gettid(2) system call has no wrapper in glibc (because it
-    // isn't portable), so we need to make the system call manually.
-    // This is only meaningful on Linux systems.
-    unsafe { libc::syscall(libc::SYS_gettid) as u64 }
-}
-
-/// Get an integer ID for the current thread on non-Linux platforms.
-/// Since there is no good portable API for getting a system-level thread
-/// ID, this function instead returns a interger representation of
-/// the Rust standard library's std::thead::ThreadId type, which
-/// (as of Rust 1.24.0) is completely unrelated to any system level
-/// thread ID.
-#[cfg(not(target_os = "linux"))]
-fn gettid() -> u64 {
-    let tid = std::thread::current().id();
-    // XXX: As of Rust 1.24.0, a std::thead::ThreadId is simply a wrapper
-    // around a u64 counter that is incremented for each new thead.
-    // Unfortunately, the standard library does not provide a way to
-    // extract this counter from the struct, so we have to resort to
-    // transmuting it.
-    unsafe { std::mem::transmute::<ThreadId, u64>(tid) }
+    u64::from(std::process::id())
 }
 
 /// Module for serializing and deserializing time::Duration structs as integer values
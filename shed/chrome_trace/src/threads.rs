@@ -0,0 +1,132 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Registry of the threads that created events, assigning them the ids used
+//! as [Event::tid] and remembering their names, so that traces can name their
+//! threads with Metadata events.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use serde_json::json;
+
+use crate::Event;
+use crate::Phase;
+use crate::Trace;
+
+static NEXT_TID: AtomicU64 = AtomicU64::new(1);
+
+/// Names of the registered threads that have one, by id. Threads are never
+/// removed, so that the events of threads that exited can still be named.
+static THREAD_NAMES: Mutex<BTreeMap<u64, String>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    static TID: u64 = register_current_thread();
+}
+
+fn register_current_thread() -> u64 {
+    let tid = NEXT_TID.fetch_add(1, Ordering::Relaxed);
+    if let Some(name) = std::thread::current().name() {
+        THREAD_NAMES
+            .lock()
+            .expect("poisoned lock")
+            .insert(tid, name.to_owned());
+    }
+    tid
+}
+
+/// Return the id of the current thread, as set in [Event::tid] by
+/// [Event::new].
+///
+/// The ids are assigned by this crate in increasing order, starting from 1,
+/// the first time each thread asks for one, so they are the same on all
+/// platforms but are unrelated to the ids of the threads in the system.
+pub fn current_tid() -> u64 {
+    TID.with(|tid| *tid)
+}
+
+/// Return the names of the threads that were assigned an id, by id. Threads
+/// without a name, see [std::thread::Builder::name], are omitted.
+pub fn thread_names() -> BTreeMap<u64, String> {
+    THREAD_NAMES.lock().expect("poisoned lock").clone()
+}
+
+/// Return the `thread_name` Metadata event naming the thread with the given
+/// id in the current process, if it has a name.
+pub fn thread_name_event(tid: u64) -> Option<Event> {
+    let name = THREAD_NAMES
+        .lock()
+        .expect("poisoned lock")
+        .get(&tid)?
+        .clone();
+    Some(
+        Event::new("thread_name", Phase::Metadata)
+            .tid(tid)
+            .args([("name".to_owned(), json!(name))].into()),
+    )
+}
+
+impl Trace {
+    /// Add a `thread_name` Metadata event for each thread of the current
+    /// process that has events in the trace and a name, so that the trace
+    /// viewer shows the names of the threads rather than their ids.
+    pub fn add_thread_names(&mut self) {
+        let pid = crate::getpid();
+        let tids = self
+            .trace_events
+            .iter()
+            .filter(|event| event.pid == pid && event.ph != Phase::Metadata)
+            .map(|event| event.tid)
+            .collect::<BTreeSet<_>>();
+        self.add_events(tids.into_iter().filter_map(thread_name_event));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn named_threads() {
+        let tid = current_tid();
+        assert_eq!(Event::new("event", Phase::Instant).tid, tid);
+
+        let (named_tid, named_event_tid) = thread::Builder::new()
+            .name("worker".to_owned())
+            .spawn(|| (current_tid(), Event::new("event", Phase::Instant).tid))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_ne!(named_tid, tid);
+        assert_eq!(named_event_tid, named_tid);
+        assert_eq!(thread_names().get(&named_tid).unwrap(), "worker");
+
+        let unnamed_tid = thread::spawn(current_tid).join().unwrap();
+        assert!(unnamed_tid > named_tid);
+        assert_eq!(thread_names().get(&unnamed_tid), None);
+
+        let mut trace = Trace::new();
+        trace.add_events([
+            Event::new("event", Phase::Instant).tid(named_tid),
+            Event::new("event", Phase::Instant).tid(unnamed_tid),
+        ]);
+        trace.add_thread_names();
+        assert_eq!(trace.trace_events.len(), 3);
+        let metadata = &trace.trace_events[2];
+        assert_eq!(metadata.ph, Phase::Metadata);
+        assert_eq!(metadata.name, "thread_name");
+        assert_eq!(metadata.tid, named_tid);
+        assert_eq!(metadata.args["name"], json!("worker"));
+    }
+}
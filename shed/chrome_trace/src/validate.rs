@@ -0,0 +1,186 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Checks for the common mistakes that make a trace render confusingly in the
+//! viewer rather than fail to load.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::Phase;
+use crate::Trace;
+
+/// A problem found in a trace by [Trace::validate].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Index in [Trace::trace_events] of the offending event.
+    pub index: usize,
+    /// What is wrong with the event.
+    pub kind: DiagnosticKind,
+}
+
+/// The kinds of problems reported by [Trace::validate].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A [Phase::Begin] event without a matching [Phase::End] event on the
+    /// same thread.
+    UnmatchedBegin,
+    /// A [Phase::End] event without a preceding [Phase::Begin] event on the
+    /// same thread.
+    UnmatchedEnd,
+    /// A [Phase::Complete] event without [Event::dur](crate::Event::dur).
+    CompleteWithoutDuration,
+    /// An event with [Phase::Unspecified], which can't be serialized.
+    UnspecifiedPhase,
+    /// An event with a timestamp earlier than the previous event of the same
+    /// thread.
+    TimestampWentBackwards {
+        /// The timestamp of the previous event of the thread.
+        previous: Duration,
+    },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "event {}: ", self.index)?;
+        match &self.kind {
+            DiagnosticKind::UnmatchedBegin => write!(f, "Begin event is never ended"),
+            DiagnosticKind::UnmatchedEnd => write!(f, "End event has no matching Begin"),
+            DiagnosticKind::CompleteWithoutDuration => {
+                write!(f, "Complete event has no duration")
+            }
+            DiagnosticKind::UnspecifiedPhase => write!(f, "event has an unspecified phase"),
+            DiagnosticKind::TimestampWentBackwards { previous } => write!(
+                f,
+                "timestamp is earlier than the previous event of the thread at {:?}",
+                previous
+            ),
+        }
+    }
+}
+
+impl Trace {
+    /// Check the trace for common mistakes and return the problems found,
+    /// ordered by event. Begin and End events are matched, and timestamps
+    /// compared, per thread in the order of [Trace::trace_events].
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut open_begins: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
+        let mut last_ts: HashMap<(u64, u64), Duration> = HashMap::new();
+
+        for (index, event) in self.trace_events.iter().enumerate() {
+            let thread = (event.pid, event.tid);
+            let mut report = |kind| diagnostics.push(Diagnostic { index, kind });
+
+            match event.ph {
+                Phase::Begin => open_begins.entry(thread).or_default().push(index),
+                // The guard pops the matching Begin, if any.
+                Phase::End
+                    if open_begins
+                        .get_mut(&thread)
+                        .and_then(|begins| begins.pop())
+                        .is_none() =>
+                {
+                    report(DiagnosticKind::UnmatchedEnd)
+                }
+                Phase::Complete if event.dur.is_none() => {
+                    report(DiagnosticKind::CompleteWithoutDuration)
+                }
+                Phase::Unspecified => report(DiagnosticKind::UnspecifiedPhase),
+                _ => {}
+            }
+
+            if let Some(ts) = event.ts {
+                match last_ts.insert(thread, ts) {
+                    Some(previous) if ts < previous => {
+                        report(DiagnosticKind::TimestampWentBackwards { previous })
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        diagnostics.extend(open_begins.into_values().flatten().map(|index| Diagnostic {
+            index,
+            kind: DiagnosticKind::UnmatchedBegin,
+        }));
+        // Sorting is stable, so problems of the same event keep their order.
+        diagnostics.sort_by_key(|diagnostic| diagnostic.index);
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Event;
+
+    fn event(phase: Phase, tid: u64, ts: u64) -> Event {
+        Event {
+            name: "test".into(),
+            ph: phase,
+            tid,
+            ts: Some(Duration::from_micros(ts)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn valid_trace() {
+        let mut trace = Trace::new();
+        trace.add_events([
+            event(Phase::Begin, 1, 10),
+            event(Phase::Begin, 2, 5),
+            event(Phase::Complete, 1, 20).dur(Duration::from_micros(5)),
+            event(Phase::End, 2, 30),
+            event(Phase::End, 1, 30),
+            event(Phase::Metadata, 1, 0).ts(Duration::from_micros(30)),
+        ]);
+        assert_eq!(trace.validate(), vec![]);
+    }
+
+    #[test]
+    fn invalid_trace() {
+        let mut trace = Trace::new();
+        trace.add_events([
+            event(Phase::End, 1, 10),
+            event(Phase::Begin, 1, 20),
+            event(Phase::Complete, 2, 5),
+            event(Phase::Instant, 1, 15),
+            Event::default(),
+            event(Phase::Begin, 2, 10),
+        ]);
+
+        let diagnostics = trace.validate();
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| (diagnostic.index, diagnostic.kind.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, DiagnosticKind::UnmatchedEnd),
+                (1, DiagnosticKind::UnmatchedBegin),
+                (2, DiagnosticKind::CompleteWithoutDuration),
+                (
+                    3,
+                    DiagnosticKind::TimestampWentBackwards {
+                        previous: Duration::from_micros(20)
+                    }
+                ),
+                (4, DiagnosticKind::UnspecifiedPhase),
+                (5, DiagnosticKind::UnmatchedBegin),
+            ]
+        );
+        assert_eq!(
+            diagnostics[3].to_string(),
+            "event 3: timestamp is earlier than the previous event of the thread at 20µs"
+        );
+    }
+}
@@ -0,0 +1,373 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Writing the events of a trace as they happen rather than all at once, and
+//! submitting them from hot paths without blocking on the writes.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use anyhow::format_err;
+use anyhow::Result;
+use crossbeam::queue::ArrayQueue;
+
+use crate::Event;
+
+/// How long the thread of a [TraceSink] waits before checking for new
+/// events once it has written all the submitted ones.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Writes the events of a trace one by one, in the same "JSON Object Format"
+/// as [Trace::save](crate::Trace::save).
+///
+/// The writes aren't buffered, so writing to a file should go through a
+/// [BufWriter], as with [TraceWriter::create].
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    events: usize,
+}
+
+impl TraceWriter<BufWriter<File>> {
+    /// Create the file at the given path and write the trace into it.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// Write the trace into the given writer.
+    pub fn new(writer: W) -> Self {
+        Self { writer, events: 0 }
+    }
+
+    /// Number of events written so far.
+    pub fn events(&self) -> usize {
+        self.events
+    }
+
+    /// Append the event to the trace. Events that can't be serialized, e.g.
+    /// with [Phase::Unspecified](crate::Phase::Unspecified), are rejected
+    /// without writing anything.
+    pub fn write_event(&mut self, event: &Event) -> Result<()> {
+        let json = serde_json::to_vec(event)?;
+        let separator: &[u8] = if self.events == 0 {
+            b"{\"traceEvents\":["
+        } else {
+            b","
+        };
+        self.writer.write_all(separator)?;
+        self.writer.write_all(&json)?;
+        self.events += 1;
+        Ok(())
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    /// Complete the trace and return the underlying writer, flushed. The
+    /// trace can't be read before it is complete.
+    pub fn finish(mut self) -> Result<W> {
+        if self.events == 0 {
+            self.writer.write_all(b"{\"traceEvents\":[")?;
+        }
+        self.writer.write_all(b"]}")?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Counters of a [TraceSink].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraceSinkStats {
+    /// Number of events queued to be written.
+    pub submitted: u64,
+    /// Number of events dropped because the queue was full, the sink was
+    /// finished or writing to the [TraceWriter] failed.
+    pub dropped: u64,
+    /// Number of events written to the [TraceWriter].
+    pub written: u64,
+}
+
+struct Shared {
+    queue: ArrayQueue<Event>,
+    closed: AtomicBool,
+    submitted: AtomicU64,
+    dropped: AtomicU64,
+    written: AtomicU64,
+}
+
+/// Handle to submit events to a [TraceWriter] from hot paths, e.g. the
+/// handling of requests.
+///
+/// Submitting an event only pushes it to a lock-free bounded queue, without
+/// taking locks or doing IO, and a background thread drains the queue into
+/// the writer. When the queue is full, the event is dropped and counted in
+/// [TraceSinkStats::dropped] rather than waiting for the writer to catch up.
+#[derive(Clone)]
+pub struct TraceSink {
+    shared: Arc<Shared>,
+}
+
+/// Owner of the background thread of a [TraceSink], completing the trace
+/// when finished.
+pub struct TraceSinkWorker<W: Write> {
+    shared: Arc<Shared>,
+    thread: JoinHandle<Result<W>>,
+}
+
+impl TraceSink {
+    /// Start a background thread writing the events submitted to the
+    /// returned sink to the writer, with room for `capacity` events waiting
+    /// to be written. A `capacity` of zero is treated as one.
+    pub fn spawn<W>(writer: TraceWriter<W>, capacity: usize) -> (Self, TraceSinkWorker<W>)
+    where
+        W: Write + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            queue: ArrayQueue::new(capacity.max(1)),
+            closed: AtomicBool::new(false),
+            submitted: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+        });
+        let thread = thread::Builder::new()
+            .name("trace-sink".to_owned())
+            .spawn({
+                let shared = shared.clone();
+                move || drain(&shared, writer)
+            })
+            .expect("failed to spawn the trace sink thread");
+        (
+            Self {
+                shared: shared.clone(),
+            },
+            TraceSinkWorker { shared, thread },
+        )
+    }
+
+    /// Queue the event to be written, returning whether it was queued. The
+    /// event is dropped if the queue is full or the sink is finished.
+    pub fn submit(&self, event: Event) -> bool {
+        if self.shared.closed.load(Ordering::Acquire) || self.shared.queue.push(event).is_err() {
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        self.shared.submitted.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Return the counters of the sink.
+    pub fn stats(&self) -> TraceSinkStats {
+        self.shared.stats()
+    }
+}
+
+impl<W: Write> TraceSinkWorker<W> {
+    /// Return the counters of the sink.
+    pub fn stats(&self) -> TraceSinkStats {
+        self.shared.stats()
+    }
+
+    /// Stop accepting events, wait for the queued ones to be written, and
+    /// complete the trace, returning the underlying writer. Fails with the
+    /// first error of the writer, if any.
+    pub fn finish(self) -> Result<W> {
+        self.shared.closed.store(true, Ordering::Release);
+        self.thread.thread().unpark();
+        let result = self
+            .thread
+            .join()
+            .map_err(|_| format_err!("The trace sink thread panicked"))?;
+        // Events submitted while the sink was being finished may have been
+        // queued after the thread was done.
+        let left = self.shared.queue.len() as u64;
+        self.shared.dropped.fetch_add(left, Ordering::Relaxed);
+        result
+    }
+}
+
+impl Shared {
+    fn stats(&self) -> TraceSinkStats {
+        TraceSinkStats {
+            submitted: self.submitted.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            written: self.written.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Write the queued events until the sink is finished. Once the writer has
+/// failed, the events are dropped.
+fn drain<W: Write>(shared: &Shared, mut writer: TraceWriter<W>) -> Result<W> {
+    let mut error = None;
+    loop {
+        // Checked before draining, so that the events queued before the sink
+        // was finished are all written.
+        let closed = shared.closed.load(Ordering::Acquire);
+        let mut wrote = false;
+        while let Some(event) = shared.queue.pop() {
+            if error.is_some() {
+                shared.dropped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            match writer.write_event(&event) {
+                Ok(()) => {
+                    shared.written.fetch_add(1, Ordering::Relaxed);
+                    wrote = true;
+                }
+                Err(err) => {
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    error = Some(err);
+                }
+            }
+        }
+        if wrote && error.is_none() {
+            if let Err(err) = writer.flush() {
+                error = Some(err);
+            }
+        }
+        if closed {
+            break;
+        }
+        thread::park_timeout(DRAIN_INTERVAL);
+    }
+    match error {
+        Some(err) => Err(err),
+        None => writer.finish(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::Phase;
+    use crate::Trace;
+
+    fn event(name: &str) -> Event {
+        Event::new(name, Phase::Instant).ts(Duration::from_micros(1))
+    }
+
+    #[test]
+    fn write_events() {
+        let mut writer = TraceWriter::new(Vec::new());
+        writer.write_event(&event("first")).unwrap();
+        assert!(writer.write_event(&Event::default()).is_err());
+        writer.write_event(&event("second")).unwrap();
+        assert_eq!(writer.events(), 2);
+        let json = String::from_utf8(writer.finish().unwrap()).unwrap();
+
+        let mut expected = Trace::new();
+        expected.add_events([event("first"), event("second")]);
+        assert_eq!(Trace::parse(&json).unwrap(), expected);
+
+        let json = TraceWriter::new(Vec::new()).finish().unwrap();
+        assert_eq!(json, b"{\"traceEvents\":[]}");
+    }
+
+    #[test]
+    fn sink() {
+        let (sink, worker) = TraceSink::spawn(TraceWriter::new(Vec::new()), 16);
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let sink = sink.clone();
+                thread::spawn(move || {
+                    for j in 0..4 {
+                        while !sink.submit(event(&format!("{}-{}", i, j))) {
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let json = worker.finish().unwrap();
+
+        let trace = Trace::parse(std::str::from_utf8(&json).unwrap()).unwrap();
+        assert_eq!(trace.trace_events.len(), 16);
+        let stats = sink.stats();
+        assert_eq!(stats.submitted, 16);
+        assert_eq!(stats.written, 16);
+        assert!(!sink.submit(event("late")));
+    }
+
+    /// Writer blocking while the test holds the lock, telling the test when
+    /// it is first used.
+    struct BlockedWriter {
+        lock: Arc<Mutex<()>>,
+        used: Option<mpsc::Sender<()>>,
+        written: Vec<u8>,
+    }
+
+    impl Write for BlockedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if let Some(used) = self.used.take() {
+                used.send(()).unwrap();
+            }
+            let _lock = self.lock.lock().unwrap();
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sink_overflow() {
+        let lock = Arc::new(Mutex::new(()));
+        let (used, first_write) = mpsc::channel();
+        let writer = BlockedWriter {
+            lock: lock.clone(),
+            used: Some(used),
+            written: Vec::new(),
+        };
+        let blocked = lock.lock().unwrap();
+        let (sink, worker) = TraceSink::spawn(TraceWriter::new(writer), 2);
+
+        // The first event is taken out of the queue by the thread, which then
+        // blocks writing it, so the queue fills up.
+        assert!(sink.submit(event("first")));
+        first_write.recv().unwrap();
+        assert!(sink.submit(event("second")));
+        assert!(sink.submit(event("third")));
+        assert!(!sink.submit(event("fourth")));
+        assert_eq!(
+            sink.stats(),
+            TraceSinkStats {
+                submitted: 3,
+                dropped: 1,
+                written: 0,
+            }
+        );
+
+        drop(blocked);
+        let writer = worker.finish().unwrap();
+        let trace = Trace::parse(std::str::from_utf8(&writer.written).unwrap()).unwrap();
+        let names: Vec<_> = trace.trace_events.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["first", "second", "third"]);
+        assert_eq!(sink.stats().written, 3);
+    }
+}
@@ -0,0 +1,351 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+#![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
+
+//! Registry of stable error codes, so that services can handle the errors of
+//! the crates they use uniformly, e.g. retrying only transient errors, and
+//! build dashboards on codes that don't change when error messages do.
+//!
+//! Each crate defines an [ErrorDomain] listing its codes and classifying its
+//! errors, which services [register] at startup. The code of any error can
+//! then be found with [error_code], which looks at the error and the errors
+//! it was caused by.
+//!
+//! Codes are unique across domains, each crate using its own range:
+//!
+//! | Range       | Crate       |
+//! |-------------|-------------|
+//! | 1000 - 1999 | `netstring` |
+//! | 2000 - 2999 | `sql`       |
+//! | 3000 - 3999 | `services`  |
+//!
+//! ```
+//! use std::error::Error as StdError;
+//!
+//! use error_codes::ErrorCategory;
+//! use error_codes::ErrorCode;
+//! use error_codes::ErrorDomain;
+//!
+//! #[derive(Debug, thiserror::Error)]
+//! #[error("backend is overloaded")]
+//! struct Overloaded;
+//!
+//! const OVERLOADED: ErrorCode = ErrorCode::new(9001, "OVERLOADED", ErrorCategory::Transient);
+//!
+//! static DOMAIN: ErrorDomain = ErrorDomain {
+//!     name: "example",
+//!     codes: &[OVERLOADED],
+//!     classify: |err: &(dyn StdError + 'static)| err.is::<Overloaded>().then_some(OVERLOADED),
+//! };
+//!
+//! error_codes::register(&DOMAIN).unwrap();
+//! let err = anyhow::Error::from(Overloaded).context("while querying");
+//! assert_eq!(error_codes::error_code(&err), Some(OVERLOADED));
+//! assert!(error_codes::is_transient(&err));
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::ptr;
+use std::sync::RwLock;
+
+use thiserror::Error;
+
+static DOMAINS: RwLock<Vec<&'static ErrorDomain>> = RwLock::new(Vec::new());
+
+/// How an error should be handled by the caller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// The operation may succeed if retried, e.g. after a conflict with
+    /// another operation or while a backend is unavailable.
+    Transient,
+    /// The operation will fail again if retried.
+    Permanent,
+    /// The caller is not authenticated or not allowed to do the operation.
+    Auth,
+    /// The input of the operation is invalid.
+    InvalidInput,
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Transient => "transient",
+            Self::Permanent => "permanent",
+            Self::Auth => "auth",
+            Self::InvalidInput => "invalid_input",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A stable error code, with a numeric and a string form.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ErrorCode {
+    code: u32,
+    name: &'static str,
+    category: ErrorCategory,
+}
+
+impl ErrorCode {
+    /// Create the code `code`, named `name`, e.g. `SQL_CONFLICT`.
+    pub const fn new(code: u32, name: &'static str, category: ErrorCategory) -> Self {
+        Self {
+            code,
+            name,
+            category,
+        }
+    }
+
+    /// Return the numeric form of the code.
+    pub fn code(&self) -> u32 {
+        self.code
+    }
+
+    /// Return the string form of the code.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Return the category of the errors with this code.
+    pub fn category(&self) -> ErrorCategory {
+        self.category
+    }
+
+    /// Whether the errors with this code are transient.
+    pub fn is_transient(&self) -> bool {
+        self.category == ErrorCategory::Transient
+    }
+}
+
+/// Formats the code as `NAME (code)`.
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.code)
+    }
+}
+
+/// Implemented by the errors that always have a code, so that the classifier
+/// of their domain only has to downcast to them.
+pub trait HasErrorCode {
+    /// Return the code of the error.
+    fn error_code(&self) -> ErrorCode;
+}
+
+/// The codes of a crate, with the function classifying its errors.
+#[derive(Debug)]
+pub struct ErrorDomain {
+    /// Name of the domain, usually the name of the crate.
+    pub name: &'static str,
+    /// All the codes of the domain.
+    pub codes: &'static [ErrorCode],
+    /// Return the code of an error of the crate, or `None` for other errors.
+    /// The errors it was caused by don't have to be looked at.
+    pub classify: fn(&(dyn StdError + 'static)) -> Option<ErrorCode>,
+}
+
+/// Error returned by [register].
+#[derive(Debug, Error)]
+pub enum RegistrationError {
+    /// A code of the domain has the number of a code already registered.
+    #[error("Error code {code} of {domain} is already registered by {existing}")]
+    DuplicateCode {
+        /// The domain being registered.
+        domain: &'static str,
+        /// The code being registered.
+        code: ErrorCode,
+        /// The domain that registered the code first.
+        existing: &'static str,
+    },
+    /// A code of the domain has the name of a code already registered.
+    #[error("Error code name {name} of {domain} is already registered by {existing}")]
+    DuplicateName {
+        /// The domain being registered.
+        domain: &'static str,
+        /// The name of the code being registered.
+        name: &'static str,
+        /// The domain that registered the name first.
+        existing: &'static str,
+    },
+}
+
+/// Register the codes of a domain, failing if one of them has the number or
+/// the name of a code of another domain. Registering a domain again does
+/// nothing.
+pub fn register(domain: &'static ErrorDomain) -> Result<(), RegistrationError> {
+    let mut domains = DOMAINS.write().expect("poisoned lock");
+    if domains.iter().any(|existing| ptr::eq(*existing, domain)) {
+        return Ok(());
+    }
+    for existing in domains.iter() {
+        for code in domain.codes {
+            for existing_code in existing.codes {
+                if existing_code.code == code.code {
+                    return Err(RegistrationError::DuplicateCode {
+                        domain: domain.name,
+                        code: *code,
+                        existing: existing.name,
+                    });
+                }
+                if existing_code.name == code.name {
+                    return Err(RegistrationError::DuplicateName {
+                        domain: domain.name,
+                        name: code.name,
+                        existing: existing.name,
+                    });
+                }
+            }
+        }
+    }
+    domains.push(domain);
+    Ok(())
+}
+
+/// Return the registered codes, with the name of their domain, sorted by
+/// number, e.g. to document them.
+pub fn registered_codes() -> Vec<(&'static str, ErrorCode)> {
+    let domains = DOMAINS.read().expect("poisoned lock");
+    let mut codes: Vec<_> = domains
+        .iter()
+        .flat_map(|domain| domain.codes.iter().map(|code| (domain.name, *code)))
+        .collect();
+    codes.sort_by_key(|(_, code)| code.code);
+    codes
+}
+
+/// Return the registered code with the number `code`.
+pub fn lookup(code: u32) -> Option<ErrorCode> {
+    lookup_by(|registered| registered.code == code)
+}
+
+/// Return the registered code named `name`.
+pub fn lookup_name(name: &str) -> Option<ErrorCode> {
+    lookup_by(|registered| registered.name == name)
+}
+
+fn lookup_by(matches: impl Fn(&ErrorCode) -> bool) -> Option<ErrorCode> {
+    let domains = DOMAINS.read().expect("poisoned lock");
+    domains
+        .iter()
+        .flat_map(|domain| domain.codes)
+        .find(|code| matches(code))
+        .copied()
+}
+
+/// Return the code of the error, or of the first error it was caused by that
+/// a registered domain classifies.
+pub fn error_code(err: &anyhow::Error) -> Option<ErrorCode> {
+    err.chain().find_map(classify)
+}
+
+/// Same as [error_code], for errors that are not [anyhow::Error].
+pub fn error_code_of(err: &(dyn StdError + 'static)) -> Option<ErrorCode> {
+    let mut cause = Some(err);
+    while let Some(err) = cause {
+        if let Some(code) = classify(err) {
+            return Some(code);
+        }
+        cause = err.source();
+    }
+    None
+}
+
+/// Return the category of the error, see [error_code].
+pub fn category(err: &anyhow::Error) -> Option<ErrorCategory> {
+    error_code(err).map(|code| code.category)
+}
+
+/// Whether the error is transient, see [error_code]. Errors without a code
+/// are not.
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    category(err) == Some(ErrorCategory::Transient)
+}
+
+fn classify(err: &(dyn StdError + 'static)) -> Option<ErrorCode> {
+    let domains = DOMAINS.read().expect("poisoned lock");
+    domains.iter().find_map(|domain| (domain.classify)(err))
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::format_err;
+
+    use super::*;
+
+    #[derive(Debug, Error)]
+    enum TestError {
+        #[error("busy")]
+        Busy,
+        #[error("denied")]
+        Denied,
+    }
+
+    #[derive(Debug, Error)]
+    #[error("wrapper")]
+    struct Wrapper(#[source] TestError);
+
+    impl HasErrorCode for TestError {
+        fn error_code(&self) -> ErrorCode {
+            match self {
+                Self::Busy => TEST_BUSY,
+                Self::Denied => TEST_DENIED,
+            }
+        }
+    }
+
+    const TEST_BUSY: ErrorCode = ErrorCode::new(90001, "TEST_BUSY", ErrorCategory::Transient);
+    const TEST_DENIED: ErrorCode = ErrorCode::new(90002, "TEST_DENIED", ErrorCategory::Auth);
+
+    static TEST_DOMAIN: ErrorDomain = ErrorDomain {
+        name: "test",
+        codes: &[TEST_BUSY, TEST_DENIED],
+        classify: |err| err.downcast_ref::<TestError>().map(TestError::error_code),
+    };
+
+    static CONFLICTING_DOMAIN: ErrorDomain = ErrorDomain {
+        name: "conflicting",
+        codes: &[ErrorCode::new(
+            90001,
+            "CONFLICTING_BUSY",
+            ErrorCategory::Transient,
+        )],
+        classify: |_| None,
+    };
+
+    #[test]
+    fn test_registry() {
+        register(&TEST_DOMAIN).unwrap();
+        register(&TEST_DOMAIN).unwrap();
+        assert!(matches!(
+            register(&CONFLICTING_DOMAIN),
+            Err(RegistrationError::DuplicateCode {
+                existing: "test",
+                ..
+            })
+        ));
+
+        assert_eq!(lookup(90002), Some(TEST_DENIED));
+        assert_eq!(lookup_name("TEST_BUSY"), Some(TEST_BUSY));
+        assert_eq!(lookup(90003), None);
+        assert!(registered_codes().contains(&("test", TEST_BUSY)));
+        assert_eq!(TEST_DENIED.to_string(), "TEST_DENIED (90002)");
+
+        let err = anyhow::Error::from(TestError::Busy).context("while testing");
+        assert_eq!(error_code(&err), Some(TEST_BUSY));
+        assert!(is_transient(&err));
+        let err = anyhow::Error::from(TestError::Denied);
+        assert_eq!(category(&err), Some(ErrorCategory::Auth));
+        assert!(!is_transient(&err));
+        assert_eq!(error_code(&format_err!("unknown")), None);
+
+        let err = Wrapper(TestError::Busy);
+        assert_eq!(error_code_of(&err), Some(TEST_BUSY));
+    }
+}
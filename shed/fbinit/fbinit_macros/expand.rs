@@ -113,12 +113,23 @@ pub fn expand(mode: Mode, args: Args, mut function: ItemFn) -> Result<TokenStrea
         }
     };
 
+    // Declared after the destroy guard, so that the shutdown hooks run
+    // before fbinit is destroyed.
+    let shutdown_guard = match mode {
+        Mode::Main => Some(quote! {
+            let shutdown_guard = fbinit::shutdown::ShutdownGuard::new();
+        }),
+        _ => None,
+    };
+
     function.block = parse_quote!({
         #guard
         #assignment unsafe {
             #perform_init
         };
+        fbinit::runtime_metadata::capture();
         let destroy_guard = unsafe { fbinit::internal::DestroyGuard::new() };
+        #shutdown_guard
         #body
     });
 
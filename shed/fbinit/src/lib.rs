@@ -18,6 +18,8 @@
 
 #[cfg(not(fbcode_build))]
 mod oss;
+pub mod runtime_metadata;
+pub mod shutdown;
 
 pub use fbinit_macros::main;
 pub use fbinit_macros::nested_test;
@@ -26,3 +28,4 @@ pub use fbinit_macros::test;
 pub use oss::*;
 #[cfg(fbcode_build)]
 pub use real_fbinit::*;
+pub use runtime_metadata::RuntimeMetadata;
@@ -0,0 +1,90 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Snapshot of the process state taken when fbinit is performed, so that
+//! code running later, e.g. logging scuba samples or writing traces, can
+//! annotate its data consistently without reading mutable process state
+//! like env vars again.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use crate::FacebookInit;
+
+static METADATA: OnceLock<RuntimeMetadata> = OnceLock::new();
+
+/// Process args, env vars and time captured when fbinit was performed.
+#[derive(Debug)]
+pub struct RuntimeMetadata {
+    args: Vec<String>,
+    env: HashMap<String, String>,
+    init_time: SystemTime,
+    init_instant: Instant,
+}
+
+impl RuntimeMetadata {
+    fn capture() -> Self {
+        Self {
+            args: std::env::args_os()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            // Env vars that aren't valid unicode are skipped.
+            env: std::env::vars_os()
+                .filter_map(|(name, value)| {
+                    Some((name.into_string().ok()?, value.into_string().ok()?))
+                })
+                .collect(),
+            init_time: SystemTime::now(),
+            init_instant: Instant::now(),
+        }
+    }
+
+    /// Return the args of the process, the first being the program. Args
+    /// that aren't valid unicode are converted lossily.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Return the value the env var `name` had when fbinit was performed.
+    ///
+    /// Env vars can only be looked up by name rather than listed, so that
+    /// annotating data with them never includes secrets by accident.
+    pub fn env_var(&self, name: &str) -> Option<&str> {
+        self.env.get(name).map(String::as_str)
+    }
+
+    /// Return when fbinit was performed.
+    pub fn init_time(&self) -> SystemTime {
+        self.init_time
+    }
+
+    /// Return how long ago fbinit was performed.
+    pub fn elapsed_since_init(&self) -> Duration {
+        self.init_instant.elapsed()
+    }
+}
+
+/// Return the metadata captured when fbinit was performed.
+///
+/// The metadata is captured by `#[fbinit::main]` and `#[fbinit::test]` right
+/// after performing the init. If the proof was obtained otherwise, e.g. with
+/// [perform_init](crate::perform_init), it is captured on the first call.
+pub fn get(_fb: FacebookInit) -> &'static RuntimeMetadata {
+    METADATA.get_or_init(RuntimeMetadata::capture)
+}
+
+// Not public API. Used by the attribute macros. Only the first capture is
+// kept, so that tests running in the same process share the metadata.
+#[doc(hidden)]
+pub fn capture() {
+    METADATA.get_or_init(RuntimeMetadata::capture);
+}
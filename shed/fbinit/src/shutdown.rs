@@ -0,0 +1,66 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Hooks run when the main function of the process returns, e.g. to flush
+//! buffered stats or logs that short-lived jobs would otherwise lose.
+//!
+//! The hooks are run by `#[fbinit::main]` after main returns, including when
+//! it returns an error, but not when the process exits otherwise, e.g. with
+//! [std::process::exit] or on a panic. They can also be run explicitly with
+//! [run_hooks].
+
+use std::mem;
+use std::sync::Mutex;
+
+use crate::FacebookInit;
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+static HOOKS: Mutex<Vec<Hook>> = Mutex::new(Vec::new());
+
+/// Register a hook to run at shutdown. The hooks run in the reverse order of
+/// their registration, so that a hook registered by a component runs before
+/// those of the components it was built on.
+pub fn register_hook(_fb: FacebookInit, hook: impl FnOnce() + Send + 'static) {
+    HOOKS.lock().expect("poisoned lock").push(Box::new(hook));
+}
+
+/// Run the hooks registered so far, each only once. Hooks registered by
+/// other hooks are run too.
+pub fn run_hooks() {
+    loop {
+        // Don't hold the lock while running the hooks, so that they can
+        // register hooks.
+        let hooks = mem::take(&mut *HOOKS.lock().expect("poisoned lock"));
+        if hooks.is_empty() {
+            return;
+        }
+        for hook in hooks.into_iter().rev() {
+            hook();
+        }
+    }
+}
+
+// Not public API. Used by the attribute macros to run the hooks when main
+// returns.
+#[doc(hidden)]
+pub struct ShutdownGuard;
+
+impl ShutdownGuard {
+    #[doc(hidden)]
+    pub fn new() -> Self {
+        ShutdownGuard
+    }
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        run_hooks();
+    }
+}
@@ -7,6 +7,11 @@
  * of this source tree.
  */
 
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
 use fbinit::FacebookInit;
 
 #[cfg(fbcode_build)]
@@ -185,3 +190,36 @@ fn test_main_with_disable_signals_default() {
 
     main();
 }
+
+#[fbinit::test]
+fn test_runtime_metadata(fb: FacebookInit) {
+    let metadata = fbinit::runtime_metadata::get(fb);
+    assert!(!metadata.args().is_empty());
+    assert_eq!(
+        metadata.env_var("PATH"),
+        std::env::var("PATH").ok().as_deref()
+    );
+    assert!(metadata.init_time() <= std::time::SystemTime::now());
+    // The metadata is captured once and shared by later inits.
+    assert!(std::ptr::eq(metadata, fbinit::runtime_metadata::get(fb)));
+}
+
+#[test]
+fn test_main_runs_shutdown_hooks() {
+    static RAN: AtomicBool = AtomicBool::new(false);
+
+    #[fbinit::main]
+    fn main(fb: FacebookInit) {
+        fbinit::shutdown::register_hook(fb, || RAN.store(true, Ordering::SeqCst));
+        assert!(!RAN.load(Ordering::SeqCst));
+    }
+
+    main();
+    // The other tests returning from main concurrently might run the hook
+    // instead, and still be running it.
+    let start = Instant::now();
+    while !RAN.load(Ordering::SeqCst) {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        std::thread::yield_now();
+    }
+}
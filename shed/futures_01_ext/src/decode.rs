@@ -0,0 +1,357 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module with [LayeredDecode], decoding the frames of a stream of [Bytes]
+//! with a tokio [Decoder].
+
+use std::error::Error;
+use std::fmt;
+
+use bytes::Buf;
+use bytes::Bytes;
+use bytes::BytesMut;
+use futures::Async;
+use futures::Poll;
+use futures::Stream;
+use tokio_util::codec::Decoder;
+
+/// Decode the frames of `input` with `decoder`. The returned stream buffers
+/// the bytes of `input` until the decoder can decode a frame from them.
+pub fn decode<In, Dec>(input: In, decoder: Dec) -> LayeredDecode<In, Dec>
+where
+    In: Stream<Item = Bytes>,
+    Dec: Decoder,
+    Dec::Error: From<In::Error>,
+{
+    LayeredDecode {
+        input,
+        decoder,
+        buf: BytesMut::new(),
+        max_buffered: None,
+        on_error: OnDecodeError::default(),
+        new_decoder: None,
+        skipping: false,
+        eof: false,
+        done: false,
+    }
+}
+
+/// Error of a [LayeredDecode] stream.
+#[derive(Debug)]
+pub enum DecodeError<E> {
+    /// The input stream or the decoder failed.
+    Decode(E),
+    /// More than the maximum number of bytes were buffered without decoding
+    /// a frame from them.
+    FrameTooLong {
+        /// The number of bytes buffered.
+        buffered: usize,
+        /// The maximum number of bytes set with
+        /// [LayeredDecode::with_max_buffered].
+        max: usize,
+    },
+}
+
+impl<E: fmt::Display> fmt::Display for DecodeError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Decode(err) => err.fmt(f),
+            Self::FrameTooLong { buffered, max } => write!(
+                f,
+                "buffered {} bytes without decoding a frame, more than the maximum of {}",
+                buffered, max
+            ),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for DecodeError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Decode(err) => err.source(),
+            Self::FrameTooLong { .. } => None,
+        }
+    }
+}
+
+/// What a [LayeredDecode] stream does after failing to decode a frame.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OnDecodeError {
+    /// End the stream after yielding the error.
+    #[default]
+    Terminate,
+    /// Yield the error, then discard the buffered bytes up to and including
+    /// the next occurrence of the given byte, e.g. the separator of the
+    /// frames, and resume decoding after it with a new decoder, as the state
+    /// of the failed one doesn't match the bytes left.
+    SkipTo(u8),
+}
+
+/// Stream returned by [decode]
+#[must_use = "streams do nothing unless polled"]
+pub struct LayeredDecode<In, Dec> {
+    input: In,
+    decoder: Dec,
+    buf: BytesMut,
+    max_buffered: Option<usize>,
+    on_error: OnDecodeError,
+    // Creates the decoder replacing the failed one when resynchronizing.
+    new_decoder: Option<fn() -> Dec>,
+    skipping: bool,
+    eof: bool,
+    done: bool,
+}
+
+impl<In, Dec> LayeredDecode<In, Dec>
+where
+    In: Stream<Item = Bytes>,
+    Dec: Decoder,
+    Dec::Error: From<In::Error>,
+{
+    /// Fail with [DecodeError::FrameTooLong] once more than `max` bytes are
+    /// buffered without the decoder decoding a frame from them, rather than
+    /// buffering the input indefinitely.
+    pub fn with_max_buffered(mut self, max: usize) -> Self {
+        self.max_buffered = Some(max);
+        self
+    }
+
+    /// Return the input stream, the decoder and the bytes buffered but not
+    /// decoded yet.
+    pub fn into_inner(self) -> (In, Dec, BytesMut) {
+        (self.input, self.decoder, self.buf)
+    }
+
+    fn fail(
+        &mut self,
+        err: DecodeError<Dec::Error>,
+    ) -> Poll<Option<Dec::Item>, DecodeError<Dec::Error>> {
+        match self.on_error {
+            OnDecodeError::Terminate => self.done = true,
+            OnDecodeError::SkipTo(_) => self.skipping = true,
+        }
+        Err(err)
+    }
+
+    /// Discard the buffered bytes up to and including the resynchronization
+    /// byte, returning whether it was found, in which case the decoder is
+    /// replaced.
+    fn skip(&mut self) -> bool {
+        let pos = match self.on_error {
+            OnDecodeError::SkipTo(delim) => self.buf.iter().position(|b| *b == delim),
+            OnDecodeError::Terminate => None,
+        };
+        match (pos, self.new_decoder) {
+            (Some(pos), Some(new_decoder)) => {
+                self.buf.advance(pos + 1);
+                self.decoder = new_decoder();
+                self.skipping = false;
+                true
+            }
+            _ => {
+                self.buf.clear();
+                false
+            }
+        }
+    }
+}
+
+impl<In, Dec> LayeredDecode<In, Dec>
+where
+    In: Stream<Item = Bytes>,
+    Dec: Decoder + Default,
+    Dec::Error: From<In::Error>,
+{
+    /// Set what to do after failing to decode a frame, which is to end the
+    /// stream by default. Errors of the input stream always end it.
+    pub fn on_error(mut self, on_error: OnDecodeError) -> Self {
+        self.on_error = on_error;
+        self.new_decoder = Some(Dec::default);
+        self
+    }
+}
+
+impl<In, Dec> Stream for LayeredDecode<In, Dec>
+where
+    In: Stream<Item = Bytes>,
+    Dec: Decoder,
+    Dec::Error: From<In::Error>,
+{
+    type Item = Dec::Item;
+    type Error = DecodeError<Dec::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            if self.skipping {
+                if self.skip() {
+                    continue;
+                }
+            } else {
+                let res = if self.eof {
+                    self.decoder.decode_eof(&mut self.buf)
+                } else {
+                    self.decoder.decode(&mut self.buf)
+                };
+                match res {
+                    Ok(Some(frame)) => return Ok(Async::Ready(Some(frame))),
+                    Ok(None) => {}
+                    Err(err) => return self.fail(DecodeError::Decode(err)),
+                }
+                if let Some(max) = self.max_buffered {
+                    if self.buf.len() > max {
+                        let buffered = self.buf.len();
+                        return self.fail(DecodeError::FrameTooLong { buffered, max });
+                    }
+                }
+            }
+
+            if self.eof {
+                self.done = true;
+                continue;
+            }
+
+            match self.input.poll() {
+                Ok(Async::Ready(Some(bytes))) => self.buf.extend_from_slice(&bytes),
+                Ok(Async::Ready(None)) => self.eof = true,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(err) => {
+                    self.done = true;
+                    return Err(DecodeError::Decode(err.into()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use assert_matches::assert_matches;
+    use futures::stream;
+    use futures::Future;
+    use tokio_util::codec::LinesCodec;
+
+    use super::*;
+
+    /// Decoder of newline terminated lines, failing without consuming them
+    /// on the lines starting with `!`.
+    #[derive(Default)]
+    struct LineDecoder;
+
+    impl Decoder for LineDecoder {
+        type Item = Bytes;
+        type Error = io::Error;
+
+        fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Bytes>> {
+            if buf.first() == Some(&b'!') {
+                return Err(io::Error::other("bad frame"));
+            }
+            match buf.iter().position(|b| *b == b'\n') {
+                Some(pos) => {
+                    let line = buf.split_to(pos + 1).freeze();
+                    Ok(Some(line.slice(..pos)))
+                }
+                None => Ok(None),
+            }
+        }
+    }
+
+    fn chunks(chunks: &[&'static [u8]]) -> impl Stream<Item = Bytes, Error = io::Error> {
+        stream::iter_ok(
+            chunks
+                .iter()
+                .map(|c| Bytes::from_static(c))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn test_decode() {
+        let frames = decode(chunks(&[b"ab\ncd", b"e", b"\nf\n"]), LineDecoder)
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(frames, vec!["ab", "cde", "f"]);
+    }
+
+    #[test]
+    fn test_trailing_bytes() {
+        let mut frames = decode(chunks(&[b"ab\ncd"]), LineDecoder).wait();
+        assert_eq!(frames.next().unwrap().unwrap(), "ab");
+        assert_matches!(frames.next(), Some(Err(DecodeError::Decode(_))));
+        assert_matches!(frames.next(), None);
+    }
+
+    #[test]
+    fn test_max_buffered() {
+        let mut frames = decode(chunks(&[b"ab\n", b"cdef", b"g", b"h\n"]), LineDecoder)
+            .with_max_buffered(4)
+            .wait();
+        assert_eq!(frames.next().unwrap().unwrap(), "ab");
+        assert_matches!(
+            frames.next(),
+            Some(Err(DecodeError::FrameTooLong {
+                buffered: 5,
+                max: 4
+            }))
+        );
+        assert_matches!(frames.next(), None);
+    }
+
+    #[test]
+    fn test_skip_bad_frames() {
+        let mut frames = decode(
+            chunks(&[b"ab\n!c", b"d\ne\n", b"fghij", b"k\nl\n"]),
+            LineDecoder,
+        )
+        .with_max_buffered(4)
+        .on_error(OnDecodeError::SkipTo(b'\n'))
+        .wait();
+        assert_eq!(frames.next().unwrap().unwrap(), "ab");
+        assert_matches!(frames.next(), Some(Err(DecodeError::Decode(_))));
+        assert_eq!(frames.next().unwrap().unwrap(), "e");
+        assert_matches!(frames.next(), Some(Err(DecodeError::FrameTooLong { .. })));
+        assert_eq!(frames.next().unwrap().unwrap(), "l");
+        assert_matches!(frames.next(), None);
+    }
+
+    #[test]
+    fn test_input_error() {
+        let input = stream::iter_result(vec![
+            Ok(Bytes::from_static(b"ab\n")),
+            Err(io::Error::other("input failed")),
+            Ok(Bytes::from_static(b"cd\n")),
+        ]);
+        let mut frames = decode(input, LineDecoder)
+            .on_error(OnDecodeError::SkipTo(b'\n'))
+            .wait();
+        assert_eq!(frames.next().unwrap().unwrap(), "ab");
+        assert_matches!(frames.next(), Some(Err(DecodeError::Decode(_))));
+        assert_matches!(frames.next(), None);
+    }
+
+    #[test]
+    fn test_skip_with_stateful_decoder() {
+        // LinesCodec remembers how far it looked for a newline, which must
+        // be forgotten once the bytes it looked at are discarded.
+        let mut frames = decode(chunks(&[b"ab\n", b"cdefgh", b"i\nj\n"]), LinesCodec::new())
+            .with_max_buffered(4)
+            .on_error(OnDecodeError::SkipTo(b'\n'))
+            .wait();
+        assert_eq!(frames.next().unwrap().unwrap(), "ab");
+        assert_matches!(frames.next(), Some(Err(DecodeError::FrameTooLong { .. })));
+        assert_eq!(frames.next().unwrap().unwrap(), "j");
+        assert_matches!(frames.next(), None);
+    }
+}
@@ -0,0 +1,251 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Definition of the FuturesOrderedBuffer combinator, a variant of
+//! [futures::stream::FuturesOrdered] that releases the memory it retains after
+//! bursts of futures.
+
+use std::cmp::Ordering;
+use std::collections::binary_heap::PeekMut;
+use std::collections::BinaryHeap;
+use std::fmt;
+
+use futures::stream::FuturesUnordered;
+use futures::try_ready;
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use futures::Stream;
+
+/// Number of results yielded between two attempts at releasing excess
+/// capacity while the queue of completed results doesn't drain.
+const SHRINK_INTERVAL: usize = 1024;
+
+/// Like [futures::stream::FuturesOrdered], runs the futures pushed to it
+/// concurrently and yields their results in the order they were pushed.
+///
+/// Results completed ahead of their turn are queued, and the allocation of
+/// that queue grows with the largest burst of such results. Unlike
+/// [futures::stream::FuturesOrdered], this buffer shrinks the queue back
+/// towards its capacity hint whenever it drains and periodically while it
+/// doesn't, so that long-lived streams don't hold onto it.
+#[must_use = "streams do nothing unless polled"]
+pub struct FuturesOrderedBuffer<T>
+where
+    T: Future,
+{
+    in_progress: FuturesUnordered<OrderWrapper<T>>,
+    queued_results: BinaryHeap<OrderWrapper<T::Item>>,
+    next_incoming_index: usize,
+    next_outgoing_index: usize,
+    yielded_since_shrink: usize,
+    capacity_hint: usize,
+    high_watermark: usize,
+}
+
+impl<T> FuturesOrderedBuffer<T>
+where
+    T: Future,
+{
+    /// Create an empty buffer.
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Create an empty buffer with room for `capacity` queued results, which
+    /// is kept when releasing excess capacity.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            in_progress: FuturesUnordered::new(),
+            queued_results: BinaryHeap::with_capacity(capacity),
+            next_incoming_index: 0,
+            next_outgoing_index: 0,
+            yielded_since_shrink: 0,
+            capacity_hint: capacity,
+            high_watermark: 0,
+        }
+    }
+
+    /// Returns the number of futures in the buffer, either running or
+    /// completed but not yet yielded.
+    pub fn len(&self) -> usize {
+        self.in_progress.len() + self.queued_results.len()
+    }
+
+    /// Returns true if there are no futures in the buffer.
+    pub fn is_empty(&self) -> bool {
+        self.in_progress.is_empty() && self.queued_results.is_empty()
+    }
+
+    /// Returns the largest number of futures that were in the buffer at the
+    /// same time.
+    pub fn high_watermark(&self) -> usize {
+        self.high_watermark
+    }
+
+    /// Push a future to the buffer. Its result will be yielded after the
+    /// results of all futures pushed before it.
+    pub fn push(&mut self, future: T) {
+        let wrapped = OrderWrapper {
+            item: future,
+            index: self.next_incoming_index,
+        };
+        self.next_incoming_index += 1;
+        self.in_progress.push(wrapped);
+        self.high_watermark = self.high_watermark.max(self.len());
+    }
+
+    /// Release the capacity of the queue of completed results in excess of
+    /// its current length or of the capacity hint, whichever is larger.
+    pub fn shrink_to_fit(&mut self) {
+        let capacity = self.queued_results.len().max(self.capacity_hint);
+        self.queued_results.shrink_to(capacity);
+        self.yielded_since_shrink = 0;
+    }
+}
+
+impl<T> Default for FuturesOrderedBuffer<T>
+where
+    T: Future,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Stream for FuturesOrderedBuffer<T>
+where
+    T: Future,
+{
+    type Item = T::Item;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        // Get any completed futures from the unordered set.
+        while let Async::Ready(Some(result)) = self.in_progress.poll()? {
+            self.queued_results.push(result);
+        }
+
+        let next_result = match self.queued_results.peek_mut() {
+            Some(next_result) if next_result.index == self.next_outgoing_index => {
+                PeekMut::pop(next_result)
+            }
+            Some(_) => return Ok(Async::NotReady),
+            None if !self.in_progress.is_empty() => return Ok(Async::NotReady),
+            None => return Ok(Async::Ready(None)),
+        };
+        self.next_outgoing_index += 1;
+        self.yielded_since_shrink += 1;
+        if self.queued_results.is_empty() || self.yielded_since_shrink >= SHRINK_INTERVAL {
+            self.shrink_to_fit();
+        }
+        Ok(Async::Ready(Some(next_result.item)))
+    }
+}
+
+impl<T> fmt::Debug for FuturesOrderedBuffer<T>
+where
+    T: Future,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("FuturesOrderedBuffer")
+            .field("len", &self.len())
+            .field("high_watermark", &self.high_watermark)
+            .finish()
+    }
+}
+
+struct OrderWrapper<T> {
+    item: T,
+    index: usize,
+}
+
+impl<T> PartialEq for OrderWrapper<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for OrderWrapper<T> {}
+
+impl<T> PartialOrd for OrderWrapper<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for OrderWrapper<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max heap, so compare backwards here.
+        other.index.cmp(&self.index)
+    }
+}
+
+impl<T> Future for OrderWrapper<T>
+where
+    T: Future,
+{
+    type Item = OrderWrapper<T::Item>;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let item = try_ready!(self.item.poll());
+        Ok(Async::Ready(OrderWrapper {
+            item,
+            index: self.index,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::future;
+    use futures::sync::oneshot;
+
+    use super::*;
+
+    #[test]
+    fn test_ordered() {
+        let mut buffer = FuturesOrderedBuffer::new();
+        for i in 0..3 {
+            buffer.push(future::ok::<_, ()>(i));
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.collect().wait(), Ok(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn test_shrinks_after_burst() {
+        futures::executor::spawn(future::lazy(|| {
+            let mut buffer = FuturesOrderedBuffer::with_capacity(4);
+            let (first_tx, first_rx) = oneshot::channel::<usize>();
+            buffer.push(future::Either::A(first_rx));
+            for i in 1..100 {
+                buffer.push(future::Either::B(future::ok::<_, oneshot::Canceled>(i)));
+            }
+
+            // All but the first future complete, and are queued waiting for it.
+            assert_eq!(buffer.poll(), Ok(Async::NotReady));
+            assert_eq!(buffer.queued_results.len(), 99);
+            assert!(buffer.queued_results.capacity() >= 99);
+
+            first_tx.send(0).unwrap();
+            for i in 0..100 {
+                assert_eq!(buffer.poll(), Ok(Async::Ready(Some(i))));
+            }
+            assert_eq!(buffer.poll(), Ok(Async::Ready(None)));
+
+            assert!(buffer.queued_results.capacity() < 99);
+            assert_eq!(buffer.high_watermark(), 100);
+            Ok::<_, ()>(())
+        }))
+        .wait_future()
+        .unwrap();
+    }
+}
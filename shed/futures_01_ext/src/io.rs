@@ -0,0 +1,377 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module with [StreamReader], reading a stream of [Bytes] as a tokio 0.1
+//! `AsyncRead`, and the [BufferPool] its [StreamReader::read_exact] and
+//! [StreamReader::read_until] helpers read into.
+
+use std::cmp;
+use std::io;
+use std::io::Read;
+use std::mem;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use bytes::Buf;
+use bytes::Bytes;
+use futures::try_ready;
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use futures::Stream;
+use tokio_io::AsyncRead;
+
+/// A pool of byte buffers reused across reads, so that reading many frames
+/// doesn't allocate a new buffer for each of them. Cloning the pool shares
+/// its buffers.
+#[derive(Clone, Debug)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_buffers: usize,
+}
+
+impl BufferPool {
+    /// Create a new pool keeping at most `max_buffers` unused buffers, the
+    /// buffers returned to a full pool are freed.
+    pub fn new(max_buffers: usize) -> Self {
+        Self {
+            buffers: Arc::new(Mutex::new(Vec::new())),
+            max_buffers,
+        }
+    }
+
+    /// Take an empty buffer from the pool, or allocate a new one if the pool
+    /// is empty. The buffer returns to the pool when dropped.
+    pub fn get(&self) -> PooledBuffer {
+        let buf = self
+            .buffers
+            .lock()
+            .expect("poisoned lock")
+            .pop()
+            .unwrap_or_default();
+        PooledBuffer {
+            buf,
+            pool: Some(self.clone()),
+        }
+    }
+
+    fn put(&self, mut buf: Vec<u8>) {
+        let mut buffers = self.buffers.lock().expect("poisoned lock");
+        if buffers.len() < self.max_buffers {
+            buf.clear();
+            buffers.push(buf);
+        }
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(16)
+    }
+}
+
+/// A buffer taken from a [BufferPool], to which it returns when dropped.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buf: Vec<u8>,
+    pool: Option<BufferPool>,
+}
+
+impl PooledBuffer {
+    /// Take the buffer out of its pool, keeping its content.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        self.pool = None;
+        mem::take(&mut self.buf)
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.put(mem::take(&mut self.buf));
+        }
+    }
+}
+
+/// Reader over a stream of [Bytes], implementing [Read] and tokio 0.1
+/// `AsyncRead` by copying out the chunks of the stream as they come, without
+/// concatenating them.
+#[derive(Debug)]
+pub struct StreamReader<S> {
+    stream: S,
+    chunk: Bytes,
+    pool: BufferPool,
+    done: bool,
+}
+
+impl<S> StreamReader<S>
+where
+    S: Stream<Item = Bytes, Error = io::Error>,
+{
+    /// Create a new reader over `stream`, with a default [BufferPool].
+    pub fn new(stream: S) -> Self {
+        Self::with_pool(stream, BufferPool::default())
+    }
+
+    /// Create a new reader over `stream`, whose helpers read into the
+    /// buffers of `pool`.
+    pub fn with_pool(stream: S, pool: BufferPool) -> Self {
+        Self {
+            stream,
+            chunk: Bytes::new(),
+            pool,
+            done: false,
+        }
+    }
+
+    /// Return the underlying stream and the part of its last chunk that
+    /// wasn't read yet.
+    pub fn into_inner(self) -> (S, Bytes) {
+        (self.stream, self.chunk)
+    }
+
+    /// Return a future reading exactly `len` bytes into a buffer of the
+    /// pool, failing with [io::ErrorKind::UnexpectedEof] if the stream ends
+    /// before that. The future resolves to this reader and the buffer.
+    pub fn read_exact(self, len: usize) -> ReadExact<S> {
+        let buf = self.pool.get();
+        ReadExact {
+            reader: Some(self),
+            buf: Some(buf),
+            len,
+        }
+    }
+
+    /// Return a future reading into a buffer of the pool up to and including
+    /// the first `delim` byte, or up to the end of the stream if there is
+    /// none. The future resolves to this reader and the buffer.
+    pub fn read_until(self, delim: u8) -> ReadUntil<S> {
+        let buf = self.pool.get();
+        ReadUntil {
+            reader: Some(self),
+            buf: Some(buf),
+            delim,
+        }
+    }
+
+    /// Poll the stream until there are bytes to read in the current chunk,
+    /// resolving to false once the stream has ended.
+    fn poll_chunk(&mut self) -> Poll<bool, io::Error> {
+        while self.chunk.is_empty() {
+            if self.done {
+                return Ok(Async::Ready(false));
+            }
+            match try_ready!(self.stream.poll()) {
+                Some(chunk) => self.chunk = chunk,
+                None => self.done = true,
+            }
+        }
+        Ok(Async::Ready(true))
+    }
+}
+
+impl<S> Read for StreamReader<S>
+where
+    S: Stream<Item = Bytes, Error = io::Error>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.poll_chunk()? {
+            Async::Ready(true) => {
+                let len = cmp::min(buf.len(), self.chunk.len());
+                buf[..len].copy_from_slice(&self.chunk[..len]);
+                self.chunk.advance(len);
+                Ok(len)
+            }
+            Async::Ready(false) => Ok(0),
+            Async::NotReady => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl<S> AsyncRead for StreamReader<S> where S: Stream<Item = Bytes, Error = io::Error> {}
+
+/// Future returned by [StreamReader::read_exact]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ReadExact<S> {
+    reader: Option<StreamReader<S>>,
+    buf: Option<PooledBuffer>,
+    len: usize,
+}
+
+impl<S> Future for ReadExact<S>
+where
+    S: Stream<Item = Bytes, Error = io::Error>,
+{
+    type Item = (StreamReader<S>, PooledBuffer);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let reader = self.reader.as_mut().expect("polled after completion");
+        let buf = self.buf.as_mut().expect("polled after completion");
+        while buf.len() < self.len {
+            if !try_ready!(reader.poll_chunk()) {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let len = cmp::min(self.len - buf.len(), reader.chunk.len());
+            buf.extend_from_slice(&reader.chunk[..len]);
+            reader.chunk.advance(len);
+        }
+        Ok(Async::Ready((
+            self.reader.take().unwrap(),
+            self.buf.take().unwrap(),
+        )))
+    }
+}
+
+/// Future returned by [StreamReader::read_until]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ReadUntil<S> {
+    reader: Option<StreamReader<S>>,
+    buf: Option<PooledBuffer>,
+    delim: u8,
+}
+
+impl<S> Future for ReadUntil<S>
+where
+    S: Stream<Item = Bytes, Error = io::Error>,
+{
+    type Item = (StreamReader<S>, PooledBuffer);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let reader = self.reader.as_mut().expect("polled after completion");
+        let buf = self.buf.as_mut().expect("polled after completion");
+        while try_ready!(reader.poll_chunk()) {
+            let (len, found) = match reader.chunk.iter().position(|b| *b == self.delim) {
+                Some(pos) => (pos + 1, true),
+                None => (reader.chunk.len(), false),
+            };
+            buf.extend_from_slice(&reader.chunk[..len]);
+            reader.chunk.advance(len);
+            if found {
+                break;
+            }
+        }
+        Ok(Async::Ready((
+            self.reader.take().unwrap(),
+            self.buf.take().unwrap(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::stream;
+    use futures::sync::mpsc;
+    use futures::Sink;
+
+    use super::*;
+
+    fn chunks(chunks: &[&'static [u8]]) -> impl Stream<Item = Bytes, Error = io::Error> {
+        stream::iter_ok(
+            chunks
+                .iter()
+                .map(|c| Bytes::from_static(c))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn test_read() {
+        let mut reader = StreamReader::new(chunks(&[b"hel", b"", b"lo"]));
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"hello");
+    }
+
+    #[test]
+    fn test_read_would_block() {
+        let (sender, receiver) = mpsc::channel::<Bytes>(1);
+        let receiver = receiver.map_err(|()| io::Error::other("receiver failed"));
+        let mut reader = StreamReader::new(receiver);
+        let mut buf = [0; 8];
+
+        let res = futures::future::lazy(|| Ok::<_, ()>(reader.read(&mut buf)))
+            .wait()
+            .unwrap();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        let sender = sender.send(Bytes::from_static(b"abc")).wait().unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+        drop(sender);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_read_exact() {
+        let reader = StreamReader::new(chunks(&[b"ab", b"cde", b"f"]));
+        let (reader, buf) = reader.read_exact(3).wait().unwrap();
+        assert_eq!(&buf[..], b"abc");
+        let (reader, buf) = reader.read_exact(2).wait().unwrap();
+        assert_eq!(&buf[..], b"de");
+        let err = reader.read_exact(2).wait().map(|_| ()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_read_until() {
+        let reader = StreamReader::new(chunks(&[b"ab\nc", b"d", b"e\nf"]));
+        let (reader, buf) = reader.read_until(b'\n').wait().unwrap();
+        assert_eq!(&buf[..], b"ab\n");
+        let (reader, buf) = reader.read_until(b'\n').wait().unwrap();
+        assert_eq!(&buf[..], b"cde\n");
+        let (reader, buf) = reader.read_until(b'\n').wait().unwrap();
+        assert_eq!(&buf[..], b"f");
+        let (_, buf) = reader.read_until(b'\n').wait().unwrap();
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_buffer_pool() {
+        let pool = BufferPool::new(1);
+        let mut buf = pool.get();
+        buf.extend_from_slice(b"hello");
+        let ptr = buf.as_ptr();
+        drop(buf);
+
+        // The buffer is reused, but cleared.
+        let buf = pool.get();
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+
+        // Only one buffer is kept.
+        let other = pool.get();
+        drop(buf);
+        drop(other);
+        assert_eq!(pool.buffers.lock().unwrap().len(), 1);
+
+        // Buffers taken out of the pool don't return to it.
+        let mut buf = pool.get();
+        buf.push(1);
+        assert_eq!(buf.into_vec(), vec![1]);
+        assert!(pool.buffers.lock().unwrap().is_empty());
+    }
+}
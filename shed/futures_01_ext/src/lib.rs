@@ -13,6 +13,7 @@
 //! Crate extending functionality of [`futures`] crate
 
 use std::fmt::Debug;
+use std::time::Duration;
 
 use futures::future;
 use futures::stream;
@@ -25,7 +26,10 @@ use futures::Poll;
 use futures::Sink;
 use futures::Stream;
 
+pub mod decode;
 mod futures_ordered;
+mod futures_ordered_buffer;
+pub mod io;
 mod select_all;
 mod split_err;
 mod stream_wrappers;
@@ -37,6 +41,7 @@ pub use futures as futures_reexport;
 
 pub use crate::futures_ordered::futures_ordered;
 pub use crate::futures_ordered::FuturesOrdered;
+pub use crate::futures_ordered_buffer::FuturesOrderedBuffer;
 pub use crate::select_all::select_all;
 pub use crate::select_all::SelectAll;
 pub use crate::split_err::split_err;
@@ -252,7 +257,8 @@ pub trait FutureExt: Future + Sized {
 
 impl<T> FutureExt for T where T: Future {}
 
-/// Params for [StreamExt::buffered_weight_limited] and [WeightLimitedBufferedStream]
+/// Params for [StreamExt::buffered_weight_limited], [StreamExt::buffer_unordered_weight_limited],
+/// [WeightLimitedBufferedStream] and [WeightLimitedBufferUnorderedStream]
 pub struct BufferedParams {
     /// Limit for the sum of weights in the [WeightLimitedBufferedStream] stream
     pub weight_limit: u64,
@@ -260,6 +266,40 @@ pub struct BufferedParams {
     pub buffer_size: usize,
 }
 
+impl BufferedParams {
+    /// Check that a [WeightLimitedBufferedStream] can make progress with
+    /// these params, i.e. that neither limit is zero.
+    pub fn validate(&self) -> Result<(), BufferedParamsError> {
+        if self.weight_limit == 0 {
+            return Err(BufferedParamsError::ZeroWeightLimit);
+        }
+        if self.buffer_size == 0 {
+            return Err(BufferedParamsError::ZeroBufferSize);
+        }
+        Ok(())
+    }
+}
+
+/// Error that can be returned by [BufferedParams::validate]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BufferedParamsError {
+    /// The weight limit is zero, so no future would ever be buffered
+    ZeroWeightLimit,
+    /// The buffer size is zero, so no future would ever be buffered
+    ZeroBufferSize,
+}
+
+impl ::std::error::Error for BufferedParamsError {}
+
+impl ::std::fmt::Display for BufferedParamsError {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            BufferedParamsError::ZeroWeightLimit => write!(fmt, "weight limit is zero"),
+            BufferedParamsError::ZeroBufferSize => write!(fmt, "buffer size is zero"),
+        }
+    }
+}
+
 /// A trait implemented by default for all Streams which extends the standard
 /// functionality.
 pub trait StreamExt: Stream {
@@ -288,6 +328,27 @@ pub trait StreamExt: Stream {
         streamfork::streamfork(self, out1, out2, pred)
     }
 
+    /// Fork elements in a stream out to any number of sinks, sending each item
+    /// to the sink at the index returned by the predicate.
+    ///
+    /// Like [StreamExt::streamfork], this keeps operating until the input
+    /// stream ends, and then returns the stream and the sinks in the resulting
+    /// Future. The future panics if the predicate returns an index past the
+    /// end of `outs`.
+    fn streamfork_n<Out, F, E>(
+        self,
+        outs: Vec<Out>,
+        pred: F,
+    ) -> streamfork::ForkerN<Self, Out, F, E>
+    where
+        Self: Sized,
+        Out: Sink<SinkItem = Self::Item>,
+        F: FnMut(&Self::Item) -> Result<usize, E>,
+        E: From<Self::Error> + From<Out::SinkError>,
+    {
+        streamfork::streamfork_n(self, outs, pred)
+    }
+
     /// Returns a future that yields a `(Vec<<Self>::Item>, Self)`, where the
     /// vector is a collections of all elements yielded by the Stream.
     fn collect_no_consume(self) -> CollectNoConsume<Self>
@@ -390,7 +451,22 @@ pub trait StreamExt: Stream {
         BatchStream::new(self, limit)
     }
 
+    /// Like [StreamExt::batch], but keeps filling the batch while the stream
+    /// isn't ready, until it reaches `limit` items or `max_delay` elapsed
+    /// since its first item.
+    ///
+    /// The delay is measured with the timer of the Tokio 1.x runtime this is
+    /// called within, and panics if there is none. Use
+    /// [BatchWithTimeoutStream::with_delay] to measure it otherwise.
+    fn batch_with_timeout(self, limit: usize, max_delay: Duration) -> BatchWithTimeoutStream<Self>
+    where
+        Self: Sized,
+    {
+        BatchWithTimeoutStream::new(self, limit, max_delay)
+    }
+
     /// Like [Stream::buffered] call, but can also limit number of futures in a buffer by "weight".
+    /// See [WeightLimitedBufferedStream::new] for how invalid `params` are handled.
     fn buffered_weight_limited<I, E, Fut>(
         self,
         params: BufferedParams,
@@ -403,6 +479,21 @@ pub trait StreamExt: Stream {
         WeightLimitedBufferedStream::new(params, self)
     }
 
+    /// Like [Stream::buffer_unordered] call, but can also limit number of futures in a buffer by
+    /// "weight". See [WeightLimitedBufferUnorderedStream::new] for how invalid `params` are
+    /// handled.
+    fn buffer_unordered_weight_limited<I, E, Fut>(
+        self,
+        params: BufferedParams,
+    ) -> WeightLimitedBufferUnorderedStream<Self, I, E>
+    where
+        Self: Sized + Send + 'static,
+        Self: Stream<Item = (Fut, u64), Error = E>,
+        Fut: Future<Item = I, Error = E>,
+    {
+        WeightLimitedBufferUnorderedStream::new(params, self)
+    }
+
     /// Returns a Future that yields a collection `C` containing all `Self::Item`
     /// yielded by the stream
     fn collect_to<C: Default + Extend<Self::Item>>(self) -> CollectTo<Self, C>
@@ -417,7 +508,7 @@ impl<T> StreamExt for T where T: Stream {}
 
 /// Like [stream::Buffered], but can also limit number of futures in a buffer by "weight".
 pub struct WeightLimitedBufferedStream<S, I, E> {
-    queue: stream::FuturesOrdered<BoxFuture<(I, u64), E>>,
+    queue: FuturesOrderedBuffer<BoxFuture<(I, u64), E>>,
     current_weight: u64,
     weight_limit: u64,
     max_buffer_size: usize,
@@ -428,16 +519,31 @@ impl<S, I, E> WeightLimitedBufferedStream<S, I, E>
 where
     S: Stream,
 {
-    /// Create a new instance that will be configured using the `params` provided
+    /// Create a new instance that will be configured using the `params` provided.
+    /// Limits of zero, which [BufferedParams::validate] rejects, are raised to one
+    /// so that the stream still makes progress.
     pub fn new(params: BufferedParams, stream: S) -> Self {
         Self {
-            queue: stream::FuturesOrdered::new(),
+            queue: FuturesOrderedBuffer::new(),
             current_weight: 0,
-            weight_limit: params.weight_limit,
-            max_buffer_size: params.buffer_size,
+            weight_limit: params.weight_limit.max(1),
+            max_buffer_size: params.buffer_size.max(1),
             stream: stream.fuse(),
         }
     }
+
+    /// Like [Self::new], but returns an error if the `params` are invalid,
+    /// see [BufferedParams::validate].
+    pub fn try_new(params: BufferedParams, stream: S) -> Result<Self, BufferedParamsError> {
+        params.validate()?;
+        Ok(Self::new(params, stream))
+    }
+
+    /// Returns the largest number of futures that were buffered at the same
+    /// time, to help sizing `buffer_size` and `weight_limit`.
+    pub fn buffer_high_watermark(&self) -> usize {
+        self.queue.high_watermark()
+    }
 }
 
 impl<S, Fut, I: 'static, E: 'static> Stream for WeightLimitedBufferedStream<S, I, E>
@@ -454,6 +560,10 @@ where
         while self.queue.len() < self.max_buffer_size && self.current_weight < self.weight_limit {
             let future = match self.stream.poll()? {
                 Async::Ready(Some((s, weight))) => {
+                    // Weights come from the caller and may add up beyond
+                    // u64::MAX, so count the part of the weight that fits,
+                    // which is then released when the future completes.
+                    let weight = weight.min(u64::MAX - self.current_weight);
                     self.current_weight += weight;
                     s.map(move |val| (val, weight)).boxify()
                 }
@@ -480,6 +590,88 @@ where
     }
 }
 
+/// Like [stream::BufferUnordered], but can also limit number of futures in a buffer by "weight".
+/// The results are returned as soon as their futures complete, whatever their order in the stream.
+pub struct WeightLimitedBufferUnorderedStream<S, I, E> {
+    queue: stream::FuturesUnordered<BoxFuture<(I, u64), E>>,
+    current_weight: u64,
+    weight_limit: u64,
+    max_buffer_size: usize,
+    high_watermark: usize,
+    stream: stream::Fuse<S>,
+}
+
+impl<S, I, E> WeightLimitedBufferUnorderedStream<S, I, E>
+where
+    S: Stream,
+{
+    /// Create a new instance that will be configured using the `params` provided.
+    /// Limits of zero, which [BufferedParams::validate] rejects, are raised to one
+    /// so that the stream still makes progress.
+    pub fn new(params: BufferedParams, stream: S) -> Self {
+        Self {
+            queue: stream::FuturesUnordered::new(),
+            current_weight: 0,
+            weight_limit: params.weight_limit.max(1),
+            max_buffer_size: params.buffer_size.max(1),
+            high_watermark: 0,
+            stream: stream.fuse(),
+        }
+    }
+
+    /// Like [Self::new], but returns an error if the `params` are invalid,
+    /// see [BufferedParams::validate].
+    pub fn try_new(params: BufferedParams, stream: S) -> Result<Self, BufferedParamsError> {
+        params.validate()?;
+        Ok(Self::new(params, stream))
+    }
+
+    /// Returns the largest number of futures that were buffered at the same
+    /// time, to help sizing `buffer_size` and `weight_limit`.
+    pub fn buffer_high_watermark(&self) -> usize {
+        self.high_watermark
+    }
+}
+
+impl<S, Fut, I: 'static, E: 'static> Stream for WeightLimitedBufferUnorderedStream<S, I, E>
+where
+    S: Stream<Item = (Fut, u64), Error = E>,
+    Fut: Future<Item = I, Error = E> + Send + 'static,
+{
+    type Item = I;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, E> {
+        // Buffer as many futures as the limits allow, like
+        // WeightLimitedBufferedStream does.
+        while self.queue.len() < self.max_buffer_size && self.current_weight < self.weight_limit {
+            let future = match self.stream.poll()? {
+                Async::Ready(Some((s, weight))) => {
+                    let weight = weight.min(u64::MAX - self.current_weight);
+                    self.current_weight += weight;
+                    s.map(move |val| (val, weight)).boxify()
+                }
+                Async::Ready(None) | Async::NotReady => break,
+            };
+
+            self.queue.push(future);
+            self.high_watermark = self.high_watermark.max(self.queue.len());
+        }
+
+        // Return the result of any future that completed
+        if let Some((val, weight)) = try_ready!(self.queue.poll()) {
+            self.current_weight -= weight;
+            return Ok(Async::Ready(Some(val)));
+        }
+
+        if self.stream.is_done() {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
 /// Like [std::iter::Enumerate], but for Stream
 pub struct Enumerate<In> {
     inner: In,
@@ -624,13 +816,10 @@ impl<In: Stream> Stream for ReturnRemainder<In> {
         };
 
         if maybe_item.is_none() {
-            let inner = self
-                .inner
-                .take()
-                .expect("inner was just polled, should be some");
-            let send = self.send.take().expect("send is None iff inner is None");
-            // The Receiver will handle errors
-            let _ = send.send(inner);
+            if let (Some(inner), Some(send)) = (self.inner.take(), self.send.take()) {
+                // The Receiver will handle errors
+                let _ = send.send(inner);
+            }
         }
 
         Ok(Async::Ready(maybe_item))
@@ -740,12 +929,13 @@ where
 }
 
 impl<S: Stream> BatchStream<S> {
-    /// Return an instance of [BatchStream] wrapping a Stream with the provided limit set
+    /// Return an instance of [BatchStream] wrapping a Stream with the provided limit set.
+    /// A limit of zero is raised to one, as no batch could be returned otherwise.
     pub fn new(s: S, limit: usize) -> Self {
         Self {
             inner: s.fuse(),
             err: None,
-            limit,
+            limit: limit.max(1),
         }
     }
 }
@@ -788,6 +978,150 @@ impl<S: Stream> Stream for BatchStream<S> {
     }
 }
 
+/// It's a combinator that converts `Stream<A>` into `Stream<Vec<A>>`, like
+/// [BatchStream], but which doesn't return a partial batch as soon as the
+/// underlying stream returns NotReady: the batch is returned once it has
+/// `limit` items, or once `max_delay` elapsed since its first item, so that
+/// sparse streams are batched without delaying their items for too long.
+///
+/// The delay is measured by the futures returned by a function called with
+/// `max_delay` for each batch, which resolve once it elapsed.
+pub struct BatchWithTimeoutStream<S>
+where
+    S: Stream,
+{
+    inner: stream::Fuse<S>,
+    err: Option<S::Error>,
+    limit: usize,
+    max_delay: Duration,
+    batch: Vec<S::Item>,
+    new_delay: Box<dyn FnMut(Duration) -> BoxFuture<(), ()> + Send>,
+    deadline: Option<BoxFuture<(), ()>>,
+}
+
+impl<S: Stream> BatchWithTimeoutStream<S> {
+    /// Return an instance of [BatchWithTimeoutStream] wrapping a Stream with
+    /// the provided limit and delay set. A limit of zero is raised to one, as
+    /// no batch could be returned otherwise.
+    ///
+    /// The delay is measured with the timer of the Tokio 1.x runtime this is
+    /// called within, and the stream can then be polled from any thread, e.g.
+    /// with `wait()`, as long as the runtime is alive.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called within a Tokio 1.x runtime.
+    pub fn new(s: S, limit: usize, max_delay: Duration) -> Self {
+        let handle = tokio::runtime::Handle::try_current().expect(
+            "batch_with_timeout must be called within a Tokio 1.x runtime, \
+            use BatchWithTimeoutStream::with_delay otherwise",
+        );
+        Self::with_delay(s, limit, max_delay, move |delay| {
+            let _guard = handle.enter();
+            Box::new(TokioDelay(Box::pin(tokio::time::sleep(delay))))
+        })
+    }
+
+    /// Same as [BatchWithTimeoutStream::new], measuring the delay with the
+    /// futures returned by `new_delay`, e.g. to use another timer than the
+    /// one of a Tokio 1.x runtime.
+    pub fn with_delay<F>(s: S, limit: usize, max_delay: Duration, new_delay: F) -> Self
+    where
+        F: FnMut(Duration) -> BoxFuture<(), ()> + Send + 'static,
+    {
+        Self {
+            inner: s.fuse(),
+            err: None,
+            limit: limit.max(1),
+            max_delay,
+            batch: vec![],
+            new_delay: Box::new(new_delay),
+            deadline: None,
+        }
+    }
+
+    fn take_batch(&mut self) -> Vec<S::Item> {
+        self.deadline = None;
+        std::mem::take(&mut self.batch)
+    }
+}
+
+impl<S: Stream> Stream for BatchWithTimeoutStream<S> {
+    type Item = Vec<S::Item>;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.batch.is_empty() {
+            if let Some(err) = self.err.take() {
+                return Err(err);
+            }
+        }
+
+        while self.batch.len() < self.limit && self.err.is_none() {
+            match self.inner.poll() {
+                Ok(Async::Ready(Some(v))) => {
+                    if self.batch.is_empty() {
+                        self.deadline = Some((self.new_delay)(self.max_delay));
+                    }
+                    self.batch.push(v);
+                }
+                Ok(Async::NotReady) | Ok(Async::Ready(None)) => break,
+                Err(err) => self.err = Some(err),
+            }
+        }
+
+        if self.batch.is_empty() {
+            if let Some(err) = self.err.take() {
+                return Err(err);
+            }
+            return if self.inner.is_done() {
+                Ok(Async::Ready(None))
+            } else {
+                Ok(Async::NotReady)
+            };
+        }
+
+        let full = self.batch.len() >= self.limit || self.err.is_some() || self.inner.is_done();
+        let expired = match &mut self.deadline {
+            Some(deadline) => deadline.poll() != Ok(Async::NotReady),
+            None => true,
+        };
+        if full || expired {
+            Ok(Async::Ready(Some(self.take_batch())))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Future resolving once a [tokio::time::Sleep] completes, polled in a
+/// futures 0.1 task.
+struct TokioDelay(std::pin::Pin<Box<tokio::time::Sleep>>);
+
+/// Notifies a futures 0.1 task when woken.
+struct NotifyTask(futures::task::Task);
+
+impl std::task::Wake for NotifyTask {
+    fn wake(self: std::sync::Arc<Self>) {
+        self.0.notify();
+    }
+}
+
+impl Future for TokioDelay {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        let waker =
+            std::task::Waker::from(std::sync::Arc::new(NotifyTask(futures::task::current())));
+        let mut cx = std::task::Context::from_waker(&waker);
+        match std::future::Future::poll(self.0.as_mut(), &mut cx) {
+            std::task::Poll::Ready(()) => Ok(Async::Ready(())),
+            std::task::Poll::Pending => Ok(Async::NotReady),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::sync::atomic::AtomicUsize;
@@ -1007,6 +1341,150 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_buffered_large_weights() {
+        // The sum of the weights overflows u64.
+        let s = stream::iter_ok::<_, ()>(vec![
+            (future::ok::<_, ()>(1).boxify(), u64::MAX),
+            (future::ok(2).boxify(), u64::MAX),
+            (future::ok(3).boxify(), 5),
+        ]);
+        let params = BufferedParams {
+            weight_limit: u64::MAX,
+            buffer_size: 10,
+        };
+        let res = s.buffered_weight_limited(params).collect().wait();
+        assert_eq!(res, Ok(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_buffered_zero_params() {
+        let zero_weight = BufferedParams {
+            weight_limit: 0,
+            buffer_size: 10,
+        };
+        assert_eq!(
+            zero_weight.validate(),
+            Err(BufferedParamsError::ZeroWeightLimit)
+        );
+        let zero_size = BufferedParams {
+            weight_limit: 10,
+            buffer_size: 0,
+        };
+        assert_eq!(
+            zero_size.validate(),
+            Err(BufferedParamsError::ZeroBufferSize)
+        );
+
+        let s = || stream::iter_ok::<_, ()>(vec![(future::ok::<_, ()>(1), 1), (future::ok(2), 1)]);
+        let res = WeightLimitedBufferedStream::<_, i32, ()>::try_new(zero_size, s());
+        assert_eq!(res.err(), Some(BufferedParamsError::ZeroBufferSize));
+        // The stream still makes progress, one future at a time.
+        let res = s().buffered_weight_limited(zero_weight).collect().wait();
+        assert_eq!(res, Ok(vec![1, 2]));
+    }
+
+    #[test]
+    fn test_buffer_unordered() {
+        let (sender, receiver) = oneshot::channel::<u32>();
+        let s = stream::iter_ok::<_, ()>(vec![
+            (receiver.map_err(|_| ()).boxify(), 1),
+            (future::ok(2).boxify(), 1),
+            (future::ok(3).boxify(), 10),
+            (future::ok(4).boxify(), 1),
+        ]);
+        let params = BufferedParams {
+            weight_limit: 5,
+            buffer_size: 10,
+        };
+        let mut s = s.buffer_unordered_weight_limited(params).wait();
+
+        // The first future is pending, the next ones complete first, until
+        // the heavy one stops the stream from buffering more.
+        assert_eq!(s.next(), Some(Ok(2)));
+        assert_eq!(s.next(), Some(Ok(3)));
+        sender.send(1).unwrap();
+        assert_eq!(s.next(), Some(Ok(1)));
+        assert_eq!(s.next(), Some(Ok(4)));
+        assert_eq!(s.next(), None);
+        assert_eq!(s.get_ref().buffer_high_watermark(), 3);
+
+        let s = stream::iter_ok::<_, ()>(vec![(future::ok::<_, ()>(1), 1), (future::ok(2), 1)]);
+        let zero_weight = BufferedParams {
+            weight_limit: 0,
+            buffer_size: 10,
+        };
+        let res = s
+            .buffer_unordered_weight_limited(zero_weight)
+            .collect()
+            .wait();
+        assert_eq!(res, Ok(vec![1, 2]));
+    }
+
+    #[test]
+    fn test_batch_zero_limit() {
+        let res = stream::iter_ok::<_, ()>(vec![1, 2])
+            .batch(0)
+            .collect()
+            .wait();
+        assert_eq!(res, Ok(vec![vec![1], vec![2]]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batch_with_timeout() {
+        let (sender, receiver) = mpsc::unbounded();
+        tokio::spawn(async move {
+            for (item, delay) in [(1, 0), (2, 10), (3, 100), (4, 0), (5, 0), (6, 0), (7, 100)] {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                sender.unbounded_send(item).unwrap();
+            }
+        });
+        let res = receiver
+            .batch_with_timeout(3, Duration::from_millis(50))
+            .collect()
+            .compat()
+            .await;
+        // The first batch is returned after the delay, the second one as soon
+        // as it is full, and the last one once the stream ends.
+        assert_eq!(res, Ok(vec![vec![1, 2], vec![3, 4, 5], vec![6], vec![7]]));
+    }
+
+    #[test]
+    fn test_batch_with_timeout_wait() {
+        let runtime = Runtime::new().unwrap();
+        let (sender, receiver) = mpsc::unbounded();
+        sender.unbounded_send(1).unwrap();
+        // The stream is created within the runtime, but polled outside of it.
+        let stream = {
+            let _guard = runtime.enter();
+            receiver.batch_with_timeout(3, Duration::from_millis(10))
+        };
+        let (batch, _stream) = stream.into_future().wait().map_err(|(err, _)| err).unwrap();
+        assert_eq!(batch, Some(vec![1]));
+    }
+
+    #[test]
+    #[should_panic(expected = "must be called within a Tokio 1.x runtime")]
+    fn test_batch_with_timeout_no_runtime() {
+        let _ = stream::iter_ok::<_, ()>(vec![1]).batch_with_timeout(3, Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_batch_with_delay() {
+        let (sender, receiver) = mpsc::unbounded();
+        let (expire, expired) = oneshot::channel();
+        let mut expired = Some(expired);
+        let new_delay =
+            move |_| -> BoxFuture<(), ()> { Box::new(expired.take().unwrap().map_err(|_| ())) };
+        let mut stream =
+            BatchWithTimeoutStream::with_delay(receiver, 3, Duration::ZERO, new_delay).wait();
+        sender.unbounded_send(1).unwrap();
+        sender.unbounded_send(2).unwrap();
+        // The batch is only returned once the delay expires.
+        std::thread::spawn(move || expire.send(()).unwrap());
+        assert_eq!(stream.next(), Some(Ok(vec![1, 2])));
+    }
+
     use std::collections::HashSet;
 
     fn assert_same_elements<I, T>(src: Vec<I>, iter: T)
@@ -208,6 +208,138 @@ where
     }
 }
 
+/// Fork a Stream into any number of Sinks
+///
+/// Returns a Future for a process that consumes items from a Stream and
+/// forwards each of them to the sink at the index returned by the predicate.
+///
+/// # Panics
+///
+/// The future panics if the predicate returns an index that isn't the index
+/// of one of the sinks.
+pub fn streamfork_n<In, O, F, E>(inp: In, outs: Vec<O>, pred: F) -> ForkerN<In, O, F, E>
+where
+    In: Stream,
+    O: Sink<SinkItem = In::Item>,
+    F: FnMut(&In::Item) -> Result<usize, E>,
+    E: From<In::Error> + From<O::SinkError>,
+{
+    ForkerN {
+        inp: Some(inp.fuse()),
+        outs: outs.into_iter().map(Out::new).collect(),
+        pred,
+        finished: None,
+    }
+}
+
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ForkerN<In, O, F, E>
+where
+    In: Stream,
+    O: Sink,
+{
+    inp: Option<Fuse<In>>,
+    outs: Vec<Out<O>>,
+    pred: F,
+    finished: Option<Result<(), E>>,
+}
+
+impl<In, O, F, E> ForkerN<In, O, F, E>
+where
+    In: Stream,
+    O: Sink,
+    E: From<In::Error> + From<O::SinkError>,
+{
+    fn inp_mut(&mut self) -> &mut Fuse<In> {
+        self.inp.as_mut().expect("Input after completion")
+    }
+
+    fn take_result(&mut self) -> (In, Vec<O>) {
+        let inp = self.inp.take().expect("Input missing in result");
+        let outs = self.outs.iter_mut().map(Out::take_result).collect();
+
+        (inp.into_inner(), outs)
+    }
+
+    fn poll_complete_all(&mut self) -> Poll<(), E> {
+        let mut ready = true;
+        for out in &mut self.outs {
+            ready &= out.poll_complete()?.is_ready();
+        }
+        if !ready {
+            return Ok(Async::NotReady);
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<In, O, F, E> Future for ForkerN<In, O, F, E>
+where
+    In: Stream,
+    O: Sink<SinkItem = In::Item>,
+    F: FnMut(&In::Item) -> Result<usize, E>,
+    E: From<In::Error> + From<O::SinkError>,
+{
+    type Item = (In, Vec<O>);
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.finished.is_some() {
+            // Polling input stream ended, possibly with an error.
+            // Let's make sure we send all already fetched data to the outputs
+            try_ready!(self.poll_complete_all());
+
+            let finished_res = self.finished.take().expect("is_some() returned false");
+            return finished_res.map(|()| Async::Ready(self.take_result()));
+        }
+
+        // Make sure all outputs are clear to accept new data
+        {
+            let mut ready = true;
+            for out in &mut self.outs {
+                ready &= out.push()?.is_ready();
+            }
+            if !ready {
+                return Ok(Async::NotReady);
+            }
+        }
+
+        // Read input and send to outputs until either input dries up or an output is full
+        loop {
+            match self.inp_mut().poll() {
+                Ok(Async::Ready(Some(item))) => {
+                    let index = (self.pred)(&item)?;
+                    let count = self.outs.len();
+                    let out = self.outs.get_mut(index).unwrap_or_else(|| {
+                        panic!("streamfork_n index {} out of {} outputs", index, count)
+                    });
+                    try_ready!(out.try_start_send(item))
+                }
+                Ok(Async::NotReady) => {
+                    for out in &mut self.outs {
+                        out.poll_complete()?;
+                    }
+                    return Ok(Async::NotReady);
+                }
+                Ok(Async::Ready(None)) => {
+                    if !self.poll_complete_all()?.is_ready() {
+                        self.finished = Some(Ok(()));
+                        return Ok(Async::NotReady);
+                    }
+                    return Ok(Async::Ready(self.take_result()));
+                }
+                Err(err) => {
+                    if !self.poll_complete_all()?.is_ready() {
+                        self.finished = Some(Err(err.into()));
+                        return Ok(Async::NotReady);
+                    }
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use futures::sink::Sink;
@@ -295,6 +427,47 @@ mod test {
         }
     }
 
+    #[test]
+    fn simple_n() {
+        let nums = iter_ok(0i32..10);
+        let outs = vec![Vec::new(), Vec::new(), Vec::new()];
+        let (_, outs) = streamfork_n(nums, outs, |n| Ok::<_, ()>(*n as usize % 3))
+            .wait()
+            .unwrap();
+
+        assert_eq!(outs, vec![vec![0, 3, 6, 9], vec![1, 4, 7], vec![2, 5, 8]]);
+    }
+
+    #[test]
+    fn delayed_poll_n() {
+        let outs = (0..3).map(|_| DelayedSink::new(5)).collect();
+
+        let nums = iter_ok(0u32..6).chain(once(Err(())));
+        let mut fork = streamfork_n(nums, outs, |n| Ok::<_, ()>(*n as usize % 3));
+        loop {
+            let res = fork.poll();
+            if res.is_err() {
+                let inner: Vec<_> = fork
+                    .outs
+                    .iter_mut()
+                    .map(|out| out.out_mut().inner.clone())
+                    .collect();
+                assert_eq!(inner, vec![vec![0, 3], vec![1, 4], vec![2, 5]]);
+                break;
+            }
+            if res.unwrap().is_ready() {
+                panic!("expected an error");
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "index 2 out of 2 outputs")]
+    fn out_of_bounds_n() {
+        let nums = iter_ok::<_, ()>(0i32..1);
+        let _ = streamfork_n(nums, vec![Vec::new(), Vec::new()], |_| Ok::<_, ()>(2)).wait();
+    }
+
     #[test]
     fn delayed_poll_with_err() {
         let even = DelayedSink::new(5);
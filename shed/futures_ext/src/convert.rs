@@ -0,0 +1,239 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Conversions between the futures, streams, sinks and IO objects of futures
+//! 0.1 and those of [`futures`] 0.3, for code migrated piecemeal from one to
+//! the other.
+//!
+//! The futures, streams and sinks are boxed, and their errors converted with
+//! [From], so that they can be passed directly to the code expecting the
+//! boxed types of the other version. The 0.1 objects converted from 0.3 ones
+//! must be polled in a futures 0.1 task, e.g. with `wait()` or on a tokio 0.1
+//! runtime, and the 0.3 objects converted from 0.1 ones in a futures 0.3
+//! task.
+
+use futures::compat::Compat;
+use futures::compat::Compat01As03;
+use futures::compat::Compat01As03Sink;
+use futures::compat::CompatSink;
+use futures::compat::Future01CompatExt;
+use futures::compat::Stream01CompatExt;
+use futures::future::BoxFuture;
+use futures::future::FutureExt;
+use futures::future::TryFuture;
+use futures::future::TryFutureExt;
+use futures::sink::Sink;
+use futures::sink::SinkExt;
+use futures::stream::BoxStream;
+use futures::stream::StreamExt;
+use futures::stream::TryStream;
+use futures::stream::TryStreamExt;
+use futures01::Future as Future01;
+use futures01::Sink as Sink01;
+use futures01::Stream as Stream01;
+
+/// Boxed futures 0.1 future, like the `BoxFuture` of `futures_01_ext`.
+pub type BoxFuture01<T, E> = Box<dyn Future01<Item = T, Error = E> + Send + 'static>;
+
+/// Boxed futures 0.1 stream, like the `BoxStream` of `futures_01_ext`.
+pub type BoxStream01<T, E> = Box<dyn Stream01<Item = T, Error = E> + Send + 'static>;
+
+/// Boxed futures 0.1 sink.
+pub type BoxSink01<T, E> = Box<dyn Sink01<SinkItem = T, SinkError = E> + Send + 'static>;
+
+/// Boxed futures 0.3 sink.
+pub type BoxSink<'a, T, E> = std::pin::Pin<Box<dyn Sink<T, Error = E> + Send + 'a>>;
+
+/// Convert a futures 0.1 future into a boxed 0.3 future of a [Result],
+/// converting its error.
+pub fn future_to_03<F, E>(future: F) -> BoxFuture<'static, Result<F::Item, E>>
+where
+    F: Future01 + Send + 'static,
+    E: From<F::Error> + Send + 'static,
+{
+    future.compat().map_err(E::from).boxed()
+}
+
+/// Convert a futures 0.3 future of a [Result] into a boxed 0.1 future,
+/// converting its error.
+pub fn future_to_01<F, E>(future: F) -> BoxFuture01<F::Ok, E>
+where
+    F: TryFuture + Send + 'static,
+    E: From<F::Error> + Send + 'static,
+{
+    Box::new(Compat::new(future.map_err(E::from).boxed()))
+}
+
+/// Convert a futures 0.1 stream into a boxed 0.3 stream of [Result]s,
+/// converting its errors.
+pub fn stream_to_03<S, E>(stream: S) -> BoxStream<'static, Result<S::Item, E>>
+where
+    S: Stream01 + Send + 'static,
+    E: From<S::Error> + Send + 'static,
+{
+    stream.compat().map_err(E::from).boxed()
+}
+
+/// Convert a futures 0.3 stream of [Result]s into a boxed 0.1 stream,
+/// converting its errors.
+pub fn stream_to_01<S, E>(stream: S) -> BoxStream01<S::Ok, E>
+where
+    S: TryStream + Send + 'static,
+    E: From<S::Error> + Send + 'static,
+{
+    Box::new(Compat::new(stream.map_err(E::from).boxed()))
+}
+
+/// Convert a futures 0.1 sink into a boxed 0.3 sink, converting its errors.
+pub fn sink_to_03<S, E>(sink: S) -> BoxSink<'static, S::SinkItem, E>
+where
+    S: Sink01 + Send + 'static,
+    S::SinkItem: Send,
+    E: From<S::SinkError> + Send + 'static,
+{
+    Box::pin(Compat01As03Sink::new(sink).sink_map_err(E::from))
+}
+
+/// Convert a futures 0.3 sink into a boxed 0.1 sink, converting its errors.
+pub fn sink_to_01<S, T, E>(sink: S) -> BoxSink01<T, E>
+where
+    S: Sink<T> + Send + 'static,
+    T: Send + 'static,
+    E: From<S::Error> + Send + 'static,
+{
+    let sink: BoxSink<'static, T, E> = Box::pin(sink.sink_map_err(E::from));
+    Box::new(CompatSink::new(sink))
+}
+
+/// Convert a tokio 0.1 `AsyncRead` into a futures 0.3
+/// [AsyncRead](futures::io::AsyncRead).
+pub fn async_read_to_03<R>(reader: R) -> Compat01As03<R> {
+    Compat01As03::new(reader)
+}
+
+/// Convert a futures 0.3 [AsyncRead](futures::io::AsyncRead) into a tokio
+/// 0.1 `AsyncRead`. The reader must be [Unpin], e.g. boxed.
+pub fn async_read_to_01<R>(reader: R) -> Compat<R> {
+    Compat::new(reader)
+}
+
+/// Convert a tokio 0.1 `AsyncWrite` into a futures 0.3
+/// [AsyncWrite](futures::io::AsyncWrite).
+pub fn async_write_to_03<W>(writer: W) -> Compat01As03<W> {
+    Compat01As03::new(writer)
+}
+
+/// Convert a futures 0.3 [AsyncWrite](futures::io::AsyncWrite) into a tokio
+/// 0.1 `AsyncWrite`. The writer must be [Unpin], e.g. boxed.
+pub fn async_write_to_01<W>(writer: W) -> Compat<W> {
+    Compat::new(writer)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::io::Write;
+
+    use anyhow::Error;
+    use futures::io::AsyncReadExt;
+    use futures01::future as future01;
+    use futures01::stream as stream01;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Error01;
+
+    #[derive(Debug, PartialEq)]
+    struct Error03(Error01);
+
+    impl From<Error01> for Error03 {
+        fn from(err: Error01) -> Self {
+            Self(err)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_future_to_03() {
+        let future = future01::ok::<_, Error01>(1);
+        assert_eq!(future_to_03::<_, Error03>(future).await, Ok(1));
+        let future = future01::err::<u32, _>(Error01);
+        assert_eq!(
+            future_to_03::<_, Error03>(future).await,
+            Err(Error03(Error01))
+        );
+    }
+
+    #[test]
+    fn test_future_to_01() {
+        let future = async { Ok::<_, Error01>(1) };
+        assert_eq!(future_to_01::<_, Error03>(future).wait(), Ok(1));
+        let future = async { Err::<u32, _>(Error01) };
+        assert_eq!(
+            future_to_01::<_, Error03>(future).wait(),
+            Err(Error03(Error01))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streams() {
+        let stream = stream01::iter_result(vec![Ok(1), Err(Error01), Ok(2)]);
+        let items = stream_to_03::<_, Error03>(stream).collect::<Vec<_>>().await;
+        assert_eq!(items, vec![Ok(1), Err(Error03(Error01)), Ok(2)]);
+
+        let stream = futures::stream::iter(vec![Ok(1), Err(Error01)]);
+        let mut items = stream_to_01::<_, Error03>(stream).wait();
+        assert_eq!(items.next(), Some(Ok(1)));
+        assert_eq!(items.next(), Some(Err(Error03(Error01))));
+        assert_eq!(items.next(), None);
+    }
+
+    #[tokio::test]
+    async fn test_sinks() -> Result<(), Error> {
+        let (sender, receiver) = futures01::sync::mpsc::channel(10);
+        let mut sink = sink_to_03::<_, Error>(sender);
+        sink.send(1).await?;
+        sink.send(2).await?;
+        drop(sink);
+        let items = stream_to_03::<_, Error03>(receiver.map_err(|()| Error01))
+            .try_collect::<Vec<_>>()
+            .await;
+        assert_eq!(items, Ok(vec![1, 2]));
+
+        let (sender, receiver) = futures::channel::mpsc::channel(10);
+        let sink = sink_to_01::<_, _, Error>(sender);
+        let sink = future_to_03::<_, Error>(sink.send(1).and_then(|sink| sink.send(2))).await?;
+        drop(sink);
+        assert_eq!(receiver.collect::<Vec<_>>().await, vec![1, 2]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_read_to_03() -> Result<(), Error> {
+        let mut reader = async_read_to_03(Cursor::new(b"hello".to_vec()));
+        let mut buf = String::new();
+        reader.read_to_string(&mut buf).await?;
+        assert_eq!(buf, "hello");
+        Ok(())
+    }
+
+    #[test]
+    fn test_async_write_to_01() -> Result<(), Error> {
+        let mut writer = async_write_to_01(futures::io::Cursor::new(Vec::new()));
+        // The 0.1 writer must be used in a futures 0.1 task.
+        let writer = future01::lazy(move || {
+            writer.write_all(b"hello")?;
+            writer.flush()?;
+            Ok::<_, std::io::Error>(writer)
+        })
+        .wait()?;
+        assert_eq!(writer.into_inner().into_inner(), b"hello");
+        Ok(())
+    }
+}
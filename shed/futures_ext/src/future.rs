@@ -11,8 +11,11 @@
 
 mod abort_handle_ref;
 mod conservative_receiver;
+mod join_all_bounded;
+mod memoize;
 mod on_cancel;
 mod on_cancel_with_data;
+mod on_complete;
 mod try_shared;
 
 use std::time::Duration;
@@ -27,9 +30,14 @@ use tokio::time::Timeout;
 pub use self::abort_handle_ref::spawn_controlled;
 pub use self::abort_handle_ref::ControlledHandle;
 pub use self::conservative_receiver::ConservativeReceiver;
+pub use self::join_all_bounded::join_all_bounded;
+pub use self::join_all_bounded::try_join_all_bounded;
+pub use self::memoize::memoize_async;
+pub use self::memoize::MemoizedAsync;
 pub use self::on_cancel::OnCancel;
 pub use self::on_cancel_with_data::CancelData;
 pub use self::on_cancel_with_data::OnCancelWithData;
+pub use self::on_complete::OnComplete;
 pub use self::try_shared::TryShared;
 
 /// A trait implemented by default for all Futures which extends the standard
@@ -62,6 +70,17 @@ pub trait FbFutureExt: Future {
     {
         OnCancelWithData::new(self, on_cancel)
     }
+
+    /// Call the `on_complete` callback with the output of this future when it
+    /// completes, unlike `on_cancel`, e.g. to release resources differently
+    /// depending on whether the future was canceled.
+    fn on_complete<F>(self, on_complete: F) -> OnComplete<Self, F>
+    where
+        Self: Sized,
+        F: FnOnce(&Self::Output),
+    {
+        OnComplete::new(self, on_complete)
+    }
 }
 
 impl<T> FbFutureExt for T where T: Future + ?Sized {}
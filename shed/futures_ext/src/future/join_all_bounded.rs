@@ -0,0 +1,181 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use futures::future::Future;
+use futures::future::FutureExt;
+use futures::future::TryFuture;
+use futures::future::TryFutureExt;
+use futures::stream;
+use futures::stream::StreamExt;
+use futures::stream::TryStreamExt;
+
+/// Run the given futures with at most `limit` of them in flight at any time,
+/// returning their outputs in the same order as the input. A `limit` of zero
+/// is treated as one.
+///
+/// Unlike `stream::iter(futures).buffered(limit)`, a slow future at the head
+/// of the input doesn't stop new futures from being started while others
+/// complete.
+pub async fn join_all_bounded<I>(limit: usize, futures: I) -> Vec<<I::Item as Future>::Output>
+where
+    I: IntoIterator,
+    I::Item: Future,
+{
+    let futures = futures.into_iter();
+    let mut results = Vec::with_capacity(futures.size_hint().0);
+    let mut outputs = stream::iter(
+        futures
+            .enumerate()
+            .map(|(index, fut)| fut.map(move |output| (index, output))),
+    )
+    .buffer_unordered(limit.max(1));
+
+    while let Some((index, output)) = outputs.next().await {
+        place(&mut results, index, output);
+    }
+
+    unwrap_all(results)
+}
+
+/// Run the given fallible futures with at most `limit` of them in flight at
+/// any time, returning their outputs in the same order as the input. A
+/// `limit` of zero is treated as one.
+///
+/// Returns the first error in completion order, without waiting for the
+/// remaining futures, which are dropped.
+pub async fn try_join_all_bounded<I>(
+    limit: usize,
+    futures: I,
+) -> Result<Vec<<I::Item as TryFuture>::Ok>, <I::Item as TryFuture>::Error>
+where
+    I: IntoIterator,
+    I::Item: TryFuture,
+{
+    let futures = futures.into_iter();
+    let mut results = Vec::with_capacity(futures.size_hint().0);
+    let mut outputs = stream::iter(
+        futures
+            .enumerate()
+            .map(|(index, fut)| fut.map_ok(move |output| (index, output))),
+    )
+    .buffer_unordered(limit.max(1));
+
+    while let Some((index, output)) = outputs.try_next().await? {
+        place(&mut results, index, output);
+    }
+
+    Ok(unwrap_all(results))
+}
+
+fn place<T>(results: &mut Vec<Option<T>>, index: usize, output: T) {
+    if results.len() <= index {
+        results.resize_with(index + 1, || None);
+    }
+    results[index] = Some(output);
+}
+
+fn unwrap_all<T>(results: Vec<Option<T>>) -> Vec<T> {
+    results
+        .into_iter()
+        .map(|output| output.expect("all futures have completed"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+    use anyhow::Error;
+    use tokio::time::Instant;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_join_all_bounded_preserves_order() {
+        tokio::time::pause();
+
+        let delays = [3u64, 1, 2, 0];
+        let futs = delays.iter().map(|delay| async move {
+            tokio::time::sleep(Duration::from_secs(*delay)).await;
+            *delay
+        });
+
+        assert_eq!(join_all_bounded(2, futs).await, vec![3, 1, 2, 0]);
+        assert_eq!(
+            join_all_bounded(2, Vec::<futures::future::Ready<()>>::new()).await,
+            vec![]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_join_all_bounded_limits_concurrency() {
+        tokio::time::pause();
+
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight = AtomicUsize::new(0);
+        let futs = (0..10).map(|i| {
+            let in_flight = &in_flight;
+            let max_in_flight = &max_in_flight;
+            async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                i
+            }
+        });
+
+        let start = Instant::now();
+        assert_eq!(join_all_bounded(3, futs).await, (0..10).collect::<Vec<_>>());
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 3);
+        assert_eq!(start.elapsed().as_secs(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_try_join_all_bounded() {
+        tokio::time::pause();
+
+        let futs = (0..5).map(|i| async move {
+            tokio::time::sleep(Duration::from_secs(5 - i)).await;
+            Ok::<_, Error>(i)
+        });
+        assert_eq!(
+            try_join_all_bounded(2, futs).await.unwrap(),
+            vec![0, 1, 2, 3, 4]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_try_join_all_bounded_short_circuits() {
+        tokio::time::pause();
+
+        let started = AtomicUsize::new(0);
+        let futs = (0..10u64).map(|i| {
+            let started = &started;
+            async move {
+                started.fetch_add(1, Ordering::SeqCst);
+                if i == 1 {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    return Err(anyhow!("failed {}", i));
+                }
+                tokio::time::sleep(Duration::from_secs(100)).await;
+                Ok(i)
+            }
+        });
+
+        let start = Instant::now();
+        let err = try_join_all_bounded(2, futs).await.unwrap_err();
+        assert_eq!(err.to_string(), "failed 1");
+        assert_eq!(start.elapsed().as_secs(), 1);
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,216 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Error;
+use futures::future::BoxFuture;
+use futures::future::Future;
+use futures::future::FutureExt;
+use futures::future::Shared;
+use futures::future::TryFutureExt;
+use shared_error::anyhow::IntoSharedError;
+use shared_error::anyhow::SharedError;
+use tokio::time::Instant;
+
+type SharedComputation<T> = Shared<BoxFuture<'static, Result<T, SharedError>>>;
+
+/// Create a [MemoizedAsync] computing its value with `f`, and caching it for
+/// `ttl` once computed successfully.
+pub fn memoize_async<F, Fut, T>(ttl: Duration, f: F) -> MemoizedAsync<T>
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<T, Error>> + Send + 'static,
+    T: Clone + Send + Sync + 'static,
+{
+    MemoizedAsync {
+        inner: Arc::new(Inner {
+            ttl,
+            compute: Box::new(move || f().boxed()),
+            state: Mutex::new(State {
+                generation: 0,
+                value: Value::Empty,
+            }),
+        }),
+    }
+}
+
+/// Cloneable handle to an async computation whose successful result is cached
+/// for a TTL, created with [memoize_async].
+///
+/// Concurrent calls to [MemoizedAsync::get] while the value is being computed
+/// share that computation rather than starting their own, and all get its
+/// result. Errors are returned to the calls sharing the computation that
+/// failed, but not cached, so the next call starts a new computation.
+pub struct MemoizedAsync<T> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    ttl: Duration,
+    compute: Box<dyn Fn() -> BoxFuture<'static, Result<T, Error>> + Send + Sync>,
+    state: Mutex<State<T>>,
+}
+
+struct State<T> {
+    /// Incremented by every new computation and invalidation, so that the
+    /// results of computations started before an invalidation aren't cached.
+    generation: u64,
+    value: Value<T>,
+}
+
+enum Value<T> {
+    Empty,
+    Computing(SharedComputation<T>),
+    Ready { value: T, expires_at: Instant },
+}
+
+impl<T> Clone for MemoizedAsync<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> MemoizedAsync<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    /// Return the cached value if it hasn't expired, or else wait for the
+    /// computation in progress, starting it if there is none.
+    pub async fn get(&self) -> Result<T, SharedError> {
+        let (generation, computation) = {
+            let mut state = self.inner.state.lock().expect("lock poisoned");
+            match &state.value {
+                Value::Ready { value, expires_at } if Instant::now() < *expires_at => {
+                    return Ok(value.clone());
+                }
+                Value::Computing(computation) => (state.generation, computation.clone()),
+                Value::Empty | Value::Ready { .. } => {
+                    let computation = (self.inner.compute)()
+                        .map_err(IntoSharedError::<SharedError>::shared_error)
+                        .boxed()
+                        .shared();
+                    state.generation += 1;
+                    state.value = Value::Computing(computation.clone());
+                    (state.generation, computation)
+                }
+            }
+        };
+
+        let result = computation.await;
+
+        let mut state = self.inner.state.lock().expect("lock poisoned");
+        if state.generation == generation && matches!(state.value, Value::Computing(_)) {
+            state.value = match &result {
+                Ok(value) => Value::Ready {
+                    value: value.clone(),
+                    expires_at: Instant::now() + self.inner.ttl,
+                },
+                Err(_) => Value::Empty,
+            };
+        }
+        result
+    }
+
+    /// Discard the cached value, so that the next call to
+    /// [MemoizedAsync::get] computes it again. A computation in progress
+    /// still completes for the calls waiting for it, but its result isn't
+    /// cached.
+    pub fn invalidate(&self) {
+        let mut state = self.inner.state.lock().expect("lock poisoned");
+        state.generation += 1;
+        state.value = Value::Empty;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use anyhow::anyhow;
+    use futures::future::join_all;
+
+    use super::*;
+
+    fn counting(calls: &Arc<AtomicUsize>, fail: bool) -> MemoizedAsync<usize> {
+        let calls = calls.clone();
+        memoize_async(Duration::from_secs(10), move || {
+            let calls = calls.clone();
+            async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                if fail {
+                    Err(anyhow!("call {} failed", call))
+                } else {
+                    Ok(call)
+                }
+            }
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_single_flight_and_ttl() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let memoized = counting(&calls, false);
+
+        let results = join_all((0..10).map(|_| memoized.get())).await;
+        assert!(results.into_iter().all(|result| result.unwrap() == 1));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_secs(9)).await;
+        assert_eq!(memoized.clone().get().await.unwrap(), 1);
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(memoized.get().await.unwrap(), 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_errors_are_not_cached() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let memoized = counting(&calls, true);
+
+        let results = join_all((0..2).map(|_| memoized.get())).await;
+        for result in results {
+            assert_eq!(result.unwrap_err().to_string(), "call 1 failed");
+        }
+        assert_eq!(
+            memoized.get().await.unwrap_err().to_string(),
+            "call 2 failed"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_invalidate() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let memoized = counting(&calls, false);
+
+        assert_eq!(memoized.get().await.unwrap(), 1);
+        memoized.invalidate();
+        assert_eq!(memoized.get().await.unwrap(), 2);
+
+        // The computation in progress when invalidating isn't cached.
+        let in_progress = tokio::spawn({
+            let memoized = memoized.clone();
+            async move {
+                memoized.invalidate();
+                memoized.get().await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        memoized.invalidate();
+        assert_eq!(in_progress.await.unwrap().unwrap(), 3);
+        assert_eq!(memoized.get().await.unwrap(), 4);
+    }
+}
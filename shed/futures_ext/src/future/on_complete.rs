@@ -0,0 +1,91 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::pin::Pin;
+
+use futures::future::Future;
+use futures::ready;
+use futures::task::Context;
+use futures::task::Poll;
+use pin_project::pin_project;
+
+/// Future combinator that executes the `on_complete` closure with the output
+/// of the inner future when it completes. This is the counterpart of
+/// [OnCancel](crate::future::OnCancel): exactly one of them runs for a
+/// future that is either completed or dropped.
+#[pin_project]
+pub struct OnComplete<Fut, OnCompleteFn>
+where
+    Fut: Future,
+    OnCompleteFn: FnOnce(&Fut::Output),
+{
+    #[pin]
+    inner: Fut,
+
+    on_complete: Option<OnCompleteFn>,
+}
+
+impl<Fut, OnCompleteFn> OnComplete<Fut, OnCompleteFn>
+where
+    Fut: Future,
+    OnCompleteFn: FnOnce(&Fut::Output),
+{
+    /// Construct an `OnComplete` combinator that will run `on_complete` when
+    /// `inner` completes.
+    pub fn new(inner: Fut, on_complete: OnCompleteFn) -> Self {
+        Self {
+            inner,
+            on_complete: Some(on_complete),
+        }
+    }
+}
+
+impl<Fut, OnCompleteFn> Future for OnComplete<Fut, OnCompleteFn>
+where
+    Fut: Future,
+    OnCompleteFn: FnOnce(&Fut::Output),
+{
+    type Output = Fut::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let v = ready!(this.inner.poll(cx));
+        if let Some(on_complete) = this.on_complete.take() {
+            on_complete(&v);
+        }
+        Poll::Ready(v)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn runs_when_complete() {
+        let completed = AtomicBool::new(false);
+        let fut = OnComplete::new(async { 1 }, |v| {
+            assert_eq!(*v, 1);
+            completed.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(fut.await, 1);
+        assert!(completed.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn doesnt_run_when_canceled() {
+        let completed = AtomicBool::new(false);
+        let fut = OnComplete::new(async {}, |_| completed.store(true, Ordering::Relaxed));
+        drop(fut);
+        assert!(!completed.load(Ordering::Relaxed));
+    }
+}
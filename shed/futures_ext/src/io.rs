@@ -0,0 +1,118 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module extending functionality of [`tokio::io`] module
+
+use std::io;
+use std::pin::Pin;
+
+use futures::ready;
+use futures::sink::Sink;
+use futures::task::Context;
+use futures::task::Poll;
+use pin_project::pin_project;
+use tokio::io::AsyncWrite;
+
+/// An [AsyncWrite] sending each buffer written to it as an item of the
+/// wrapped sink, e.g. the sender of a bounded channel.
+///
+/// Writes wait for the sink to be ready with [Sink::poll_ready], which
+/// registers the waker of the writing task, so writers are woken once the
+/// sink has capacity again rather than polling it in a loop.
+#[pin_project]
+pub struct SinkToAsyncWrite<S> {
+    #[pin]
+    sink: S,
+}
+
+impl<S> SinkToAsyncWrite<S> {
+    /// Create a new [SinkToAsyncWrite] writing to `sink`.
+    pub fn new(sink: S) -> Self {
+        Self { sink }
+    }
+
+    /// Return the wrapped sink.
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
+
+fn into_io_error<E>(err: E) -> io::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    io::Error::new(io::ErrorKind::BrokenPipe, err)
+}
+
+impl<S, E> AsyncWrite for SinkToAsyncWrite<S>
+where
+    S: Sink<Vec<u8>, Error = E>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut this = self.project();
+        ready!(this.sink.as_mut().poll_ready(cx)).map_err(into_io_error)?;
+        this.sink.start_send(buf.to_vec()).map_err(into_io_error)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().sink.poll_flush(cx).map_err(into_io_error)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().sink.poll_close(cx).map_err(into_io_error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures::channel::mpsc;
+    use futures::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_backpressure() {
+        // The sender of a channel without buffer has a single slot, so the
+        // writer has to wait for each chunk to be received.
+        let (sender, receiver) = mpsc::channel(0);
+        let writer = tokio::spawn(async move {
+            let mut writer = SinkToAsyncWrite::new(sender);
+            for chunk in [&b"a"[..], b"bc", b"def"] {
+                writer.write_all(chunk).await?;
+            }
+            writer.shutdown().await
+        });
+
+        // The writer would never be woken if it didn't register its waker
+        // when the channel is full.
+        let chunks = tokio::time::timeout(Duration::from_secs(10), receiver.collect::<Vec<_>>())
+            .await
+            .unwrap();
+        assert_eq!(chunks, vec![b"a".to_vec(), b"bc".to_vec(), b"def".to_vec()]);
+        writer.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_closed() {
+        let (sender, receiver) = mpsc::channel(0);
+        drop(receiver);
+        let mut writer = SinkToAsyncWrite::new(sender);
+        let err = writer.write_all(b"a").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+}
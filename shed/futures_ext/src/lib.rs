@@ -11,7 +11,9 @@
 
 //! Crate extending functionality of [`futures`] crate
 
+pub mod convert;
 pub mod future;
+pub mod io;
 pub mod stream;
 
 pub use crate::future::FbFutureExt;
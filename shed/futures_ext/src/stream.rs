@@ -9,8 +9,15 @@
 
 //! Module extending functionality of [`futures::stream`] module
 
+mod broadcast;
+mod checkpointed;
+mod iter_blocking;
+mod pausable;
 mod return_remainder;
 mod stream_with_timeout;
+mod throttle;
+mod timed_items;
+mod try_collect_partitioned;
 mod weight_limited_buffered_stream;
 mod yield_periodically;
 
@@ -21,10 +28,29 @@ use futures::Stream;
 use futures::StreamExt;
 use futures::TryFuture;
 use futures::TryStream;
+use futures::TryStreamExt;
+use shared_error::anyhow::IntoSharedError;
+use shared_error::anyhow::SharedError;
 
+pub use self::broadcast::Broadcast;
+pub use self::broadcast::BroadcastItem;
+pub use self::broadcast::LagPolicy;
+pub use self::checkpointed::CheckpointStore;
+pub use self::checkpointed::Checkpointed;
+pub use self::checkpointed::FileCheckpointStore;
+pub use self::iter_blocking::iter_to_stream_blocking;
+pub use self::iter_blocking::IterToStreamBlocking;
+pub use self::pausable::PauseHandle;
+pub use self::pausable::Pausable;
 pub use self::return_remainder::ReturnRemainder;
 pub use self::stream_with_timeout::StreamTimeoutError;
 pub use self::stream_with_timeout::StreamWithTimeout;
+pub use self::throttle::Throttle;
+pub use self::throttle::ThrottleRate;
+pub use self::timed_items::OnSlowItems;
+pub use self::timed_items::Timed;
+pub use self::timed_items::TimedItems;
+pub use self::try_collect_partitioned::TryCollectPartitioned;
 pub use self::weight_limited_buffered_stream::BufferedParams;
 pub use self::weight_limited_buffered_stream::WeightLimitedBufferedStream;
 pub use self::weight_limited_buffered_stream::WeightLimitedBufferedTryStream;
@@ -66,7 +92,80 @@ pub trait FbStreamExt: Stream {
         StreamWithTimeout::new(self, timeout)
     }
 
+    /// Construct a new [self::throttle::Throttle], limiting this stream to
+    /// the given sustained `rate` while allowing bursts of up to `burst` items.
+    fn throttle(self, rate: ThrottleRate, burst: u32) -> Throttle<Self>
+    where
+        Self: Sized,
+    {
+        Throttle::new(self, rate, burst)
+    }
+
+    /// Construct a new [self::pausable::Pausable] and the
+    /// [self::pausable::PauseHandle] pausing and resuming it from outside,
+    /// e.g. for consumer driven flow control.
+    fn pausable(self) -> (Pausable<Self>, PauseHandle)
+    where
+        Self: Sized,
+    {
+        Pausable::new(self)
+    }
+
+    /// Construct a new [self::timed_items::TimedItems], yielding each item
+    /// along with how long this stream took to produce it.
+    fn timed_items(self) -> TimedItems<Self>
+    where
+        Self: Sized,
+    {
+        TimedItems::new(self)
+    }
+
+    /// Construct a new [self::timed_items::Timed], yielding each item along
+    /// with how long this stream took to produce it, and aggregating those
+    /// latencies into percentiles.
+    fn timed(self) -> Timed<Self>
+    where
+        Self: Sized,
+    {
+        Timed::new(self)
+    }
+
+    /// Construct a new [self::timed_items::OnSlowItems], calling `callback`
+    /// with the items this stream took longer than `threshold` to produce,
+    /// e.g. to log slow producers.
+    fn on_slow_items<F>(self, threshold: Duration, callback: F) -> OnSlowItems<Self, F>
+    where
+        Self: Sized,
+        F: FnMut(Duration, &Self::Item),
+    {
+        OnSlowItems::new(self, threshold, callback)
+    }
+
+    /// Construct a new [self::checkpointed::Checkpointed], saving the marker
+    /// computed by `key` for the items consumed to `store` every `interval`.
+    fn checkpointed<F, C>(self, store: C, interval: Duration, key: F) -> Checkpointed<Self, F, C>
+    where
+        Self: Sized,
+        F: FnMut(&Self::Item) -> C::Checkpoint,
+        C: CheckpointStore,
+    {
+        Checkpointed::new(self, store, interval, key)
+    }
+
+    /// Construct a new [self::broadcast::Broadcast] receiving the items of
+    /// this stream, which is polled by a spawned task. Clone the receiver to
+    /// fan the items out to more consumers, each of them buffering up to
+    /// `capacity` items before lagging as specified by `policy`.
+    fn broadcast(self, capacity: usize, policy: LagPolicy) -> Broadcast<Self::Item>
+    where
+        Self: Sized + Send + 'static,
+        Self::Item: Clone + Send + 'static,
+    {
+        Broadcast::new(self, capacity, policy)
+    }
+
     /// Construct a new [self::yield_periodically::YieldPeriodically], with a sensible default.
+    /// Use [YieldPeriodically::with_item_budget] to also yield after a number of items.
     #[track_caller]
     fn yield_periodically<'a>(self) -> YieldPeriodically<'a, Self>
     where
@@ -119,6 +218,43 @@ pub trait FbTryStreamExt: TryStream {
 
         self.map(flatten_err)
     }
+
+    /// Collect the successes and the failures of the stream into separate
+    /// collections, rather than stopping at the first failure like
+    /// [TryStreamExt::try_collect].
+    ///
+    /// If `max_errors` is set, stop polling the stream once that many
+    /// failures were collected, and return what was collected so far.
+    fn try_collect_partitioned<C, D>(
+        self,
+        max_errors: Option<usize>,
+    ) -> TryCollectPartitioned<Self, C, D>
+    where
+        Self: Sized,
+        C: Default + Extend<Self::Ok>,
+        D: Default + Extend<Self::Error>,
+    {
+        TryCollectPartitioned::new(self, max_errors)
+    }
+
+    /// Like [FbStreamExt::broadcast], converting the errors of the stream to
+    /// [SharedError] so that every receiver gets them too.
+    fn try_broadcast(
+        self,
+        capacity: usize,
+        policy: LagPolicy,
+    ) -> Broadcast<Result<Self::Ok, SharedError>>
+    where
+        Self: Sized + Send + 'static,
+        Self::Ok: Clone + Send + 'static,
+        Self::Error: Into<anyhow::Error>,
+    {
+        Broadcast::new(
+            self.map_err(IntoSharedError::shared_error),
+            capacity,
+            policy,
+        )
+    }
 }
 
 impl<T> FbTryStreamExt for T where T: TryStream + ?Sized {}
@@ -0,0 +1,221 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Weak;
+
+use futures::future::BoxFuture;
+use futures::future::FutureExt;
+use futures::stream::Stream;
+use futures::stream::StreamExt;
+use futures::task::Context;
+use futures::task::Poll;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// What happens to a [Broadcast] receiver that falls so far behind that
+/// items it hasn't received yet are overwritten by newer ones.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LagPolicy {
+    /// The receiver gets a [BroadcastItem::Lagged] marker with the number of
+    /// items it missed, and continues with the oldest item still available.
+    Mark,
+    /// The receiver gets a [BroadcastItem::Lagged] marker with the number of
+    /// items it missed, and then ends.
+    Drop,
+}
+
+/// Item produced by a [Broadcast] receiver.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BroadcastItem<T> {
+    /// An item of the broadcast stream.
+    Item(T),
+    /// The receiver missed this many items, see [LagPolicy].
+    Lagged(u64),
+}
+
+type Recv<T> = BoxFuture<'static, (broadcast::Receiver<T>, Result<T, RecvError>)>;
+
+enum State<T> {
+    Idle(broadcast::Receiver<T>),
+    Receiving(Recv<T>),
+    Done,
+}
+
+/// One of the receivers of a stream broadcast with
+/// [FbStreamExt::broadcast](crate::FbStreamExt::broadcast). Every receiver
+/// gets every item produced by the stream after it was created, as long as it
+/// keeps up, and ends once the stream ends.
+///
+/// Cloning a receiver creates a new one, which gets the items produced after
+/// the clone rather than those its original hasn't received yet.
+pub struct Broadcast<T> {
+    sender: Weak<broadcast::Sender<T>>,
+    policy: LagPolicy,
+    state: State<T>,
+}
+
+impl<T> Broadcast<T>
+where
+    T: Clone + Send + 'static,
+{
+    /// Spawn a task forwarding the items of `stream` to the receivers, which
+    /// buffer up to `capacity` items each. The task stops once the stream
+    /// ends, or when it produces an item after all receivers have been
+    /// dropped. A `capacity` of zero is treated as one.
+    pub fn new<S>(stream: S, capacity: usize, policy: LagPolicy) -> Self
+    where
+        S: Stream<Item = T> + Send + 'static,
+    {
+        let (sender, receiver) = broadcast::channel(capacity.max(1));
+        let sender = Arc::new(sender);
+        let weak_sender = Arc::downgrade(&sender);
+        tokio::spawn(async move {
+            let mut stream = std::pin::pin!(stream);
+            while let Some(item) = stream.next().await {
+                if sender.send(item).is_err() {
+                    // All receivers have been dropped.
+                    break;
+                }
+            }
+        });
+        Self {
+            sender: weak_sender,
+            policy,
+            state: State::Idle(receiver),
+        }
+    }
+}
+
+impl<T> Clone for Broadcast<T> {
+    fn clone(&self) -> Self {
+        let state = match self.sender.upgrade() {
+            Some(sender) => State::Idle(sender.subscribe()),
+            None => State::Done,
+        };
+        Self {
+            sender: self.sender.clone(),
+            policy: self.policy,
+            state,
+        }
+    }
+}
+
+impl<T> Stream for Broadcast<T>
+where
+    T: Clone + Send + 'static,
+{
+    type Item = BroadcastItem<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            match std::mem::replace(&mut this.state, State::Done) {
+                State::Idle(mut receiver) => {
+                    this.state = State::Receiving(
+                        async move {
+                            let result = receiver.recv().await;
+                            (receiver, result)
+                        }
+                        .boxed(),
+                    );
+                }
+                State::Receiving(mut recv) => {
+                    let (receiver, result) = match recv.poll_unpin(cx) {
+                        Poll::Ready(received) => received,
+                        Poll::Pending => {
+                            this.state = State::Receiving(recv);
+                            return Poll::Pending;
+                        }
+                    };
+                    return Poll::Ready(match result {
+                        Ok(item) => {
+                            this.state = State::Idle(receiver);
+                            Some(BroadcastItem::Item(item))
+                        }
+                        Err(RecvError::Lagged(missed)) => {
+                            if this.policy == LagPolicy::Mark {
+                                this.state = State::Idle(receiver);
+                            }
+                            Some(BroadcastItem::Lagged(missed))
+                        }
+                        Err(RecvError::Closed) => None,
+                    });
+                }
+                State::Done => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::anyhow;
+    use futures::stream;
+
+    use super::*;
+    use crate::FbTryStreamExt;
+
+    #[tokio::test]
+    async fn test_fan_out() {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let source = stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|item| (item, receiver))
+        });
+        let first = Broadcast::new(source, 8, LagPolicy::Mark);
+        let second = first.clone();
+
+        for i in 0..3 {
+            sender.send(i).unwrap();
+        }
+        drop(sender);
+
+        let expected = (0..3).map(BroadcastItem::Item).collect::<Vec<_>>();
+        assert_eq!(first.collect::<Vec<_>>().await, expected);
+        assert_eq!(second.collect::<Vec<_>>().await, expected);
+    }
+
+    #[tokio::test]
+    async fn test_lag() {
+        let items = || stream::iter(0..5);
+
+        // Receivers that aren't polled yet lag once the task forwarded all
+        // items, which it does as soon as the test yields.
+        let marked = Broadcast::new(items(), 2, LagPolicy::Mark);
+        tokio::task::yield_now().await;
+        assert_eq!(
+            marked.collect::<Vec<_>>().await,
+            vec![
+                BroadcastItem::Lagged(3),
+                BroadcastItem::Item(3),
+                BroadcastItem::Item(4)
+            ]
+        );
+
+        let dropped = Broadcast::new(items(), 2, LagPolicy::Drop);
+        tokio::task::yield_now().await;
+        assert_eq!(
+            dropped.collect::<Vec<_>>().await,
+            vec![BroadcastItem::Lagged(3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let items = stream::iter(vec![Ok(1), Err(anyhow!("failed"))]);
+        let receiver = items.try_broadcast(4, LagPolicy::Mark);
+        let received = receiver.collect::<Vec<_>>().await;
+        assert!(matches!(received[0], BroadcastItem::Item(Ok(1))));
+        match &received[1] {
+            BroadcastItem::Item(Err(err)) => assert_eq!(err.to_string(), "failed"),
+            other => panic!("unexpected item {:?}", other),
+        }
+    }
+}
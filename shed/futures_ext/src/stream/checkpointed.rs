@@ -0,0 +1,318 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::fmt::Display;
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::format_err;
+use anyhow::Context as _;
+use anyhow::Error;
+use futures::future::BoxFuture;
+use futures::future::FutureExt;
+use futures::stream::Stream;
+use futures::task::Context;
+use futures::task::Poll;
+use pin_project::pin_project;
+use tokio::time::Instant;
+
+/// Persists the progress marker of a [Checkpointed] stream, so that a job
+/// can resume where it stopped.
+pub trait CheckpointStore {
+    /// The progress marker.
+    type Checkpoint;
+
+    /// Load the last saved checkpoint, if any.
+    fn load(&self) -> BoxFuture<'static, Result<Option<Self::Checkpoint>, Error>>;
+
+    /// Save the checkpoint, replacing the previous one.
+    fn save(&self, checkpoint: Self::Checkpoint) -> BoxFuture<'static, Result<(), Error>>;
+}
+
+/// A [CheckpointStore] keeping the checkpoint as text in a file, replaced
+/// atomically on every save.
+pub struct FileCheckpointStore<K> {
+    path: PathBuf,
+    checkpoint: PhantomData<fn() -> K>,
+}
+
+impl<K> FileCheckpointStore<K> {
+    /// Create a store keeping the checkpoint in the file at `path`, which
+    /// doesn't need to exist yet.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            checkpoint: PhantomData,
+        }
+    }
+}
+
+impl<K> CheckpointStore for FileCheckpointStore<K>
+where
+    K: Display + FromStr + Send + 'static,
+    K::Err: Display,
+{
+    type Checkpoint = K;
+
+    fn load(&self) -> BoxFuture<'static, Result<Option<K>, Error>> {
+        let path = self.path.clone();
+        async move {
+            let checkpoint = match tokio::fs::read_to_string(&path).await {
+                Ok(checkpoint) => checkpoint,
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(err) => {
+                    return Err(Error::from(err)
+                        .context(format!("Failed to read checkpoint {}", path.display())));
+                }
+            };
+            let checkpoint = checkpoint
+                .parse()
+                .map_err(|err| format_err!("Invalid checkpoint in {}: {}", path.display(), err))?;
+            Ok(Some(checkpoint))
+        }
+        .boxed()
+    }
+
+    fn save(&self, checkpoint: K) -> BoxFuture<'static, Result<(), Error>> {
+        let path = self.path.clone();
+        let checkpoint = checkpoint.to_string();
+        async move {
+            // Write a temporary file first so that a crash can't leave a
+            // truncated checkpoint behind.
+            let mut tmp_path = path.clone().into_os_string();
+            tmp_path.push(".tmp");
+            tokio::fs::write(&tmp_path, checkpoint)
+                .await
+                .and(tokio::fs::rename(&tmp_path, &path).await)
+                .with_context(|| format!("Failed to save checkpoint {}", path.display()))
+        }
+        .boxed()
+    }
+}
+
+/// A stream saving the progress marker of the items consumed so far to a
+/// [CheckpointStore], at most once per interval and once more when the
+/// stream ends.
+///
+/// The marker of an item is taken as consumed once the next item is
+/// requested, so the saved checkpoint is exact for consumers processing items
+/// one at a time, and may be ahead of the work done for consumers buffering
+/// items. A failure to save the checkpoint is returned as an error, after
+/// which the stream ends.
+#[pin_project]
+pub struct Checkpointed<S, F, C: CheckpointStore> {
+    #[pin]
+    inner: S,
+    key: F,
+    store: C,
+    interval: Duration,
+    last_saved_at: Instant,
+    /// Marker of the last item returned, if it hasn't been saved yet.
+    unsaved: Option<C::Checkpoint>,
+    saving: Option<BoxFuture<'static, Result<(), Error>>>,
+    done: bool,
+}
+
+impl<S, F, C> Checkpointed<S, F, C>
+where
+    S: Stream,
+    F: FnMut(&S::Item) -> C::Checkpoint,
+    C: CheckpointStore,
+{
+    /// Create a new [Checkpointed] stream, saving the marker computed by
+    /// `key` for the items consumed to `store` every `interval`.
+    pub fn new(inner: S, store: C, interval: Duration, key: F) -> Self {
+        Self {
+            inner,
+            key,
+            store,
+            interval,
+            last_saved_at: Instant::now(),
+            unsaved: None,
+            saving: None,
+            done: false,
+        }
+    }
+
+    /// Load the last checkpoint from `store` and create the source stream
+    /// from it, `None` meaning that the job starts from the beginning, then
+    /// checkpoint that stream as with [Checkpointed::new].
+    pub async fn resume(
+        store: C,
+        interval: Duration,
+        source: impl FnOnce(Option<C::Checkpoint>) -> S,
+        key: F,
+    ) -> Result<Self, Error> {
+        let checkpoint = store.load().await?;
+        Ok(Self::new(source(checkpoint), store, interval, key))
+    }
+}
+
+impl<S, F, C> Stream for Checkpointed<S, F, C>
+where
+    S: Stream,
+    F: FnMut(&S::Item) -> C::Checkpoint,
+    C: CheckpointStore,
+{
+    type Item = Result<S::Item, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            if let Some(saving) = this.saving.as_mut() {
+                let res = futures::ready!(saving.poll_unpin(cx));
+                *this.saving = None;
+                *this.last_saved_at = Instant::now();
+                if let Err(err) = res {
+                    *this.done = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
+            }
+
+            if *this.done {
+                return Poll::Ready(None);
+            }
+
+            if this.unsaved.is_some() && this.last_saved_at.elapsed() >= *this.interval {
+                if let Some(checkpoint) = this.unsaved.take() {
+                    *this.saving = Some(this.store.save(checkpoint));
+                }
+                continue;
+            }
+
+            match futures::ready!(this.inner.as_mut().poll_next(cx)) {
+                Some(item) => {
+                    *this.unsaved = Some((this.key)(&item));
+                    return Poll::Ready(Some(Ok(item)));
+                }
+                None => {
+                    *this.done = true;
+                    if let Some(checkpoint) = this.unsaved.take() {
+                        *this.saving = Some(this.store.save(checkpoint));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use anyhow::bail;
+    use futures::stream::StreamExt;
+    use futures::stream::TryStreamExt;
+
+    use super::*;
+
+    /// Records every saved checkpoint.
+    #[derive(Clone, Default)]
+    struct MemoryStore {
+        saved: Arc<Mutex<Vec<u32>>>,
+        fail: bool,
+    }
+
+    impl CheckpointStore for MemoryStore {
+        type Checkpoint = u32;
+
+        fn load(&self) -> BoxFuture<'static, Result<Option<u32>, Error>> {
+            let last = self.saved.lock().unwrap().last().copied();
+            async move { Ok(last) }.boxed()
+        }
+
+        fn save(&self, checkpoint: u32) -> BoxFuture<'static, Result<(), Error>> {
+            let saved = self.saved.clone();
+            let fail = self.fail;
+            async move {
+                if fail {
+                    bail!("store is unavailable");
+                }
+                saved.lock().unwrap().push(checkpoint);
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checkpointed_interval() -> Result<(), Error> {
+        tokio::time::pause();
+
+        let store = MemoryStore::default();
+        let s = async_stream::stream! {
+            for i in 0..6 {
+                tokio::time::sleep(Duration::from_secs(4)).await;
+                yield i;
+            }
+        };
+        let items: Vec<u32> =
+            Checkpointed::new(s.boxed(), store.clone(), Duration::from_secs(10), |i| *i)
+                .try_collect()
+                .await?;
+
+        assert_eq!(items, vec![0, 1, 2, 3, 4, 5]);
+        // Checkpoints are saved while waiting for the next item, at 12s
+        // once item 1 is consumed and at 24s once item 4 is, and the last
+        // item is saved when the stream ends.
+        assert_eq!(*store.saved.lock().unwrap(), vec![1, 4, 5]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_checkpointed_save_failure() {
+        let store = MemoryStore {
+            fail: true,
+            ..Default::default()
+        };
+        let mut s = Checkpointed::new(futures::stream::iter(0..3), store, Duration::ZERO, |i| *i);
+
+        assert_eq!(s.next().await.map(Result::ok), Some(Some(0)));
+        assert!(s.next().await.unwrap().is_err());
+        assert!(s.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_file() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("checkpoint");
+        let source = |checkpoint: Option<u32>| {
+            futures::stream::iter(checkpoint.map_or(0, |last| last + 1)..10)
+        };
+
+        let s = Checkpointed::resume(
+            FileCheckpointStore::new(&path),
+            Duration::ZERO,
+            source,
+            |i| *i,
+        )
+        .await?;
+        let first: Vec<u32> = s.take(4).try_collect().await?;
+        assert_eq!(first, vec![0, 1, 2, 3]);
+
+        // The last item taken was never consumed, so it is processed again.
+        let s = Checkpointed::resume(
+            FileCheckpointStore::new(&path),
+            Duration::ZERO,
+            source,
+            |i| *i,
+        )
+        .await?;
+        let rest: Vec<u32> = s.try_collect().await?;
+        assert_eq!(rest, vec![3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(tokio::fs::read_to_string(&path).await?, "9");
+        Ok(())
+    }
+}
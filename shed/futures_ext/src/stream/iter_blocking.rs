@@ -0,0 +1,157 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::future::Future;
+use std::mem;
+use std::panic;
+use std::pin::Pin;
+
+use futures::stream::Stream;
+use futures::task::Context;
+use futures::task::Poll;
+use tokio::task::JoinHandle;
+
+/// Convert an iterator whose `next` blocks, e.g. on IO, into a stream of its
+/// items. The items are pulled from the iterator in batches of `batch_size`
+/// on the blocking pool of tokio, see [tokio::task::spawn_blocking], so that
+/// they never block the async task polling the stream. A `batch_size` of
+/// zero is treated as one.
+///
+/// The next batch is only pulled once the items of the previous one have all
+/// been consumed, so a slow consumer doesn't make the iterator run ahead.
+/// The stream ends once the iterator does. A panic of the iterator is
+/// propagated to the task polling the stream.
+pub fn iter_to_stream_blocking<I>(iter: I, batch_size: usize) -> IterToStreamBlocking<I>
+where
+    I: Iterator + Send + 'static,
+    I::Item: Send + 'static,
+{
+    IterToStreamBlocking {
+        batch_size: batch_size.max(1),
+        batch: Vec::new().into_iter(),
+        state: State::Idle(iter),
+    }
+}
+
+enum State<I: Iterator> {
+    Idle(I),
+    Pulling(JoinHandle<(I, Vec<I::Item>)>),
+    Done,
+}
+
+/// Stream returned by [iter_to_stream_blocking].
+///
+/// When dropped while a batch is being pulled, the iterator is dropped once
+/// the batch is complete.
+pub struct IterToStreamBlocking<I: Iterator> {
+    batch_size: usize,
+    batch: std::vec::IntoIter<I::Item>,
+    state: State<I>,
+}
+
+// The iterator is only ever moved, never pinned.
+impl<I: Iterator> Unpin for IterToStreamBlocking<I> {}
+
+impl<I> Stream for IterToStreamBlocking<I>
+where
+    I: Iterator + Send + 'static,
+    I::Item: Send + 'static,
+{
+    type Item = I::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(item) = this.batch.next() {
+                return Poll::Ready(Some(item));
+            }
+            match mem::replace(&mut this.state, State::Done) {
+                State::Idle(mut iter) => {
+                    let batch_size = this.batch_size;
+                    this.state = State::Pulling(tokio::task::spawn_blocking(move || {
+                        let batch = iter.by_ref().take(batch_size).collect();
+                        (iter, batch)
+                    }));
+                }
+                State::Pulling(mut handle) => match Pin::new(&mut handle).poll(cx) {
+                    Poll::Pending => {
+                        this.state = State::Pulling(handle);
+                        return Poll::Pending;
+                    }
+                    Poll::Ready(Ok((iter, batch))) => {
+                        // A short batch means that the iterator has ended.
+                        if batch.len() == this.batch_size {
+                            this.state = State::Idle(iter);
+                        }
+                        this.batch = batch.into_iter();
+                    }
+                    Poll::Ready(Err(err)) => {
+                        if err.is_panic() {
+                            panic::resume_unwind(err.into_panic());
+                        }
+                        // The runtime is shutting down.
+                        return Poll::Ready(None);
+                    }
+                },
+                State::Done => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use futures::stream::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_iter_to_stream_blocking() {
+        let items: Vec<_> = iter_to_stream_blocking(0..10, 3).collect().await;
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+
+        let items: Vec<_> = iter_to_stream_blocking(0..6, 3).collect().await;
+        assert_eq!(items, (0..6).collect::<Vec<_>>());
+
+        let items: Vec<_> = iter_to_stream_blocking(std::iter::empty::<u32>(), 0)
+            .collect()
+            .await;
+        assert!(items.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_backpressure() {
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let iter = {
+            let pulled = pulled.clone();
+            (0..100).inspect(move |_| {
+                pulled.fetch_add(1, Ordering::Relaxed);
+            })
+        };
+        let mut stream = iter_to_stream_blocking(iter, 4);
+
+        assert_eq!(stream.next().await, Some(0));
+        assert_eq!(pulled.load(Ordering::Relaxed), 4);
+        for i in 1..5 {
+            assert_eq!(stream.next().await, Some(i));
+        }
+        assert_eq!(pulled.load(Ordering::Relaxed), 8);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "iterator failed")]
+    async fn test_panic() {
+        let iter = (0..10).map(|i| if i == 5 { panic!("iterator failed") } else { i });
+        let _: Vec<_> = iter_to_stream_blocking(iter, 2).collect().await;
+    }
+}
@@ -0,0 +1,185 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use futures::stream::Stream;
+use futures::task::AtomicWaker;
+use futures::task::Context;
+use futures::task::Poll;
+use pin_project::pin_project;
+
+#[derive(Debug, Default)]
+struct PauseState {
+    paused: AtomicBool,
+    /// Number of live [PauseHandle]s.
+    handles: AtomicUsize,
+    /// The task polling the stream, woken up when it is resumed.
+    waker: AtomicWaker,
+}
+
+/// Pauses and resumes a [Pausable] stream from outside of it, e.g. from the
+/// task handling a maintenance window while the consumer of the stream keeps
+/// running. Clones control the same stream.
+///
+/// Dropping the last handle resumes the stream, so that it can't stay paused
+/// with no way of resuming it.
+#[derive(Debug)]
+pub struct PauseHandle {
+    state: Arc<PauseState>,
+}
+
+impl PauseHandle {
+    /// Stop polling the inner stream. The consumer of the stream waits for
+    /// the stream to be resumed; an item already being produced isn't
+    /// interrupted but is only returned once resumed.
+    pub fn pause(&self) {
+        self.state.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Resume polling the inner stream, waking up the consumer waiting for
+    /// it.
+    pub fn resume(&self) {
+        self.state.paused.store(false, Ordering::SeqCst);
+        self.state.waker.wake();
+    }
+
+    /// Whether the stream is paused.
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::SeqCst)
+    }
+}
+
+impl Clone for PauseHandle {
+    fn clone(&self) -> Self {
+        self.state.handles.fetch_add(1, Ordering::SeqCst);
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl Drop for PauseHandle {
+    fn drop(&mut self) {
+        if self.state.handles.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.resume();
+        }
+    }
+}
+
+/// A stream that stops polling its inner stream while paused by its
+/// [PauseHandle], without tearing it down.
+///
+/// The wakeups of the inner stream while paused are not lost: the inner
+/// stream is polled again as soon as it is resumed.
+#[pin_project]
+pub struct Pausable<S> {
+    #[pin]
+    inner: S,
+    state: Arc<PauseState>,
+}
+
+impl<S> Pausable<S> {
+    /// Create a new [Pausable] stream, initially running, and the handle
+    /// controlling it.
+    pub fn new(inner: S) -> (Self, PauseHandle) {
+        let state = Arc::new(PauseState {
+            handles: AtomicUsize::new(1),
+            ..Default::default()
+        });
+        let handle = PauseHandle {
+            state: state.clone(),
+        };
+        (Self { inner, state }, handle)
+    }
+
+    /// Whether the stream is paused.
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::SeqCst)
+    }
+}
+
+impl<S: Stream> Stream for Pausable<S> {
+    type Item = <S as Stream>::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+
+        // Register before checking the flag, so that a concurrent resume
+        // can't be missed.
+        this.state.waker.register(cx.waker());
+        if this.state.paused.load(Ordering::SeqCst) {
+            return Poll::Pending;
+        }
+        this.inner.poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures::channel::mpsc;
+    use futures::stream::StreamExt;
+    use futures::FutureExt;
+    use futures::SinkExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pause_resume() {
+        let (mut s, handle) = Pausable::new(futures::stream::iter(0..4));
+        assert_eq!(s.next().await, Some(0));
+
+        handle.pause();
+        assert!(s.is_paused() && handle.is_paused());
+        assert_eq!(s.next().now_or_never(), None);
+
+        handle.resume();
+        assert!(!s.is_paused());
+        assert_eq!(s.collect::<Vec<_>>().await, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_resume_wakes_consumer() {
+        tokio::time::pause();
+
+        let (mut sender, receiver) = mpsc::channel(10);
+        let (s, handle) = Pausable::new(receiver);
+        handle.pause();
+        let consumer = tokio::spawn(s.collect::<Vec<_>>());
+
+        // The wakeups of the inner stream while paused don't deliver items.
+        sender.send(0).await.unwrap();
+        sender.send(1).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!consumer.is_finished());
+
+        drop(sender);
+        handle.resume();
+        assert_eq!(consumer.await.unwrap(), vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_drop_last_handle_resumes() {
+        let (s, handle) = Pausable::new(futures::stream::iter(0..2));
+        let clone = handle.clone();
+        handle.pause();
+        drop(handle);
+        assert!(clone.is_paused());
+
+        drop(clone);
+        assert!(!s.is_paused());
+        assert_eq!(s.collect::<Vec<_>>().await, vec![0, 1]);
+    }
+}
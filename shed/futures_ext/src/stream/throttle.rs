@@ -0,0 +1,224 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures::future::Future;
+use futures::stream::Stream;
+use futures::task::Context;
+use futures::task::Poll;
+use pin_project::pin_project;
+use tokio::time::Instant;
+use tokio::time::Sleep;
+
+/// The sustained rate a [Throttle] stream is allowed to produce items at,
+/// expressed as a maximum number of items per time window.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ThrottleRate {
+    items: u32,
+    window: Duration,
+}
+
+impl ThrottleRate {
+    /// Allow at most `items` items per `window`. A rate of zero items is
+    /// treated as one item per window.
+    pub fn new(items: u32, window: Duration) -> Self {
+        Self {
+            items: items.max(1),
+            window,
+        }
+    }
+
+    /// Allow at most `items` items per second.
+    pub fn per_second(items: u32) -> Self {
+        Self::new(items, Duration::from_secs(1))
+    }
+
+    /// Time between two items when the stream runs at the sustained rate.
+    fn interval(&self) -> Duration {
+        self.window / self.items
+    }
+}
+
+/// A stream that delays polling the inner stream so that items are produced
+/// no faster than the given [ThrottleRate]. Up to `burst` items can be
+/// produced back to back if the stream has been idle for long enough,
+/// after which it falls back to the sustained rate.
+///
+/// Time is measured with [tokio::time], so tests can drive it with
+/// [tokio::time::pause] and [tokio::time::advance].
+#[pin_project]
+pub struct Throttle<S> {
+    #[pin]
+    inner: S,
+    interval: Duration,
+    /// How far ahead of the sustained schedule the stream is allowed to get.
+    burst_tolerance: Duration,
+    /// Theoretical time at which the next item would be produced if the
+    /// stream ran exactly at the sustained rate.
+    next_at: Option<Instant>,
+    #[pin]
+    delay: Option<Sleep>,
+}
+
+impl<S> Throttle<S> {
+    /// Create a new [Throttle]. A `burst` of zero is treated as one, i.e. no
+    /// bursting above the sustained rate.
+    pub fn new(inner: S, rate: ThrottleRate, burst: u32) -> Self {
+        let interval = rate.interval();
+        Self {
+            inner,
+            interval,
+            burst_tolerance: interval.saturating_mul(burst.max(1) - 1),
+            next_at: None,
+            delay: None,
+        }
+    }
+}
+
+impl<S: Stream> Stream for Throttle<S> {
+    type Item = <S as Stream>::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            if let Some(delay) = this.delay.as_mut().as_pin_mut() {
+                futures::ready!(delay.poll(cx));
+                this.delay.set(None);
+            }
+
+            let now = Instant::now();
+            let allowed_at = this
+                .next_at
+                .and_then(|next_at| next_at.checked_sub(*this.burst_tolerance));
+
+            match allowed_at {
+                Some(allowed_at) if allowed_at > now => {
+                    this.delay.set(Some(tokio::time::sleep_until(allowed_at)));
+                }
+                _ => break,
+            }
+        }
+
+        let res = futures::ready!(this.inner.poll_next(cx));
+        if res.is_some() {
+            // The inner stream might have been pending for a while, so
+            // account for the time the item was actually produced at.
+            let now = Instant::now();
+            let next_at = match *this.next_at {
+                Some(next_at) if next_at > now => next_at,
+                _ => now,
+            };
+            *this.next_at = Some(next_at + *this.interval);
+        }
+
+        Poll::Ready(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::stream::StreamExt;
+
+    use super::*;
+
+    /// Collect the time each item was produced at, relative to the start of
+    /// the stream. Offsets are truncated to 100ms, as tokio timers have
+    /// millisecond granularity and may fire slightly late.
+    async fn item_offsets<S: Stream + Unpin>(mut s: S) -> Vec<Duration> {
+        let start = Instant::now();
+        let mut offsets = Vec::new();
+        while s.next().await.is_some() {
+            let millis = start.elapsed().as_millis() as u64;
+            offsets.push(Duration::from_millis(millis / 100 * 100));
+        }
+        offsets
+    }
+
+    #[tokio::test]
+    async fn test_throttle_sustained_rate() {
+        tokio::time::pause();
+
+        let s = Throttle::new(futures::stream::iter(0..4), ThrottleRate::per_second(2), 1);
+        let offsets = item_offsets(s.boxed()).await;
+
+        assert_eq!(
+            offsets,
+            vec![
+                Duration::from_millis(0),
+                Duration::from_millis(500),
+                Duration::from_millis(1000),
+                Duration::from_millis(1500),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_throttle_burst() {
+        tokio::time::pause();
+
+        let s = Throttle::new(futures::stream::iter(0..5), ThrottleRate::per_second(1), 3);
+        let offsets = item_offsets(s.boxed()).await;
+
+        assert_eq!(
+            offsets,
+            vec![
+                Duration::from_secs(0),
+                Duration::from_secs(0),
+                Duration::from_secs(0),
+                Duration::from_secs(1),
+                Duration::from_secs(2),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_throttle_burst_refills_when_idle() {
+        tokio::time::pause();
+
+        // After the first two items the throttle waits for a second before
+        // polling the inner stream again, so the idle period ends at 11s.
+        let s = async_stream::stream! {
+            yield 0;
+            yield 1;
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            yield 2;
+            yield 3;
+            yield 4;
+        };
+        let s = Throttle::new(s, ThrottleRate::per_second(1), 2);
+        let offsets = item_offsets(s.boxed()).await;
+
+        assert_eq!(
+            offsets,
+            vec![
+                Duration::from_secs(0),
+                Duration::from_secs(0),
+                Duration::from_secs(11),
+                Duration::from_secs(11),
+                Duration::from_secs(12),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_throttle_huge_burst() {
+        tokio::time::pause();
+
+        // The burst tolerance overflows a Duration, so it saturates and the
+        // stream is never throttled.
+        let rate = ThrottleRate::new(1, Duration::from_secs(10_000_000_000));
+        let s = Throttle::new(futures::stream::iter(0..3), rate, u32::MAX);
+        let offsets = item_offsets(s.boxed()).await;
+
+        assert_eq!(offsets, vec![Duration::from_secs(0); 3]);
+    }
+}
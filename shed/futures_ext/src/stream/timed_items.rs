@@ -0,0 +1,323 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures::stream::Stream;
+use futures::task::Context;
+use futures::task::Poll;
+use pin_project::pin_project;
+use tokio::time::Instant;
+
+/// A stream yielding the items of the inner stream along with how long the
+/// inner stream took to produce each of them: the time since the previous
+/// item, or since the stream was first polled for the first item.
+///
+/// Time is measured with [tokio::time], so tests can drive it with
+/// [tokio::time::pause] and [tokio::time::advance].
+#[pin_project]
+pub struct TimedItems<S> {
+    #[pin]
+    inner: S,
+    /// When the previous item was produced, or the stream first polled.
+    since: Option<Instant>,
+}
+
+impl<S> TimedItems<S> {
+    /// Create a new [TimedItems].
+    pub fn new(inner: S) -> Self {
+        Self { inner, since: None }
+    }
+}
+
+impl<S: Stream> Stream for TimedItems<S> {
+    type Item = (Duration, S::Item);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let since = *this.since.get_or_insert_with(Instant::now);
+        let item = futures::ready!(this.inner.poll_next(cx));
+        let now = Instant::now();
+        *this.since = Some(now);
+        Poll::Ready(item.map(|item| (now - since, item)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// A stream yielding the items of the inner stream, calling a callback with
+/// the items that took longer than a threshold to be produced, as measured
+/// by [TimedItems].
+#[pin_project]
+pub struct OnSlowItems<S, F> {
+    #[pin]
+    inner: TimedItems<S>,
+    threshold: Duration,
+    callback: F,
+}
+
+impl<S, F> OnSlowItems<S, F>
+where
+    S: Stream,
+    F: FnMut(Duration, &S::Item),
+{
+    /// Create a new [OnSlowItems], calling `callback` with the items that
+    /// took longer than `threshold` and how long they took.
+    pub fn new(inner: S, threshold: Duration, callback: F) -> Self {
+        Self {
+            inner: TimedItems::new(inner),
+            threshold,
+            callback,
+        }
+    }
+}
+
+impl<S, F> Stream for OnSlowItems<S, F>
+where
+    S: Stream,
+    F: FnMut(Duration, &S::Item),
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let item = futures::ready!(this.inner.poll_next(cx));
+        Poll::Ready(item.map(|(elapsed, item)| {
+            if elapsed > *this.threshold {
+                (this.callback)(elapsed, &item);
+            }
+            item
+        }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// A stream yielding the items of the inner stream along with how long the
+/// inner stream took to produce each of them, as measured by [TimedItems],
+/// and aggregating those latencies so that percentiles can be reported once
+/// the stream is consumed, e.g. through
+/// [StreamExt::by_ref](futures::StreamExt::by_ref).
+#[pin_project]
+pub struct Timed<S> {
+    #[pin]
+    inner: TimedItems<S>,
+    latencies: LatencyHistogram,
+}
+
+impl<S> Timed<S> {
+    /// Create a new [Timed].
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner: TimedItems::new(inner),
+            latencies: LatencyHistogram::default(),
+        }
+    }
+
+    /// Return how many items were produced so far.
+    pub fn item_count(&self) -> u64 {
+        self.latencies.count
+    }
+
+    /// Return the mean latency of the items, if any.
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.latencies.count).ok().filter(|c| *c > 0);
+        count.map(|count| self.latencies.sum / count)
+    }
+
+    /// Return the largest latency of the items, if any.
+    pub fn max(&self) -> Option<Duration> {
+        (self.latencies.count > 0).then_some(self.latencies.max)
+    }
+
+    /// Return the latency under which `percentile` percent of the items were
+    /// produced, e.g. 99.0 for the p99, if any. The latencies are kept with a
+    /// precision of one microsecond or one eighth of the latency, whichever
+    /// is larger, and the returned latency is rounded up.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        self.latencies.percentile(percentile)
+    }
+}
+
+impl<S: Stream> Stream for Timed<S> {
+    type Item = (S::Item, Duration);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let item = futures::ready!(this.inner.poll_next(cx));
+        Poll::Ready(item.map(|(elapsed, item)| {
+            this.latencies.record(elapsed);
+            (item, elapsed)
+        }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// Histogram of latencies in microseconds, with 8 buckets per power of two so
+/// that its memory is bounded whatever the number of items.
+#[derive(Default)]
+struct LatencyHistogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: Duration,
+    max: Duration,
+}
+
+impl LatencyHistogram {
+    const SUB_BUCKETS: u64 = 8;
+
+    fn record(&mut self, latency: Duration) {
+        let bucket = Self::bucket(u64::try_from(latency.as_micros()).unwrap_or(u64::MAX));
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(latency);
+        self.max = self.max.max(latency);
+    }
+
+    fn percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * self.count as f64).ceil() as u64)
+            .clamp(1, self.count);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let upper = Duration::from_micros(Self::upper_bound(bucket));
+                return Some(upper.min(self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// The values below `2 * SUB_BUCKETS` have a bucket each, the others
+    /// share buckets by their 4 most significant bits.
+    fn bucket(micros: u64) -> usize {
+        if micros < 2 * Self::SUB_BUCKETS {
+            return micros as usize;
+        }
+        let shift = micros.ilog2() - Self::SUB_BUCKETS.ilog2();
+        let mantissa = micros >> shift;
+        ((u64::from(shift) + 1) * Self::SUB_BUCKETS + mantissa - Self::SUB_BUCKETS) as usize
+    }
+
+    /// The largest value in the bucket.
+    fn upper_bound(bucket: usize) -> u64 {
+        let bucket = bucket as u64;
+        if bucket < 2 * Self::SUB_BUCKETS {
+            return bucket;
+        }
+        let shift = bucket / Self::SUB_BUCKETS - 1;
+        let mantissa = bucket % Self::SUB_BUCKETS + Self::SUB_BUCKETS;
+        (mantissa << shift) + ((1 << shift) - 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::stream;
+    use futures::StreamExt;
+
+    use super::*;
+
+    /// Stream producing its items after the given delays.
+    fn delayed(delays: Vec<u64>) -> impl Stream<Item = u64> {
+        stream::iter(delays).then(|delay| async move {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            delay
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timed_items() {
+        let timed = TimedItems::new(delayed(vec![10, 0, 30]));
+        // Time spent before the first poll isn't counted.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let items = timed.collect::<Vec<_>>().await;
+        assert_eq!(
+            items,
+            vec![
+                (Duration::from_millis(10), 10),
+                (Duration::ZERO, 0),
+                (Duration::from_millis(30), 30),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timed() {
+        let mut timed = std::pin::pin!(Timed::new(delayed((1..=100).collect())));
+        assert_eq!(timed.percentile(50.0), None);
+
+        let items = timed.by_ref().take(3).collect::<Vec<_>>().await;
+        assert_eq!(
+            items,
+            vec![
+                (1, Duration::from_millis(1)),
+                (2, Duration::from_millis(2)),
+                (3, Duration::from_millis(3)),
+            ]
+        );
+        timed.by_ref().for_each(|_| async {}).await;
+
+        assert_eq!(timed.item_count(), 100);
+        assert_eq!(timed.mean(), Some(Duration::from_micros(50500)));
+        assert_eq!(timed.max(), Some(Duration::from_millis(100)));
+        assert_eq!(timed.percentile(100.0), Some(Duration::from_millis(100)));
+        // The percentiles are rounded up to the upper bound of their bucket.
+        let p50 = timed.percentile(50.0).unwrap();
+        assert!(p50 >= Duration::from_millis(50) && p50 <= Duration::from_millis(50) * 9 / 8);
+        let p99 = timed.percentile(99.0).unwrap();
+        assert!(p99 >= Duration::from_millis(99) && p99 <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_latency_buckets() {
+        let mut previous = 0;
+        for micros in (0..100_000).chain([u64::MAX / 2, u64::MAX]) {
+            let bucket = LatencyHistogram::bucket(micros);
+            assert!(bucket == previous || bucket == previous + 1 || micros > 100_000);
+            assert!(LatencyHistogram::upper_bound(bucket) >= micros);
+            assert!(micros >= LatencyHistogram::upper_bound(bucket) / 9 * 8);
+            previous = bucket;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_on_slow_items() {
+        let mut slow = Vec::new();
+        let items = OnSlowItems::new(
+            delayed(vec![10, 50, 20, 60]),
+            Duration::from_millis(20),
+            |elapsed, item| slow.push((elapsed, *item)),
+        )
+        .collect::<Vec<_>>()
+        .await;
+        assert_eq!(items, vec![10, 50, 20, 60]);
+        assert_eq!(
+            slow,
+            vec![
+                (Duration::from_millis(50), 50),
+                (Duration::from_millis(60), 60),
+            ]
+        );
+    }
+}
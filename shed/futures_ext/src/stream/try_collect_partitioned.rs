@@ -0,0 +1,122 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::mem;
+use std::pin::Pin;
+
+use futures::future::FusedFuture;
+use futures::ready;
+use futures::stream::TryStream;
+use futures::task::Context;
+use futures::task::Poll;
+use futures::Future;
+use pin_project::pin_project;
+
+/// Future returned by
+/// [FbTryStreamExt::try_collect_partitioned](crate::stream::FbTryStreamExt::try_collect_partitioned).
+#[pin_project]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct TryCollectPartitioned<S, C, D> {
+    #[pin]
+    inner: S,
+    oks: C,
+    errs: D,
+    errors: usize,
+    max_errors: Option<usize>,
+    done: bool,
+}
+
+impl<S, C: Default, D: Default> TryCollectPartitioned<S, C, D> {
+    pub(crate) fn new(inner: S, max_errors: Option<usize>) -> Self {
+        Self {
+            inner,
+            oks: C::default(),
+            errs: D::default(),
+            errors: 0,
+            max_errors,
+            done: false,
+        }
+    }
+}
+
+impl<S, C, D> Future for TryCollectPartitioned<S, C, D>
+where
+    S: TryStream,
+    C: Default + Extend<S::Ok>,
+    D: Default + Extend<S::Error>,
+{
+    type Output = (C, D);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        assert!(!*this.done, "TryCollectPartitioned polled after completion");
+        loop {
+            if this.max_errors.is_some_and(|max| *this.errors >= max) {
+                break;
+            }
+            match ready!(this.inner.as_mut().try_poll_next(cx)) {
+                Some(Ok(item)) => this.oks.extend(Some(item)),
+                Some(Err(err)) => {
+                    *this.errors += 1;
+                    this.errs.extend(Some(err));
+                }
+                None => break,
+            }
+        }
+        *this.done = true;
+        Poll::Ready((mem::take(this.oks), mem::take(this.errs)))
+    }
+}
+
+impl<S, C, D> FusedFuture for TryCollectPartitioned<S, C, D>
+where
+    S: TryStream,
+    C: Default + Extend<S::Ok>,
+    D: Default + Extend<S::Error>,
+{
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::stream;
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn results() -> impl TryStream<Ok = u32, Error = String> {
+        stream::iter(1..=10).map(|i| {
+            if i % 3 == 0 {
+                Err(format!("error {}", i))
+            } else {
+                Ok(i)
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_collect_all() {
+        let (oks, errs): (Vec<_>, Vec<_>) = TryCollectPartitioned::new(results(), None).await;
+        assert_eq!(oks, vec![1, 2, 4, 5, 7, 8, 10]);
+        assert_eq!(errs, vec!["error 3", "error 6", "error 9"]);
+    }
+
+    #[tokio::test]
+    async fn test_max_errors() {
+        let (oks, errs): (Vec<_>, Vec<_>) = TryCollectPartitioned::new(results(), Some(2)).await;
+        // The stream isn't polled after the second error.
+        assert_eq!(oks, vec![1, 2, 4, 5]);
+        assert_eq!(errs, vec!["error 3", "error 6"]);
+
+        let (oks, errs): (Vec<u32>, Vec<_>) = TryCollectPartitioned::new(results(), Some(0)).await;
+        assert!(oks.is_empty() && errs.is_empty());
+    }
+}
@@ -74,6 +74,10 @@ where
         while this.queue.len() < *this.max_buffer_size && this.current_weight < this.weight_limit {
             let future = match this.stream.as_mut().poll_next(cx) {
                 Poll::Ready(Some((f, weight))) => {
+                    // Weights come from the caller and may add up beyond
+                    // u64::MAX, so count the part of the weight that fits,
+                    // which is then released when the future completes.
+                    let weight = weight.min(u64::MAX - *this.current_weight);
                     *this.current_weight += weight;
                     f.map(move |val| (val, weight)).boxed()
                 }
@@ -146,6 +150,8 @@ where
         while this.queue.len() < *this.max_buffer_size && this.current_weight < this.weight_limit {
             let future = match this.stream.as_mut().poll_next(cx) {
                 Poll::Ready(Some(Ok((f, weight)))) => {
+                    // See WeightLimitedBufferedStream.
+                    let weight = weight.min(u64::MAX - *this.current_weight);
                     *this.current_weight += weight;
                     f.map(move |val| (val, weight)).boxed()
                 }
@@ -305,6 +311,22 @@ mod test {
         counted_try_stream(s)
     }
 
+    #[tokio::test]
+    async fn test_large_weights() {
+        // The sum of the weights overflows u64.
+        let s = stream::iter(vec![
+            (future::ready(1).boxed(), u64::MAX),
+            (future::ready(2).boxed(), u64::MAX),
+            (future::ready(3).boxed(), 5),
+        ]);
+        let params = BufferedParams {
+            weight_limit: u64::MAX,
+            buffer_size: 10,
+        };
+        let s = WeightLimitedBufferedStream::new(params, s);
+        assert_eq!(s.collect::<Vec<_>>().await, vec![1, 2, 3]);
+    }
+
     #[tokio::test]
     async fn test_try_all_in_one_go() {
         let (counter, s) = create_try_stream_all_good();
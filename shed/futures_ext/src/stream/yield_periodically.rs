@@ -23,6 +23,10 @@ const BUDGET_OVERSHOOT_MULTIPLIER: u32 = 3;
 /// A stream that will yield control back to the caller if it runs for more than a given duration
 /// without yielding (i.e. returning Poll::Pending).  The clock starts counting the first time the
 /// stream is polled, and is reset every time the stream yields.
+///
+/// It can also yield after producing a given number of items without yielding, see
+/// [YieldPeriodically::with_item_budget], for streams whose items are cheap to produce one by one
+/// but expensive to process.
 #[pin_project]
 pub struct YieldPeriodically<'a, S> {
     #[pin]
@@ -31,6 +35,10 @@ pub struct YieldPeriodically<'a, S> {
     budget: Duration,
     /// Budget left for the current iteration.
     current_budget: Duration,
+    /// Default budget of items, if any.
+    item_budget: Option<u64>,
+    /// Items left for the current iteration.
+    current_item_budget: u64,
     /// Whether the next iteration must yield because the budget was exceeded.
     must_yield: bool,
     /// Callback for when we overshoot the budget by more than
@@ -45,6 +53,8 @@ impl<S> YieldPeriodically<'_, S> {
             inner,
             budget,
             current_budget: budget,
+            item_budget: None,
+            current_item_budget: 0,
             must_yield: false,
             on_large_overshoot: None,
         }
@@ -57,6 +67,14 @@ impl<S> YieldPeriodically<'_, S> {
         self
     }
 
+    /// Also yield after producing `items` items without yielding, whatever
+    /// the time it took. A budget of zero items is treated as one.
+    pub fn with_item_budget(mut self, items: u64) -> Self {
+        self.item_budget = Some(items.max(1));
+        self.current_item_budget = items.max(1);
+        self
+    }
+
     /// If we are unable to yield in time because a single poll exceeds the
     /// budget by more than BUDGET_OVERSHOOT_MULTIPLIER times, call this
     /// callback.  The caller can use this to log the location where long
@@ -89,6 +107,7 @@ impl<S: Stream> Stream for YieldPeriodically<'_, S> {
 
         if res.is_pending() {
             *this.current_budget = *this.budget;
+            *this.current_item_budget = this.item_budget.unwrap_or_default();
             return res;
         }
 
@@ -108,6 +127,14 @@ impl<S: Stream> Stream for YieldPeriodically<'_, S> {
             }
         };
 
+        if let (Some(item_budget), Poll::Ready(Some(_))) = (*this.item_budget, &res) {
+            *this.current_item_budget = this.current_item_budget.saturating_sub(1);
+            if *this.must_yield || *this.current_item_budget == 0 {
+                *this.must_yield = true;
+                *this.current_item_budget = item_budget;
+            }
+        }
+
         res
     }
 }
@@ -173,6 +200,37 @@ mod test {
         stream.collect::<Vec<_>>().await;
     }
 
+    #[test]
+    fn test_yield_after_items() {
+        let stream = YieldPeriodically::new(futures::stream::iter(0..5), Duration::from_secs(60))
+            .with_item_budget(2);
+
+        futures::pin_mut!(stream);
+
+        let waker = futures::task::noop_waker();
+        let mut cx = futures::task::Context::from_waker(&waker);
+
+        let mut polls = Vec::new();
+        for _ in 0..9 {
+            polls.push(stream.as_mut().poll_next(&mut cx));
+        }
+
+        assert_eq!(
+            polls,
+            vec![
+                Poll::Ready(Some(0)),
+                Poll::Ready(Some(1)),
+                Poll::Pending,
+                Poll::Ready(Some(2)),
+                Poll::Ready(Some(3)),
+                Poll::Pending,
+                Poll::Ready(Some(4)),
+                Poll::Ready(None),
+                Poll::Ready(None),
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn test_on_large_overshoot() {
         let stream = futures::stream::repeat(()).inspect(|_| {
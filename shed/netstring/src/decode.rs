@@ -7,10 +7,10 @@
  * of this source tree.
  */
 
-use anyhow::bail;
-use anyhow::ensure;
 use anyhow::Error;
 use anyhow::Result;
+use anyhow::bail;
+use anyhow::ensure;
 use bytes::BytesMut;
 use tokio_util::codec::Decoder;
 
@@ -379,7 +379,10 @@ mod test {
         let mut codec = NetstringDecoder::default();
 
         match codec.decode(&mut buf) {
-            Err(e) => println!("got expected error {e:?}"),
+            Err(e) => {
+                error_codes::register(&crate::ERROR_DOMAIN).unwrap();
+                assert_eq!(error_codes::error_code(&e), Some(crate::NETSTRING_DECODE));
+            }
             bad => panic!(
                 "decode succeeded: {:?}",
                 bad.as_ref().map(|x| x.as_ref().map(BytesMut::as_ref))
@@ -18,6 +18,10 @@
 //! payload, followed by a ':', then the payload, and a terminating ','. There is no error
 //! checking or correction other than the requirement that the message be followed by a comma.
 
+use error_codes::ErrorCategory;
+use error_codes::ErrorCode;
+use error_codes::ErrorDomain;
+use error_codes::HasErrorCode;
 use thiserror::Error;
 
 /// Errors that can originate from this crate
@@ -28,6 +32,26 @@ pub enum ErrorKind {
     NetstringDecode(&'static str),
 }
 
+/// Code of the errors decoding a netstring, see [error_codes].
+pub const NETSTRING_DECODE: ErrorCode =
+    ErrorCode::new(1001, "NETSTRING_DECODE", ErrorCategory::InvalidInput);
+
+impl HasErrorCode for ErrorKind {
+    fn error_code(&self) -> ErrorCode {
+        match self {
+            ErrorKind::NetstringDecode(_) => NETSTRING_DECODE,
+        }
+    }
+}
+
+/// The error codes of this crate, to be registered with
+/// [error_codes::register].
+pub static ERROR_DOMAIN: ErrorDomain = ErrorDomain {
+    name: "netstring",
+    codes: &[NETSTRING_DECODE],
+    classify: |err| err.downcast_ref::<ErrorKind>().map(ErrorKind::error_code),
+};
+
 mod decode;
 mod encode;
 
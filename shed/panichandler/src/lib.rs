@@ -12,12 +12,20 @@
 
 #![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
 
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::hash::Hasher;
 use std::io;
 use std::io::BufWriter;
 use std::io::Write;
 use std::panic;
+use std::panic::Location;
 use std::panic::PanicHookInfo;
 use std::ptr;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
 
 use backtrace::Backtrace;
 use backtrace::SymbolName;
@@ -40,10 +48,144 @@ pub enum Fate {
     Abort,
 }
 
-fn handler(panic: &PanicHookInfo<'_>, fate: Fate) {
-    let stderr = io::stderr();
-    let mut w = BufWriter::new(stderr.lock());
+/// Configuration for deduplicating reports of panics that keep happening on
+/// the same code path, e.g. once per request.
+///
+/// Panics are fingerprinted by their message, location and the top
+/// `fingerprint_frames` frames of their backtrace. At most `max_reports`
+/// panics with the same fingerprint are reported per `window`, the rest are
+/// counted and the count is included in the next report for that fingerprint.
+///
+/// At most `max_fingerprints` fingerprints are tracked at once, the ones whose
+/// window expired are forgotten to make room for new ones. Panics with a new
+/// fingerprint that can't be tracked are suppressed too, and their count is
+/// included in the next report.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Dedup {
+    /// Number of backtrace frames that contribute to the fingerprint.
+    pub fingerprint_frames: usize,
+    /// Maximum number of reports per fingerprint in each window.
+    pub max_reports: u32,
+    /// Length of the rate limiting window.
+    pub window: Duration,
+    /// Maximum number of fingerprints tracked at once.
+    pub max_fingerprints: usize,
+}
+
+impl Default for Dedup {
+    fn default() -> Self {
+        Self {
+            fingerprint_frames: 32,
+            max_reports: 1,
+            window: Duration::from_secs(60),
+            max_fingerprints: 1024,
+        }
+    }
+}
+
+struct FingerprintState {
+    window_start: Instant,
+    reports: u32,
+    suppressed: u64,
+}
+
+#[derive(Default)]
+struct DedupState {
+    fingerprints: HashMap<u64, FingerprintState>,
+    // Panics suppressed without being counted for their fingerprint, because
+    // it couldn't be tracked or was forgotten.
+    untracked: u64,
+}
+
+/// Number of panics suppressed since the last report.
+struct Suppressed {
+    similar: u64,
+    other: u64,
+}
+
+struct Deduplicator {
+    config: Dedup,
+    // Panic hooks may run concurrently on several threads.
+    state: Mutex<DedupState>,
+}
+
+impl Deduplicator {
+    fn new(config: Dedup) -> Self {
+        Self {
+            config,
+            state: Mutex::new(DedupState::default()),
+        }
+    }
+
+    fn fingerprint(&self, msg: &str, loc: Option<&Location<'_>>, bt: &Backtrace) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        msg.hash(&mut hasher);
+        loc.map(|loc| (loc.file(), loc.line(), loc.column()))
+            .hash(&mut hasher);
+        for f in bt.frames().iter().take(self.config.fingerprint_frames) {
+            (f.ip() as usize).hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Returns `None` if this panic should not be reported, otherwise the
+    /// number of panics suppressed since the last report.
+    fn should_report(&self, fingerprint: u64) -> Option<Suppressed> {
+        // Don't let a poisoned lock stop us from reporting panics.
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let DedupState {
+            fingerprints,
+            untracked,
+        } = &mut *state;
+        let now = Instant::now();
+        let window = self.config.window;
+
+        if !fingerprints.contains_key(&fingerprint)
+            && fingerprints.len() >= self.config.max_fingerprints
+        {
+            fingerprints.retain(|_, fp| {
+                let expired = now.duration_since(fp.window_start) >= window;
+                if expired {
+                    *untracked += fp.suppressed;
+                }
+                !expired
+            });
+            if fingerprints.len() >= self.config.max_fingerprints {
+                *untracked += 1;
+                return None;
+            }
+        }
+
+        let fp = fingerprints
+            .entry(fingerprint)
+            .or_insert_with(|| FingerprintState {
+                window_start: now,
+                reports: 0,
+                suppressed: 0,
+            });
+
+        if now.duration_since(fp.window_start) >= window {
+            fp.window_start = now;
+            fp.reports = 0;
+        }
+
+        if fp.reports < self.config.max_reports {
+            fp.reports += 1;
+            Some(Suppressed {
+                similar: std::mem::take(&mut fp.suppressed),
+                other: std::mem::take(untracked),
+            })
+        } else {
+            fp.suppressed += 1;
+            None
+        }
+    }
+}
 
+fn handler(panic: &PanicHookInfo<'_>, fate: Fate, dedup: Option<&Deduplicator>) {
     let payload = panic.payload();
     let msg: &str = payload
         .downcast_ref::<&str>()
@@ -51,12 +193,42 @@ fn handler(panic: &PanicHookInfo<'_>, fate: Fate) {
         .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
         .unwrap_or("(about something)");
 
+    // Only resolve symbols once we know the panic is going to be reported.
+    let mut bt = Backtrace::new_unresolved();
+    let suppressed = match dedup {
+        Some(dedup) => {
+            let fingerprint = dedup.fingerprint(msg, panic.location(), &bt);
+            match dedup.should_report(fingerprint) {
+                Some(suppressed) => Some(suppressed),
+                None => {
+                    apply_fate(fate);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+    bt.resolve();
+
+    let stderr = io::stderr();
+    let mut w = BufWriter::new(stderr.lock());
+
     let _ = writeln!(w, "PANIC: {msg}");
     if let Some(loc) = panic.location() {
         let _ = writeln!(w, "from {loc}");
     }
+    if let Some(Suppressed { similar, other }) = suppressed {
+        if similar > 0 {
+            let _ = writeln!(
+                w,
+                "(suppressed {similar} similar panics since the last report)"
+            );
+        }
+        if other > 0 {
+            let _ = writeln!(w, "(suppressed {other} other panics since the last report)");
+        }
+    }
 
-    let bt = Backtrace::new();
     let frames = bt.frames();
     let frames = if frames.len() > MAX_FRAMES {
         let _ = writeln!(w, "(limiting {} frames to {})", frames.len(), MAX_FRAMES);
@@ -98,6 +270,10 @@ fn handler(panic: &PanicHookInfo<'_>, fate: Fate) {
     // Make sure everything's flushed before we (maybe) exit
     let _ = w.into_inner();
 
+    apply_fate(fate);
+}
+
+fn apply_fate(fate: Fate) {
     match fate {
         Fate::Continue => {}
         Fate::Exit(exit) => {
@@ -126,5 +302,13 @@ fn handler(panic: &PanicHookInfo<'_>, fate: Fate) {
 /// happens. The [Fate] parameter will define what this handler will do when
 /// panicing.
 pub fn set_panichandler(fate: Fate) {
-    panic::set_hook(Box::new(move |panic| handler(panic, fate)));
+    panic::set_hook(Box::new(move |panic| handler(panic, fate, None)));
+}
+
+/// Like [set_panichandler], but repeated panics are deduplicated and rate
+/// limited according to the [Dedup] configuration, to avoid flooding the
+/// output when a panicking code path is hit in a loop.
+pub fn set_panichandler_with_dedup(fate: Fate, dedup: Dedup) {
+    let dedup = Deduplicator::new(dedup);
+    panic::set_hook(Box::new(move |panic| handler(panic, fate, Some(&dedup))));
 }
@@ -0,0 +1,43 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::panic;
+use std::thread;
+use std::time::Duration;
+
+use panichandler::Dedup;
+use panichandler::Fate;
+
+fn adventure() {
+    let _ = panic::catch_unwind(|| panic!("I paniced! {}", 1234));
+}
+
+fn main() {
+    println!("I'm on an adventure!");
+
+    panichandler::set_panichandler_with_dedup(
+        Fate::Continue,
+        Dedup {
+            max_reports: 2,
+            window: Duration::from_millis(500),
+            ..Default::default()
+        },
+    );
+
+    // Panic from the same call site every time, so all panics share a
+    // fingerprint. The last one happens in a new window and gets reported.
+    for i in 0..11 {
+        if i == 10 {
+            thread::sleep(Duration::from_millis(600));
+        }
+        adventure();
+    }
+
+    println!("I'm back!");
+}
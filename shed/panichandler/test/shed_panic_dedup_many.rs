@@ -0,0 +1,44 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::panic;
+use std::thread;
+use std::time::Duration;
+
+use panichandler::Dedup;
+use panichandler::Fate;
+
+fn adventure(i: usize) {
+    // The message differs every time, so does the fingerprint.
+    let _ = panic::catch_unwind(|| panic!("I paniced! {}", i));
+}
+
+fn main() {
+    println!("I'm on an adventure!");
+
+    panichandler::set_panichandler_with_dedup(
+        Fate::Continue,
+        Dedup {
+            window: Duration::from_millis(500),
+            max_fingerprints: 2,
+            ..Default::default()
+        },
+    );
+
+    // Only the first two fingerprints can be tracked, until their window
+    // expires and they are forgotten, making room for the last one.
+    for i in 0..6 {
+        if i == 5 {
+            thread::sleep(Duration::from_millis(600));
+        }
+        adventure(i);
+    }
+
+    println!("I'm back!");
+}
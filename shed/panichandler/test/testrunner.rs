@@ -56,6 +56,43 @@ fn test_deep() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_dedup() -> Result<()> {
+    let mut cmd = get_command!("shed_panic_dedup");
+    let output = cmd.output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"I'm on an adventure!\nI'm back!\n");
+
+    let stderr = String::from_utf8(output.stderr)?;
+    assert_eq!(stderr.matches("PANIC: I paniced! 1234\n").count(), 3);
+    assert_eq!(
+        stderr
+            .matches("(suppressed 8 similar panics since the last report)")
+            .count(),
+        1
+    );
+    Ok(())
+}
+
+#[test]
+fn test_dedup_many() -> Result<()> {
+    let mut cmd = get_command!("shed_panic_dedup_many");
+    let output = cmd.output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"I'm on an adventure!\nI'm back!\n");
+
+    let stderr = String::from_utf8(output.stderr)?;
+    assert_eq!(stderr.matches("PANIC: I paniced! ").count(), 3);
+    assert!(stderr.contains("PANIC: I paniced! 5\n"));
+    assert_eq!(
+        stderr
+            .matches("(suppressed 3 other panics since the last report)")
+            .count(),
+        1
+    );
+    Ok(())
+}
+
 #[test]
 fn testmultithread() -> Result<()> {
     let mut cmd = get_command!("shed_panic_multithread");
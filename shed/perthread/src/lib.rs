@@ -37,9 +37,14 @@
 //!
 //! COUNTER.with(|c| println!("COUNTER: {:?}", *c));
 //! ```
+//!
+//! The [registry] module builds on this to keep track of the live threads of
+//! the process.
 
 #![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
 
+pub mod registry;
+
 use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Debug;
@@ -0,0 +1,204 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Registry of the live threads of the process that registered themselves,
+//! with their name, registration time and, where supported, CPU time, to
+//! diagnose runaway helper threads.
+//!
+//! Threads register themselves by calling [register_current_thread], usually
+//! first thing after being spawned, and are removed from the registry when
+//! they exit.
+//!
+//! ```
+//! use perthread::registry;
+//!
+//! std::thread::Builder::new()
+//!     .name("helper".to_owned())
+//!     .spawn(|| {
+//!         registry::register_current_thread();
+//!         assert!(
+//!             registry::registered_threads()
+//!                 .iter()
+//!                 .any(|thread| thread.name.as_deref() == Some("helper"))
+//!         );
+//!     })
+//!     .unwrap()
+//!     .join()
+//!     .unwrap();
+//! ```
+
+use std::sync::LazyLock;
+use std::thread::ThreadId;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crate::PerThread;
+use crate::ThreadMap;
+
+static THREADS: LazyLock<ThreadMap<RegisteredThread>> = LazyLock::new(ThreadMap::default);
+
+thread_local! {
+    static REGISTRATION: PerThread<RegisteredThread> =
+        THREADS.register(RegisteredThread::current());
+}
+
+struct RegisteredThread {
+    name: Option<String>,
+    id: ThreadId,
+    os_id: Option<u64>,
+    registered_at: SystemTime,
+    #[cfg(target_os = "linux")]
+    pthread: libc::pthread_t,
+}
+
+impl RegisteredThread {
+    fn current() -> Self {
+        let thread = std::thread::current();
+        Self {
+            name: thread.name().map(ToOwned::to_owned),
+            id: thread.id(),
+            os_id: os_id(),
+            registered_at: SystemTime::now(),
+            #[cfg(target_os = "linux")]
+            // SAFETY: pthread_self has no preconditions.
+            pthread: unsafe { libc::pthread_self() },
+        }
+    }
+
+    fn snapshot(&self) -> ThreadSnapshot {
+        ThreadSnapshot {
+            name: self.name.clone(),
+            id: self.id,
+            os_id: self.os_id,
+            registered_at: self.registered_at,
+            cpu_time: self.cpu_time(),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn cpu_time(&self) -> Option<Duration> {
+        // The thread is alive as it unregisters itself before exiting, which
+        // waits for the lock of the registry held while taking snapshots.
+        let mut clock_id = 0;
+        // SAFETY: the thread handle is valid, see above.
+        if unsafe { libc::pthread_getcpuclockid(self.pthread, &mut clock_id) } != 0 {
+            return None;
+        }
+        let mut time = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: time is a valid timespec to write to.
+        if unsafe { libc::clock_gettime(clock_id, &mut time) } != 0 {
+            return None;
+        }
+        Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn cpu_time(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn os_id() -> Option<u64> {
+    // SAFETY: gettid has no preconditions.
+    Some(unsafe { libc::gettid() } as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn os_id() -> Option<u64> {
+    None
+}
+
+/// The state of a registered thread at the time [registered_threads] was
+/// called.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThreadSnapshot {
+    /// Name of the thread, if it was given one.
+    pub name: Option<String>,
+    /// Id of the thread within the process.
+    pub id: ThreadId,
+    /// Id of the thread given by the operating system, on Linux only.
+    pub os_id: Option<u64>,
+    /// When the thread registered itself.
+    pub registered_at: SystemTime,
+    /// CPU time used by the thread since it started, on Linux only.
+    pub cpu_time: Option<Duration>,
+}
+
+/// Register the current thread, if it isn't registered yet. The thread stays
+/// registered until it exits.
+pub fn register_current_thread() {
+    REGISTRATION.with(|_| {});
+}
+
+/// Return the snapshots of the registered threads that are still alive, in no
+/// particular order.
+pub fn registered_threads() -> Vec<ThreadSnapshot> {
+    let mut threads = Vec::new();
+    THREADS.for_each(|thread| threads.push(thread.snapshot()));
+    threads
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::sync_channel;
+
+    use super::*;
+
+    fn find(name: &str) -> Option<ThreadSnapshot> {
+        registered_threads()
+            .into_iter()
+            .find(|thread| thread.name.as_deref() == Some(name))
+    }
+
+    #[test]
+    fn test_registration() {
+        let (registered_sender, registered_receiver) = sync_channel(0);
+        let (exit_sender, exit_receiver) = sync_channel(0);
+
+        let thread = std::thread::Builder::new()
+            .name("test_registration".to_owned())
+            .spawn(move || {
+                register_current_thread();
+                register_current_thread();
+                // Burn some CPU time.
+                let mut x = 0u64;
+                for i in 0..1_000_000 {
+                    x = std::hint::black_box(x.wrapping_add(i));
+                }
+                registered_sender.send(()).unwrap();
+                exit_receiver.recv().unwrap();
+                x
+            })
+            .unwrap();
+
+        registered_receiver.recv().unwrap();
+        let snapshot = find("test_registration").unwrap();
+        assert_eq!(snapshot.id, thread.thread().id());
+        assert!(snapshot.registered_at <= SystemTime::now());
+        if cfg!(target_os = "linux") {
+            assert!(snapshot.os_id.is_some());
+            assert!(snapshot.cpu_time.unwrap() > Duration::ZERO);
+        }
+        assert_eq!(
+            registered_threads()
+                .iter()
+                .filter(|thread| thread.id == snapshot.id)
+                .count(),
+            1
+        );
+
+        exit_sender.send(()).unwrap();
+        thread.join().unwrap();
+        assert_eq!(find("test_registration"), None);
+    }
+}
@@ -0,0 +1,307 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! TLS connectors presenting a client identity selected by destination, for
+//! clients connecting with mutual TLS to services that each expect their own
+//! identity, see [MtlsConnectorBuilder].
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use anyhow::format_err;
+use anyhow::Context;
+use anyhow::Result;
+#[allow(deprecated)]
+use openssl::pkcs12::ParsedPkcs12;
+use openssl::ssl::SslConnector;
+use openssl::ssl::SslMethod;
+
+use crate::build_identity;
+use crate::read_x509_stack;
+
+/// Certificate and private key pem files presented by a client to the
+/// servers requiring client authentication.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ClientIdentity {
+    cert: PathBuf,
+    private_key: PathBuf,
+}
+
+impl ClientIdentity {
+    /// Create a new instance of ClientIdentity
+    pub fn new(cert: impl Into<PathBuf>, private_key: impl Into<PathBuf>) -> Self {
+        Self {
+            cert: cert.into(),
+            private_key: private_key.into(),
+        }
+    }
+
+    /// The certificate pem file
+    pub fn cert(&self) -> &Path {
+        &self.cert
+    }
+
+    /// The private key pem file
+    pub fn private_key(&self) -> &Path {
+        &self.private_key
+    }
+}
+
+/// A destination a client connects to: the server name it sends with SNI,
+/// and the labels of the endpoint, e.g. the tier it belongs to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Destination {
+    server_name: String,
+    labels: Vec<String>,
+}
+
+impl Destination {
+    /// Create a destination with the given server name and no labels
+    pub fn new(server_name: impl Into<String>) -> Self {
+        Self {
+            server_name: server_name.into(),
+            labels: Vec::new(),
+        }
+    }
+
+    /// Add a label to the destination
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.labels.push(label.into());
+        self
+    }
+
+    /// The server name of the destination
+    pub fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    /// The labels of the destination
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+}
+
+/// The client identities to present to each destination.
+///
+/// The identity of a destination is, in order of precedence, the one of its
+/// server name, the one of the longest wildcard pattern matching its server
+/// name, the one of the first of its labels that has one, and the default
+/// one.
+#[derive(Clone, Debug, Default)]
+pub struct IdentityRegistry {
+    server_names: HashMap<String, ClientIdentity>,
+    wildcards: Vec<(String, ClientIdentity)>,
+    labels: HashMap<String, ClientIdentity>,
+    default: Option<ClientIdentity>,
+}
+
+impl IdentityRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Present `identity` to the destinations with the server name
+    /// `pattern`, or, if it starts with `*.`, e.g. `*.example.com`, to those
+    /// whose server name ends with the rest of it, e.g. `a.b.example.com`.
+    pub fn with_server_name(mut self, pattern: &str, identity: ClientIdentity) -> Self {
+        match pattern.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') => {
+                self.wildcards.retain(|(other, _)| other != suffix);
+                self.wildcards.push((suffix.to_owned(), identity));
+                // The longest suffixes are the most specific.
+                self.wildcards
+                    .sort_by_key(|(suffix, _)| Reverse(suffix.len()));
+            }
+            _ => {
+                self.server_names.insert(pattern.to_owned(), identity);
+            }
+        }
+        self
+    }
+
+    /// Present `identity` to the destinations with the label `label`
+    pub fn with_label(mut self, label: &str, identity: ClientIdentity) -> Self {
+        self.labels.insert(label.to_owned(), identity);
+        self
+    }
+
+    /// Present `identity` to the destinations no other identity applies to
+    pub fn with_default(mut self, identity: ClientIdentity) -> Self {
+        self.default = Some(identity);
+        self
+    }
+
+    /// Select the identity to present to the destination, if any
+    pub fn select(&self, destination: &Destination) -> Option<&ClientIdentity> {
+        let server_name = destination.server_name();
+        self.server_names
+            .get(server_name)
+            .or_else(|| {
+                self.wildcards
+                    .iter()
+                    .find(|(suffix, _)| server_name.ends_with(suffix.as_str()))
+                    .map(|(_, identity)| identity)
+            })
+            .or_else(|| {
+                destination
+                    .labels()
+                    .iter()
+                    .find_map(|label| self.labels.get(label))
+            })
+            .or(self.default.as_ref())
+    }
+}
+
+/// Builds the TLS connectors presenting the identity selected for each
+/// destination by an [IdentityRegistry], and verifying the servers with the
+/// root certificates of a CA pem file.
+///
+/// A connector is built once per identity and shared by the destinations
+/// using it. It is rebuilt when the pem files it was built from are modified,
+/// e.g. when the certificates are renewed, or when the registry is replaced
+/// with [MtlsConnectorBuilder::set_registry].
+pub struct MtlsConnectorBuilder {
+    ca_pem: PathBuf,
+    registry: RwLock<Arc<IdentityRegistry>>,
+    connectors: Mutex<HashMap<ClientIdentity, CachedConnector>>,
+}
+
+struct CachedConnector {
+    connector: SslConnector,
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl MtlsConnectorBuilder {
+    /// Create a new instance of MtlsConnectorBuilder
+    pub fn new(ca_pem: impl Into<PathBuf>, registry: IdentityRegistry) -> Self {
+        Self {
+            ca_pem: ca_pem.into(),
+            registry: RwLock::new(Arc::new(registry)),
+            connectors: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The registry selecting the identities
+    pub fn registry(&self) -> Arc<IdentityRegistry> {
+        self.registry.read().expect("poisoned lock").clone()
+    }
+
+    /// Replace the registry, e.g. when its configuration is reloaded. The
+    /// connectors are rebuilt.
+    pub fn set_registry(&self, registry: IdentityRegistry) {
+        *self.registry.write().expect("poisoned lock") = Arc::new(registry);
+        self.connectors.lock().expect("poisoned lock").clear();
+    }
+
+    /// Builds the connector for the destination, whose server name should be
+    /// used as the domain of the connection. Fails if no identity applies to
+    /// the destination.
+    pub fn build(&self, destination: &Destination) -> Result<SslConnector> {
+        let registry = self.registry();
+        let identity = registry.select(destination).ok_or_else(|| {
+            format_err!(
+                "No client identity for destination {}",
+                destination.server_name()
+            )
+        })?;
+        let modified = [&self.ca_pem, &identity.cert, &identity.private_key]
+            .into_iter()
+            .map(|path| path.metadata().and_then(|m| m.modified()).ok())
+            .collect::<Vec<_>>();
+
+        let mut connectors = self.connectors.lock().expect("poisoned lock");
+        if let Some(cached) = connectors.get(identity) {
+            if cached.modified == modified {
+                return Ok(cached.connector.clone());
+            }
+        }
+        let connector = self.build_connector(identity).with_context(|| {
+            format!(
+                "failed to build connector for destination {}",
+                destination.server_name()
+            )
+        })?;
+        connectors.insert(
+            identity.clone(),
+            CachedConnector {
+                connector: connector.clone(),
+                modified,
+            },
+        );
+        Ok(connector)
+    }
+
+    fn build_connector(&self, identity: &ClientIdentity) -> Result<SslConnector> {
+        let mut connector = SslConnector::builder(SslMethod::tls_client())?;
+
+        let pkcs12 = build_identity(&identity.cert, &identity.private_key)
+            .context("failed to build pkcs12")?;
+
+        #[allow(deprecated)]
+        let ParsedPkcs12 { cert, pkey, .. } = pkcs12;
+
+        connector.set_certificate(&cert)?;
+        connector.set_private_key(&pkey)?;
+        connector.check_private_key()?;
+
+        // Verify the servers via root certificate
+        for cert in read_x509_stack(&self.ca_pem)? {
+            connector.cert_store_mut().add_cert(cert)?;
+        }
+
+        Ok(connector.build())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let identity = |name: &str| ClientIdentity::new(name, name);
+        let registry = IdentityRegistry::new()
+            .with_server_name("db.example.com", identity("db"))
+            .with_server_name("*.example.com", identity("example"))
+            .with_server_name("*.eu.example.com", identity("eu"))
+            .with_label("prod", identity("prod"));
+        let select = |destination: Destination| {
+            registry
+                .select(&destination)
+                .map(|identity| identity.cert().to_str().unwrap().to_owned())
+        };
+
+        assert_eq!(select(Destination::new("db.example.com")).unwrap(), "db");
+        assert_eq!(select(Destination::new("a.eu.example.com")).unwrap(), "eu");
+        assert_eq!(
+            select(Destination::new("a.example.com")).unwrap(),
+            "example"
+        );
+        assert_eq!(
+            select(Destination::new("example.com").with_label("prod")).unwrap(),
+            "prod"
+        );
+        assert_eq!(select(Destination::new("example.com")), None);
+
+        let registry = registry.with_default(identity("default"));
+        assert_eq!(
+            registry
+                .select(&Destination::new("example.com"))
+                .map(ClientIdentity::cert),
+            Some(Path::new("default"))
+        );
+    }
+}
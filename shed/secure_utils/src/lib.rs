@@ -11,6 +11,8 @@
 
 #![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
 
+pub mod connector;
+
 #[cfg(fbcode_build)]
 pub mod facebook;
 
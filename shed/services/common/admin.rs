@@ -0,0 +1,541 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! A minimal HTTP admin server exposing the status of a service and debug
+//! endpoints, with a chain of middlewares wrapping every request, e.g. so
+//! that the debug endpoints can be exposed in production behind an
+//! authorization check.
+//!
+//! ```
+//! use services_common::admin::AdminResponse;
+//! use services_common::admin::AdminServer;
+//! use services_common::admin::Authorize;
+//! use services_common::admin::CatchPanic;
+//! use services_common::AliveService;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let server = AdminServer::new()
+//!     .with_status(AliveService::new().into())
+//!     .route("/debug/config", |_req| async {
+//!         AdminResponse::text(200, "{}")
+//!     })
+//!     .with_middleware(CatchPanic)
+//!     .with_middleware(Authorize::new(|req| {
+//!         req.path() == "/status" || req.header("x-admin-token") == Some("secret")
+//!     }))
+//!     .bind("[::]:8080")
+//!     .await?;
+//! println!("admin server listening on {}", server.local_addr());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The server only supports `HTTP/1.1` requests without a body, and closes
+//! the connection after each response.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::bail;
+use anyhow::Error;
+use futures::future::BoxFuture;
+use futures::future::FutureExt;
+use scuba_sample_builder::ScubaSampleBuilder;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::net::ToSocketAddrs;
+use tokio::task::JoinHandle;
+
+use crate::Fb303Service;
+use crate::FbStatus;
+
+/// Largest size of the head of a request, above which it is rejected.
+const MAX_HEAD_BYTES: u64 = 16 * 1024;
+
+/// How long a client has to send the head of its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request received by the admin server.
+#[derive(Clone, Debug)]
+pub struct AdminRequest {
+    method: String,
+    path: String,
+    query: Option<String>,
+    headers: Vec<(String, String)>,
+    peer_addr: SocketAddr,
+}
+
+impl AdminRequest {
+    /// Return the method of the request, e.g. `GET`.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Return the path of the request, without its query string.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Return the query string of the request, without the `?`, if any.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// Return the value of the first header with the given name, compared
+    /// case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Return the address of the client.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+}
+
+/// A response of the admin server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdminResponse {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl AdminResponse {
+    /// Create a plain text response with the given status code.
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into().into_bytes(),
+        }
+    }
+
+    /// Create a JSON response with the given status code.
+    pub fn json(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: body.into().into_bytes(),
+        }
+    }
+
+    /// Return the status code of the response.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Return the body of the response.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+type Handler = Arc<dyn Fn(AdminRequest) -> BoxFuture<'static, AdminResponse> + Send + Sync>;
+
+/// A middleware wrapping the requests of the admin server, which can answer
+/// them itself, e.g. to deny them, or pass them on to the rest of the chain
+/// with [Next::run].
+pub trait AdminMiddleware: Send + Sync + 'static {
+    /// Handle the request, calling `next` to pass it on.
+    fn handle<'a>(&'a self, req: AdminRequest, next: Next<'a>) -> BoxFuture<'a, AdminResponse>;
+}
+
+/// The rest of the middleware chain, ending with the handler of the route.
+pub struct Next<'a> {
+    middlewares: &'a [Arc<dyn AdminMiddleware>],
+    handler: Option<&'a Handler>,
+}
+
+impl<'a> Next<'a> {
+    /// Pass the request on to the next middleware, or to the handler of its
+    /// route once all the middlewares ran.
+    pub fn run(self, req: AdminRequest) -> BoxFuture<'a, AdminResponse> {
+        match self.middlewares.split_first() {
+            Some((middleware, middlewares)) => middleware.handle(
+                req,
+                Next {
+                    middlewares,
+                    handler: self.handler,
+                },
+            ),
+            None => match self.handler {
+                Some(handler) => handler(req),
+                None => async { AdminResponse::text(404, "Not Found") }.boxed(),
+            },
+        }
+    }
+}
+
+/// Middleware answering the requests for which the callback returns false
+/// with `403 Forbidden`, without running the rest of the chain.
+pub struct Authorize<F> {
+    authorize: F,
+}
+
+impl<F> Authorize<F>
+where
+    F: Fn(&AdminRequest) -> bool + Send + Sync + 'static,
+{
+    /// Create the middleware, authorizing the requests with `authorize`.
+    pub fn new(authorize: F) -> Self {
+        Self { authorize }
+    }
+}
+
+impl<F> AdminMiddleware for Authorize<F>
+where
+    F: Fn(&AdminRequest) -> bool + Send + Sync + 'static,
+{
+    fn handle<'a>(&'a self, req: AdminRequest, next: Next<'a>) -> BoxFuture<'a, AdminResponse> {
+        if (self.authorize)(&req) {
+            next.run(req)
+        } else {
+            async { AdminResponse::text(403, "Forbidden") }.boxed()
+        }
+    }
+}
+
+/// Middleware logging a sample per request to scuba, with its method, path,
+/// client, status code and duration in microseconds.
+pub struct LogRequests {
+    scuba: ScubaSampleBuilder,
+}
+
+impl LogRequests {
+    /// Create the middleware, logging to the dataset of `scuba`, whose other
+    /// columns are logged with every sample.
+    pub fn new(scuba: ScubaSampleBuilder) -> Self {
+        Self { scuba }
+    }
+}
+
+impl AdminMiddleware for LogRequests {
+    fn handle<'a>(&'a self, req: AdminRequest, next: Next<'a>) -> BoxFuture<'a, AdminResponse> {
+        async move {
+            let mut scuba = self.scuba.clone();
+            scuba
+                .add("method", req.method())
+                .add("path", req.path())
+                .add("client", req.peer_addr().to_string());
+            let start = Instant::now();
+            let response = next.run(req).await;
+            scuba
+                .add("status", response.status())
+                .add("duration_us", start.elapsed().as_micros() as u64)
+                .log();
+            response
+        }
+        .boxed()
+    }
+}
+
+/// Middleware answering the requests whose handling panicked with
+/// `500 Internal Server Error`, rather than dropping their connection.
+pub struct CatchPanic;
+
+impl AdminMiddleware for CatchPanic {
+    fn handle<'a>(&'a self, req: AdminRequest, next: Next<'a>) -> BoxFuture<'a, AdminResponse> {
+        AssertUnwindSafe(next.run(req))
+            .catch_unwind()
+            .map(|res| res.unwrap_or_else(|_| AdminResponse::text(500, "Internal Server Error")))
+            .boxed()
+    }
+}
+
+/// Builder of an admin server, see the [module documentation](self).
+#[derive(Default)]
+pub struct AdminServer {
+    routes: HashMap<String, Handler>,
+    middlewares: Vec<Arc<dyn AdminMiddleware>>,
+}
+
+impl AdminServer {
+    /// Create a server without routes nor middlewares.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer the requests for `path` with `handler`, replacing the previous
+    /// handler of that path.
+    pub fn route<F, Fut>(mut self, path: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(AdminRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = AdminResponse> + Send + 'static,
+    {
+        self.routes
+            .insert(path.into(), Arc::new(move |req| handler(req).boxed()));
+        self
+    }
+
+    /// Answer the requests for `/status` with the status of `service`, and a
+    /// `503 Service Unavailable` status code unless it is alive.
+    pub fn with_status(self, service: Arc<dyn Fb303Service>) -> Self {
+        self.route("/status", move |_req| {
+            let status = service.getStatus();
+            async move {
+                let code = match status {
+                    FbStatus::Alive | FbStatus::Warning => 200,
+                    _ => 503,
+                };
+                AdminResponse::text(code, format!("{:?}", status))
+            }
+        })
+    }
+
+    /// Add a middleware wrapping every request, including those of unknown
+    /// paths. Middlewares run in the order they were added, so the first one
+    /// wraps all the others.
+    pub fn with_middleware(mut self, middleware: impl AdminMiddleware) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Listen on `addr` and serve the requests in the background until the
+    /// returned handle is dropped.
+    pub async fn bind(self, addr: impl ToSocketAddrs) -> Result<AdminServerHandle, Error> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let server = Arc::new(self);
+        let handle = tokio::spawn(async move {
+            loop {
+                let (stream, peer_addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(_) => continue,
+                };
+                let server = server.clone();
+                tokio::spawn(async move {
+                    // The client may have gone away, there is nobody to
+                    // report the error to.
+                    let _ = server.serve_connection(stream, peer_addr).await;
+                });
+            }
+        });
+        Ok(AdminServerHandle { handle, local_addr })
+    }
+
+    /// Run a request through the middlewares and the handler of its route.
+    pub async fn handle(&self, req: AdminRequest) -> AdminResponse {
+        let next = Next {
+            middlewares: &self.middlewares,
+            handler: self.routes.get(req.path()),
+        };
+        next.run(req).await
+    }
+
+    async fn serve_connection(
+        &self,
+        stream: TcpStream,
+        peer_addr: SocketAddr,
+    ) -> Result<(), Error> {
+        let mut stream = BufReader::new(stream);
+        let request =
+            tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream, peer_addr)).await;
+        let response = match request {
+            Ok(Ok(req)) => self.handle(req).await,
+            Ok(Err(_)) => AdminResponse::text(400, "Bad Request"),
+            Err(_) => AdminResponse::text(408, "Request Timeout"),
+        };
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.status,
+            reason(response.status),
+            response.content_type,
+            response.body.len(),
+        );
+        let stream = stream.get_mut();
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&response.body).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// Handle of a server started by [AdminServer::bind], which stops it when
+/// dropped.
+#[must_use = "the admin server stops when its handle is dropped"]
+pub struct AdminServerHandle {
+    handle: JoinHandle<()>,
+    local_addr: SocketAddr,
+}
+
+impl AdminServerHandle {
+    /// Return the address the server listens on, e.g. to find the port
+    /// chosen by the system when binding port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for AdminServerHandle {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Read the request line and the headers of a request.
+async fn read_request(
+    stream: &mut BufReader<TcpStream>,
+    peer_addr: SocketAddr,
+) -> Result<AdminRequest, Error> {
+    let mut head = stream.take(MAX_HEAD_BYTES);
+    let mut line = String::new();
+    head.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        bail!("Invalid request line: {:?}", line);
+    };
+    if !version.starts_with("HTTP/1.") {
+        bail!("Unsupported HTTP version: {}", version);
+    }
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_owned())),
+        None => (target, None),
+    };
+    let method = method.to_owned();
+    let path = path.to_owned();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if head.read_line(&mut line).await? == 0 {
+            bail!("Request head truncated");
+        }
+        let header = line.trim_end_matches(['\r', '\n']);
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            bail!("Invalid header: {:?}", header);
+        };
+        headers.push((name.trim().to_owned(), value.trim().to_owned()));
+    }
+
+    Ok(AdminRequest {
+        method,
+        path,
+        query,
+        headers,
+        peer_addr,
+    })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        408 => "Request Timeout",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    /// Send a GET request for `target` and return the status code and body
+    /// of the response.
+    async fn get(addr: SocketAddr, target: &str, token: Option<&str>) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n", target);
+        if let Some(token) = token {
+            request.push_str(&format!("X-Admin-Token: {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, body.to_owned())
+    }
+
+    #[tokio::test]
+    async fn test_middlewares() -> Result<(), Error> {
+        let log = tempfile::NamedTempFile::new()?;
+        let scuba = ScubaSampleBuilder::with_discard().with_log_file(log.path())?;
+        let server = AdminServer::new()
+            .with_status(crate::AliveService::new().into())
+            .route("/debug/echo", |req| async move {
+                AdminResponse::text(200, req.query().unwrap_or_default())
+            })
+            .route("/debug/panic", |_req| async { panic!("handler panicked") })
+            .with_middleware(LogRequests::new(scuba))
+            .with_middleware(CatchPanic)
+            .with_middleware(Authorize::new(|req| {
+                req.path() == "/status" || req.header("x-admin-token") == Some("secret")
+            }))
+            .bind("127.0.0.1:0")
+            .await?;
+        let addr = server.local_addr();
+
+        assert_eq!(get(addr, "/status", None).await, (200, "Alive".to_owned()));
+        assert_eq!(get(addr, "/debug/echo?a=1", None).await.0, 403);
+        assert_eq!(
+            get(addr, "/debug/echo?a=1", Some("secret")).await,
+            (200, "a=1".to_owned())
+        );
+        assert_eq!(get(addr, "/debug/panic", Some("secret")).await.0, 500);
+        assert_eq!(get(addr, "/unknown", Some("secret")).await.0, 404);
+
+        let samples = fs::read_to_string(log.path())?;
+        let statuses: Vec<_> = samples
+            .lines()
+            .map(|sample| {
+                let sample: serde_json::Value = serde_json::from_str(sample).unwrap();
+                (
+                    sample["normal"]["path"].as_str().unwrap().to_owned(),
+                    sample["int"]["status"].as_i64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("/status".to_owned(), 200),
+                ("/debug/echo".to_owned(), 403),
+                ("/debug/echo".to_owned(), 200),
+                ("/debug/panic".to_owned(), 500),
+                ("/unknown".to_owned(), 404),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bad_request() -> Result<(), Error> {
+        let server = AdminServer::new().bind("127.0.0.1:0").await?;
+        let mut stream = TcpStream::connect(server.local_addr()).await?;
+        stream.write_all(b"NOT HTTP\r\n\r\n").await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        Ok(())
+    }
+}
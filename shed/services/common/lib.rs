@@ -11,8 +11,15 @@
 
 //! Crate defining basic trates and structures for handing fb303 thrift services
 
+use error_codes::ErrorCategory;
+use error_codes::ErrorCode;
+use error_codes::ErrorDomain;
+use error_codes::HasErrorCode;
 use thiserror::Error;
 
+pub mod admin;
+pub mod scheduler;
+
 /// Services Error type.
 #[derive(Error, Debug)]
 pub enum ServicesError {
@@ -21,6 +28,26 @@ pub enum ServicesError {
     CxxException(#[from] cxx::Exception),
 }
 
+/// Code of the unknown C++ exceptions, see [error_codes].
+pub const SERVICES_CXX_EXCEPTION: ErrorCode =
+    ErrorCode::new(3001, "SERVICES_CXX_EXCEPTION", ErrorCategory::Permanent);
+
+impl HasErrorCode for ServicesError {
+    fn error_code(&self) -> ErrorCode {
+        match self {
+            ServicesError::CxxException(_) => SERVICES_CXX_EXCEPTION,
+        }
+    }
+}
+
+/// The error codes of this crate, to be registered with
+/// [error_codes::register].
+pub static ERROR_DOMAIN: ErrorDomain = ErrorDomain {
+    name: "services",
+    codes: &[SERVICES_CXX_EXCEPTION],
+    classify: |err| err.downcast_ref::<ServicesError>().map(ServicesError::error_code),
+};
+
 #[cfg(fbcode_build)]
 mod facebook;
 
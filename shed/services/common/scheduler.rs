@@ -0,0 +1,554 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! A scheduler running named async jobs in the background, either
+//! periodically or at the times matching cron expressions.
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use services_common::scheduler::OverlapPolicy;
+//! use services_common::scheduler::Schedule;
+//! use services_common::scheduler::Scheduler;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let mut scheduler = Scheduler::new();
+//! scheduler.add_job(
+//!     "refresh_cache",
+//!     Schedule::every(Duration::from_secs(60)).with_jitter(Duration::from_secs(5)),
+//!     OverlapPolicy::Skip,
+//!     || async { Ok(()) },
+//! )?;
+//! scheduler.add_job(
+//!     "daily_cleanup",
+//!     Schedule::cron("30 2 * * *")?,
+//!     OverlapPolicy::Queue,
+//!     || async { Ok(()) },
+//! )?;
+//! let handle = scheduler.start();
+//!
+//! for status in handle.statuses() {
+//!     println!("{}: {:?}", status.name, status.last_error);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod cron;
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use anyhow::Error;
+use chrono::DateTime;
+use chrono::Utc;
+use futures::future::BoxFuture;
+use futures::future::FutureExt;
+use rand::Rng;
+use stats::prelude::*;
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+pub use self::cron::CronError;
+pub use self::cron::CronSchedule;
+
+define_stats! {
+    prefix = "services.scheduler";
+    runs: dynamic_timeseries("{}.runs", (job: String); Rate, Sum),
+    failures: dynamic_timeseries("{}.failures", (job: String); Rate, Sum),
+    skipped: dynamic_timeseries("{}.skipped", (job: String); Rate, Sum),
+    cancelled: dynamic_timeseries("{}.cancelled", (job: String); Rate, Sum),
+    duration_ms: dynamic_histogram("{}.duration_ms", (job: String); 100, 0, 10_000, Average, Count; P 50; P 95; P 99),
+}
+
+/// Errors of the scheduler.
+#[derive(Debug, Error)]
+pub enum SchedulerError {
+    /// A job with the same name was already added to the scheduler.
+    #[error("A job named {0} is already scheduled")]
+    DuplicateJob(String),
+}
+
+/// When a job runs.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    trigger: Trigger,
+    jitter: Duration,
+}
+
+#[derive(Clone, Debug)]
+enum Trigger {
+    Every(Duration),
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    /// Run the job when the scheduler starts, and then every `period`.
+    /// Runs that are late, e.g. because the runtime is busy, don't
+    /// accumulate: the next run is `period` after the late one.
+    pub fn every(period: Duration) -> Self {
+        Self {
+            trigger: Trigger::Every(period),
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// Run the job at the times matching the cron expression, in UTC. See
+    /// [CronSchedule] for the supported syntax.
+    pub fn cron(expression: &str) -> Result<Self, CronError> {
+        Ok(Self {
+            trigger: Trigger::Cron(expression.parse()?),
+            jitter: Duration::ZERO,
+        })
+    }
+
+    /// Delay each run by a random duration up to `jitter`, so that the jobs
+    /// of many processes sharing a schedule don't all run at the same time.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    fn random_jitter(&self) -> Duration {
+        if self.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            rand::thread_rng().gen_range(Duration::ZERO..=self.jitter)
+        }
+    }
+}
+
+/// What to do when a run of a job is due while the previous one is still
+/// running.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OverlapPolicy {
+    /// Don't start the new run.
+    Skip,
+    /// Start the new run once the previous one completes. Runs pile up if
+    /// the job keeps taking longer than its schedule allows.
+    Queue,
+    /// Cancel the previous run, and start the new one.
+    CancelPrevious,
+}
+
+/// Error returned by the last failed run of a job.
+#[derive(Clone, Debug)]
+pub struct JobError {
+    /// When the failed run started.
+    pub time: SystemTime,
+    /// The error, with its context.
+    pub message: String,
+}
+
+/// Snapshot of the state of a job, e.g. to report it from an admin
+/// endpoint.
+#[derive(Clone, Debug)]
+pub struct JobStatus {
+    /// Name of the job.
+    pub name: String,
+    /// Whether the job is currently running.
+    pub running: bool,
+    /// Number of runs that completed, successfully or not.
+    pub runs: u64,
+    /// Number of runs that failed.
+    pub failures: u64,
+    /// Number of runs skipped because of [OverlapPolicy::Skip].
+    pub skipped: u64,
+    /// Number of runs cancelled because of [OverlapPolicy::CancelPrevious].
+    pub cancelled: u64,
+    /// When the last run started.
+    pub last_start: Option<SystemTime>,
+    /// How long the last completed run took.
+    pub last_duration: Option<Duration>,
+    /// Error of the last failed run, even if later runs succeeded.
+    pub last_error: Option<JobError>,
+}
+
+type JobFn = Box<dyn Fn() -> BoxFuture<'static, Result<(), Error>> + Send + Sync>;
+
+struct Job {
+    name: String,
+    schedule: Schedule,
+    overlap_policy: OverlapPolicy,
+    run: JobFn,
+    status: Mutex<JobState>,
+}
+
+#[derive(Default)]
+struct JobState {
+    running: usize,
+    runs: u64,
+    failures: u64,
+    skipped: u64,
+    cancelled: u64,
+    last_start: Option<SystemTime>,
+    last_duration: Option<Duration>,
+    last_error: Option<JobError>,
+}
+
+/// Collects the jobs to run, which start running with [Scheduler::start].
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Vec<Arc<Job>>,
+    names: HashSet<String>,
+}
+
+impl Scheduler {
+    /// Create a scheduler without any job.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a job, identified by its name in stats and statuses.
+    pub fn add_job<F, Fut>(
+        &mut self,
+        name: impl Into<String>,
+        schedule: Schedule,
+        overlap_policy: OverlapPolicy,
+        job: F,
+    ) -> Result<&mut Self, SchedulerError>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let name = name.into();
+        if !self.names.insert(name.clone()) {
+            return Err(SchedulerError::DuplicateJob(name));
+        }
+        self.jobs.push(Arc::new(Job {
+            name,
+            schedule,
+            overlap_policy,
+            run: Box::new(move || job().boxed()),
+            status: Mutex::new(JobState::default()),
+        }));
+        Ok(self)
+    }
+
+    /// Start running the jobs on the current tokio runtime. They run until
+    /// the returned handle is dropped.
+    pub fn start(self) -> SchedulerHandle {
+        let tasks = self
+            .jobs
+            .iter()
+            .map(|job| tokio::spawn(schedule_job(job.clone())))
+            .collect();
+        SchedulerHandle {
+            jobs: self.jobs,
+            tasks,
+        }
+    }
+}
+
+/// Handle to the running jobs of a [Scheduler]. Dropping it stops scheduling
+/// new runs, while runs in progress complete.
+pub struct SchedulerHandle {
+    jobs: Vec<Arc<Job>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl SchedulerHandle {
+    /// Return the status of every job, in the order they were added.
+    pub fn statuses(&self) -> Vec<JobStatus> {
+        self.jobs.iter().map(|job| job.status()).collect()
+    }
+
+    /// Return the status of the job with the given name.
+    pub fn status(&self, name: &str) -> Option<JobStatus> {
+        self.jobs
+            .iter()
+            .find(|job| job.name == name)
+            .map(|job| job.status())
+    }
+}
+
+impl Drop for SchedulerHandle {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+impl Job {
+    fn status(&self) -> JobStatus {
+        let state = self.status.lock().expect("lock poisoned");
+        JobStatus {
+            name: self.name.clone(),
+            running: state.running > 0,
+            runs: state.runs,
+            failures: state.failures,
+            skipped: state.skipped,
+            cancelled: state.cancelled,
+            last_start: state.last_start,
+            last_duration: state.last_duration,
+            last_error: state.last_error.clone(),
+        }
+    }
+
+    fn update(&self, update: impl FnOnce(&mut JobState)) {
+        update(&mut self.status.lock().expect("lock poisoned"));
+    }
+
+    /// Return when the next run is due, or `None` if the job never runs
+    /// again.
+    fn next_run(
+        &self,
+        next_tick: &mut Instant,
+        last_cron_time: &mut Option<DateTime<Utc>>,
+    ) -> Option<Instant> {
+        let tick = match &self.schedule.trigger {
+            Trigger::Every(period) => {
+                let tick = (*next_tick).max(Instant::now());
+                *next_tick = tick + *period;
+                tick
+            }
+            Trigger::Cron(cron) => {
+                // Never match the same time twice if the clock goes back.
+                let now = Utc::now();
+                let after = last_cron_time.map_or(now, |last| last.max(now));
+                let time = cron.next_after(after)?;
+                *last_cron_time = Some(time);
+                Instant::now() + (time - now).to_std().unwrap_or_default()
+            }
+        };
+        Some(tick + self.schedule.random_jitter())
+    }
+}
+
+async fn schedule_job(job: Arc<Job>) {
+    let mut next_tick = Instant::now();
+    let mut last_cron_time = None;
+    let mut previous: Option<JoinHandle<()>> = None;
+
+    while let Some(next_run) = job.next_run(&mut next_tick, &mut last_cron_time) {
+        tokio::time::sleep_until(next_run).await;
+
+        let previous_running = previous.as_ref().is_some_and(|run| !run.is_finished());
+        let run = match job.overlap_policy {
+            _ if !previous_running => tokio::spawn(run_job(job.clone())),
+            OverlapPolicy::Skip => {
+                job.update(|state| state.skipped += 1);
+                STATS::skipped.add_value(1, (job.name.clone(),));
+                continue;
+            }
+            OverlapPolicy::Queue => {
+                let previous = previous.take();
+                let job = job.clone();
+                tokio::spawn(async move {
+                    if let Some(previous) = previous {
+                        let _ = previous.await;
+                    }
+                    run_job(job).await
+                })
+            }
+            OverlapPolicy::CancelPrevious => {
+                if let Some(previous) = previous.take() {
+                    previous.abort();
+                }
+                job.update(|state| state.cancelled += 1);
+                STATS::cancelled.add_value(1, (job.name.clone(),));
+                tokio::spawn(run_job(job.clone()))
+            }
+        };
+        previous = Some(run);
+    }
+}
+
+async fn run_job(job: Arc<Job>) {
+    // Marks the job as not running anymore even if the run is cancelled.
+    struct RunningGuard<'a>(&'a Job);
+
+    impl Drop for RunningGuard<'_> {
+        fn drop(&mut self) {
+            self.0.update(|state| state.running -= 1);
+        }
+    }
+
+    let start_time = SystemTime::now();
+    job.update(|state| {
+        state.running += 1;
+        state.last_start = Some(start_time);
+    });
+    let _running = RunningGuard(&job);
+
+    let start = Instant::now();
+    let result = (job.run)().await;
+    let duration = start.elapsed();
+
+    STATS::runs.add_value(1, (job.name.clone(),));
+    STATS::duration_ms.add_value(duration.as_millis() as i64, (job.name.clone(),));
+    if result.is_err() {
+        STATS::failures.add_value(1, (job.name.clone(),));
+    }
+    job.update(|state| {
+        state.runs += 1;
+        state.last_duration = Some(duration);
+        if let Err(err) = result {
+            state.failures += 1;
+            state.last_error = Some(JobError {
+                time: start_time,
+                message: format!("{:#}", err),
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::Ordering;
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    fn counting_job(
+        runs: &Arc<AtomicU64>,
+        duration: Duration,
+    ) -> impl Fn() -> BoxFuture<'static, Result<(), Error>> + Send + Sync + 'static {
+        let runs = runs.clone();
+        move || {
+            let runs = runs.clone();
+            async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(duration).await;
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    async fn run_for(scheduler: Scheduler, duration: Duration) -> SchedulerHandle {
+        let handle = scheduler.start();
+        tokio::time::sleep(duration).await;
+        handle
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_periodic() {
+        let started = Arc::new(AtomicU64::new(0));
+        let mut scheduler = Scheduler::new();
+        scheduler
+            .add_job(
+                "job",
+                Schedule::every(Duration::from_secs(10)),
+                OverlapPolicy::Skip,
+                counting_job(&started, Duration::from_secs(1)),
+            )
+            .unwrap();
+
+        let handle = run_for(scheduler, Duration::from_secs(35)).await;
+        // Runs at 0, 10, 20 and 30 seconds.
+        assert_eq!(started.load(Ordering::SeqCst), 4);
+        let status = handle.status("job").unwrap();
+        assert_eq!(status.runs, 4);
+        assert!(!status.running);
+        assert_eq!(status.last_duration, Some(Duration::from_secs(1)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_overlap_policies() {
+        let skipped = Arc::new(AtomicU64::new(0));
+        let queued = Arc::new(AtomicU64::new(0));
+        let cancelled = Arc::new(AtomicU64::new(0));
+        let mut scheduler = Scheduler::new();
+        let schedule = Schedule::every(Duration::from_secs(10));
+        scheduler
+            .add_job(
+                "skip",
+                schedule.clone(),
+                OverlapPolicy::Skip,
+                counting_job(&skipped, Duration::from_secs(25)),
+            )
+            .unwrap()
+            .add_job(
+                "queue",
+                schedule.clone(),
+                OverlapPolicy::Queue,
+                counting_job(&queued, Duration::from_secs(15)),
+            )
+            .unwrap()
+            .add_job(
+                "cancel",
+                schedule,
+                OverlapPolicy::CancelPrevious,
+                counting_job(&cancelled, Duration::from_secs(3600)),
+            )
+            .unwrap();
+
+        let handle = run_for(scheduler, Duration::from_secs(35)).await;
+
+        // Started at 0 and 30 seconds, the runs due at 10 and 20 are skipped.
+        assert_eq!(skipped.load(Ordering::SeqCst), 2);
+        let status = handle.status("skip").unwrap();
+        assert_eq!((status.runs, status.skipped), (1, 2));
+
+        // Started at 0, 15 and 30 seconds, with one more queued.
+        assert_eq!(queued.load(Ordering::SeqCst), 3);
+        let status = handle.status("queue").unwrap();
+        assert_eq!((status.runs, status.skipped), (2, 0));
+
+        // Each run is cancelled by the next one.
+        assert_eq!(cancelled.load(Ordering::SeqCst), 4);
+        let status = handle.status("cancel").unwrap();
+        assert_eq!((status.runs, status.cancelled), (0, 3));
+        assert!(status.running);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failures() {
+        let mut scheduler = Scheduler::new();
+        scheduler
+            .add_job(
+                "job",
+                Schedule::every(Duration::from_secs(10)),
+                OverlapPolicy::Skip,
+                || async { Err(anyhow!("failed")) },
+            )
+            .unwrap();
+        assert!(matches!(
+            scheduler.add_job(
+                "job",
+                Schedule::every(Duration::from_secs(10)),
+                OverlapPolicy::Skip,
+                || async { Ok(()) },
+            ),
+            Err(SchedulerError::DuplicateJob(_))
+        ));
+
+        let handle = run_for(scheduler, Duration::from_secs(15)).await;
+        let status = handle.status("job").unwrap();
+        assert_eq!((status.runs, status.failures), (2, 2));
+        assert_eq!(status.last_error.unwrap().message, "failed");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_jitter() {
+        let started = Arc::new(AtomicU64::new(0));
+        let mut scheduler = Scheduler::new();
+        scheduler
+            .add_job(
+                "job",
+                Schedule::every(Duration::from_secs(10)).with_jitter(Duration::from_secs(5)),
+                OverlapPolicy::Skip,
+                counting_job(&started, Duration::ZERO),
+            )
+            .unwrap();
+
+        // Runs due at 0, 10 and 20 seconds happen within 5 seconds of it.
+        let _handle = run_for(scheduler, Duration::from_secs(26)).await;
+        assert_eq!(started.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,287 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Parsing of cron expressions and computation of the times they match.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Duration as ChronoDuration;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::Timelike;
+use chrono::Utc;
+use thiserror::Error;
+
+/// Number of days searched for a match before concluding that an expression
+/// never matches, e.g. `0 0 30 2 *`. Long enough to cover the eight years
+/// between two February 29ths around a century.
+const MAX_SEARCHED_DAYS: i64 = 366 * 9;
+
+const MONTH_NAMES: &[&str] = &[
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const DAY_NAMES: &[&str] = &["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// Error returned when parsing an invalid cron expression.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+#[error("Invalid cron expression `{expression}`: {reason}")]
+pub struct CronError {
+    expression: String,
+    reason: String,
+}
+
+/// A cron expression, matching times in UTC.
+///
+/// Expressions have the five standard fields `minute hour day-of-month month
+/// day-of-week`, each being `*` or a comma separated list of values, `a-b`
+/// ranges and `/step` increments of either. Months and days of the week can
+/// be given by their three letter names, and both 0 and 7 stand for Sunday.
+/// As in cron, when both the day of the month and the day of the week are
+/// restricted, times matching either of them match. The `@yearly`,
+/// `@monthly`, `@weekly`, `@daily` and `@hourly` shorthands are supported.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    days_of_month_restricted: bool,
+    days_of_week_restricted: bool,
+}
+
+impl CronSchedule {
+    /// Return the first time matching the expression strictly after `time`,
+    /// or `None` if the expression never matches.
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start =
+            time.naive_utc().with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+        let mut date = start.date();
+        for _ in 0..MAX_SEARCHED_DAYS {
+            if self.matches_date(date) {
+                let (hour, minute) = if date == start.date() {
+                    (start.hour(), start.minute())
+                } else {
+                    (0, 0)
+                };
+                if let Some(time) = self.first_time_of_day(date, hour, minute) {
+                    return Some(time.and_utc());
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if !contains(self.months, date.month()) {
+            return false;
+        }
+        let day_of_month = contains(self.days_of_month, date.day());
+        let day_of_week = contains(self.days_of_week, date.weekday().num_days_from_sunday());
+        match (self.days_of_month_restricted, self.days_of_week_restricted) {
+            (true, true) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        }
+    }
+
+    /// Return the first matching time of the day at or after the given hour
+    /// and minute.
+    fn first_time_of_day(&self, date: NaiveDate, hour: u32, minute: u32) -> Option<NaiveDateTime> {
+        (hour..24)
+            .filter(|hour| contains(self.hours, *hour))
+            .find_map(|h| {
+                let first_minute = if h == hour { minute } else { 0 };
+                (first_minute..60)
+                    .find(|minute| contains(self.minutes, *minute))
+                    .and_then(|minute| date.and_hms_opt(h, minute, 0))
+            })
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = CronError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let error = |reason: String| CronError {
+            expression: expression.to_owned(),
+            reason,
+        };
+
+        let expanded = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            expression => expression,
+        };
+        let fields: Vec<_> = expanded.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            return Err(error(format!("expected 5 fields, got {}", fields.len())));
+        };
+
+        let mut days_of_week_set = parse_field(days_of_week, 0, 7, DAY_NAMES, 0)
+            .map_err(|reason| error(format!("day of week: {}", reason)))?;
+        // Both 0 and 7 are Sunday.
+        if contains(days_of_week_set, 7) {
+            days_of_week_set |= 1;
+        }
+
+        Ok(Self {
+            expression: expression.to_owned(),
+            minutes: parse_field(minutes, 0, 59, &[], 0)
+                .map_err(|reason| error(format!("minute: {}", reason)))?,
+            hours: parse_field(hours, 0, 23, &[], 0)
+                .map_err(|reason| error(format!("hour: {}", reason)))?,
+            days_of_month: parse_field(days_of_month, 1, 31, &[], 0)
+                .map_err(|reason| error(format!("day of month: {}", reason)))?,
+            months: parse_field(months, 1, 12, MONTH_NAMES, 1)
+                .map_err(|reason| error(format!("month: {}", reason)))?,
+            days_of_week: days_of_week_set,
+            days_of_month_restricted: !days_of_month.starts_with('*'),
+            days_of_week_restricted: !days_of_week.starts_with('*'),
+        })
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.expression)
+    }
+}
+
+impl fmt::Debug for CronSchedule {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_tuple("CronSchedule")
+            .field(&self.expression)
+            .finish()
+    }
+}
+
+fn contains(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Parse a field into the set of values it matches, as a bitset. `names`
+/// are alternative names of the values from `first_name` onwards.
+fn parse_field(
+    field: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+    first_name: u32,
+) -> Result<u64, String> {
+    let value = |value: &str| -> Result<u32, String> {
+        let parsed = match names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(value))
+        {
+            Some(position) => position as u32 + first_name,
+            None => value
+                .parse()
+                .map_err(|_| format!("invalid value `{}`", value))?,
+        };
+        if parsed < min || parsed > max {
+            return Err(format!("value {} out of range {}-{}", parsed, min, max));
+        }
+        Ok(parsed)
+    };
+
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .map_err(|_| format!("invalid step `{}`", step))?;
+                if step == 0 {
+                    return Err("step must be positive".to_owned());
+                }
+                (range, Some(step))
+            }
+            None => (part, None),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (value(start)?, value(end)?)
+        } else if step.is_some() {
+            // `a/step` goes from a to the maximum.
+            (value(range)?, max)
+        } else {
+            let value = value(range)?;
+            (value, value)
+        };
+        if start > end {
+            return Err(format!("invalid range `{}`", range));
+        }
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn next(expression: &str, time: &str) -> Option<String> {
+        let schedule: CronSchedule = expression.parse().unwrap();
+        let time = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S")
+            .unwrap()
+            .and_utc();
+        schedule
+            .next_after(time)
+            .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+    }
+
+    #[test]
+    fn test_next_after() {
+        let start = "2024-01-31 10:30:15";
+        assert_eq!(next("* * * * *", start).unwrap(), "2024-01-31 10:31");
+        assert_eq!(next("*/15 * * * *", start).unwrap(), "2024-01-31 10:45");
+        assert_eq!(next("0 9-17 * * *", start).unwrap(), "2024-01-31 11:00");
+        assert_eq!(next("30 10 * * *", start).unwrap(), "2024-02-01 10:30");
+        assert_eq!(next("@monthly", start).unwrap(), "2024-02-01 00:00");
+        assert_eq!(next("0 0 29 feb *", start).unwrap(), "2024-02-29 00:00");
+        assert_eq!(next("0 0 * * SUN", start).unwrap(), "2024-02-04 00:00");
+        assert_eq!(next("0 0 * * 7", start).unwrap(), "2024-02-04 00:00");
+        assert_eq!(next("0 12 1,15 * *", start).unwrap(), "2024-02-01 12:00");
+        // Either the day of the month or the day of the week.
+        assert_eq!(next("0 0 15 * MON", start).unwrap(), "2024-02-05 00:00");
+        assert_eq!(next("0 0 30 2 *", start), None);
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        for expression in [
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "foo * * * *",
+        ] {
+            assert!(
+                expression.parse::<CronSchedule>().is_err(),
+                "{} should be invalid",
+                expression
+            );
+        }
+    }
+}
@@ -7,10 +7,15 @@
  * of this source tree.
  */
 
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use anyhow::Error;
-use thiserror::Error;
 
 /// SharedError is a simple, cloneable `anyhow::Error` wrapper.
 /// It holds the inner error in an `Arc<anyhow::Error>` to support Clone.
@@ -54,11 +59,15 @@ use thiserror::Error;
 ///     let _result = some_fallible_func_anyhow();
 /// }
 /// ```
-#[derive(Error, Debug, Clone)]
-#[error(transparent)]
+///
+/// An error shared by many futures tends to be logged by each of them. Log it
+/// with [SharedError::log_once] to only record the whole chain of causes the
+/// first time, and a short fingerprint of it afterwards.
+#[derive(Clone)]
 pub struct SharedError {
-    #[from]
     error: Arc<Error>,
+    // Whether the error was logged with `log_once`, shared by all clones.
+    logged: Arc<AtomicBool>,
 }
 
 impl SharedError {
@@ -69,7 +78,100 @@ impl SharedError {
 
     /// Creates a new arced error
     pub fn new_arcederror(error: Arc<anyhow::Error>) -> Self {
-        Self { error }
+        Self {
+            error,
+            logged: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Return a short fingerprint of the error and its chain of causes,
+    /// identifying it in logs.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        format!("{:#}", self.error).hash(&mut hasher);
+        format!("{:08x}", hasher.finish() as u32)
+    }
+
+    /// Return a value to log this error with, either with slog as a
+    /// [slog::Value] or with tracing as a `%` field. The first time any clone
+    /// of this error is logged this way, the value displays the whole chain
+    /// of causes followed by the fingerprint of the error. Afterwards it only
+    /// displays the fingerprint, to refer to the first log.
+    pub fn log_once(&self) -> LogOnce<'_> {
+        LogOnce {
+            error: self,
+            first: !self.logged.swap(true, Ordering::Relaxed),
+        }
+    }
+}
+
+impl From<Arc<Error>> for SharedError {
+    fn from(error: Arc<Error>) -> Self {
+        Self::new_arcederror(error)
+    }
+}
+
+impl fmt::Display for SharedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&*self.error, f)
+    }
+}
+
+impl fmt::Debug for SharedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedError")
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl std::error::Error for SharedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Displays a [SharedError] logged with [SharedError::log_once].
+pub struct LogOnce<'a> {
+    error: &'a SharedError,
+    first: bool,
+}
+
+impl fmt::Display for LogOnce<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.first {
+            write!(
+                f,
+                "{:#} [error {}]",
+                self.error.error,
+                self.error.fingerprint()
+            )
+        } else {
+            write!(f, "[error {} logged before]", self.error.fingerprint())
+        }
+    }
+}
+
+impl slog::Value for LogOnce<'_> {
+    fn serialize(
+        &self,
+        _record: &slog::Record<'_>,
+        key: slog::Key,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{}", self))
+    }
+}
+
+/// Logs the error with its whole chain of causes.
+impl slog::Value for SharedError {
+    fn serialize(
+        &self,
+        _record: &slog::Record<'_>,
+        key: slog::Key,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{:#}", self.error))
     }
 }
 
@@ -81,9 +183,7 @@ pub trait IntoSharedError<Ret> {
 
 impl<E: Into<Error>> IntoSharedError<SharedError> for E {
     fn shared_error(self) -> SharedError {
-        SharedError {
-            error: Arc::new(self.into()),
-        }
+        SharedError::new_arcederror(Arc::new(self.into()))
     }
 }
 
@@ -109,6 +209,8 @@ impl slog::KV for SharedError {
 mod tests {
     use std::error::Error as _;
 
+    use thiserror::Error;
+
     use super::*;
 
     #[derive(Debug, Error)]
@@ -190,4 +292,60 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_log_once() {
+        let shared_error = ::anyhow::anyhow!("inner").context("outer").shared_error();
+        let cloned_error = shared_error.clone();
+        let fingerprint = shared_error.fingerprint();
+        assert_eq!(fingerprint.len(), 8);
+        assert_eq!(cloned_error.fingerprint(), fingerprint);
+
+        assert_eq!(
+            cloned_error.log_once().to_string(),
+            format!("outer: inner [error {}]", fingerprint)
+        );
+        assert_eq!(
+            shared_error.log_once().to_string(),
+            format!("[error {} logged before]", fingerprint)
+        );
+
+        // Other errors with the same chain are logged in full once too.
+        let other_error = ::anyhow::anyhow!("inner").context("outer").shared_error();
+        assert_eq!(
+            other_error.log_once().to_string(),
+            format!("outer: inner [error {}]", fingerprint)
+        );
+    }
+
+    #[test]
+    fn test_slog_value() {
+        struct Capture(String);
+
+        impl slog::Serializer for Capture {
+            fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments<'_>) -> slog::Result {
+                self.0 = format!("{}={}", key, val);
+                Ok(())
+            }
+        }
+
+        fn serialized(value: &impl slog::Value) -> String {
+            let mut capture = Capture(String::new());
+            value
+                .serialize(
+                    &slog::record!(slog::Level::Error, "", &format_args!(""), slog::b!()),
+                    "error",
+                    &mut capture,
+                )
+                .unwrap();
+            capture.0
+        }
+
+        let shared_error = ::anyhow::anyhow!("inner").context("outer").shared_error();
+        assert_eq!(serialized(&shared_error), "error=outer: inner");
+        assert_eq!(
+            serialized(&shared_error.log_once()),
+            format!("error=outer: inner [error {}]", shared_error.fingerprint())
+        );
+    }
 }
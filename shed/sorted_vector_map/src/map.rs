@@ -181,6 +181,9 @@ where
     }
 
     /// Moves all elements from other into Self, leaving other empty.
+    ///
+    /// As with `BTreeMap::append`, the keys of `other` already in `self`
+    /// take the values from `other`.
     pub fn append(&mut self, other: &mut SortedVectorMap<K, V>) {
         if other.is_empty() {
             return;
@@ -191,6 +194,13 @@ where
             return;
         }
 
+        // All other items are after the end, so we can append them to the
+        // vector directly.
+        if self.0.last().map(|(k, _)| k) < other.0.first().map(|(k, _)| k) {
+            self.0.append(&mut other.0);
+            return;
+        }
+
         let self_iter = mem::take(self).into_iter();
         let other_iter = mem::take(other).into_iter();
         self.0 = MergeIter::new(self_iter, other_iter).collect();
@@ -375,10 +385,19 @@ where
         self.0.last().map(|(k, v)| (k, v))
     }
 
-    /// Removes and returns the last key-value pair in the map.
+    /// Removes and returns the first key-value pair in the map.
     ///
-    /// There is no `pop_first` equivalent as removing the first item from a
-    /// vector is not efficient.
+    /// This shifts all the other items of the vector, so prefer `pop_last`
+    /// or `split_off` to remove many items.
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        if self.0.is_empty() {
+            None
+        } else {
+            Some(self.0.remove(0))
+        }
+    }
+
+    /// Removes and returns the last key-value pair in the map.
     pub fn pop_last(&mut self) -> Option<(K, V)> {
         self.0.pop()
     }
@@ -1161,6 +1180,9 @@ mod tests {
         assert_eq!(svm.first_key_value(), Some((&5, &100)));
         assert_eq!(svm.last_key_value(), Some((&20, &400)));
         assert_eq!(svm.first_entry().map(|e| *e.key()), Some(5));
+        assert_eq!(svm.pop_first(), Some((5, 100)));
+        assert_eq!(svm.first_key_value(), Some((&10, &200)));
+        svm.insert(5, 100);
         assert_eq!(svm.pop_last(), Some((20, 400)));
         assert_eq!(svm.last_key_value(), Some((&15, &300)));
         assert_eq!(svm.last_entry().map(|e| *e.key()), Some(15));
@@ -1170,6 +1192,7 @@ mod tests {
         assert_eq!(svm.last_key_value(), Some((&5, &100)));
         assert_eq!(svm.pop_last(), Some((5, 100)));
         assert_eq!(svm.pop_last(), None);
+        assert_eq!(svm.pop_first(), None);
         assert_eq!(svm.first_key_value(), None);
         assert_eq!(svm.last_key_value(), None);
         assert_eq!(svm.first_entry().map(|e| *e.key()), None);
@@ -1251,6 +1274,36 @@ mod tests {
             svm2.keys().cloned().collect::<Vec<_>>(),
             vec![1, 2, 3, 4, 5, 6, 7]
         );
+
+        // Values from the other map overwrite existing values.
+        let mut svm3 = sorted_vector_map! {
+            0 => "zero",
+            3 => "three again",
+            8 => "eight",
+        };
+        svm2.append(&mut svm3);
+        assert!(svm3.is_empty());
+        assert_eq!(
+            svm2,
+            sorted_vector_map! {
+                0 => "zero",
+                1 => "one",
+                2 => "two",
+                3 => "three again",
+                4 => "four",
+                5 => "five",
+                6 => "six",
+                7 => "seven",
+                8 => "eight",
+            }
+        );
+
+        // Splitting off and appending back restores the map.
+        let mut svm4 = svm2.split_off(&5);
+        assert_eq!(svm4.first_key_value(), Some((&5, &"five")));
+        svm2.append(&mut svm4);
+        assert_eq!(svm2.len(), 9);
+        assert_eq!(svm2.split_off(&9).len(), 0);
     }
 
     #[test]
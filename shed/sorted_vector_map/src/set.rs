@@ -273,6 +273,13 @@ where
             return;
         }
 
+        // All other items are after the end, so we can append them to the
+        // vector directly.
+        if self.0.last() < other.0.first() {
+            self.0.append(&mut other.0);
+            return;
+        }
+
         let self_iter = mem::take(self).into_iter();
         let other_iter = mem::take(other).into_iter();
         let iter = MergeIter::new(self_iter, other_iter);
@@ -313,10 +320,19 @@ where
         self.0.last()
     }
 
-    /// Removes and returns the last value in the set, if any.
+    /// Removes and returns the first value in the set, if any.
     ///
-    /// There is no `pop_first` equivalent as removing the first item from a
-    /// vector is not efficient.
+    /// This shifts all the other items of the vector, so prefer `pop_last`
+    /// or `split_off` to remove many items.
+    pub fn pop_first(&mut self) -> Option<T> {
+        if self.0.is_empty() {
+            None
+        } else {
+            Some(self.0.remove(0))
+        }
+    }
+
+    /// Removes and returns the last value in the set, if any.
     pub fn pop_last(&mut self) -> Option<T> {
         self.0.pop()
     }
@@ -852,6 +868,9 @@ mod tests {
         let mut svs = sorted_vector_set! { 5, 10, 15, 20 };
         assert_eq!(svs.first(), Some(&5));
         assert_eq!(svs.last(), Some(&20));
+        assert_eq!(svs.pop_first(), Some(5));
+        assert_eq!(svs.first(), Some(&10));
+        svs.insert(5);
         assert_eq!(svs.pop_last(), Some(20));
         assert_eq!(svs.last(), Some(&15));
         assert_eq!(svs.pop_last(), Some(15));
@@ -860,6 +879,7 @@ mod tests {
         assert_eq!(svs.last(), Some(&5));
         assert_eq!(svs.pop_last(), Some(5));
         assert_eq!(svs.pop_last(), None);
+        assert_eq!(svs.pop_first(), None);
         assert_eq!(svs.first(), None);
         assert_eq!(svs.last(), None);
     }
@@ -890,6 +910,9 @@ mod tests {
             svs2.iter().cloned().collect::<Vec<_>>(),
             vec![1, 3, 4, 5, 6, 7, 8, 9, 11]
         );
+        svs2.append(&mut sorted_vector_set! { 12, 13 });
+        assert_eq!(svs2.last(), Some(&13));
+        assert_eq!(svs2.len(), 11);
     }
 
     #[test]
@@ -0,0 +1,155 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module with the result of writes executed as several statements, e.g.
+//! inserts split into chunks, reporting the outcome of each statement.
+
+use std::future::Future;
+
+use anyhow::Error;
+
+use crate::WriteResult;
+
+/// Value returned from writes executed as several statements, holding the
+/// result of each statement in the order they were executed.
+///
+/// When the statements run outside of a transaction, the statements that
+/// succeeded stay applied even if others failed, so callers can retry only
+/// those that failed, whose index is returned by [Self::errors].
+#[derive(Debug, Default)]
+pub struct BatchWriteResult {
+    results: Vec<Result<WriteResult, Error>>,
+}
+
+impl BatchWriteResult {
+    /// Create a result from the result of each statement, in the order they
+    /// were executed.
+    pub fn new(results: Vec<Result<WriteResult, Error>>) -> Self {
+        BatchWriteResult { results }
+    }
+
+    /// Return the result of each statement.
+    pub fn results(&self) -> &[Result<WriteResult, Error>] {
+        &self.results
+    }
+
+    /// Return the result of each statement, consuming the result.
+    pub fn into_results(self) -> Vec<Result<WriteResult, Error>> {
+        self.results
+    }
+
+    /// Return whether every statement succeeded.
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(Result::is_ok)
+    }
+
+    /// Return the statements that failed, with their index.
+    pub fn errors(&self) -> impl Iterator<Item = (usize, &Error)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(index, result)| result.as_ref().err().map(|err| (index, err)))
+    }
+
+    /// Return number of rows affected by the statements that succeeded.
+    pub fn affected_rows(&self) -> u64 {
+        self.results
+            .iter()
+            .flatten()
+            .map(WriteResult::affected_rows)
+            .sum()
+    }
+
+    /// Return the id of the last row inserted by the statements that
+    /// succeeded, if any.
+    pub fn last_insert_id(&self) -> Option<u64> {
+        self.results
+            .iter()
+            .flatten()
+            .filter_map(WriteResult::last_insert_id)
+            .next_back()
+    }
+
+    /// Return a single result for all the statements, failing with the first
+    /// error if any statement failed.
+    pub fn into_write_result(self) -> Result<WriteResult, Error> {
+        let mut last_insert_id = None;
+        let mut affected_rows = 0;
+        for result in self.results {
+            let result = result?;
+            last_insert_id = result.last_insert_id().or(last_insert_id);
+            affected_rows += result.affected_rows();
+        }
+        Ok(WriteResult::new(last_insert_id, affected_rows))
+    }
+}
+
+impl FromIterator<Result<WriteResult, Error>> for BatchWriteResult {
+    fn from_iter<I: IntoIterator<Item = Result<WriteResult, Error>>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+/// Run `write` on each of the batches one after the other, outside of a
+/// transaction, running the remaining batches even if some fail.
+pub async fn write_batches<B, F, Fut>(
+    batches: impl IntoIterator<Item = B>,
+    mut write: F,
+) -> BatchWriteResult
+where
+    F: FnMut(B) -> Fut,
+    Fut: Future<Output = Result<WriteResult, Error>>,
+{
+    let mut results = Vec::new();
+    for batch in batches {
+        results.push(write(batch).await);
+    }
+    BatchWriteResult::new(results)
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_batches() {
+        let result = write_batches(vec![2, 0, 3], |rows| async move {
+            if rows == 0 {
+                Err(anyhow!("empty batch"))
+            } else {
+                Ok(WriteResult::new(Some(rows * 10), rows))
+            }
+        })
+        .await;
+
+        assert!(!result.is_success());
+        assert_eq!(result.results().len(), 3);
+        let errors: Vec<_> = result
+            .errors()
+            .map(|(index, err)| (index, err.to_string()))
+            .collect();
+        assert_eq!(errors, vec![(1, "empty batch".to_owned())]);
+        assert_eq!(result.affected_rows(), 5);
+        assert_eq!(result.last_insert_id(), Some(30));
+        assert!(result.into_write_result().is_err());
+
+        let result: BatchWriteResult = vec![
+            Ok(WriteResult::new(Some(1), 1)),
+            Ok(WriteResult::new(None, 2)),
+        ]
+        .into_iter()
+        .collect();
+        assert!(result.is_success());
+        let result = result.into_write_result().unwrap();
+        assert_eq!(result.affected_rows(), 3);
+        assert_eq!(result.last_insert_id(), Some(1));
+    }
+}
@@ -0,0 +1,297 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Caching the results of read queries made with `queries!`, for hot tables
+//! that are read much more often than they are written, e.g. config-style
+//! tables. See [CachedConnection].
+
+use std::any::Any;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Error;
+use lru::LruCache;
+use stats::prelude::*;
+
+use crate::Connection;
+
+define_stats! {
+    prefix = "sql.query_cache";
+    hits: timeseries(Rate, Sum),
+    misses: timeseries(Rate, Sum),
+    invalidations: timeseries(Rate, Sum),
+}
+
+/// Number of results kept by the default store of [CachedConnection::new].
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// Key of a cached query result.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// Path of the module the query is declared in with `queries!`. The
+    /// write queries declared in the same module invalidate the result.
+    pub scope: &'static str,
+    /// Name of the query.
+    pub query: &'static str,
+    /// The query, with the values of its parameters escaped and interpolated
+    /// as for MySQL whatever the backend.
+    pub sql: String,
+}
+
+/// A cached query result, downcast to the rows of the query when read.
+pub type CachedValue = Arc<dyn Any + Send + Sync>;
+
+/// Store holding the results cached by a [CachedConnection].
+pub trait QueryCacheStore: Send + Sync {
+    /// Return the result cached for the key, unless it has expired.
+    fn get(&self, key: &CacheKey) -> Option<CachedValue>;
+
+    /// Cache the result for the key until `expires_at`.
+    fn insert(&self, key: CacheKey, value: CachedValue, expires_at: Instant);
+
+    /// Remove the results of the queries declared in the module `scope`.
+    fn invalidate_scope(&self, scope: &str);
+
+    /// Remove all the results.
+    fn clear(&self);
+}
+
+/// In-memory store keeping the most recently used results, up to a capacity.
+pub struct LruQueryCacheStore {
+    entries: Mutex<LruCache<CacheKey, (CachedValue, Instant)>>,
+}
+
+impl LruQueryCacheStore {
+    /// Create a store keeping at most `capacity` results.
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+}
+
+impl QueryCacheStore for LruQueryCacheStore {
+    fn get(&self, key: &CacheKey) -> Option<CachedValue> {
+        let mut entries = self.entries.lock().expect("poisoned lock");
+        match entries.get(key) {
+            Some((value, expires_at)) if *expires_at > Instant::now() => Some(value.clone()),
+            Some(_) => {
+                entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, key: CacheKey, value: CachedValue, expires_at: Instant) {
+        let mut entries = self.entries.lock().expect("poisoned lock");
+        entries.put(key, (value, expires_at));
+    }
+
+    fn invalidate_scope(&self, scope: &str) {
+        let mut entries = self.entries.lock().expect("poisoned lock");
+        let keys: Vec<_> = entries
+            .iter()
+            .filter(|(key, _)| key.scope == scope)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            entries.pop(&key);
+        }
+    }
+
+    fn clear(&self) {
+        self.entries.lock().expect("poisoned lock").clear();
+    }
+}
+
+/// Wrapper around a [Connection] caching the results of the read queries
+/// made with the `cached_query` function that `queries!` generates, for a
+/// time to live.
+///
+/// The results are keyed by the query and its parameters. Write queries made
+/// with their `cached_query` function invalidate the results of the queries
+/// declared in the same module, and other writes, e.g. made in transactions
+/// or by other processes, are only seen once the results expire or are
+/// invalidated with [Self::invalidate].
+///
+/// Clones share the cache.
+#[derive(Clone)]
+pub struct CachedConnection {
+    connection: Connection,
+    ttl: Duration,
+    store: Arc<dyn QueryCacheStore>,
+    /// Incremented by invalidations, so that the results of reads that ran
+    /// concurrently with an invalidation are not cached.
+    generation: Arc<AtomicU64>,
+}
+
+impl CachedConnection {
+    /// Cache the results of the queries made on `connection` for `ttl`, in
+    /// memory, keeping at most the [DEFAULT_CAPACITY] most recently used.
+    pub fn new(connection: Connection, ttl: Duration) -> Self {
+        let capacity = NonZeroUsize::new(DEFAULT_CAPACITY).expect("capacity should not be zero");
+        Self::with_store(connection, ttl, Arc::new(LruQueryCacheStore::new(capacity)))
+    }
+
+    /// Cache the results of the queries made on `connection` for `ttl`, in
+    /// the provided store.
+    pub fn with_store(
+        connection: Connection,
+        ttl: Duration,
+        store: Arc<dyn QueryCacheStore>,
+    ) -> Self {
+        Self {
+            connection,
+            ttl,
+            store,
+            generation: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Return the wrapped connection, on which queries are not cached.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Remove the results of the queries declared with `queries!` in the
+    /// module at `scope`, as given by `module_path!()`.
+    pub fn invalidate(&self, scope: &str) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.store.invalidate_scope(scope);
+        STATS::invalidations.add_value(1);
+    }
+
+    /// Remove all the cached results.
+    pub fn invalidate_all(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.store.clear();
+        STATS::invalidations.add_value(1);
+    }
+
+    /// Return the result cached for the query at `query_path`, as given by
+    /// `module_path!()` in the module generated for the query, or run the
+    /// query and cache its result.
+    #[doc(hidden)]
+    pub async fn read_cached<T, Fut>(
+        &self,
+        query_path: &'static str,
+        sql: String,
+        query: Fut,
+    ) -> Result<Arc<T>, Error>
+    where
+        T: Send + Sync + 'static,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let (scope, name) = split_query_path(query_path);
+        let key = CacheKey {
+            scope,
+            query: name,
+            sql,
+        };
+        if let Some(value) = self.store.get(&key) {
+            if let Ok(value) = value.downcast::<T>() {
+                STATS::hits.add_value(1);
+                return Ok(value);
+            }
+        }
+        STATS::misses.add_value(1);
+
+        let generation = self.generation.load(Ordering::Acquire);
+        let value = Arc::new(query.await?);
+        if self.generation.load(Ordering::Acquire) == generation {
+            self.store
+                .insert(key, value.clone(), Instant::now() + self.ttl);
+        }
+        Ok(value)
+    }
+
+    /// Run the write query at `query_path`, as given by `module_path!()` in
+    /// the module generated for the query, then invalidate the results of
+    /// the queries declared in the same module, whether the write succeeded
+    /// or not.
+    #[doc(hidden)]
+    pub async fn write_invalidating<T, Fut>(
+        &self,
+        query_path: &'static str,
+        query: Fut,
+    ) -> Result<T, Error>
+    where
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let result = query.await;
+        self.invalidate(split_query_path(query_path).0);
+        result
+    }
+}
+
+/// Split the path of the module generated for a query into the path of the
+/// module the query is declared in and the name of the query.
+fn split_query_path(query_path: &'static str) -> (&'static str, &'static str) {
+    query_path.rsplit_once("::").unwrap_or(("", query_path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(scope: &'static str, sql: &str) -> CacheKey {
+        CacheKey {
+            scope,
+            query: "Query",
+            sql: sql.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_lru_store() {
+        let store = LruQueryCacheStore::new(NonZeroUsize::new(2).unwrap());
+        let later = Instant::now() + Duration::from_secs(60);
+        let value = |v: u64| Arc::new(v) as CachedValue;
+        let get = |key: &CacheKey| {
+            store
+                .get(key)
+                .map(|value| *value.downcast::<u64>().unwrap())
+        };
+
+        store.insert(key("a", "1"), value(1), later);
+        store.insert(key("b", "2"), value(2), later);
+        assert_eq!(get(&key("a", "1")), Some(1));
+        // "b" is the least recently used.
+        store.insert(key("a", "3"), value(3), later);
+        assert_eq!(get(&key("b", "2")), None);
+        assert_eq!(get(&key("a", "3")), Some(3));
+
+        store.insert(key("b", "2"), value(2), Instant::now());
+        assert_eq!(get(&key("b", "2")), None);
+
+        store.insert(key("b", "2"), value(2), later);
+        store.invalidate_scope("a");
+        assert_eq!(get(&key("a", "1")), None);
+        assert_eq!(get(&key("a", "3")), None);
+        assert_eq!(get(&key("b", "2")), Some(2));
+        store.clear();
+        assert_eq!(get(&key("b", "2")), None);
+    }
+
+    #[test]
+    fn test_split_query_path() {
+        assert_eq!(
+            split_query_path("krate::module::Query"),
+            ("krate::module", "Query")
+        );
+        assert_eq!(split_query_path("Query"), ("", "Query"));
+    }
+}
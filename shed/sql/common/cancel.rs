@@ -0,0 +1,189 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module with the token used to cancel queries in flight, and the error
+//! returned by the queries that were cancelled.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use anyhow::Error;
+use thiserror::Error;
+use tokio::sync::Notify;
+
+/// Error returned by queries that were cancelled with their
+/// [CancellationToken].
+/// It can be told apart from other errors with `error.is::<QueryCancelled>()`.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+#[error("Query was cancelled")]
+pub struct QueryCancelled;
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+    // Callbacks to run on cancellation keyed by id, and the next id.
+    callbacks: Mutex<(HashMap<u64, Callback>, u64)>,
+}
+
+/// Handle to cancel the queries it is given to. Cancelling it stops the
+/// queries that are running and fails them with [QueryCancelled], as well as
+/// the queries started afterwards.
+///
+/// Clones of a token share its state, so that any of them can be used to
+/// cancel the queries given any other.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+impl CancellationToken {
+    /// Create a token that isn't cancelled yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the queries given this token. Cancelling a token more than
+    /// once has no effect.
+    pub fn cancel(&self) {
+        let callbacks = {
+            let mut callbacks = self.inner.callbacks.lock().expect("lock poisoned");
+            if self.inner.cancelled.swap(true, Ordering::SeqCst) {
+                return;
+            }
+            std::mem::take(&mut callbacks.0)
+        };
+        for (_, callback) in callbacks {
+            callback();
+        }
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Whether the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wait for the token to be cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            // Created before checking the flag so that a cancellation in
+            // between isn't missed.
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Run `callback` when the token is cancelled, from the thread cancelling
+    /// it, unless the returned guard has been dropped by then. It is run
+    /// right away if the token is already cancelled.
+    ///
+    /// This lets queries that block their thread be cancelled, e.g. by
+    /// interrupting the sqlite connection they run on.
+    pub fn on_cancel(&self, callback: impl FnOnce() + Send + 'static) -> OnCancelGuard {
+        let mut callbacks = self.inner.callbacks.lock().expect("lock poisoned");
+        if self.is_cancelled() {
+            drop(callbacks);
+            callback();
+            return OnCancelGuard {
+                token: self.clone(),
+                id: None,
+            };
+        }
+        let id = callbacks.1;
+        callbacks.1 += 1;
+        callbacks.0.insert(id, Box::new(callback));
+        OnCancelGuard {
+            token: self.clone(),
+            id: Some(id),
+        }
+    }
+}
+
+/// Guard returned by [CancellationToken::on_cancel], unregistering the
+/// callback when dropped.
+pub struct OnCancelGuard {
+    token: CancellationToken,
+    id: Option<u64>,
+}
+
+impl Drop for OnCancelGuard {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let mut callbacks = self.token.inner.callbacks.lock().expect("lock poisoned");
+            callbacks.0.remove(&id);
+        }
+    }
+}
+
+/// Run the query, failing with [QueryCancelled] if a cancellation token is
+/// given and it is cancelled before the query completes. The query is dropped
+/// when it is cancelled.
+pub async fn with_cancellation<T>(
+    cancellation: Option<&CancellationToken>,
+    query: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    match cancellation {
+        None => query.await,
+        Some(cancellation) => tokio::select! {
+            biased;
+            _ = cancellation.cancelled() => Err(QueryCancelled.into()),
+            result = query => result,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancellation() {
+        let token = CancellationToken::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let on_cancel = |calls: &Arc<AtomicUsize>| {
+            let calls = calls.clone();
+            move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+            }
+        };
+
+        let _guard = token.on_cancel(on_cancel(&calls));
+        drop(token.on_cancel(on_cancel(&calls)));
+        let query = with_cancellation(Some(&token), futures::future::pending::<Result<(), _>>());
+        let cancel = async {
+            tokio::task::yield_now().await;
+            token.clone().cancel();
+        };
+        let (result, ()) = tokio::join!(query, cancel);
+        assert!(result.unwrap_err().is::<QueryCancelled>());
+        assert!(token.is_cancelled());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Cancelling again has no effect, callbacks registered afterwards
+        // run right away.
+        token.cancel();
+        let _guard = token.on_cancel(on_cancel(&calls));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        let result = with_cancellation(Some(&token), async { Ok(()) }).await;
+        assert!(result.unwrap_err().is::<QueryCancelled>());
+        assert!(with_cancellation(None, async { Ok(()) }).await.is_ok());
+    }
+}
@@ -0,0 +1,250 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! The version of the server behind a connection and the features of SQL it
+//! supports, probed once when the connection is created and cached on it, see
+//! [Connection::with_probed_capabilities], so that callers and generated code
+//! can choose the SQL to run, e.g. the schema variant of the connection, see
+//! [OssConnection::with_schema_variant](crate::mysql::OssConnection::with_schema_variant).
+
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::format_err;
+use anyhow::Error;
+
+use crate::sqlite::SqliteQueryType;
+use crate::Connection;
+use crate::SqlConnections;
+
+/// The kind of server behind a connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ServerKind {
+    /// SQLite
+    Sqlite,
+    /// MySQL
+    Mysql,
+    /// MariaDB, which is mostly compatible with MySQL
+    MariaDb,
+}
+
+/// The version of a server, e.g. 8.0.36.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ServerVersion {
+    /// The major version
+    pub major: u32,
+    /// The minor version
+    pub minor: u32,
+    /// The patch version
+    pub patch: u32,
+}
+
+impl ServerVersion {
+    /// Create a version.
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse the version at the start of a version string reported by a
+    /// server, e.g. `8.0.36-28-log`. Missing components are zero.
+    pub fn parse(version: &str) -> Result<Self, Error> {
+        let invalid = || format_err!("Invalid server version {:?}", version);
+        let components = version
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(str::parse::<u32>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        match components[..] {
+            [major] => Ok(Self::new(major, 0, 0)),
+            [major, minor] => Ok(Self::new(major, minor, 0)),
+            [major, minor, patch, ..] => Ok(Self::new(major, minor, patch)),
+            [] => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The version of the server behind a connection and the features of SQL it
+/// supports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    /// The kind of server
+    pub kind: ServerKind,
+    /// The version of the server
+    pub version: ServerVersion,
+    /// Whether common table expressions, `WITH ... SELECT`, are supported.
+    pub ctes: bool,
+    /// Whether `RETURNING` clauses of writes are supported.
+    pub returning: bool,
+    /// Whether JSON values are supported, with a `JSON` type on MySQL and
+    /// MariaDB, and with the JSON functions on SQLite.
+    pub json: bool,
+}
+
+impl ServerCapabilities {
+    /// The capabilities of the given version of a server, as documented by
+    /// each kind of server.
+    pub fn new(kind: ServerKind, version: ServerVersion) -> Self {
+        let at_least = |major, minor, patch| version >= ServerVersion::new(major, minor, patch);
+        let (ctes, returning, json) = match kind {
+            ServerKind::Sqlite => (at_least(3, 8, 3), at_least(3, 35, 0), at_least(3, 38, 0)),
+            ServerKind::Mysql => (at_least(8, 0, 1), false, at_least(5, 7, 8)),
+            ServerKind::MariaDb => (at_least(10, 2, 1), at_least(10, 5, 0), at_least(10, 2, 7)),
+        };
+        Self {
+            kind,
+            version,
+            ctes,
+            returning,
+            json,
+        }
+    }
+
+    /// The capabilities of a MySQL or MariaDB server reporting the given
+    /// version string, e.g. `10.11.6-MariaDB-log`.
+    pub fn from_mysql_version(version: &str) -> Result<Self, Error> {
+        let kind = if version.contains("MariaDB") {
+            ServerKind::MariaDb
+        } else {
+            ServerKind::Mysql
+        };
+        Ok(Self::new(kind, ServerVersion::parse(version)?))
+    }
+}
+
+impl Connection {
+    /// Probe the version of the server behind this connection and the
+    /// features of SQL it supports, with a query.
+    pub async fn probe_capabilities(&self) -> Result<ServerCapabilities, Error> {
+        match self {
+            Connection::Sqlite(con) => {
+                let version = con
+                    .run_query(SqliteQueryType::Read, None, |con| {
+                        Ok(con.query_row("SELECT sqlite_version()", [], |row| {
+                            row.get::<_, String>(0)
+                        })?)
+                    })
+                    .await?;
+                Ok(ServerCapabilities::new(
+                    ServerKind::Sqlite,
+                    ServerVersion::parse(&version)?,
+                ))
+            }
+            Connection::Mysql(conn) => {
+                let version: Vec<(String,)> =
+                    conn.read_query("SELECT VERSION()".to_owned()).await?;
+                mysql_capabilities(version)
+            }
+            Connection::OssMysql(conn) => {
+                let mut con = conn.get_conn().await?;
+                let version: Vec<(String,)> = conn
+                    .read_query(&mut con, "SELECT VERSION()")
+                    .await?
+                    .collect_and_drop()
+                    .await?;
+                mysql_capabilities(version)
+            }
+        }
+    }
+
+    /// Probe the capabilities of the server behind this connection, see
+    /// [Self::probe_capabilities], and cache them on it, e.g. right after
+    /// creating it. The Meta internal client doesn't cache them.
+    pub async fn with_probed_capabilities(self) -> Result<Self, Error> {
+        let capabilities = Some(Arc::new(self.probe_capabilities().await?));
+        Ok(match self {
+            Connection::Sqlite(con) => Connection::Sqlite(con.with_capabilities(capabilities)),
+            Connection::Mysql(conn) => Connection::Mysql(conn),
+            Connection::OssMysql(conn) => {
+                Connection::OssMysql(conn.with_capabilities(capabilities))
+            }
+        })
+    }
+
+    /// The capabilities of the server behind this connection, if they were
+    /// probed with [Self::with_probed_capabilities].
+    pub fn capabilities(&self) -> Option<&ServerCapabilities> {
+        match self {
+            Connection::Sqlite(con) => con.capabilities(),
+            Connection::Mysql(..) => None,
+            Connection::OssMysql(conn) => conn.capabilities(),
+        }
+    }
+}
+
+impl SqlConnections {
+    /// Probe the capabilities of the servers behind the connection of each
+    /// role and cache them on it, see [Connection::with_probed_capabilities].
+    pub async fn with_probed_capabilities(self) -> Result<Self, Error> {
+        Ok(Self {
+            write_connection: self.write_connection.with_probed_capabilities().await?,
+            read_connection: self.read_connection.with_probed_capabilities().await?,
+            read_master_connection: self
+                .read_master_connection
+                .with_probed_capabilities()
+                .await?,
+        })
+    }
+}
+
+fn mysql_capabilities(version: Vec<(String,)>) -> Result<ServerCapabilities, Error> {
+    let (version,) = version
+        .into_iter()
+        .next()
+        .ok_or_else(|| format_err!("The server didn't report its version"))?;
+    ServerCapabilities::from_mysql_version(&version)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            ServerVersion::parse("8.0.36-28-log").unwrap(),
+            ServerVersion::new(8, 0, 36)
+        );
+        assert_eq!(
+            ServerVersion::parse("3.45").unwrap(),
+            ServerVersion::new(3, 45, 0)
+        );
+        assert!(ServerVersion::parse("MariaDB").is_err());
+        assert!(ServerVersion::parse("").is_err());
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let mysql = ServerCapabilities::from_mysql_version("8.0.36").unwrap();
+        assert_eq!(mysql.kind, ServerKind::Mysql);
+        assert!(mysql.ctes && mysql.json && !mysql.returning);
+
+        let mysql = ServerCapabilities::from_mysql_version("5.7.44-log").unwrap();
+        assert!(!mysql.ctes && mysql.json && !mysql.returning);
+
+        let mariadb = ServerCapabilities::from_mysql_version("10.11.6-MariaDB-log").unwrap();
+        assert_eq!(mariadb.kind, ServerKind::MariaDb);
+        assert!(mariadb.ctes && mariadb.json && mariadb.returning);
+
+        let sqlite = ServerCapabilities::new(ServerKind::Sqlite, ServerVersion::new(3, 36, 0));
+        assert!(sqlite.ctes && !sqlite.json && sqlite.returning);
+    }
+}
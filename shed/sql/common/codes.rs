@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Stable codes of the errors returned by queries, see [error_codes]. Register
+//! [ERROR_DOMAIN] at startup to have them recognized by
+//! [error_codes::error_code].
+
+use std::error::Error as StdError;
+
+use error_codes::ErrorCategory;
+use error_codes::ErrorCode;
+use error_codes::ErrorDomain;
+
+/// The query conflicted with another transaction, see [crate::retry].
+pub const SQL_CONFLICT: ErrorCode = ErrorCode::new(2001, "SQL_CONFLICT", ErrorCategory::Transient);
+/// The query didn't complete within its timeout, see
+/// [QueryTimeout](crate::timeout::QueryTimeout).
+pub const SQL_QUERY_TIMEOUT: ErrorCode =
+    ErrorCode::new(2002, "SQL_QUERY_TIMEOUT", ErrorCategory::Transient);
+/// No connection became available in time, see
+/// [AcquireTimeout](crate::timeout::AcquireTimeout).
+pub const SQL_ACQUIRE_TIMEOUT: ErrorCode =
+    ErrorCode::new(2003, "SQL_ACQUIRE_TIMEOUT", ErrorCategory::Transient);
+/// The query was cancelled, see [QueryCancelled](crate::cancel::QueryCancelled).
+pub const SQL_QUERY_CANCELLED: ErrorCode =
+    ErrorCode::new(2004, "SQL_QUERY_CANCELLED", ErrorCategory::Permanent);
+/// A row has the key of an existing row.
+pub const SQL_DUPLICATE_KEY: ErrorCode =
+    ErrorCode::new(2005, "SQL_DUPLICATE_KEY", ErrorCategory::InvalidInput);
+/// The user couldn't be authenticated or is not allowed to run the query.
+pub const SQL_ACCESS_DENIED: ErrorCode =
+    ErrorCode::new(2006, "SQL_ACCESS_DENIED", ErrorCategory::Auth);
+/// The connection to the database was lost.
+pub const SQL_CONNECTION_LOST: ErrorCode =
+    ErrorCode::new(2007, "SQL_CONNECTION_LOST", ErrorCategory::Transient);
+/// The query is invalid, e.g. it has a syntax error.
+pub const SQL_SYNTAX: ErrorCode = ErrorCode::new(2008, "SQL_SYNTAX", ErrorCategory::InvalidInput);
+
+/// The error codes of the sql crate, to be registered with
+/// [error_codes::register].
+pub static ERROR_DOMAIN: ErrorDomain = ErrorDomain {
+    name: "sql",
+    codes: &[
+        SQL_CONFLICT,
+        SQL_QUERY_TIMEOUT,
+        SQL_ACQUIRE_TIMEOUT,
+        SQL_QUERY_CANCELLED,
+        SQL_DUPLICATE_KEY,
+        SQL_ACCESS_DENIED,
+        SQL_CONNECTION_LOST,
+        SQL_SYNTAX,
+    ],
+    classify,
+};
+
+/// Return the code of an error returned by a query, without looking at the
+/// errors it was caused by. Only errors of the OssMysql and SQLite backends
+/// are recognized, see [SqlErrorKind](crate::error::SqlErrorKind).
+pub fn classify(err: &(dyn StdError + 'static)) -> Option<ErrorCode> {
+    crate::error::classify_cause(err).and_then(|(kind, _)| kind.error_code())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use anyhow::Error;
+    use mysql_async::ServerError;
+
+    use super::*;
+    use crate::cancel::QueryCancelled;
+    use crate::timeout::QueryTimeout;
+
+    fn mysql_error(code: u16) -> Error {
+        mysql_async::Error::Server(ServerError {
+            code,
+            message: "test".to_owned(),
+            state: "HY000".to_owned(),
+        })
+        .into()
+    }
+
+    fn sqlite_error(code: std::os::raw::c_int) -> Error {
+        rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(code), None).into()
+    }
+
+    #[test]
+    fn test_classify() {
+        error_codes::register(&ERROR_DOMAIN).unwrap();
+        let code = |err: Error| error_codes::error_code(&err);
+
+        assert_eq!(code(mysql_error(1213)), Some(SQL_CONFLICT));
+        assert_eq!(
+            code(mysql_error(1205).context("While running a query")),
+            Some(SQL_CONFLICT)
+        );
+        assert_eq!(code(mysql_error(1062)), Some(SQL_DUPLICATE_KEY));
+        assert_eq!(code(mysql_error(1045)), Some(SQL_ACCESS_DENIED));
+        assert_eq!(code(mysql_error(1064)), Some(SQL_SYNTAX));
+        assert_eq!(code(mysql_error(1048)), None);
+        assert_eq!(
+            code(sqlite_error(rusqlite::ffi::SQLITE_BUSY)),
+            Some(SQL_CONFLICT)
+        );
+        assert_eq!(
+            code(sqlite_error(rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE)),
+            Some(SQL_DUPLICATE_KEY)
+        );
+        assert_eq!(
+            code(sqlite_error(rusqlite::ffi::SQLITE_CONSTRAINT_NOTNULL)),
+            None
+        );
+        assert_eq!(
+            code(
+                QueryTimeout {
+                    timeout: Duration::from_secs(1)
+                }
+                .into()
+            ),
+            Some(SQL_QUERY_TIMEOUT)
+        );
+        assert_eq!(code(QueryCancelled.into()), Some(SQL_QUERY_CANCELLED));
+        assert!(!error_codes::is_transient(&QueryCancelled.into()));
+    }
+}
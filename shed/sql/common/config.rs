@@ -0,0 +1,222 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Configuration of the connections of [SqlConnections] per role, as write
+//! pools usually need to be much smaller than read pools. The configuration
+//! can be deserialized, e.g. from a cached_config config.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use mysql_async::Opts;
+use mysql_async::OptsBuilder;
+use mysql_async::Pool;
+use mysql_async::PoolConstraints;
+use serde::Deserialize;
+
+use crate::mysql::ConnectionStats;
+use crate::mysql::OssConnection;
+use crate::mysql::TlsConfig;
+use crate::Connection;
+use crate::SqlConnections;
+
+/// Configuration of the connections of a single role.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ConnectionConfig {
+    /// Maximum number of connections of the pool, the default of the client
+    /// being used if unset.
+    pub max_connections: Option<usize>,
+    /// How long to wait for a connection of the pool to become available
+    /// before failing with [AcquireTimeout](crate::timeout::AcquireTimeout).
+    pub acquire_timeout_ms: Option<u64>,
+    /// Timeout of the queries that aren't given their own, after which they
+    /// fail with [QueryTimeout](crate::timeout::QueryTimeout).
+    pub statement_timeout_ms: Option<u64>,
+    /// Number of connections to establish when the connections are warmed
+    /// up, see [SqlConnections::spawn_warm_up]. The pool then keeps at least
+    /// that many connections open.
+    pub warm_connections: Option<usize>,
+    /// TLS configuration, required by every connection of the pool if set.
+    pub tls: Option<TlsConfig>,
+}
+
+impl ConnectionConfig {
+    /// Return the acquire timeout, if any.
+    pub fn acquire_timeout(&self) -> Option<Duration> {
+        self.acquire_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// Return the statement timeout, if any.
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        self.statement_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// Return the number of connections to warm up, which is at most
+    /// `max_connections`.
+    pub fn warm_connections(&self) -> usize {
+        let warm = self.warm_connections.unwrap_or(0);
+        match self.max_connections {
+            None => warm,
+            Some(max) => warm.min(max.max(1)),
+        }
+    }
+
+    /// Return the options with the pool limited to `max_connections` and
+    /// keeping at least `warm_connections` open, and with TLS required as
+    /// configured by `tls`, if set, the other options being left as they are.
+    pub fn apply_to_opts(&self, opts: Opts) -> Opts {
+        let opts = match &self.tls {
+            Some(tls) => tls.apply_to_opts(opts),
+            None => opts,
+        };
+        if self.max_connections.is_none() && self.warm_connections.is_none() {
+            return opts;
+        }
+        let constraints = opts.pool_opts().constraints();
+        let max = self
+            .max_connections
+            .map_or(constraints.max(), |max| max.max(1));
+        let min = constraints.min().max(self.warm_connections()).min(max);
+        let constraints = PoolConstraints::new(min, max).expect("min should not be above max");
+        let pool_opts = opts.pool_opts().clone().with_constraints(constraints);
+        OptsBuilder::from_opts(opts).pool_opts(pool_opts).into()
+    }
+
+    /// Create a connection to the MySQL server of `opts` with this
+    /// configuration.
+    pub fn oss_mysql_connection(&self, opts: Opts, stats: Arc<ConnectionStats>) -> Connection {
+        let pool = Pool::new(self.apply_to_opts(opts));
+        OssConnection::new(pool, stats)
+            .with_acquire_timeout(self.acquire_timeout())
+            .with_statement_timeout(self.statement_timeout())
+            .into()
+    }
+}
+
+/// Configuration of the connections of each role of [SqlConnections].
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct SqlConnectionsConfig {
+    /// Configuration of [SqlConnections::write_connection].
+    pub write: ConnectionConfig,
+    /// Configuration of [SqlConnections::read_connection].
+    pub read: ConnectionConfig,
+    /// Configuration of [SqlConnections::read_master_connection].
+    pub read_master: ConnectionConfig,
+}
+
+impl SqlConnectionsConfig {
+    /// Create the connections of each role to a MySQL server with this
+    /// configuration, the write and read master connections to `master` and
+    /// the read connection to `replica`. The stats of each role are labeled
+    /// with `label` followed by the role.
+    pub fn oss_mysql_connections(
+        &self,
+        master: Opts,
+        replica: Opts,
+        label: &str,
+    ) -> SqlConnections {
+        let stats = |role| Arc::new(ConnectionStats::new(format!("{}.{}", label, role)));
+        SqlConnections {
+            write_connection: self
+                .write
+                .oss_mysql_connection(master.clone(), stats("write")),
+            read_connection: self.read.oss_mysql_connection(replica, stats("read")),
+            read_master_connection: self
+                .read_master
+                .oss_mysql_connection(master, stats("read_master")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let config: SqlConnectionsConfig = serde_json::from_str(
+            r#"{
+                "write": {"max_connections": 5, "acquire_timeout_ms": 100},
+                "read": {"statement_timeout_ms": 2000}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            SqlConnectionsConfig {
+                write: ConnectionConfig {
+                    max_connections: Some(5),
+                    acquire_timeout_ms: Some(100),
+                    statement_timeout_ms: None,
+                    warm_connections: None,
+                    tls: None,
+                },
+                read: ConnectionConfig {
+                    statement_timeout_ms: Some(2000),
+                    ..Default::default()
+                },
+                read_master: ConnectionConfig::default(),
+            }
+        );
+        assert_eq!(
+            config.write.acquire_timeout(),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(config.write.statement_timeout(), None);
+    }
+
+    #[test]
+    fn test_apply_to_opts() {
+        let opts = Opts::from_url("mysql://localhost/db?pool_min=2&pool_max=50").unwrap();
+        assert_eq!(
+            ConnectionConfig::default()
+                .apply_to_opts(opts.clone())
+                .pool_opts()
+                .constraints(),
+            PoolConstraints::new(2, 50).unwrap()
+        );
+
+        let config = ConnectionConfig {
+            max_connections: Some(4),
+            ..Default::default()
+        };
+        let opts = config.apply_to_opts(opts);
+        assert_eq!(
+            opts.pool_opts().constraints(),
+            PoolConstraints::new(2, 4).unwrap()
+        );
+        assert_eq!(opts.db_name(), Some("db"));
+
+        let config = ConnectionConfig {
+            max_connections: Some(4),
+            warm_connections: Some(8),
+            ..Default::default()
+        };
+        assert_eq!(config.warm_connections(), 4);
+        let opts = config.apply_to_opts(opts);
+        assert_eq!(
+            opts.pool_opts().constraints(),
+            PoolConstraints::new(4, 4).unwrap()
+        );
+        assert_eq!(opts.ssl_opts(), None);
+
+        let config = ConnectionConfig {
+            tls: Some(TlsConfig::new()),
+            ..Default::default()
+        };
+        let opts = config.apply_to_opts(opts);
+        assert!(opts.ssl_opts().is_some());
+        assert_eq!(
+            opts.pool_opts().constraints(),
+            PoolConstraints::new(4, 4).unwrap()
+        );
+    }
+}
@@ -0,0 +1,383 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! The error returned by queries and transactions, telling apart the kinds
+//! of failures, e.g. a duplicate key from a lost connection, without matching
+//! error messages.
+
+use std::error::Error as StdError;
+use std::fmt;
+
+use anyhow::Error;
+use error_codes::ErrorCode;
+
+use crate::cancel::QueryCancelled;
+use crate::codes;
+use crate::timeout::AcquireTimeout;
+use crate::timeout::QueryTimeout;
+
+/// MySQL error returned to the transaction chosen as the victim of a
+/// deadlock, which is rolled back.
+const ER_LOCK_DEADLOCK: u16 = 1213;
+/// MySQL error returned when a lock couldn't be acquired within
+/// `innodb_lock_wait_timeout`.
+const ER_LOCK_WAIT_TIMEOUT: u16 = 1205;
+/// MySQL error returned when a row has the key of an existing row.
+const ER_DUP_ENTRY: u16 = 1062;
+/// Same as [ER_DUP_ENTRY], with the name of the key.
+const ER_DUP_ENTRY_WITH_KEY_NAME: u16 = 1586;
+/// MySQL error returned when the user couldn't be authenticated.
+const ER_ACCESS_DENIED_ERROR: u16 = 1045;
+/// MySQL error returned when the user is not allowed to use a database.
+const ER_DBACCESS_DENIED_ERROR: u16 = 1044;
+/// MySQL error returned when the user is not allowed to use a table.
+const ER_TABLEACCESS_DENIED_ERROR: u16 = 1142;
+/// MySQL error returned when the query can't be parsed.
+const ER_PARSE_ERROR: u16 = 1064;
+/// MySQL error returned when the query uses a table that doesn't exist.
+const ER_NO_SUCH_TABLE: u16 = 1146;
+/// MySQL error returned when the query uses a column that doesn't exist.
+const ER_BAD_FIELD_ERROR: u16 = 1054;
+/// MySQL error returned to the queries of a connection whose server is
+/// shutting down.
+const ER_SERVER_SHUTDOWN: u16 = 1053;
+
+/// Kind of a [SqlError].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SqlErrorKind {
+    /// A row has the key of an existing row.
+    DuplicateKey,
+    /// The query conflicted with another transaction, and can be retried,
+    /// see [crate::retry].
+    Conflict,
+    /// The connection to the database was lost.
+    ConnectionLost,
+    /// The query is invalid, e.g. it has a syntax error or uses a table or a
+    /// column that doesn't exist.
+    Syntax,
+    /// The user couldn't be authenticated or is not allowed to run the query.
+    AccessDenied,
+    /// The query didn't complete within its timeout, see [QueryTimeout].
+    QueryTimeout,
+    /// No connection became available in time, see [AcquireTimeout].
+    AcquireTimeout,
+    /// The query was cancelled, see [QueryCancelled].
+    Cancelled,
+    /// Any other error.
+    Other,
+}
+
+impl SqlErrorKind {
+    /// Return the stable code of the errors of this kind, see [codes].
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self {
+            Self::DuplicateKey => Some(codes::SQL_DUPLICATE_KEY),
+            Self::Conflict => Some(codes::SQL_CONFLICT),
+            Self::ConnectionLost => Some(codes::SQL_CONNECTION_LOST),
+            Self::Syntax => Some(codes::SQL_SYNTAX),
+            Self::AccessDenied => Some(codes::SQL_ACCESS_DENIED),
+            Self::QueryTimeout => Some(codes::SQL_QUERY_TIMEOUT),
+            Self::AcquireTimeout => Some(codes::SQL_ACQUIRE_TIMEOUT),
+            Self::Cancelled => Some(codes::SQL_QUERY_CANCELLED),
+            Self::Other => None,
+        }
+    }
+}
+
+/// The code of the error as returned by the backend.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BackendCode {
+    /// Error returned by a MySQL server, with its error number and SQLSTATE.
+    Mysql {
+        /// The error number, e.g. 1062 for a duplicate entry.
+        code: u16,
+        /// The SQLSTATE of the error, e.g. `23000`.
+        state: String,
+    },
+    /// Error returned by SQLite, with its primary and extended result codes.
+    Sqlite {
+        /// The primary result code.
+        code: rusqlite::ErrorCode,
+        /// The extended result code, e.g. `SQLITE_CONSTRAINT_UNIQUE`.
+        extended_code: i32,
+    },
+}
+
+/// Error returned by the queries generated by `queries!` and by
+/// [Transaction](crate::transaction::Transaction), with its [SqlErrorKind]
+/// and the code returned by the backend, if any.
+///
+/// It wraps the [anyhow::Error] of the failure, with the same message and
+/// causes. Converting it into an [anyhow::Error] with `?` keeps it as the
+/// outermost error, see [SqlError::into_anyhow] to get the wrapped one back,
+/// e.g. to keep downcasting it.
+pub struct SqlError {
+    kind: SqlErrorKind,
+    backend_code: Option<BackendCode>,
+    error: Error,
+}
+
+impl SqlError {
+    /// Wrap an error, finding its kind and backend code in the errors of its
+    /// chain. Only errors of the OssMysql and SQLite backends are recognized,
+    /// the others are of kind [SqlErrorKind::Other].
+    pub fn new(error: Error) -> Self {
+        let (kind, backend_code) = error
+            .chain()
+            .find_map(classify_cause)
+            .unwrap_or((SqlErrorKind::Other, None));
+        Self {
+            kind,
+            backend_code,
+            error,
+        }
+    }
+
+    /// Return the kind of the error.
+    pub fn kind(&self) -> SqlErrorKind {
+        self.kind
+    }
+
+    /// Return the code of the error as returned by the backend, if the error
+    /// comes from the backend.
+    pub fn backend_code(&self) -> Option<&BackendCode> {
+        self.backend_code.as_ref()
+    }
+
+    /// Return the stable code of the error, see [codes].
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        self.kind.error_code()
+    }
+
+    /// Whether the query conflicted with another transaction, and can be
+    /// retried.
+    pub fn is_conflict(&self) -> bool {
+        self.kind == SqlErrorKind::Conflict
+    }
+
+    /// Whether the error is a duplicate key.
+    pub fn is_duplicate_key(&self) -> bool {
+        self.kind == SqlErrorKind::DuplicateKey
+    }
+
+    /// Return the wrapped error, e.g. a [QueryTimeout] with its context.
+    pub fn as_anyhow(&self) -> &Error {
+        &self.error
+    }
+
+    /// Unwrap the error.
+    pub fn into_anyhow(self) -> Error {
+        self.error
+    }
+
+    /// Downcast the wrapped error, see [anyhow::Error::downcast_ref].
+    pub fn downcast_ref<E>(&self) -> Option<&E>
+    where
+        E: fmt::Display + fmt::Debug + Send + Sync + 'static,
+    {
+        self.error.downcast_ref()
+    }
+
+    /// Whether the wrapped error is an `E`, see [anyhow::Error::is].
+    pub fn is<E>(&self) -> bool
+    where
+        E: fmt::Display + fmt::Debug + Send + Sync + 'static,
+    {
+        self.error.is::<E>()
+    }
+}
+
+impl From<Error> for SqlError {
+    fn from(error: Error) -> Self {
+        Self::new(error)
+    }
+}
+
+impl fmt::Display for SqlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl fmt::Debug for SqlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.error, f)
+    }
+}
+
+impl StdError for SqlError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Return the kind and backend code of an error, without looking at the
+/// errors it was caused by.
+pub(crate) fn classify_cause(
+    err: &(dyn StdError + 'static),
+) -> Option<(SqlErrorKind, Option<BackendCode>)> {
+    if let Some(err) = err.downcast_ref::<SqlError>() {
+        return Some((err.kind, err.backend_code.clone()));
+    }
+    if err.is::<QueryTimeout>() {
+        return Some((SqlErrorKind::QueryTimeout, None));
+    }
+    if err.is::<AcquireTimeout>() {
+        return Some((SqlErrorKind::AcquireTimeout, None));
+    }
+    if err.is::<QueryCancelled>() {
+        return Some((SqlErrorKind::Cancelled, None));
+    }
+    if let Some(err) = err.downcast_ref::<mysql_async::Error>() {
+        let err = match err {
+            mysql_async::Error::Server(err) => err,
+            mysql_async::Error::Io(_) => return Some((SqlErrorKind::ConnectionLost, None)),
+            _ => return None,
+        };
+        let kind = match err.code {
+            ER_DUP_ENTRY | ER_DUP_ENTRY_WITH_KEY_NAME => SqlErrorKind::DuplicateKey,
+            ER_LOCK_DEADLOCK | ER_LOCK_WAIT_TIMEOUT => SqlErrorKind::Conflict,
+            ER_SERVER_SHUTDOWN => SqlErrorKind::ConnectionLost,
+            ER_PARSE_ERROR | ER_NO_SUCH_TABLE | ER_BAD_FIELD_ERROR => SqlErrorKind::Syntax,
+            ER_ACCESS_DENIED_ERROR | ER_DBACCESS_DENIED_ERROR | ER_TABLEACCESS_DENIED_ERROR => {
+                SqlErrorKind::AccessDenied
+            }
+            _ => SqlErrorKind::Other,
+        };
+        let code = BackendCode::Mysql {
+            code: err.code,
+            state: err.state.clone(),
+        };
+        return Some((kind, Some(code)));
+    }
+    if let Some(rusqlite::Error::SqliteFailure(err, _)) = err.downcast_ref::<rusqlite::Error>() {
+        let kind = match err.code {
+            rusqlite::ErrorCode::ConstraintViolation
+                if err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY
+                    || err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE =>
+            {
+                SqlErrorKind::DuplicateKey
+            }
+            rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked => {
+                SqlErrorKind::Conflict
+            }
+            rusqlite::ErrorCode::PermissionDenied
+            | rusqlite::ErrorCode::AuthorizationForStatementDenied => SqlErrorKind::AccessDenied,
+            // SQLITE_ERROR is returned for the queries that can't be
+            // prepared, e.g. with a syntax error or an unknown table.
+            rusqlite::ErrorCode::Unknown if err.extended_code == rusqlite::ffi::SQLITE_ERROR => {
+                SqlErrorKind::Syntax
+            }
+            _ => SqlErrorKind::Other,
+        };
+        let code = BackendCode::Sqlite {
+            code: err.code,
+            extended_code: err.extended_code,
+        };
+        return Some((kind, Some(code)));
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use anyhow::format_err;
+    use mysql_async::ServerError;
+
+    use super::*;
+
+    fn mysql_error(code: u16) -> Error {
+        mysql_async::Error::Server(ServerError {
+            code,
+            message: "test".to_owned(),
+            state: "23000".to_owned(),
+        })
+        .into()
+    }
+
+    fn sqlite_error(code: std::os::raw::c_int) -> Error {
+        rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(code), None).into()
+    }
+
+    #[test]
+    fn test_kind() {
+        let err = SqlError::from(mysql_error(ER_DUP_ENTRY).context("While executing Insert query"));
+        assert!(err.is_duplicate_key());
+        assert_eq!(
+            err.backend_code(),
+            Some(&BackendCode::Mysql {
+                code: ER_DUP_ENTRY,
+                state: "23000".to_owned(),
+            })
+        );
+
+        assert!(SqlError::from(mysql_error(ER_LOCK_DEADLOCK)).is_conflict());
+        assert_eq!(
+            SqlError::from(mysql_error(ER_PARSE_ERROR)).kind(),
+            SqlErrorKind::Syntax
+        );
+        let err = SqlError::from(mysql_error(1048));
+        assert_eq!(err.kind(), SqlErrorKind::Other);
+        assert!(err.backend_code().is_some());
+
+        let err = SqlError::from(sqlite_error(rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE));
+        assert!(err.is_duplicate_key());
+        assert_eq!(
+            err.backend_code(),
+            Some(&BackendCode::Sqlite {
+                code: rusqlite::ErrorCode::ConstraintViolation,
+                extended_code: rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE,
+            })
+        );
+        assert_eq!(
+            SqlError::from(sqlite_error(rusqlite::ffi::SQLITE_ERROR)).kind(),
+            SqlErrorKind::Syntax
+        );
+
+        let err = SqlError::from(Error::from(QueryTimeout {
+            timeout: Duration::from_secs(1),
+        }));
+        assert_eq!(err.kind(), SqlErrorKind::QueryTimeout);
+        assert!(err.is::<QueryTimeout>());
+        assert_eq!(err.error_code(), Some(codes::SQL_QUERY_TIMEOUT));
+
+        let err = SqlError::from(format_err!("unknown"));
+        assert_eq!(err.kind(), SqlErrorKind::Other);
+        assert_eq!(err.backend_code(), None);
+        assert_eq!(err.error_code(), None);
+    }
+
+    #[test]
+    fn test_display() {
+        let err = SqlError::from(format_err!("test").context("While executing Insert query"));
+        assert_eq!(err.to_string(), "While executing Insert query");
+        assert_eq!(format!("{:#}", err), "While executing Insert query: test");
+
+        let err = Error::from(err).context("While inserting");
+        assert_eq!(
+            format!("{:#}", err),
+            "While inserting: While executing Insert query: test"
+        );
+    }
+
+    #[test]
+    fn test_into_anyhow() {
+        let err = SqlError::from(mysql_error(ER_DUP_ENTRY).context("While executing Insert query"));
+        // The kind is kept when the error is wrapped again.
+        let err = SqlError::from(Error::from(err).context("While inserting"));
+        assert!(err.is_duplicate_key());
+
+        let err = SqlError::from(Error::from(QueryCancelled));
+        assert!(!Error::from(SqlError::from(Error::from(QueryCancelled))).is::<QueryCancelled>());
+        assert!(err.into_anyhow().is::<QueryCancelled>());
+    }
+}
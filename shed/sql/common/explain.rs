@@ -0,0 +1,207 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Structured plans of queries, as returned by the `explain` function of the
+//! queries generated by `queries!`, so that tests can assert which indexes
+//! a query uses.
+
+use anyhow::Context;
+use anyhow::Error;
+use serde_json::Value;
+
+use crate::Connection;
+
+/// Name of the index of [PlanStep::index] when the primary key is used.
+pub const PRIMARY_KEY: &str = "PRIMARY";
+
+/// The plan of a query, with a step for each table it reads.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueryPlan {
+    /// The steps of the plan, in the order given by the backend.
+    pub steps: Vec<PlanStep>,
+}
+
+/// How a table is read by a query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlanStep {
+    /// The table read, if the step reads one.
+    pub table: Option<String>,
+    /// The index used to read the table, [PRIMARY_KEY] for the primary key.
+    pub index: Option<String>,
+    /// Whether all the rows of the table are read, without using an index.
+    pub full_scan: bool,
+    /// Description of the step by the backend: the detail of the step on
+    /// sqlite, the access type of the table on MySQL.
+    pub detail: String,
+}
+
+impl QueryPlan {
+    /// Whether a step of the plan uses the index `index`.
+    pub fn uses_index(&self, index: &str) -> bool {
+        self.steps
+            .iter()
+            .any(|step| step.index.as_deref() == Some(index))
+    }
+
+    /// Whether a step of the plan reads all the rows of the table `table`.
+    pub fn scans_table(&self, table: &str) -> bool {
+        self.steps
+            .iter()
+            .any(|step| step.full_scan && step.table.as_deref() == Some(table))
+    }
+
+    /// Whether a step of the plan reads all the rows of a table.
+    pub fn has_full_scan(&self) -> bool {
+        self.steps.iter().any(|step| step.full_scan)
+    }
+
+    /// Parse the plan explained by sqlite, with a line per step as returned
+    /// by [Connection::explain], e.g. `SEARCH foo USING INDEX foo_x (x=?)`.
+    pub fn from_sqlite(plan: &str) -> Self {
+        let steps = plan.lines().map(parse_sqlite_step).collect();
+        Self { steps }
+    }
+
+    /// Parse the plan explained by MySQL with `EXPLAIN FORMAT=JSON`, as
+    /// returned by [Connection::explain].
+    pub fn from_mysql_json(plan: &str) -> Result<Self, Error> {
+        let plan: Value = serde_json::from_str(plan).context("Invalid MySQL plan")?;
+        let mut steps = Vec::new();
+        collect_mysql_steps(&plan, &mut steps);
+        Ok(Self { steps })
+    }
+}
+
+fn parse_sqlite_step(detail: &str) -> PlanStep {
+    let mut words = detail.split_whitespace();
+    let access = words.next();
+    let mut table = words.next();
+    // Older versions of sqlite write `SCAN TABLE foo`.
+    if table == Some("TABLE") {
+        table = words.next();
+    }
+    let rest: Vec<_> = words.collect();
+    let index = match rest.as_slice() {
+        ["USING", "INDEX", index, ..] | ["USING", "COVERING", "INDEX", index, ..] => {
+            Some(index.to_string())
+        }
+        ["USING", "INTEGER", "PRIMARY", "KEY", ..] | ["USING", "PRIMARY", "KEY", ..] => {
+            Some(PRIMARY_KEY.to_owned())
+        }
+        _ => None,
+    };
+    let is_table_access = matches!(access, Some("SCAN" | "SEARCH"));
+    PlanStep {
+        table: table.filter(|_| is_table_access).map(str::to_owned),
+        full_scan: access == Some("SCAN") && index.is_none(),
+        index,
+        detail: detail.to_owned(),
+    }
+}
+
+/// Collect the tables of a MySQL plan, which are nested in the query blocks,
+/// joins and subqueries of the plan.
+fn collect_mysql_steps(value: &Value, steps: &mut Vec<PlanStep>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                if key == "table" {
+                    if let Some(table) = value.as_object() {
+                        let field = |name| table.get(name).and_then(Value::as_str);
+                        let detail = field("access_type").unwrap_or_default();
+                        steps.push(PlanStep {
+                            table: field("table_name").map(str::to_owned),
+                            index: field("key").map(str::to_owned),
+                            full_scan: detail == "ALL",
+                            detail: detail.to_owned(),
+                        });
+                    }
+                }
+                collect_mysql_steps(value, steps);
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                collect_mysql_steps(value, steps);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Connection {
+    /// Return the structured plan of `sql`, see [Connection::explain].
+    pub async fn explain_plan(&self, sql: &str) -> Result<QueryPlan, Error> {
+        let plan = self.explain(sql).await?;
+        match self {
+            Connection::Sqlite(_) => Ok(QueryPlan::from_sqlite(&plan)),
+            Connection::Mysql(_) | Connection::OssMysql(_) => QueryPlan::from_mysql_json(&plan),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_sqlite() {
+        let plan = QueryPlan::from_sqlite(
+            "SEARCH foo USING INDEX foo_x (x=?)\n\
+             SCAN bar\n\
+             SEARCH baz USING INTEGER PRIMARY KEY (rowid=?)\n\
+             SCAN TABLE qux USING COVERING INDEX qux_y\n\
+             USE TEMP B-TREE FOR ORDER BY",
+        );
+        assert_eq!(plan.steps.len(), 5);
+        assert!(plan.uses_index("foo_x"));
+        assert!(plan.uses_index(PRIMARY_KEY));
+        assert!(plan.uses_index("qux_y"));
+        assert!(plan.scans_table("bar"));
+        assert!(!plan.scans_table("qux"));
+        assert_eq!(plan.steps[3].table.as_deref(), Some("qux"));
+        assert_eq!(plan.steps[4].table, None);
+        assert!(!plan.steps[4].full_scan);
+    }
+
+    #[test]
+    fn test_from_mysql_json() {
+        let plan = QueryPlan::from_mysql_json(
+            r#"{
+              "query_block": {
+                "select_id": 1,
+                "nested_loop": [
+                  {"table": {"table_name": "foo", "access_type": "ref", "key": "foo_x"}},
+                  {"table": {"table_name": "bar", "access_type": "ALL"}}
+                ]
+              }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            plan.steps,
+            vec![
+                PlanStep {
+                    table: Some("foo".to_owned()),
+                    index: Some("foo_x".to_owned()),
+                    full_scan: false,
+                    detail: "ref".to_owned(),
+                },
+                PlanStep {
+                    table: Some("bar".to_owned()),
+                    index: None,
+                    full_scan: true,
+                    detail: "ALL".to_owned(),
+                },
+            ]
+        );
+        assert!(plan.scans_table("bar"));
+        assert!(QueryPlan::from_mysql_json("not json").is_err());
+    }
+}
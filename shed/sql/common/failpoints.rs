@@ -0,0 +1,199 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Fault injection with [fail](https://docs.rs/fail) failpoints, for testing
+//! how services handle database failures, enabled by the `failpoints`
+//! feature.
+//!
+//! Every query made with `queries!` evaluates the failpoint
+//! `sql::query::<name>`, see [query_failpoint], and every transaction
+//! operation the failpoint `sql::transaction::<begin|commit|rollback>`, see
+//! [transaction_failpoint], on all the backends. A failpoint configured with
+//! `return(<fault>)`, e.g. with `fail::cfg` or the `FAILPOINTS` environment
+//! variable, injects the [Fault] instead of running the operation, or
+//! before running it for [Fault::Slow]. The other actions of `fail`, e.g.
+//! `1*return(...)` or `10%return(...)`, are supported.
+//!
+//! The injected errors are those a real failure would return, so that they
+//! have the same [SqlErrorKind](crate::error::SqlErrorKind): the errors of
+//! the MySQL client for lost connections and duplicate keys, whatever the
+//! backend, and [QueryTimeout] for timeouts.
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::format_err;
+use anyhow::Error;
+use mysql_async::ServerError;
+
+use crate::observer::TransactionOperation;
+use crate::timeout::QueryTimeout;
+
+/// MySQL error returned when a row has the key of an existing row.
+const ER_DUP_ENTRY: u16 = 1062;
+
+/// A fault injected by a failpoint, written in its `return(...)` action as
+/// `connection_lost`, `timeout`, `duplicate_key` or `slow(<ms>)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Fail as if the connection to the database was dropped.
+    ConnectionLost,
+    /// Fail with a [QueryTimeout].
+    Timeout,
+    /// Fail as if a row had the key of an existing row.
+    DuplicateKey,
+    /// Wait for the duration before running the operation.
+    Slow(Duration),
+}
+
+impl Fault {
+    /// The `return(...)` action injecting this fault, e.g. to configure a
+    /// failpoint with `fail::cfg`.
+    pub fn action(&self) -> String {
+        format!("return({})", self)
+    }
+
+    /// Inject the fault, returning the error of the operation, if any.
+    async fn inject(self) -> Result<(), Error> {
+        match self {
+            Fault::ConnectionLost => Err(mysql_async::Error::from(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "Connection reset by injected fault",
+            ))
+            .into()),
+            Fault::Timeout => Err(QueryTimeout {
+                timeout: Duration::ZERO,
+            }
+            .into()),
+            Fault::DuplicateKey => Err(mysql_async::Error::Server(ServerError {
+                code: ER_DUP_ENTRY,
+                message: "Duplicate entry injected by fault".to_owned(),
+                state: "23000".to_owned(),
+            })
+            .into()),
+            Fault::Slow(duration) => {
+                tokio::time::sleep(duration).await;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::ConnectionLost => write!(f, "connection_lost"),
+            Fault::Timeout => write!(f, "timeout"),
+            Fault::DuplicateKey => write!(f, "duplicate_key"),
+            Fault::Slow(duration) => write!(f, "slow({})", duration.as_millis()),
+        }
+    }
+}
+
+impl FromStr for Fault {
+    type Err = Error;
+
+    fn from_str(fault: &str) -> Result<Self, Error> {
+        match fault.trim() {
+            "connection_lost" => Ok(Fault::ConnectionLost),
+            "timeout" => Ok(Fault::Timeout),
+            "duplicate_key" => Ok(Fault::DuplicateKey),
+            fault => {
+                let millis = fault
+                    .strip_prefix("slow(")
+                    .and_then(|fault| fault.strip_suffix(')'))
+                    .and_then(|millis| millis.trim().parse().ok())
+                    .ok_or_else(|| format_err!("Invalid injected fault {:?}", fault))?;
+                Ok(Fault::Slow(Duration::from_millis(millis)))
+            }
+        }
+    }
+}
+
+/// The failpoint evaluated by the query named `name` in `queries!`.
+pub fn query_failpoint(name: &str) -> String {
+    format!("sql::query::{}", name)
+}
+
+/// The failpoint evaluated by the transaction operation.
+pub fn transaction_failpoint(operation: TransactionOperation) -> &'static str {
+    match operation {
+        TransactionOperation::Begin => "sql::transaction::begin",
+        TransactionOperation::Commit => "sql::transaction::commit",
+        TransactionOperation::Rollback => "sql::transaction::rollback",
+    }
+}
+
+/// Inject the fault of the failpoint, if it is configured with a
+/// `return(<fault>)` action that triggers.
+pub(crate) async fn inject(failpoint: &str) -> Result<(), Error> {
+    let fault = match fail::eval(failpoint, |fault| fault) {
+        None => return Ok(()),
+        Some(fault) => fault.unwrap_or_default(),
+    };
+    let fault: Fault = fault
+        .parse()
+        .map_err(|err: Error| err.context(format!("In failpoint {}", failpoint)))?;
+    fault.inject().await
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::SqlError;
+    use crate::error::SqlErrorKind;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_fault() {
+        for fault in [
+            Fault::ConnectionLost,
+            Fault::Timeout,
+            Fault::DuplicateKey,
+            Fault::Slow(Duration::from_millis(250)),
+        ] {
+            assert_eq!(fault.to_string().parse::<Fault>().unwrap(), fault);
+        }
+        assert!("slow(x)".parse::<Fault>().is_err());
+        assert!("".parse::<Fault>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_inject() {
+        tokio::time::pause();
+
+        let failpoint = query_failpoint("TestInject");
+        assert!(inject(&failpoint).await.is_ok());
+
+        for (fault, kind) in [
+            (Fault::ConnectionLost, SqlErrorKind::ConnectionLost),
+            (Fault::Timeout, SqlErrorKind::QueryTimeout),
+            (Fault::DuplicateKey, SqlErrorKind::DuplicateKey),
+        ] {
+            fail::cfg(&failpoint, &fault.action()).unwrap();
+            let err = SqlError::from(inject(&failpoint).await.unwrap_err());
+            assert_eq!(err.kind(), kind);
+        }
+
+        fail::cfg(&failpoint, &Fault::Slow(Duration::from_secs(1)).action()).unwrap();
+        let start = tokio::time::Instant::now();
+        inject(&failpoint).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        // Only the first evaluation triggers.
+        fail::cfg(&failpoint, "1*return(timeout)").unwrap();
+        assert!(inject(&failpoint).await.is_err());
+        assert!(inject(&failpoint).await.is_ok());
+
+        fail::remove(&failpoint);
+        assert!(inject(&failpoint).await.is_ok());
+    }
+}
@@ -0,0 +1,154 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Reading from the replicas of [SqlConnections], falling back to the
+//! master when the replicas are unavailable or the data read from them is
+//! stale, e.g. because it doesn't include a write that was just made.
+
+use std::future::Future;
+
+use anyhow::Error;
+use stats::prelude::*;
+
+use crate::timeout::AcquireTimeout;
+use crate::timeout::QueryTimeout;
+use crate::Connection;
+use crate::SqlConnections;
+
+define_stats! {
+    prefix = "sql.read_fallback";
+    unavailable: timeseries(Rate, Sum),
+    stale: timeseries(Rate, Sum),
+}
+
+impl SqlConnections {
+    /// Run `read` on the read connection, and run it again on the read
+    /// master connection if it fails because the replica is unavailable, see
+    /// [is_unavailable]. Other errors are returned as they are.
+    ///
+    /// Fallbacks are exported as `sql.read_fallback.*` stats.
+    pub async fn read_with_fallback<T, F, Fut>(&self, read: F) -> Result<T, Error>
+    where
+        F: Fn(Connection) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        self.read_with_fallback_if_stale(read, |_| false).await
+    }
+
+    /// Same as [Self::read_with_fallback], also running `read` again on the
+    /// read master connection if `is_stale` returns true for the result read
+    /// from the replica.
+    pub async fn read_with_fallback_if_stale<T, F, Fut>(
+        &self,
+        read: F,
+        is_stale: impl FnOnce(&T) -> bool,
+    ) -> Result<T, Error>
+    where
+        F: Fn(Connection) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        match read(self.read_connection.clone()).await {
+            Ok(result) if !is_stale(&result) => return Ok(result),
+            Ok(_) => STATS::stale.add_value(1),
+            Err(err) if is_unavailable(&err) => STATS::unavailable.add_value(1),
+            Err(err) => return Err(err),
+        }
+        read(self.read_master_connection.clone()).await
+    }
+}
+
+/// Whether the error, or any error it was caused by, means that the database
+/// couldn't be reached or didn't answer in time, rather than that the query
+/// itself failed.
+pub fn is_unavailable(err: &Error) -> bool {
+    err.chain().any(|cause| {
+        cause.is::<AcquireTimeout>()
+            || cause.is::<QueryTimeout>()
+            || matches!(
+                cause.downcast_ref::<mysql_async::Error>(),
+                Some(mysql_async::Error::Io(..))
+            )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use anyhow::format_err;
+
+    use super::*;
+    use crate::mysql::ConnectionStats;
+    use crate::mysql::OssConnection;
+
+    /// Connections telling which role they have through their schema
+    /// variant. They never connect as the tests don't run any query.
+    fn connections() -> SqlConnections {
+        let pool = mysql_async::Pool::new("mysql://localhost/db");
+        let stats = Arc::new(ConnectionStats::new("test".to_owned()));
+        let conn = |role: &str| {
+            Connection::from(
+                OssConnection::new(pool.clone(), stats.clone()).with_schema_variant(role),
+            )
+        };
+        SqlConnections {
+            write_connection: conn("write"),
+            read_connection: conn("read"),
+            read_master_connection: conn("read_master"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_with_fallback() {
+        let connections = connections();
+        let roles = Mutex::new(Vec::new());
+        let read = |replica_result: fn() -> Result<u32, Error>| {
+            roles.lock().unwrap().clear();
+            let roles = &roles;
+            move |conn: Connection| async move {
+                let role = conn.schema_variant().unwrap().to_owned();
+                roles.lock().unwrap().push(role.clone());
+                if role == "read" {
+                    replica_result()
+                } else {
+                    Ok(2)
+                }
+            }
+        };
+
+        let result = connections.read_with_fallback(read(|| Ok(1))).await;
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(*roles.lock().unwrap(), vec!["read"]);
+
+        let result = connections
+            .read_with_fallback(read(|| {
+                Err(Error::from(AcquireTimeout {
+                    timeout: Duration::from_secs(1),
+                })
+                .context("While reading"))
+            }))
+            .await;
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(*roles.lock().unwrap(), vec!["read", "read_master"]);
+
+        let result = connections
+            .read_with_fallback(read(|| Err(format_err!("syntax error"))))
+            .await;
+        assert!(result.is_err());
+        assert_eq!(*roles.lock().unwrap(), vec!["read"]);
+
+        let result = connections
+            .read_with_fallback_if_stale(read(|| Ok(1)), |result| *result < 2)
+            .await;
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(*roles.lock().unwrap(), vec!["read", "read_master"]);
+    }
+}
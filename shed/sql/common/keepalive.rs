@@ -0,0 +1,110 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Keeping connections healthy in the background, so that the connections
+//! broken while idle, e.g. closed by the server, are replaced before queries
+//! get them rather than making those queries fail.
+
+use std::time::Duration;
+
+use anyhow::Error;
+use stats::prelude::*;
+use tokio::task::JoinHandle;
+
+use crate::Connection;
+
+define_stats! {
+    prefix = "sql.keepalive";
+    failures: dynamic_timeseries("{}.failures", (backend: &'static str); Rate, Sum),
+    recovered: dynamic_timeseries("{}.recovered", (backend: &'static str); Rate, Sum),
+}
+
+impl Connection {
+    /// Check the connections and recover the broken ones, returning how many
+    /// were recovered:
+    ///
+    /// * on [Connection::OssMysql], `connections` connections of the pool
+    ///   are pinged and the failing ones closed, see
+    ///   [OssConnection::validate_pooled](crate::mysql::OssConnection::validate_pooled),
+    /// * on sqlite, the connections are recovered from callers that panicked
+    ///   while using them, see
+    ///   [SqliteMultithreaded::recover](crate::sqlite::SqliteMultithreaded::recover),
+    /// * on [Connection::Mysql], whose client manages its own pool, the
+    ///   server is only pinged.
+    pub async fn keepalive(&self, connections: usize) -> Result<usize, Error> {
+        match self {
+            Connection::Sqlite(multithread_con) => {
+                Ok(usize::from(multithread_con.recover().await?))
+            }
+            Connection::Mysql(conn) => {
+                conn.ping().await?;
+                Ok(0)
+            }
+            Connection::OssMysql(conn) => conn.validate_pooled(connections).await,
+        }
+    }
+
+    /// Run [Self::keepalive] every `interval` in the background until the
+    /// returned handle is dropped. Each run fails if it doesn't complete
+    /// within `interval`.
+    ///
+    /// Recovered connections and failures are exported as
+    /// `sql.keepalive.<backend>.*` stats.
+    pub fn spawn_keepalive(&self, interval: Duration, connections: usize) -> KeepAlive {
+        let connection = self.clone();
+        let handle = tokio::spawn(async move {
+            let backend = connection.backend_name();
+            loop {
+                tokio::time::sleep(interval).await;
+                let result = tokio::time::timeout(interval, connection.keepalive(connections));
+                match result.await {
+                    Ok(Ok(recovered)) => {
+                        STATS::recovered.add_value(recovered as i64, (backend,));
+                    }
+                    Ok(Err(_)) | Err(_) => STATS::failures.add_value(1, (backend,)),
+                }
+            }
+        });
+        KeepAlive { handle }
+    }
+}
+
+/// Handle of the task spawned by [Connection::spawn_keepalive], which stops
+/// the task when dropped.
+#[must_use = "the keepalive task stops when its handle is dropped"]
+pub struct KeepAlive {
+    handle: JoinHandle<()>,
+}
+
+impl Drop for KeepAlive {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use mysql_async::Pool;
+
+    use super::*;
+    use crate::mysql::ConnectionStats;
+    use crate::mysql::OssConnection;
+
+    #[tokio::test]
+    async fn test_keepalive_oss_mysql() {
+        // Nothing listens on port 1, so connections are refused.
+        let pool = Pool::new("mysql://127.0.0.1:1/db");
+        let stats = Arc::new(ConnectionStats::new("test".to_owned()));
+        let conn = Connection::from(OssConnection::new(pool, stats));
+        assert!(conn.keepalive(1).await.is_err());
+        assert_eq!(conn.keepalive(0).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,305 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Measuring how far the replicas of [SqlConnections] lag behind the master,
+//! and reading from them only when they are fresh enough, routing the reads
+//! to the master otherwise.
+//!
+//! The [Consistency] a read needs can also be stated explicitly, e.g. with the
+//! `query_with_consistency` of the read queries made with `queries!`, which
+//! picks the connection accordingly.
+
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::format_err;
+use anyhow::Error;
+use mysql_async::Row;
+use stats::prelude::*;
+use time_ext::DurationExt;
+
+use crate::Connection;
+use crate::SqlConnections;
+
+define_stats! {
+    prefix = "sql.replica_lag";
+    lag_ms: histogram(100, 0, 60_000, Average; P 50; P 99),
+    stale: timeseries(Rate, Sum),
+    unknown: timeseries(Rate, Sum),
+}
+
+/// Where the replication lag of a replica is read from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum LagSource {
+    /// The lag reported by the replica itself, i.e. `Seconds_Behind_Master`
+    /// in `SHOW SLAVE STATUS` on MySQL, which has a granularity of a second
+    /// and only measures how far the replica is behind the relay log it has
+    /// received.
+    #[default]
+    ReplicaStatus,
+    /// The age of the most recent timestamp in a heartbeat table, which a
+    /// process such as `pt-heartbeat` updates periodically on the master, so
+    /// that the lag measured includes the time taken to receive the updates.
+    /// The timestamps must be in UTC.
+    Heartbeat {
+        /// The heartbeat table.
+        table: String,
+        /// The column of the table holding the timestamps.
+        column: String,
+    },
+}
+
+/// How recent the data seen by a read must be, which decides the connection
+/// of [SqlConnections] it runs on, see [SqlConnections::read_with_consistency].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Consistency {
+    /// The read may see data of any age, and runs on the read connection.
+    #[default]
+    Eventual,
+    /// The read may see data at most this old, and runs on the read
+    /// connection if its replica lags behind the master by at most this
+    /// much, and on the read master connection otherwise, see
+    /// [SqlConnections::read_if_fresh].
+    BoundedStaleness(Duration),
+    /// The read must see all the committed writes, and runs on the read
+    /// master connection.
+    Strong,
+}
+
+impl LagSource {
+    /// Read the lag from the heartbeat `column` of `table`.
+    pub fn heartbeat(table: impl Into<String>, column: impl Into<String>) -> Self {
+        Self::Heartbeat {
+            table: table.into(),
+            column: column.into(),
+        }
+    }
+}
+
+impl Connection {
+    /// Return how far the database behind this connection lags behind the
+    /// master it replicates, as read from `source`. The lag of a master, or
+    /// of sqlite, is zero. Fails if the lag is unknown, e.g. because the
+    /// replication is stopped.
+    pub async fn replication_lag(&self, source: &LagSource) -> Result<Duration, Error> {
+        let lag = match (self, source) {
+            (Connection::Sqlite(..), _) => Some(Duration::ZERO),
+            (Connection::Mysql(conn), LagSource::ReplicaStatus) => {
+                conn.get_replica_lag_secs().await?.map(Duration::from_secs)
+            }
+            (Connection::Mysql(conn), LagSource::Heartbeat { table, column }) => {
+                let lag: Vec<(Option<i64>,)> =
+                    conn.read_query(heartbeat_query(table, column)).await?;
+                heartbeat_lag(lag)
+            }
+            (Connection::OssMysql(conn), LagSource::ReplicaStatus) => {
+                let mut con = conn.get_conn().await?;
+                let status: Vec<Row> = conn
+                    .read_query(&mut con, "SHOW SLAVE STATUS")
+                    .await?
+                    .collect_and_drop()
+                    .await?;
+                match status.first() {
+                    // Only replicas have a status.
+                    None => Some(Duration::ZERO),
+                    Some(row) => ["Seconds_Behind_Master", "Seconds_Behind_Source"]
+                        .into_iter()
+                        .find_map(|column| row.get_opt::<Option<u64>, _>(column))
+                        .transpose()?
+                        .flatten()
+                        .map(Duration::from_secs),
+                }
+            }
+            (Connection::OssMysql(conn), LagSource::Heartbeat { table, column }) => {
+                let mut con = conn.get_conn().await?;
+                let query = heartbeat_query(table, column);
+                let lag: Vec<(Option<i64>,)> = conn
+                    .read_query(&mut con, &query)
+                    .await?
+                    .collect_and_drop()
+                    .await?;
+                heartbeat_lag(lag)
+            }
+        };
+        lag.ok_or_else(|| format_err!("Replication lag of {:?} is unknown", self))
+    }
+}
+
+fn heartbeat_query(table: &str, column: &str) -> String {
+    format!(
+        "SELECT TIMESTAMPDIFF(MICROSECOND, MAX({}), UTC_TIMESTAMP(6)) FROM {}",
+        column, table
+    )
+}
+
+/// The lag read from a heartbeat table, unknown if the table is empty. The
+/// clocks of the master and the replica may differ slightly, so a heartbeat
+/// from the future counts as no lag.
+fn heartbeat_lag(rows: Vec<(Option<i64>,)>) -> Option<Duration> {
+    let (micros,) = rows.into_iter().next()?;
+    Some(Duration::from_micros(micros?.max(0) as u64))
+}
+
+impl SqlConnections {
+    /// Return how far the replica of the read connection lags behind the
+    /// master, as read from `source`, see [Connection::replication_lag].
+    pub async fn replication_lag(&self, source: &LagSource) -> Result<Duration, Error> {
+        self.read_connection.replication_lag(source).await
+    }
+
+    /// Run `read` on the read connection if its replica lags behind the
+    /// master by at most `max_lag`, as reported by the replica, and on the
+    /// read master connection otherwise, or if the lag is unknown.
+    ///
+    /// The lag is measured before each read, so this is meant for the reads
+    /// that must see recent writes, the others should use the read
+    /// connection directly. Reads routed to the master are exported as
+    /// `sql.replica_lag.*` stats.
+    pub async fn read_if_fresh<T, F, Fut>(&self, max_lag: Duration, read: F) -> Result<T, Error>
+    where
+        F: FnOnce(Connection) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        self.read_if_fresh_with_source(&LagSource::default(), max_lag, read)
+            .await
+    }
+
+    /// Same as [Self::read_if_fresh], with the lag read from `source`.
+    pub async fn read_if_fresh_with_source<T, F, Fut>(
+        &self,
+        source: &LagSource,
+        max_lag: Duration,
+        read: F,
+    ) -> Result<T, Error>
+    where
+        F: FnOnce(Connection) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        match self.replication_lag(source).await {
+            Ok(lag) => {
+                STATS::lag_ms.add_value(lag.as_millis_unchecked() as i64);
+                if lag <= max_lag {
+                    return read(self.read_connection.clone()).await;
+                }
+                STATS::stale.add_value(1);
+            }
+            Err(_) => STATS::unknown.add_value(1),
+        }
+        read(self.read_master_connection.clone()).await
+    }
+
+    /// Run `read` on the connection matching `consistency`, with the lag of
+    /// the replica read from the replica for [Consistency::BoundedStaleness].
+    pub async fn read_with_consistency<T, F, Fut>(
+        &self,
+        consistency: Consistency,
+        read: F,
+    ) -> Result<T, Error>
+    where
+        F: FnOnce(Connection) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        self.read_with_consistency_and_source(&LagSource::default(), consistency, read)
+            .await
+    }
+
+    /// Same as [Self::read_with_consistency], with the lag read from
+    /// `source`.
+    pub async fn read_with_consistency_and_source<T, F, Fut>(
+        &self,
+        source: &LagSource,
+        consistency: Consistency,
+        read: F,
+    ) -> Result<T, Error>
+    where
+        F: FnOnce(Connection) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        match consistency {
+            Consistency::Eventual => read(self.read_connection.clone()).await,
+            Consistency::BoundedStaleness(max_lag) => {
+                self.read_if_fresh_with_source(source, max_lag, read).await
+            }
+            Consistency::Strong => read(self.read_master_connection.clone()).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use mysql_async::Pool;
+    use rusqlite::Connection as SqliteConnection;
+
+    use super::*;
+    use crate::mysql::ConnectionStats;
+    use crate::mysql::OssConnection;
+    use crate::sqlite::SqliteMultithreaded;
+
+    #[test]
+    fn test_heartbeat_lag() {
+        assert_eq!(
+            heartbeat_lag(vec![(Some(1_500_000),)]),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(heartbeat_lag(vec![(Some(-10),)]), Some(Duration::ZERO));
+        assert_eq!(heartbeat_lag(vec![(None,)]), None);
+        assert_eq!(heartbeat_lag(vec![]), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_if_fresh() {
+        let sqlite = Connection::from(SqliteMultithreaded::new(
+            SqliteConnection::open_in_memory().unwrap(),
+        ));
+        // Nothing listens on port 1, so the lag of the replica is unknown.
+        let pool = Pool::new("mysql://127.0.0.1:1/db");
+        let stats = Arc::new(ConnectionStats::new("test".to_owned()));
+        let unreachable = Connection::from(OssConnection::new(pool, stats));
+        let read = |conn: Connection| async move { Ok(conn.backend_name()) };
+
+        let connections = SqlConnections::new_single(sqlite.clone());
+        assert_eq!(
+            connections
+                .replication_lag(&LagSource::default())
+                .await
+                .unwrap(),
+            Duration::ZERO
+        );
+        let result = connections.read_if_fresh(Duration::ZERO, read).await;
+        assert_eq!(result.unwrap(), "sqlite");
+
+        let connections = SqlConnections {
+            write_connection: sqlite.clone(),
+            read_connection: unreachable,
+            read_master_connection: sqlite,
+        };
+        assert!(
+            connections
+                .replication_lag(&LagSource::heartbeat("heartbeat", "ts"))
+                .await
+                .is_err()
+        );
+        let result = connections
+            .read_if_fresh(Duration::from_secs(10), read)
+            .await;
+        assert_eq!(result.unwrap(), "sqlite");
+
+        let max_lag = Duration::from_secs(10);
+        for (consistency, backend) in [
+            (Consistency::Eventual, "oss_mysql"),
+            (Consistency::BoundedStaleness(max_lag), "sqlite"),
+            (Consistency::Strong, "sqlite"),
+        ] {
+            let result = connections.read_with_consistency(consistency, read).await;
+            assert_eq!(result.unwrap(), backend, "{:?}", consistency);
+        }
+    }
+}
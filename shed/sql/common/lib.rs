@@ -12,9 +12,31 @@
 
 #![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
 
+pub mod batch;
+pub mod cache;
+pub mod cancel;
+pub mod capabilities;
+pub mod codes;
+pub mod config;
+pub mod error;
+pub mod explain;
+#[cfg(feature = "failpoints")]
+pub mod failpoints;
+pub mod fallback;
+pub mod keepalive;
+pub mod lag;
+pub mod metrics;
 pub mod mysql;
+pub mod observer;
+mod ping;
+pub mod retry;
+mod session;
+mod sharded;
+pub mod slow_query;
 pub mod sqlite;
+pub mod timeout;
 pub mod transaction;
+pub mod warm;
 
 use std::fmt;
 use std::fmt::Debug;
@@ -109,6 +131,18 @@ impl From<mysql::OssConnection> for Connection {
     }
 }
 
+impl Connection {
+    /// The schema variant whose SQL is run for the queries declaring one, see
+    /// [OssConnection::with_schema_variant](mysql::OssConnection::with_schema_variant).
+    /// Only OssMysql connections have a schema variant.
+    pub fn schema_variant(&self) -> Option<&str> {
+        match self {
+            Connection::Sqlite(..) | Connection::Mysql(..) => None,
+            Connection::OssMysql(conn) => conn.schema_variant(),
+        }
+    }
+}
+
 impl Debug for Connection {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -151,3 +185,32 @@ impl WriteResult {
         self.affected_rows
     }
 }
+
+/// Value returned from a `write` type of query with a `RETURNING` clause,
+/// holding a row for each of the rows it affected.
+#[derive(Debug)]
+pub struct WriteResultWithRows<T> {
+    rows: Vec<T>,
+}
+
+impl<T> WriteResultWithRows<T> {
+    /// Method made public for access from inside macros, you probably don't want to use it.
+    pub fn new(rows: Vec<T>) -> Self {
+        WriteResultWithRows { rows }
+    }
+
+    /// Return number of rows affected by the `write` query
+    pub fn affected_rows(&self) -> u64 {
+        self.rows.len() as u64
+    }
+
+    /// Return the rows returned for the affected rows.
+    pub fn rows(&self) -> &[T] {
+        &self.rows
+    }
+
+    /// Return the rows returned for the affected rows, consuming the result.
+    pub fn into_rows(self) -> Vec<T> {
+        self.rows
+    }
+}
@@ -0,0 +1,151 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Stats of the queries and transactions of each connection, exported as
+//! `sql.connection.<label>.*` for the connections given a label with
+//! [Connection::with_metrics_label] or [SqlConnections::with_metrics_label]:
+//!
+//! * `queries` and `errors`, the queries made with `queries!` and those of
+//!   them that failed, including the queries run in transactions,
+//! * `open_transactions`, the transactions started and not yet committed,
+//!   rolled back or dropped,
+//! * `lock_wait_ms`, on sqlite, the time spent waiting for a connection by
+//!   the queries and transactions.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use stats::prelude::*;
+
+use crate::Connection;
+use crate::SqlConnections;
+
+define_stats_struct! {
+    ConnectionMetricsStats("sql.connection.{}", label: String),
+    queries: timeseries(Rate, Sum),
+    errors: timeseries(Rate, Sum),
+    open_transactions: counter(),
+    lock_wait_ms: histogram(10, 0, 10_000, Average; P 50; P 99),
+}
+
+/// The stats of a connection, see the [module](self) documentation.
+#[derive(Clone, Debug)]
+pub struct ConnectionMetrics {
+    inner: Arc<ConnectionMetricsInner>,
+}
+
+#[derive(Debug)]
+struct ConnectionMetricsInner {
+    label: String,
+    stats: ConnectionMetricsStats,
+}
+
+impl ConnectionMetrics {
+    /// Create the stats of the connections labeled `label`.
+    pub fn new(label: &str) -> Self {
+        Self {
+            inner: Arc::new(ConnectionMetricsInner {
+                label: label.to_owned(),
+                stats: ConnectionMetricsStats::new(label.to_owned()),
+            }),
+        }
+    }
+
+    /// The label of the stats.
+    pub fn label(&self) -> &str {
+        &self.inner.label
+    }
+
+    pub(crate) fn query_completed(&self, failed: bool) {
+        self.inner.stats.queries.add_value(1);
+        if failed {
+            self.inner.stats.errors.add_value(1);
+        }
+    }
+
+    pub(crate) fn lock_waited(&self, wait: Duration) {
+        self.inner
+            .stats
+            .lock_wait_ms
+            .add_value(wait.as_millis() as i64);
+    }
+
+    pub(crate) fn transaction_started(&self) -> OpenTransaction {
+        self.inner.stats.open_transactions.increment_value(1);
+        OpenTransaction {
+            metrics: self.clone(),
+        }
+    }
+}
+
+/// Counts a transaction in the open transactions of its connection until it
+/// is dropped.
+#[derive(Debug)]
+pub struct OpenTransaction {
+    metrics: ConnectionMetrics,
+}
+
+impl OpenTransaction {
+    /// The stats of the connection the transaction was started on.
+    pub fn metrics(&self) -> &ConnectionMetrics {
+        &self.metrics
+    }
+}
+
+impl Drop for OpenTransaction {
+    fn drop(&mut self) {
+        self.metrics
+            .inner
+            .stats
+            .open_transactions
+            .increment_value(-1);
+    }
+}
+
+impl Connection {
+    /// Export the stats of this connection labeled with `label`, see the
+    /// [metrics](crate::metrics) module. The Meta internal client doesn't
+    /// support them.
+    pub fn with_metrics_label(self, label: &str) -> Self {
+        let metrics = Some(ConnectionMetrics::new(label));
+        match self {
+            Connection::Sqlite(con) => Connection::Sqlite(con.with_metrics(metrics)),
+            Connection::Mysql(conn) => Connection::Mysql(conn),
+            Connection::OssMysql(conn) => Connection::OssMysql(conn.with_metrics(metrics)),
+        }
+    }
+
+    /// The stats of this connection, if it has a label.
+    pub fn metrics(&self) -> Option<&ConnectionMetrics> {
+        match self {
+            Connection::Sqlite(con) => con.metrics(),
+            Connection::Mysql(..) => None,
+            Connection::OssMysql(conn) => conn.metrics(),
+        }
+    }
+}
+
+impl SqlConnections {
+    /// Export the stats of the connection of each role labeled with `label`
+    /// followed by the role, e.g. `label.read_master`, see
+    /// [Connection::with_metrics_label].
+    pub fn with_metrics_label(self, label: &str) -> Self {
+        Self {
+            write_connection: self
+                .write_connection
+                .with_metrics_label(&format!("{}.write", label)),
+            read_connection: self
+                .read_connection
+                .with_metrics_label(&format!("{}.read", label)),
+            read_master_connection: self
+                .read_master_connection
+                .with_metrics_label(&format!("{}.read_master", label)),
+        }
+    }
+}
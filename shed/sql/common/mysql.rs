@@ -14,6 +14,7 @@ mod facebook;
 #[cfg(not(fbcode_build))]
 mod mysql_stub;
 mod ossmysql_wrapper;
+mod tls;
 #[cfg(fbcode_build)]
 pub use facebook::Connection;
 #[cfg(fbcode_build)]
@@ -34,6 +35,10 @@ pub use mysql_stub::MysqlError;
 #[cfg(not(fbcode_build))]
 pub use mysql_stub::Transaction;
 pub use ossmysql_wrapper::OssConnection;
+pub use ossmysql_wrapper::PooledConnection;
+pub use tls::TlsClientIdentity;
+pub use tls::TlsConfig;
+pub use tls::TlsVerification;
 use stats::prelude::*;
 
 use super::WriteResult as SqlWriteResult;
@@ -42,6 +47,7 @@ define_stats_struct! {
     ConnectionStats("sql.mysql_ffi.{}", label: String),
     get_connection_ms: histogram(100, 0, 5_000, Average, Count; P 50; P 95; P 99),
     raw_query_ms: histogram(100, 0, 5_000, Average, Count; P 50; P 95; P 99),
+    exec_query_ms: histogram(100, 0, 5_000, Average, Count; P 50; P 95; P 99),
 }
 
 /// A simple wrapper struct around a SQL string, just to add some type
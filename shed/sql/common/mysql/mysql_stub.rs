@@ -64,6 +64,11 @@ impl Connection {
         unimplemented!("This is a stub");
     }
 
+    /// Runs a trivial query to check that the server is able to serve queries.
+    pub async fn ping(&self) -> Result<(), MysqlError> {
+        unimplemented!("This is a stub");
+    }
+
     /// Returns the replication lag for a connection.
     pub async fn get_replica_lag_secs(&self) -> Result<Option<u64>, MysqlError> {
         unimplemented!("This is a stub");
@@ -10,12 +10,21 @@
 //! Module hides the implementation details of the Facebook Mysql client library
 //! and provides API that is used in sql crate.
 
+use std::future::Future;
+use std::ops::Deref;
+use std::ops::DerefMut;
 use std::sync::Arc;
+use std::time::Duration;
 
+use anyhow::bail;
+use anyhow::Context;
 use anyhow::Error;
+use futures::future::try_join_all;
 use futures_stats::futures03::TimedFutureExt;
 use mysql_async::prelude::Queryable;
+use mysql_async::BinaryProtocol;
 use mysql_async::Conn as MysqlConnection;
+use mysql_async::Params;
 use mysql_async::Pool;
 use mysql_async::QueryResult as MysqlQueryResult;
 use mysql_async::TextProtocol;
@@ -23,11 +32,23 @@ use mysql_async::Transaction;
 use mysql_async::TxOpts;
 use stats::prelude::*;
 use time_ext::DurationExt;
+use tokio::sync::Mutex;
+use tokio::sync::OwnedMutexGuard;
 
+use crate::cancel::with_cancellation;
+use crate::cancel::CancellationToken;
+use crate::cancel::QueryCancelled;
+use crate::capabilities::ServerCapabilities;
+use crate::metrics::ConnectionMetrics;
 use crate::mysql::ConnectionStats;
 use crate::mysql::WriteResult;
+use crate::slow_query::SlowQueryLog;
+use crate::timeout::with_timeout;
+use crate::timeout::AcquireTimeout;
+use crate::timeout::QueryTimeout;
 
 type QueryResult<'a> = MysqlQueryResult<'a, 'static, TextProtocol>;
+type PreparedQueryResult<'a> = MysqlQueryResult<'a, 'static, BinaryProtocol>;
 
 /// OssConnection is a wrapper around a MySQL async Pool
 /// It provides read/write query and begin transaction API.
@@ -37,12 +58,175 @@ pub struct OssConnection {
     pub pool: Pool,
     /// Stats struct for logging performance
     pub stats: Arc<ConnectionStats>,
+    acquire_timeout: Option<Duration>,
+    statement_timeout: Option<Duration>,
+    schema_variant: Option<Arc<str>>,
+    slow_query_log: Option<SlowQueryLog>,
+    metrics: Option<ConnectionMetrics>,
+    capabilities: Option<Arc<ServerCapabilities>>,
+    session: Option<Arc<Mutex<Option<MysqlConnection>>>>,
+}
+
+/// A connection checked out by [OssConnection::get_conn], which goes back to
+/// the pool when dropped.
+pub enum PooledConnection {
+    /// A connection of the pool.
+    Pooled(MysqlConnection),
+    /// The connection the session variables were set on, see
+    /// [crate::Connection::with_session_vars], which all the queries of the
+    /// session share.
+    Session(OwnedMutexGuard<Option<MysqlConnection>>),
+}
+
+impl Deref for PooledConnection {
+    type Target = MysqlConnection;
+
+    fn deref(&self) -> &MysqlConnection {
+        match self {
+            PooledConnection::Pooled(con) => con,
+            PooledConnection::Session(con) => con.as_ref().expect("session should be open"),
+        }
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut MysqlConnection {
+        match self {
+            PooledConnection::Pooled(con) => con,
+            PooledConnection::Session(con) => con.as_mut().expect("session should be open"),
+        }
+    }
 }
 
 impl OssConnection {
     /// Creates OssConnection from a Pool object
     pub fn new(pool: Pool, stats: Arc<ConnectionStats>) -> Self {
-        Self { pool, stats }
+        Self {
+            pool,
+            stats,
+            acquire_timeout: None,
+            statement_timeout: None,
+            schema_variant: None,
+            slow_query_log: None,
+            metrics: None,
+            capabilities: None,
+            session: None,
+        }
+    }
+
+    /// Run the SQL of the given variant for the queries declaring one, e.g.
+    /// `mysql8` for a query declaring `mysql8("...")`, and their default
+    /// MySQL SQL otherwise.
+    pub fn with_schema_variant(mut self, schema_variant: impl Into<Arc<str>>) -> Self {
+        self.schema_variant = Some(schema_variant.into());
+        self
+    }
+
+    /// The schema variant of this connection, if any.
+    pub fn schema_variant(&self) -> Option<&str> {
+        self.schema_variant.as_deref()
+    }
+
+    /// Report the queries of this connection slower than the threshold of
+    /// the log, see [crate::Connection::with_slow_query_log].
+    pub fn with_slow_query_log(mut self, slow_query_log: Option<SlowQueryLog>) -> Self {
+        self.slow_query_log = slow_query_log;
+        self
+    }
+
+    /// The slow query log of this connection, if any.
+    pub fn slow_query_log(&self) -> Option<&SlowQueryLog> {
+        self.slow_query_log.as_ref()
+    }
+
+    /// Export the stats of the queries and transactions of this connection,
+    /// see [crate::Connection::with_metrics_label].
+    pub fn with_metrics(mut self, metrics: Option<ConnectionMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// The stats of this connection, if any.
+    pub fn metrics(&self) -> Option<&ConnectionMetrics> {
+        self.metrics.as_ref()
+    }
+
+    /// Cache the capabilities of the server behind this connection, see
+    /// [crate::Connection::with_probed_capabilities].
+    pub fn with_capabilities(mut self, capabilities: Option<Arc<ServerCapabilities>>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// The capabilities of the server behind this connection, if probed.
+    pub fn capabilities(&self) -> Option<&ServerCapabilities> {
+        self.capabilities.as_deref()
+    }
+
+    /// Fail with [AcquireTimeout] when no connection of the pool becomes
+    /// available within the given timeout.
+    pub fn with_acquire_timeout(mut self, acquire_timeout: Option<Duration>) -> Self {
+        self.acquire_timeout = acquire_timeout;
+        self
+    }
+
+    /// Use the given timeout for the queries that aren't given their own.
+    pub fn with_statement_timeout(mut self, statement_timeout: Option<Duration>) -> Self {
+        self.statement_timeout = statement_timeout;
+        self
+    }
+
+    /// Run all the queries on the given connection rather than on
+    /// connections of the pool, until it is taken out of the mutex.
+    pub(crate) fn with_session(&self, session: Arc<Mutex<Option<MysqlConnection>>>) -> Self {
+        Self {
+            session: Some(session),
+            ..self.clone()
+        }
+    }
+
+    /// Whether the queries run on the connection of a session, see
+    /// [crate::Connection::with_session_vars].
+    pub fn in_session(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Checks out a connection from the pool, failing with [AcquireTimeout]
+    /// if none becomes available within the acquire timeout. In a session,
+    /// waits for the connection of the session instead.
+    pub async fn get_conn(&self) -> Result<PooledConnection, Error> {
+        match &self.session {
+            None => Ok(PooledConnection::Pooled(self.checkout().await?)),
+            Some(session) => {
+                let con = session.clone().lock_owned().await;
+                if con.is_none() {
+                    bail!("The connection was used after the end of its session");
+                }
+                Ok(PooledConnection::Session(con))
+            }
+        }
+    }
+
+    /// Checks out a connection from the pool, even in a session.
+    pub(crate) async fn checkout(&self) -> Result<MysqlConnection, Error> {
+        self.acquire(OssConnection::get_conn_counted(
+            self.pool.clone(),
+            &self.stats,
+        ))
+        .await
+    }
+
+    async fn acquire<T>(
+        &self,
+        acquire: impl Future<Output = Result<T, mysql_async::Error>>,
+    ) -> Result<T, Error> {
+        match self.acquire_timeout {
+            None => Ok(acquire.await?),
+            Some(timeout) => match tokio::time::timeout(timeout, acquire).await {
+                Ok(result) => Ok(result?),
+                Err(_) => Err(AcquireTimeout { timeout }.into()),
+            },
+        }
     }
 
     /// Checks out a connection from the pool while collecting stats
@@ -70,6 +254,21 @@ impl OssConnection {
         result
     }
 
+    /// Executes a given query as a prepared statement with the given
+    /// parameters and returns the result while collecting stats
+    pub async fn exec_query_counted<'a>(
+        conn: &'a mut MysqlConnection,
+        stats: &ConnectionStats,
+        query: &'a str,
+        params: Params,
+    ) -> Result<PreparedQueryResult<'a>, mysql_async::Error> {
+        let (st, result) = conn.exec_iter(query, params).timed().await;
+        stats
+            .exec_query_ms
+            .add_value(st.completion_time.as_millis_unchecked() as i64);
+        result
+    }
+
     /// Performs a given query and returns the result as a QueryResult
     pub async fn read_query<'a>(
         &self,
@@ -79,9 +278,57 @@ impl OssConnection {
         OssConnection::raw_query_counted(conn, &self.stats, query).await
     }
 
+    /// Performs a given query as a prepared statement with the given
+    /// parameters and returns the result as a QueryResult
+    pub async fn read_prepared_query<'a>(
+        &self,
+        conn: &'a mut MysqlConnection,
+        query: &'a str,
+        params: Params,
+    ) -> Result<PreparedQueryResult<'a>, mysql_async::Error> {
+        OssConnection::exec_query_counted(conn, &self.stats, query, params).await
+    }
+
+    /// Runs a trivial query on the given connection to check that the server
+    /// is able to serve queries.
+    pub async fn ping(&self, conn: &mut MysqlConnection) -> Result<(), Error> {
+        let result = OssConnection::raw_query_counted(conn, &self.stats, "SELECT 1").await?;
+        result.drop_result().await?;
+        Ok(())
+    }
+
+    /// Checks out `connections` connections of the pool at the same time and
+    /// pings each of them, closing the ones that fail rather than returning
+    /// them to the pool, e.g. those the server has closed while they were
+    /// idle. Returns the number of connections that were closed.
+    ///
+    /// `connections` must not be above the maximum number of connections of
+    /// the pool.
+    pub async fn validate_pooled(&self, connections: usize) -> Result<usize, Error> {
+        // The connections are held until all are checked so that they are
+        // distinct.
+        let checked = try_join_all((0..connections).map(|_| async {
+            let mut con = self.checkout().await?;
+            let alive = self.ping(&mut con).await.is_ok();
+            Ok::<_, Error>((con, alive))
+        }))
+        .await?;
+
+        let mut closed = 0;
+        for (con, alive) in checked {
+            if !alive {
+                // The connection is broken, so failing to close it cleanly
+                // doesn't matter.
+                let _ = con.disconnect().await;
+                closed += 1;
+            }
+        }
+        Ok(closed)
+    }
+
     /// Performs a given query and returns the write result.
     pub async fn write_query(&self, query: String) -> Result<WriteResult, Error> {
-        let mut conn = OssConnection::get_conn_counted(self.pool.clone(), &self.stats).await?;
+        let mut conn = self.get_conn().await?;
         let result = OssConnection::raw_query_counted(&mut conn, &self.stats, &query).await?;
 
         let last_insert_id = result.last_insert_id().unwrap_or(0);
@@ -89,9 +336,89 @@ impl OssConnection {
         Ok(WriteResult::new(last_insert_id, rows_affected))
     }
 
+    /// Performs a given query as a prepared statement with the given
+    /// parameters and returns the write result. If a timeout is given, the
+    /// query is killed if it doesn't complete within it, and likewise if a
+    /// cancellation token is given and cancelled.
+    pub async fn write_prepared_query(
+        &self,
+        query: String,
+        params: Params,
+        timeout: Option<Duration>,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<WriteResult, Error> {
+        let mut conn = self.get_conn().await?;
+        let connection_id = conn.id();
+        self.run_with_cancellation(connection_id, timeout, cancellation, async {
+            let result =
+                OssConnection::exec_query_counted(&mut conn, &self.stats, &query, params).await?;
+
+            let last_insert_id = result.last_insert_id().unwrap_or(0);
+            let rows_affected = result.affected_rows();
+            Ok(WriteResult::new(last_insert_id, rows_affected))
+        })
+        .await
+    }
+
+    /// Run the query executed on the connection with the given id. If a
+    /// timeout is given, or the connection has a statement timeout, and the
+    /// query doesn't complete within it, the query is killed on the server
+    /// and this fails with [QueryTimeout].
+    pub async fn run_with_timeout<T>(
+        &self,
+        connection_id: u32,
+        timeout: Option<Duration>,
+        query: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        self.run_with_cancellation(connection_id, timeout, None, query)
+            .await
+    }
+
+    /// Same as [Self::run_with_timeout], also killing the query and failing
+    /// with [QueryCancelled] if a cancellation token is given and cancelled
+    /// before the query completes.
+    pub async fn run_with_cancellation<T>(
+        &self,
+        connection_id: u32,
+        timeout: Option<Duration>,
+        cancellation: Option<&CancellationToken>,
+        query: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let result = with_cancellation(
+            cancellation,
+            with_timeout(timeout.or(self.statement_timeout), query),
+        )
+        .await;
+        if let Err(err) = &result {
+            if err.is::<QueryTimeout>() {
+                self.kill_query(connection_id)
+                    .await
+                    .context("While killing the query that timed out")?;
+            } else if err.is::<QueryCancelled>() {
+                self.kill_query(connection_id)
+                    .await
+                    .context("While killing the query that was cancelled")?;
+            }
+        }
+        result
+    }
+
+    /// Kills the query running on the connection with the given id, leaving
+    /// the connection open.
+    pub async fn kill_query(&self, connection_id: u32) -> Result<(), Error> {
+        let mut conn = OssConnection::get_conn_counted(self.pool.clone(), &self.stats).await?;
+        let query = format!("KILL QUERY {}", connection_id);
+        let result = OssConnection::raw_query_counted(&mut conn, &self.stats, &query).await?;
+        result.drop_result().await?;
+        Ok(())
+    }
+
     /// Begins transaction and returns Transaction object.
     pub async fn begin_transaction(&self, tx_opts: TxOpts) -> Result<Transaction<'static>, Error> {
-        let tr = self.pool.start_transaction(tx_opts).await?;
+        if self.in_session() {
+            bail!("Transactions can't be started on the connection of a session");
+        }
+        let tr = self.acquire(self.pool.start_transaction(tx_opts)).await?;
 
         Ok(tr)
     }
@@ -0,0 +1,172 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! TLS configuration of the pools behind [OssConnection](super::OssConnection),
+//! e.g. to connect to MySQL servers hosted by a cloud provider.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use mysql_async::ClientIdentity;
+use mysql_async::Opts;
+use mysql_async::OptsBuilder;
+use mysql_async::SslOpts;
+use serde::Deserialize;
+
+/// How the certificate of the server is verified.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TlsVerification {
+    /// Verify that the certificate is signed by a trusted root and issued
+    /// for the host being connected to.
+    #[default]
+    Full,
+    /// Verify that the certificate is signed by a trusted root, whatever the
+    /// host it is issued for, e.g. when connecting through a proxy.
+    SkipHostname,
+    /// Accept any certificate. The connection is encrypted but not
+    /// authenticated, so this should only be used for testing.
+    None,
+}
+
+/// Certificate and private key presented to the server by the client, for
+/// servers requiring X.509 authentication.
+#[derive(Clone, Deserialize, PartialEq, Eq)]
+pub struct TlsClientIdentity {
+    /// Path of the PKCS #12 archive holding the certificate and its private
+    /// key, the format expected by the TLS backend of the client.
+    pub pkcs12_path: PathBuf,
+    /// Password of the archive, if any.
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+impl fmt::Debug for TlsClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsClientIdentity")
+            .field("pkcs12_path", &self.pkcs12_path)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+/// TLS configuration of a pool, requiring every connection of the pool to
+/// use TLS. It can be built with the `with_*` methods or deserialized, e.g.
+/// as part of a [ConnectionConfig](crate::config::ConnectionConfig).
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// Path of the certificate of the root CA the certificate of the server
+    /// must be signed by, in PEM or DER format, in addition to the roots
+    /// trusted by the system.
+    pub root_cert_path: Option<PathBuf>,
+    /// Identity presented to the server, if it authenticates clients.
+    pub client_identity: Option<TlsClientIdentity>,
+    /// How the certificate of the server is verified.
+    pub verification: TlsVerification,
+}
+
+impl TlsConfig {
+    /// Require TLS, verifying the certificate of the server against the
+    /// roots trusted by the system.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also trust the root CA certificate at `path`, e.g. the one published
+    /// by the cloud provider hosting the server.
+    pub fn with_root_cert(mut self, path: impl Into<PathBuf>) -> Self {
+        self.root_cert_path = Some(path.into());
+        self
+    }
+
+    /// Present the certificate and private key of the PKCS #12 archive at
+    /// `path` to the server.
+    pub fn with_client_identity(
+        mut self,
+        path: impl Into<PathBuf>,
+        password: Option<String>,
+    ) -> Self {
+        self.client_identity = Some(TlsClientIdentity {
+            pkcs12_path: path.into(),
+            password,
+        });
+        self
+    }
+
+    /// Set how the certificate of the server is verified.
+    pub fn with_verification(mut self, verification: TlsVerification) -> Self {
+        self.verification = verification;
+        self
+    }
+
+    /// Return the TLS options of the client for this configuration.
+    pub fn ssl_opts(&self) -> SslOpts {
+        let mut ssl_opts = SslOpts::default()
+            .with_root_cert_path(self.root_cert_path.clone())
+            .with_danger_skip_domain_validation(self.verification != TlsVerification::Full)
+            .with_danger_accept_invalid_certs(self.verification == TlsVerification::None);
+        if let Some(identity) = &self.client_identity {
+            let mut client_identity = ClientIdentity::new(identity.pkcs12_path.clone());
+            if let Some(password) = &identity.password {
+                client_identity = client_identity.with_password(password.clone());
+            }
+            ssl_opts = ssl_opts.with_client_identity(Some(client_identity));
+        }
+        ssl_opts
+    }
+
+    /// Return the options with TLS required as configured, the other options
+    /// being left as they are.
+    pub fn apply_to_opts(&self, opts: Opts) -> Opts {
+        OptsBuilder::from_opts(opts)
+            .ssl_opts(self.ssl_opts())
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apply_to_opts() {
+        let opts = Opts::from_url("mysql://localhost/db").unwrap();
+        assert_eq!(opts.ssl_opts(), None);
+
+        let tls = TlsConfig::new()
+            .with_root_cert("/etc/ssl/cloud-ca.pem")
+            .with_verification(TlsVerification::SkipHostname);
+        let opts = tls.apply_to_opts(opts);
+        let ssl_opts = opts.ssl_opts().unwrap();
+        assert!(ssl_opts.skip_domain_validation());
+        assert!(!ssl_opts.accept_invalid_certs());
+        assert_eq!(opts.db_name(), Some("db"));
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let tls: TlsConfig = serde_json::from_str(
+            r#"{
+                "root_cert_path": "/etc/ssl/cloud-ca.pem",
+                "client_identity": {"pkcs12_path": "/etc/ssl/client.p12", "password": "secret"},
+                "verification": "none"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            tls,
+            TlsConfig::new()
+                .with_root_cert("/etc/ssl/cloud-ca.pem")
+                .with_client_identity("/etc/ssl/client.p12", Some("secret".to_owned()))
+                .with_verification(TlsVerification::None)
+        );
+        assert!(!format!("{:?}", tls).contains("secret"));
+    }
+}
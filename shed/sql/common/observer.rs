@@ -0,0 +1,227 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Observers called for every query made with `queries!` and every
+//! transaction operation, to wire logging, stats or tracing around them.
+//!
+//! Observers are registered for the whole process, as connections and
+//! transactions are plain enums over the backends with nowhere to hold them.
+//! Queries made with `query_stream` are not observed.
+
+use std::future::Future;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Error;
+
+use crate::metrics::ConnectionMetrics;
+use crate::slow_query::report_if_slow;
+use crate::slow_query::slow_query_config;
+use crate::slow_query::QueryTelemetry;
+use crate::slow_query::SlowQuery;
+use crate::slow_query::SlowQueryEvent;
+use crate::slow_query::SlowQueryLog;
+
+static OBSERVERS: RwLock<Vec<Arc<dyn QueryObserver>>> = RwLock::new(Vec::new());
+
+/// Checked before taking the lock, so that queries don't pay for observers
+/// when there are none.
+static HAS_OBSERVERS: AtomicBool = AtomicBool::new(false);
+
+/// Hooks called once queries and transaction operations have completed,
+/// whether they succeeded or not. They are called inline, so they should be
+/// quick and not block.
+pub trait QueryObserver: Send + Sync {
+    /// Called once a query has completed.
+    fn query_completed(&self, _event: &QueryEvent<'_>) {}
+
+    /// Called once a transaction operation has completed.
+    fn transaction_completed(&self, _event: &TransactionEvent<'_>) {}
+
+    /// Called after `query_completed` for queries slower than the threshold
+    /// set with [set_slow_query_config](crate::slow_query::set_slow_query_config),
+    /// once their plan has been explained if configured to.
+    fn slow_query(&self, _event: &SlowQueryEvent<'_>) {}
+}
+
+/// A completed query.
+#[derive(Debug)]
+pub struct QueryEvent<'a> {
+    /// Name of the query, as given to `queries!`.
+    pub name: &'a str,
+    /// The SQL sent to the backend, without any comment.
+    pub sql: &'a str,
+    /// Whether the query ran in a transaction.
+    pub in_transaction: bool,
+    /// How long the query took.
+    pub duration: Duration,
+    /// The number of rows returned by a read query or affected by a write
+    /// query, if it succeeded.
+    pub rows: Option<u64>,
+    /// The error of the query, if it failed.
+    pub error: Option<&'a Error>,
+}
+
+/// The operations of a transaction reported to observers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionOperation {
+    /// Starting the transaction.
+    Begin,
+    /// Committing the transaction.
+    Commit,
+    /// Rolling the transaction back explicitly.
+    Rollback,
+}
+
+/// A completed transaction operation.
+#[derive(Debug)]
+pub struct TransactionEvent<'a> {
+    /// The operation.
+    pub operation: TransactionOperation,
+    /// How long the operation took.
+    pub duration: Duration,
+    /// The error of the operation, if it failed.
+    pub error: Option<&'a Error>,
+}
+
+/// Register an observer for all the queries and transactions of the process.
+pub fn register_query_observer(observer: Arc<dyn QueryObserver>) {
+    OBSERVERS.write().expect("poisoned lock").push(observer);
+    HAS_OBSERVERS.store(true, Ordering::Release);
+}
+
+fn observers() -> Option<Vec<Arc<dyn QueryObserver>>> {
+    if !HAS_OBSERVERS.load(Ordering::Acquire) {
+        return None;
+    }
+    Some(OBSERVERS.read().expect("poisoned lock").clone())
+}
+
+/// What a query is reported to besides the observers, taken from its
+/// connection or transaction.
+///
+/// This should never be used directly, it is made public so that internal macros can make use of it
+#[doc(hidden)]
+pub struct QueryContext<'a> {
+    /// The comment of the query, reported to the slow query log.
+    pub comment: Option<&'a str>,
+    /// The slow query log of the connection.
+    pub slow_query_log: Option<&'a SlowQueryLog>,
+    /// The stats of the connection.
+    pub metrics: Option<ConnectionMetrics>,
+}
+
+/// Run the query and report it to the observers, if any, and as a slow query
+/// if it is, including to the slow query log of its connection, and count it
+/// in the stats of its connection. `sql` is only called when there are
+/// observers or slow queries are reported, so that queries aren't rendered
+/// needlessly.
+#[doc(hidden)]
+pub async fn observe_query<T, F>(
+    name: &str,
+    in_transaction: bool,
+    context: QueryContext<'_>,
+    sql: impl FnOnce() -> String,
+    rows: impl FnOnce(&T) -> u64,
+    query: F,
+) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    let QueryContext {
+        comment,
+        slow_query_log,
+        metrics,
+    } = context;
+    let query = async {
+        #[cfg(feature = "failpoints")]
+        crate::failpoints::inject(&crate::failpoints::query_failpoint(name)).await?;
+        let result = query.await;
+        if let Some(metrics) = &metrics {
+            metrics.query_completed(result.is_err());
+        }
+        result
+    };
+    let observers = observers();
+    let slow_query_config = slow_query_config();
+    if observers.is_none() && slow_query_config.is_none() && slow_query_log.is_none() {
+        return query.await;
+    }
+    let start = Instant::now();
+    let result = query.await;
+    let duration = start.elapsed();
+    let slow_query_log = slow_query_log.filter(|log| duration > log.threshold());
+    if observers.is_none() && slow_query_config.is_none() && slow_query_log.is_none() {
+        return result;
+    }
+    let sql = sql();
+    let rows = result.as_ref().ok().map(rows);
+    if let Some(observers) = &observers {
+        let event = QueryEvent {
+            name,
+            sql: &sql,
+            in_transaction,
+            duration,
+            rows,
+            error: result.as_ref().err(),
+        };
+        for observer in observers {
+            observer.query_completed(&event);
+        }
+    }
+    if let Some(log) = slow_query_log {
+        log.report(&SlowQuery {
+            name,
+            comment,
+            duration,
+            telemetry: QueryTelemetry {
+                sql: &sql,
+                threshold: log.threshold(),
+                rows,
+                error: result.as_ref().err(),
+            },
+        });
+    }
+    if let Some(config) = slow_query_config {
+        report_if_slow(&config, observers, name, sql, in_transaction, duration);
+    }
+    result
+}
+
+pub(crate) async fn observe_transaction<T, F>(
+    operation: TransactionOperation,
+    future: F,
+) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    #[cfg(feature = "failpoints")]
+    let future = async {
+        crate::failpoints::inject(crate::failpoints::transaction_failpoint(operation)).await?;
+        future.await
+    };
+    let Some(observers) = observers() else {
+        return future.await;
+    };
+    let start = Instant::now();
+    let result = future.await;
+    let event = TransactionEvent {
+        operation,
+        duration: start.elapsed(),
+        error: result.as_ref().err(),
+    };
+    for observer in observers {
+        observer.transaction_completed(&event);
+    }
+    result
+}
@@ -0,0 +1,90 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module with the health check query shared by all backends.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::format_err;
+use anyhow::Error;
+use stats::prelude::*;
+use time_ext::DurationExt;
+
+use crate::sqlite::SqliteQueryType;
+use crate::Connection;
+
+define_stats! {
+    prefix = "sql.ping";
+    latency_ms: dynamic_histogram("{}.latency_ms", (backend: &'static str); 10, 0, 1_000, Average, Count; P 50; P 95; P 99),
+    failures: dynamic_timeseries("{}.failures", (backend: &'static str); Rate, Sum),
+    timeouts: dynamic_timeseries("{}.timeouts", (backend: &'static str); Rate, Sum),
+}
+
+impl Connection {
+    /// Check that the database behind this connection is able to serve
+    /// queries by running the cheapest statement supported by the backend,
+    /// returning how long it took. Fails if the statement fails or doesn't
+    /// complete within `timeout`.
+    ///
+    /// Latency, failures and timeouts are exported as `sql.ping.<backend>.*`
+    /// stats, so pool health checkers and readiness endpoints should use this
+    /// method rather than issuing their own queries.
+    pub async fn ping(&self, timeout: Duration) -> Result<Duration, Error> {
+        let backend = self.backend_name();
+        let start = Instant::now();
+
+        let result = match tokio::time::timeout(timeout, self.ping_query()).await {
+            Ok(result) => result,
+            Err(_) => {
+                STATS::timeouts.add_value(1, (backend,));
+                Err(format_err!(
+                    "Ping of {:?} timed out after {:?}",
+                    self,
+                    timeout
+                ))
+            }
+        };
+        let latency = start.elapsed();
+
+        STATS::latency_ms.add_value(latency.as_millis_unchecked() as i64, (backend,));
+        if result.is_err() {
+            STATS::failures.add_value(1, (backend,));
+        }
+
+        result.map(|()| latency)
+    }
+
+    pub(crate) async fn ping_query(&self) -> Result<(), Error> {
+        match self {
+            Connection::Sqlite(multithread_con) => {
+                let con = multithread_con
+                    .acquire_sqlite_connection(SqliteQueryType::Read)
+                    .await?;
+                con.query_row("PRAGMA schema_version", [], |_| Ok(()))?;
+            }
+            Connection::Mysql(conn) => {
+                conn.ping().await?;
+            }
+            Connection::OssMysql(conn) => {
+                let mut con = conn.get_conn().await?;
+                conn.ping(&mut con).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn backend_name(&self) -> &'static str {
+        match self {
+            Connection::Sqlite(..) => "sqlite",
+            Connection::Mysql(..) => "mysql",
+            Connection::OssMysql(..) => "oss_mysql",
+        }
+    }
+}
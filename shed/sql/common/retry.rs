@@ -0,0 +1,387 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Retrying queries and transactions that failed because they conflicted
+//! with another transaction: a deadlock or lock wait timeout on MySQL, or a
+//! busy or locked database on SQLite. Such failures are transient and the
+//! query or transaction should simply be run again.
+//!
+//! A commit failing because the connection dropped after COMMIT was sent
+//! may have been applied, so the transaction can't safely be run again.
+//! [Transaction::commit_with_retry] finds out whether it was.
+
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::Error;
+use rand::Rng;
+use rusqlite::OptionalExtension;
+use stats::prelude::*;
+
+use crate::codes::SQL_CONFLICT;
+use crate::error::SqlError;
+use crate::sqlite::SqliteQueryType;
+use crate::transaction::Transaction;
+use crate::Connection;
+
+define_stats! {
+    prefix = "sql.retry";
+    retries: timeseries(Rate, Sum),
+    exhausted: timeseries(Rate, Sum),
+    applied_failed_commits: timeseries(Rate, Sum),
+}
+
+/// How to retry queries and transactions failing with a conflict or another
+/// transient error, see [is_retryable]. Retries are delayed with an exponential backoff.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every further retry.
+    pub base_delay: Duration,
+    /// Upper bound of the delay between retries.
+    pub max_delay: Duration,
+    /// How the delays are randomized.
+    pub jitter: Jitter,
+}
+
+/// How the delays between retries are randomized, so that clients failing at
+/// the same time, e.g. on the same conflict or outage, don't all retry at the
+/// same time again. The strategies are those of
+/// <https://aws.amazon.com/blogs/architecture/exponential-backoff-and-jitter/>.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Jitter {
+    /// The delay is the exponential backoff.
+    #[default]
+    None,
+    /// The delay is random, up to the exponential backoff.
+    Full,
+    /// The delay is half the exponential backoff, plus a random delay up to
+    /// the other half.
+    Equal,
+    /// The delay is random, between `base_delay` and three times the previous
+    /// delay. It grows with the previous delay rather than with the number of
+    /// retries, which spreads the retries of many clients the most.
+    Decorrelated,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(1),
+            jitter: Jitter::None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Run `attempt` until it succeeds, fails with an error that is not
+    /// retryable, or `max_attempts` attempts have failed, returning the result
+    /// of the last attempt.
+    ///
+    /// `attempt` should run the whole query or transaction, from the
+    /// beginning of the transaction to its commit, as a conflict aborts the
+    /// transaction it happens in. Retries and exhausted attempts are exported
+    /// as `sql.retry.*` stats.
+    pub async fn run<T, F, Fut>(&self, mut attempt: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut attempts = 1;
+        let mut delay = self.base_delay;
+        loop {
+            let err = match attempt().await {
+                Ok(result) => return Ok(result),
+                Err(err) => err,
+            };
+            if !is_retryable(&err) {
+                return Err(err);
+            }
+            if attempts >= self.max_attempts {
+                STATS::exhausted.add_value(1);
+                return Err(err.context(format!("Error persisted after {} attempts", attempts)));
+            }
+            STATS::retries.add_value(1);
+            delay = self.jittered_delay(attempts, delay);
+            tokio::time::sleep(delay).await;
+            attempts += 1;
+        }
+    }
+
+    /// The delay before the given retry, starting from 1.
+    fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// The delay before the given retry randomized according to `jitter`,
+    /// given the delay before the previous one, `base_delay` for the first.
+    fn jittered_delay(&self, retry: u32, previous: Duration) -> Duration {
+        let delay = self.delay(retry);
+        let mut rng = rand::thread_rng();
+        match self.jitter {
+            Jitter::None => delay,
+            Jitter::Full => rng.gen_range(Duration::ZERO..=delay),
+            Jitter::Equal => delay / 2 + rng.gen_range(Duration::ZERO..=delay - delay / 2),
+            Jitter::Decorrelated => {
+                let upper = previous.saturating_mul(3).max(self.base_delay);
+                rng.gen_range(self.base_delay..=upper).min(self.max_delay)
+            }
+        }
+    }
+}
+
+/// Whether the error, or any error it was caused by, is a conflict with
+/// another transaction that can be retried.
+///
+/// Only errors of the OssMysql and SQLite backends are recognized, see
+/// [crate::codes::classify].
+pub fn is_conflict(err: &Error) -> bool {
+    err.chain()
+        .any(|cause| crate::codes::classify(cause) == Some(SQL_CONFLICT))
+}
+
+/// Whether the error can be retried: it is a conflict, see [is_conflict], or
+/// its code is [ErrorCategory::Transient](error_codes::ErrorCategory::Transient)
+/// in a domain registered with [error_codes::register].
+pub fn is_retryable(err: &Error) -> bool {
+    is_conflict(err) || error_codes::is_transient(err)
+}
+
+/// How [Transaction::commit_with_retry] finds out whether a failed commit
+/// was applied.
+#[derive(Clone, Debug)]
+pub struct CommitRetryPolicy {
+    /// Connection to the database the transactions run on, on which the
+    /// idempotency tokens are looked up. On MySQL, it must read from the
+    /// master, as a replica may not have the token yet.
+    pub connection: Connection,
+    /// Table the idempotency tokens are inserted into, whose primary key is
+    /// a `token` column of a string type of at least 32 characters. Its rows
+    /// are only needed until the commits they were inserted by return, so
+    /// they can be purged regularly.
+    pub table: String,
+    /// How to retry the lookup of the token while it fails with a transient
+    /// error.
+    pub retry: RetryPolicy,
+}
+
+impl CommitRetryPolicy {
+    /// Look up the idempotency tokens inserted into `table` on `connection`,
+    /// with the default retry policy.
+    pub fn new(connection: Connection, table: impl Into<String>) -> Self {
+        Self {
+            connection,
+            table: table.into(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    async fn token_exists(&self, token: &str) -> Result<bool, Error> {
+        // Tokens are hexadecimal, so they can be put in the query as they are.
+        let query = format!("SELECT 1 FROM {} WHERE token = '{}'", self.table, token);
+        let exists = match &self.connection {
+            Connection::Sqlite(con) => con
+                .run_query(SqliteQueryType::Read, None, move |con| {
+                    Ok(con.query_row(&query, [], |_| Ok(())).optional()?)
+                })
+                .await?
+                .is_some(),
+            Connection::Mysql(conn) => {
+                let rows: Vec<(i64,)> = conn.read_query(query).await?;
+                !rows.is_empty()
+            }
+            Connection::OssMysql(conn) => {
+                let mut con = conn.get_conn().await?;
+                let rows: Vec<(i64,)> = conn
+                    .read_query(&mut con, &query)
+                    .await?
+                    .collect_and_drop()
+                    .await?;
+                !rows.is_empty()
+            }
+        };
+        Ok(exists)
+    }
+}
+
+impl Transaction {
+    /// Commit the transaction, finding out whether the commit was applied
+    /// when it fails, e.g. because the connection dropped after COMMIT was
+    /// sent. Returns `Ok` if and only if the transaction was committed, so
+    /// that the callers retrying failed transactions don't apply them twice.
+    ///
+    /// A random idempotency token is inserted into the table of the policy
+    /// as part of the transaction, and looked up on the connection of the
+    /// policy if the commit fails, retrying the lookup according to the
+    /// policy. The commit is reported as failed if the token isn't found,
+    /// and as failed with the error of the lookup if it kept failing, in
+    /// which case whether the commit was applied is unknown.
+    pub async fn commit_with_retry(mut self, policy: &CommitRetryPolicy) -> Result<(), SqlError> {
+        let token = format!("{:032x}", rand::random::<u128>());
+        self.execute_statement(&format!(
+            "INSERT INTO {} (token) VALUES ('{}')",
+            policy.table, token
+        ))
+        .await
+        .map_err(SqlError::from)?;
+        let err = match self.commit().await {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        match policy.retry.run(|| policy.token_exists(&token)).await {
+            Ok(true) => {
+                STATS::applied_failed_commits.add_value(1);
+                Ok(())
+            }
+            Ok(false) => Err(err),
+            Err(lookup_err) => Err(SqlError::from(lookup_err.context(format!(
+                "Commit failed with {:#}, and whether it was applied is unknown",
+                err
+            )))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicU32;
+    use std::sync::atomic::Ordering;
+
+    use anyhow::format_err;
+    use mysql_async::ServerError;
+
+    use super::*;
+
+    const ER_LOCK_DEADLOCK: u16 = 1213;
+    const ER_LOCK_WAIT_TIMEOUT: u16 = 1205;
+
+    fn mysql_error(code: u16) -> Error {
+        mysql_async::Error::Server(ServerError {
+            code,
+            message: "test".to_owned(),
+            state: "HY000".to_owned(),
+        })
+        .into()
+    }
+
+    fn sqlite_error(code: std::os::raw::c_int) -> Error {
+        rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(code), None).into()
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&mysql_error(ER_LOCK_DEADLOCK)));
+        assert!(is_retryable(
+            &mysql_error(ER_LOCK_WAIT_TIMEOUT).context("While running a query")
+        ));
+        assert!(!is_retryable(&mysql_error(1062)));
+        assert!(is_retryable(&sqlite_error(rusqlite::ffi::SQLITE_BUSY)));
+        assert!(is_retryable(&sqlite_error(rusqlite::ffi::SQLITE_LOCKED)));
+        assert!(!is_retryable(&sqlite_error(
+            rusqlite::ffi::SQLITE_CONSTRAINT
+        )));
+        assert!(!is_retryable(&format_err!("deadlock")));
+
+        let timeout = Error::from(crate::timeout::QueryTimeout {
+            timeout: Duration::from_secs(1),
+        });
+        assert!(!is_conflict(&timeout));
+        error_codes::register(&crate::codes::ERROR_DOMAIN).unwrap();
+        assert!(is_retryable(&timeout));
+        assert!(!is_retryable(&crate::cancel::QueryCancelled.into()));
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter: Jitter::None,
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(400));
+        assert_eq!(policy.delay(4), Duration::from_millis(500));
+        assert_eq!(policy.delay(100), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_jitter() {
+        let mut policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter: Jitter::None,
+        };
+        let previous = Duration::from_millis(100);
+        assert_eq!(
+            policy.jittered_delay(3, previous),
+            Duration::from_millis(400)
+        );
+        for _ in 0..100 {
+            policy.jitter = Jitter::Full;
+            assert!(policy.jittered_delay(3, previous) <= Duration::from_millis(400));
+
+            policy.jitter = Jitter::Equal;
+            let delay = policy.jittered_delay(3, previous);
+            assert!(delay >= Duration::from_millis(200));
+            assert!(delay <= Duration::from_millis(400));
+
+            policy.jitter = Jitter::Decorrelated;
+            let delay = policy.jittered_delay(3, previous);
+            assert!(delay >= Duration::from_millis(100));
+            assert!(delay <= Duration::from_millis(300));
+            let delay = policy.jittered_delay(3, Duration::from_secs(1));
+            assert!(delay >= Duration::from_millis(100));
+            assert!(delay <= Duration::from_millis(500));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        tokio::time::pause();
+        let policy = RetryPolicy::default();
+
+        let attempts = AtomicU32::new(0);
+        let result = policy
+            .run(|| async {
+                match attempts.fetch_add(1, Ordering::Relaxed) {
+                    0 => Err(mysql_error(ER_LOCK_DEADLOCK)),
+                    n => Ok(n),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 1);
+
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = policy
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                Err(mysql_error(ER_LOCK_DEADLOCK))
+            })
+            .await;
+        assert!(is_retryable(&result.unwrap_err()));
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = policy
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                Err(format_err!("not a conflict"))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,183 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Session variables set for the duration of a closure, e.g. a stricter
+//! `sql_mode`, on a connection that is reset before it goes back to the
+//! pool, so that the variables never leak to the other users of the pool.
+
+use std::future::Future;
+use std::sync::Arc;
+
+use anyhow::bail;
+use anyhow::Error;
+use mysql_async::prelude::Queryable;
+use mysql_async::Conn as MysqlConnection;
+use mysql_async::Value;
+use rusqlite::types::Value as SqliteValue;
+use rusqlite::OptionalExtension;
+use tokio::sync::Mutex;
+
+use crate::sqlite::SqliteQueryType;
+use crate::Connection;
+
+impl Connection {
+    /// Set the session variables `vars`, given as name and value, and run
+    /// `f` with a connection on which they are set.
+    ///
+    /// On MySQL, the queries run by `f` share a single connection of the
+    /// pool, which is reset once `f` is done, or dropped before completing,
+    /// and before it goes back to the pool, clearing every variable of the
+    /// session. A connection that can't be reset is closed instead. The
+    /// values that are integers are set as integers, the others as strings.
+    /// Transactions can't be started on the connection given to `f`.
+    ///
+    /// On sqlite, the variables are pragmas, set on the connection for
+    /// writes and restored to their previous values once `f` is done. The
+    /// read-only connections of a database in WAL mode are left untouched.
+    pub async fn with_session_vars<T, F, Fut>(
+        &self,
+        vars: &[(&str, &str)],
+        f: F,
+    ) -> Result<T, Error>
+    where
+        F: FnOnce(Connection) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        for (name, _) in vars {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                bail!("Invalid session variable name {:?}", name);
+            }
+        }
+        match self {
+            Connection::Sqlite(con) => {
+                let previous = con
+                    .run_query(SqliteQueryType::Write, None, |con| {
+                        let mut previous = Vec::new();
+                        for (name, value) in vars {
+                            let value_before = con
+                                .query_row(&format!("PRAGMA {}", name), [], |row| {
+                                    row.get::<_, SqliteValue>(0)
+                                })
+                                .optional()?;
+                            con.execute_batch(&format!(
+                                "PRAGMA {} = {}",
+                                name,
+                                sqlite_literal(value)
+                            ))?;
+                            if let Some(value_before) = value_before {
+                                previous.push((name.to_string(), value_before));
+                            }
+                        }
+                        Ok(previous)
+                    })
+                    .await?;
+                let result = f(self.clone()).await;
+                let restored = con
+                    .run_query(SqliteQueryType::Write, None, |con| {
+                        for (name, value) in previous.iter().rev() {
+                            let value = match value {
+                                SqliteValue::Integer(value) => value.to_string(),
+                                SqliteValue::Real(value) => value.to_string(),
+                                SqliteValue::Text(value) => sqlite_literal(value),
+                                SqliteValue::Null | SqliteValue::Blob(_) => continue,
+                            };
+                            con.execute_batch(&format!("PRAGMA {} = {}", name, value))?;
+                        }
+                        Ok(())
+                    })
+                    .await;
+                let result = result?;
+                restored?;
+                Ok(result)
+            }
+            Connection::Mysql(_) => {
+                bail!("Session variables are not supported by this client")
+            }
+            Connection::OssMysql(conn) => {
+                if conn.in_session() {
+                    bail!("Sessions can't be nested");
+                }
+                let session = MysqlSession(Arc::new(Mutex::new(Some(conn.checkout().await?))));
+                let result = match session.set_vars(vars).await {
+                    Ok(()) => f(Connection::OssMysql(conn.with_session(session.0.clone()))).await,
+                    Err(err) => Err(err),
+                };
+                session.close().await;
+                result
+            }
+        }
+    }
+}
+
+/// Quote a value as an SQLite literal, leaving integers as they are.
+fn sqlite_literal(value: &str) -> String {
+    if value.parse::<i64>().is_ok() {
+        value.to_owned()
+    } else {
+        format!("'{}'", value.replace('\'', "''"))
+    }
+}
+
+/// The connection of a session on MySQL, taken out of the mutex once the
+/// session ends so that the clones of the connection given to the closure
+/// can't use it anymore.
+struct MysqlSession(Arc<Mutex<Option<MysqlConnection>>>);
+
+impl MysqlSession {
+    async fn set_vars(&self, vars: &[(&str, &str)]) -> Result<(), Error> {
+        let mut con = self.0.lock().await;
+        let con = con.as_mut().expect("session should be open");
+        for (name, value) in vars {
+            let value = match value.parse::<i64>() {
+                Ok(value) => Value::from(value),
+                Err(_) => Value::from(*value),
+            };
+            con.exec_drop(format!("SET SESSION {} = ?", name), (value,))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// End the session, waiting for the queries still running on the
+    /// connection.
+    async fn close(&self) {
+        let con = self.0.lock().await.take();
+        if let Some(con) = con {
+            reset_or_disconnect(con).await;
+        }
+    }
+}
+
+impl Drop for MysqlSession {
+    fn drop(&mut self) {
+        // The session was dropped before it was closed, e.g. because the
+        // closure timed out, and a query may still hold the connection.
+        if matches!(self.0.try_lock(), Ok(con) if con.is_none()) {
+            return;
+        }
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let session = self.0.clone();
+            handle.spawn(async move {
+                let con = session.lock().await.take();
+                if let Some(con) = con {
+                    reset_or_disconnect(con).await;
+                }
+            });
+        }
+    }
+}
+
+/// Reset the session of the connection before it goes back to the pool, or
+/// close the connection if it can't be reset.
+async fn reset_or_disconnect(mut con: MysqlConnection) {
+    if con.reset().await.is_err() {
+        // The connection is dropped either way.
+        let _ = con.disconnect().await;
+    }
+}
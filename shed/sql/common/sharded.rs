@@ -0,0 +1,132 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module running queries against every shard of [SqlShardedConnections].
+
+use std::collections::BTreeMap;
+use std::future::Future;
+
+use anyhow::Error;
+use futures::stream;
+use futures::StreamExt;
+use futures_ext::BufferedParams;
+use futures_ext::FbStreamExt;
+
+use crate::SqlConnections;
+use crate::SqlShardedConnections;
+
+impl SqlShardedConnections {
+    /// Number of shards.
+    pub fn num_shards(&self) -> usize {
+        self.read_connections.len()
+    }
+
+    /// Return the connections of the shard with the given id, if it exists.
+    pub fn shard(&self, shard_id: usize) -> Option<SqlConnections> {
+        Some(SqlConnections {
+            write_connection: self.write_connections.get(shard_id)?.clone(),
+            read_connection: self.read_connections.get(shard_id)?.clone(),
+            read_master_connection: self.read_master_connections.get(shard_id)?.clone(),
+        })
+    }
+
+    /// Run `query` against every shard, with at most `concurrency` shards
+    /// queried at a time, and return the result of each shard keyed by shard
+    /// id. `query` is given the id and the connections of the shard, and the
+    /// failure of a shard doesn't prevent the other shards from being
+    /// queried.
+    pub async fn query_all_shards<T, F, Fut>(
+        &self,
+        query: F,
+        concurrency: usize,
+    ) -> BTreeMap<usize, Result<T, Error>>
+    where
+        F: Fn(usize, SqlConnections) -> Fut,
+        Fut: Future<Output = Result<T, Error>> + Send,
+    {
+        let concurrency = concurrency.max(1);
+        let params = BufferedParams {
+            weight_limit: concurrency as u64,
+            buffer_size: concurrency,
+        };
+        // The queries are created upfront so that the stream doesn't borrow
+        // `query`, which isn't required to be Sync.
+        let queries: Vec<_> = (0..self.num_shards())
+            .map(|shard_id| {
+                let shard = self.shard(shard_id).expect("shard id should be in range");
+                let result = query(shard_id, shard);
+                (async move { (shard_id, result.await) }, 1)
+            })
+            .collect();
+        stream::iter(queries)
+            .buffered_weight_limited(params)
+            .collect()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use anyhow::format_err;
+    use vec1::vec1;
+
+    use super::*;
+    use crate::Connection;
+
+    fn sqlite() -> Connection {
+        Connection::with_sqlite(rusqlite::Connection::open_in_memory().unwrap())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_query_all_shards() {
+        let connections = SqlShardedConnections::from(vec1![
+            SqlConnections::new_single(sqlite()),
+            SqlConnections::new_single(sqlite()),
+            SqlConnections::new_single(sqlite()),
+        ]);
+        assert_eq!(connections.num_shards(), 3);
+        assert!(connections.shard(3).is_none());
+
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let results = connections
+            .query_all_shards(
+                |shard_id, _connections| {
+                    let running = &running;
+                    let max_running = &max_running;
+                    async move {
+                        let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_running.fetch_max(now_running, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        if shard_id == 1 {
+                            Err(format_err!("shard {} failed", shard_id))
+                        } else {
+                            Ok(shard_id * 10)
+                        }
+                    }
+                },
+                2,
+            )
+            .await;
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+        assert_eq!(results.keys().copied().collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(results[&0].as_ref().unwrap(), &0);
+        assert_eq!(
+            results[&1].as_ref().unwrap_err().to_string(),
+            "shard 1 failed"
+        );
+        assert_eq!(results[&2].as_ref().unwrap(), &20);
+    }
+}
@@ -0,0 +1,329 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Reporting the queries made with `queries!` that take longer than a
+//! threshold, with the plan of the query as explained by a replica, so that
+//! production slowness comes with the context needed to investigate it.
+//!
+//! Like observers, the configuration applies to the whole process, see
+//! [set_slow_query_config]. Slow queries are counted in the
+//! `sql.slow_queries.<query>.count` stats and reported to the
+//! [QueryObserver::slow_query] hook of the observers.
+//!
+//! A connection can also have its own threshold and callback, see
+//! [Connection::with_slow_query_log], e.g. to log the slow queries of a
+//! single database with slog or tracing.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use anyhow::Error;
+use stats::prelude::*;
+
+use crate::observer::QueryObserver;
+use crate::sqlite::SqliteQueryType;
+use crate::Connection;
+
+define_stats! {
+    prefix = "sql.slow_queries";
+    count: dynamic_timeseries("{}.count", (query: String); Rate, Sum),
+    explain_failures: timeseries(Rate, Sum),
+}
+
+static CONFIG: RwLock<Option<Arc<SlowQueryConfig>>> = RwLock::new(None);
+
+/// Checked before taking the lock, so that queries don't pay for the
+/// configuration when there is none.
+static HAS_CONFIG: AtomicBool = AtomicBool::new(false);
+
+/// Configuration of the slow query reporting.
+#[derive(Clone, Debug)]
+pub struct SlowQueryConfig {
+    threshold: Duration,
+    query_thresholds: HashMap<String, Duration>,
+    explain_connection: Option<Connection>,
+}
+
+impl SlowQueryConfig {
+    /// Report the queries that take longer than `threshold`.
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            query_thresholds: HashMap::new(),
+            explain_connection: None,
+        }
+    }
+
+    /// Use `threshold` for the queries named `name` in `queries!`, instead
+    /// of the one given to [Self::new], e.g. for queries that are expected
+    /// to be slow. The threshold applies to all the queries with that name.
+    pub fn with_query_threshold(mut self, name: impl Into<String>, threshold: Duration) -> Self {
+        self.query_thresholds.insert(name.into(), threshold);
+        self
+    }
+
+    /// Explain the plan of slow queries by running `EXPLAIN` for them on
+    /// `connection`, which should be a replica of the database they ran on.
+    /// The plan is then reported in the background, without delaying the
+    /// result of the query.
+    pub fn with_explain_connection(mut self, connection: Connection) -> Self {
+        self.explain_connection = Some(connection);
+        self
+    }
+
+    /// Return the threshold above which the queries named `name` are slow.
+    pub fn threshold(&self, name: &str) -> Duration {
+        self.query_thresholds
+            .get(name)
+            .copied()
+            .unwrap_or(self.threshold)
+    }
+}
+
+/// A query that took longer than its threshold.
+#[derive(Debug)]
+pub struct SlowQueryEvent<'a> {
+    /// Name of the query, as given to `queries!`.
+    pub name: &'a str,
+    /// The SQL sent to the backend, without any comment.
+    pub sql: &'a str,
+    /// Whether the query ran in a transaction.
+    pub in_transaction: bool,
+    /// How long the query took.
+    pub duration: Duration,
+    /// The threshold the query exceeded.
+    pub threshold: Duration,
+    /// The plan of the query, if an explain connection is configured and
+    /// explaining the query succeeded.
+    pub plan: Option<&'a str>,
+    /// The error of explaining the query, if it failed.
+    pub explain_error: Option<&'a Error>,
+}
+
+/// Threshold and callback of the slow query log of a connection, see
+/// [Connection::with_slow_query_log].
+#[derive(Clone)]
+pub struct SlowQueryLog {
+    threshold: Duration,
+    callback: Arc<dyn Fn(&SlowQuery<'_>) + Send + Sync>,
+}
+
+impl SlowQueryLog {
+    /// Call `callback` for the queries that take longer than `threshold`.
+    /// It is called inline once the query has completed, so it should be
+    /// quick and not block.
+    pub fn new(
+        threshold: Duration,
+        callback: impl Fn(&SlowQuery<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            threshold,
+            callback: Arc::new(callback),
+        }
+    }
+
+    /// Return the threshold above which the queries are slow.
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    pub(crate) fn report(&self, query: &SlowQuery<'_>) {
+        (self.callback)(query)
+    }
+}
+
+impl fmt::Debug for SlowQueryLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlowQueryLog")
+            .field("threshold", &self.threshold)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A query that took longer than the threshold of the [SlowQueryLog] of
+/// its connection.
+#[derive(Debug)]
+pub struct SlowQuery<'a> {
+    /// Name of the query, as given to `queries!`.
+    pub name: &'a str,
+    /// The comment the query was made with, if any, e.g. with
+    /// `commented_query`.
+    pub comment: Option<&'a str>,
+    /// How long the query took.
+    pub duration: Duration,
+    /// What else is known of the query.
+    pub telemetry: QueryTelemetry<'a>,
+}
+
+/// How a slow query ran.
+#[derive(Debug)]
+pub struct QueryTelemetry<'a> {
+    /// The SQL sent to the backend, without any comment.
+    pub sql: &'a str,
+    /// The threshold the query exceeded.
+    pub threshold: Duration,
+    /// The number of rows returned by a read query or affected by a write
+    /// query, if it succeeded.
+    pub rows: Option<u64>,
+    /// The error of the query, if it failed.
+    pub error: Option<&'a Error>,
+}
+
+/// Set the slow query configuration of the process, or disable the
+/// reporting of slow queries with `None`.
+pub fn set_slow_query_config(config: Option<SlowQueryConfig>) {
+    let mut current = CONFIG.write().expect("poisoned lock");
+    HAS_CONFIG.store(config.is_some(), Ordering::Release);
+    *current = config.map(Arc::new);
+}
+
+pub(crate) fn slow_query_config() -> Option<Arc<SlowQueryConfig>> {
+    if !HAS_CONFIG.load(Ordering::Acquire) {
+        return None;
+    }
+    CONFIG.read().expect("poisoned lock").clone()
+}
+
+/// Count the query and report it to the observers if it is slow, once its
+/// plan has been explained if an explain connection is configured.
+pub(crate) fn report_if_slow(
+    config: &SlowQueryConfig,
+    observers: Option<Vec<Arc<dyn QueryObserver>>>,
+    name: &str,
+    sql: String,
+    in_transaction: bool,
+    duration: Duration,
+) {
+    let threshold = config.threshold(name);
+    if duration <= threshold {
+        return;
+    }
+    STATS::count.add_value(1, (name.to_owned(),));
+
+    let observers = observers.unwrap_or_default();
+    let Some(connection) = config.explain_connection.clone() else {
+        let event = SlowQueryEvent {
+            name,
+            sql: &sql,
+            in_transaction,
+            duration,
+            threshold,
+            plan: None,
+            explain_error: None,
+        };
+        for observer in &observers {
+            observer.slow_query(&event);
+        }
+        return;
+    };
+
+    let name = name.to_owned();
+    tokio::spawn(async move {
+        let plan = connection.explain(&sql).await;
+        if plan.is_err() {
+            STATS::explain_failures.add_value(1);
+        }
+        let event = SlowQueryEvent {
+            name: &name,
+            sql: &sql,
+            in_transaction,
+            duration,
+            threshold,
+            plan: plan.as_deref().ok(),
+            explain_error: plan.as_ref().err(),
+        };
+        for observer in &observers {
+            observer.slow_query(&event);
+        }
+    });
+}
+
+impl Connection {
+    /// Call the callback of `slow_query_log` for the queries made with
+    /// `queries!` on this connection that take longer than its threshold,
+    /// whether they succeed or not, in addition to the reporting configured
+    /// with [set_slow_query_config]. The queries run in transactions are not
+    /// reported. The Meta internal client doesn't support slow query logs.
+    pub fn with_slow_query_log(self, slow_query_log: SlowQueryLog) -> Self {
+        match self {
+            Connection::Sqlite(con) => {
+                Connection::Sqlite(con.with_slow_query_log(Some(slow_query_log)))
+            }
+            Connection::Mysql(conn) => Connection::Mysql(conn),
+            Connection::OssMysql(conn) => {
+                Connection::OssMysql(conn.with_slow_query_log(Some(slow_query_log)))
+            }
+        }
+    }
+
+    /// The slow query log of this connection, if any.
+    pub fn slow_query_log(&self) -> Option<&SlowQueryLog> {
+        match self {
+            Connection::Sqlite(con) => con.slow_query_log(),
+            Connection::Mysql(..) => None,
+            Connection::OssMysql(conn) => conn.slow_query_log(),
+        }
+    }
+
+    /// Return the plan of `sql` as explained by the backend, without running
+    /// it: `EXPLAIN QUERY PLAN` on sqlite, with a line per step, and
+    /// `EXPLAIN FORMAT=JSON` on MySQL.
+    pub async fn explain(&self, sql: &str) -> Result<String, Error> {
+        match self {
+            Connection::Sqlite(multithread_con) => {
+                let query = format!("EXPLAIN QUERY PLAN {}", sql);
+                multithread_con
+                    .run_query(SqliteQueryType::Read, None, move |con| {
+                        let mut stmt = con.prepare(&query)?;
+                        // The parameters of the query are left unbound.
+                        let mut rows = stmt.raw_query();
+                        let mut steps = Vec::new();
+                        while let Some(row) = rows.next()? {
+                            steps.push(row.get::<_, String>(3)?);
+                        }
+                        Ok(steps.join("\n"))
+                    })
+                    .await
+            }
+            Connection::Mysql(conn) => {
+                let query = format!("EXPLAIN FORMAT=JSON {}", sql);
+                let plan: Vec<(String,)> = conn.read_query(query).await?;
+                Ok(plan.into_iter().map(|(plan,)| plan).collect())
+            }
+            Connection::OssMysql(conn) => {
+                let query = format!("EXPLAIN FORMAT=JSON {}", sql);
+                let mut con = conn.get_conn().await?;
+                let plan: Vec<(String,)> = conn
+                    .read_query(&mut con, &query)
+                    .await?
+                    .collect_and_drop()
+                    .await?;
+                Ok(plan.into_iter().map(|(plan,)| plan).collect())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_threshold() {
+        let config = SlowQueryConfig::new(Duration::from_millis(100))
+            .with_query_threshold("Scan", Duration::from_secs(10));
+        assert_eq!(config.threshold("Scan"), Duration::from_secs(10));
+        assert_eq!(config.threshold("Lookup"), Duration::from_millis(100));
+    }
+}
@@ -11,14 +11,60 @@
 
 #![allow(clippy::mutex_atomic)]
 
+mod blob;
+mod maintenance;
+mod options;
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt;
 use std::ops::Deref;
+use std::panic::RefUnwindSafe;
+use std::panic::UnwindSafe;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering as AtomicOrdering;
 use std::sync::Arc;
 use std::sync::Condvar;
 use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
+use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
 use async_trait::async_trait;
+use rusqlite::backup::Progress;
+use rusqlite::functions::Aggregate;
+use rusqlite::functions::Context as FunctionContext;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::ToSql;
 use rusqlite::Connection as SqliteConnection;
+use rusqlite::DatabaseName;
+use rusqlite::InterruptHandle;
+use rusqlite::OpenFlags;
+use stats::prelude::*;
+use tokio::runtime::Handle;
+use tokio::runtime::RuntimeFlavor;
+
+pub use self::blob::SqliteBlob;
+pub use self::maintenance::SqliteMaintenance;
+pub use self::maintenance::SqliteMaintenanceOptions;
+pub use self::maintenance::SqliteMaintenanceRun;
+pub use self::maintenance::SqliteMaintenanceStats;
+pub use self::options::SqliteConnectionOptions;
+pub use self::options::SqliteJournalMode;
+pub use self::options::SqliteSynchronous;
+use crate::cancel::CancellationToken;
+use crate::cancel::QueryCancelled;
+use crate::capabilities::ServerCapabilities;
+use crate::metrics::ConnectionMetrics;
+use crate::metrics::OpenTransaction;
+use crate::slow_query::SlowQueryLog;
+use crate::timeout::QueryTimeout;
 
 /// Lock to ensure that only one connection is in use for writes at a time
 /// inside the process TODO: Remove this lock, and replace by better connection
@@ -28,6 +74,20 @@ static CONN_LOCK: Mutex<bool> = Mutex::new(true);
 
 static CONN_CONDVAR: Condvar = Condvar::new();
 
+static INTERRUPT_TIMERS: Mutex<InterruptTimers> = Mutex::new(InterruptTimers {
+    next_id: 0,
+    timers: BTreeMap::new(),
+    thread_started: false,
+});
+
+static INTERRUPT_CONDVAR: Condvar = Condvar::new();
+
+define_stats! {
+    prefix = "sql.sqlite";
+    read_wait_ms: histogram(10, 0, 10_000, Average; P 50; P 99),
+    write_wait_ms: histogram(10, 0, 10_000, Average; P 50; P 99),
+}
+
 impl crate::Connection {
     /// Given a `rusqlite::Connection` create a connection to Sqlite database that might be used
     /// by this crate.
@@ -43,6 +103,184 @@ impl crate::Connection {
     ) -> Self {
         SqliteMultithreaded::new_with_callbacks(con, callbacks).into()
     }
+
+    /// Given a `rusqlite::Connection` create a connection to Sqlite database that might be used
+    /// by this crate, after registering the provided functions and collations on it.
+    pub fn with_sqlite_extensions(
+        con: SqliteConnection,
+        extensions: &SqliteExtensions,
+    ) -> Result<Self> {
+        extensions.register(&con)?;
+        Ok(Self::with_sqlite(con))
+    }
+
+    /// Given a `rusqlite::Connection` create a connection to Sqlite database that might be used
+    /// by this crate, after setting the pragmas of the provided options on it.
+    pub fn with_sqlite_options(
+        con: SqliteConnection,
+        options: &SqliteConnectionOptions,
+    ) -> Result<Self> {
+        Ok(SqliteMultithreaded::new_with_options(con, options)?.into())
+    }
+
+    /// Open the Sqlite database file at `path` in WAL mode, with one
+    /// connection for writes and a pool of `readers` read-only connections,
+    /// see [SqliteMultithreaded::open_wal].
+    pub fn with_sqlite_wal(path: impl AsRef<Path>, readers: usize) -> Result<Self> {
+        Self::with_sqlite_wal_extensions(path, readers, &SqliteExtensions::new())
+    }
+
+    /// Same as [Self::with_sqlite_wal], after registering the provided
+    /// functions and collations on every connection.
+    pub fn with_sqlite_wal_extensions(
+        path: impl AsRef<Path>,
+        readers: usize,
+        extensions: &SqliteExtensions,
+    ) -> Result<Self> {
+        Ok(SqliteMultithreaded::open_wal(path, readers, extensions)?.into())
+    }
+
+    /// Same as [Self::with_sqlite_wal_extensions], after setting the pragmas
+    /// of the provided options on every connection, see
+    /// [SqliteMultithreaded::open_wal_with_options].
+    pub fn with_sqlite_wal_options(
+        path: impl AsRef<Path>,
+        readers: usize,
+        extensions: &SqliteExtensions,
+        options: &SqliteConnectionOptions,
+    ) -> Result<Self> {
+        Ok(SqliteMultithreaded::open_wal_with_options(path, readers, extensions, options)?.into())
+    }
+}
+
+type Registration = Arc<dyn Fn(&SqliteConnection) -> rusqlite::Result<()> + Send + Sync>;
+
+/// A set of user-defined functions and collations to register on sqlite
+/// connections, for queries that rely on functionality not present in stock
+/// SQLite. The same set can be registered on any number of connections.
+#[derive(Clone, Default)]
+pub struct SqliteExtensions {
+    registrations: Vec<(String, Registration)>,
+}
+
+impl SqliteExtensions {
+    /// Name of the collation added by [SqliteExtensions::with_unicode_nocase_collation].
+    pub const UNICODE_NOCASE: &'static str = "UNICODE_NOCASE";
+
+    /// Create an empty set of extensions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a scalar function called `name` taking `n_arg` arguments, or any
+    /// number of arguments if `n_arg` is -1.
+    /// See [rusqlite::Connection::create_scalar_function].
+    pub fn with_scalar_function<F, T>(
+        mut self,
+        name: &str,
+        n_arg: i32,
+        flags: FunctionFlags,
+        function: F,
+    ) -> Self
+    where
+        F: Fn(&FunctionContext<'_>) -> rusqlite::Result<T>
+            + Clone
+            + Send
+            + Sync
+            + UnwindSafe
+            + 'static,
+        T: ToSql,
+    {
+        let fn_name = name.to_owned();
+        // FunctionFlags is neither Copy nor Clone, so keep the raw bits.
+        let flags = flags.bits();
+        self.registrations.push((
+            name.to_owned(),
+            Arc::new(move |con| {
+                con.create_scalar_function(
+                    &fn_name,
+                    n_arg,
+                    FunctionFlags::from_bits_truncate(flags),
+                    function.clone(),
+                )
+            }),
+        ));
+        self
+    }
+
+    /// Add an aggregate function called `name` taking `n_arg` arguments, or
+    /// any number of arguments if `n_arg` is -1.
+    /// See [rusqlite::Connection::create_aggregate_function].
+    pub fn with_aggregate_function<A, D, T>(
+        mut self,
+        name: &str,
+        n_arg: i32,
+        flags: FunctionFlags,
+        aggregate: D,
+    ) -> Self
+    where
+        A: RefUnwindSafe + UnwindSafe,
+        D: Aggregate<A, T> + Clone + Send + Sync + 'static,
+        T: ToSql,
+    {
+        let fn_name = name.to_owned();
+        // FunctionFlags is neither Copy nor Clone, so keep the raw bits.
+        let flags = flags.bits();
+        self.registrations.push((
+            name.to_owned(),
+            Arc::new(move |con| {
+                con.create_aggregate_function(
+                    &fn_name,
+                    n_arg,
+                    FunctionFlags::from_bits_truncate(flags),
+                    aggregate.clone(),
+                )
+            }),
+        ));
+        self
+    }
+
+    /// Add a collation called `name`, usable in queries as `COLLATE name`.
+    /// See [rusqlite::Connection::create_collation].
+    pub fn with_collation<C>(mut self, name: &str, compare: C) -> Self
+    where
+        C: Fn(&str, &str) -> Ordering + Clone + Send + Sync + UnwindSafe + 'static,
+    {
+        let collation_name = name.to_owned();
+        self.registrations.push((
+            name.to_owned(),
+            Arc::new(move |con| con.create_collation(&collation_name, compare.clone())),
+        ));
+        self
+    }
+
+    /// Add a case-insensitive collation called [SqliteExtensions::UNICODE_NOCASE]
+    /// that, unlike the builtin `NOCASE`, folds the case of non-ASCII
+    /// characters too.
+    pub fn with_unicode_nocase_collation(self) -> Self {
+        self.with_collation(Self::UNICODE_NOCASE, |a, b| {
+            a.chars()
+                .flat_map(char::to_lowercase)
+                .cmp(b.chars().flat_map(char::to_lowercase))
+        })
+    }
+
+    /// Register all functions and collations of this set on the connection.
+    pub fn register(&self, con: &SqliteConnection) -> Result<()> {
+        for (name, registration) in &self.registrations {
+            registration(con)
+                .with_context(|| format!("Failed to register sqlite extension {}", name))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for SqliteExtensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.registrations.iter().map(|(name, _)| name))
+            .finish()
+    }
 }
 
 /// Sqlite query categorization to allow callbacks to perform different
@@ -78,17 +316,100 @@ pub trait SqliteCallbacks: Send + Sync {
     async fn after_transaction_commit(&self) {}
 }
 
+/// Priority class of the callers waiting for the connection for writes. The
+/// callers of a higher class get the connection first, and those of the
+/// same class in the order they started waiting.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SqlitePriority {
+    /// Queries a user is waiting for, e.g. the reads of a request.
+    Interactive,
+    /// The default class.
+    #[default]
+    Normal,
+    /// Queries that can wait, e.g. long write transactions of batch jobs.
+    Background,
+}
+
+/// Time spent by the callers waiting for connections.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SqliteWaitStats {
+    /// Number of connections acquired so far.
+    pub acquired: u64,
+    /// Total time spent waiting for the connections acquired.
+    pub total_wait: Duration,
+    /// Longest time spent waiting for a connection.
+    pub max_wait: Duration,
+}
+
+impl SqliteWaitStats {
+    fn record(&mut self, wait: Duration) {
+        self.acquired += 1;
+        self.total_wait += wait;
+        self.max_wait = self.max_wait.max(wait);
+    }
+}
+
 /// Wrapper around rusqlite connection that makes it fully thread safe (but not deadlock safe)
 #[derive(Clone)]
 pub struct SqliteMultithreaded {
     inner: Arc<SqliteMultithreadedInner>,
+    priority: SqlitePriority,
+    slow_query_log: Option<SlowQueryLog>,
+    metrics: Option<ConnectionMetrics>,
+    capabilities: Option<Arc<ServerCapabilities>>,
 }
 
 /// Shared inner part of SqliteMultithreded plus any active connection guard.
 pub struct SqliteMultithreadedInner {
-    connection: Mutex<Option<SqliteConnection>>,
+    writer: Mutex<SqliteWriter>,
     condvar: Condvar,
     callbacks: Option<Box<dyn SqliteCallbacks>>,
+    readers: Option<SqliteReaders>,
+    read_waits: Mutex<SqliteWaitStats>,
+    write_waits: Mutex<SqliteWaitStats>,
+}
+
+impl SqliteMultithreadedInner {
+    fn new(
+        connection: SqliteConnection,
+        callbacks: Option<Box<dyn SqliteCallbacks>>,
+        readers: Option<SqliteReaders>,
+    ) -> Self {
+        Self {
+            writer: Mutex::new(SqliteWriter {
+                connection: Some(connection),
+                queue: BTreeSet::new(),
+                next_ticket: 0,
+            }),
+            condvar: Condvar::new(),
+            callbacks,
+            readers,
+            read_waits: Mutex::new(SqliteWaitStats::default()),
+            write_waits: Mutex::new(SqliteWaitStats::default()),
+        }
+    }
+
+    fn waits(&self, query_type: SqliteQueryType) -> &Mutex<SqliteWaitStats> {
+        match query_type {
+            SqliteQueryType::Read => &self.read_waits,
+            _ => &self.write_waits,
+        }
+    }
+}
+
+/// The connection for writes, unless a caller holds it, and the callers
+/// waiting for it, in the order they get it.
+struct SqliteWriter {
+    connection: Option<SqliteConnection>,
+    queue: BTreeSet<(SqlitePriority, u64)>,
+    next_ticket: u64,
+}
+
+/// Pool of read-only connections to a database in WAL mode, which can read
+/// concurrently with each other and with the writer.
+struct SqliteReaders {
+    connections: Mutex<Vec<SqliteConnection>>,
+    condvar: Condvar,
 }
 
 /// Guard containing an active connection.
@@ -99,51 +420,160 @@ pub struct SqliteConnectionGuard {
     inner: Arc<SqliteMultithreadedInner>,
     // drop() needs to remove the connection, so use Option<...> here
     connection: Option<SqliteConnection>,
+    // Whether the connection is one of the read-only connections.
+    reader: bool,
+    // The transaction running on the connection, if counted in the stats of
+    // its connection.
+    transaction: Option<OpenTransaction>,
 }
 
 impl SqliteConnectionGuard {
-    fn new(inner: Arc<SqliteMultithreadedInner>) -> SqliteConnectionGuard {
-        let _global_lock =
-            CONN_CONDVAR.wait_while(CONN_LOCK.lock().expect("lock poisoned"), |allowed| {
+    /// Wait for a connection suitable for the query type, giving up and
+    /// returning `None` if the deadline passes first. Reads use one of the
+    /// read-only connections if there are some. The time spent waiting is
+    /// recorded in the wait stats of the query type, and in the lock wait
+    /// stats of the connection if given.
+    fn acquire(
+        inner: Arc<SqliteMultithreadedInner>,
+        query_type: SqliteQueryType,
+        priority: SqlitePriority,
+        deadline: Option<Instant>,
+        metrics: Option<&ConnectionMetrics>,
+    ) -> Option<SqliteConnectionGuard> {
+        let start = Instant::now();
+        let guard = Self::acquire_untimed(inner.clone(), query_type, priority, deadline);
+        let wait = start.elapsed();
+        if guard.is_some() {
+            inner
+                .waits(query_type)
+                .lock()
+                .expect("poisoned lock")
+                .record(wait);
+            let wait_ms = wait.as_millis() as i64;
+            match query_type {
+                SqliteQueryType::Read => STATS::read_wait_ms.add_value(wait_ms),
+                _ => STATS::write_wait_ms.add_value(wait_ms),
+            }
+            if let Some(metrics) = metrics {
+                metrics.lock_waited(wait);
+            }
+        }
+        guard
+    }
+
+    fn acquire_untimed(
+        inner: Arc<SqliteMultithreadedInner>,
+        query_type: SqliteQueryType,
+        priority: SqlitePriority,
+        deadline: Option<Instant>,
+    ) -> Option<SqliteConnectionGuard> {
+        match &inner.readers {
+            Some(readers) if query_type == SqliteQueryType::Read => {
+                let connection = wait_while(
+                    &readers.condvar,
+                    readers.connections.lock().expect("poisoned lock"),
+                    deadline,
+                    |connections| connections.is_empty(),
+                )?
+                .pop()
+                .expect("connections should not be empty");
+                Some(SqliteConnectionGuard {
+                    inner,
+                    connection: Some(connection),
+                    reader: true,
+                    transaction: None,
+                })
+            }
+            _ => Self::new(inner, priority, deadline),
+        }
+    }
+
+    /// Wait for the connection for writes, after the callers of a higher
+    /// priority and those of the same priority that started waiting first,
+    /// giving up and returning `None` if the deadline passes first.
+    fn new(
+        inner: Arc<SqliteMultithreadedInner>,
+        priority: SqlitePriority,
+        deadline: Option<Instant>,
+    ) -> Option<SqliteConnectionGuard> {
+        let connection = {
+            let mut writer = inner.writer.lock().expect("poisoned lock");
+            let ticket = (priority, writer.next_ticket);
+            writer.next_ticket += 1;
+            writer.queue.insert(ticket);
+            let writer = wait_while(&inner.condvar, writer, deadline, |writer| {
+                writer.connection.is_none() || writer.queue.first() != Some(&ticket)
+            });
+            match writer {
+                Some(mut writer) => {
+                    writer.queue.remove(&ticket);
+                    writer
+                        .connection
+                        .take()
+                        .expect("connection should not be empty")
+                }
+                None => {
+                    let mut writer = inner.writer.lock().expect("poisoned lock");
+                    writer.queue.remove(&ticket);
+                    // The next caller may be the first of the queue now.
+                    inner.condvar.notify_all();
+                    return None;
+                }
+            }
+        };
+        // The global lock is taken last, so that the callers holding it never
+        // wait for anything else.
+        let global_lock = wait_while(
+            &CONN_CONDVAR,
+            CONN_LOCK.lock().expect("lock poisoned"),
+            deadline,
+            |allowed| {
                 if *allowed {
                     *allowed = false;
                     false
                 } else {
                     true
                 }
-            });
-        let connection = {
-            let mut connection = inner
-                .condvar
-                .wait_while(inner.connection.lock().expect("poisoned lock"), |con| {
-                    con.is_none()
-                })
-                .expect("poisoned lock");
-
-            connection.take().expect("connection should not be empty")
-        };
+            },
+        );
+        if global_lock.is_none() {
+            let mut writer = inner.writer.lock().expect("poisoned lock");
+            writer.connection = Some(connection);
+            inner.condvar.notify_all();
+            return None;
+        }
 
-        SqliteConnectionGuard {
+        Some(SqliteConnectionGuard {
             inner,
             connection: Some(connection),
-        }
+            reader: false,
+            transaction: None,
+        })
+    }
+
+    /// Count the transaction started on this connection in the open
+    /// transactions of the stats, until the guard is dropped.
+    pub(crate) fn track_transaction(&mut self, metrics: Option<&ConnectionMetrics>) {
+        self.transaction = metrics.map(ConnectionMetrics::transaction_started);
+    }
+
+    /// The stats of the connection of the transaction running on this
+    /// connection, if any.
+    pub(crate) fn transaction_metrics(&self) -> Option<&ConnectionMetrics> {
+        self.transaction.as_ref().map(OpenTransaction::metrics)
     }
 
     /// Commit a transaction that is being executed on this connection, and
     /// then release the connection.  If the commit fails, the connection is
     /// not release, and is instead returned along with the error.
     pub async fn commit(self) -> Result<(), (Self, rusqlite::Error)> {
-        fn commit_and_release(
-            guard: SqliteConnectionGuard,
-        ) -> Result<Arc<SqliteMultithreadedInner>, (SqliteConnectionGuard, rusqlite::Error)>
-        {
-            match guard.execute_batch("COMMIT") {
-                Ok(()) => Ok(guard.inner.clone()),
-                Err(e) => Err((guard, e)),
-            }
+        if let Err(e) = self.execute_batch("COMMIT") {
+            return Err((self, e));
         }
 
-        let inner = commit_and_release(self)?;
+        // Release the connection before running the callbacks.
+        let inner = self.inner.clone();
+        drop(self);
         if let Some(callbacks) = &inner.callbacks {
             callbacks.after_transaction_commit().await;
         }
@@ -151,6 +581,127 @@ impl SqliteConnectionGuard {
     }
 }
 
+/// Wait on the condvar while the condition holds, or until the deadline if
+/// any. Returns `None` if the deadline passed.
+fn wait_while<'a, T>(
+    condvar: &Condvar,
+    guard: MutexGuard<'a, T>,
+    deadline: Option<Instant>,
+    condition: impl FnMut(&mut T) -> bool,
+) -> Option<MutexGuard<'a, T>> {
+    match deadline {
+        None => Some(condvar.wait_while(guard, condition).expect("lock poisoned")),
+        Some(deadline) => {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let (guard, result) = condvar
+                .wait_timeout_while(guard, timeout, condition)
+                .expect("lock poisoned");
+            if result.timed_out() {
+                None
+            } else {
+                Some(guard)
+            }
+        }
+    }
+}
+
+/// The deadlines of the running [SqliteInterruptTimer]s, served by a single
+/// thread that interrupts the statements whose deadline passed.
+struct InterruptTimers {
+    next_id: u64,
+    // Keyed by deadline, then by id for the timers with the same deadline.
+    timers: BTreeMap<(Instant, u64), (InterruptHandle, Arc<AtomicBool>)>,
+    thread_started: bool,
+}
+
+impl InterruptTimers {
+    fn run() {
+        let mut timers = INTERRUPT_TIMERS.lock().expect("lock poisoned");
+        loop {
+            let now = Instant::now();
+            while let Some(timer) = timers.timers.first_entry() {
+                if timer.key().0 > now {
+                    break;
+                }
+                let (handle, interrupted) = timer.remove();
+                interrupted.store(true, AtomicOrdering::SeqCst);
+                handle.interrupt();
+            }
+            timers = match timers.timers.keys().next() {
+                Some((deadline, _)) => {
+                    let timeout = deadline.saturating_duration_since(now);
+                    INTERRUPT_CONDVAR
+                        .wait_timeout(timers, timeout)
+                        .expect("lock poisoned")
+                        .0
+                }
+                None => INTERRUPT_CONDVAR.wait(timers).expect("lock poisoned"),
+            };
+        }
+    }
+}
+
+/// Interrupts the statement running on a sqlite connection once a deadline
+/// passes, until it is dropped.
+struct SqliteInterruptTimer {
+    key: (Instant, u64),
+    interrupted: Arc<AtomicBool>,
+}
+
+impl SqliteInterruptTimer {
+    fn start(connection: &SqliteConnection, deadline: Instant) -> Self {
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let mut timers = INTERRUPT_TIMERS.lock().expect("lock poisoned");
+        if !timers.thread_started {
+            thread::Builder::new()
+                .name("sqlite-interrupt-timer".to_owned())
+                .spawn(InterruptTimers::run)
+                .expect("failed to spawn the sqlite interrupt timer thread");
+            timers.thread_started = true;
+        }
+        let key = (deadline, timers.next_id);
+        timers.next_id += 1;
+        timers.timers.insert(
+            key,
+            (connection.get_interrupt_handle(), interrupted.clone()),
+        );
+        // Wake the thread up if this timer is now the first to expire.
+        if timers.timers.keys().next() == Some(&key) {
+            INTERRUPT_CONDVAR.notify_one();
+        }
+        Self { key, interrupted }
+    }
+
+    fn interrupted(&self) -> bool {
+        self.interrupted.load(AtomicOrdering::SeqCst)
+    }
+}
+
+impl Drop for SqliteInterruptTimer {
+    fn drop(&mut self) {
+        // The timer thread interrupts the statements while holding the lock,
+        // so once the timer is removed, the connection can't be interrupted
+        // anymore.
+        INTERRUPT_TIMERS
+            .lock()
+            .expect("lock poisoned")
+            .timers
+            .remove(&self.key);
+    }
+}
+
+/// Run `f`, which blocks the thread it runs on, without blocking the other
+/// tasks of a multi-threaded runtime, which are moved to another thread in
+/// the meantime. On a current-thread runtime, `f` blocks the runtime.
+fn block_in_place<T>(f: impl FnOnce() -> T) -> T {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
 impl Deref for SqliteConnectionGuard {
     type Target = SqliteConnection;
 
@@ -163,11 +714,25 @@ impl Deref for SqliteConnectionGuard {
 
 impl Drop for SqliteConnectionGuard {
     fn drop(&mut self) {
+        if self.reader {
+            let readers = self
+                .inner
+                .readers
+                .as_ref()
+                .expect("reader guard without readers");
+            let mut connections = readers.connections.lock().expect("poisoned lock");
+            connections.push(self.connection.take().unwrap());
+            readers.condvar.notify_one();
+            return;
+        }
         *(CONN_LOCK.lock().expect("lock poisoned")) = true;
-        let mut connection = self.inner.connection.lock().expect("poisoned lock");
-        connection.get_or_insert(self.connection.take().unwrap());
-        // notify others that wait for this connection
-        self.inner.condvar.notify_one();
+        let mut writer = self.inner.writer.lock().expect("poisoned lock");
+        writer
+            .connection
+            .get_or_insert(self.connection.take().unwrap());
+        // Only the first of the queue takes the connection, but all the
+        // callers waiting for it need to check whether they are the first.
+        self.inner.condvar.notify_all();
         CONN_CONDVAR.notify_one();
     }
 }
@@ -176,12 +741,93 @@ impl SqliteMultithreaded {
     /// Create a new instance wrapping the provided sqlite connection.
     pub fn new(connection: SqliteConnection) -> Self {
         Self {
-            inner: Arc::new(SqliteMultithreadedInner {
-                connection: Mutex::new(Some(connection)),
-                condvar: Condvar::new(),
-                callbacks: None,
-            }),
+            inner: Arc::new(SqliteMultithreadedInner::new(connection, None, None)),
+            priority: SqlitePriority::default(),
+            slow_query_log: None,
+            metrics: None,
+            capabilities: None,
+        }
+    }
+
+    /// Create a new instance wrapping the provided sqlite connection, after
+    /// setting the pragmas of the provided options on it.
+    pub fn new_with_options(
+        connection: SqliteConnection,
+        options: &SqliteConnectionOptions,
+    ) -> Result<Self> {
+        options.apply(&connection)?;
+        Ok(Self::new(connection))
+    }
+
+    /// Open the database file at `path` in WAL mode, with one connection for
+    /// writes, schema changes and transactions, and a pool of `readers`
+    /// read-only connections on which read queries run concurrently. With no
+    /// readers, reads share the connection for writes as with [Self::new].
+    ///
+    /// Reads outside of a transaction don't see the writes of transactions
+    /// that haven't been committed yet.
+    pub fn open_wal(
+        path: impl AsRef<Path>,
+        readers: usize,
+        extensions: &SqliteExtensions,
+    ) -> Result<Self> {
+        Self::open_wal_with_options(path, readers, extensions, &SqliteConnectionOptions::new())
+    }
+
+    /// Same as [Self::open_wal], after setting the pragmas of the provided
+    /// options on every connection. The journal mode of the options, if set,
+    /// must be [SqliteJournalMode::Wal].
+    pub fn open_wal_with_options(
+        path: impl AsRef<Path>,
+        readers: usize,
+        extensions: &SqliteExtensions,
+        options: &SqliteConnectionOptions,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(journal_mode) = options.journal_mode() {
+            if journal_mode != SqliteJournalMode::Wal {
+                bail!(
+                    "Can't open {} in WAL mode with journal mode {}",
+                    path.display(),
+                    journal_mode
+                );
+            }
         }
+        let writer = SqliteConnection::open(path)?;
+        options
+            .clone()
+            .with_journal_mode(SqliteJournalMode::Wal)
+            .apply(&writer)
+            .with_context(|| format!("Failed to enable WAL mode for {}", path.display()))?;
+        extensions.register(&writer)?;
+
+        let readers = if readers == 0 {
+            None
+        } else {
+            let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX
+                | OpenFlags::SQLITE_OPEN_URI;
+            let connections = (0..readers)
+                .map(|_| {
+                    let reader = SqliteConnection::open_with_flags(path, flags)?;
+                    options.apply_to_reader(&reader)?;
+                    extensions.register(&reader)?;
+                    Ok(reader)
+                })
+                .collect::<Result<_>>()?;
+            Some(SqliteReaders {
+                connections: Mutex::new(connections),
+                condvar: Condvar::new(),
+            })
+        };
+
+        Ok(Self {
+            inner: Arc::new(SqliteMultithreadedInner::new(writer, None, readers)),
+            priority: SqlitePriority::default(),
+            slow_query_log: None,
+            metrics: None,
+            capabilities: None,
+        })
     }
 
     /// Create a new instance wrapping the provided sqlite connection, and
@@ -191,14 +837,91 @@ impl SqliteMultithreaded {
         callbacks: Box<dyn SqliteCallbacks>,
     ) -> Self {
         Self {
-            inner: Arc::new(SqliteMultithreadedInner {
-                connection: Mutex::new(Some(connection)),
-                condvar: Condvar::new(),
-                callbacks: Some(callbacks),
-            }),
+            inner: Arc::new(SqliteMultithreadedInner::new(
+                connection,
+                Some(callbacks),
+                None,
+            )),
+            priority: SqlitePriority::default(),
+            slow_query_log: None,
+            metrics: None,
+            capabilities: None,
         }
     }
 
+    /// Acquire the connection for writes with the given priority, e.g. to
+    /// let the reads of interactive requests go before the transactions of
+    /// a batch job waiting for the connection. Waiting callers of the same
+    /// priority get the connection in the order they started waiting.
+    ///
+    /// Reads using the read-only connections of a database in WAL mode
+    /// don't wait for the connection for writes.
+    pub fn with_priority(mut self, priority: SqlitePriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// The priority of this instance when acquiring the connection for
+    /// writes, see [Self::with_priority].
+    pub fn priority(&self) -> SqlitePriority {
+        self.priority
+    }
+
+    /// Report the queries of this instance slower than the threshold of the
+    /// log, see [crate::Connection::with_slow_query_log].
+    pub fn with_slow_query_log(mut self, slow_query_log: Option<SlowQueryLog>) -> Self {
+        self.slow_query_log = slow_query_log;
+        self
+    }
+
+    /// The slow query log of this instance, if any.
+    pub fn slow_query_log(&self) -> Option<&SlowQueryLog> {
+        self.slow_query_log.as_ref()
+    }
+
+    /// Export the stats of the queries and transactions of this instance,
+    /// see [crate::Connection::with_metrics_label].
+    pub fn with_metrics(mut self, metrics: Option<ConnectionMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// The stats of this instance, if any.
+    pub fn metrics(&self) -> Option<&ConnectionMetrics> {
+        self.metrics.as_ref()
+    }
+
+    /// Cache the capabilities of the server behind this instance, see
+    /// [crate::Connection::with_probed_capabilities].
+    pub fn with_capabilities(mut self, capabilities: Option<Arc<ServerCapabilities>>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// The capabilities of the server behind this instance, if probed.
+    pub fn capabilities(&self) -> Option<&ServerCapabilities> {
+        self.capabilities.as_deref()
+    }
+
+    /// Time spent waiting for connections by the read queries, whether they
+    /// use the read-only connections or the connection for writes. The wait
+    /// times are also exported as the `sql.sqlite.read_wait_ms` stat.
+    pub fn read_wait_stats(&self) -> SqliteWaitStats {
+        *self.inner.read_waits.lock().expect("poisoned lock")
+    }
+
+    /// Time spent waiting for the connection for writes by the other
+    /// queries and the transactions. The wait times are also exported as the
+    /// `sql.sqlite.write_wait_ms` stat.
+    pub fn write_wait_stats(&self) -> SqliteWaitStats {
+        *self.inner.write_waits.lock().expect("poisoned lock")
+    }
+
+    /// Number of callers waiting for the connection for writes.
+    pub fn write_queue_len(&self) -> usize {
+        self.inner.writer.lock().expect("poisoned lock").queue.len()
+    }
+
     /// Returns a guard that acquires the sqlite connection.
     ///
     /// When guard is destroyed then connection is put back and threads that are waiting for it
@@ -213,6 +936,194 @@ impl SqliteMultithreaded {
         if let Some(callbacks) = &self.inner.callbacks {
             callbacks.query_start(query_type).await?;
         }
-        Ok(SqliteConnectionGuard::new(self.inner.clone()))
+        Ok(SqliteConnectionGuard::acquire(
+            self.inner.clone(),
+            query_type,
+            self.priority,
+            None,
+            self.metrics.as_ref(),
+        )
+        .expect("acquiring a connection without a deadline should not fail"))
+    }
+
+    /// Acquire the connection and run the query on it, releasing the
+    /// connection once the query is done.
+    ///
+    /// If a timeout is given and the connection can't be acquired or the
+    /// query doesn't complete within it, the statement running on the
+    /// connection is interrupted and the query fails with [QueryTimeout].
+    ///
+    /// The connection is acquired and the query runs on the calling thread.
+    /// On a multi-threaded runtime, the other tasks of that thread are moved
+    /// to another one in the meantime, so that they aren't blocked.
+    pub async fn run_query<T>(
+        &self,
+        query_type: SqliteQueryType,
+        timeout: Option<Duration>,
+        query: impl FnOnce(&SqliteConnection) -> Result<T>,
+    ) -> Result<T> {
+        self.run_query_with_cancellation(query_type, timeout, None, query)
+            .await
+    }
+
+    /// Same as [Self::run_query], also interrupting the statement running on
+    /// the connection if the cancellation token is cancelled, in which case
+    /// the query fails with [QueryCancelled]. As the query blocks the thread
+    /// it runs on, the token must be cancelled from another thread.
+    pub async fn run_query_with_cancellation<T>(
+        &self,
+        query_type: SqliteQueryType,
+        timeout: Option<Duration>,
+        cancellation: Option<&CancellationToken>,
+        query: impl FnOnce(&SqliteConnection) -> Result<T>,
+    ) -> Result<T> {
+        let cancelled = || cancellation.is_some_and(CancellationToken::is_cancelled);
+        if cancelled() {
+            return Err(QueryCancelled.into());
+        }
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let timed_out = || QueryTimeout {
+            timeout: timeout.unwrap_or_default(),
+        };
+
+        if let Some(callbacks) = &self.inner.callbacks {
+            callbacks.query_start(query_type).await?;
+        }
+        block_in_place(|| {
+            let con = SqliteConnectionGuard::acquire(
+                self.inner.clone(),
+                query_type,
+                self.priority,
+                deadline,
+                self.metrics.as_ref(),
+            )
+            .ok_or_else(timed_out)?;
+            let timer = deadline.map(|deadline| SqliteInterruptTimer::start(&con, deadline));
+            let on_cancel = cancellation.map(|cancellation| {
+                let handle = con.get_interrupt_handle();
+                cancellation.on_cancel(move || handle.interrupt())
+            });
+            let result = query(&con);
+            drop(on_cancel);
+            // A statement interrupted by the timer or the cancellation fails
+            // with an error that is replaced here, any statement that
+            // completed succeeded.
+            let interrupted = timer.is_some_and(|timer| timer.interrupted());
+            match result {
+                Err(_) if interrupted => Err(timed_out().into()),
+                Err(_) if cancelled() => Err(QueryCancelled.into()),
+                result => result,
+            }
+        })
+    }
+
+    /// Copy the database, e.g. an in-memory one, to the database file at
+    /// `path` with the online backup API of sqlite, overwriting it.
+    ///
+    /// The connection for writes is held during the backup, so no write or
+    /// transaction is running or started until it completes, and the copy is
+    /// a consistent snapshot. Reads on the read-only connections of a
+    /// database in WAL mode keep running.
+    pub async fn backup_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref().to_owned();
+        self.run_blocking_query(SqliteQueryType::Write, move |con| {
+            con.backup(DatabaseName::Main, &path, None)
+                .with_context(|| format!("Failed to back up database to {}", path.display()))
+        })
+        .await
+    }
+
+    /// Replace the content of the database with the one of the database file
+    /// at `path`, e.g. one created by [Self::backup_to], with the online
+    /// backup API of sqlite.
+    ///
+    /// The connection for writes is held during the restore, as with
+    /// [Self::backup_to]. On a database in WAL mode, the restore waits for
+    /// the reads running on the read-only connections to complete.
+    pub async fn restore_from(&self, path: impl AsRef<Path>) -> Result<()> {
+        if let Some(callbacks) = &self.inner.callbacks {
+            callbacks.query_start(SqliteQueryType::SchemaChange).await?;
+        }
+        let path = path.as_ref().to_owned();
+        let inner = self.inner.clone();
+        let priority = self.priority;
+        tokio::task::spawn_blocking(move || {
+            let mut con = SqliteConnectionGuard::new(inner, priority, None)
+                .expect("acquiring a connection without a deadline should not fail");
+            con.connection
+                .as_mut()
+                .expect("invariant violation - restore called after drop()")
+                .restore(DatabaseName::Main, &path, None::<fn(Progress)>)
+                .with_context(|| format!("Failed to restore database from {}", path.display()))
+        })
+        .await?
+    }
+
+    /// Recover the connections from the failures of the callers that used
+    /// them, returning whether anything had to be recovered, and check that
+    /// the connection for writes is able to serve queries.
+    ///
+    /// Locks poisoned by a caller panicking while holding them would make
+    /// every later query panic, so they are cleared. A transaction left open
+    /// on the connection for writes, e.g. by a caller that panicked in the
+    /// middle of it, is rolled back, as no transaction can be running on the
+    /// connection once it is acquired.
+    pub async fn recover(&self) -> Result<bool> {
+        let inner = self.inner.clone();
+        let priority = self.priority;
+        tokio::task::spawn_blocking(move || {
+            let mut recovered = false;
+            if CONN_LOCK.is_poisoned() {
+                CONN_LOCK.clear_poison();
+                recovered = true;
+            }
+            if inner.writer.is_poisoned() {
+                inner.writer.clear_poison();
+                recovered = true;
+            }
+            if let Some(readers) = &inner.readers {
+                if readers.connections.is_poisoned() {
+                    readers.connections.clear_poison();
+                    recovered = true;
+                }
+            }
+
+            let con =
+                SqliteConnectionGuard::acquire(inner, SqliteQueryType::Write, priority, None, None)
+                    .expect("acquiring a connection without a deadline should not fail");
+            if !con.is_autocommit() {
+                con.execute_batch("ROLLBACK; PRAGMA query_only = 0")?;
+                recovered = true;
+            }
+            con.query_row("PRAGMA schema_version", [], |_| Ok(()))?;
+            Ok(recovered)
+        })
+        .await?
+    }
+
+    /// Acquire the connection and run the query on it in a blocking thread,
+    /// so that neither waiting for the connection nor the query block the
+    /// async runtime.
+    pub async fn run_blocking_query<T>(
+        &self,
+        query_type: SqliteQueryType,
+        query: impl FnOnce(&SqliteConnection) -> Result<T> + Send + 'static,
+    ) -> Result<T>
+    where
+        T: Send + 'static,
+    {
+        if let Some(callbacks) = &self.inner.callbacks {
+            callbacks.query_start(query_type).await?;
+        }
+        let inner = self.inner.clone();
+        let priority = self.priority;
+        let metrics = self.metrics.clone();
+        tokio::task::spawn_blocking(move || {
+            let con =
+                SqliteConnectionGuard::acquire(inner, query_type, priority, None, metrics.as_ref())
+                    .expect("acquiring a connection without a deadline should not fail");
+            query(&con)
+        })
+        .await?
     }
 }
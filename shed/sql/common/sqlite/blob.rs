@@ -0,0 +1,268 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Incremental I/O on the BLOB values of a sqlite database, to read or write
+//! large values in chunks rather than holding them wholly in memory.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::ready;
+use std::task::Context;
+use std::task::Poll;
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use futures::future::FutureExt;
+use rusqlite::blob::Blob;
+use rusqlite::Connection as SqliteConnection;
+use rusqlite::DatabaseName;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+
+use super::SqliteMultithreaded;
+use super::SqliteQueryType;
+
+/// The default size of the chunks read or written by a [SqliteBlob].
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+struct BlobLocation {
+    table: String,
+    column: String,
+    row_id: i64,
+    read_only: bool,
+}
+
+enum State {
+    Idle,
+    Reading(BoxFuture<'static, Result<Vec<u8>>>),
+    Writing(BoxFuture<'static, Result<usize>>),
+}
+
+/// Handle to a BLOB value of a sqlite database, reading and writing it in
+/// chunks through [AsyncRead] and [AsyncWrite].
+///
+/// Every chunk is read or written on a blocking thread, which acquires the
+/// connection only for the duration of that chunk, so other queries can run
+/// between chunks. Writes can't change the size of a BLOB, so a large value is
+/// written by first inserting a `zeroblob(size)` and then writing into it.
+pub struct SqliteBlob {
+    sqlite: SqliteMultithreaded,
+    location: Arc<BlobLocation>,
+    len: u64,
+    position: u64,
+    chunk_size: usize,
+    state: State,
+    buffer: Vec<u8>,
+    buffer_position: usize,
+}
+
+impl SqliteMultithreaded {
+    /// Open the BLOB value of `column` in the row `row_id` of `table`, which
+    /// is then only writable if `read_only` is false.
+    pub async fn open_blob(
+        &self,
+        table: &str,
+        column: &str,
+        row_id: i64,
+        read_only: bool,
+    ) -> Result<SqliteBlob> {
+        let location = Arc::new(BlobLocation {
+            table: table.to_owned(),
+            column: column.to_owned(),
+            row_id,
+            read_only,
+        });
+        let len = self
+            .run_blocking_query(location.read_query_type(), {
+                let location = location.clone();
+                move |con| Ok(location.open(con)?.len())
+            })
+            .await?;
+        Ok(SqliteBlob {
+            sqlite: self.clone(),
+            location,
+            len: len as u64,
+            position: 0,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            state: State::Idle,
+            buffer: Vec::new(),
+            buffer_position: 0,
+        })
+    }
+}
+
+impl BlobLocation {
+    /// Blobs opened for writing can't be opened on the read-only connections
+    /// of a database in WAL mode, so they are read through the connection
+    /// for writes.
+    fn read_query_type(&self) -> SqliteQueryType {
+        if self.read_only {
+            SqliteQueryType::Read
+        } else {
+            SqliteQueryType::Write
+        }
+    }
+
+    fn open<'a>(&self, con: &'a SqliteConnection) -> rusqlite::Result<Blob<'a>> {
+        con.blob_open(
+            DatabaseName::Main,
+            &self.table,
+            &self.column,
+            self.row_id,
+            self.read_only,
+        )
+    }
+}
+
+impl SqliteBlob {
+    /// Set the maximum number of bytes read or written at once.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Return the size of the BLOB in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Return true if the BLOB is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return the offset in the BLOB where the next read or write starts.
+    pub fn position(&self) -> u64 {
+        self.position - (self.buffer.len() - self.buffer_position) as u64
+    }
+
+    fn poll_pending_write(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let State::Writing(write) = &mut self.state {
+            let result = ready!(write.poll_unpin(cx));
+            self.state = State::Idle;
+            self.position += result.map_err(io::Error::other)? as u64;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for SqliteBlob {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        ready!(this.poll_pending_write(cx))?;
+
+        if this.buffer_position == this.buffer.len() {
+            if let State::Idle = this.state {
+                let size = this.chunk_size.min((this.len - this.position) as usize);
+                if size == 0 {
+                    return Poll::Ready(Ok(()));
+                }
+                let location = this.location.clone();
+                let offset = this.position as usize;
+                let sqlite = this.sqlite.clone();
+                this.state = State::Reading(
+                    async move {
+                        sqlite
+                            .run_blocking_query(location.read_query_type(), move |con| {
+                                let mut chunk = vec![0; size];
+                                let read = location.open(con)?.read_at(&mut chunk, offset)?;
+                                chunk.truncate(read);
+                                Ok(chunk)
+                            })
+                            .await
+                    }
+                    .boxed(),
+                );
+            }
+            if let State::Reading(read) = &mut this.state {
+                let result = ready!(read.poll_unpin(cx));
+                this.state = State::Idle;
+                let chunk = result.map_err(io::Error::other)?;
+                this.position += chunk.len() as u64;
+                this.buffer = chunk;
+                this.buffer_position = 0;
+            }
+        }
+
+        let available = &this.buffer[this.buffer_position..];
+        let size = available.len().min(buf.remaining());
+        buf.put_slice(&available[..size]);
+        this.buffer_position += size;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for SqliteBlob {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if let State::Idle = this.state {
+            // Writes start where the reads stopped, not after the buffered data.
+            this.position = this.position();
+            this.buffer.clear();
+            this.buffer_position = 0;
+
+            let size = buf
+                .len()
+                .min(this.chunk_size)
+                .min((this.len - this.position) as usize);
+            if size == 0 {
+                return Poll::Ready(Ok(0));
+            }
+            let location = this.location.clone();
+            let offset = this.position as usize;
+            let chunk = buf[..size].to_vec();
+            let sqlite = this.sqlite.clone();
+            this.state = State::Writing(
+                async move {
+                    sqlite
+                        .run_blocking_query(SqliteQueryType::Write, move |con| {
+                            location.open(con)?.write_at(&chunk, offset)?;
+                            Ok(chunk.len())
+                        })
+                        .await
+                }
+                .boxed(),
+            );
+        }
+
+        match &mut this.state {
+            State::Writing(write) => {
+                let result = ready!(write.poll_unpin(cx));
+                this.state = State::Idle;
+                let written = result.map_err(io::Error::other)?;
+                this.position += written as u64;
+                Poll::Ready(Ok(written))
+            }
+            State::Reading(_) | State::Idle => {
+                // A read can only be pending if the caller stopped polling
+                // it, drop it and start the write.
+                this.state = State::Idle;
+                Pin::new(this).poll_write(cx, buf)
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_pending_write(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
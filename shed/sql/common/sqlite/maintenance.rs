@@ -0,0 +1,272 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Periodic maintenance of a database, so that the write-ahead log and the
+//! free pages of long-running services don't grow without bounds.
+//!
+//! Sqlite only checkpoints the write-ahead log when it is committed to, and
+//! never shrinks the file, so the log of a database that readers keep busy
+//! can grow to many times the size of the database.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Error;
+use anyhow::Result;
+use rand::Rng;
+use rusqlite::Connection as SqliteConnection;
+use stats::prelude::*;
+use tokio::task::JoinHandle;
+
+use super::SqliteMultithreaded;
+use super::SqliteQueryType;
+
+define_stats! {
+    prefix = "sql.sqlite.maintenance";
+    checkpoint_ms: histogram(10, 0, 10_000, Average; P 50; P 99),
+    wal_bytes: timeseries(Average),
+    vacuumed_pages: timeseries(Rate, Sum),
+    failures: timeseries(Rate, Sum),
+}
+
+/// What to do on each maintenance run and how often, see
+/// [SqliteMultithreaded::spawn_maintenance].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SqliteMaintenanceOptions {
+    interval: Duration,
+    jitter: Duration,
+    checkpoint: bool,
+    incremental_vacuum: Option<u32>,
+}
+
+impl SqliteMaintenanceOptions {
+    /// Run the maintenance every `interval`, checkpointing and truncating the
+    /// write-ahead log, without vacuuming.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            jitter: Duration::ZERO,
+            checkpoint: true,
+            incremental_vacuum: None,
+        }
+    }
+
+    /// Wait up to `jitter` more than the interval, chosen at random before
+    /// each run, so that the databases of the processes started at the same
+    /// time aren't all maintained at the same time.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set whether the write-ahead log is checkpointed and truncated, with
+    /// `PRAGMA wal_checkpoint(TRUNCATE)`.
+    pub fn with_checkpoint(mut self, checkpoint: bool) -> Self {
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    /// Release up to `pages` free pages to the file system on each run, all
+    /// of them if zero, with `PRAGMA incremental_vacuum`. This only has an
+    /// effect on databases whose `auto_vacuum` pragma is `INCREMENTAL`.
+    pub fn with_incremental_vacuum(mut self, pages: u32) -> Self {
+        self.incremental_vacuum = Some(pages);
+        self
+    }
+
+    /// Return the interval between the runs, jitter included.
+    fn next_delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            self.interval
+        } else {
+            self.interval + rand::thread_rng().gen_range(Duration::ZERO..=self.jitter)
+        }
+    }
+}
+
+/// Outcome of a maintenance run.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SqliteMaintenanceRun {
+    /// Size of the write-ahead log file before the checkpoint, zero for
+    /// databases not in WAL mode or in memory.
+    pub wal_bytes: u64,
+    /// How long the checkpoint took, if one ran.
+    pub checkpoint: Option<Duration>,
+    /// Whether the checkpoint couldn't complete, e.g. because of readers
+    /// still reading the write-ahead log.
+    pub checkpoint_busy: bool,
+    /// Number of free pages released by the incremental vacuum.
+    pub vacuumed_pages: u64,
+}
+
+/// Counters of the runs of a [SqliteMaintenance] task.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SqliteMaintenanceStats {
+    /// Number of runs that succeeded.
+    pub runs: u64,
+    /// Number of runs that failed.
+    pub failures: u64,
+    /// Size of the write-ahead log file before the last successful run.
+    pub last_wal_bytes: u64,
+    /// Largest size of the write-ahead log file before a run.
+    pub max_wal_bytes: u64,
+    /// How long the last checkpoint took.
+    pub last_checkpoint: Duration,
+    /// Longest time a checkpoint took.
+    pub max_checkpoint: Duration,
+    /// Total time spent checkpointing.
+    pub total_checkpoint: Duration,
+    /// Number of checkpoints that couldn't complete.
+    pub busy_checkpoints: u64,
+    /// Total number of free pages released by the incremental vacuums.
+    pub vacuumed_pages: u64,
+}
+
+impl SqliteMaintenanceStats {
+    fn record(&mut self, run: &SqliteMaintenanceRun) {
+        self.runs += 1;
+        self.last_wal_bytes = run.wal_bytes;
+        self.max_wal_bytes = self.max_wal_bytes.max(run.wal_bytes);
+        if let Some(checkpoint) = run.checkpoint {
+            self.last_checkpoint = checkpoint;
+            self.max_checkpoint = self.max_checkpoint.max(checkpoint);
+            self.total_checkpoint += checkpoint;
+        }
+        self.busy_checkpoints += u64::from(run.checkpoint_busy);
+        self.vacuumed_pages += run.vacuumed_pages;
+    }
+}
+
+impl SqliteMultithreaded {
+    /// Run the maintenance of the database now, on the connection for
+    /// writes, so that it doesn't run concurrently with writes or
+    /// transactions.
+    pub async fn run_maintenance(
+        &self,
+        options: &SqliteMaintenanceOptions,
+    ) -> Result<SqliteMaintenanceRun> {
+        let options = options.clone();
+        self.run_blocking_query(SqliteQueryType::Write, move |con| maintain(con, &options))
+            .await
+    }
+
+    /// Run [Self::run_maintenance] periodically in the background until the
+    /// returned handle is dropped.
+    ///
+    /// The runs are exported as `sql.sqlite.maintenance.*` stats, and
+    /// counted in [SqliteMaintenance::stats]. The error of the last run that
+    /// failed is kept in [SqliteMaintenance::last_error].
+    pub fn spawn_maintenance(&self, options: SqliteMaintenanceOptions) -> SqliteMaintenance {
+        let sqlite = self.clone();
+        let state = Arc::new(Mutex::new(MaintenanceState::default()));
+        let handle = tokio::spawn({
+            let state = state.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(options.next_delay()).await;
+                    match sqlite.run_maintenance(&options).await {
+                        Ok(run) => {
+                            if let Some(checkpoint) = run.checkpoint {
+                                STATS::checkpoint_ms.add_value(checkpoint.as_millis() as i64);
+                                STATS::wal_bytes.add_value(run.wal_bytes as i64);
+                            }
+                            STATS::vacuumed_pages.add_value(run.vacuumed_pages as i64);
+                            state.lock().expect("poisoned lock").stats.record(&run);
+                        }
+                        Err(err) => {
+                            STATS::failures.add_value(1);
+                            let mut guard = state.lock().expect("poisoned lock");
+                            guard.stats.failures += 1;
+                            guard.last_error = Some(Arc::new(err));
+                        }
+                    }
+                }
+            }
+        });
+        SqliteMaintenance { handle, state }
+    }
+}
+
+#[derive(Default)]
+struct MaintenanceState {
+    stats: SqliteMaintenanceStats,
+    last_error: Option<Arc<Error>>,
+}
+
+/// Handle of the task spawned by [SqliteMultithreaded::spawn_maintenance],
+/// which stops the task when dropped.
+#[must_use = "the maintenance task stops when its handle is dropped"]
+pub struct SqliteMaintenance {
+    handle: JoinHandle<()>,
+    state: Arc<Mutex<MaintenanceState>>,
+}
+
+impl SqliteMaintenance {
+    /// Return the counters of the runs so far.
+    pub fn stats(&self) -> SqliteMaintenanceStats {
+        self.state.lock().expect("poisoned lock").stats
+    }
+
+    /// Return the error of the last run that failed, if any did.
+    pub fn last_error(&self) -> Option<Arc<Error>> {
+        self.state.lock().expect("poisoned lock").last_error.clone()
+    }
+}
+
+impl Drop for SqliteMaintenance {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+fn maintain(
+    con: &SqliteConnection,
+    options: &SqliteMaintenanceOptions,
+) -> Result<SqliteMaintenanceRun> {
+    let mut run = SqliteMaintenanceRun::default();
+    // Vacuum first, so that the pages it writes are checkpointed too.
+    if let Some(pages) = options.incremental_vacuum {
+        let freelist_count =
+            || -> Result<u64> { Ok(con.query_row("PRAGMA freelist_count", [], |row| row.get(0))?) };
+        let before = freelist_count()?;
+        con.execute_batch(&format!("PRAGMA incremental_vacuum({})", pages))?;
+        run.vacuumed_pages = before.saturating_sub(freelist_count()?);
+    }
+    if options.checkpoint {
+        run.wal_bytes = wal_bytes(con)?;
+        let start = Instant::now();
+        // Returns whether the checkpoint was blocked, and the number of
+        // frames in the log and checkpointed, or -1 if not in WAL mode.
+        let busy: i64 = con.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+        run.checkpoint = Some(start.elapsed());
+        run.checkpoint_busy = busy != 0;
+    }
+    Ok(run)
+}
+
+/// Size of the write-ahead log file of the main database, which is next to
+/// the database file.
+fn wal_bytes(con: &SqliteConnection) -> Result<u64> {
+    let file: String = con.query_row(
+        "SELECT file FROM pragma_database_list WHERE name = 'main'",
+        [],
+        |row| row.get(0),
+    )?;
+    if file.is_empty() {
+        // In-memory or temporary database.
+        return Ok(0);
+    }
+    match std::fs::metadata(format!("{}-wal", file)) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,204 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Pragmas configuring sqlite connections, applied to every connection of a
+//! [SqliteMultithreaded](super::SqliteMultithreaded) when it is created.
+
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use rusqlite::Connection as SqliteConnection;
+
+/// Journal mode of a database, see the `journal_mode` pragma.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SqliteJournalMode {
+    /// Rollback journal deleted at the end of each transaction.
+    Delete,
+    /// Rollback journal truncated at the end of each transaction.
+    Truncate,
+    /// Rollback journal whose header is zeroed at the end of each transaction.
+    Persist,
+    /// Rollback journal kept in memory.
+    Memory,
+    /// Write-ahead log, letting readers run concurrently with a writer.
+    Wal,
+    /// No journal, transactions can't be rolled back reliably.
+    Off,
+}
+
+impl fmt::Display for SqliteJournalMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Delete => "DELETE",
+            Self::Truncate => "TRUNCATE",
+            Self::Persist => "PERSIST",
+            Self::Memory => "MEMORY",
+            Self::Wal => "WAL",
+            Self::Off => "OFF",
+        })
+    }
+}
+
+/// How often sqlite waits for writes to reach the disk, see the
+/// `synchronous` pragma.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SqliteSynchronous {
+    /// Never wait for writes to reach the disk.
+    Off,
+    /// Wait at the most critical moments only.
+    Normal,
+    /// Wait for the writes of every transaction.
+    Full,
+    /// Like `Full`, also waiting for the removal of rollback journals.
+    Extra,
+}
+
+impl fmt::Display for SqliteSynchronous {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Off => "OFF",
+            Self::Normal => "NORMAL",
+            Self::Full => "FULL",
+            Self::Extra => "EXTRA",
+        })
+    }
+}
+
+/// Pragmas to set on sqlite connections. Those that aren't set are left to
+/// the defaults of sqlite.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SqliteConnectionOptions {
+    journal_mode: Option<SqliteJournalMode>,
+    synchronous: Option<SqliteSynchronous>,
+    busy_timeout: Option<Duration>,
+    foreign_keys: Option<bool>,
+    cache_size: Option<i64>,
+}
+
+impl SqliteConnectionOptions {
+    /// Create options leaving every pragma to its default.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the journal mode of the database. As the journal mode is a
+    /// property of the database rather than of a connection, it is only set
+    /// on the connection used for writes.
+    pub fn with_journal_mode(mut self, journal_mode: SqliteJournalMode) -> Self {
+        self.journal_mode = Some(journal_mode);
+        self
+    }
+
+    /// Set how often sqlite waits for writes to reach the disk.
+    pub fn with_synchronous(mut self, synchronous: SqliteSynchronous) -> Self {
+        self.synchronous = Some(synchronous);
+        self
+    }
+
+    /// Set how long a connection waits for a lock held by another connection
+    /// to the same database before failing with `SQLITE_BUSY`.
+    pub fn with_busy_timeout(mut self, busy_timeout: Duration) -> Self {
+        self.busy_timeout = Some(busy_timeout);
+        self
+    }
+
+    /// Set whether foreign key constraints are enforced.
+    pub fn with_foreign_keys(mut self, foreign_keys: bool) -> Self {
+        self.foreign_keys = Some(foreign_keys);
+        self
+    }
+
+    /// Set the size of the page cache of each connection, in pages if
+    /// positive or in KiB if negative, see the `cache_size` pragma.
+    pub fn with_cache_size(mut self, cache_size: i64) -> Self {
+        self.cache_size = Some(cache_size);
+        self
+    }
+
+    /// Return the journal mode to set, if any.
+    pub fn journal_mode(&self) -> Option<SqliteJournalMode> {
+        self.journal_mode
+    }
+
+    /// Set the pragmas on the connection used for writes.
+    pub fn apply(&self, con: &SqliteConnection) -> Result<()> {
+        if let Some(journal_mode) = self.journal_mode {
+            let actual: String = con
+                .query_row(
+                    &format!("PRAGMA journal_mode = {}", journal_mode),
+                    [],
+                    |row| row.get(0),
+                )
+                .context("Failed to set sqlite journal_mode")?;
+            if !actual.eq_ignore_ascii_case(&journal_mode.to_string()) {
+                bail!(
+                    "Failed to set sqlite journal_mode to {}, journal mode is {}",
+                    journal_mode,
+                    actual
+                );
+            }
+        }
+        self.apply_to_reader(con)
+    }
+
+    /// Set the pragmas on a read-only connection, which are those of
+    /// [Self::apply] but the journal mode.
+    pub fn apply_to_reader(&self, con: &SqliteConnection) -> Result<()> {
+        if let Some(synchronous) = self.synchronous {
+            con.execute_batch(&format!("PRAGMA synchronous = {}", synchronous))
+                .context("Failed to set sqlite synchronous")?;
+        }
+        if let Some(busy_timeout) = self.busy_timeout {
+            con.busy_timeout(busy_timeout)
+                .context("Failed to set sqlite busy_timeout")?;
+        }
+        if let Some(foreign_keys) = self.foreign_keys {
+            con.execute_batch(&format!("PRAGMA foreign_keys = {}", foreign_keys as u8))
+                .context("Failed to set sqlite foreign_keys")?;
+        }
+        if let Some(cache_size) = self.cache_size {
+            con.execute_batch(&format!("PRAGMA cache_size = {}", cache_size))
+                .context("Failed to set sqlite cache_size")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pragma(con: &SqliteConnection, name: &str) -> i64 {
+        con.query_row(&format!("PRAGMA {}", name), [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_apply() {
+        let con = SqliteConnection::open_in_memory().unwrap();
+        let options = SqliteConnectionOptions::new()
+            .with_journal_mode(SqliteJournalMode::Memory)
+            .with_synchronous(SqliteSynchronous::Off)
+            .with_busy_timeout(Duration::from_millis(1500))
+            .with_foreign_keys(true)
+            .with_cache_size(-4096);
+        options.apply(&con).unwrap();
+        assert_eq!(pragma(&con, "synchronous"), 0);
+        assert_eq!(pragma(&con, "busy_timeout"), 1500);
+        assert_eq!(pragma(&con, "foreign_keys"), 1);
+        assert_eq!(pragma(&con, "cache_size"), -4096);
+
+        // In-memory databases can only use the MEMORY or OFF journal modes.
+        let options = options.with_journal_mode(SqliteJournalMode::Wal);
+        assert!(options.apply(&con).is_err());
+    }
+}
@@ -0,0 +1,51 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module with the error returned by queries that take longer than their
+//! timeout.
+
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::Error;
+use thiserror::Error;
+
+/// Error returned by queries that didn't complete within their timeout.
+/// It can be told apart from other errors with `error.is::<QueryTimeout>()`.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+#[error("Query did not complete within {timeout:?}")]
+pub struct QueryTimeout {
+    /// The timeout of the query.
+    pub timeout: Duration,
+}
+
+/// Error returned when no connection of a pool became available within the
+/// acquire timeout of the pool.
+/// It can be told apart from other errors with `error.is::<AcquireTimeout>()`.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+#[error("No connection became available within {timeout:?}")]
+pub struct AcquireTimeout {
+    /// The acquire timeout of the pool.
+    pub timeout: Duration,
+}
+
+/// Run the query, failing with [QueryTimeout] if a timeout is given and the
+/// query doesn't complete within it. The query is dropped when it times out.
+pub async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    query: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    match timeout {
+        None => query.await,
+        Some(timeout) => match tokio::time::timeout(timeout, query).await {
+            Ok(result) => result,
+            Err(_) => Err(QueryTimeout { timeout }.into()),
+        },
+    }
+}
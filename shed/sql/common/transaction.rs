@@ -9,23 +9,122 @@
 
 //! Module that provides support for SQL transactions to this library.
 
+use std::sync::Arc;
+
+use anyhow::bail;
 use anyhow::Error;
 use futures::future::TryFutureExt;
+use mysql_async::prelude::Queryable;
+pub use mysql_async::IsolationLevel;
+use mysql_async::TxOpts;
 
+use crate::error::SqlError;
+use crate::metrics::ConnectionMetrics;
+use crate::metrics::OpenTransaction;
 use crate::mysql;
+use crate::observer::observe_transaction;
+use crate::observer::TransactionOperation;
 use crate::sqlite::SqliteConnectionGuard;
 use crate::sqlite::SqliteQueryType;
 
 impl crate::Connection {
     /// Start an SQL transaction for this connection. Refer to `transaction::Transaction` docs for
     /// more info
-    pub async fn start_transaction(&self) -> Result<Transaction, Error> {
+    pub async fn start_transaction(&self) -> Result<Transaction, SqlError> {
         Transaction::new(self).await
     }
+
+    /// Start an SQL transaction with the given options for this connection.
+    pub async fn start_transaction_with_options(
+        &self,
+        options: TransactionOptions,
+    ) -> Result<Transaction, SqlError> {
+        Transaction::new_with_options(self, options).await
+    }
+}
+
+/// Options of a transaction, used with `Connection::start_transaction_with_options`.
+///
+/// Sqlite transactions hold the only connection to the database, so they are
+/// always serializable and see a consistent snapshot whatever the options.
+/// Read-only ones are enforced with the `query_only` pragma, and the others
+/// requesting a consistent snapshot start with `BEGIN IMMEDIATE` to take the
+/// write lock of the database file right away.
+///
+/// The client behind `Connection::Mysql` only supports the READ COMMITTED
+/// isolation level, and starting a transaction with other options fails.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TransactionOptions {
+    isolation_level: Option<IsolationLevel>,
+    read_only: bool,
+    consistent_snapshot: bool,
+}
+
+impl TransactionOptions {
+    /// Create options for a read-write transaction with the default isolation
+    /// level of the database.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the isolation level of the transaction.
+    pub fn with_isolation_level(mut self, isolation_level: IsolationLevel) -> Self {
+        self.isolation_level = Some(isolation_level);
+        self
+    }
+
+    /// Make the transaction read-only, so that writes in it fail.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Start the transaction with a consistent snapshot, rather than taking
+    /// it on the first read.
+    pub fn with_consistent_snapshot(mut self, consistent_snapshot: bool) -> Self {
+        self.consistent_snapshot = consistent_snapshot;
+        self
+    }
+
+    fn sqlite_begin(&self) -> &'static str {
+        if self.read_only {
+            "PRAGMA query_only = 1; BEGIN DEFERRED"
+        } else if self.consistent_snapshot {
+            "BEGIN IMMEDIATE"
+        } else {
+            "BEGIN DEFERRED"
+        }
+    }
+
+    fn mysql_isolation_level(&self) -> Result<Option<mysql::IsolationLevel>, Error> {
+        if self.read_only || self.consistent_snapshot {
+            bail!(
+                "Read-only and consistent snapshot transactions are not supported by this client"
+            );
+        }
+        match self.isolation_level {
+            None => Ok(None),
+            Some(IsolationLevel::ReadCommitted) => Ok(Some(mysql::IsolationLevel::ReadCommitted)),
+            Some(level) => bail!("Isolation level {} is not supported by this client", level),
+        }
+    }
+
+    fn tx_opts(&self) -> TxOpts {
+        let mut tx_opts = TxOpts::default();
+        tx_opts
+            .with_isolation_level(self.isolation_level)
+            .with_consistent_snapshot(self.consistent_snapshot);
+        if self.read_only {
+            tx_opts.with_readonly(true);
+        }
+        tx_opts
+    }
 }
 
 /// Enum for generalizing transactions over Sqlite and MyRouter.
 ///
+/// Its operations fail with a [SqlError], like the queries run in it.
+///
 /// # Example
 /// ```
 /// use anyhow::Error;
@@ -51,7 +150,8 @@ impl crate::Connection {
 ///         MySelect::query_with_transaction(transaction, &A, &44).await?;
 ///     let (transaction, write_result) =
 ///         MyInsert::query_with_transaction(transaction, &[(&2,)]).await?;
-///     transaction.commit().await
+///     transaction.commit().await?;
+///     Ok(())
 /// }
 /// #
 /// # fn main() {}
@@ -66,39 +166,114 @@ pub enum Transaction {
     Sqlite(Option<SqliteConnectionGuard>),
     /// A variant used for the internal Mysql client connection.
     Mysql(Option<mysql::Transaction>),
-    /// A variant used for the external Mysql client connection.
-    OssMysql(Option<mysql_async::Transaction<'static>>),
+    /// A variant used for the external Mysql client connection, with the
+    /// schema variant of the connection the transaction was started on, and
+    /// the transaction counted in the stats of the connection if it has any.
+    OssMysql(
+        Option<mysql_async::Transaction<'static>>,
+        Option<Arc<str>>,
+        Option<OpenTransaction>,
+    ),
 }
 
 impl Transaction {
     /// Create a new transaction for the provided connection.
-    pub async fn new(connection: &super::Connection) -> Result<Transaction, Error> {
+    pub async fn new(connection: &super::Connection) -> Result<Transaction, SqlError> {
+        Self::new_with_options(connection, TransactionOptions::default()).await
+    }
+
+    /// Create a new transaction with the given options for the provided
+    /// connection.
+    pub async fn new_with_options(
+        connection: &super::Connection,
+        options: TransactionOptions,
+    ) -> Result<Transaction, SqlError> {
+        observe_transaction(
+            TransactionOperation::Begin,
+            Self::begin(connection, options),
+        )
+        .await
+        .map_err(SqlError::from)
+    }
+
+    async fn begin(
+        connection: &super::Connection,
+        options: TransactionOptions,
+    ) -> Result<Transaction, Error> {
         match connection {
             super::Connection::Sqlite(con) => {
-                let con = con
+                let metrics = con.metrics();
+                let mut con = con
                     .acquire_sqlite_connection(SqliteQueryType::Transaction)
                     .await?;
-                // Transactions in SQLite are always SERIALIZABLE; no transaction options.
-                con.execute_batch("BEGIN DEFERRED")?;
+                // Transactions in SQLite are always SERIALIZABLE, so only
+                // read-only and consistent snapshot options apply.
+                if let Err(err) = con.execute_batch(options.sqlite_begin()) {
+                    con.execute_batch("PRAGMA query_only = 0")?;
+                    return Err(err.into());
+                }
+                con.track_transaction(metrics);
                 Ok(Transaction::Sqlite(Some(con)))
             }
             super::Connection::Mysql(conn) => {
-                let transaction = conn.begin_transaction().map_err(Error::from).await?;
+                let isolation_level = options.mysql_isolation_level()?;
+                let transaction = if isolation_level.is_some() {
+                    let mut conn = conn.clone();
+                    conn.set_isolation_level(isolation_level);
+                    conn.begin_transaction().map_err(Error::from).await?
+                } else {
+                    conn.begin_transaction().map_err(Error::from).await?
+                };
                 Ok(Transaction::Mysql(Some(transaction)))
             }
             super::Connection::OssMysql(conn) => {
-                let transaction = conn
-                    .begin_transaction(mysql_async::TxOpts::default())
-                    .await?;
-                Ok(Transaction::OssMysql(Some(transaction)))
+                let transaction = conn.begin_transaction(options.tx_opts()).await?;
+                Ok(Transaction::OssMysql(
+                    Some(transaction),
+                    conn.schema_variant().map(Arc::from),
+                    conn.metrics().map(ConnectionMetrics::transaction_started),
+                ))
+            }
+        }
+    }
+
+    /// The schema variant of the connection the transaction was started on,
+    /// see [super::Connection::schema_variant].
+    pub fn schema_variant(&self) -> Option<&str> {
+        match self {
+            Transaction::Sqlite(..) | Transaction::Mysql(..) => None,
+            Transaction::OssMysql(_, schema_variant, _) => schema_variant.as_deref(),
+        }
+    }
+
+    /// The stats of the connection the transaction was started on, if any,
+    /// see [super::Connection::with_metrics_label].
+    pub fn metrics(&self) -> Option<&ConnectionMetrics> {
+        match self {
+            Transaction::Sqlite(con) => con.as_ref()?.transaction_metrics(),
+            Transaction::Mysql(..) => None,
+            Transaction::OssMysql(_, _, open_transaction) => {
+                open_transaction.as_ref().map(OpenTransaction::metrics)
             }
         }
     }
 
     /// Perform a commit on this transaction
-    pub async fn commit(mut self) -> Result<(), Error> {
+    pub async fn commit(self) -> Result<(), SqlError> {
+        observe_transaction(TransactionOperation::Commit, self.commit_inner())
+            .await
+            .map_err(SqlError::from)
+    }
+
+    async fn commit_inner(mut self) -> Result<(), Error> {
         match self {
             Transaction::Sqlite(ref mut tr_con) => {
+                // Read-only transactions make the connection read-only until
+                // they end.
+                tr_con
+                    .as_ref()
+                    .unwrap()
+                    .execute_batch("PRAGMA query_only = 0")?;
                 let con = tr_con.take().unwrap();
                 match con.commit().await {
                     Ok(()) => Ok(()),
@@ -114,15 +289,90 @@ impl Transaction {
                 let tr = tr.take().expect("Called commit after drop");
                 Ok(tr.commit().await?)
             }
-            Transaction::OssMysql(ref mut tr) => {
+            Transaction::OssMysql(ref mut tr, ..) => {
                 let tr = tr.take().expect("Called rollback after drop");
                 Ok(tr.commit().await?)
             }
         }
     }
 
+    /// Create a savepoint with the given name in this transaction, which can
+    /// later be released or rolled back to without ending the transaction.
+    /// Names must be made of ASCII letters, digits and underscores only.
+    pub async fn savepoint(&mut self, name: &str) -> Result<(), SqlError> {
+        self.execute_savepoint_statement("SAVEPOINT", name)
+            .await
+            .map_err(SqlError::from)
+    }
+
+    /// Release the savepoint with the given name, keeping the changes made
+    /// since it was created as part of the transaction.
+    pub async fn release_savepoint(&mut self, name: &str) -> Result<(), SqlError> {
+        self.execute_savepoint_statement("RELEASE SAVEPOINT", name)
+            .await
+            .map_err(SqlError::from)
+    }
+
+    /// Roll back the changes made since the savepoint with the given name was
+    /// created. The savepoint is kept, so it can be rolled back to again.
+    pub async fn rollback_to_savepoint(&mut self, name: &str) -> Result<(), SqlError> {
+        self.execute_savepoint_statement("ROLLBACK TO SAVEPOINT", name)
+            .await
+            .map_err(SqlError::from)
+    }
+
+    async fn execute_savepoint_statement(
+        &mut self,
+        statement: &str,
+        name: &str,
+    ) -> Result<(), Error> {
+        // Savepoint names can't be bound as parameters, so only allow plain
+        // identifiers in the query.
+        if name.is_empty()
+            || name.starts_with(|c: char| c.is_ascii_digit())
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            bail!("Invalid savepoint name {:?}", name);
+        }
+        self.execute_statement(&format!("{} {}", statement, name))
+            .await
+    }
+
+    /// Run the statement, which returns no rows, in this transaction.
+    pub(crate) async fn execute_statement(&mut self, query: &str) -> Result<(), Error> {
+        match self {
+            Transaction::Sqlite(ref mut con) => {
+                let con = con
+                    .as_mut()
+                    .expect("should be Some before transaction ended");
+                con.execute_batch(query)?;
+            }
+            Transaction::Mysql(ref mut tr) => {
+                let tr = tr
+                    .as_mut()
+                    .expect("should be Some before transaction ended");
+                tr.write_query(query.to_owned())
+                    .map_err(Error::from)
+                    .await?;
+            }
+            Transaction::OssMysql(ref mut tr, ..) => {
+                let tr = tr
+                    .as_mut()
+                    .expect("should be Some before transaction ended");
+                tr.query_drop(query).await?;
+            }
+        }
+        Ok(())
+    }
+
     /// Perform a rollback on this transaction
-    pub async fn rollback(mut self) -> Result<(), Error> {
+    pub async fn rollback(self) -> Result<(), SqlError> {
+        observe_transaction(TransactionOperation::Rollback, self.rollback_inner())
+            .await
+            .map_err(SqlError::from)
+    }
+
+    async fn rollback_inner(mut self) -> Result<(), Error> {
         match self {
             // Sqlite will rollback on drop
             Transaction::Sqlite(..) => Ok(()),
@@ -130,7 +380,7 @@ impl Transaction {
                 let tr = tr.take().expect("Called rollback after drop");
                 Ok(tr.rollback().await?)
             }
-            Transaction::OssMysql(ref mut tr) => {
+            Transaction::OssMysql(ref mut tr, ..) => {
                 let tr = tr.take().expect("Called rollback after drop");
                 Ok(tr.rollback().await?)
             }
@@ -149,11 +399,11 @@ impl Drop for Transaction {
                     return;
                 };
 
-                if let Err(err) = con.execute_batch("ROLLBACK") {
+                if let Err(err) = con.execute_batch("ROLLBACK; PRAGMA query_only = 0") {
                     panic!("Rollback on drop of Sqlite connection has failed: {err:#?}");
                 }
             }
-            Transaction::Mysql(_) | Transaction::OssMysql(_) => {}
+            Transaction::Mysql(_) | Transaction::OssMysql(..) => {}
         }
     }
 }
@@ -0,0 +1,163 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Warming up the pools of [SqlConnections] when they are created, so that
+//! the first burst of queries after a service starts doesn't have to wait for
+//! connections to be established.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+
+use anyhow::Context as _;
+use anyhow::Error;
+use futures::future::try_join_all;
+use futures::future::BoxFuture;
+use futures::future::FutureExt;
+use futures::future::Shared;
+
+use crate::config::SqlConnectionsConfig;
+use crate::Connection;
+use crate::SqlConnections;
+
+impl Connection {
+    /// Establish `connections` connections of the pool at the same time and
+    /// check that each of them is able to serve queries, before returning
+    /// them to the pool. Fails if any of them can't be established in time,
+    /// see [ConnectionConfig::acquire_timeout](crate::config::ConnectionConfig::acquire_timeout).
+    ///
+    /// `connections` must not be above the maximum number of connections of
+    /// the pool. As sqlite connections are opened upfront, they are only
+    /// checked.
+    pub async fn warm_up(&self, connections: usize) -> Result<(), Error> {
+        match self {
+            Connection::OssMysql(conn) => {
+                // The connections are held until all are established so that
+                // they are distinct.
+                let established = try_join_all((0..connections).map(|_| async {
+                    let mut con = conn.checkout().await?;
+                    conn.ping(&mut con).await?;
+                    Ok::<_, Error>(con)
+                }))
+                .await?;
+                drop(established);
+            }
+            Connection::Sqlite(..) | Connection::Mysql(..) => {
+                if connections > 0 {
+                    self.ping_query().await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SqlConnections {
+    /// Warm up the connections of each role with the number of connections
+    /// of its configuration, see [Connection::warm_up].
+    pub async fn warm_up(&self, config: &SqlConnectionsConfig) -> Result<(), Error> {
+        futures::try_join!(
+            self.write_connection
+                .warm_up(config.write.warm_connections())
+                .map(|result| result.context("While warming up write connections")),
+            self.read_connection
+                .warm_up(config.read.warm_connections())
+                .map(|result| result.context("While warming up read connections")),
+            self.read_master_connection
+                .warm_up(config.read_master.warm_connections())
+                .map(|result| result.context("While warming up read master connections")),
+        )?;
+        Ok(())
+    }
+
+    /// Warm up the connections in the background, see [Self::warm_up],
+    /// returning a future resolving once they are ready. The connections can
+    /// be used in the meantime, e.g. by services that only wait for the
+    /// future before reporting themselves ready.
+    pub fn spawn_warm_up(&self, config: &SqlConnectionsConfig) -> WarmUp {
+        let connections = self.clone();
+        let config = config.clone();
+        let handle = tokio::spawn(async move { connections.warm_up(&config).await });
+        let ready = async move {
+            match handle.await {
+                Ok(result) => result.map_err(Arc::new),
+                Err(err) => Err(Arc::new(Error::from(err).context("Warm up task failed"))),
+            }
+        };
+        WarmUp {
+            ready: ready.boxed().shared(),
+        }
+    }
+}
+
+/// Future returned by [SqlConnections::spawn_warm_up], resolving once the
+/// connections are warmed up. Clones resolve with the same result.
+#[derive(Clone)]
+pub struct WarmUp {
+    ready: Shared<BoxFuture<'static, Result<(), Arc<Error>>>>,
+}
+
+impl WarmUp {
+    /// Whether the connections are warmed up, without waiting. Returns
+    /// `false` if warming them up failed.
+    pub fn is_ready(&self) -> bool {
+        matches!(self.ready.peek(), Some(Ok(())))
+    }
+}
+
+impl Future for WarmUp {
+    type Output = Result<(), Arc<Error>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.ready.poll_unpin(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mysql_async::Pool;
+
+    use super::*;
+    use crate::config::ConnectionConfig;
+    use crate::mysql::ConnectionStats;
+    use crate::mysql::OssConnection;
+    use crate::sqlite::SqliteMultithreaded;
+
+    fn config(warm_connections: usize) -> SqlConnectionsConfig {
+        let config = ConnectionConfig {
+            warm_connections: Some(warm_connections),
+            ..Default::default()
+        };
+        SqlConnectionsConfig {
+            write: config.clone(),
+            read: config.clone(),
+            read_master: config,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_warm_up() {
+        let sqlite = rusqlite::Connection::open_in_memory().unwrap();
+        let connections = SqlConnections::new_single(SqliteMultithreaded::new(sqlite).into());
+        let warm_up = connections.spawn_warm_up(&config(2));
+        warm_up.clone().await.unwrap();
+        assert!(warm_up.is_ready());
+
+        // Nothing listens on port 1, so connections are refused.
+        let pool = Pool::new("mysql://127.0.0.1:1/db");
+        let stats = Arc::new(ConnectionStats::new("test".to_owned()));
+        let connections = SqlConnections::new_single(OssConnection::new(pool, stats).into());
+        let warm_up = connections.spawn_warm_up(&config(2));
+        assert!(warm_up.clone().await.is_err());
+        assert!(!warm_up.is_ready());
+        connections.warm_up(&config(0)).await.unwrap();
+    }
+}
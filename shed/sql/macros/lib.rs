@@ -0,0 +1,1782 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module introduces the proc macro implementing `sql::queries!`.
+//!
+//! The macro parses query definitions, checks the placeholders of every
+//! query against its parameters and then generates a module per query. The
+//! backend specific parts of those modules are left to the `_read_query_impl`
+//! and `_write_query_impl` macros of the sql crate.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use proc_macro2::TokenStream as TokenStream2;
+use proc_macro2::TokenTree;
+use quote::quote;
+use syn::braced;
+use syn::ext::IdentExt;
+use syn::parenthesized;
+use syn::parse::Error;
+use syn::parse::Parse;
+use syn::parse::ParseStream;
+use syn::parse::Result;
+use syn::parse_macro_input;
+use syn::punctuated::Punctuated;
+use syn::token::Paren;
+use syn::Data;
+use syn::DataStruct;
+use syn::DeriveInput;
+use syn::Expr;
+use syn::Fields;
+use syn::Ident;
+use syn::Lit;
+use syn::LitInt;
+use syn::LitStr;
+use syn::Token;
+use syn::Type;
+use syn::Visibility;
+
+mod kw {
+    syn::custom_keyword!(read);
+    syn::custom_keyword!(read_paged);
+    syn::custom_keyword!(write);
+    syn::custom_keyword!(list);
+    syn::custom_keyword!(maybe);
+    syn::custom_keyword!(values);
+    syn::custom_keyword!(chunk_size);
+    syn::custom_keyword!(mysql);
+    syn::custom_keyword!(sqlite);
+}
+
+/// Implementation of `sql::queries!`, which passes its `$crate` as the first
+/// token followed by a semicolon. Use `sql::queries!` instead.
+#[doc(hidden)]
+#[proc_macro]
+pub fn _queries_impl(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as QueriesInput);
+    input.expand().into()
+}
+
+/// Derive `sql::FromRow` for a struct with named fields, reading each field
+/// from the column at its position in the row.
+#[proc_macro_derive(FromRow)]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_from_row(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+struct QueriesInput {
+    krate: TokenTree,
+    queries: Vec<Query>,
+}
+
+struct Query {
+    vis: Visibility,
+    name: Ident,
+    params: Vec<Param>,
+    lists: Vec<Param>,
+    maybes: Vec<MaybeParam>,
+    kind: QueryKind,
+    body: QueryBody,
+    /// The type of the key of `read_paged` queries, returned as the first
+    /// column of their rows. Their `page_limit` parameter and `page_after`
+    /// `>maybe` parameter are the last of `params` and `maybes`.
+    paged: Option<Type>,
+}
+
+enum QueryKind {
+    Read {
+        returns: Returns,
+    },
+    Write {
+        qtype: Ident,
+        /// The columns updated by `insert_or_update(...)` queries.
+        update_columns: Option<Vec<Ident>>,
+        values: Option<Vec<Param>>,
+        /// The maximum number of rows of `values` inserted by each statement,
+        /// declared with `chunk_size(...)`.
+        chunk_size: Option<LitInt>,
+        /// What the rows returned by a `RETURNING` clause are read as.
+        returns: Option<Returns>,
+    },
+}
+
+/// What a read query returns each row as.
+enum Returns {
+    /// A tuple of the given types, one per column.
+    Tuple(Vec<Type>),
+    /// A type implementing `sql::FromRow`.
+    Row(Box<Type>),
+}
+
+struct Param {
+    name: Ident,
+    ty: Type,
+}
+
+/// An optional parameter, whose fragment is interpolated into the query only
+/// when the parameter is given.
+struct MaybeParam {
+    name: Ident,
+    ty: Type,
+    fragment: LitStr,
+}
+
+/// The query for each backend, `sqlite` being `None` when the `mysql` one
+/// is shared by all backends.
+struct QueryBody {
+    mysql: Expr,
+    /// The MySQL queries of the schema variants, e.g. `mysql8("...")`, run
+    /// instead of `mysql` on connections with that schema variant.
+    variants: Vec<(Ident, Expr)>,
+    sqlite: Option<Expr>,
+}
+
+impl Parse for QueriesInput {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let krate = input.parse()?;
+        input.parse::<Token![;]>()?;
+
+        let mut queries = Vec::new();
+        while !input.is_empty() {
+            queries.push(Query::parse(input, &krate)?);
+        }
+        Ok(Self { krate, queries })
+    }
+}
+
+impl Query {
+    /// Parse a query, referring to the items of the sql crate through
+    /// `krate`.
+    fn parse(input: ParseStream, krate: &TokenTree) -> Result<Self> {
+        let vis = input.parse()?;
+        let lookahead = input.lookahead1();
+        let is_paged = lookahead.peek(kw::read_paged);
+        let is_read = if lookahead.peek(kw::read) {
+            input.parse::<kw::read>()?;
+            true
+        } else if is_paged {
+            input.parse::<kw::read_paged>()?;
+            true
+        } else if lookahead.peek(kw::write) {
+            input.parse::<kw::write>()?;
+            false
+        } else {
+            return Err(lookahead.error());
+        };
+        let name = input.parse()?;
+
+        let content;
+        parenthesized!(content in input);
+        let (values, params, lists, maybes) = if !is_read
+            && content.peek(kw::values)
+            && content.peek2(Token![:])
+            && content.peek3(Paren)
+        {
+            let (values, params, lists) = parse_values_params(&content)?;
+            (Some(values), params, lists, Vec::new())
+        } else {
+            let (params, lists, maybes) = parse_params(&content)?;
+            (None, params, lists, maybes)
+        };
+
+        let returns = if is_read || input.peek(Token![->]) {
+            input.parse::<Token![->]>()?;
+            if input.peek(Paren) {
+                let returns;
+                parenthesized!(returns in input);
+                let returns = Punctuated::<Type, Token![,]>::parse_terminated(&returns)?;
+                Some(Returns::Tuple(returns.into_iter().collect()))
+            } else {
+                Some(Returns::Row(input.parse()?))
+            }
+        } else {
+            None
+        };
+
+        let content;
+        braced!(content in input);
+        if is_paged {
+            return parse_paged(krate, vis, name, params, lists, maybes, returns, &content);
+        }
+        let kind = match returns {
+            Some(returns) if is_read => QueryKind::Read { returns },
+            returns => {
+                let qtype = content.parse()?;
+                let update_columns = if content.peek(Paren) {
+                    let columns;
+                    parenthesized!(columns in content);
+                    let columns = Punctuated::<Ident, Token![,]>::parse_terminated(&columns)?;
+                    Some(columns.into_iter().collect())
+                } else {
+                    None
+                };
+                content.parse::<Token![,]>()?;
+                let chunk_size = if content.peek(kw::chunk_size) && content.peek2(Paren) {
+                    content.parse::<kw::chunk_size>()?;
+                    let chunk_size;
+                    parenthesized!(chunk_size in content);
+                    let chunk_size = chunk_size.parse()?;
+                    content.parse::<Token![,]>()?;
+                    Some(chunk_size)
+                } else {
+                    None
+                };
+                QueryKind::Write {
+                    qtype,
+                    update_columns,
+                    values,
+                    chunk_size,
+                    returns,
+                }
+            }
+        };
+        let body = content.parse()?;
+
+        Ok(Self {
+            vis,
+            name,
+            params,
+            lists,
+            maybes,
+            kind,
+            body,
+            paged: None,
+        })
+    }
+}
+
+/// Parse the rest of a `read_paged` query, from the key column in its body,
+/// into the read query of a page: the query is wrapped into a derived table
+/// filtered by the cursor, ordered by the key and limited to the page size.
+#[allow(clippy::too_many_arguments)]
+fn parse_paged(
+    krate: &TokenTree,
+    vis: Visibility,
+    name: Ident,
+    mut params: Vec<Param>,
+    lists: Vec<Param>,
+    mut maybes: Vec<MaybeParam>,
+    returns: Option<Returns>,
+    content: ParseStream,
+) -> Result<Query> {
+    let column: Ident = content.parse()?;
+    content.parse::<Token![,]>()?;
+    let mut body: QueryBody = content.parse()?;
+
+    let ty = match &returns {
+        Some(Returns::Tuple(types)) if !types.is_empty() => types[0].clone(),
+        _ => {
+            return Err(Error::new(
+                name.span(),
+                "`read_paged` queries must return a tuple whose first column is the key",
+            ));
+        }
+    };
+    let key = column.unraw();
+    let wrap = |query: &mut Expr| {
+        let prefix = "SELECT * FROM (";
+        let suffix = format!(") AS page {{page_after}} ORDER BY page.{key} LIMIT {{page_limit}}");
+        *query = match string_literal(query) {
+            Some(lit) => {
+                let wrapped = format!("{prefix}{}{suffix}", lit.value());
+                Expr::Lit(syn::ExprLit {
+                    attrs: Vec::new(),
+                    lit: Lit::Str(LitStr::new(&wrapped, lit.span())),
+                })
+            }
+            None => syn::parse_quote!(concat!(#prefix, #query, #suffix)),
+        };
+    };
+    wrap(&mut body.mysql);
+    for (_, query) in &mut body.variants {
+        wrap(query);
+    }
+    if let Some(sqlite) = &mut body.sqlite {
+        wrap(sqlite);
+    }
+
+    params.push(Param {
+        name: Ident::new("page_limit", name.span()),
+        ty: syn::parse_quote!(#krate::Limit),
+    });
+    maybes.push(MaybeParam {
+        name: Ident::new("page_after", name.span()),
+        ty: ty.clone(),
+        fragment: LitStr::new(&format!("WHERE page.{key} > {{page_after}}"), column.span()),
+    });
+
+    Ok(Query {
+        vis,
+        name,
+        params,
+        lists,
+        maybes,
+        kind: QueryKind::Read {
+            returns: returns.expect("checked above"),
+        },
+        body,
+        paged: Some(ty),
+    })
+}
+
+impl Parse for Param {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let ty = input.parse()?;
+        Ok(Self { name, ty })
+    }
+}
+
+impl Parse for MaybeParam {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let Param { name, ty } = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let fragment = input.parse()?;
+        Ok(Self { name, ty, fragment })
+    }
+}
+
+impl Parse for QueryBody {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if input.peek(kw::mysql) && input.peek2(Paren) {
+            input.parse::<kw::mysql>()?;
+            let mysql;
+            parenthesized!(mysql in input);
+            let mysql = mysql.parse()?;
+            let mut variants = Vec::new();
+            while !input.peek(kw::sqlite) && input.peek(Ident) && input.peek2(Paren) {
+                let name = input.parse()?;
+                let query;
+                parenthesized!(query in input);
+                variants.push((name, query.parse()?));
+            }
+            input.parse::<kw::sqlite>()?;
+            let sqlite;
+            parenthesized!(sqlite in input);
+            let sqlite = sqlite.parse()?;
+            Ok(Self {
+                mysql,
+                variants,
+                sqlite: Some(sqlite),
+            })
+        } else {
+            Ok(Self {
+                mysql: input.parse()?,
+                variants: Vec::new(),
+                sqlite: None,
+            })
+        }
+    }
+}
+
+/// The type of a write query as passed to `_write_query_impl`, which takes
+/// `insert_or_update` queries as `[insert_or_update "mysql" "sqlite"]` with the
+/// clause rendered for each backend.
+fn write_query_type(qtype: &Ident, update_columns: &Option<Vec<Ident>>) -> TokenStream2 {
+    match update_columns {
+        Some(columns) => {
+            let columns: Vec<_> = columns.iter().map(Ident::unraw).collect();
+            let mysql = columns
+                .iter()
+                .map(|column| format!("{column} = VALUES({column})"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let sqlite = columns
+                .iter()
+                .map(|column| format!("{column} = excluded.{column}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mysql = LitStr::new(&format!("ON DUPLICATE KEY UPDATE {mysql}"), qtype.span());
+            let sqlite = LitStr::new(&format!("ON CONFLICT DO UPDATE SET {sqlite}"), qtype.span());
+            quote!([#qtype #mysql #sqlite])
+        }
+        None => quote!(#qtype),
+    }
+}
+
+/// Parse `name: Type, ... >list name: Type ... >maybe name: Type = "..." ...`.
+fn parse_params(input: ParseStream) -> Result<(Vec<Param>, Vec<Param>, Vec<MaybeParam>)> {
+    let mut params = Vec::new();
+    let mut lists = Vec::new();
+    let mut maybes = Vec::new();
+    while !input.is_empty() {
+        if input.peek(Token![>]) {
+            input.parse::<Token![>]>()?;
+            let lookahead = input.lookahead1();
+            if lookahead.peek(kw::list) {
+                input.parse::<kw::list>()?;
+                let list: Param = input.parse()?;
+                if !maybes.is_empty() {
+                    return Err(Error::new(
+                        list.name.span(),
+                        "`>maybe` parameters must come after all other parameters",
+                    ));
+                }
+                lists.push(list);
+            } else if lookahead.peek(kw::maybe) {
+                input.parse::<kw::maybe>()?;
+                maybes.push(input.parse()?);
+            } else {
+                return Err(lookahead.error());
+            }
+            if input.peek(Token![,]) {
+                input.parse::<Token![,]>()?;
+            }
+        } else {
+            let param: Param = input.parse()?;
+            if !lists.is_empty() || !maybes.is_empty() {
+                return Err(Error::new(
+                    param.name.span(),
+                    "`>list` and `>maybe` parameters must come after all other parameters",
+                ));
+            }
+            params.push(param);
+            if !input.is_empty() && !input.peek(Token![>]) {
+                input.parse::<Token![,]>()?;
+            }
+        }
+    }
+    Ok((params, lists, maybes))
+}
+
+/// Parse `values: (name: Type, ...), name: Type, ... >list name: Type ...`.
+fn parse_values_params(input: ParseStream) -> Result<(Vec<Param>, Vec<Param>, Vec<Param>)> {
+    input.parse::<kw::values>()?;
+    input.parse::<Token![:]>()?;
+    let values;
+    parenthesized!(values in input);
+    let values = Punctuated::<Param, Token![,]>::parse_terminated(&values)?;
+
+    if !input.is_empty() {
+        input.parse::<Token![,]>()?;
+    }
+    let (params, lists, maybes) = parse_params(input)?;
+    if let Some(maybe) = maybes.first() {
+        return Err(Error::new(
+            maybe.name.span(),
+            "`>maybe` parameters can't be used with `values`",
+        ));
+    }
+    Ok((values.into_iter().collect(), params, lists))
+}
+
+impl QueriesInput {
+    fn expand(self) -> TokenStream2 {
+        let krate = &self.krate;
+        self.queries
+            .iter()
+            .map(|query| {
+                query
+                    .check()
+                    .map(|()| query.expand(krate))
+                    .unwrap_or_else(Error::into_compile_error)
+            })
+            .collect()
+    }
+}
+
+impl Query {
+    /// Check that the placeholders of string literal queries match the
+    /// parameters of the query, as `format!` would otherwise report the
+    /// mismatch from deep inside the expansion of `queries!`.
+    fn check(&self) -> Result<()> {
+        if self.paged.is_some() {
+            let user_names = self.params[..self.params.len() - 1]
+                .iter()
+                .chain(&self.lists)
+                .map(|param| &param.name)
+                .chain(
+                    self.maybes[..self.maybes.len() - 1]
+                        .iter()
+                        .map(|maybe| &maybe.name),
+                );
+            for name in user_names {
+                if name == "page_after" || name == "page_limit" {
+                    return Err(Error::new(
+                        name.span(),
+                        format!("`{}` is reserved by `read_paged` queries", name),
+                    ));
+                }
+            }
+        }
+        let mut names: Vec<&Ident> = self.params.iter().map(|param| &param.name).collect();
+        names.extend(self.lists.iter().map(|param| &param.name));
+        names.extend(self.maybes.iter().map(|param| &param.name));
+        let mut implicit = Vec::new();
+        if let QueryKind::Write {
+            qtype,
+            update_columns,
+            values,
+            chunk_size,
+            returns,
+        } = &self.kind
+        {
+            if values.is_some() && returns.is_some() {
+                return Err(Error::new(
+                    qtype.span(),
+                    "write queries taking `values` can't return rows",
+                ));
+            }
+            if let Some(chunk_size) = chunk_size {
+                if values.is_none() {
+                    return Err(Error::new(
+                        chunk_size.span(),
+                        "`chunk_size` can only be used with `values`",
+                    ));
+                }
+                if chunk_size.base10_parse::<usize>()? == 0 {
+                    return Err(Error::new(
+                        chunk_size.span(),
+                        "`chunk_size` must be greater than zero",
+                    ));
+                }
+            }
+            if qtype == "insert_or_update" {
+                match update_columns {
+                    Some(columns) if !columns.is_empty() => {}
+                    _ => {
+                        return Err(Error::new(
+                            qtype.span(),
+                            "`insert_or_update` takes the columns to update, \
+                             e.g. `insert_or_update(value)`",
+                        ));
+                    }
+                }
+                implicit.push("insert_or_update");
+            } else if update_columns.is_some() {
+                return Err(Error::new(
+                    qtype.span(),
+                    format!("`{}` doesn't take columns", qtype),
+                ));
+            } else if qtype == "insert_or_ignore" {
+                implicit.push("insert_or_ignore");
+            } else if qtype != "none" {
+                return Err(Error::new(
+                    qtype.span(),
+                    format!(
+                        "unknown write query type `{}`, expected `none`, `insert_or_ignore` or \
+                         `insert_or_update(...)`",
+                        qtype
+                    ),
+                ));
+            }
+            if values.is_some() {
+                implicit.push("values");
+            }
+        }
+
+        let mut errors: Option<Error> = None;
+        let mut add_error = |result: Result<()>| {
+            if let Err(err) = result {
+                match &mut errors {
+                    Some(errors) => errors.combine(err),
+                    None => errors = Some(err),
+                }
+            }
+        };
+        match &self.body.sqlite {
+            None => add_error(check_placeholders(
+                &self.body.mysql,
+                "the query",
+                &names,
+                &implicit,
+            )),
+            Some(sqlite) => {
+                add_error(check_placeholders(
+                    &self.body.mysql,
+                    "the mysql query",
+                    &names,
+                    &implicit,
+                ));
+                add_error(check_placeholders(
+                    sqlite,
+                    "the sqlite query",
+                    &names,
+                    &implicit,
+                ));
+            }
+        }
+        for (i, (variant, query)) in self.body.variants.iter().enumerate() {
+            if variant == "mysql"
+                || self.body.variants[..i]
+                    .iter()
+                    .any(|(other, _)| other == variant)
+            {
+                add_error(Err(Error::new(
+                    variant.span(),
+                    format!("duplicate query for `{}`", variant),
+                )));
+            }
+            add_error(check_placeholders(
+                query,
+                &format!("the `{}` query", variant),
+                &names,
+                &implicit,
+            ));
+        }
+        let typed: Vec<(&Ident, &Type)> = self
+            .params
+            .iter()
+            .map(|param| (&param.name, &param.ty))
+            .chain(self.maybes.iter().map(|maybe| (&maybe.name, &maybe.ty)))
+            .collect();
+        let queries = std::iter::once(&self.body.mysql)
+            .chain(&self.body.sqlite)
+            .chain(self.body.variants.iter().map(|(_, query)| query));
+        for query in queries {
+            if let Some(lit) = string_literal(query) {
+                add_error(check_limit_placeholders(lit, &typed));
+            }
+        }
+        for maybe in &self.maybes {
+            add_error(check_limit_placeholders(&maybe.fragment, &typed));
+        }
+        // The fragment of a `>maybe` parameter is interpolated on its own,
+        // so it can only use that parameter.
+        for maybe in &self.maybes {
+            add_error(check_literal_placeholders(
+                &maybe.fragment,
+                &format!("the fragment of `{}`", maybe.name),
+                &[&maybe.name],
+                &[],
+            ));
+        }
+
+        errors.map_or(Ok(()), Err)
+    }
+
+    fn expand(&self, krate: &TokenTree) -> TokenStream2 {
+        let vis = &self.vis;
+        let name = &self.name;
+        let pname: Vec<_> = self.params.iter().map(|param| &param.name).collect();
+        let ptype: Vec<_> = self.params.iter().map(|param| &param.ty).collect();
+        let lname: Vec<_> = self.lists.iter().map(|param| &param.name).collect();
+        let ltype: Vec<_> = self.lists.iter().map(|param| &param.ty).collect();
+        let mname: Vec<_> = self.maybes.iter().map(|param| &param.name).collect();
+        let mtype: Vec<_> = self.maybes.iter().map(|param| &param.ty).collect();
+        let mfrag: Vec<_> = self.maybes.iter().map(|param| &param.fragment).collect();
+        let mysql_q = &self.body.mysql;
+        let sqlite_q = self.body.sqlite.as_ref().unwrap_or(mysql_q);
+        let variant: Vec<_> = self
+            .body
+            .variants
+            .iter()
+            .map(|(variant, _)| LitStr::new(&variant.to_string(), variant.span()))
+            .collect();
+        let variant_q: Vec<_> = self.body.variants.iter().map(|(_, query)| query).collect();
+
+        // Names used by the generated code only, which must not clash with
+        // the parameters of the query.
+        let connection = Ident::new("connection", Span::mixed_site());
+        let comment = Ident::new("comment", Span::mixed_site());
+        let transaction = Ident::new("transaction", Span::mixed_site());
+        let values = Ident::new("values", Span::mixed_site());
+        let timeout = Ident::new("timeout", Span::mixed_site());
+        let cancellation = Ident::new("cancellation", Span::mixed_site());
+
+        let context = LitStr::new(&format!("While executing {} query", name), name.span());
+
+        let body = match &self.kind {
+            // Write queries returning rows are run like read queries.
+            QueryKind::Read { returns }
+            | QueryKind::Write {
+                returns: Some(returns),
+                ..
+            } => {
+                let write_qtype = match &self.kind {
+                    QueryKind::Write {
+                        qtype,
+                        update_columns,
+                        ..
+                    } => Some(write_query_type(qtype, update_columns)),
+                    QueryKind::Read { .. } => None,
+                };
+                let context_in_transaction = LitStr::new(
+                    &format!("While executing {} query in transaction", name),
+                    name.span(),
+                );
+                let (returns, row) = match returns {
+                    Returns::Tuple(types) => (quote!((#( #types ),*)), quote!((#( #types, )*))),
+                    Returns::Row(ty) => (quote!(row #ty), quote!(#ty)),
+                };
+                let (query_type, qtype, output, to_output, to_output_in_transaction) =
+                    match &write_qtype {
+                        Some(qtype) => (
+                            quote!(Write),
+                            qtype.clone(),
+                            quote!(#krate::WriteResultWithRows<#row>),
+                            quote!(.map(#krate::WriteResultWithRows::new)),
+                            quote! {
+                                .map(|(transaction, rows)| {
+                                    (transaction, #krate::WriteResultWithRows::new(rows))
+                                })
+                            },
+                        ),
+                        None => (
+                            quote!(Read),
+                            quote!(none),
+                            quote!(Vec<#row>),
+                            quote!(),
+                            quote!(),
+                        ),
+                    };
+                let render_args = quote!(#( #pname, )* #( #lname, )* #( #mname, )*);
+                let explain = explain(
+                    krate,
+                    name,
+                    quote!(#( #pname: &#ptype, )* #( #lname: &[#ltype], )* #( #mname: Option<&#mtype>, )*),
+                    &render_args,
+                );
+                let count = quote!(len() as u64);
+                let observe = |in_transaction, commented, call| {
+                    observe(name, &render_args, &count, in_transaction, commented, call)
+                };
+                let observed_query = observe(
+                    false,
+                    false,
+                    quote!(query_internal(#connection, None, None, None #( , #pname )* #( , #lname )* #( , #mname )*)),
+                );
+                let observed_commented = observe(
+                    false,
+                    true,
+                    quote!(query_internal(#connection, Some(#comment), None, None #( , #pname )* #( , #lname )* #( , #mname )*)),
+                );
+                let observed_timeout = observe(
+                    false,
+                    false,
+                    quote!(query_internal(#connection, None, Some(#timeout), None #( , #pname )* #( , #lname )* #( , #mname )*)),
+                );
+                let observed_cancellation = observe(
+                    false,
+                    false,
+                    quote!(query_internal(#connection, None, None, Some(#cancellation) #( , #pname )* #( , #lname )* #( , #mname )*)),
+                );
+                let observed_transaction = observe(
+                    true,
+                    false,
+                    quote!(query_internal_with_transaction(#transaction, None #( , #pname )* #( , #lname )* #( , #mname )*)),
+                );
+                let observed_commented_transaction = observe(
+                    true,
+                    true,
+                    quote!(query_internal_with_transaction(#transaction, Some(#comment) #( , #pname )* #( , #lname )* #( , #mname )*)),
+                );
+                let next_page = match &self.paged {
+                    Some(key) => self.expand_next_page(krate, key, &row),
+                    None => quote!(),
+                };
+                let cached_query = if write_qtype.is_none() {
+                    quote! {
+                        #[allow(dead_code)]
+                        pub async fn cached_query(
+                            #connection: &#krate::CachedConnection,
+                            #( #pname: &#ptype, )*
+                            #( #lname: &[#ltype], )*
+                            #( #mname: Option<&#mtype>, )*
+                        ) -> Result<std::sync::Arc<#output>, SqlError> {
+                            // The query rendered for MySQL has the values of
+                            // its parameters interpolated, so it is the key.
+                            #connection
+                                .read_cached(
+                                    module_path!(),
+                                    render_internal(#render_args).mysql,
+                                    query(#connection.connection() #( , #pname )* #( , #lname )* #( , #mname )*).map_err(SqlError::into_anyhow),
+                                )
+                                .await
+                                .map_err(SqlError::from)
+                        }
+                    }
+                } else {
+                    quote! {
+                        #[allow(dead_code)]
+                        pub async fn cached_query(
+                            #connection: &#krate::CachedConnection,
+                            #( #pname: &#ptype, )*
+                            #( #lname: &[#ltype], )*
+                            #( #mname: Option<&#mtype>, )*
+                        ) -> Result<#output, SqlError> {
+                            #connection
+                                .write_invalidating(
+                                    module_path!(),
+                                    query(#connection.connection() #( , #pname )* #( , #lname )* #( , #mname )*).map_err(SqlError::into_anyhow),
+                                )
+                                .await
+                                .map_err(SqlError::from)
+                        }
+                    }
+                };
+                let query_with_consistency = if write_qtype.is_none() {
+                    let connections = Ident::new("connections", Span::mixed_site());
+                    let consistency = Ident::new("consistency", Span::mixed_site());
+                    quote! {
+                        #[allow(dead_code)]
+                        pub async fn query_with_consistency(
+                            #connections: &#krate::SqlConnections,
+                            #consistency: #krate::lag::Consistency,
+                            #( #pname: &#ptype, )*
+                            #( #lname: &[#ltype], )*
+                            #( #mname: Option<&#mtype>, )*
+                        ) -> Result<#output, SqlError> {
+                            #connections
+                                .read_with_consistency(#consistency, |#connection| async move {
+                                    query(&#connection #( , #pname )* #( , #lname )* #( , #mname )*)
+                                        .await
+                                        .map_err(SqlError::into_anyhow)
+                                })
+                                .await
+                                .map_err(SqlError::from)
+                        }
+                    }
+                } else {
+                    quote!()
+                };
+                let query_stream = if write_qtype.is_none() {
+                    quote! {
+                        #[allow(dead_code)]
+                        pub fn query_stream(
+                            #connection: &Connection,
+                            #( #pname: &#ptype, )*
+                            #( #lname: &[#ltype], )*
+                            #( #mname: Option<&#mtype>, )*
+                        ) -> #krate::futures::stream::BoxStream<'static, Result<#row, SqlError>> {
+                            use #krate::futures::stream::StreamExt;
+
+                            query_stream_internal(#connection #( , #pname )* #( , #lname )* #( , #mname )*)
+                                .map(|row| row.context(#context).map_err(SqlError::from))
+                                .boxed()
+                        }
+                    }
+                } else {
+                    quote!()
+                };
+                quote! {
+                    #krate::_read_query_impl!((
+                        #( #pname: #ptype, )*
+                        #( >list #lname: #ltype )*
+                        #( >maybe #mname: #mtype = #mfrag )*
+                    ) -> #returns { #query_type #qtype, mysql(#mysql_q #( , #variant => #variant_q )*) sqlite(#sqlite_q) });
+
+                    #[allow(dead_code)]
+                    pub async fn query(
+                        #connection: &Connection,
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> Result<#output, SqlError> {
+                        #observed_query
+                            .await
+                            #to_output
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn commented_query(
+                        #connection: &Connection,
+                        #comment: &str,
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> Result<#output, SqlError> {
+                        #observed_commented
+                            .await
+                            #to_output
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn query_with_timeout(
+                        #connection: &Connection,
+                        #timeout: std::time::Duration,
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> Result<#output, SqlError> {
+                        #observed_timeout
+                            .await
+                            #to_output
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn query_with_cancellation(
+                        #connection: &Connection,
+                        #cancellation: &#krate::CancellationToken,
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> Result<#output, SqlError> {
+                        #observed_cancellation
+                            .await
+                            #to_output
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #query_stream
+
+                    #next_page
+
+                    #cached_query
+
+                    #query_with_consistency
+
+                    #[allow(dead_code)]
+                    pub fn render(
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> RenderedQuery {
+                        render_internal(#( #pname, )* #( #lname, )* #( #mname, )*)
+                    }
+
+                    #explain
+
+                    #[allow(dead_code)]
+                    pub async fn query_with_transaction(
+                        #transaction: Transaction,
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> Result<(Transaction, #output), SqlError> {
+                        #observed_transaction
+                            .await
+                            #to_output_in_transaction
+                            .context(#context_in_transaction)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn commented_query_with_transaction(
+                        #transaction: Transaction,
+                        #comment: &str,
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> Result<(Transaction, #output), SqlError> {
+                        #observed_commented_transaction
+                            .await
+                            #to_output_in_transaction
+                            .context(#context_in_transaction)
+                            .map_err(SqlError::from)
+                    }
+                }
+            }
+            QueryKind::Write {
+                qtype,
+                update_columns,
+                values: Some(value_params),
+                chunk_size,
+                ..
+            } => {
+                let qtype = write_query_type(qtype, update_columns);
+                let chunk_size = chunk_size.iter();
+                let vname: Vec<_> = value_params.iter().map(|param| &param.name).collect();
+                let vtype: Vec<_> = value_params.iter().map(|param| &param.ty).collect();
+                let render_args = quote!(#values #( , #pname )* #( , #lname )*);
+                let explain = explain(
+                    krate,
+                    name,
+                    quote!(#values: &[(#( &#vtype, )*)], #( #pname: &#ptype, )* #( #lname: &[#ltype], )*),
+                    &render_args,
+                );
+                let count = quote!(affected_rows());
+                let observe = |in_transaction, commented, call| {
+                    observe(name, &render_args, &count, in_transaction, commented, call)
+                };
+                let observed_query = observe(
+                    false,
+                    false,
+                    quote!(query_internal(#connection, None, None, None, #values #( , #pname )* #( , #lname )*)),
+                );
+                let observed_commented = observe(
+                    false,
+                    true,
+                    quote!(query_internal(#connection, Some(#comment), None, None, #values #( , #pname )* #( , #lname )*)),
+                );
+                let observed_timeout = observe(
+                    false,
+                    false,
+                    quote!(query_internal(#connection, None, Some(#timeout), None, #values #( , #pname )* #( , #lname )*)),
+                );
+                let observed_cancellation = observe(
+                    false,
+                    false,
+                    quote!(query_internal(#connection, None, None, Some(#cancellation), #values #( , #pname )* #( , #lname )*)),
+                );
+                let observed_transaction = observe(
+                    true,
+                    false,
+                    quote!(query_internal_with_transaction(#transaction, None, #values #( , #pname )* #( , #lname )*)),
+                );
+                let observed_commented_transaction = observe(
+                    true,
+                    true,
+                    quote!(query_internal_with_transaction(#transaction, Some(#comment), #values #( , #pname )* #( , #lname )*)),
+                );
+                quote! {
+                    #krate::_write_query_impl!(values: (#( #vname: #vtype ),*), (#( #pname: #ptype, )* #( >list #lname: #ltype )*) {
+                        #qtype,
+                        #( chunk_size(#chunk_size), )*
+                        mysql(#mysql_q #( , #variant => #variant_q )*)
+                        sqlite(#sqlite_q)
+                    });
+
+                    #[allow(dead_code)]
+                    pub async fn query(
+                        #connection: &Connection,
+                        #values: &[(#( &#vtype, )*)],
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                    ) -> Result<WriteResult, SqlError> {
+                        #observed_query
+                            .await
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn commented_query(
+                        #connection: &Connection,
+                        #comment: &str,
+                        #values: &[(#( &#vtype, )*)],
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                    ) -> Result<WriteResult, SqlError> {
+                        #observed_commented
+                            .await
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn query_with_timeout(
+                        #connection: &Connection,
+                        #timeout: std::time::Duration,
+                        #values: &[(#( &#vtype, )*)],
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                    ) -> Result<WriteResult, SqlError> {
+                        #observed_timeout
+                            .await
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn query_with_cancellation(
+                        #connection: &Connection,
+                        #cancellation: &#krate::CancellationToken,
+                        #values: &[(#( &#vtype, )*)],
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                    ) -> Result<WriteResult, SqlError> {
+                        #observed_cancellation
+                            .await
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn cached_query(
+                        #connection: &#krate::CachedConnection,
+                        #values: &[(#( &#vtype, )*)],
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                    ) -> Result<WriteResult, SqlError> {
+                        #connection
+                            .write_invalidating(
+                                module_path!(),
+                                query(#connection.connection(), #values #( , #pname )* #( , #lname )*).map_err(SqlError::into_anyhow),
+                            )
+                            .await
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub fn render(
+                        #values: &[(#( &#vtype, )*)],
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                    ) -> RenderedQuery {
+                        render_internal(#values #( , #pname )* #( , #lname )*)
+                    }
+
+                    #explain
+
+                    #[allow(dead_code)]
+                    pub async fn query_with_transaction(
+                        #transaction: Transaction,
+                        #values: &[(#( &#vtype, )*)],
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                    ) -> Result<(Transaction, WriteResult), SqlError> {
+                        #observed_transaction
+                            .await
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn commented_query_with_transaction(
+                        #transaction: Transaction,
+                        #comment: &str,
+                        #values: &[(#( &#vtype, )*)],
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                    ) -> Result<(Transaction, WriteResult), SqlError> {
+                        #observed_commented_transaction
+                            .await
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+                }
+            }
+            QueryKind::Write {
+                qtype,
+                update_columns,
+                values: None,
+                ..
+            } => {
+                let qtype = write_query_type(qtype, update_columns);
+                let render_args = quote!(#( #pname, )* #( #lname, )* #( #mname, )*);
+                let explain = explain(
+                    krate,
+                    name,
+                    quote!(#( #pname: &#ptype, )* #( #lname: &[#ltype], )* #( #mname: Option<&#mtype>, )*),
+                    &render_args,
+                );
+                let count = quote!(affected_rows());
+                let observe = |in_transaction, commented, call| {
+                    observe(name, &render_args, &count, in_transaction, commented, call)
+                };
+                let observed_query = observe(
+                    false,
+                    false,
+                    quote!(query_internal(#connection, None, None, None #( , #pname )* #( , #lname )* #( , #mname )*)),
+                );
+                let observed_commented = observe(
+                    false,
+                    true,
+                    quote!(query_internal(#connection, Some(#comment), None, None #( , #pname )* #( , #lname )* #( , #mname )*)),
+                );
+                let observed_timeout = observe(
+                    false,
+                    false,
+                    quote!(query_internal(#connection, None, Some(#timeout), None #( , #pname )* #( , #lname )* #( , #mname )*)),
+                );
+                let observed_cancellation = observe(
+                    false,
+                    false,
+                    quote!(query_internal(#connection, None, None, Some(#cancellation) #( , #pname )* #( , #lname )* #( , #mname )*)),
+                );
+                let observed_transaction = observe(
+                    true,
+                    false,
+                    quote!(query_internal_with_transaction(#transaction, None #( , #pname )* #( , #lname )* #( , #mname )*)),
+                );
+                let observed_commented_transaction = observe(
+                    true,
+                    true,
+                    quote!(query_internal_with_transaction(#transaction, Some(#comment) #( , #pname )* #( , #lname )* #( , #mname )*)),
+                );
+                quote! {
+                    #krate::_write_query_impl!((
+                        #( #pname: #ptype, )*
+                        #( >list #lname: #ltype )*
+                        #( >maybe #mname: #mtype = #mfrag )*
+                    ) {
+                        #qtype,
+                        mysql(#mysql_q #( , #variant => #variant_q )*)
+                        sqlite(#sqlite_q)
+                    });
+
+                    #[allow(dead_code)]
+                    pub async fn query(
+                        #connection: &Connection,
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> Result<WriteResult, SqlError> {
+                        #observed_query
+                            .await
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn commented_query(
+                        #connection: &Connection,
+                        #comment: &str,
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> Result<WriteResult, SqlError> {
+                        #observed_commented
+                            .await
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn query_with_timeout(
+                        #connection: &Connection,
+                        #timeout: std::time::Duration,
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> Result<WriteResult, SqlError> {
+                        #observed_timeout
+                            .await
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn query_with_cancellation(
+                        #connection: &Connection,
+                        #cancellation: &#krate::CancellationToken,
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> Result<WriteResult, SqlError> {
+                        #observed_cancellation
+                            .await
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn cached_query(
+                        #connection: &#krate::CachedConnection,
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> Result<WriteResult, SqlError> {
+                        #connection
+                            .write_invalidating(
+                                module_path!(),
+                                query(#connection.connection() #( , #pname )* #( , #lname )* #( , #mname )*).map_err(SqlError::into_anyhow),
+                            )
+                            .await
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub fn render(
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> RenderedQuery {
+                        render_internal(#( #pname, )* #( #lname, )* #( #mname, )*)
+                    }
+
+                    #explain
+
+                    #[allow(dead_code)]
+                    pub async fn query_with_transaction(
+                        #transaction: Transaction,
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> Result<(Transaction, WriteResult), SqlError> {
+                        #observed_transaction
+                            .await
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+
+                    #[allow(dead_code)]
+                    pub async fn commented_query_with_transaction(
+                        #transaction: Transaction,
+                        #comment: &str,
+                        #( #pname: &#ptype, )*
+                        #( #lname: &[#ltype], )*
+                        #( #mname: Option<&#mtype>, )*
+                    ) -> Result<(Transaction, WriteResult), SqlError> {
+                        #observed_commented_transaction
+                            .await
+                            .context(#context)
+                            .map_err(SqlError::from)
+                    }
+                }
+            }
+        };
+
+        quote! {
+            #[allow(non_snake_case)]
+            #vis mod #name {
+                #body
+            }
+        }
+    }
+
+    /// Generate the `next_page` function of a `read_paged` query, reading a
+    /// page with its `query` function.
+    fn expand_next_page(&self, krate: &TokenTree, key: &Type, row: &TokenStream2) -> TokenStream2 {
+        let params = &self.params[..self.params.len() - 1];
+        let maybes = &self.maybes[..self.maybes.len() - 1];
+        let pname: Vec<_> = params.iter().map(|param| &param.name).collect();
+        let ptype: Vec<_> = params.iter().map(|param| &param.ty).collect();
+        let lname: Vec<_> = self.lists.iter().map(|param| &param.name).collect();
+        let ltype: Vec<_> = self.lists.iter().map(|param| &param.ty).collect();
+        let mname: Vec<_> = maybes.iter().map(|param| &param.name).collect();
+        let mtype: Vec<_> = maybes.iter().map(|param| &param.ty).collect();
+
+        let connection = Ident::new("connection", Span::mixed_site());
+        let cursor = Ident::new("cursor", Span::mixed_site());
+        let limit = Ident::new("limit", Span::mixed_site());
+        let rows = Ident::new("rows", Span::mixed_site());
+
+        quote! {
+            #[allow(dead_code)]
+            pub async fn next_page(
+                #connection: &Connection,
+                #cursor: Option<&#key>,
+                #limit: u64,
+                #( #pname: &#ptype, )*
+                #( #lname: &[#ltype], )*
+                #( #mname: Option<&#mtype>, )*
+            ) -> Result<#krate::Page<#row, #key>, SqlError> {
+                let #limit = #krate::Limit::from(#limit);
+                let #rows = query(
+                    #connection,
+                    #( #pname, )*
+                    &#limit,
+                    #( #lname, )*
+                    #( #mname, )*
+                    #cursor
+                )
+                .await?;
+                Ok(#krate::Page::new(#rows, #limit.get(), |row| row.0.clone()))
+            }
+        }
+    }
+}
+
+/// Generate the `explain` function of a query, returning the plan of the
+/// query rendered for the backend of the connection.
+fn explain(
+    krate: &TokenTree,
+    name: &Ident,
+    params: TokenStream2,
+    render_args: &TokenStream2,
+) -> TokenStream2 {
+    let context = LitStr::new(&format!("While explaining {} query", name), name.span());
+    let connection = Ident::new("connection", Span::mixed_site());
+    let rendered = Ident::new("rendered", Span::mixed_site());
+    quote! {
+        #[allow(dead_code)]
+        pub async fn explain(
+            #connection: &Connection,
+            #params
+        ) -> Result<#krate::explain::QueryPlan, SqlError> {
+            let #rendered = render_internal(#render_args);
+            let #rendered = if matches!(#connection, Connection::Sqlite(..)) {
+                #rendered.sqlite
+            } else {
+                #rendered.mysql
+            };
+            #connection
+                .explain_plan(&#rendered)
+                .await
+                .context(#context)
+                .map_err(SqlError::from)
+        }
+    }
+}
+
+/// Wrap the call of a query so that it's reported to the query observers,
+/// with the number of rows taken from its result with `count`, and to the
+/// slow query log of the connection with the comment of the query if it is
+/// `commented`, and to the stats of the connection.
+fn observe(
+    name: &Ident,
+    render_args: &TokenStream2,
+    count: &TokenStream2,
+    in_transaction: bool,
+    commented: bool,
+    call: TokenStream2,
+) -> TokenStream2 {
+    let name = LitStr::new(&name.to_string(), name.span());
+    let sqlite = Ident::new("sqlite", Span::mixed_site());
+    let rendered = Ident::new("rendered", Span::mixed_site());
+    let rows = Ident::new("rows", Span::mixed_site());
+    let (backend, rows_pattern, slow_query_log, metrics) = if in_transaction {
+        let transaction = Ident::new("transaction", Span::mixed_site());
+        (
+            quote!(matches!(#transaction, Transaction::Sqlite(..))),
+            quote!((_, #rows)),
+            quote!(None),
+            quote!(#transaction.metrics().cloned()),
+        )
+    } else {
+        let connection = Ident::new("connection", Span::mixed_site());
+        (
+            quote!(matches!(#connection, Connection::Sqlite(..))),
+            quote!(#rows),
+            quote!(#connection.slow_query_log()),
+            quote!(#connection.metrics().cloned()),
+        )
+    };
+    let comment = if commented {
+        let comment = Ident::new("comment", Span::mixed_site());
+        quote!(Some(#comment))
+    } else {
+        quote!(None)
+    };
+    quote! {{
+        let #sqlite = #backend;
+        observe_query(
+            #name,
+            #in_transaction,
+            QueryContext {
+                comment: #comment,
+                slow_query_log: #slow_query_log,
+                metrics: #metrics,
+            },
+            move || {
+                let #rendered = render_internal(#render_args);
+                if #sqlite { #rendered.sqlite } else { #rendered.mysql }
+            },
+            |#rows_pattern| #rows.#count,
+            #call,
+        )
+    }}
+}
+
+fn expand_from_row(input: DeriveInput) -> Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(DataStruct {
+            fields: Fields::Named(fields),
+            ..
+        }) => &fields.named,
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "FromRow can only be derived for structs with named fields",
+            ));
+        }
+    };
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let field: Vec<_> = fields
+        .iter()
+        .filter_map(|field| field.ident.as_ref())
+        .collect();
+    let field_name: Vec<_> = field
+        .iter()
+        .map(|field| field.unraw().to_string())
+        .collect();
+    let count = field.len();
+    let values = Ident::new("values", Span::mixed_site());
+
+    Ok(quote! {
+        impl #impl_generics ::sql::FromRow for #name #ty_generics #where_clause {
+            fn from_row(
+                #values: ::std::vec::Vec<::sql::mysql_async::Value>,
+            ) -> ::std::result::Result<Self, ::sql::anyhow::Error> {
+                let mut #values = ::sql::RowValues::new::<Self>(#values, #count)?;
+                ::std::result::Result::Ok(Self {
+                    #( #field: #values.field(#field_name)?, )*
+                })
+            }
+        }
+    })
+}
+
+/// Return the string literal a query is made of, if any. Queries that are
+/// not plain literals, e.g. `concat!(..)`, are left for `format!` to check.
+fn string_literal(query: &Expr) -> Option<&LitStr> {
+    match query {
+        Expr::Lit(lit) => match &lit.lit {
+            Lit::Str(lit) => Some(lit),
+            _ => None,
+        },
+        Expr::Group(group) => string_literal(&group.expr),
+        Expr::Paren(paren) => string_literal(&paren.expr),
+        _ => None,
+    }
+}
+
+fn check_placeholders(query: &Expr, what: &str, names: &[&Ident], implicit: &[&str]) -> Result<()> {
+    match string_literal(query) {
+        Some(lit) => check_literal_placeholders(lit, what, names, implicit),
+        None => Ok(()),
+    }
+}
+
+/// Check that the placeholders of `lit`, described as `what` in errors, are
+/// exactly the given parameters and implicit placeholders.
+fn check_literal_placeholders(
+    lit: &LitStr,
+    what: &str,
+    names: &[&Ident],
+    implicit: &[&str],
+) -> Result<()> {
+    let used = placeholders(&lit.value()).map_err(|msg| Error::new(lit.span(), msg))?;
+
+    for placeholder in &used {
+        if !names.iter().any(|name| *name == placeholder) && !implicit.contains(&&**placeholder) {
+            let expected: Vec<_> = names
+                .iter()
+                .map(|name| name.to_string())
+                .chain(implicit.iter().map(|name| name.to_string()))
+                .map(|name| format!("`{{{}}}`", name))
+                .collect();
+            let msg = if expected.is_empty() {
+                format!(
+                    "unknown placeholder `{{{}}}` in {}, which has no parameters",
+                    placeholder, what
+                )
+            } else {
+                format!(
+                    "unknown placeholder `{{{}}}` in {}, expected one of {}",
+                    placeholder,
+                    what,
+                    expected.join(", ")
+                )
+            };
+            return Err(Error::new(lit.span(), msg));
+        }
+    }
+
+    for name in names {
+        if !used.iter().any(|placeholder| *name == placeholder) {
+            return Err(Error::new(
+                name.span(),
+                format!(
+                    "parameter `{}` is not used in {}, add a `{{{}}}` placeholder for it",
+                    name, what, name
+                ),
+            ));
+        }
+    }
+    for name in implicit {
+        if !used.iter().any(|placeholder| placeholder == name) {
+            return Err(Error::new(
+                lit.span(),
+                format!("missing `{{{}}}` placeholder in {}", name, what),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the placeholders of `lit` following `LIMIT` and `OFFSET` are
+/// parameters of type `Limit` and `Offset`, so that they can't be
+/// interpolated as strings or negative values.
+fn check_limit_placeholders(lit: &LitStr, params: &[(&Ident, &Type)]) -> Result<()> {
+    for (placeholder, expected) in limit_placeholders(&lit.value()) {
+        let ty = params
+            .iter()
+            .find(|(name, _)| *name == &placeholder)
+            .map(|(_, ty)| *ty);
+        if !ty.is_some_and(|ty| is_type_named(ty, expected)) {
+            return Err(Error::new(
+                lit.span(),
+                format!(
+                    "placeholder `{{{}}}` of a `{}` clause must be a parameter of type `sql::{}`",
+                    placeholder,
+                    expected.to_uppercase(),
+                    expected
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `ty` is a path to a type named `name`, e.g. `sql::Limit`.
+fn is_type_named(ty: &Type, name: &str) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == name),
+        Type::Group(group) => is_type_named(&group.elem, name),
+        Type::Paren(paren) => is_type_named(&paren.elem, name),
+        _ => false,
+    }
+}
+
+/// Return the placeholders of a query that follow `LIMIT` and `OFFSET`,
+/// along with the type they must have: `LIMIT {limit}`, `OFFSET {offset}`
+/// and `LIMIT {offset}, {limit}`.
+fn limit_placeholders(query: &str) -> Vec<(String, &'static str)> {
+    enum Token {
+        Word(String),
+        Placeholder(String),
+        Comma,
+        Other,
+    }
+
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                tokens.push(Token::Other);
+            }
+            '{' => {
+                let placeholder: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                let name = placeholder.split(':').next().unwrap_or_default().trim();
+                tokens.push(Token::Placeholder(name.to_owned()));
+            }
+            ',' => tokens.push(Token::Comma),
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                    word.push(c);
+                }
+                tokens.push(Token::Word(word));
+            }
+            c if c.is_whitespace() => {}
+            _ => tokens.push(Token::Other),
+        }
+    }
+
+    let mut placeholders = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let Token::Word(word) = token else {
+            continue;
+        };
+        let Some(Token::Placeholder(first)) = tokens.get(i + 1) else {
+            continue;
+        };
+        if word.eq_ignore_ascii_case("offset") {
+            placeholders.push((first.clone(), "Offset"));
+        } else if word.eq_ignore_ascii_case("limit") {
+            match (tokens.get(i + 2), tokens.get(i + 3)) {
+                (Some(Token::Comma), Some(Token::Placeholder(second))) => {
+                    placeholders.push((first.clone(), "Offset"));
+                    placeholders.push((second.clone(), "Limit"));
+                }
+                _ => placeholders.push((first.clone(), "Limit")),
+            }
+        }
+    }
+    placeholders
+}
+
+/// Return the names of the placeholders of a query, which uses the syntax of
+/// `format!` with named arguments only.
+fn placeholders(query: &str) -> std::result::Result<Vec<String>, String> {
+    let mut names = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => {
+                            return Err("unclosed `{` in query, use `{{` to escape it".to_owned());
+                        }
+                    }
+                }
+                let name = placeholder.split(':').next().unwrap_or_default().trim();
+                if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+                    return Err(format!(
+                        "positional placeholder `{{{}}}` in query, use the name of a parameter instead",
+                        placeholder
+                    ));
+                }
+                names.push(name.to_owned());
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+            }
+            '}' => return Err("unmatched `}` in query, use `}}` to escape it".to_owned()),
+            _ => {}
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(
+            placeholders("SELECT x FROM foo WHERE id IN {ids} AND y = {y}"),
+            Ok(vec!["ids".to_owned(), "y".to_owned()])
+        );
+        assert_eq!(
+            placeholders("SELECT '{{}}', {x:?}"),
+            Ok(vec!["x".to_owned()])
+        );
+        assert!(placeholders("SELECT {}").is_err());
+        assert!(placeholders("SELECT {0}").is_err());
+        assert!(placeholders("SELECT {x").is_err());
+        assert!(placeholders("SELECT x}").is_err());
+    }
+
+    #[test]
+    fn test_check_placeholders() {
+        let query: Expr = syn::parse_quote!("SELECT {a} FROM foo WHERE b = {b}");
+        let a = Ident::new("a", Span::call_site());
+        let b = Ident::new("b", Span::call_site());
+        let c = Ident::new("c", Span::call_site());
+
+        assert!(check_placeholders(&query, "the query", &[&a, &b], &[]).is_ok());
+        assert!(check_placeholders(&query, "the query", &[&a], &[]).is_err());
+        assert!(check_placeholders(&query, "the query", &[&a, &b, &c], &[]).is_err());
+        assert!(check_placeholders(&query, "the query", &[&a, &b], &["values"]).is_err());
+
+        let query: Expr = syn::parse_quote!(concat!("SELECT ", "{a}"));
+        assert!(check_placeholders(&query, "the query", &[&b], &[]).is_ok());
+    }
+
+    #[test]
+    fn test_limit_placeholders() {
+        let placeholders = |query| {
+            limit_placeholders(query)
+                .into_iter()
+                .map(|(name, ty)| format!("{}: {}", name, ty))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            placeholders("SELECT x FROM foo WHERE y = {y} LIMIT {l} OFFSET {o}"),
+            vec!["l: Limit", "o: Offset"]
+        );
+        assert_eq!(
+            placeholders("SELECT x FROM foo limit {o},{l}"),
+            vec!["o: Offset", "l: Limit"]
+        );
+        assert_eq!(
+            placeholders("SELECT x FROM foo LIMIT 10 OFFSET {{o}} {sublimit}"),
+            Vec::<String>::new()
+        );
+
+        let limit = Ident::new("limit", Span::call_site());
+        let limit_type: Type = syn::parse_quote!(sql::Limit);
+        let u64_type: Type = syn::parse_quote!(u64);
+        let query: LitStr = syn::parse_quote!("SELECT x FROM foo LIMIT {limit}");
+        assert!(check_limit_placeholders(&query, &[(&limit, &limit_type)]).is_ok());
+        assert!(check_limit_placeholders(&query, &[(&limit, &u64_type)]).is_err());
+        assert!(check_limit_placeholders(&query, &[]).is_err());
+    }
+}
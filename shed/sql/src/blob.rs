@@ -0,0 +1,301 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Reading and writing large BLOB values in chunks, so that values of
+//! hundreds of megabytes are never wholly held in memory, see [BlobHandle].
+
+use std::pin::pin;
+
+use anyhow::bail;
+use anyhow::Error;
+use futures::stream;
+use futures::stream::BoxStream;
+use futures::stream::Stream;
+use futures::stream::StreamExt;
+use futures::stream::TryStreamExt;
+use mysql_async::Params;
+use mysql_async::Value;
+use rusqlite::OptionalExtension;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+
+use crate::sqlite::SqliteBlob;
+use crate::sqlite::SqliteMultithreaded;
+use crate::sqlite::SqliteQueryType;
+use crate::Connection;
+use crate::ValueWrapper;
+
+/// The default size of the chunks read by a [BlobHandle].
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Handle to the BLOB value of a column in a row of a table, read and
+/// written in chunks.
+///
+/// On sqlite, the chunks are read and written with incremental BLOB I/O, see
+/// [SqliteBlob]. On MySQL, they are read with `SUBSTRING` and appended with
+/// `CONCAT`, a query per chunk. The server then rewrites the value for every
+/// chunk written, so the chunks written should be large, and the value is
+/// seen partially written until the last chunk is. The Meta internal client
+/// doesn't support BLOB handles.
+///
+/// The names of the table and columns are put in the queries as they are, so
+/// they must not come from untrusted input.
+#[derive(Clone, Debug)]
+pub struct BlobHandle {
+    connection: Connection,
+    table: String,
+    column: String,
+    key_column: String,
+    key: Value,
+    chunk_size: usize,
+}
+
+impl BlobHandle {
+    /// Create a handle to the value of `column` in the row of `table` whose
+    /// `key_column`, e.g. its primary key, is `key`.
+    pub fn new(
+        connection: &Connection,
+        table: &str,
+        column: &str,
+        key_column: &str,
+        key: impl Into<Value>,
+    ) -> Self {
+        Self {
+            connection: connection.clone(),
+            table: table.to_owned(),
+            column: column.to_owned(),
+            key_column: key_column.to_owned(),
+            key: key.into(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Set the maximum number of bytes of the chunks read.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Return the size of the value in bytes, zero if it is `NULL`. Fails if
+    /// there is no such row.
+    pub async fn size(&self) -> Result<u64, Error> {
+        let query = format!(
+            "SELECT LENGTH({}) FROM {} WHERE {} = ?",
+            self.column, self.table, self.key_column
+        );
+        let len = match &self.connection {
+            Connection::Sqlite(sqlite) => {
+                let key = ValueWrapper(self.key.clone());
+                sqlite
+                    .run_query(SqliteQueryType::Read, None, move |con| {
+                        let len = con
+                            .query_row(&query, [key], |row| row.get::<_, Option<i64>>(0))
+                            .optional()?;
+                        Ok(len.map(|len| len.map(|len| len as u64)))
+                    })
+                    .await?
+            }
+            Connection::Mysql(_) => bail!("BLOB handles are not supported by this client"),
+            Connection::OssMysql(conn) => {
+                let mut con = conn.get_conn().await?;
+                let params = Params::Positional(vec![self.key.clone()]);
+                let len: Vec<(Option<u64>,)> = conn
+                    .read_prepared_query(&mut con, &query, params)
+                    .await?
+                    .collect_and_drop()
+                    .await?;
+                len.into_iter().next().map(|(len,)| len)
+            }
+        };
+        match len {
+            Some(len) => Ok(len.unwrap_or(0)),
+            None => bail!(
+                "No row of {} has {} {:?}",
+                self.table,
+                self.key_column,
+                self.key
+            ),
+        }
+    }
+
+    /// Return a stream of the chunks of the value, in order. The value must
+    /// not change while it is read.
+    pub fn read(&self) -> BoxStream<'static, Result<Vec<u8>, Error>> {
+        let handle = self.clone();
+        stream::once(handle.read_chunks()).try_flatten().boxed()
+    }
+
+    async fn read_chunks(self) -> Result<BoxStream<'static, Result<Vec<u8>, Error>>, Error> {
+        let sqlite = match &self.connection {
+            Connection::Sqlite(sqlite) => sqlite.clone(),
+            Connection::Mysql(_) => bail!("BLOB handles are not supported by this client"),
+            Connection::OssMysql(_) => {
+                let len = self.size().await?;
+                return Ok(stream::try_unfold(0, move |offset| {
+                    let handle = self.clone();
+                    async move {
+                        if offset >= len {
+                            return Ok(None);
+                        }
+                        let chunk = handle.read_mysql_chunk(offset).await?;
+                        if chunk.is_empty() {
+                            bail!("The value was truncated while being read");
+                        }
+                        let offset = offset + chunk.len() as u64;
+                        Ok(Some((chunk, offset)))
+                    }
+                })
+                .boxed());
+            }
+        };
+        let chunk_size = self.chunk_size;
+        let blob = self.open_sqlite_blob(&sqlite, true).await?;
+        Ok(stream::try_unfold(blob, move |mut blob| async move {
+            // The blob reads a chunk of the same size at once.
+            let mut chunk = vec![0; chunk_size];
+            let read = blob.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok(None);
+            }
+            chunk.truncate(read);
+            Ok(Some((chunk, blob)))
+        })
+        .boxed())
+    }
+
+    async fn read_mysql_chunk(&self, offset: u64) -> Result<Vec<u8>, Error> {
+        let Connection::OssMysql(conn) = &self.connection else {
+            bail!("Not a MySQL connection");
+        };
+        let query = format!(
+            "SELECT SUBSTRING({}, ?, ?) FROM {} WHERE {} = ?",
+            self.column, self.table, self.key_column
+        );
+        // Positions start at 1 in SQL.
+        let params = Params::Positional(vec![
+            Value::UInt(offset + 1),
+            Value::UInt(self.chunk_size as u64),
+            self.key.clone(),
+        ]);
+        let mut con = conn.get_conn().await?;
+        let chunk: Vec<(Option<Vec<u8>>,)> = conn
+            .read_prepared_query(&mut con, &query, params)
+            .await?
+            .collect_and_drop()
+            .await?;
+        Ok(chunk
+            .into_iter()
+            .next()
+            .and_then(|(chunk,)| chunk)
+            .unwrap_or_default())
+    }
+
+    async fn open_sqlite_blob(
+        &self,
+        sqlite: &SqliteMultithreaded,
+        read_only: bool,
+    ) -> Result<SqliteBlob, Error> {
+        let query = format!(
+            "SELECT rowid FROM {} WHERE {} = ?",
+            self.table, self.key_column
+        );
+        let key = ValueWrapper(self.key.clone());
+        let row_id = sqlite
+            .run_query(SqliteQueryType::Read, None, move |con| {
+                Ok(con
+                    .query_row(&query, [key], |row| row.get::<_, i64>(0))
+                    .optional()?)
+            })
+            .await?;
+        let Some(row_id) = row_id else {
+            bail!(
+                "No row of {} has {} {:?}",
+                self.table,
+                self.key_column,
+                self.key
+            );
+        };
+        Ok(sqlite
+            .open_blob(&self.table, &self.column, row_id, read_only)
+            .await?
+            .with_chunk_size(self.chunk_size))
+    }
+
+    /// Replace the value with the `len` bytes of `chunks`. Fails if the
+    /// chunks don't add up to `len` bytes, which are needed upfront as the
+    /// size of a BLOB can't change on sqlite while it is written. On MySQL,
+    /// each chunk is appended with its own query. A write that fails leaves
+    /// the value partially written.
+    pub async fn write<S>(&self, len: u64, chunks: S) -> Result<(), Error>
+    where
+        S: Stream<Item = Result<Vec<u8>, Error>>,
+    {
+        // Fails if there is no such row, which updates wouldn't.
+        self.size().await?;
+        let mut chunks = pin!(chunks);
+        let mut written = 0;
+        match &self.connection {
+            Connection::Sqlite(sqlite) => {
+                let query = format!(
+                    "UPDATE {} SET {} = zeroblob(?) WHERE {} = ?",
+                    self.table, self.column, self.key_column
+                );
+                let key = ValueWrapper(self.key.clone());
+                sqlite
+                    .run_query(SqliteQueryType::Write, None, move |con| {
+                        con.execute(
+                            &query,
+                            rusqlite::params![ValueWrapper(Value::UInt(len)), key],
+                        )?;
+                        Ok(())
+                    })
+                    .await?;
+                let mut blob = self.open_sqlite_blob(sqlite, false).await?;
+                while let Some(chunk) = chunks.try_next().await? {
+                    written += chunk.len() as u64;
+                    if written > len {
+                        bail!("The chunks written have more than {} bytes", len);
+                    }
+                    blob.write_all(&chunk).await?;
+                }
+                blob.flush().await?;
+            }
+            Connection::Mysql(_) => bail!("BLOB handles are not supported by this client"),
+            Connection::OssMysql(conn) => {
+                let clear = format!(
+                    "UPDATE {} SET {} = '' WHERE {} = ?",
+                    self.table, self.column, self.key_column
+                );
+                let params = Params::Positional(vec![self.key.clone()]);
+                conn.write_prepared_query(clear, params, None, None).await?;
+                let append = format!(
+                    "UPDATE {} SET {} = CONCAT({}, ?) WHERE {} = ?",
+                    self.table, self.column, self.column, self.key_column
+                );
+                while let Some(chunk) = chunks.try_next().await? {
+                    written += chunk.len() as u64;
+                    if written > len {
+                        bail!("The chunks written have more than {} bytes", len);
+                    }
+                    let params = Params::Positional(vec![Value::Bytes(chunk), self.key.clone()]);
+                    conn.write_prepared_query(append.clone(), params, None, None)
+                        .await?;
+                }
+            }
+        }
+        if written < len {
+            bail!(
+                "The chunks written have {} bytes rather than {}",
+                written,
+                len
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Optimistic concurrency control with compare-and-swap updates.
+//!
+//! The rows updated this way have a version column, and an update only
+//! applies if the version is still the one the caller read, bumping it at the
+//! same time:
+//!
+//! ```sql
+//! UPDATE counters SET value = {value}, version = {old_version} + 1
+//! WHERE id = {id} AND version = {old_version}
+//! ```
+//!
+//! The update must always change the version, as MySQL only counts the rows
+//! whose values changed as affected, and must match at most one row.
+//! [cas_update] then tells whether the update applied from the number of
+//! affected rows, reading the current version back on conflict.
+
+use std::future::Future;
+
+use anyhow::bail;
+use anyhow::Error;
+
+use crate::Transaction;
+use crate::WriteResult;
+
+/// Outcome of a compare-and-swap update.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CasOutcome<V> {
+    /// The version was the expected one, and the row was updated.
+    Updated,
+    /// The version wasn't the expected one, and the row was left untouched.
+    Conflict {
+        /// The current version of the row, or `None` if the row doesn't
+        /// exist.
+        current_version: Option<V>,
+    },
+}
+
+impl<V> CasOutcome<V> {
+    /// Whether the row was updated.
+    pub fn is_updated(&self) -> bool {
+        matches!(self, CasOutcome::Updated)
+    }
+}
+
+fn outcome<V>(result: &WriteResult) -> Result<Option<CasOutcome<V>>, Error> {
+    match result.affected_rows() {
+        0 => Ok(None),
+        1 => Ok(Some(CasOutcome::Updated)),
+        rows => bail!(
+            "Compare-and-swap update affected {} rows, it must match a single row",
+            rows
+        ),
+    }
+}
+
+/// Run the compare-and-swap `update`, and `read_version` to get the current
+/// version of the row if it didn't apply.
+///
+/// Fails if the update affected more than one row.
+pub async fn cas_update<V, U, E, R, RFut>(
+    update: U,
+    read_version: R,
+) -> Result<CasOutcome<V>, Error>
+where
+    U: Future<Output = Result<WriteResult, E>>,
+    Error: From<E>,
+    R: FnOnce() -> RFut,
+    RFut: Future<Output = Result<Option<V>, Error>>,
+{
+    match outcome(&update.await?)? {
+        Some(outcome) => Ok(outcome),
+        None => Ok(CasOutcome::Conflict {
+            current_version: read_version().await?,
+        }),
+    }
+}
+
+/// Same as [cas_update] within a transaction, so that the current version is
+/// read in the same transaction as the update.
+pub async fn cas_update_with_transaction<V, U, UFut, E, R, RFut>(
+    transaction: Transaction,
+    update: U,
+    read_version: R,
+) -> Result<(Transaction, CasOutcome<V>), Error>
+where
+    U: FnOnce(Transaction) -> UFut,
+    UFut: Future<Output = Result<(Transaction, WriteResult), E>>,
+    Error: From<E>,
+    R: FnOnce(Transaction) -> RFut,
+    RFut: Future<Output = Result<(Transaction, Option<V>), Error>>,
+{
+    let (transaction, result) = update(transaction).await?;
+    match outcome(&result)? {
+        Some(outcome) => Ok((transaction, outcome)),
+        None => {
+            let (transaction, current_version) = read_version(transaction).await?;
+            Ok((transaction, CasOutcome::Conflict { current_version }))
+        }
+    }
+}
@@ -0,0 +1,181 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module with the [Compressed] wrapper, compressing large blobs stored
+//! through queries.
+
+use mysql_async::prelude::ConvIr;
+use mysql_async::prelude::FromValue;
+use mysql_async::prelude::ToValue;
+use mysql_async::FromValueError;
+use mysql_async::Value;
+
+/// Prefix of the values compressed by [Compressed].
+const MAGIC: &[u8] = b"\0zstd\0";
+
+/// Size in bytes from which values are compressed.
+const COMPRESSION_THRESHOLD: usize = 4096;
+
+/// zstd compression level, 0 meaning zstd's default level.
+const COMPRESSION_LEVEL: i32 = 0;
+
+/// Wrapper around a blob, used as a query parameter or in the results of a
+/// read query, that is stored compressed with zstd when it is large enough
+/// to benefit from it.
+///
+/// Blobs of at least 4 KiB are compressed and stored with a magic prefix,
+/// unless compressing them doesn't make them smaller. Stored values without
+/// that prefix are read as they are, so existing uncompressed blobs can be
+/// read through this wrapper, and columns can be migrated to it without
+/// rewriting them.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Compressed<T>(pub T);
+
+impl<T> Compressed<T> {
+    /// Return the wrapped blob.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Compressed<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> ToValue for Compressed<T>
+where
+    T: AsRef<[u8]>,
+{
+    fn to_value(&self) -> Value {
+        Value::Bytes(compress(self.0.as_ref()))
+    }
+}
+
+/// Intermediate type used to parse a [Compressed] value, holding the stored
+/// value and its decompressed bytes.
+#[doc(hidden)]
+pub struct CompressedIr {
+    stored: Vec<u8>,
+    bytes: Vec<u8>,
+}
+
+impl<T> ConvIr<Compressed<T>> for CompressedIr
+where
+    T: From<Vec<u8>>,
+{
+    fn new(v: Value) -> Result<Self, FromValueError> {
+        match v {
+            Value::Bytes(stored) => match decompress(&stored) {
+                Some(bytes) => Ok(CompressedIr { stored, bytes }),
+                None => Err(FromValueError(Value::Bytes(stored))),
+            },
+            v => Err(FromValueError(v)),
+        }
+    }
+
+    fn commit(self) -> Compressed<T> {
+        Compressed(T::from(self.bytes))
+    }
+
+    fn rollback(self) -> Value {
+        Value::Bytes(self.stored)
+    }
+}
+
+impl<T> FromValue for Compressed<T>
+where
+    T: From<Vec<u8>>,
+{
+    type Intermediate = CompressedIr;
+}
+
+fn compress(bytes: &[u8]) -> Vec<u8> {
+    // Values starting with the magic prefix are always compressed, so that
+    // they are not mistaken for compressed ones when read back.
+    let has_magic = bytes.starts_with(MAGIC);
+    if bytes.len() < COMPRESSION_THRESHOLD && !has_magic {
+        return bytes.to_vec();
+    }
+
+    let compressed = zstd::bulk::compress(bytes, COMPRESSION_LEVEL)
+        .expect("zstd compression of a buffer should not fail");
+    if MAGIC.len() + compressed.len() >= bytes.len() && !has_magic {
+        return bytes.to_vec();
+    }
+
+    let mut stored = Vec::with_capacity(MAGIC.len() + compressed.len());
+    stored.extend_from_slice(MAGIC);
+    stored.extend_from_slice(&compressed);
+    stored
+}
+
+fn decompress(stored: &[u8]) -> Option<Vec<u8>> {
+    match stored.strip_prefix(MAGIC) {
+        Some(compressed) => zstd::stream::decode_all(compressed).ok(),
+        None => Some(stored.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(bytes: Vec<u8>) -> Vec<u8> {
+        let value = Compressed(bytes.clone()).to_value();
+        let Compressed(read) = Compressed::<Vec<u8>>::from_value_opt(value.clone()).unwrap();
+        assert_eq!(read, bytes);
+        match value {
+            Value::Bytes(stored) => stored,
+            v => panic!("unexpected value {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_small_values_are_not_compressed() {
+        assert_eq!(round_trip(b"foo".to_vec()), b"foo");
+    }
+
+    #[test]
+    fn test_large_values_are_compressed() {
+        let stored = round_trip(vec![42; COMPRESSION_THRESHOLD * 4]);
+        assert!(stored.starts_with(MAGIC));
+        assert!(stored.len() < COMPRESSION_THRESHOLD);
+    }
+
+    #[test]
+    fn test_incompressible_values_are_not_compressed() {
+        let mut state: u64 = 0x2545f4914f6cdd1d;
+        let bytes: Vec<u8> = (0..COMPRESSION_THRESHOLD * 4)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 56) as u8
+            })
+            .collect();
+        assert_eq!(round_trip(bytes.clone()), bytes);
+    }
+
+    #[test]
+    fn test_values_with_magic_prefix() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(b"foo");
+        assert!(round_trip(bytes).starts_with(MAGIC));
+    }
+
+    #[test]
+    fn test_invalid_compressed_value() {
+        let mut stored = MAGIC.to_vec();
+        stored.extend_from_slice(b"not zstd");
+        assert!(Compressed::<Vec<u8>>::from_value_opt(Value::Bytes(stored)).is_err());
+        assert!(Compressed::<Vec<u8>>::from_value_opt(Value::Int(1)).is_err());
+    }
+}
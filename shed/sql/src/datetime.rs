@@ -0,0 +1,121 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module with the [UtcDateTime] wrapper, storing timezone aware date times
+//! in UTC.
+
+use chrono::DateTime;
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use mysql_async::prelude::ConvIr;
+use mysql_async::prelude::FromValue;
+use mysql_async::prelude::ToValue;
+use mysql_async::FromValueError;
+use mysql_async::Value;
+
+/// Wrapper around a [DateTime] in UTC, used as a query parameter or in the
+/// results of a read query, that is stored as the naive date time in UTC.
+///
+/// Naive date times, i.e. [NaiveDateTime], are supported by queries without
+/// a wrapper, and are stored as they are. Date times in other timezones are
+/// converted to UTC when creating the wrapper, and are read back in UTC, so
+/// the timezone of the server or of the session never matters:
+///
+/// - On MySQL, they are best stored in `DATETIME` columns, which aren't
+///   converted. `TIMESTAMP` columns are converted from and to the timezone
+///   of the session, which must then be UTC. Columns need a fractional
+///   precision, e.g. `DATETIME(6)`, to keep sub-second values, which are
+///   rounded otherwise.
+/// - On sqlite, they are stored as `YYYY-MM-DD HH:MM:SS.SSSSSS` text, which
+///   sorts chronologically and is understood by the date and time functions
+///   of sqlite, whose results, e.g. of `CURRENT_TIMESTAMP`, are in UTC too.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct UtcDateTime(pub DateTime<Utc>);
+
+impl UtcDateTime {
+    /// Return the wrapped date time.
+    pub fn into_inner(self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+impl<Tz: TimeZone> From<DateTime<Tz>> for UtcDateTime {
+    fn from(datetime: DateTime<Tz>) -> Self {
+        Self(datetime.with_timezone(&Utc))
+    }
+}
+
+impl From<UtcDateTime> for DateTime<Utc> {
+    fn from(datetime: UtcDateTime) -> Self {
+        datetime.0
+    }
+}
+
+impl ToValue for UtcDateTime {
+    fn to_value(&self) -> Value {
+        self.0.naive_utc().to_value()
+    }
+}
+
+/// Intermediate type used to parse a [UtcDateTime] value, holding the stored
+/// value and the date time parsed from it.
+#[doc(hidden)]
+pub struct UtcDateTimeIr {
+    stored: Value,
+    datetime: NaiveDateTime,
+}
+
+impl ConvIr<UtcDateTime> for UtcDateTimeIr {
+    fn new(v: Value) -> Result<Self, FromValueError> {
+        let datetime = NaiveDateTime::from_value_opt(v.clone())?;
+        Ok(UtcDateTimeIr {
+            stored: v,
+            datetime,
+        })
+    }
+
+    fn commit(self) -> UtcDateTime {
+        UtcDateTime(Utc.from_utc_datetime(&self.datetime))
+    }
+
+    fn rollback(self) -> Value {
+        self.stored
+    }
+}
+
+impl FromValue for UtcDateTime {
+    type Intermediate = UtcDateTimeIr;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let datetime = DateTime::parse_from_rfc3339("2021-01-21T23:21:21.5+02:00").unwrap();
+        let value = UtcDateTime::from(datetime).to_value();
+        assert_eq!(value, Value::Date(2021, 1, 21, 21, 21, 21, 500_000));
+        let UtcDateTime(read) = UtcDateTime::from_value_opt(value).unwrap();
+        assert_eq!(read, datetime);
+        assert_eq!(read.to_rfc3339(), "2021-01-21T21:21:21.500+00:00");
+    }
+
+    #[test]
+    fn test_text_values() {
+        // The formats of date times read from sqlite.
+        for text in ["2021-01-21 21:21:21", "2021-01-21 21:21:21.000000"] {
+            let UtcDateTime(read) =
+                UtcDateTime::from_value_opt(Value::Bytes(text.as_bytes().to_vec())).unwrap();
+            assert_eq!(read.to_rfc3339(), "2021-01-21T21:21:21+00:00");
+        }
+        assert!(UtcDateTime::from_value_opt(Value::Bytes(b"yesterday".to_vec())).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module with the [FromRow] trait, used to map the rows returned by read
+//! queries into structs.
+
+use std::vec::IntoIter;
+
+use anyhow::format_err;
+use anyhow::Error;
+use mysql_async::prelude::FromValue;
+use mysql_async::Value;
+
+/// Types built from the columns of a row returned by a read query, in the
+/// order they were selected. Read queries declared with
+/// `-> Type` rather than a tuple of types return their rows as `Type`.
+///
+/// This trait is usually derived for structs with named fields, each field
+/// being read from the column at its position and parsed with
+/// `mysql_async::prelude::FromValue`:
+///
+/// ```
+/// use sql::FromRow;
+/// use sql::queries;
+///
+/// #[derive(FromRow)]
+/// struct Entry {
+///     id: u64,
+///     value: String,
+///     comment: Option<String>,
+/// }
+///
+/// queries! {
+///     read SelectEntries(min: u64) -> Entry {
+///         "SELECT id, value, comment FROM entries WHERE id >= {min}"
+///     }
+/// }
+/// #
+/// # fn main() {}
+/// ```
+pub trait FromRow: Sized {
+    /// Build the value from the values of the columns of a row.
+    fn from_row(values: Vec<Value>) -> Result<Self, Error>;
+}
+
+/// Values of a row consumed field by field by implementations of [FromRow]
+/// derived with `#[derive(FromRow)]`.
+/// This should never be used directly, it is made public so that the derive
+/// macro can make use of it
+#[doc(hidden)]
+pub struct RowValues {
+    type_name: &'static str,
+    values: IntoIter<Value>,
+}
+
+impl RowValues {
+    /// Check that the row has as many columns as the type has fields.
+    pub fn new<T>(values: Vec<Value>, fields: usize) -> Result<Self, Error> {
+        let type_name = std::any::type_name::<T>();
+        if values.len() != fields {
+            return Err(format_err!(
+                "Failed to parse `{}`: expected {} columns, got {}",
+                type_name,
+                fields,
+                values.len()
+            ));
+        }
+        Ok(Self {
+            type_name,
+            values: values.into_iter(),
+        })
+    }
+
+    /// Parse the value of the next column as the given field.
+    pub fn field<T: FromValue>(&mut self, field: &str) -> Result<T, Error> {
+        let value = self
+            .values
+            .next()
+            .ok_or_else(|| format_err!("Missing column for `{}.{}`", self.type_name, field))?;
+        T::from_value_opt(value)
+            .map_err(|err| format_err!("Failed to parse `{}.{}`: {}", self.type_name, field, err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Entry {
+        id: u64,
+        value: String,
+        comment: Option<String>,
+    }
+
+    impl FromRow for Entry {
+        fn from_row(values: Vec<Value>) -> Result<Self, Error> {
+            let mut values = RowValues::new::<Self>(values, 3)?;
+            Ok(Self {
+                id: values.field("id")?,
+                value: values.field("value")?,
+                comment: values.field("comment")?,
+            })
+        }
+    }
+
+    #[test]
+    fn test_from_row() {
+        let entry = Entry::from_row(vec![
+            Value::Int(1),
+            Value::Bytes(b"foo".to_vec()),
+            Value::NULL,
+        ])
+        .unwrap();
+        assert_eq!(
+            entry,
+            Entry {
+                id: 1,
+                value: "foo".to_owned(),
+                comment: None,
+            }
+        );
+
+        let err = Entry::from_row(vec![Value::Int(1)]).unwrap_err();
+        assert!(err.to_string().contains("expected 3 columns, got 1"));
+
+        let err = Entry::from_row(vec![Value::NULL, Value::NULL, Value::NULL]).unwrap_err();
+        assert!(err.to_string().contains("Entry.id"));
+    }
+}
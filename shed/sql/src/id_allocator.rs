@@ -0,0 +1,122 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Allocation of unique ids that does not depend on `last_insert_id`.
+//!
+//! Ids are reserved in contiguous ranges from a dedicated `id_allocator`
+//! table, which has to exist in the database:
+//!
+//! ```sql
+//! CREATE TABLE id_allocator (
+//!     name VARCHAR(255) PRIMARY KEY,
+//!     next_id BIGINT UNSIGNED NOT NULL
+//! );
+//! ```
+//!
+//! Every row tracks the next free id of one named sequence. Rows are created
+//! on first use, with the first id handed out being 1.
+
+use std::ops::Range;
+
+use anyhow::anyhow;
+use anyhow::Error;
+use futures::lock::Mutex;
+
+use self::sequence_queries::BumpNextId;
+use self::sequence_queries::InitSequence;
+use self::sequence_queries::SelectNextId;
+use crate::Connection;
+
+mod sequence_queries {
+    use crate::queries;
+
+    queries! {
+        pub(super) write BumpNextId(name: String, count: u64) {
+            none,
+            "UPDATE id_allocator SET next_id = next_id + {count} WHERE name = {name}"
+        }
+
+        pub(super) write InitSequence(name: String) {
+            insert_or_ignore,
+            "{insert_or_ignore} INTO id_allocator (name, next_id) VALUES ({name}, 1)"
+        }
+
+        pub(super) read SelectNextId(name: String) -> (u64) {
+            "SELECT next_id FROM id_allocator WHERE name = {name}"
+        }
+    }
+}
+
+/// Hands out unique ids for a named sequence, reserving them from the
+/// database in batches to reduce the number of round trips.
+///
+/// The reservation is a single transaction that bumps the sequence and reads
+/// the new value back, which behaves the same on MySQL and SQLite. Ids
+/// reserved but not handed out before the allocator is dropped are lost, so
+/// sequences may have gaps but will never hand out the same id twice.
+pub struct IdAllocator {
+    connection: Connection,
+    name: String,
+    batch_size: u64,
+    reserved: Mutex<Range<u64>>,
+}
+
+impl IdAllocator {
+    /// Create an allocator for the sequence `name` that reserves
+    /// `batch_size` ids at a time. The connection must be able to write to
+    /// the `id_allocator` table.
+    pub fn new(connection: Connection, name: impl Into<String>, batch_size: u64) -> Self {
+        Self {
+            connection,
+            name: name.into(),
+            batch_size: batch_size.max(1),
+            reserved: Mutex::new(0..0),
+        }
+    }
+
+    /// Return the next unique id of this sequence, reserving a new batch
+    /// from the database if the local one is exhausted.
+    pub async fn next_id(&self) -> Result<u64, Error> {
+        let mut reserved = self.reserved.lock().await;
+        if reserved.is_empty() {
+            *reserved = self.reserve(self.batch_size).await?;
+        }
+        let id = reserved.start;
+        reserved.start += 1;
+        Ok(id)
+    }
+
+    /// Reserve `count` contiguous ids directly from the database, bypassing
+    /// the local batch.
+    pub async fn reserve(&self, count: u64) -> Result<Range<u64>, Error> {
+        let transaction = self.connection.start_transaction().await?;
+        let (transaction, res) =
+            BumpNextId::query_with_transaction(transaction, &self.name, &count).await?;
+
+        let transaction = if res.affected_rows() == 0 {
+            let (transaction, _) =
+                InitSequence::query_with_transaction(transaction, &self.name).await?;
+            let (transaction, _) =
+                BumpNextId::query_with_transaction(transaction, &self.name, &count).await?;
+            transaction
+        } else {
+            transaction
+        };
+
+        let (transaction, rows) =
+            SelectNextId::query_with_transaction(transaction, &self.name).await?;
+        transaction.commit().await?;
+
+        let end = rows
+            .first()
+            .map(|(next_id,)| *next_id)
+            .ok_or_else(|| anyhow!("Sequence {} missing after reservation", self.name))?;
+        Ok(end - count..end)
+    }
+}
@@ -0,0 +1,165 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module with the [Json] wrapper, storing values serialized as JSON.
+
+use std::fmt;
+use std::hash::Hash;
+use std::hash::Hasher;
+
+use mysql_async::prelude::ConvIr;
+use mysql_async::prelude::FromValue;
+use mysql_async::prelude::ToValue;
+use mysql_async::FromValueError;
+use mysql_async::Value;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Wrapper around a value, used as a query parameter or in the results of a
+/// read query, that is stored as JSON, e.g. in a MySQL `JSON` column or a
+/// sqlite `TEXT` column.
+///
+/// The value is serialized when it is wrapped with [Json::new], which fails
+/// for the values that can't be serialized as JSON, e.g. maps with keys that
+/// aren't strings, rather than when the query is made.
+///
+/// Untyped JSON can be stored as a [serde_json::Value], which can also be
+/// used as it is, without this wrapper.
+///
+/// Like strings, JSON is bound to sqlite queries as a blob. The JSON
+/// functions of sqlite need it as text, e.g. `json_extract(CAST(x AS TEXT),
+/// '$.a')`.
+#[derive(Clone)]
+pub struct Json<T> {
+    value: T,
+    stored: Vec<u8>,
+}
+
+impl<T> Json<T>
+where
+    T: Serialize,
+{
+    /// Wrap the value, serializing it as JSON.
+    pub fn new(value: T) -> serde_json::Result<Self> {
+        let stored = serde_json::to_vec(&value)?;
+        Ok(Self { value, stored })
+    }
+}
+
+impl<T> Json<T> {
+    /// Return a reference to the wrapped value.
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    /// Return the wrapped value.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Json<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Json").field(&self.value).finish()
+    }
+}
+
+// The same value may be serialized differently, e.g. with the keys of a map
+// in another order, so only the values are compared.
+impl<T: PartialEq> PartialEq for Json<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: Eq> Eq for Json<T> {}
+
+impl<T: Hash> Hash for Json<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state)
+    }
+}
+
+impl<T> ToValue for Json<T> {
+    fn to_value(&self) -> Value {
+        Value::Bytes(self.stored.clone())
+    }
+}
+
+/// Intermediate type used to parse a [Json] value, holding the stored value
+/// and the value deserialized from it.
+#[doc(hidden)]
+pub struct JsonIr<T> {
+    stored: Vec<u8>,
+    value: T,
+}
+
+impl<T> ConvIr<Json<T>> for JsonIr<T>
+where
+    T: DeserializeOwned,
+{
+    fn new(v: Value) -> Result<Self, FromValueError> {
+        match v {
+            Value::Bytes(stored) => match serde_json::from_slice(&stored) {
+                Ok(value) => Ok(JsonIr { stored, value }),
+                Err(_) => Err(FromValueError(Value::Bytes(stored))),
+            },
+            v => Err(FromValueError(v)),
+        }
+    }
+
+    fn commit(self) -> Json<T> {
+        Json {
+            value: self.value,
+            stored: self.stored,
+        }
+    }
+
+    fn rollback(self) -> Value {
+        Value::Bytes(self.stored)
+    }
+}
+
+impl<T> FromValue for Json<T>
+where
+    T: DeserializeOwned,
+{
+    type Intermediate = JsonIr<T>;
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let map = BTreeMap::from([("a".to_owned(), vec![1, 2]), ("b".to_owned(), vec![])]);
+        let value = Json::new(map.clone()).unwrap().to_value();
+        assert_eq!(value, Value::Bytes(br#"{"a":[1,2],"b":[]}"#.to_vec()));
+        let read = Json::<BTreeMap<String, Vec<u64>>>::from_value_opt(value).unwrap();
+        assert_eq!(read.into_inner(), map);
+    }
+
+    #[test]
+    fn test_unserializable_value() {
+        let map = BTreeMap::from([(vec![1], 1)]);
+        assert!(Json::new(map).is_err());
+    }
+
+    #[test]
+    fn test_invalid_value() {
+        let value = Value::Bytes(b"{not json".to_vec());
+        assert!(Json::<serde_json::Value>::from_value_opt(value).is_err());
+        let value = Value::Bytes(b"[1, -2]".to_vec());
+        assert!(Json::<Vec<u64>>::from_value_opt(value).is_err());
+        assert!(Json::<Vec<u64>>::from_value_opt(Value::Int(1)).is_err());
+    }
+}
@@ -20,6 +20,33 @@
 //!
 //! This crate also supports SQL transactions, see [Transaction] for more details.
 //!
+//! Queries and transactions fail with a [SqlError], telling apart duplicate
+//! keys, conflicts, lost connections and the other kinds of failures, see
+//! [SqlErrorKind]. It converts into an [anyhow::Error] with `?`.
+//!
+//! Queries and transaction operations can be logged or measured by registering a
+//! [QueryObserver](observer::QueryObserver), see the [observer] module, and
+//! queries slower than a threshold reported with their plan, see the
+//! [slow_query] module, or to a callback of their connection, see
+//! [Connection::with_slow_query_log]. Connections given a label export the
+//! stats of their queries and transactions, see the [metrics] module. The
+//! version and features of the server behind a connection can be probed and
+//! cached on it, see the [capabilities] module.
+//!
+//! Pool sizes and timeouts can be configured per connection role, see the
+//! [config] module, and transactions aborted by a deadlock retried, or
+//! committed without being applied twice when their commit fails ambiguously,
+//! see the [retry] module. The results of read queries on hot tables can be
+//! cached, see the [cache] module, and broken connections replaced in the
+//! background, see the [keepalive] module. Reads that must see recent writes
+//! can avoid lagging replicas, see the [lag] module. Session variables can
+//! be set for the duration of a closure, see [Connection::with_session_vars].
+//! Large BLOB values can be read and written in chunks, see [BlobHandle].
+//! The rows of queries can be paginated with [Limit] and [Offset]
+//! parameters, which the macro requires after `LIMIT` and `OFFSET`.
+//! With the `failpoints` feature, failures of queries and transactions can be
+//! injected in tests, see the `failpoints` module.
+//!
 //! For some working example usage you can look at `tests.rs`, below is a simplified one.
 //!
 //! # Example
@@ -55,6 +82,18 @@
 
 #![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
 
+pub mod blob;
+pub mod cas;
+mod compressed;
+mod datetime;
+mod from_row;
+pub mod id_allocator;
+mod json;
+mod limit;
+pub mod migrations;
+mod paged;
+#[doc(hidden)]
+pub mod query_stream;
 #[cfg(test)]
 mod tests;
 
@@ -67,6 +106,7 @@ pub use futures_util;
 pub use mysql_async;
 use mysql_async::Value;
 pub use rusqlite;
+pub use serde_json;
 use rusqlite::types::FromSql as FromSqliteValue;
 use rusqlite::types::FromSqlResult as FromSqliteValueResult;
 use rusqlite::types::ToSql as ToSqliteValue;
@@ -75,14 +115,59 @@ use rusqlite::types::Value as SqliteValue;
 use rusqlite::types::ValueRef as SqliteValueRef;
 use rusqlite::Result as SqliteResult;
 pub use sql_common;
+pub use sql_common::cache;
+pub use sql_common::capabilities;
+pub use sql_common::codes;
+pub use sql_common::config;
+pub use sql_common::error;
+pub use sql_common::explain;
+#[cfg(feature = "failpoints")]
+pub use sql_common::failpoints;
+pub use sql_common::fallback;
+pub use sql_common::keepalive;
+pub use sql_common::lag;
+pub use sql_common::metrics;
 pub use sql_common::mysql;
 pub use sql_common::mysql::OssConnection;
+pub use sql_common::observer;
+pub use sql_common::retry;
+pub use sql_common::slow_query;
 pub use sql_common::sqlite;
+pub use sql_common::warm;
+pub use sql_common::batch::write_batches;
+pub use sql_common::batch::BatchWriteResult;
+pub use sql_common::cache::CachedConnection;
+pub use sql_common::cancel::CancellationToken;
+pub use sql_common::cancel::QueryCancelled;
+pub use sql_common::error::SqlError;
+pub use sql_common::error::SqlErrorKind;
+pub use sql_common::timeout::AcquireTimeout;
+pub use sql_common::timeout::QueryTimeout;
+pub use sql_common::transaction::IsolationLevel;
 pub use sql_common::transaction::Transaction;
+pub use sql_common::transaction::TransactionOptions;
 pub use sql_common::Connection;
 pub use sql_common::SqlConnections;
 pub use sql_common::SqlShardedConnections;
 pub use sql_common::WriteResult;
+pub use sql_common::WriteResultWithRows;
+#[doc(hidden)]
+pub use sql_macros::_queries_impl;
+pub use sql_macros::FromRow;
+
+pub use crate::blob::BlobHandle;
+pub use crate::compressed::Compressed;
+pub use crate::datetime::UtcDateTime;
+pub use crate::from_row::FromRow;
+#[doc(hidden)]
+pub use crate::from_row::RowValues;
+pub use crate::id_allocator::IdAllocator;
+pub use crate::json::Json;
+pub use crate::limit::Limit;
+pub use crate::limit::Offset;
+pub use crate::migrations::Migration;
+pub use crate::migrations::Migrator;
+pub use crate::paged::Page;
 
 /// Wrapper around MySql Value to implement Sqlite traits on it.
 /// This should never be used directly, it is made public so that internal macros can make use of it
@@ -122,251 +207,243 @@ impl FromSqliteValue for ValueWrapper {
     }
 }
 
-#[macro_export]
-/// TODO: write doc for this macro and consider rewriting this as a proc macro
-macro_rules! queries {
-    () => ();
-
-    (
-        $vi:vis read $name:ident (
-            $( $pname:ident: $ptype:ty ),* $(,)*
-            $( >list $lname:ident: $ltype:ty )*
-        ) -> ($( $rtype:ty ),* $(,)*) { $q:expr }
-        $( $tt:tt )*
-    ) => (
-        $crate::queries! {
-            $vi read $name (
-                $( $pname: $ptype ),*
-                $( >list $lname: $ltype )*
-            ) -> ($( $rtype ),*) { mysql($q) sqlite($q) }
-            $( $tt )*
-        }
-    );
-
-    (
-        $vi:vis read $name:ident (
-            $( $pname:ident: $ptype:ty ),* $(,)*
-            $( >list $lname:ident: $ltype:ty )*
-        ) -> ($( $rtype:ty ),* $(,)*) { mysql($mysql_q:expr) sqlite($sqlite_q:expr) }
-        $( $tt:tt )*
-    ) => (
-        #[allow(non_snake_case)]
-        $vi mod $name {
-            $crate::_read_query_impl!((
-                $( $pname: $ptype, )*
-                $( >list $lname: $ltype )*
-            ) -> ($( $rtype ),*) { mysql($mysql_q) sqlite($sqlite_q) });
-
-            #[allow(dead_code)]
-            pub async fn query(
-                connection: & Connection,
-                $( $pname: & $ptype, )*
-                $( $lname: & [ $ltype ], )*
-            ) -> Result<Vec<($( $rtype, )*)>, Error> {
-                query_internal(connection, None $( , $pname )* $( , $lname )*)
-                    .await
-                    .context(stringify!(While executing $name query))
-            }
-
-            #[allow(dead_code)]
-            pub async fn commented_query(
-                connection: & Connection,
-                comment: &str,
-                $( $pname: & $ptype, )*
-                $( $lname: & [ $ltype ], )*
-            ) -> Result<Vec<($( $rtype, )*)>, Error> {
-                query_internal(connection, Some(comment) $( , $pname )* $( , $lname )*)
-                    .await
-                    .context(stringify!(While executing $name query))
-            }
-
-            #[allow(dead_code)]
-            pub async fn query_with_transaction(
-                transaction: Transaction,
-                $( $pname: & $ptype, )*
-                $( $lname: & [ $ltype ], )*
-            ) -> Result<(Transaction, Vec<($( $rtype, )*)>), Error> {
-                query_internal_with_transaction(transaction, None $( , $pname )* $( , $lname )*)
-                    .await
-                    .context(stringify!(While executing $name query in transaction))
-            }
-
-            #[allow(dead_code)]
-            pub async fn commented_query_with_transaction(
-                transaction: Transaction,
-                comment: &str,
-                $( $pname: & $ptype, )*
-                $( $lname: & [ $ltype ], )*
-            ) -> Result<(Transaction, Vec<($( $rtype, )*)>), Error> {
-                query_internal_with_transaction(transaction, Some(comment) $( , $pname )* $( , $lname )*)
-                    .await
-                    .context(stringify!(While executing $name query in transaction))
-            }
-        }
-        $crate::queries!($( $tt )*);
-    );
-
-    (
-        $vi:vis write $name:ident (
-            values: ($( $vname:ident: $vtype:ty ),* $(,)*)
-            $( , $pname:ident: $ptype:ty )* $(,)*
-        ) { $qtype:ident, $q:expr }
-        $( $tt:tt )*
-    ) => (
-        $crate::queries! {
-            $vi write $name (
-                values: ($( $vname: $vtype ),*)
-                $( , $pname: $ptype )*
-            ) { $qtype, mysql($q) sqlite($q) }
-            $( $tt )*
-        }
-    );
+/// Parameters of a query executed as a MySQL prepared statement, each bound to
+/// its own named placeholder.
+/// This should never be used directly, it is made public so that internal macros can make use of it
+#[doc(hidden)]
+#[derive(Default)]
+pub struct MysqlParams(Vec<(String, Value)>);
+
+impl MysqlParams {
+    /// The most placeholders a MySQL prepared statement can have.
+    pub const MAX_PLACEHOLDERS: usize = 65535;
+
+    /// Bind the value to a new placeholder and return that placeholder.
+    pub fn bind(&mut self, value: Value) -> String {
+        let name = format!("p{}", self.0.len());
+        let placeholder = format!(":{}", name);
+        self.0.push((name, value));
+        placeholder
+    }
 
-    (
-        $vi:vis write $name:ident (
-            values: ($( $vname:ident: $vtype:ty ),* $(,)*)
-            $( , $pname:ident: $ptype:ty )* $(,)*
-        ) { $qtype:ident, mysql($mysql_q:expr) sqlite($sqlite_q:expr) }
-        $( $tt:tt )*
-    ) => (
-        #[allow(non_snake_case)]
-        $vi mod $name {
-            $crate::_write_query_impl!(values: ($( $vname: $vtype ),*), ($( $pname: $ptype ),* ) {
-                $qtype,
-                mysql($mysql_q)
-                sqlite($sqlite_q)
-            });
-
-            #[allow(dead_code)]
-            pub async fn query(
-                connection: &Connection,
-                values: &[($( & $vtype, )*)],
-                $( $pname: & $ptype ),*
-            ) -> Result<WriteResult, Error> {
-                query_internal(connection, None, values $( , $pname )*)
-                    .await
-                    .context(stringify!(While executing $name query))
-            }
+    /// Bind each of the values to a new placeholder and return the
+    /// parenthesized list of those placeholders.
+    pub fn bind_list(&mut self, values: impl IntoIterator<Item = Value>) -> String {
+        let placeholders: Vec<_> = values.into_iter().map(|value| self.bind(value)).collect();
+        format!("({})", placeholders.join(", "))
+    }
+}
 
-            #[allow(dead_code)]
-            pub async fn commented_query(
-                connection: &Connection,
-                comment: &str,
-                values: &[($( & $vtype, )*)],
-                $( $pname: & $ptype ),*
-            ) -> Result<WriteResult, Error> {
-                query_internal(connection, Some(comment), values $( , $pname )*)
-                    .await
-                    .context(stringify!(While executing $name query))
-            }
+/// The most rows of `columns` values each a statement executed as a MySQL
+/// prepared statement can insert, with `other_placeholders` of its
+/// placeholders taken by the other parameters of the query.
+/// This should never be used directly, it is made public so that internal macros can make use of it
+#[doc(hidden)]
+pub fn mysql_prepared_max_rows(columns: usize, other_placeholders: usize) -> usize {
+    let placeholders = MysqlParams::MAX_PLACEHOLDERS.saturating_sub(other_placeholders);
+    (placeholders / columns.max(1)).max(1)
+}
 
-            #[allow(dead_code)]
-            pub async fn query_with_transaction(
-                transaction: Transaction,
-                values: &[($( & $vtype, )*)],
-                $( $pname: & $ptype ),*
-            ) -> Result<(Transaction, WriteResult), Error> {
-                query_internal_with_transaction(transaction, None, values $( , $pname )*)
-                    .await
-                    .context(stringify!(While executing $name query))
-            }
+/// The number of placeholders of a `>list` parameter of `len` values in SQLite
+/// queries. Short lists are rounded up to a power of two, the last value
+/// filling the extra placeholders, which doesn't change the result of an
+/// `IN` clause, so that queries over lists of similar lengths share a single
+/// cached prepared statement.
+/// This should never be used directly, it is made public so that internal macros can make use of it
+#[doc(hidden)]
+pub fn sqlite_list_placeholders(len: usize) -> usize {
+    // Longer lists are rare enough, and costly enough to run, that preparing
+    // their statements doesn't matter.
+    const MAX_PADDED_LEN: usize = 1024;
+    if len == 0 || len > MAX_PADDED_LEN {
+        len
+    } else {
+        len.next_power_of_two()
+    }
+}
 
-            #[allow(dead_code)]
-            pub async fn commented_query_with_transaction(
-                transaction: Transaction,
-                comment: &str,
-                values: &[($( & $vtype, )*)],
-                $( $pname: & $ptype ),*
-            ) -> Result<(Transaction, WriteResult), Error> {
-                query_internal_with_transaction(transaction, Some(comment), values $( , $pname )*)
-                    .await
-                    .context(stringify!(While executing $name query))
-            }
-        }
-        $crate::queries!($( $tt )*);
-    );
+/// The SQL of a query as it would be sent to each backend, returned by the
+/// `render` function of the modules defined by [queries!].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RenderedQuery {
+    /// The query sent to [Connection::Mysql] connections, with the values of
+    /// its parameters escaped and interpolated.
+    pub mysql: String,
+    /// The query prepared on [Connection::Sqlite] connections, with a named
+    /// placeholder for each of its values.
+    pub sqlite: String,
+}
 
-    (
-        $vi:vis write $name:ident (
-            $( $pname:ident: $ptype:ty ),* $(,)*
-            $( >list $lname:ident: $ltype:ty )*
-        ) { $qtype:ident, $q:expr }
-        $( $tt:tt )*
-    ) => (
-        $crate::queries! {
-            $vi write $name (
-                $( $pname: $ptype ),*
-                $( >list $lname: $ltype )*
-            ) { $qtype, mysql($q) sqlite($q) }
-            $( $tt )*
+impl From<MysqlParams> for mysql_async::Params {
+    fn from(params: MysqlParams) -> Self {
+        if params.0.is_empty() {
+            mysql_async::Params::Empty
+        } else {
+            mysql_async::Params::from(params.0)
         }
-    );
-
-    (
-        $vi:vis write $name:ident (
-            $( $pname:ident: $ptype:ty ),* $(,)*
-            $( >list $lname:ident: $ltype:ty )*
-        ) { $qtype:ident, mysql($mysql_q:expr) sqlite($sqlite_q:expr) }
-        $( $tt:tt )*
-    ) => (
-        #[allow(non_snake_case)]
-        $vi mod $name {
-            $crate::_write_query_impl!((
-                $( $pname: $ptype, )*
-                $( >list $lname: $ltype )*
-            ) {
-                $qtype,
-                mysql($mysql_q)
-                sqlite($sqlite_q)
-            });
-
-            #[allow(dead_code)]
-            pub async fn query(
-                connection: &Connection,
-                $( $pname: & $ptype, )*
-                $( $lname: & [ $ltype ], )*
-            ) -> Result<WriteResult, Error> {
-                query_internal(connection, None $( , $pname )* $( , $lname )*)
-                    .await
-                    .context(stringify!(While executing $name query))
-            }
-
-            #[allow(dead_code)]
-            pub async fn commented_query(
-                connection: &Connection,
-                comment: &str,
-                $( $pname: & $ptype, )*
-                $( $lname: & [ $ltype ], )*
-            ) -> Result<WriteResult, Error> {
-                query_internal(connection, Some(comment) $( , $pname )* $( , $lname )*)
-                    .await
-                    .context(stringify!(While executing $name query))
-            }
-
-            #[allow(dead_code)]
-            pub async fn query_with_transaction(
-                transaction: Transaction,
-                $( $pname: & $ptype, )*
-                $( $lname: & [ $ltype ], )*
-            ) -> Result<(Transaction, WriteResult), Error> {
-                query_internal_with_transaction(transaction, None $( , $pname )* $( , $lname )*)
-                    .await
-                    .context(stringify!(While executing $name query))
-            }
+    }
+}
 
-            #[allow(dead_code)]
-            pub async fn commented_query_with_transaction(
-                transaction: Transaction,
-                comment: &str,
-                $( $pname: & $ptype, )*
-                $( $lname: & [ $ltype ], )*
-            ) -> Result<(Transaction, WriteResult), Error> {
-                query_internal_with_transaction(transaction, Some(comment) $( , $pname )* $( , $lname )*)
-                    .await
-                    .context(stringify!(While executing $name query))
-            }
-        }
-        $crate::queries!($( $tt )*);
+/// Define SQL queries, each as a module named after the query.
+///
+/// `read` queries take the parameters in parentheses and return the rows of
+/// their result as tuples of the types after `->`, or as a single type
+/// implementing [FromRow], usually derived, when `->` is followed by that
+/// type rather than a parenthesized list. `write` queries return a
+/// [WriteResult] and must start with their type, either `none`,
+/// `insert_or_ignore` or `insert_or_update(column, ...)`. The
+/// `insert_or_ignore` type provides an `{insert_or_ignore}` placeholder for
+/// the backend specific `INSERT IGNORE` statement, and `insert_or_update` an
+/// `{insert_or_update}` placeholder, put after the inserted rows, for the
+/// `ON DUPLICATE KEY UPDATE` or `ON CONFLICT DO UPDATE` clause setting the
+/// given columns to their inserted values. A `write` query can also take the
+/// rows to insert as a first `values` parameter, used as the `{values}`
+/// placeholder. A `write` query without `values` can read the rows it affects
+/// with a `RETURNING` clause, supported by MariaDB and SQLite but not MySQL,
+/// when declared with a `->` return type like a `read` query, and then
+/// returns a [WriteResultWithRows] instead.
+///
+/// A `write` query taking `values` can declare `chunk_size(rows)` after its
+/// type, e.g. `none, chunk_size(1000),`, to insert its values with one
+/// statement per chunk of at most that many rows rather than a single one,
+/// which could exceed the `max_allowed_packet` of the server. The chunks are
+/// inserted in a transaction, started by `query` when there is more than one
+/// chunk, and the returned [WriteResult] adds up their affected rows. The
+/// query returned by `render` still inserts all the rows. On
+/// [Connection::OssMysql] connections, the values are also chunked so that
+/// no statement has more than the 65535 parameters a prepared statement
+/// accepts.
+///
+/// Parameters are used in a query as `{name}` placeholders, and parameters
+/// declared as `>list name: Type` after all others take a slice of values for
+/// `IN {name}` clauses, also with `values`, e.g. to only update some of the
+/// inserted rows on conflicts. Optional parameters, declared last as
+/// `>maybe name: Type = "fragment"`, are taken as an `Option` and replace
+/// their `{name}` placeholder with their fragment, itself using `{name}` for
+/// the value, when given and with nothing otherwise, e.g. for filters that
+/// only apply to some calls. A query can be given once for all backends, or
+/// as `mysql("...") sqlite("...")` if they need a different syntax.
+///
+/// Alternative MySQL queries for schema or server variants can be declared
+/// between the `mysql` and `sqlite` ones, e.g. `mysql8("...")`. They are run
+/// instead of the `mysql` one on [Connection::OssMysql] connections created
+/// with [OssConnection::with_schema_variant] for that variant, and on their
+/// transactions. `render` returns the `mysql` one.
+///
+/// ```
+/// use sql::queries;
+///
+/// queries! {
+///     read SelectValues(min: u64, >list ids: u64) -> (u64, String) {
+///         "SELECT id, value FROM foo WHERE id >= {min} AND id IN {ids}"
+///     }
+///
+///     read SelectFiltered(min: u64, >maybe value: String = "AND value = {value}") -> (u64) {
+///         "SELECT id FROM foo WHERE id >= {min} {value}"
+///     }
+///
+///     write InsertValues(values: (id: u64, value: String)) {
+///         insert_or_ignore,
+///         "{insert_or_ignore} INTO foo (id, value) VALUES {values}"
+///     }
+///
+///     write UpsertValues(values: (id: u64, value: String)) {
+///         insert_or_update(value),
+///         "INSERT INTO foo (id, value) VALUES {values} {insert_or_update}"
+///     }
+///
+///     write DeleteValues(>list ids: u64) -> (u64, String) {
+///         none,
+///         "DELETE FROM foo WHERE id IN {ids} RETURNING id, value"
+///     }
+///
+///     pub(crate) write UpdateValue(id: u64, value: String) {
+///         none,
+///         mysql("UPDATE foo SET value = {value} WHERE id = {id} LIMIT 1")
+///         sqlite("UPDATE foo SET value = {value} WHERE id = {id}")
+///     }
+///
+///     read SelectRanks() -> (u64, u64) {
+///         mysql("SELECT id, (SELECT COUNT(*) FROM foo AS f WHERE f.id <= foo.id) FROM foo")
+///         mysql8("SELECT id, ROW_NUMBER() OVER (ORDER BY id) FROM foo")
+///         sqlite("SELECT id, ROW_NUMBER() OVER (ORDER BY id) FROM foo")
+///     }
+/// }
+/// #
+/// # fn main() {}
+/// ```
+///
+/// Besides `query`, `read` queries have a `query_stream` function returning a
+/// stream of their rows, to read large results without holding them all in
+/// memory. On sqlite, the rows are read in batches, each of them running the
+/// query again, so the query should order its rows deterministically.
+///
+/// They also have a `query_with_consistency` function taking
+/// [SqlConnections] and the [lag::Consistency] the read needs instead of a
+/// connection, and running on the read connection, the read master
+/// connection, or the read connection only if its replica is fresh enough,
+/// see [SqlConnections::read_with_consistency].
+///
+/// Queries also have a `query_with_timeout` function taking the maximum
+/// [std::time::Duration] of the query after the connection, failing with a
+/// [QueryTimeout] error if it passes. The query is then killed on
+/// [Connection::OssMysql] connections, and interrupted on sqlite ones, whose
+/// connection is released. Queries on [Connection::Mysql] are only dropped.
+///
+/// Likewise, the `query_with_cancellation` function takes a
+/// [CancellationToken] and fails with a [QueryCancelled] error if it is
+/// cancelled before the query completes. As sqlite queries block the thread
+/// they run on, the token must then be cancelled from another thread.
+///
+/// `read_paged` queries read their rows a page at a time, ordered by a key
+/// that must be unique and returned as the first column, e.g. the primary key
+/// of the table. They start with the name of that column, followed by a
+/// `SELECT` without `ORDER BY` or `LIMIT`, and have a `next_page` function
+/// taking the cursor and the maximum number of rows of the page after the
+/// connection, and returning a [Page]. The first page is read with a `None`
+/// cursor, and the following ones with the cursor of the previous page.
+///
+/// ```
+/// use sql::queries;
+///
+/// queries! {
+///     read_paged SelectPaged(min: u64) -> (u64, String) {
+///         id,
+///         "SELECT id, value FROM foo WHERE id >= {min}"
+///     }
+/// }
+/// #
+/// # fn main() {}
+/// ```
+///
+/// The query is run as a derived table filtered by the cursor, ordered by the
+/// key and limited by the size of the page, which is identical on every
+/// backend. As the page is read with `page_after` and `page_limit`
+/// parameters, those names can't be used by the parameters of the query.
+/// The size of the page is lowered to [Limit::MAX].
+///
+/// The `render` function of each query takes its parameters like `query`,
+/// without the connection, and returns the [RenderedQuery] with the SQL that
+/// would be sent to each backend, e.g. for logging or snapshot tests.
+///
+/// The `explain` function takes the connection and parameters like `query`,
+/// and returns the [QueryPlan](explain::QueryPlan) of the query on that
+/// connection without running it, e.g. to assert in tests that the query
+/// uses an index.
+///
+/// The placeholders of every query given as a string literal are checked
+/// against its parameters when the macro is expanded, and those following
+/// `LIMIT` and `OFFSET` must be parameters of type [Limit] and [Offset].
+///
+/// On [Connection::OssMysql] connections and transactions, queries are
+/// executed as prepared statements with their parameters bound by the server
+/// rather than interpolated into the query. The client behind
+/// [Connection::Mysql] only accepts query strings, so parameters are still
+/// escaped and interpolated there.
+#[macro_export]
+macro_rules! queries {
+    ( $( $tt:tt )* ) => (
+        $crate::_queries_impl! { $crate; $( $tt )* }
     );
 }
 
@@ -374,30 +451,29 @@ macro_rules! queries {
 #[doc(hidden)]
 macro_rules! _query_common {
     () => {
-        use std::fmt::Write;
         // Some users of queries! have redefined Result
         use std::result::Result;
 
-        use $crate::anyhow::anyhow;
         use $crate::anyhow::Context;
         use $crate::anyhow::Error;
-        use $crate::cloned::cloned;
-        use $crate::futures::compat::Future01CompatExt;
-        use $crate::futures::future::Future;
-        use $crate::futures::future::FutureExt;
         use $crate::futures::future::TryFutureExt;
         use $crate::mysql_async::prelude::*;
         use $crate::rusqlite::types::ToSql as ToSqliteValue;
         use $crate::rusqlite::Connection as SqliteConnection;
         use $crate::rusqlite::Result as SqliteResult;
-        use $crate::rusqlite::Row as SqliteRow;
-        use $crate::rusqlite::Statement as SqliteStatement;
-        use $crate::sql_common::mysql::OssConnection;
+        use $crate::rusqlite::CachedStatement as SqliteStatement;
+        use $crate::sql_common::cancel::with_cancellation;
+        use $crate::sql_common::cancel::CancellationToken;
+        use $crate::sql_common::observer::observe_query;
+        use $crate::sql_common::observer::QueryContext;
+        use $crate::sql_common::timeout::with_timeout;
         use $crate::sqlite::SqliteConnectionGuard;
         use $crate::sqlite::SqliteMultithreaded;
         use $crate::sqlite::SqliteQueryType;
         use $crate::Connection;
-        use $crate::HList;
+        use $crate::MysqlParams;
+        use $crate::RenderedQuery;
+        use $crate::SqlError;
         use $crate::Transaction;
         use $crate::ValueWrapper;
 
@@ -412,60 +488,185 @@ macro_rules! _read_query_impl {
     ( (
         $( $pname:ident: $ptype:ty, )*
         $( >list $lname:ident: $ltype:ty )*
-    ) -> ($( $rtype:ty ),*) { mysql($mysql_q:expr) sqlite($sqlite_q:expr) } ) => (
+        $( >maybe $mname:ident: $mtype:ty = $mfrag:literal )*
+    ) -> ($( $rtype:ty ),*) { $query_type:ident $qtype:tt, mysql($mysql_q:expr $( , $variant:literal => $variant_q:expr )*) sqlite($sqlite_q:expr) } ) => (
+        $crate::_read_query_impl!(@common (
+            $( $pname: $ptype, )*
+            $( >list $lname: $ltype )*
+            $( >maybe $mname: $mtype = $mfrag )*
+        ) -> ($( $rtype, )*) { $query_type $qtype, mysql($mysql_q $( , $variant => $variant_q )*) sqlite($sqlite_q) });
+
+        fn mysql_async_row_to_tuple(row: $crate::mysql_async::Row) -> Result<($( $rtype, )*), Error> {
+                let mut idx = 0;
+                let res = (
+                    $({
+                        let res: $crate::mysql_async::Value = row.get(idx).ok_or($crate::anyhow::anyhow!("Failed to parse idx"))?;
+                        idx += 1;
+                        <$rtype as FromValue>::from_value_opt(res)
+                            .unwrap_or_else(|err| {
+                                panic!("Failed to parse `{}`: {}", stringify!($rtype), err)
+                            })
+                    },)*
+                );
+                // suppress unused_assignments warning
+                let _ = idx;
+                Ok(res)
+        }
+
+        fn sqlite_row_to_tuple(row: &SqliteRow) -> SqliteResult<($( $rtype, )*)> {
+            // This is currently necessary to use the `mut idx` to keep track of which element of
+            // the tuple we are constructing.
+            // Once the feature: `macro_metavar_expr` is stable, we can replace `row.get(idx)` with
+            // ${index()} and clean up this code a little
+            {
+                let mut idx = 0;
+                let res = (
+                    $({
+                        let res: ValueWrapper = row.get(idx)?;
+                        idx += 1;
+                        <$rtype as FromValue>::from_value_opt(res.0)
+                            .unwrap_or_else(|err| {
+                                panic!("Failed to parse `{}`: {}", stringify!($rtype), err)
+                            })
+                    },)*
+                );
+                // suppress unused_assignments warning
+                let _ = idx;
+                Ok(res)
+            }
+        }
+    );
+    ( (
+        $( $pname:ident: $ptype:ty, )*
+        $( >list $lname:ident: $ltype:ty )*
+        $( >maybe $mname:ident: $mtype:ty = $mfrag:literal )*
+    ) -> row $row:ty { $query_type:ident $qtype:tt, mysql($mysql_q:expr $( , $variant:literal => $variant_q:expr )*) sqlite($sqlite_q:expr) } ) => (
+        $crate::_read_query_impl!(@common (
+            $( $pname: $ptype, )*
+            $( >list $lname: $ltype )*
+            $( >maybe $mname: $mtype = $mfrag )*
+        ) -> $row { $query_type $qtype, mysql($mysql_q $( , $variant => $variant_q )*) sqlite($sqlite_q) });
+
+        fn mysql_async_row_to_tuple(row: $crate::mysql_async::Row) -> Result<$row, Error> {
+            <$row as $crate::FromRow>::from_row(row.unwrap())
+        }
+
+        fn sqlite_row_to_tuple(row: &SqliteRow) -> SqliteResult<$row> {
+            let values = (0..row.as_ref().column_count())
+                .map(|idx| row.get::<_, ValueWrapper>(idx).map(|value| value.0))
+                .collect::<SqliteResult<Vec<_>>>()?;
+            <$row as $crate::FromRow>::from_row(values)
+                .map_err(|err| $crate::rusqlite::Error::UserFunctionError(err.into()))
+        }
+    );
+    (@common (
+        $( $pname:ident: $ptype:ty, )*
+        $( >list $lname:ident: $ltype:ty )*
+        $( >maybe $mname:ident: $mtype:ty = $mfrag:literal )*
+    ) -> $row:ty { $query_type:ident $qtype:tt, mysql($mysql_q:expr $( , $variant:literal => $variant_q:expr )*) sqlite($sqlite_q:expr) } ) => (
+        use $crate::rusqlite::Row as SqliteRow;
+
         $crate::_query_common!();
 
         async fn query_internal(
             connection: &Connection,
             comment: Option<&str>,
+            timeout: Option<std::time::Duration>,
+            cancellation: Option<&CancellationToken>,
             $( $pname: & $ptype, )*
             $( $lname: & [ $ltype ], )*
-        ) -> Result<Vec<($( $rtype, )*)>, Error> {
+            $( $mname: Option<& $mtype>, )*
+        ) -> Result<Vec<$row>, Error> {
             match connection {
                 Connection::Sqlite(multithread_con) => {
-                    sqlite_query(multithread_con $( , $pname )* $( , $lname )*).await
+                    sqlite_query(multithread_con, timeout, cancellation $( , $pname )* $( , $lname )* $( , $mname )*).await
                 }
                 Connection::Mysql(conn) => {
-                    let mut query = mysql_query($( $pname, )* $( $lname, )*);
+                    let mut query = mysql_query($( $pname, )* $( $lname, )* $( $mname, )*);
                     if let Some(comment) = comment {
                         query.insert_str(0, &format!("/* {} */", comment));
                     }
-                    conn.read_query(query).map_err(Error::from).await
+                    with_cancellation(
+                        cancellation,
+                        with_timeout(timeout, conn.read_query(query).map_err(Error::from)),
+                    )
+                    .await
                 }
                 Connection::OssMysql(conn) => {
-                    let query = mysql_query($( $pname, )* $( $lname, )*);
-
-                    let mut con = OssConnection::get_conn_counted(conn.pool.clone(), &conn.stats).await?;
-                    let mut res = conn.read_query(&mut con, &query).map_err(Error::from).await?;
-
-                    let result = res
-                        .map( |row| mysql_async_row_to_tuple(row))
-                        .await?
-                        .into_iter()
-                        .collect::<Result<Vec<($( $rtype, )*)>, Error>>()?;
-
-
-                    Ok(result)
+                    let (query, params) = mysql_prepared_query(conn.schema_variant(), $( $pname, )* $( $lname, )* $( $mname, )*);
+
+                    let mut con = conn.get_conn().await?;
+                    let connection_id = con.id();
+                    conn.run_with_cancellation(connection_id, timeout, cancellation, async {
+                        let mut res = conn
+                            .read_prepared_query(&mut con, &query, params.into())
+                            .map_err(Error::from)
+                            .await?;
+
+                        let result = res
+                            .map( |row| mysql_async_row_to_tuple(row))
+                            .await?
+                            .into_iter()
+                            .collect::<Result<Vec<$row>, Error>>()?;
+
+                        Ok(result)
+                    })
+                    .await
                 }
             }
         }
 
-        fn mysql_async_row_to_tuple(row: $crate::mysql_async::Row) -> Result<($( $rtype, )*), Error> {
-            #[allow(clippy::eval_order_dependence)]
-                let mut idx = 0;
-                let res = (
-                    $({
-                        let res: $crate::mysql_async::Value = row.get(idx).ok_or($crate::anyhow::anyhow!("Failed to parse idx"))?;
-                        idx += 1;
-                        <$rtype as FromValue>::from_value_opt(res)
-                            .unwrap_or_else(|err| {
-                                panic!("Failed to parse `{}`: {}", stringify!($rtype), err)
-                            })
-                    },)*
-                );
-                // suppress unused_assignments warning
-                let _ = idx;
-                Ok(res)
+        // Not used by write queries returning rows, which can't be streamed.
+        #[allow(dead_code)]
+        fn query_stream_internal(
+            connection: &Connection,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+            $( $mname: Option<& $mtype>, )*
+        ) -> $crate::futures::stream::BoxStream<'static, Result<$row, Error>> {
+            use $crate::futures::stream::StreamExt;
+            use $crate::futures::stream::TryStreamExt;
+
+            match connection {
+                Connection::Sqlite(multithread_con) => {
+                    $crate::_prepare_sqlite_params!(
+                        params,
+                        $( $pname ),*
+                        $( >list $lname )*
+                        $( >maybe $mname )*
+                    );
+                    let query = sqlite_query_text($( $lname, )* $( $mname, )*);
+                    $crate::query_stream::sqlite_query_stream(
+                        multithread_con,
+                        query,
+                        params,
+                        sqlite_row_to_tuple,
+                    )
+                }
+                Connection::Mysql(conn) => {
+                    // The client only returns complete results, so stream
+                    // them once they have been read.
+                    let conn = conn.clone();
+                    let query = mysql_query($( $pname, )* $( $lname, )* $( $mname, )*);
+                    $crate::futures::stream::once(async move {
+                        conn.read_query(query).map_err(Error::from).await
+                    })
+                    .map_ok(|rows: Vec<$row>| {
+                        $crate::futures::stream::iter(rows.into_iter().map(Ok))
+                    })
+                    .try_flatten()
+                    .boxed()
+                }
+                Connection::OssMysql(conn) => {
+                    let (query, params) = mysql_prepared_query(conn.schema_variant(), $( $pname, )* $( $lname, )* $( $mname, )*);
+                    $crate::query_stream::mysql_query_stream(
+                        conn,
+                        query,
+                        params.into(),
+                        mysql_async_row_to_tuple,
+                    )
+                }
+            }
         }
 
         async fn query_internal_with_transaction(
@@ -473,21 +674,22 @@ macro_rules! _read_query_impl {
             comment: Option<&str>,
             $( $pname: & $ptype, )*
             $( $lname: & [ $ltype ], )*
-        ) -> Result<(Transaction, Vec<($( $rtype, )*)>), Error>{
+            $( $mname: Option<& $mtype>, )*
+        ) -> Result<(Transaction, Vec<$row>), Error>{
             match transaction {
                 Transaction::Sqlite(ref mut con) => {
                     let con = con
                         .take()
                         .expect("should be Some before transaction ended");
 
-                    sqlite_query_with_transaction(con $( , $pname )* $( , $lname )*)
+                    sqlite_query_with_transaction(con $( , $pname )* $( , $lname )* $( , $mname )*)
                         .await
                         .map(move |(con, res)| {
                             (Transaction::Sqlite(Some(con)), res)
                         })
                 }
                 Transaction::Mysql(ref mut transaction) => {
-                    let mut query = mysql_query($( $pname, )* $( $lname, )*);
+                    let mut query = mysql_query($( $pname, )* $( $lname, )* $( $mname, )*);
                     if let Some(comment) = comment {
                         query.insert_str(0, &format!("/* {} */", comment));
                     }
@@ -496,59 +698,65 @@ macro_rules! _read_query_impl {
                     let result = tr.read_query(query).map_err(Error::from).await?;
                     Ok((Transaction::Mysql(Some(tr)), result))
                 }
-                Transaction::OssMysql(ref mut transaction) => {
-                    let query = mysql_query($( $pname, )* $( $lname, )*);
+                Transaction::OssMysql(ref mut transaction, ref mut schema_variant, ref mut open_transaction) => {
+                    let (query, params) = mysql_prepared_query(schema_variant.as_deref(), $( $pname, )* $( $lname, )* $( $mname, )*);
 
                     let mut tr = transaction.take().expect("should be Some before transaction ended");
-                    let mut query_result  = tr.query_iter(query).map_err(Error::from).await?;
+                    let mut query_result  = tr.exec_iter(query, params).map_err(Error::from).await?;
                     let result = query_result
                         .map(
                         |row| mysql_async_row_to_tuple(row)
                         )
                         .await?
                         .into_iter()
-                        .collect::<Result<Vec<($( $rtype, )*)>, Error>>()?;
-                    Ok((Transaction::OssMysql(Some(tr)), result))
+                        .collect::<Result<Vec<$row>, Error>>()?;
+                    Ok((Transaction::OssMysql(Some(tr), schema_variant.take(), open_transaction.take()), result))
                 }
             }
         }
 
         async fn sqlite_query(
             multithread_con: &SqliteMultithreaded,
+            timeout: Option<std::time::Duration>,
+            cancellation: Option<&CancellationToken>,
             $( $pname: & $ptype, )*
             $( $lname: & [ $ltype ], )*
-        ) -> Result<Vec<($( $rtype, )*)>, Error> {
+            $( $mname: Option<& $mtype>, )*
+        ) -> Result<Vec<$row>, Error> {
             $crate::_prepare_sqlite_params!(
                 params,
                 $( $pname ),*
                 $( >list $lname )*
+                $( >maybe $mname )*
             );
 
-            let con = multithread_con.acquire_sqlite_connection(SqliteQueryType::Read).await?;
-
-            let mut ref_params: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
-            for idx in 0..params.len() {
-                ref_params.push((&params[idx].0, &params[idx].1))
-            }
+            multithread_con.run_query_with_cancellation(SqliteQueryType::$query_type, timeout, cancellation, |con| {
+                let mut ref_params: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
+                for idx in 0..params.len() {
+                    ref_params.push((&params[idx].0, &params[idx].1))
+                }
 
-            sqlite_statement(&con  $( , $lname )*)
-                .and_then(|mut stmt| {
-                    stmt.query_map(
-                        &ref_params[..],
-                        sqlite_row_to_tuple
-                    )?.collect()
-                }).map_err(Error::from)
+                sqlite_statement(con  $( , $lname )* $( , $mname )*)
+                    .and_then(|mut stmt| {
+                        stmt.query_map(
+                            &ref_params[..],
+                            sqlite_row_to_tuple
+                        )?.collect()
+                    }).map_err(Error::from)
+            }).await
         }
 
         async fn sqlite_query_with_transaction(
             transaction: SqliteConnectionGuard,
             $( $pname: & $ptype, )*
             $( $lname: & [ $ltype ], )*
-        ) -> Result<(SqliteConnectionGuard, Vec<($( $rtype, )*)>), Error> {
+            $( $mname: Option<& $mtype>, )*
+        ) -> Result<(SqliteConnectionGuard, Vec<$row>), Error> {
             $crate::_prepare_sqlite_params!(
                 params,
                 $( $pname ),*
                 $( >list $lname )*
+                $( >maybe $mname )*
             );
 
             let mut ref_params: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
@@ -556,8 +764,8 @@ macro_rules! _read_query_impl {
                 ref_params.push((&params[idx].0, &params[idx].1))
             }
 
-            let res: SqliteResult<Vec<($( $rtype, )*)>> = {
-                let mut stmt = sqlite_statement(&transaction  $( , $lname )*)?;
+            let res: SqliteResult<Vec<$row>> = {
+                let mut stmt = sqlite_statement(&transaction  $( , $lname )* $( , $mname )*)?;
                 let res = stmt.query_map(
                     &ref_params[..],
                     sqlite_row_to_tuple
@@ -568,90 +776,173 @@ macro_rules! _read_query_impl {
             Ok((transaction, res?))
         }
 
-        fn mysql_query($( $pname: & $ptype, )* $( $lname: & [ $ltype ], )*) -> String {
+        fn render_internal(
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+            $( $mname: Option<& $mtype>, )*
+        ) -> RenderedQuery {
+            RenderedQuery {
+                mysql: mysql_query($( $pname, )* $( $lname, )* $( $mname, )*),
+                sqlite: sqlite_query_text(
+                    $( $crate::sqlite_list_placeholders($lname.len()), )*
+                    $( $mname.is_some(), )*
+                ),
+            }
+        }
+
+        fn mysql_query(
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+            $( $mname: Option<& $mtype>, )*
+        ) -> String {
             $crate::_emit_mysql_lnames!($( $lname ),*);
-            format!(
+            $crate::_emit_mysql_mnames!($( $mname = $mfrag ),*);
+            $crate::_write_mysql_query!(
+                $qtype,
                 $mysql_q,
-                $( $pname = ToValue::to_value(&$pname).as_sql(false), )*
-                $( $lname = $lname, )*
+                $( $pname ),*
+                $( >list $lname )*
+                $( >maybe $mname )*
             )
         }
 
+        fn mysql_prepared_query(
+            schema_variant: Option<&str>,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+            $( $mname: Option<& $mtype>, )*
+        ) -> (String, MysqlParams) {
+            let mut params = MysqlParams::default();
+            $( let $pname = params.bind(ToValue::to_value($pname)); )*
+            $( let $lname = params.bind_list($lname.iter().map(ToValue::to_value)); )*
+            $(
+                let $mname = $mname
+                    .map(|$mname| format!($mfrag, $mname = params.bind(ToValue::to_value($mname))))
+                    .unwrap_or_default();
+            )*
+            let query = $crate::_mysql_schema_variant!(
+                schema_variant,
+                [$( $variant => $variant_q ),*],
+                $mysql_q,
+                _write_mysql_prepared_query!($qtype, ($( $pname ),* $( >list $lname )* $( >maybe $mname )*))
+            );
+            (query, params)
+        }
+
         fn sqlite_statement<'a>(
             connection: &'a SqliteConnection,
             $( $lname: usize, )*
+            $( $mname: bool, )*
         ) -> SqliteResult<SqliteStatement<'a>> {
+            connection.prepare_cached(&sqlite_query_text($( $lname, )* $( $mname, )*))
+        }
+
+        fn sqlite_query_text($( $lname: usize, )* $( $mname: bool, )*) -> String {
             $crate::_emit_sqlite_lnames!($( $lname ),*);
-            connection.prepare(&format!(
+            $crate::_emit_sqlite_mnames!($( $mname = $mfrag ),*);
+            $crate::_write_sqlite_query!(
+                $qtype,
                 $sqlite_q,
-                $( $pname = concat!(":", stringify!($pname)), )*
-                $( $lname = $lname, )*
-            ))
+                $( $pname ),*
+                $( >list $lname )*
+                $( >maybe $mname )*
+            )
         }
 
-        fn sqlite_row_to_tuple(row: &SqliteRow) -> SqliteResult<($( $rtype, )*)> {
-            // This is currently necessary to use the `mut idx` to keep track of which element of
-            // the tuple we are constructing.
-            // Once the feature: `macro_metavar_expr` is stable, we can replace `row.get(idx)` with
-            // ${index()} and clean up this code a little
-            #[allow(clippy::eval_order_dependence)]
-            {
-                let mut idx = 0;
-                let res = (
-                    $({
-                        let res: ValueWrapper = row.get(idx)?;
-                        idx += 1;
-                        <$rtype as FromValue>::from_value_opt(res.0)
-                            .unwrap_or_else(|err| {
-                                panic!("Failed to parse `{}`: {}", stringify!($rtype), err)
-                            })
-                    },)*
-                );
-                // suppress unused_assignments warning
-                let _ = idx;
-                Ok(res)
-            }
-        }
     );
 }
 
 #[macro_export]
 #[doc(hidden)]
 macro_rules! _write_query_impl {
-    ( values: ($( $vname:ident: $vtype:ty ),*), ($( $pname:ident: $ptype:ty ),*) {
-        $qtype:ident,
-        mysql($mysql_q:expr)
+    ( values: ($( $vname:ident: $vtype:ty ),*), (
+        $( $pname:ident: $ptype:ty, )*
+        $( >list $lname:ident: $ltype:ty )*
+    ) {
+        $qtype:tt,
+        $( chunk_size($chunk_size:expr), )?
+        mysql($mysql_q:expr $( , $variant:literal => $variant_q:expr )*)
         sqlite($sqlite_q:expr)
     } ) => (
+        use $crate::BatchWriteResult;
         use $crate::WriteResult;
 
         $crate::_query_common!();
 
+        // The maximum number of rows inserted by each statement, if any. The
+        // statements executed as MySQL prepared statements are also limited
+        // to the rows fitting in their placeholders.
+        fn chunk_size(
+            prepared: bool,
+            $( $lname: & [ $ltype ], )*
+        ) -> Option<usize> {
+            let chunk_size: Option<usize> = None;
+            $( let chunk_size = Some($chunk_size); )?
+            if !prepared {
+                return chunk_size;
+            }
+            let columns: &[&str] = &[$( stringify!($vname) ),*];
+            let params: &[&str] = &[$( stringify!($pname) ),*];
+            let max_rows = $crate::mysql_prepared_max_rows(
+                columns.len(),
+                params.len() $( + $lname.len() )*,
+            );
+            Some(chunk_size.map_or(max_rows, |chunk_size| chunk_size.min(max_rows)))
+        }
+
         async fn query_internal(
             connection: &Connection,
             comment: Option<&str>,
+            timeout: Option<std::time::Duration>,
+            cancellation: Option<&CancellationToken>,
             values: &[($( & $vtype, )*)],
-            $( $pname: & $ptype ),*
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
         ) -> Result<WriteResult, Error> {
             if values.is_empty() {
                 return Ok(WriteResult::new(None, 0));
             }
 
+            let prepared = matches!(connection, Connection::OssMysql(_));
+            if chunk_size(prepared, $( $lname, )*).is_some_and(|chunk_size| values.len() > chunk_size) {
+                // The statements of the chunks are applied all or nothing.
+                let chunked = async {
+                    let transaction = connection.start_transaction().await?;
+                    let (transaction, res) = query_internal_with_transaction(
+                        transaction,
+                        comment,
+                        values,
+                        $( $pname, )*
+                        $( $lname, )*
+                    ).await?;
+                    transaction.commit().await?;
+                    Ok(res)
+                };
+                return with_cancellation(cancellation, with_timeout(timeout, chunked)).await;
+            }
+
             match connection {
                 Connection::Sqlite(multithread_con) => {
-                    sqlite_exec_query(multithread_con, values, $( $pname ),*).await
+                    sqlite_exec_query(multithread_con, timeout, cancellation, values, $( $pname, )* $( $lname, )*).await
                 }
                 Connection::Mysql(conn) => {
-                    let mut query = mysql_query(values, $( $pname ),*);
+                    let mut query = mysql_query(values, $( $pname, )* $( $lname, )*);
                     if let Some(comment) = comment {
                         query.insert_str(0, &format!("/* {} */", comment));
                     }
-                    let res = conn.write_query(query).map_err(Error::from).await?;
+                    let res = with_cancellation(
+                        cancellation,
+                        with_timeout(timeout, conn.write_query(query).map_err(Error::from)),
+                    )
+                    .await?;
                     Ok(res.into())
                 }
                 Connection::OssMysql(conn)=> {
-                    let query = mysql_query(values, $( $pname ),*);
-                    let res = conn.write_query(query).map_err(Error::from).await?;
+                    let (query, params) = mysql_prepared_query(conn.schema_variant(), values, $( $pname, )* $( $lname, )*);
+                    let res = conn
+                        .write_prepared_query(query, params.into(), timeout, cancellation)
+                        .map_err(Error::from)
+                        .await?;
                     Ok(res.into())
                 },
             }
@@ -661,26 +952,44 @@ macro_rules! _write_query_impl {
             mut transaction: Transaction,
             comment: Option<&str>,
             values: &[($( & $vtype, )*)],
-            $( $pname: & $ptype ),*
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
         ) -> Result<(Transaction, WriteResult), Error> {
             if values.is_empty() {
                 return Ok((transaction, WriteResult::new(None, 0)));
             }
 
+            let prepared = matches!(transaction, Transaction::OssMysql(..));
+            let chunk_size = chunk_size(prepared, $( $lname, )*).unwrap_or(values.len());
+            let mut results = Vec::new();
+            for chunk in values.chunks(chunk_size) {
+                let (tr, res) =
+                    query_chunk_with_transaction(transaction, comment, chunk, $( $pname, )* $( $lname, )*).await?;
+                transaction = tr;
+                results.push(Ok(res));
+            }
+            Ok((transaction, BatchWriteResult::new(results).into_write_result()?))
+        }
+
+        async fn query_chunk_with_transaction(
+            mut transaction: Transaction,
+            comment: Option<&str>,
+            values: &[($( & $vtype, )*)],
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> Result<(Transaction, WriteResult), Error> {
             match transaction {
-                Transaction::Sqlite(ref mut transaction) => {
-                    let con = transaction
-                        .take()
+                Transaction::Sqlite(ref con) => {
+                    let con = con
+                        .as_ref()
                         .expect("should be Some before transaction ended");
 
-                    sqlite_exec_query_with_transaction(con, values, $( $pname ),*)
-                        .await
-                        .map(move |(con, res)| {
-                            (Transaction::Sqlite(Some(con)), res)
-                        })
+                    // The transaction is rolled back when dropped on errors.
+                    let res = sqlite_exec_query_with_transaction(con, values, $( $pname, )* $( $lname, )*)?;
+                    Ok((transaction, res))
                 }
                 Transaction::Mysql(ref mut transaction) => {
-                    let mut query = mysql_query(values, $( $pname ),*);
+                    let mut query = mysql_query(values, $( $pname, )* $( $lname, )*);
                     if let Some(comment) = comment {
                         query.insert_str(0, &format!("/* {} */", comment));
                     }
@@ -690,24 +999,41 @@ macro_rules! _write_query_impl {
                     let result = tr.write_query(query).map_err(Error::from).await?;
                     Ok((Transaction::Mysql(Some(tr)), result.into()))
                 },
-                Transaction::OssMysql(ref mut transaction)=>{
-                    let query = mysql_query(values, $( $pname ),*);
+                Transaction::OssMysql(ref mut transaction, ref mut schema_variant, ref mut open_transaction)=>{
+                    let (query, params) = mysql_prepared_query(schema_variant.as_deref(), values, $( $pname, )* $( $lname, )*);
                     let mut tr = transaction.take().expect("should be Some before transaction ended");
 
-                    let query_result = tr.query_iter(query).await?;
+                    let query_result = tr.exec_iter(query, params).await?;
 
                     let last_insert_id = query_result.last_insert_id();
                     let rows_affected = query_result.affected_rows();
 
                     let result = WriteResult::new(last_insert_id, rows_affected);
 
-                    Ok((Transaction::OssMysql(Some(tr)), result.into()))
+                    Ok((Transaction::OssMysql(Some(tr), schema_variant.take(), open_transaction.take()), result.into()))
 
                 },
             }
         }
 
-        fn mysql_query(values: &[($( & $vtype, )*)], $( $pname: & $ptype ),*) -> String {
+        fn render_internal(
+            values: &[($( & $vtype, )*)],
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> RenderedQuery {
+            RenderedQuery {
+                mysql: mysql_query(values, $( $pname, )* $( $lname, )*),
+                sqlite: sqlite_query_text($( $crate::sqlite_list_placeholders($lname.len()) ),*),
+            }
+        }
+
+        fn mysql_query(
+            values: &[($( & $vtype, )*)],
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> String {
+            use std::fmt::Write;
+
             let mut val = String::new();
             let mut first = true;
             for value in values {
@@ -721,13 +1047,40 @@ macro_rules! _write_query_impl {
                 write!(&mut val, ")").unwrap();
             }
 
-            $crate::_write_mysql_query!($qtype, $mysql_q, values: val, $( $pname ),*)
+            $crate::_emit_mysql_lnames!($( $lname ),*);
+            $crate::_write_mysql_query!($qtype, $mysql_q, values: val, $( $pname ),* $( >list $lname )*)
+        }
+
+        fn mysql_prepared_query(
+            schema_variant: Option<&str>,
+            values: &[($( & $vtype, )*)],
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> (String, MysqlParams) {
+            let mut params = MysqlParams::default();
+            $( let $pname = params.bind(ToValue::to_value($pname)); )*
+            $( let $lname = params.bind_list($lname.iter().map(ToValue::to_value)); )*
+            let mut rows = Vec::with_capacity(values.len());
+            for ($( $vname, )*) in values {
+                rows.push(params.bind_list([$( ToValue::to_value(*$vname), )*]));
+            }
+
+            let query = $crate::_mysql_schema_variant!(
+                schema_variant,
+                [$( $variant => $variant_q ),*],
+                $mysql_q,
+                _write_mysql_prepared_query!($qtype, (values: rows.join(", "), $( $pname ),* $( >list $lname )*))
+            );
+            (query, params)
         }
 
         async fn sqlite_exec_query(
             multithread_con: &SqliteMultithreaded,
+            timeout: Option<std::time::Duration>,
+            cancellation: Option<&CancellationToken>,
             values: &[($( & $vtype, )*)],
-            $( $pname: & $ptype ),*
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
         ) -> Result<WriteResult, Error> {
             let mut multi_params = Vec::new();
             for value in values {
@@ -743,33 +1096,38 @@ macro_rules! _write_query_impl {
 
                 multi_params.push(params);
             }
+            $crate::_prepare_sqlite_params!(list_params, $( >list $lname )*);
 
-            let con = multithread_con.acquire_sqlite_connection(SqliteQueryType::Write).await?;
+            multithread_con.run_query_with_cancellation(SqliteQueryType::Write, timeout, cancellation, |con| {
+                let mut stmt = sqlite_statement(con $( , $lname )*)?;
 
-            let mut stmt = sqlite_statement(&con)?;
+                let mut res = Vec::new();
+                for params in multi_params {
+                    let mut param_refs: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
+                    for param in &params {
+                        param_refs.push((param.0, &param.1));
+                    }
+                    for param in &list_params {
+                        param_refs.push((&param.0, &param.1));
+                    }
 
-            let mut res = Vec::new();
-            for params in multi_params {
-                let mut param_refs: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
-                for param in &params {
-                    param_refs.push((param.0, &param.1));
+                    let a: &[(&str, &dyn ToSqliteValue)] = &param_refs[..];
+                    res.push(stmt.execute(a)?);
                 }
 
-                let a: &[(&str, &dyn ToSqliteValue)] = &param_refs[..];
-                res.push(stmt.execute(a)?);
-            }
-
-            Ok(WriteResult::new(
-                Some(con.last_insert_rowid() as u64),
-                res.into_iter().sum::<usize>() as u64,
-            ))
+                Ok(WriteResult::new(
+                    Some(con.last_insert_rowid() as u64),
+                    res.into_iter().sum::<usize>() as u64,
+                ))
+            }).await
         }
 
-        async fn sqlite_exec_query_with_transaction(
-            transaction: SqliteConnectionGuard,
+        fn sqlite_exec_query_with_transaction(
+            transaction: &SqliteConnection,
             values: &[($( & $vtype, )*)],
-            $( $pname: & $ptype ),*
-        ) -> Result<(SqliteConnectionGuard, WriteResult), Error> {
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> Result<WriteResult, Error> {
             let mut multi_params = Vec::new();
             for value in values {
                 let mut params: Vec<(&str, ValueWrapper)> = Vec::new();
@@ -784,9 +1142,10 @@ macro_rules! _write_query_impl {
 
                 multi_params.push(params);
             }
+            $crate::_prepare_sqlite_params!(list_params, $( >list $lname )*);
 
             let res: usize = {
-                let mut stmt = sqlite_statement(&transaction)?;
+                let mut stmt = sqlite_statement(&transaction $( , $lname )*)?;
 
                 let mut res = Vec::new();
                 for params in multi_params {
@@ -794,6 +1153,9 @@ macro_rules! _write_query_impl {
                     for param in &params {
                         param_refs.push((param.0, &param.1));
                     }
+                    for param in &list_params {
+                        param_refs.push((&param.0, &param.1));
+                    }
 
                     let a: &[(&str, &dyn ToSqliteValue)] = &param_refs[..];
                     res.push(stmt.execute(a)?);
@@ -802,34 +1164,41 @@ macro_rules! _write_query_impl {
                 res.into_iter().sum::<usize>()
             };
 
-            let res = WriteResult::new(
+            Ok(WriteResult::new(
                 Some(transaction.last_insert_rowid() as u64),
                 res as u64,
-            );
-
-            Ok((transaction, res))
+            ))
         }
 
         fn sqlite_statement<'a>(
             connection: &'a SqliteConnection,
+            $( $lname: usize, )*
         ) -> SqliteResult<SqliteStatement<'a>> {
+            connection.prepare_cached(&sqlite_query_text($( $lname ),*))
+        }
+
+        // The query inserting a single row of values, executed once per row.
+        fn sqlite_query_text($( $lname: usize ),*) -> String {
+            $crate::_emit_sqlite_lnames!($( $lname ),*);
             let mut val = Vec::new();
             $(
                 val.push(concat!(":", stringify!($vname)));
             )*
-            connection.prepare(&$crate::_write_sqlite_query!(
+            $crate::_write_sqlite_query!(
                 $qtype,
                 $sqlite_q,
                 values: &format!("({})", val.join(", ")),
                 $( $pname ),*
-            ))
+                $( >list $lname )*
+            )
         }
     );
 
     ( (
         $( $pname:ident: $ptype:ty, )*
         $( >list $lname:ident: $ltype:ty )*
-    ) { $qtype:ident, mysql($mysql_q:expr) sqlite($sqlite_q:expr) } ) => (
+        $( >maybe $mname:ident: $mtype:ty = $mfrag:literal )*
+    ) { $qtype:tt, mysql($mysql_q:expr $( , $variant:literal => $variant_q:expr )*) sqlite($sqlite_q:expr) } ) => (
         use $crate::WriteResult;
 
         $crate::_query_common!();
@@ -837,24 +1206,34 @@ macro_rules! _write_query_impl {
         async fn query_internal(
             connection: &Connection,
             comment: Option<&str>,
+            timeout: Option<std::time::Duration>,
+            cancellation: Option<&CancellationToken>,
             $( $pname: & $ptype, )*
             $( $lname: & [ $ltype ], )*
+            $( $mname: Option<& $mtype>, )*
         ) -> Result<WriteResult, Error> {
             match connection {
                 Connection::Sqlite(multithread_con) => {
-                    sqlite_exec_query(multithread_con $( , $pname )* $( , $lname )*).await
+                    sqlite_exec_query(multithread_con, timeout, cancellation $( , $pname )* $( , $lname )* $( , $mname )*).await
                 }
                 Connection::Mysql(conn) => {
-                    let mut query = mysql_query($( $pname, )* $( $lname, )*);
+                    let mut query = mysql_query($( $pname, )* $( $lname, )* $( $mname, )*);
                     if let Some(comment) = comment {
                         query.insert_str(0, &format!("/* {} */", comment));
                     }
-                    let res = conn.write_query(query).map_err(Error::from).await?;
+                    let res = with_cancellation(
+                        cancellation,
+                        with_timeout(timeout, conn.write_query(query).map_err(Error::from)),
+                    )
+                    .await?;
                     Ok(res.into())
                 }
                 Connection::OssMysql(conn) => {
-                    let query = mysql_query($( $pname, )* $( $lname, )*);
-                    let res = conn.write_query(query).map_err(Error::from).await?;
+                    let (query, params) = mysql_prepared_query(conn.schema_variant(), $( $pname, )* $( $lname, )* $( $mname, )*);
+                    let res = conn
+                        .write_prepared_query(query, params.into(), timeout, cancellation)
+                        .map_err(Error::from)
+                        .await?;
                     Ok(res.into())
                 },
             }
@@ -865,6 +1244,7 @@ macro_rules! _write_query_impl {
             comment: Option<&str>,
             $( $pname: & $ptype, )*
             $( $lname: & [ $ltype ], )*
+            $( $mname: Option<& $mtype>, )*
         ) -> Result<(Transaction, WriteResult), Error> {
             match transaction {
                 Transaction::Sqlite(ref mut transaction) => {
@@ -872,14 +1252,14 @@ macro_rules! _write_query_impl {
                         .take()
                         .expect("should be Some before transaction ended");
 
-                    sqlite_exec_query_with_transaction(con $( , $pname )* $( , $lname )*)
+                    sqlite_exec_query_with_transaction(con $( , $pname )* $( , $lname )* $( , $mname )*)
                         .await
                         .map(move |(con, res)| {
                             (Transaction::Sqlite(Some(con)), res)
                         })
                 }
                 Transaction::Mysql(ref mut transaction) => {
-                    let mut query = mysql_query($( $pname, )* $( $lname, )*);
+                    let mut query = mysql_query($( $pname, )* $( $lname, )* $( $mname, )*);
                     if let Some(comment) = comment {
                         query.insert_str(0, &format!("/* {} */", comment));
                     }
@@ -888,67 +1268,121 @@ macro_rules! _write_query_impl {
                     let result = tr.write_query(query).map_err(Error::from).await?;
                     Ok((Transaction::Mysql(Some(tr)), result.into()))
                 },
-                Transaction::OssMysql(ref mut transaction) => {
-                    let query = mysql_query($( $pname, )* $( $lname, )*);
+                Transaction::OssMysql(ref mut transaction, ref mut schema_variant, ref mut open_transaction) => {
+                    let (query, params) = mysql_prepared_query(schema_variant.as_deref(), $( $pname, )* $( $lname, )* $( $mname, )*);
                     let mut tr = transaction.take()
                         .expect("should be Some before transaction ended");
-                    let query_result = tr.query_iter(query).await?;
+                    let query_result = tr.exec_iter(query, params).await?;
 
                     let last_insert_id = query_result.last_insert_id();
                     let rows_affected = query_result.affected_rows();
                     let result = WriteResult::new(last_insert_id, rows_affected);
-                    Ok((Transaction::OssMysql(Some(tr)), result))
+                    Ok((Transaction::OssMysql(Some(tr), schema_variant.take(), open_transaction.take()), result))
                 }
             }
         }
 
-        fn mysql_query($( $pname: & $ptype, )* $( $lname: & [ $ltype ], )*) -> String {
+        fn render_internal(
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+            $( $mname: Option<& $mtype>, )*
+        ) -> RenderedQuery {
+            RenderedQuery {
+                mysql: mysql_query($( $pname, )* $( $lname, )* $( $mname, )*),
+                sqlite: sqlite_query_text(
+                    $( $crate::sqlite_list_placeholders($lname.len()), )*
+                    $( $mname.is_some(), )*
+                ),
+            }
+        }
+
+        fn mysql_query(
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+            $( $mname: Option<& $mtype>, )*
+        ) -> String {
             $crate::_emit_mysql_lnames!($( $lname ),*);
-            $crate::_write_mysql_query!($qtype, $mysql_q, $( $pname ),* $( >list $lname )*)
+            $crate::_emit_mysql_mnames!($( $mname = $mfrag ),*);
+            $crate::_write_mysql_query!(
+                $qtype,
+                $mysql_q,
+                $( $pname ),*
+                $( >list $lname )*
+                $( >maybe $mname )*
+            )
+        }
+
+        fn mysql_prepared_query(
+            schema_variant: Option<&str>,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+            $( $mname: Option<& $mtype>, )*
+        ) -> (String, MysqlParams) {
+            let mut params = MysqlParams::default();
+            $( let $pname = params.bind(ToValue::to_value($pname)); )*
+            $( let $lname = params.bind_list($lname.iter().map(ToValue::to_value)); )*
+            $(
+                let $mname = $mname
+                    .map(|$mname| format!($mfrag, $mname = params.bind(ToValue::to_value($mname))))
+                    .unwrap_or_default();
+            )*
+            let query = $crate::_mysql_schema_variant!(
+                schema_variant,
+                [$( $variant => $variant_q ),*],
+                $mysql_q,
+                _write_mysql_prepared_query!($qtype, ($( $pname ),* $( >list $lname )* $( >maybe $mname )*))
+            );
+            (query, params)
         }
 
         async fn sqlite_exec_query(
             multithread_con: &SqliteMultithreaded,
+            timeout: Option<std::time::Duration>,
+            cancellation: Option<&CancellationToken>,
             $( $pname: & $ptype, )*
             $( $lname: & [ $ltype ], )*
+            $( $mname: Option<& $mtype>, )*
         ) -> Result<WriteResult, Error> {
             $crate::_prepare_sqlite_params!(
                 params,
                 $( $pname ),*
                 $( >list $lname )*
+                $( >maybe $mname )*
             );
 
-            let con = multithread_con.acquire_sqlite_connection(SqliteQueryType::Write).await?;
-
-            let mut stmt = sqlite_statement(&con  $( , $lname )*)?;
+            multithread_con.run_query_with_cancellation(SqliteQueryType::Write, timeout, cancellation, |con| {
+                let mut stmt = sqlite_statement(con  $( , $lname )* $( , $mname )*)?;
 
-            let mut param_refs: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
-            for param in &params {
-                param_refs.push((&param.0, &param.1));
-            }
+                let mut param_refs: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
+                for param in &params {
+                    param_refs.push((&param.0, &param.1));
+                }
 
-                    let a: &[(&str, &dyn ToSqliteValue)] = &param_refs[..];
-                    let res = stmt.execute(a)?;
+                let a: &[(&str, &dyn ToSqliteValue)] = &param_refs[..];
+                let res = stmt.execute(a)?;
 
-            Ok(WriteResult::new(
-                Some(con.last_insert_rowid() as u64),
-                res as u64,
-            ))
+                Ok(WriteResult::new(
+                    Some(con.last_insert_rowid() as u64),
+                    res as u64,
+                ))
+            }).await
         }
 
         async fn sqlite_exec_query_with_transaction(
             transaction: SqliteConnectionGuard,
             $( $pname: & $ptype, )*
             $( $lname: & [ $ltype ], )*
+            $( $mname: Option<& $mtype>, )*
         ) -> Result<(SqliteConnectionGuard, WriteResult), Error> {
             $crate::_prepare_sqlite_params!(
                 params,
                 $( $pname ),*
                 $( >list $lname )*
+                $( >maybe $mname )*
             );
 
             let res = {
-                let mut stmt = sqlite_statement(&transaction  $( , $lname )*)?;
+                let mut stmt = sqlite_statement(&transaction  $( , $lname )* $( , $mname )*)?;
 
                 let mut param_refs: Vec<(&str, &dyn ToSqliteValue)> = Vec::new();
                 for param in &params {
@@ -970,52 +1404,167 @@ macro_rules! _write_query_impl {
         fn sqlite_statement<'a>(
             connection: &'a SqliteConnection,
             $( $lname: usize, )*
+            $( $mname: bool, )*
         ) -> SqliteResult<SqliteStatement<'a>> {
+            connection.prepare_cached(&sqlite_query_text($( $lname, )* $( $mname, )*))
+        }
+
+        fn sqlite_query_text($( $lname: usize, )* $( $mname: bool, )*) -> String {
             $crate::_emit_sqlite_lnames!($( $lname ),*);
-            connection.prepare(&$crate::_write_sqlite_query!(
+            $crate::_emit_sqlite_mnames!($( $mname = $mfrag ),*);
+            $crate::_write_sqlite_query!(
                 $qtype,
                 $sqlite_q,
                 $( $pname ),*
                 $( >list $lname )*
-            ))
+                $( >maybe $mname )*
+            )
         }
     );
 }
 
+/// Write the MySQL query of the schema variant of the connection, if the
+/// query declares one, or the default MySQL query with the given macro.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! _mysql_schema_variant {
+    ($schema_variant:expr, [], $mysql_q:expr, $write:ident!($qtype:tt, $args:tt)) => {{
+        let _ = $schema_variant;
+        $crate::_mysql_schema_variant!(@write $write, $qtype, $mysql_q, $args)
+    }};
+
+    ($schema_variant:expr, [$( $variant:literal => $variant_q:expr ),+], $mysql_q:expr, $write:ident!($qtype:tt, $args:tt)) => {
+        match $schema_variant {
+            $( Some($variant) => $crate::_mysql_schema_variant!(@write $write, $qtype, $variant_q, $args), )+
+            _ => $crate::_mysql_schema_variant!(@write $write, $qtype, $mysql_q, $args),
+        }
+    };
+
+    (@write $write:ident, $qtype:tt, $q:expr, ($( $args:tt )*)) => {
+        $crate::$write!($qtype, $q, $( $args )*)
+    };
+}
+
 #[macro_export]
 #[doc(hidden)]
 macro_rules! _write_mysql_query {
-    (insert_or_ignore, $q:expr, values: $values:expr, $( $pname:ident ),*) => {
+    ([insert_or_update $mysql_clause:literal $sqlite_clause:literal], $q:expr, values: $values:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+        format!(
+            $q,
+            insert_or_update = $mysql_clause,
+            values = $values,
+            $( $pname = ToValue::to_value(&$pname).as_sql(false), )*
+            $( $lname = $lname, )*
+        )
+    };
+
+    ([insert_or_update $mysql_clause:literal $sqlite_clause:literal], $q:expr, $( $pname:ident ),* $( >list $lname:ident )* $( >maybe $mname:ident )*) => {
+        format!(
+            $q,
+            insert_or_update = $mysql_clause,
+            $( $pname = ToValue::to_value(&$pname).as_sql(false), )*
+            $( $lname = $lname, )*
+            $( $mname = $mname, )*
+        )
+    };
+
+    (insert_or_ignore, $q:expr, values: $values:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
         format!(
             $q,
             insert_or_ignore = "INSERT IGNORE",
             values = $values,
             $( $pname = ToValue::to_value(&$pname).as_sql(false), )*
+            $( $lname = $lname, )*
         )
     };
 
-    (insert_or_ignore, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+    (insert_or_ignore, $q:expr, $( $pname:ident ),* $( >list $lname:ident )* $( >maybe $mname:ident )*) => {
         format!(
             $q,
             insert_or_ignore = "INSERT IGNORE",
             $( $pname = ToValue::to_value(&$pname).as_sql(false), )*
             $( $lname = $lname, )*
+            $( $mname = $mname, )*
         )
     };
 
-    (none, $q:expr, values: $values:expr, $( $pname:ident ),*) => {
+    (none, $q:expr, values: $values:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
         format!(
             $q,
             values = $values,
             $( $pname = ToValue::to_value(&$pname).as_sql(false), )*
+            $( $lname = $lname, )*
         )
     };
 
-    (none, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+    (none, $q:expr, $( $pname:ident ),* $( >list $lname:ident )* $( >maybe $mname:ident )*) => {
         format!(
             $q,
             $( $pname = ToValue::to_value(&$pname).as_sql(false), )*
             $( $lname = $lname, )*
+            $( $mname = $mname, )*
+        )
+    };
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! _write_mysql_prepared_query {
+    ([insert_or_update $mysql_clause:literal $sqlite_clause:literal], $q:expr, values: $values:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+        format!(
+            $q,
+            insert_or_update = $mysql_clause,
+            values = $values,
+            $( $pname = $pname, )*
+            $( $lname = $lname, )*
+        )
+    };
+
+    ([insert_or_update $mysql_clause:literal $sqlite_clause:literal], $q:expr, $( $pname:ident ),* $( >list $lname:ident )* $( >maybe $mname:ident )*) => {
+        format!(
+            $q,
+            insert_or_update = $mysql_clause,
+            $( $pname = $pname, )*
+            $( $lname = $lname, )*
+            $( $mname = $mname, )*
+        )
+    };
+
+    (insert_or_ignore, $q:expr, values: $values:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+        format!(
+            $q,
+            insert_or_ignore = "INSERT IGNORE",
+            values = $values,
+            $( $pname = $pname, )*
+            $( $lname = $lname, )*
+        )
+    };
+
+    (insert_or_ignore, $q:expr, $( $pname:ident ),* $( >list $lname:ident )* $( >maybe $mname:ident )*) => {
+        format!(
+            $q,
+            insert_or_ignore = "INSERT IGNORE",
+            $( $pname = $pname, )*
+            $( $lname = $lname, )*
+            $( $mname = $mname, )*
+        )
+    };
+
+    (none, $q:expr, values: $values:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+        format!(
+            $q,
+            values = $values,
+            $( $pname = $pname, )*
+            $( $lname = $lname, )*
+        )
+    };
+
+    (none, $q:expr, $( $pname:ident ),* $( >list $lname:ident )* $( >maybe $mname:ident )*) => {
+        format!(
+            $q,
+            $( $pname = $pname, )*
+            $( $lname = $lname, )*
+            $( $mname = $mname, )*
         )
     };
 }
@@ -1023,37 +1572,61 @@ macro_rules! _write_mysql_query {
 #[macro_export]
 #[doc(hidden)]
 macro_rules! _write_sqlite_query {
-    (insert_or_ignore, $q:expr, values: $values:expr, $( $pname:ident ),*) => {
+    ([insert_or_update $mysql_clause:literal $sqlite_clause:literal], $q:expr, values: $values:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+        format!(
+            $q,
+            insert_or_update = $sqlite_clause,
+            values = $values,
+            $( $pname = concat!(":", stringify!($pname)), )*
+            $( $lname = $lname, )*
+        )
+    };
+
+    ([insert_or_update $mysql_clause:literal $sqlite_clause:literal], $q:expr, $( $pname:ident ),* $( >list $lname:ident )* $( >maybe $mname:ident )*) => {
+        format!(
+            $q,
+            insert_or_update = $sqlite_clause,
+            $( $pname = concat!(":", stringify!($pname)), )*
+            $( $lname = $lname, )*
+            $( $mname = $mname, )*
+        )
+    };
+
+    (insert_or_ignore, $q:expr, values: $values:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
         format!(
             $q,
             insert_or_ignore = "INSERT OR IGNORE",
             values = $values,
             $( $pname = concat!(":", stringify!($pname)), )*
+            $( $lname = $lname, )*
         )
     };
 
-    (insert_or_ignore, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+    (insert_or_ignore, $q:expr, $( $pname:ident ),* $( >list $lname:ident )* $( >maybe $mname:ident )*) => {
         format!(
             $q,
             insert_or_ignore = "INSERT OR IGNORE",
             $( $pname = concat!(":", stringify!($pname)), )*
             $( $lname = $lname, )*
+            $( $mname = $mname, )*
         )
     };
 
-    (none, $q:expr, values: $values:expr, $( $pname:ident ),*) => {
+    (none, $q:expr, values: $values:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
         format!(
             $q,
             values = $values,
             $( $pname = concat!(":", stringify!($pname)), )*
+            $( $lname = $lname, )*
         )
     };
 
-    (none, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+    (none, $q:expr, $( $pname:ident ),* $( >list $lname:ident )* $( >maybe $mname:ident )*) => {
         format!(
             $q,
             $( $pname = concat!(":", stringify!($pname)), )*
             $( $lname = $lname, )*
+            $( $mname = $mname, )*
         )
     };
 }
@@ -1143,6 +1716,8 @@ macro_rules! _emit_mysql_lnames {
     ($( $lname:ident ),*) => {
         $(
             let $lname = {
+                use std::fmt::Write;
+
                 let mut val = String::new();
                 write!(&mut val, "(").unwrap();
                 let mut first = true;
@@ -1169,6 +1744,8 @@ macro_rules! _emit_sqlite_lnames {
     ($( $lname:ident ),*) => {
         $(
             let $lname = {
+                use std::fmt::Write;
+
                 let mut val = String::new();
                 write!(&mut val, "(").unwrap();
                 let mut first = true;
@@ -1187,30 +1764,73 @@ macro_rules! _emit_sqlite_lnames {
     }
 }
 
+#[macro_export]
+#[doc(hidden)]
+/// Render all >maybe $mname fragments into strings suitable for interpolation into a SQL string,
+/// empty for the parameters that are not given.
+macro_rules! _emit_mysql_mnames {
+    ($( $mname:ident = $mfrag:literal ),*) => {
+        $(
+            let $mname = $mname
+                .map(|$mname| format!($mfrag, $mname = ToValue::to_value($mname).as_sql(false)))
+                .unwrap_or_default();
+        )*
+    }
+}
+
+#[macro_export]
+#[doc(hidden)]
+/// Render all >maybe $mname fragments into strings suitable for interpolation into a SQLite
+/// prepared statement, empty for the parameters that are not given.
+macro_rules! _emit_sqlite_mnames {
+    ($( $mname:ident = $mfrag:literal ),*) => {
+        $(
+            let $mname = if $mname {
+                format!($mfrag, $mname = concat!(":", stringify!($mname)))
+            } else {
+                String::new()
+            };
+        )*
+    }
+}
+
 #[macro_export]
 #[doc(hidden)]
 /// Prepares $params for a SQLite query.
 macro_rules! _prepare_sqlite_params {
-    ($params:ident, $( $pname:ident ),* $( >list $lname:ident )*) => (
+    ($params:ident, $( $pname:ident ),* $( >list $lname:ident )* $( >maybe $mname:ident )*) => (
         let $params = vec![ $(
             (format!(":{}", stringify!($pname)), ValueWrapper(ToValue::to_value($pname)))
         ),* ].into_iter();
 
         $(
             let $params = $params.chain(
-                $lname.into_iter()
-                    .enumerate()
-                    .map(|(idx, val)| (
+                (0..$crate::sqlite_list_placeholders($lname.len()))
+                    .map(|idx| (
                         format!(":{}{}", stringify!($lname), idx),
-                        ValueWrapper(ToValue::to_value(val)),
+                        // The extra placeholders repeat the last value.
+                        ValueWrapper(ToValue::to_value(&$lname[idx.min($lname.len() - 1)])),
                     ))
             );
         )*
 
+        $(
+            let $params = $params.chain(
+                $mname.map(|val| (
+                    format!(":{}", stringify!($mname)),
+                    ValueWrapper(ToValue::to_value(val)),
+                ))
+            );
+        )*
+
         let $params: Vec<(String, ValueWrapper)> = $params.collect();
 
         $(
-            let $lname = $lname.len();
+            let $lname = $crate::sqlite_list_placeholders($lname.len());
+        )*
+
+        $(
+            let $mname = $mname.is_some();
         )*
     )
 }
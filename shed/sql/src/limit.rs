@@ -0,0 +1,134 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Module with the [Limit] and [Offset] parameters of paginated queries.
+
+use anyhow::bail;
+use anyhow::Error;
+use mysql_async::prelude::ToValue;
+use mysql_async::Value;
+
+/// The number of rows of a `LIMIT` clause, bound to at most [Limit::MAX].
+///
+/// The placeholders following `LIMIT` in the queries of
+/// [queries!](crate::queries) must be parameters of this type, so that the
+/// number of rows is always interpolated as a non-negative integer.
+///
+/// ```
+/// use sql::queries;
+/// use sql::Limit;
+/// use sql::Offset;
+///
+/// queries! {
+///     read SelectPage(limit: Limit, offset: Offset) -> (u64) {
+///         "SELECT id FROM foo ORDER BY id LIMIT {limit} OFFSET {offset}"
+///     }
+/// }
+/// #
+/// # fn main() {}
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Limit(u64);
+
+impl Limit {
+    /// The largest number of rows of a limit. Larger limits are lowered to
+    /// it, as reading more rows than that at once is almost always a bug.
+    pub const MAX: u64 = 1_000_000;
+
+    /// Return the number of rows.
+    pub fn get(self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for Limit {
+    fn from(rows: u64) -> Self {
+        Self(rows.min(Self::MAX))
+    }
+}
+
+impl TryFrom<i64> for Limit {
+    type Error = Error;
+
+    fn try_from(rows: i64) -> Result<Self, Error> {
+        if rows < 0 {
+            bail!("limit can't be negative: {}", rows);
+        }
+        Ok(Self::from(rows as u64))
+    }
+}
+
+impl ToValue for Limit {
+    fn to_value(&self) -> Value {
+        Value::UInt(self.0)
+    }
+}
+
+/// The number of rows skipped by an `OFFSET` clause, bound to at most
+/// [Offset::MAX].
+///
+/// The placeholders following `OFFSET`, or the first of the two following
+/// `LIMIT` in the MySQL syntax `LIMIT {offset}, {limit}`, in the queries of
+/// [queries!](crate::queries) must be parameters of this type, see [Limit].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Offset(u64);
+
+impl Offset {
+    /// The largest number of rows skipped by an offset, which is also the
+    /// largest offset sqlite accepts. Larger offsets are lowered to it.
+    pub const MAX: u64 = i64::MAX as u64;
+
+    /// Return the number of rows skipped.
+    pub fn get(self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for Offset {
+    fn from(rows: u64) -> Self {
+        Self(rows.min(Self::MAX))
+    }
+}
+
+impl TryFrom<i64> for Offset {
+    type Error = Error;
+
+    fn try_from(rows: i64) -> Result<Self, Error> {
+        if rows < 0 {
+            bail!("offset can't be negative: {}", rows);
+        }
+        Ok(Self::from(rows as u64))
+    }
+}
+
+impl ToValue for Offset {
+    fn to_value(&self) -> Value {
+        Value::UInt(self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bounds() {
+        assert_eq!(Limit::from(10).get(), 10);
+        assert_eq!(Limit::from(u64::MAX).get(), Limit::MAX);
+        assert_eq!(Limit::try_from(10i64).unwrap().get(), 10);
+        assert!(Limit::try_from(-1i64).is_err());
+
+        assert_eq!(Offset::from(10).get(), 10);
+        assert_eq!(Offset::from(u64::MAX).get(), i64::MAX as u64);
+        assert!(Offset::try_from(i64::MIN).is_err());
+
+        assert_eq!(Limit::from(10).to_value(), Value::UInt(10));
+        assert_eq!(Offset::default().to_value(), Value::UInt(0));
+    }
+}
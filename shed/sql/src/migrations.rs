@@ -0,0 +1,257 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Schema migrations applied in order of version against a [Connection].
+//!
+//! The versions of the applied migrations are tracked in a `schema_versions`
+//! table, which is created on the first run:
+//!
+//! ```sql
+//! CREATE TABLE schema_versions (
+//!     version BIGINT NOT NULL PRIMARY KEY,
+//!     name VARCHAR(255) NOT NULL
+//! );
+//! ```
+//!
+//! Every migration is applied in its own transaction together with the
+//! insertion of its version, so concurrent runs can't apply it twice. Note
+//! that MySQL commits implicitly after most DDL statements, so a migration
+//! failing there may be left partially applied.
+
+use std::collections::HashSet;
+
+use anyhow::bail;
+use anyhow::Error;
+use futures::TryFutureExt;
+use mysql_async::prelude::Queryable;
+
+use self::schema_versions_queries::CreateSchemaVersions;
+use self::schema_versions_queries::InsertSchemaVersion;
+use self::schema_versions_queries::SelectSchemaVersions;
+use self::schema_versions_queries::SelectSchemaVersionsTable;
+use crate::Connection;
+use crate::Transaction;
+
+// Modules generated by `queries!` bind parameters mutably even for queries
+// without any, which is only linted when the macro is expanded inside this
+// crate.
+#[allow(unused_mut)]
+mod schema_versions_queries {
+    use crate::queries;
+
+    queries! {
+        pub(super) write CreateSchemaVersions() {
+            none,
+            "CREATE TABLE IF NOT EXISTS schema_versions (
+                version BIGINT NOT NULL PRIMARY KEY,
+                name VARCHAR(255) NOT NULL
+            )"
+        }
+
+        pub(super) write InsertSchemaVersion(version: u64, name: String) {
+            none,
+            "INSERT INTO schema_versions (version, name) VALUES ({version}, {name})"
+        }
+
+        pub(super) read SelectSchemaVersions() -> (u64) {
+            "SELECT version FROM schema_versions ORDER BY version"
+        }
+
+        pub(super) read SelectSchemaVersionsTable() -> (String) {
+            mysql("SELECT table_name FROM information_schema.tables
+                WHERE table_schema = DATABASE() AND table_name = 'schema_versions'")
+            sqlite("SELECT name FROM sqlite_master
+                WHERE type = 'table' AND name = 'schema_versions'")
+        }
+    }
+}
+
+/// A single schema change, identified by its version.
+#[derive(Clone, Debug)]
+pub struct Migration {
+    version: u64,
+    name: String,
+    mysql: String,
+    sqlite: String,
+}
+
+impl Migration {
+    /// Create a migration running the same SQL on every backend. The SQL may
+    /// contain several statements separated by `;`.
+    pub fn new(version: u64, name: impl Into<String>, sql: impl Into<String>) -> Self {
+        let sql = sql.into();
+        Self {
+            version,
+            name: name.into(),
+            mysql: sql.clone(),
+            sqlite: sql,
+        }
+    }
+
+    /// Create a migration running different SQL on MySQL and SQLite, for
+    /// changes that need a different syntax.
+    pub fn with_variants(
+        version: u64,
+        name: impl Into<String>,
+        mysql: impl Into<String>,
+        sqlite: impl Into<String>,
+    ) -> Self {
+        Self {
+            version,
+            name: name.into(),
+            mysql: mysql.into(),
+            sqlite: sqlite.into(),
+        }
+    }
+
+    /// Return the version of this migration.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Return the name of this migration.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn apply(&self, transaction: &mut Transaction) -> Result<(), Error> {
+        match transaction {
+            Transaction::Sqlite(ref mut con) => {
+                let con = con
+                    .as_mut()
+                    .expect("should be Some before transaction ended");
+                con.execute_batch(&self.sqlite)?;
+            }
+            Transaction::Mysql(ref mut tr) => {
+                let tr = tr
+                    .as_mut()
+                    .expect("should be Some before transaction ended");
+                tr.write_query(self.mysql.clone())
+                    .map_err(Error::from)
+                    .await?;
+            }
+            Transaction::OssMysql(ref mut tr, ..) => {
+                let tr = tr
+                    .as_mut()
+                    .expect("should be Some before transaction ended");
+                tr.query_drop(&self.mysql).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies the migrations not yet applied to a database, in order of version.
+pub struct Migrator {
+    migrations: Vec<Migration>,
+    dry_run: bool,
+}
+
+impl Migrator {
+    /// Create a migrator for the given migrations, which must have distinct
+    /// versions.
+    pub fn new(mut migrations: Vec<Migration>) -> Result<Self, Error> {
+        migrations.sort_by_key(|migration| migration.version);
+        if let Some(pair) = migrations
+            .windows(2)
+            .find(|pair| pair[0].version == pair[1].version)
+        {
+            bail!(
+                "Migrations {:?} and {:?} have the same version {}",
+                pair[0].name,
+                pair[1].name,
+                pair[0].version
+            );
+        }
+        Ok(Self {
+            migrations,
+            dry_run: false,
+        })
+    }
+
+    /// In dry-run mode, `run` only returns the migrations that would be
+    /// applied, without changing the database.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Return the versions of the migrations that have been applied to the
+    /// database.
+    pub async fn applied_versions(&self, connection: &Connection) -> Result<Vec<u64>, Error> {
+        if SelectSchemaVersionsTable::query(connection)
+            .await?
+            .is_empty()
+        {
+            return Ok(Vec::new());
+        }
+        let versions = SelectSchemaVersions::query(connection).await?;
+        Ok(versions.into_iter().map(|(version,)| version).collect())
+    }
+
+    /// Return the migrations that haven't been applied to the database yet,
+    /// in the order they would be applied. Fails if the database has a
+    /// version newer than all the migrations, as it's then ahead of the code.
+    pub async fn pending(&self, connection: &Connection) -> Result<Vec<&Migration>, Error> {
+        let applied: HashSet<u64> = self
+            .applied_versions(connection)
+            .await?
+            .into_iter()
+            .collect();
+        let latest = self
+            .migrations
+            .last()
+            .map_or(0, |migration| migration.version);
+        if let Some(version) = applied.iter().find(|version| **version > latest) {
+            bail!(
+                "Database has schema version {}, newer than the latest migration {}",
+                version,
+                latest
+            );
+        }
+        Ok(self
+            .migrations
+            .iter()
+            .filter(|migration| !applied.contains(&migration.version))
+            .collect())
+    }
+
+    /// Apply the pending migrations and return their versions, or only
+    /// return them in dry-run mode. Migrations applied before a failing one
+    /// stay applied.
+    pub async fn run(&self, connection: &Connection) -> Result<Vec<u64>, Error> {
+        if !self.dry_run {
+            CreateSchemaVersions::query(connection).await?;
+        }
+        let pending = self.pending(connection).await?;
+        if self.dry_run {
+            return Ok(pending.iter().map(|migration| migration.version).collect());
+        }
+
+        let mut applied = Vec::with_capacity(pending.len());
+        for migration in pending {
+            let mut transaction = connection.start_transaction().await?;
+            migration.apply(&mut transaction).await.map_err(|err| {
+                err.context(format!(
+                    "Failed to apply migration {} {:?}",
+                    migration.version, migration.name
+                ))
+            })?;
+            let (transaction, _) = InsertSchemaVersion::query_with_transaction(
+                transaction,
+                &migration.version,
+                &migration.name,
+            )
+            .await?;
+            transaction.commit().await?;
+            applied.push(migration.version);
+        }
+        Ok(applied)
+    }
+}
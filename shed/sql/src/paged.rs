@@ -0,0 +1,49 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+/// A page of the rows of a `read_paged` query, see [queries!](crate::queries).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Page<T, K> {
+    rows: Vec<T>,
+    next_cursor: Option<K>,
+}
+
+impl<T, K> Page<T, K> {
+    /// Method made public for access from inside macros, you probably don't want to use it.
+    pub fn new(rows: Vec<T>, limit: u64, key: impl FnOnce(&T) -> K) -> Self {
+        let next_cursor = if rows.len() as u64 >= limit {
+            rows.last().map(key)
+        } else {
+            None
+        };
+        Page { rows, next_cursor }
+    }
+
+    /// Return the rows of the page, ordered by their key.
+    pub fn rows(&self) -> &[T] {
+        &self.rows
+    }
+
+    /// Return the rows of the page, consuming it.
+    pub fn into_rows(self) -> Vec<T> {
+        self.rows
+    }
+
+    /// Return the cursor to pass to `next_page` to read the page after this
+    /// one, or `None` if this is the last page. The last page may be followed
+    /// by an empty one when the rows end right at the limit of a page.
+    pub fn next_cursor(&self) -> Option<&K> {
+        self.next_cursor.as_ref()
+    }
+
+    /// Return the rows of the page and the cursor of the next page.
+    pub fn into_parts(self) -> (Vec<T>, Option<K>) {
+        (self.rows, self.next_cursor)
+    }
+}
@@ -0,0 +1,153 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Implementation of the `query_stream` function of read queries. This should
+//! never be used directly, it is made public so that internal macros can make
+//! use of it.
+
+use std::sync::Arc;
+
+use anyhow::Error;
+use futures::channel::mpsc;
+use futures::stream;
+use futures::stream::BoxStream;
+use futures::SinkExt;
+use futures::StreamExt;
+use futures::TryStreamExt;
+use mysql_async::Params;
+use mysql_async::Row;
+use rusqlite::types::ToSql as ToSqliteValue;
+use rusqlite::Connection as SqliteConnection;
+use rusqlite::Result as SqliteResult;
+use rusqlite::Row as SqliteRow;
+use sql_common::mysql::OssConnection;
+use sql_common::sqlite::SqliteMultithreaded;
+use sql_common::sqlite::SqliteQueryType;
+
+use crate::ValueWrapper;
+
+/// Number of rows fetched ahead of the consumer of a mysql stream.
+const STREAM_BUFFER_SIZE: usize = 128;
+
+/// Number of rows read at once from a sqlite connection.
+const SQLITE_BATCH_SIZE: usize = 1024;
+
+/// Stream the rows of the query from a sqlite connection, read in batches.
+/// The connection is acquired on a blocking thread for each batch and
+/// released before the rows of the batch are yielded, so that the consumer of
+/// the stream can run other queries, e.g. write for every row, without
+/// deadlocking.
+///
+/// Each batch runs the query again and skips the rows of the previous ones,
+/// so the query must order its rows deterministically, and the rows written
+/// between two batches may be missed or read twice.
+pub fn sqlite_query_stream<T>(
+    multithread_con: &SqliteMultithreaded,
+    query: String,
+    params: Vec<(String, ValueWrapper)>,
+    row_to_tuple: fn(&SqliteRow) -> SqliteResult<T>,
+) -> BoxStream<'static, Result<T, Error>>
+where
+    T: Send + 'static,
+{
+    let multithread_con = multithread_con.clone();
+    let query = Arc::new(query);
+    let params = Arc::new(params);
+    stream::try_unfold(Some(0), move |offset| {
+        let multithread_con = multithread_con.clone();
+        let query = query.clone();
+        let params = params.clone();
+        async move {
+            let offset = match offset {
+                Some(offset) => offset,
+                None => return Ok(None),
+            };
+            let rows = multithread_con
+                .run_blocking_query(SqliteQueryType::Read, move |con| {
+                    read_sqlite_rows(con, &query, &params, offset, row_to_tuple)
+                })
+                .await?;
+            let next = if rows.len() < SQLITE_BATCH_SIZE {
+                None
+            } else {
+                Some(offset + rows.len())
+            };
+            Ok::<_, Error>(Some((stream::iter(rows.into_iter().map(Ok)), next)))
+        }
+    })
+    .try_flatten()
+    .boxed()
+}
+
+/// Read the batch of rows of the query following the first `offset` ones.
+fn read_sqlite_rows<T>(
+    con: &SqliteConnection,
+    query: &str,
+    params: &[(String, ValueWrapper)],
+    offset: usize,
+    row_to_tuple: fn(&SqliteRow) -> SqliteResult<T>,
+) -> Result<Vec<T>, Error> {
+    let mut stmt = con.prepare_cached(query)?;
+    let param_refs: Vec<(&str, &dyn ToSqliteValue)> = params
+        .iter()
+        .map(|(name, value)| (name.as_str(), value as &dyn ToSqliteValue))
+        .collect();
+    let mut rows = stmt.query(&param_refs[..])?;
+    for _ in 0..offset {
+        if rows.next()?.is_none() {
+            return Ok(Vec::new());
+        }
+    }
+    let mut batch = Vec::new();
+    while batch.len() < SQLITE_BATCH_SIZE {
+        match rows.next()? {
+            Some(row) => batch.push(row_to_tuple(row)?),
+            None => break,
+        }
+    }
+    Ok(batch)
+}
+
+/// Stream the rows of the query, executed as a prepared statement, from a
+/// connection of the pool. The connection goes back to the pool once the
+/// stream is exhausted or dropped.
+pub fn mysql_query_stream<T>(
+    conn: &OssConnection,
+    query: String,
+    params: Params,
+    row_to_tuple: fn(Row) -> Result<T, Error>,
+) -> BoxStream<'static, Result<T, Error>>
+where
+    T: Send + 'static,
+{
+    let conn = conn.clone();
+    stream::once(async move {
+        let (mut tx, rx) = mpsc::channel(STREAM_BUFFER_SIZE);
+        tokio::spawn(async move {
+            let send_rows = async {
+                let mut con = conn.get_conn().await?;
+                let mut result = conn.read_prepared_query(&mut con, &query, params).await?;
+                while let Some(row) = result.next().await? {
+                    if tx.send(row_to_tuple(row)).await.is_err() {
+                        // The stream was dropped, stop reading rows.
+                        break;
+                    }
+                }
+                Ok::<_, Error>(())
+            };
+
+            if let Err(err) = send_rows.await {
+                let _ = tx.send(Err(err)).await;
+            }
+        });
+        rx
+    })
+    .flatten()
+    .boxed()
+}
@@ -9,17 +9,75 @@
 
 #![deny(warnings)]
 
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sql_tests_lib::test_blob_handle;
+use sql_tests_lib::test_cached_query;
+use sql_tests_lib::test_cas_update;
+use sql_tests_lib::test_chunked_values;
+use sql_tests_lib::test_commit_with_retry;
+use sql_tests_lib::test_compressed;
+use sql_tests_lib::test_connection_metrics;
 use sql_tests_lib::test_datetime_query;
+use sql_tests_lib::test_explain;
+use sql_tests_lib::test_from_row;
+use sql_tests_lib::test_id_allocator;
+use sql_tests_lib::test_insert_or_update;
+use sql_tests_lib::test_json;
+use sql_tests_lib::test_maybe_fragments;
+use sql_tests_lib::test_migrations;
+use sql_tests_lib::test_ping;
+use sql_tests_lib::test_probe_capabilities;
+use sql_tests_lib::test_query_observer;
+use sql_tests_lib::test_query_stream;
 use sql_tests_lib::test_query_visibility_modifiers_compile;
+use sql_tests_lib::test_query_with_consistency;
+use sql_tests_lib::test_read_paged;
 use sql_tests_lib::test_read_query;
+use sql_tests_lib::test_render;
+use sql_tests_lib::test_schema_variants;
+use sql_tests_lib::test_slow_query;
+use sql_tests_lib::test_slow_query_log;
+use sql_tests_lib::test_sql_error;
+use sql_tests_lib::test_sqlite_blob;
+use sql_tests_lib::test_sqlite_extensions;
+use sql_tests_lib::test_sqlite_query_cancellation;
+use sql_tests_lib::test_sqlite_query_timeout;
+use sql_tests_lib::test_sqlite_session_vars;
+use sql_tests_lib::test_sqlite_wal_reads;
+use sql_tests_lib::test_sqlite_write_priority;
 use sql_tests_lib::test_transaction_commit;
+use sql_tests_lib::test_transaction_options;
 use sql_tests_lib::test_transaction_rollback;
 use sql_tests_lib::test_transaction_rollback_on_drop;
+use sql_tests_lib::test_transaction_savepoints;
+use sql_tests_lib::test_utc_datetime;
+use sql_tests_lib::test_values_with_list;
 use sql_tests_lib::test_write_query;
+use sql_tests_lib::test_write_returning;
 use sql_tests_lib::TestSemantics;
 
+use crate::mysql::ConnectionStats;
+use crate::mysql_async::Params;
+use crate::mysql_async::Value;
+use crate::mysql_prepared_max_rows;
+use crate::rusqlite::functions::Aggregate;
+use crate::rusqlite::functions::Context;
+use crate::rusqlite::functions::FunctionFlags;
 use crate::rusqlite::Connection as SqliteConnection;
+use crate::sqlite::SqliteConnectionOptions;
+use crate::sqlite::SqliteExtensions;
+use crate::sqlite::SqliteJournalMode;
+use crate::sqlite::SqliteMaintenanceOptions;
+use crate::sqlite::SqliteMultithreaded;
+use crate::sqlite::SqliteQueryType;
+use crate::sqlite::SqliteSynchronous;
+use crate::sqlite_list_placeholders;
 use crate::Connection;
+use crate::MysqlParams;
+use crate::OssConnection;
 
 #[tokio::test]
 async fn test_read_query_sqlite() {
@@ -31,7 +89,20 @@ async fn test_read_query_sqlite() {
 }
 
 fn prepare_sqlite_con() -> Connection {
+    Connection::with_sqlite(prepare_sqlite_raw_con())
+}
+
+fn prepare_sqlite_raw_con() -> SqliteConnection {
     let conn = SqliteConnection::open_in_memory().unwrap();
+    create_test_tables(&conn);
+    conn
+}
+
+fn prepare_sqlite_raw_con_at(path: &Path) {
+    create_test_tables(&SqliteConnection::open(path).unwrap());
+}
+
+fn create_test_tables(conn: &SqliteConnection) {
     conn.execute_batch(
         "BEGIN;
             CREATE TABLE foo(
@@ -39,10 +110,13 @@ fn prepare_sqlite_con() -> Connection {
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 y DATETIME DEFAULT CURRENT_TIMESTAMP
             );
+            CREATE TABLE id_allocator(
+                name VARCHAR(255) PRIMARY KEY,
+                next_id INTEGER NOT NULL
+            );
             COMMIT;",
     )
     .unwrap();
-    Connection::with_sqlite(conn)
 }
 
 #[tokio::test]
@@ -50,11 +124,26 @@ async fn test_datetime_query_with_sqlite() {
     test_datetime_query(prepare_sqlite_con()).await;
 }
 
+#[tokio::test]
+async fn test_utc_datetime_with_sqlite() {
+    test_utc_datetime(prepare_sqlite_con()).await;
+}
+
 #[tokio::test]
 async fn test_write_query_with_sqlite() {
     test_write_query(prepare_sqlite_con()).await;
 }
 
+#[tokio::test]
+async fn test_chunked_values_with_sqlite() {
+    test_chunked_values(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_write_returning_with_sqlite() {
+    test_write_returning(prepare_sqlite_con()).await;
+}
+
 #[tokio::test]
 async fn test_transaction_rollback_with_sqlite() {
     test_transaction_rollback(prepare_sqlite_con(), TestSemantics::Sqlite).await;
@@ -70,11 +159,405 @@ async fn test_transaction_commit_with_sqlite() {
     test_transaction_commit(prepare_sqlite_con(), TestSemantics::Sqlite).await;
 }
 
+#[tokio::test]
+async fn test_transaction_savepoints_with_sqlite() {
+    test_transaction_savepoints(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_transaction_options_with_sqlite() {
+    test_transaction_options(prepare_sqlite_con()).await;
+}
+
 #[tokio::test]
 async fn test_visibility_modifiers_compile_with_sqlite() {
     test_query_visibility_modifiers_compile(prepare_sqlite_con()).await;
 }
 
+#[tokio::test]
+async fn test_id_allocator_with_sqlite() {
+    test_id_allocator(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_query_observer_with_sqlite() {
+    test_query_observer(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_slow_query_with_sqlite() {
+    test_slow_query(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_slow_query_log_with_sqlite() {
+    test_slow_query_log(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_commit_with_retry_with_sqlite() {
+    test_commit_with_retry(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_connection_metrics_with_sqlite() {
+    test_connection_metrics(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_probe_capabilities_with_sqlite() {
+    test_probe_capabilities(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_values_with_list_with_sqlite() {
+    test_values_with_list(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_explain_with_sqlite() {
+    test_explain(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_query_stream_with_sqlite() {
+    test_query_stream(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_read_paged_with_sqlite() {
+    test_read_paged(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_sql_error_with_sqlite() {
+    test_sql_error(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_compressed_with_sqlite() {
+    test_compressed(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_json_with_sqlite() {
+    test_json(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_insert_or_update_with_sqlite() {
+    test_insert_or_update(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_cas_update_with_sqlite() {
+    test_cas_update(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_schema_variants_with_sqlite() {
+    test_schema_variants(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_oss_mysql_schema_variant() {
+    let pool = mysql_async::Pool::new("mysql://localhost/db");
+    let stats = Arc::new(ConnectionStats::new("test".to_owned()));
+    let conn = Connection::from(OssConnection::new(pool.clone(), stats.clone()));
+    assert_eq!(conn.schema_variant(), None);
+    let conn = Connection::from(OssConnection::new(pool, stats).with_schema_variant("mysql8"));
+    assert_eq!(conn.schema_variant(), Some("mysql8"));
+}
+
+#[tokio::test]
+async fn test_maybe_fragments_with_sqlite() {
+    test_maybe_fragments(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_migrations_with_sqlite() {
+    test_migrations(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_from_row_with_sqlite() {
+    test_from_row(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_sqlite_blob_with_sqlite() {
+    test_sqlite_blob(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_blob_handle_with_sqlite() {
+    test_blob_handle(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_sqlite_session_vars_with_sqlite() {
+    test_sqlite_session_vars(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_sqlite_write_priority_with_sqlite() {
+    test_sqlite_write_priority(prepare_sqlite_con()).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_query_timeout_with_sqlite() {
+    test_sqlite_query_timeout(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_query_cancellation_with_sqlite() {
+    test_sqlite_query_cancellation(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_wal_reads_with_sqlite() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("test.db");
+    prepare_sqlite_raw_con_at(&path);
+    test_sqlite_wal_reads(Connection::with_sqlite_wal(&path, 2).unwrap()).await;
+}
+
+#[tokio::test]
+async fn test_wal_options_with_sqlite() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("test.db");
+    prepare_sqlite_raw_con_at(&path);
+    let options = SqliteConnectionOptions::new()
+        .with_synchronous(SqliteSynchronous::Normal)
+        .with_busy_timeout(Duration::from_secs(2))
+        .with_foreign_keys(true);
+
+    let sqlite =
+        SqliteMultithreaded::open_wal_with_options(&path, 1, &SqliteExtensions::new(), &options)
+            .unwrap();
+    for query_type in [SqliteQueryType::Read, SqliteQueryType::Write] {
+        let con = sqlite.acquire_sqlite_connection(query_type).await.unwrap();
+        let pragma = |name: &str| -> i64 {
+            con.query_row(&format!("PRAGMA {}", name), [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(pragma("synchronous"), 1);
+        assert_eq!(pragma("busy_timeout"), 2000);
+        assert_eq!(pragma("foreign_keys"), 1);
+    }
+
+    let options = options.with_journal_mode(SqliteJournalMode::Delete);
+    assert!(
+        SqliteMultithreaded::open_wal_with_options(&path, 1, &SqliteExtensions::new(), &options)
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_backup_and_restore_with_sqlite() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("backup.db");
+    let sqlite = SqliteMultithreaded::new(prepare_sqlite_raw_con());
+    let count = |sqlite: SqliteMultithreaded| async move {
+        sqlite
+            .run_query(SqliteQueryType::Read, None, |con| {
+                Ok(con.query_row("SELECT COUNT(*) FROM foo", [], |row| row.get::<_, i64>(0))?)
+            })
+            .await
+            .unwrap()
+    };
+    let insert = |sqlite: SqliteMultithreaded| async move {
+        sqlite
+            .run_query(SqliteQueryType::Write, None, |con| {
+                Ok(con.execute("INSERT INTO foo (x) VALUES (1)", [])?)
+            })
+            .await
+            .unwrap();
+    };
+
+    insert(sqlite.clone()).await;
+    sqlite.backup_to(&path).await.unwrap();
+    insert(sqlite.clone()).await;
+    assert_eq!(count(sqlite.clone()).await, 2);
+
+    // The backup is a standalone database file.
+    let backup = SqliteConnection::open(&path).unwrap();
+    let rows: i64 = backup
+        .query_row("SELECT COUNT(*) FROM foo", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(rows, 1);
+
+    sqlite.restore_from(&path).await.unwrap();
+    assert_eq!(count(sqlite.clone()).await, 1);
+    assert!(sqlite.restore_from(dir.path()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_maintenance_with_sqlite() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("test.db");
+    let con = SqliteConnection::open(&path).unwrap();
+    con.execute_batch("PRAGMA auto_vacuum = INCREMENTAL")
+        .unwrap();
+    create_test_tables(&con);
+    drop(con);
+
+    let sqlite = SqliteMultithreaded::open_wal(&path, 1, &SqliteExtensions::new()).unwrap();
+    let write = |query: &'static str| {
+        let sqlite = sqlite.clone();
+        async move {
+            sqlite
+                .run_query(SqliteQueryType::Write, None, move |con| {
+                    Ok(con.execute_batch(query)?)
+                })
+                .await
+                .unwrap()
+        }
+    };
+    write(
+        "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5000)
+            INSERT INTO foo (x) SELECT i FROM n",
+    )
+    .await;
+    write("DELETE FROM foo").await;
+
+    let options = SqliteMaintenanceOptions::new(Duration::from_millis(10))
+        .with_jitter(Duration::from_millis(10))
+        .with_incremental_vacuum(0);
+    let run = sqlite.run_maintenance(&options).await.unwrap();
+    assert!(run.wal_bytes > 0);
+    assert!(run.checkpoint.is_some() && !run.checkpoint_busy);
+    assert!(run.vacuumed_pages > 0);
+    // The write-ahead log is truncated.
+    assert_eq!(
+        std::fs::metadata(dir.path().join("test.db-wal"))
+            .unwrap()
+            .len(),
+        0
+    );
+
+    write("INSERT INTO foo (x) VALUES (1)").await;
+    let options = options.with_checkpoint(false);
+    let maintenance = sqlite.spawn_maintenance(options.clone());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let stats = maintenance.stats();
+    assert!(stats.runs > 0);
+    assert_eq!(stats.failures, 0);
+    assert_eq!(stats.max_wal_bytes, 0);
+    assert_eq!(stats.total_checkpoint, Duration::ZERO);
+    assert!(maintenance.last_error().is_none());
+    drop(maintenance);
+
+    // The vacuum fails on a read-only connection.
+    write("PRAGMA query_only = 1").await;
+    let maintenance = sqlite.spawn_maintenance(options);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let stats = maintenance.stats();
+    assert_eq!(stats.runs, 0);
+    assert!(stats.failures > 0);
+    let err = maintenance.last_error().unwrap();
+    assert!(err.to_string().contains("readonly"), "{err}");
+}
+
+#[tokio::test]
+async fn test_keepalive_with_sqlite() {
+    let conn = prepare_sqlite_con();
+    let sqlite = match &conn {
+        Connection::Sqlite(sqlite) => sqlite.clone(),
+        _ => unreachable!(),
+    };
+    // Leave a transaction open, as a caller panicking in the middle of it.
+    let leave_transaction_open = || async {
+        let con = sqlite
+            .acquire_sqlite_connection(SqliteQueryType::Transaction)
+            .await
+            .unwrap();
+        con.execute_batch("BEGIN").unwrap();
+    };
+
+    assert_eq!(conn.keepalive(0).await.unwrap(), 0);
+    leave_transaction_open().await;
+    assert!(conn.start_transaction().await.is_err());
+    assert_eq!(conn.keepalive(0).await.unwrap(), 1);
+    assert_eq!(conn.keepalive(0).await.unwrap(), 0);
+    conn.start_transaction().await.unwrap();
+
+    leave_transaction_open().await;
+    let keepalive = conn.spawn_keepalive(Duration::from_millis(10), 0);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    drop(keepalive);
+    conn.start_transaction().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_cached_query_with_sqlite() {
+    test_cached_query(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_query_with_consistency_with_sqlite() {
+    test_query_with_consistency(prepare_sqlite_con()).await;
+}
+
+#[tokio::test]
+async fn test_ping_with_sqlite() {
+    test_ping(prepare_sqlite_con()).await;
+}
+
+#[test]
+fn test_render_queries() {
+    test_render();
+}
+
+#[test]
+fn test_mysql_params() {
+    let mut params = MysqlParams::default();
+    let id = params.bind(Value::from(1));
+    let ids = params.bind_list([Value::from(2), Value::from("x")]);
+    let empty = params.bind_list([]);
+    assert_eq!(
+        format!("id = {id} OR id IN {ids} OR id IN {empty}"),
+        "id = :p0 OR id IN (:p1, :p2) OR id IN ()"
+    );
+
+    let names = [
+        b"p2".to_vec(),
+        b"p0".to_vec(),
+        b"p1".to_vec(),
+        b"p0".to_vec(),
+    ];
+    assert_eq!(
+        Params::from(params).into_positional(&names).unwrap(),
+        Params::Positional(vec![
+            Value::from("x"),
+            Value::from(1),
+            Value::from(2),
+            Value::from(1)
+        ])
+    );
+    assert_eq!(Params::from(MysqlParams::default()), Params::Empty);
+}
+
+#[test]
+fn test_mysql_prepared_max_rows() {
+    assert_eq!(mysql_prepared_max_rows(1, 0), MysqlParams::MAX_PLACEHOLDERS);
+    assert_eq!(mysql_prepared_max_rows(3, 0), 21845);
+    // 21845 rows of 3 values and 1 other parameter don't fit.
+    assert_eq!(mysql_prepared_max_rows(3, 1), 21844);
+    assert_eq!(mysql_prepared_max_rows(0, 0), MysqlParams::MAX_PLACEHOLDERS);
+    // At least one row is inserted by each statement.
+    assert_eq!(mysql_prepared_max_rows(2, 70000), 1);
+}
+
+#[test]
+fn test_sqlite_list_placeholders() {
+    assert_eq!(sqlite_list_placeholders(0), 0);
+    assert_eq!(sqlite_list_placeholders(1), 1);
+    assert_eq!(sqlite_list_placeholders(3), 4);
+    assert_eq!(sqlite_list_placeholders(1024), 1024);
+    assert_eq!(sqlite_list_placeholders(1025), 1025);
+}
+
 #[cfg(fbcode_build)]
 #[cfg(test)]
 mod mysql {
@@ -137,3 +620,37 @@ mod mysql {
         Ok(())
     }
 }
+
+#[derive(Clone)]
+struct Product;
+
+impl Aggregate<i64, i64> for Product {
+    fn init(&self, _ctx: &mut Context<'_>) -> rusqlite::Result<i64> {
+        Ok(1)
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut i64) -> rusqlite::Result<()> {
+        *acc *= ctx.get::<i64>(0)?;
+        Ok(())
+    }
+
+    fn finalize(&self, _ctx: &mut Context<'_>, acc: Option<i64>) -> rusqlite::Result<i64> {
+        Ok(acc.unwrap_or(1))
+    }
+}
+
+#[tokio::test]
+async fn test_sqlite_extensions_with_sqlite() {
+    let extensions = SqliteExtensions::new()
+        .with_scalar_function(
+            "test_reverse",
+            1,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| Ok(ctx.get::<String>(0)?.chars().rev().collect::<String>()),
+        )
+        .with_aggregate_function("test_product", 1, FunctionFlags::SQLITE_UTF8, Product)
+        .with_unicode_nocase_collation();
+
+    let conn = Connection::with_sqlite_extensions(prepare_sqlite_raw_con(), &extensions).unwrap();
+    test_sqlite_extensions(conn).await;
+}
@@ -9,19 +9,72 @@
 
 #![cfg_attr(fbcode_build, deny(warnings, clippy::all))]
 
+pub mod replay;
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::DateTime;
 use chrono::NaiveDate;
 use chrono::NaiveDateTime;
 use rand::distributions::Alphanumeric;
 use rand::thread_rng;
 use rand::Rng;
 use sql::anyhow::Error;
+use sql::capabilities::ServerKind;
+use sql::cas::cas_update;
+use sql::cas::cas_update_with_transaction;
+use sql::cas::CasOutcome;
+use sql::explain::PRIMARY_KEY;
+use sql::futures::stream;
+use sql::futures::StreamExt;
+use sql::futures::TryStreamExt;
+use sql::lag::Consistency;
+use sql::metrics::ConnectionMetrics;
 use sql::mysql_async::prelude::*;
 use sql::mysql_async::FromValueError;
 use sql::mysql_async::Value;
+use sql::observer::register_query_observer;
+use sql::observer::QueryEvent;
+use sql::observer::QueryObserver;
+use sql::observer::TransactionEvent;
+use sql::observer::TransactionOperation;
+use sql::retry::CommitRetryPolicy;
+use sql::slow_query::set_slow_query_config;
+use sql::slow_query::SlowQueryConfig;
+use sql::slow_query::SlowQueryEvent;
+use sql::slow_query::SlowQueryLog;
 use sql::queries;
+use sql::serde_json;
+use sql::CancellationToken;
 use sql::sql_common::mysql;
+use sql::sqlite::SqlitePriority;
+use sql::sqlite::SqliteQueryType;
+use sql::BlobHandle;
+use sql::CachedConnection;
+use sql::Compressed;
 use sql::Connection;
+use sql::FromRow;
+use sql::IdAllocator;
+use sql::IsolationLevel;
+use sql::Json;
+use sql::Limit;
+use sql::Migration;
+use sql::Migrator;
+use sql::Offset;
+use sql::QueryCancelled;
+use sql::QueryTimeout;
+use sql::RenderedQuery;
+use sql::SqlConnections;
+use sql::SqlErrorKind;
 use sql::Transaction;
+use sql::TransactionOptions;
+use sql::UtcDateTime;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
 
 pub struct A;
 
@@ -56,6 +109,13 @@ impl FromValue for B {
     type Intermediate = IntB;
 }
 
+#[derive(Debug, Eq, PartialEq, FromRow)]
+pub struct FooRow {
+    pub id: i64,
+    pub x: i64,
+    pub label: Option<String>,
+}
+
 queries! {
     read TestQuery(param_a: A, param_uint: u64) -> (u64, B, B, i64) {
         "SELECT 44, NULL, {param_a}, {param_uint}"
@@ -109,6 +169,148 @@ queries! {
     read TestQuery14(date: NaiveDateTime) -> (String) {
         "SELECT datetime(y) FROM foo WHERE y = {date}"
     }
+
+    read TestQuery15(test: String) -> (String) {
+        "SELECT test_reverse(CAST({test} AS TEXT))"
+    }
+
+    read TestQuery16() -> (i64) {
+        "SELECT test_product(x) FROM foo"
+    }
+
+    read TestQuery17(a: String, b: String) -> (bool) {
+        "SELECT CAST({a} AS TEXT) = CAST({b} AS TEXT) COLLATE UNICODE_NOCASE"
+    }
+
+    read TestQuery18(id1: u64, id2: u64) -> FooRow {
+        "SELECT id, x, NULL FROM foo WHERE {id1} <= id AND id <= {id2}"
+    }
+
+    read TestQuery19() -> FooRow {
+        "SELECT id, x FROM foo"
+    }
+
+    read TestQuery20(data: Compressed<Vec<u8>>) -> (Compressed<Vec<u8>>, Vec<u8>) {
+        "SELECT {data}, {data}"
+    }
+
+    read TestQuery21() -> (i64) {
+        "WITH RECURSIVE c(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM c) SELECT COUNT(*) FROM c"
+    }
+
+    read TestQuery22(x: i64, >maybe min_id: u64 = "AND id >= {min_id}") -> (u64) {
+        "SELECT id FROM foo WHERE x = {x} {min_id} ORDER BY id"
+    }
+
+    write TestQuery23(x: i64, >list ids: u64, >maybe old_x: i64 = "AND x = {old_x}") {
+        none,
+        "UPDATE foo SET x = {x} WHERE id IN {ids} {old_x}"
+    }
+
+    read TestQuery24() -> (i64, String) {
+        "SELECT id, name FROM migrated ORDER BY id"
+    }
+
+    write TestQuery25(values: (x: i64)) {
+        none,
+        "INSERT INTO foo (x) VALUES {values}"
+    }
+
+    read TestQuery26(x: i64) -> (i64) {
+        "SELECT id FROM foo WHERE x = {x}"
+    }
+
+    read TestQuery27() -> (i64) {
+        "SELECT x FROM missing"
+    }
+
+    write TestQuery28(values: (id: i64, x: i64)) {
+        insert_or_update(x),
+        "INSERT INTO foo (id, x) VALUES {values} {insert_or_update}"
+    }
+
+    write TestQuery29(id: i64, x: i64) {
+        insert_or_update(x),
+        "INSERT INTO foo (id, x) VALUES ({id}, {x}) {insert_or_update}"
+    }
+
+    write TestQuery30(x: i64) -> (u64) {
+        none,
+        "INSERT INTO foo (x) VALUES ({x}) RETURNING id"
+    }
+
+    write TestQuery31(x: i64, >list ids: u64) -> (u64, i64) {
+        none,
+        "UPDATE foo SET x = {x} WHERE id IN {ids} RETURNING id, x"
+    }
+
+    read TestQuery32(x: i64) -> (i64) {
+        mysql("SELECT {x} + 1")
+        mysql8("SELECT {x} + 8")
+        sqlite("SELECT {x} + 2")
+    }
+
+    write TestQuery33(values: (x: i64)) {
+        none,
+        mysql("INSERT INTO foo (x) VALUES {values}")
+        mysql8("INSERT INTO foo (x) VALUES {values} AS new")
+        sqlite("INSERT INTO foo (x) VALUES {values}")
+    }
+
+    write TestQuery34(x: i64, >list ids: u64) {
+        none,
+        mysql("UPDATE foo SET x = {x} WHERE id IN {ids}")
+        mysql8("UPDATE foo SET x = {x} WHERE id IN {ids} ORDER BY id")
+        sqlite("UPDATE foo SET x = {x} WHERE id IN {ids}")
+    }
+
+    write TestQuery35(id: u64, old_x: i64) {
+        none,
+        "UPDATE foo SET x = {old_x} + 1 WHERE id = {id} AND x = {old_x}"
+    }
+
+    read TestQuery36(id: u64) -> (i64) {
+        "SELECT x FROM foo WHERE id = {id}"
+    }
+
+    read_paged TestQuery37(x: i64) -> (u64, i64) {
+        id,
+        "SELECT id, x FROM foo WHERE x = {x}"
+    }
+
+    read TestQuery38(data: Json<Vec<u64>>, value: serde_json::Value) -> (Json<Vec<u64>>, serde_json::Value, i64) {
+        mysql("SELECT {data}, {value}, JSON_EXTRACT({value}, '$.a')")
+        sqlite("SELECT {data}, {value}, json_extract(CAST({value} AS TEXT), '$.a')")
+    }
+
+    write TestQuery39(x: i64, y: UtcDateTime) {
+        none,
+        "INSERT INTO foo (x, y) VALUES ({x}, {y})"
+    }
+
+    read TestQuery40(x: i64) -> (UtcDateTime, NaiveDateTime) {
+        "SELECT y, y FROM foo WHERE x = {x}"
+    }
+
+    write TestQuery41(values: (x: i64, id: u64)) {
+        none,
+        chunk_size(2),
+        "INSERT INTO foo (x, id) VALUES {values}"
+    }
+
+    read TestQuery42(x: i64) -> (u64) {
+        "SELECT id FROM foo WHERE x = {x}"
+    }
+
+    write TestQuery43(values: (id: u64, x: i64), >list ids: u64) {
+        none,
+        mysql("INSERT INTO foo (id, x) VALUES {values} ON DUPLICATE KEY UPDATE x = IF(id IN {ids}, VALUES(x), x)")
+        sqlite("INSERT INTO foo (id, x) VALUES {values} ON CONFLICT(id) DO UPDATE SET x = excluded.x WHERE id IN {ids}")
+    }
+
+    read TestQuery44(x: i64, limit: Limit, offset: Offset) -> (u64) {
+        "SELECT id FROM foo WHERE x = {x} ORDER BY id LIMIT {limit} OFFSET {offset}"
+    }
 }
 
 pub async fn test_basic_query(conn: Connection) -> Result<(), Error> {
@@ -176,6 +378,112 @@ pub async fn test_datetime_query(conn: Connection) {
     assert_eq!(res, vec![("2021-01-21 21:21:21".to_owned(),)]);
 }
 
+pub async fn test_utc_datetime(conn: Connection) {
+    let date = DateTime::parse_from_rfc3339("2021-01-21T23:21:21.25+02:00").unwrap();
+    let res = TestQuery39::query(&conn, &1, &UtcDateTime::from(date))
+        .await
+        .unwrap();
+    assert_eq!(res.affected_rows(), 1);
+    let res = TestQuery40::query(&conn, &1).await.unwrap();
+    let naive = NaiveDate::from_ymd_opt(2021, 1, 21)
+        .unwrap()
+        .and_hms_milli_opt(21, 21, 21, 250)
+        .unwrap();
+    assert_eq!(res, vec![(UtcDateTime::from(date), naive)]);
+
+    // Dates set by the database, e.g. from CURRENT_TIMESTAMP, are read too.
+    TestQuery3::query(&conn, &[(&2,)]).await.unwrap();
+    let res = TestQuery40::query(&conn, &2).await.unwrap();
+    assert_eq!(res.len(), 1);
+}
+
+pub async fn test_query_with_consistency(conn: Connection) {
+    TestQuery3::query(&conn, &[(&1,)]).await.unwrap();
+    // The lag of sqlite is zero, so every consistency reads the same data.
+    let connections = SqlConnections::new_single(conn);
+    for consistency in [
+        Consistency::Eventual,
+        Consistency::BoundedStaleness(Duration::ZERO),
+        Consistency::Strong,
+    ] {
+        let res = TestQuery36::query_with_consistency(&connections, consistency, &1)
+            .await
+            .unwrap();
+        assert_eq!(res, vec![(1,)], "{:?}", consistency);
+    }
+}
+
+pub async fn test_cached_query(conn: Connection) {
+    let cached = CachedConnection::new(conn.clone(), Duration::from_secs(60));
+    TestQuery3::query(&conn, &[(&1,)]).await.unwrap();
+    let res = TestQuery36::cached_query(&cached, &1).await.unwrap();
+    assert_eq!(*res, vec![(1,)]);
+
+    // Writes made without the cached connection are not seen.
+    TestQuery10::query(&conn, &2, &1).await.unwrap();
+    let again = TestQuery36::cached_query(&cached, &1).await.unwrap();
+    assert!(Arc::ptr_eq(&res, &again));
+    assert_eq!(
+        *TestQuery36::cached_query(&cached, &2).await.unwrap(),
+        vec![]
+    );
+
+    // Writes declared in the same module invalidate the cached results.
+    let res = TestQuery10::cached_query(&cached, &3, &1).await.unwrap();
+    assert_eq!(res.affected_rows(), 1);
+    assert_eq!(
+        *TestQuery36::cached_query(&cached, &1).await.unwrap(),
+        vec![(3,)]
+    );
+
+    TestQuery10::query(&conn, &4, &1).await.unwrap();
+    cached.invalidate_all();
+    assert_eq!(
+        *TestQuery36::cached_query(&cached, &1).await.unwrap(),
+        vec![(4,)]
+    );
+
+    // Results expire after the time to live.
+    let cached = CachedConnection::new(conn.clone(), Duration::ZERO);
+    assert_eq!(
+        *TestQuery36::cached_query(&cached, &1).await.unwrap(),
+        vec![(4,)]
+    );
+    TestQuery10::query(&conn, &5, &1).await.unwrap();
+    assert_eq!(
+        *TestQuery36::cached_query(&cached, &1).await.unwrap(),
+        vec![(5,)]
+    );
+}
+
+pub async fn test_chunked_values(conn: Connection) {
+    let res = TestQuery41::query(&conn, &[(&1, &1), (&2, &2), (&3, &3), (&4, &4), (&5, &5)])
+        .await
+        .unwrap();
+    assert_eq!(res.affected_rows(), 5);
+    assert_eq!(res.last_insert_id(), Some(5));
+    assert_eq!(TestQuery36::query(&conn, &5).await.unwrap(), vec![(5,)]);
+
+    // The chunks are inserted in a transaction, so the rows of the chunks
+    // before the failing one are not inserted either.
+    assert!(
+        TestQuery41::query(&conn, &[(&6, &6), (&7, &7), (&8, &1)])
+            .await
+            .is_err()
+    );
+    assert_eq!(TestQuery36::query(&conn, &6).await.unwrap(), vec![]);
+
+    // Queries in a transaction are chunked in that transaction.
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, res) =
+        TestQuery41::query_with_transaction(transaction, &[(&6, &6), (&7, &7), (&8, &8)])
+            .await
+            .unwrap();
+    assert_eq!(res.affected_rows(), 3);
+    transaction.commit().await.unwrap();
+    assert_eq!(TestQuery36::query(&conn, &8).await.unwrap(), vec![(8,)]);
+}
+
 pub async fn test_write_query(conn: Connection) {
     let res = TestQuery3::commented_query(&conn, "comment", &[(&44,)])
         .await
@@ -299,6 +607,113 @@ pub async fn test_transaction_commit(conn: Connection, semantics: TestSemantics)
     );
 }
 
+pub async fn test_commit_with_retry(conn: Connection) {
+    let Connection::Sqlite(sqlite) = &conn else {
+        panic!("expected a sqlite connection");
+    };
+    sqlite
+        .run_query(SqliteQueryType::SchemaChange, None, |con| {
+            con.execute_batch(
+                "CREATE TABLE commit_tokens (token VARCHAR(32) PRIMARY KEY);
+                CREATE TABLE parents (id INTEGER PRIMARY KEY);
+                CREATE TABLE children (
+                    parent INTEGER REFERENCES parents(id) DEFERRABLE INITIALLY DEFERRED
+                );
+                PRAGMA foreign_keys = ON;",
+            )?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+    let count = |table: &'static str| {
+        let query = format!("SELECT COUNT(*) FROM {}", table);
+        sqlite.run_query(SqliteQueryType::Read, None, move |con| {
+            Ok(con.query_row(&query, [], |row| row.get::<_, i64>(0))?)
+        })
+    };
+    let policy = CommitRetryPolicy::new(conn.clone(), "commit_tokens");
+
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, _) = TestQuery3::query_with_transaction(transaction, &[(&1,)])
+        .await
+        .unwrap();
+    transaction.commit_with_retry(&policy).await.unwrap();
+    assert_eq!(TestQuery4::query(&conn, &1, &1).await.unwrap(), vec![(1,)]);
+    assert_eq!(count("commit_tokens").await.unwrap(), 1);
+
+    // The commit fails on the deferred foreign key, and the token isn't
+    // found as the transaction was rolled back.
+    let transaction = conn.start_transaction().await.unwrap();
+    let Transaction::Sqlite(Some(con)) = &transaction else {
+        panic!("expected a sqlite transaction");
+    };
+    con.execute("INSERT INTO children (parent) VALUES (1)", [])
+        .unwrap();
+    let err = transaction.commit_with_retry(&policy).await.unwrap_err();
+    assert!(format!("{:#}", err).contains("FOREIGN KEY"), "{:#}", err);
+    assert_eq!(count("children").await.unwrap(), 0);
+    assert_eq!(count("commit_tokens").await.unwrap(), 1);
+}
+
+pub async fn test_transaction_savepoints(conn: Connection) {
+    let transaction = conn.start_transaction().await.unwrap();
+    let (mut transaction, _) = TestQuery3::query_with_transaction(transaction, &[(&1,)])
+        .await
+        .unwrap();
+    transaction.savepoint("first").await.unwrap();
+    let (mut transaction, _) = TestQuery3::query_with_transaction(transaction, &[(&2,)])
+        .await
+        .unwrap();
+    transaction.rollback_to_savepoint("first").await.unwrap();
+    let (mut transaction, _) = TestQuery3::query_with_transaction(transaction, &[(&3,)])
+        .await
+        .unwrap();
+    transaction.release_savepoint("first").await.unwrap();
+    assert!(transaction.rollback_to_savepoint("first").await.is_err());
+    assert!(transaction.savepoint("x; DROP TABLE foo").await.is_err());
+    transaction.commit().await.unwrap();
+
+    assert_eq!(
+        TestQuery4::query(&conn, &1, &10).await.unwrap(),
+        vec![(1,), (3,)]
+    );
+}
+
+pub async fn test_transaction_options(conn: Connection) {
+    let snapshot = TransactionOptions::new()
+        .with_isolation_level(IsolationLevel::Serializable)
+        .with_consistent_snapshot(true);
+    let transaction = conn.start_transaction_with_options(snapshot).await.unwrap();
+    let (transaction, _) = TestQuery3::query_with_transaction(transaction, &[(&2,)])
+        .await
+        .unwrap();
+    transaction.commit().await.unwrap();
+
+    let read_only = TransactionOptions::new().with_read_only(true);
+    let transaction = conn
+        .start_transaction_with_options(read_only.clone())
+        .await
+        .unwrap();
+    let (transaction, res) = TestQuery4::query_with_transaction(transaction, &1, &10)
+        .await
+        .unwrap();
+    assert_eq!(res, vec![(2,)]);
+    transaction.commit().await.unwrap();
+
+    // Writes are allowed again once the read-only transaction has ended.
+    TestQuery3::query(&conn, &[(&3,)]).await.unwrap();
+
+    let transaction = conn
+        .start_transaction_with_options(read_only)
+        .await
+        .unwrap();
+    assert!(
+        TestQuery3::query_with_transaction(transaction, &[(&4,)])
+            .await
+            .is_err()
+    );
+}
+
 pub async fn test_query_visibility_modifiers_compile(conn: Connection) {
     mod b {
         use crate::queries;
@@ -320,3 +735,1048 @@ pub async fn test_query_visibility_modifiers_compile(conn: Connection) {
     assert_eq!(res.affected_rows(), 1);
     assert_eq!(res.last_insert_id(), Some(1));
 }
+
+pub async fn test_id_allocator(conn: Connection) {
+    let first = IdAllocator::new(conn.clone(), "test", 3);
+    let second = IdAllocator::new(conn.clone(), "test", 3);
+    let other = IdAllocator::new(conn, "other", 3);
+
+    assert_eq!(first.next_id().await.unwrap(), 1);
+    assert_eq!(second.next_id().await.unwrap(), 4);
+    assert_eq!(first.next_id().await.unwrap(), 2);
+    assert_eq!(first.next_id().await.unwrap(), 3);
+    assert_eq!(first.next_id().await.unwrap(), 7);
+    assert_eq!(second.next_id().await.unwrap(), 5);
+
+    assert_eq!(other.next_id().await.unwrap(), 1);
+    assert_eq!(other.reserve(10).await.unwrap(), 4..14);
+    assert_eq!(other.next_id().await.unwrap(), 2);
+}
+
+/// Expects the connection to have the `test_reverse` scalar function, the
+/// `test_product` aggregate function and the `UNICODE_NOCASE` collation
+/// registered.
+pub async fn test_migrations(conn: Connection) {
+    let migrations = vec![
+        Migration::new(
+            2,
+            "insert_first",
+            "INSERT INTO migrated (id, name) VALUES (1, 'first')",
+        ),
+        Migration::new(
+            1,
+            "create_migrated",
+            "CREATE TABLE migrated (id INTEGER PRIMARY KEY, name VARCHAR(255));
+            INSERT INTO migrated (id, name) VALUES (0, 'zero')",
+        ),
+    ];
+
+    let dry_run = Migrator::new(migrations.clone())
+        .unwrap()
+        .with_dry_run(true);
+    assert_eq!(dry_run.run(&conn).await.unwrap(), vec![1, 2]);
+    assert!(dry_run.applied_versions(&conn).await.unwrap().is_empty());
+
+    let migrator = Migrator::new(migrations.clone()).unwrap();
+    assert_eq!(migrator.run(&conn).await.unwrap(), vec![1, 2]);
+    assert!(migrator.run(&conn).await.unwrap().is_empty());
+    assert_eq!(
+        TestQuery24::query(&conn).await.unwrap(),
+        vec![(0, "zero".to_owned()), (1, "first".to_owned())]
+    );
+
+    let mut migrations = migrations;
+    migrations.push(Migration::with_variants(
+        3,
+        "insert_second",
+        "INSERT INTO migrated (id, name) VALUES (2, 'mysql')",
+        "INSERT INTO migrated (id, name) VALUES (2, 'sqlite')",
+    ));
+    migrations.push(Migration::new(
+        4,
+        "broken",
+        "INSERT INTO migrated (id, name) VALUES (3, 'broken'); INSERT INTO missing VALUES (1)",
+    ));
+    let migrator = Migrator::new(migrations.clone()).unwrap();
+    assert!(migrator.run(&conn).await.is_err());
+    assert_eq!(
+        migrator.applied_versions(&conn).await.unwrap(),
+        vec![1, 2, 3]
+    );
+    assert_eq!(TestQuery24::query(&conn).await.unwrap().len(), 3);
+
+    // The database is ahead of a migrator missing the latest migrations.
+    let migrator = Migrator::new(migrations[..2].to_vec()).unwrap();
+    assert!(migrator.run(&conn).await.is_err());
+
+    migrations.push(Migration::new(4, "duplicate", "SELECT 1"));
+    assert!(Migrator::new(migrations).is_err());
+}
+
+pub async fn test_sqlite_extensions(conn: Connection) {
+    assert_eq!(
+        TestQuery15::query(&conn, &"abc".to_owned()).await.unwrap(),
+        vec![("cba".to_owned(),)]
+    );
+
+    TestQuery3::query(&conn, &[(&2,), (&3,), (&7,)])
+        .await
+        .unwrap();
+    assert_eq!(TestQuery16::query(&conn).await.unwrap(), vec![(42,)]);
+
+    assert_eq!(
+        TestQuery17::query(&conn, &"ÉCOLE".to_owned(), &"école".to_owned())
+            .await
+            .unwrap(),
+        vec![(true,)]
+    );
+    assert_eq!(
+        TestQuery17::query(&conn, &"école".to_owned(), &"ecole".to_owned())
+            .await
+            .unwrap(),
+        vec![(false,)]
+    );
+}
+
+pub async fn test_ping(conn: Connection) {
+    let timeout = Duration::from_secs(10);
+    let latency = conn.ping(timeout).await.unwrap();
+    assert!(latency <= timeout);
+}
+
+pub async fn test_query_stream(conn: Connection) {
+    let rows: Vec<i64> = (0..300).collect();
+    let values: Vec<_> = rows.iter().map(|x| (x,)).collect();
+    let res = TestQuery3::query(&conn, &values).await.unwrap();
+    assert_eq!(res.affected_rows(), 300);
+
+    let streamed: Vec<_> = TestQuery4::query_stream(&conn, &1, &300)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(streamed, rows.iter().map(|x| (*x,)).collect::<Vec<_>>());
+
+    let streamed: Vec<_> = TestQuery5::query_stream(&conn, &[2, 3])
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(streamed, vec![(1,), (2,)]);
+
+    // Dropping a partially consumed stream releases the connection.
+    let first: Vec<_> = TestQuery4::query_stream(&conn, &1, &300)
+        .take(10)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(first.len(), 10);
+    assert_eq!(TestQuery5::query(&conn, &[1]).await.unwrap(), vec![(0,)]);
+
+    // Other queries can run while the stream is consumed, here with more
+    // rows than sqlite reads at once.
+    let rows: Vec<i64> = (300..2500).collect();
+    for chunk in rows.chunks(500) {
+        let values: Vec<_> = chunk.iter().map(|x| (x,)).collect();
+        TestQuery3::query(&conn, &values).await.unwrap();
+    }
+    let mut stream = TestQuery4::query_stream(&conn, &1, &2500);
+    let mut streamed = Vec::new();
+    while let Some((x,)) = stream.try_next().await.unwrap() {
+        TestQuery3::query(&conn, &[(&(x + 10000),)]).await.unwrap();
+        streamed.push(x);
+    }
+    assert_eq!(streamed, (0..2500).collect::<Vec<_>>());
+}
+
+pub async fn test_from_row(conn: Connection) {
+    let res = TestQuery3::query(&conn, &[(&10,), (&20,)]).await.unwrap();
+    assert_eq!(res.affected_rows(), 2);
+    let expected = vec![
+        FooRow {
+            id: 1,
+            x: 10,
+            label: None,
+        },
+        FooRow {
+            id: 2,
+            x: 20,
+            label: None,
+        },
+    ];
+
+    assert_eq!(TestQuery18::query(&conn, &1, &2).await.unwrap(), expected);
+
+    let streamed: Vec<_> = TestQuery18::query_stream(&conn, &1, &2)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(streamed, expected);
+
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, res) = TestQuery18::query_with_transaction(transaction, &2, &2)
+        .await
+        .unwrap();
+    assert_eq!(res, expected[1..]);
+    transaction.rollback().await.unwrap();
+
+    // The query returns fewer columns than FooRow has fields.
+    assert!(TestQuery19::query(&conn).await.is_err());
+}
+
+pub async fn test_compressed(conn: Connection) {
+    for data in [b"small".to_vec(), vec![42; 1 << 16]] {
+        let res = TestQuery20::query(&conn, &Compressed(data.clone()))
+            .await
+            .unwrap();
+        let [(Compressed(read), stored)] = &res[..] else {
+            panic!("expected a single row, got {}", res.len());
+        };
+        assert_eq!(read, &data);
+        assert!(stored.len() < 1024);
+    }
+}
+
+pub async fn test_json(conn: Connection) {
+    let value = serde_json::json!({"a": 1, "b": [true, null]});
+    let json = Json::new(vec![1, 2]).unwrap();
+    let res = TestQuery38::query(&conn, &json, &value).await.unwrap();
+    assert_eq!(res, vec![(json, value, 1)]);
+}
+
+pub async fn test_sqlite_blob(conn: Connection) {
+    let Connection::Sqlite(sqlite) = &conn else {
+        panic!("expected a sqlite connection");
+    };
+    sqlite
+        .run_query(SqliteQueryType::SchemaChange, None, |con| {
+            con.execute_batch(
+                "CREATE TABLE blobs (id INTEGER PRIMARY KEY, data BLOB);
+                INSERT INTO blobs (id, data) VALUES (1, zeroblob(10000));",
+            )?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+    let data: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+
+    let mut blob = sqlite
+        .open_blob("blobs", "data", 1, false)
+        .await
+        .unwrap()
+        .with_chunk_size(777);
+    assert_eq!(blob.len(), 10000);
+    blob.write_all(&data).await.unwrap();
+    blob.flush().await.unwrap();
+    // The size of a BLOB can't change.
+    assert_eq!(blob.write(b"x").await.unwrap(), 0);
+
+    let mut blob = sqlite
+        .open_blob("blobs", "data", 1, true)
+        .await
+        .unwrap()
+        .with_chunk_size(1000);
+    let mut read = Vec::new();
+    blob.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, data);
+    assert_eq!(blob.position(), 10000);
+
+    // Writes start after the data read, not after the chunk read.
+    let mut blob = sqlite.open_blob("blobs", "data", 1, false).await.unwrap();
+    let mut start = [0; 5];
+    blob.read_exact(&mut start).await.unwrap();
+    assert_eq!(start, data[..5]);
+    blob.write_all(b"abc").await.unwrap();
+    assert_eq!(blob.position(), 8);
+    let mut blob = sqlite.open_blob("blobs", "data", 1, true).await.unwrap();
+    let mut start = [0; 10];
+    blob.read_exact(&mut start).await.unwrap();
+    assert_eq!(start[..8], [0, 1, 2, 3, 4, b'a', b'b', b'c']);
+
+    assert!(blob.write_all(b"x").await.is_err());
+    assert!(sqlite.open_blob("blobs", "data", 2, true).await.is_err());
+}
+
+pub async fn test_blob_handle(conn: Connection) {
+    let Connection::Sqlite(sqlite) = &conn else {
+        panic!("expected a sqlite connection");
+    };
+    sqlite
+        .run_query(SqliteQueryType::SchemaChange, None, |con| {
+            con.execute_batch(
+                "CREATE TABLE blob_values (id INTEGER PRIMARY KEY, data BLOB);
+                INSERT INTO blob_values (id, data) VALUES (1, NULL);",
+            )?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+    let data: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+    let chunks = |data: &[u8]| {
+        let chunks: Vec<_> = data
+            .chunks(3000)
+            .map(|chunk| Ok::<_, Error>(chunk.to_vec()))
+            .collect();
+        stream::iter(chunks)
+    };
+
+    let blob = BlobHandle::new(&conn, "blob_values", "data", "id", 1).with_chunk_size(1000);
+    assert_eq!(blob.size().await.unwrap(), 0);
+    blob.write(10000, chunks(&data)).await.unwrap();
+    assert_eq!(blob.size().await.unwrap(), 10000);
+
+    let read: Vec<Vec<u8>> = blob.read().try_collect().await.unwrap();
+    assert_eq!(read.len(), 10);
+    assert!(read.iter().all(|chunk| chunk.len() == 1000));
+    assert_eq!(read.concat(), data);
+
+    // The chunks must add up to the size given.
+    assert!(blob.write(9000, chunks(&data)).await.is_err());
+    assert!(blob.write(20000, chunks(&data)).await.is_err());
+
+    let missing = BlobHandle::new(&conn, "blob_values", "data", "id", 2);
+    assert!(missing.size().await.is_err());
+    assert!(missing.read().try_collect::<Vec<_>>().await.is_err());
+    assert!(missing.write(3, chunks(b"abc")).await.is_err());
+}
+
+pub async fn test_sqlite_session_vars(conn: Connection) {
+    let Connection::Sqlite(sqlite) = &conn else {
+        panic!("expected a sqlite connection");
+    };
+    let read_pragma = |name: &'static str| async move {
+        sqlite
+            .run_query(SqliteQueryType::Read, None, |con| {
+                let query = format!("PRAGMA {}", name);
+                Ok(con.query_row(&query, [], |row| row.get::<_, i64>(0))?)
+            })
+            .await
+            .unwrap()
+    };
+    let cache_size = read_pragma("cache_size").await;
+    // The default depends on how sqlite was built, set the other value.
+    let foreign_keys = read_pragma("foreign_keys").await;
+    let other_foreign_keys = (1 - foreign_keys).to_string();
+
+    let vars = [
+        ("foreign_keys", other_foreign_keys.as_str()),
+        ("cache_size", "100"),
+    ];
+    let values = conn
+        .with_session_vars(&vars, |_| async {
+            Ok((
+                read_pragma("foreign_keys").await,
+                read_pragma("cache_size").await,
+            ))
+        })
+        .await
+        .unwrap();
+    assert_eq!(values, (1 - foreign_keys, 100));
+    assert_eq!(read_pragma("foreign_keys").await, foreign_keys);
+    assert_eq!(read_pragma("cache_size").await, cache_size);
+
+    // The variables are restored when the closure fails too.
+    let res = conn
+        .with_session_vars(&vars, |_| async { Err::<(), _>(Error::msg("failed")) })
+        .await;
+    assert!(res.is_err());
+    assert_eq!(read_pragma("foreign_keys").await, foreign_keys);
+
+    let vars = [("x; DROP TABLE foo", "1")];
+    let res = conn.with_session_vars(&vars, |_| async { Ok(()) }).await;
+    assert!(res.is_err());
+}
+
+/// Must run on a multi-threaded runtime, to check that the queries don't
+/// block the other tasks.
+pub async fn test_sqlite_query_timeout(conn: Connection) {
+    let timeout = Duration::from_millis(100);
+
+    // The query never completes, so it is interrupted. The other tasks keep
+    // running in the meantime.
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let ticker = tokio::spawn({
+        let ticks = ticks.clone();
+        async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                ticks.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    });
+    let err = TestQuery21::query_with_timeout(&conn, timeout)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<QueryTimeout>(),
+        Some(&QueryTimeout { timeout })
+    );
+    assert!(ticks.load(Ordering::SeqCst) > 0);
+    ticker.abort();
+
+    // The connection can't be acquired while another query holds it.
+    let Connection::Sqlite(multithread_con) = &conn else {
+        panic!("expected a sqlite connection");
+    };
+    let guard = multithread_con
+        .acquire_sqlite_connection(sql::sqlite::SqliteQueryType::Read)
+        .await
+        .unwrap();
+    let err = TestQuery6::query_with_timeout(&conn, timeout)
+        .await
+        .unwrap_err();
+    assert!(err.is::<QueryTimeout>());
+    drop(guard);
+
+    // The connection was released by the queries that timed out.
+    assert_eq!(
+        TestQuery6::query_with_timeout(&conn, timeout)
+            .await
+            .unwrap(),
+        vec![(7,)]
+    );
+    let res = TestQuery3::query_with_timeout(&conn, Duration::from_secs(10), &[(&44,)])
+        .await
+        .unwrap();
+    assert_eq!(res.affected_rows(), 1);
+}
+
+pub async fn test_sqlite_query_cancellation(conn: Connection) {
+    // The query never completes, so it is interrupted once the token is
+    // cancelled. As it blocks this thread, cancel it from another one.
+    let cancellation = CancellationToken::new();
+    let cancel = std::thread::spawn({
+        let cancellation = cancellation.clone();
+        move || {
+            std::thread::sleep(Duration::from_millis(100));
+            cancellation.cancel();
+        }
+    });
+    let err = TestQuery21::query_with_cancellation(&conn, &cancellation)
+        .await
+        .unwrap_err();
+    assert!(err.is::<QueryCancelled>());
+    cancel.join().unwrap();
+
+    // Queries given a cancelled token fail right away.
+    let err = TestQuery3::query_with_cancellation(&conn, &cancellation, &[(&44,)])
+        .await
+        .unwrap_err();
+    assert!(err.is::<QueryCancelled>());
+
+    // The connection was released by the query that was cancelled.
+    let cancellation = CancellationToken::new();
+    assert_eq!(
+        TestQuery6::query_with_cancellation(&conn, &cancellation)
+            .await
+            .unwrap(),
+        vec![(7,)]
+    );
+    let res = TestQuery3::query_with_cancellation(&conn, &cancellation, &[(&44,)])
+        .await
+        .unwrap();
+    assert_eq!(res.affected_rows(), 1);
+}
+
+/// Expects a sqlite connection in WAL mode with at least two readers.
+pub async fn test_sqlite_wal_reads(conn: Connection) {
+    let timeout = Duration::from_secs(10);
+    TestQuery7::query(&conn, &1).await.unwrap();
+
+    // Reads don't wait for the transaction holding the connection for writes,
+    // and don't see its writes until it is committed.
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, _) = TestQuery7::query_with_transaction(transaction, &2)
+        .await
+        .unwrap();
+    assert_eq!(
+        TestQuery4::query_with_timeout(&conn, timeout, &1, &1)
+            .await
+            .unwrap(),
+        vec![(1,)]
+    );
+
+    // Reads use separate connections.
+    let Connection::Sqlite(multithread_con) = &conn else {
+        panic!("expected a sqlite connection");
+    };
+    let guard = multithread_con
+        .acquire_sqlite_connection(SqliteQueryType::Read)
+        .await
+        .unwrap();
+    assert_eq!(
+        TestQuery4::query_with_timeout(&conn, timeout, &1, &1)
+            .await
+            .unwrap(),
+        vec![(1,)]
+    );
+    drop(guard);
+
+    transaction.commit().await.unwrap();
+    assert_eq!(TestQuery4::query(&conn, &1, &1).await.unwrap(), vec![(2,)]);
+}
+
+/// Expects a sqlite connection without read-only connections, so that reads
+/// wait for the connection for writes too.
+pub async fn test_sqlite_write_priority(conn: Connection) {
+    let Connection::Sqlite(sqlite) = &conn else {
+        panic!("expected a sqlite connection");
+    };
+    let acquired = Arc::new(Mutex::new(Vec::new()));
+    let guard = sqlite
+        .acquire_sqlite_connection(SqliteQueryType::Write)
+        .await
+        .unwrap();
+
+    let waiters = [
+        ("first", SqliteQueryType::Write, SqlitePriority::Normal),
+        (
+            "batch",
+            SqliteQueryType::Transaction,
+            SqlitePriority::Background,
+        ),
+        ("second", SqliteQueryType::Write, SqlitePriority::Normal),
+        (
+            "request",
+            SqliteQueryType::Read,
+            SqlitePriority::Interactive,
+        ),
+    ];
+    let mut handles = Vec::new();
+    for (i, (name, query_type, priority)) in waiters.into_iter().enumerate() {
+        let sqlite = sqlite.clone().with_priority(priority);
+        let waiter = sqlite.clone();
+        let acquired = acquired.clone();
+        let runtime = tokio::runtime::Handle::current();
+        handles.push(tokio::task::spawn_blocking(move || {
+            runtime.block_on(waiter.run_query(query_type, None, |_| {
+                acquired.lock().unwrap().push(name);
+                Ok(())
+            }))
+        }));
+        // The order of the callers of the same priority is the order in which
+        // they started waiting.
+        while sqlite.write_queue_len() <= i {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    drop(guard);
+    for handle in handles {
+        handle.await.unwrap().unwrap();
+    }
+
+    assert_eq!(
+        *acquired.lock().unwrap(),
+        vec!["request", "first", "second", "batch"]
+    );
+    assert_eq!(sqlite.write_queue_len(), 0);
+    let read_waits = sqlite.read_wait_stats();
+    assert_eq!(read_waits.acquired, 1);
+    assert!(read_waits.max_wait >= Duration::from_millis(10));
+    assert!(sqlite.write_wait_stats().acquired >= 4);
+}
+
+/// Name, SQL, whether in a transaction, rows and whether it failed.
+type RecordedQuery = (String, String, bool, Option<u64>, bool);
+
+#[derive(Default)]
+struct RecordingObserver {
+    queries: Mutex<Vec<RecordedQuery>>,
+    transaction_operations: Mutex<Vec<TransactionOperation>>,
+}
+
+impl QueryObserver for RecordingObserver {
+    fn query_completed(&self, event: &QueryEvent<'_>) {
+        // Observers see the queries of the whole process, so only record the
+        // ones used by this test.
+        if ["TestQuery25", "TestQuery26", "TestQuery27"].contains(&event.name) {
+            self.queries.lock().unwrap().push((
+                event.name.to_owned(),
+                event.sql.to_owned(),
+                event.in_transaction,
+                event.rows,
+                event.error.is_some(),
+            ));
+        }
+    }
+
+    fn transaction_completed(&self, event: &TransactionEvent<'_>) {
+        self.transaction_operations
+            .lock()
+            .unwrap()
+            .push(event.operation);
+    }
+}
+
+/// Expects a sqlite connection, as it checks the SQL sent to the backend.
+pub async fn test_query_observer(conn: Connection) {
+    let observer = Arc::new(RecordingObserver::default());
+    register_query_observer(observer.clone());
+
+    TestQuery25::query(&conn, &[(&77,), (&77,)]).await.unwrap();
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, _) = TestQuery26::query_with_transaction(transaction, &77)
+        .await
+        .unwrap();
+    transaction.commit().await.unwrap();
+    assert!(TestQuery27::query(&conn).await.is_err());
+
+    assert_eq!(
+        *observer.queries.lock().unwrap(),
+        vec![
+            (
+                "TestQuery25".to_owned(),
+                TestQuery25::render(&[(&77,), (&77,)]).sqlite,
+                false,
+                Some(2),
+                false
+            ),
+            (
+                "TestQuery26".to_owned(),
+                TestQuery26::render(&77).sqlite,
+                true,
+                Some(2),
+                false
+            ),
+            (
+                "TestQuery27".to_owned(),
+                TestQuery27::render().sqlite,
+                false,
+                None,
+                true
+            ),
+        ]
+    );
+    let operations = observer.transaction_operations.lock().unwrap();
+    assert!(operations.contains(&TransactionOperation::Begin));
+    assert!(operations.contains(&TransactionOperation::Commit));
+}
+
+#[derive(Default)]
+struct SlowQueryRecorder {
+    plans: Mutex<Vec<Option<String>>>,
+}
+
+impl QueryObserver for SlowQueryRecorder {
+    fn slow_query(&self, event: &SlowQueryEvent<'_>) {
+        if event.name == "TestQuery42" {
+            assert_eq!(event.threshold, Duration::ZERO);
+            self.plans
+                .lock()
+                .unwrap()
+                .push(event.plan.map(str::to_owned));
+        }
+    }
+}
+
+/// Expects a sqlite connection, as it checks the plan explained by sqlite.
+pub async fn test_slow_query(conn: Connection) {
+    let observer = Arc::new(SlowQueryRecorder::default());
+    register_query_observer(observer.clone());
+    set_slow_query_config(Some(
+        SlowQueryConfig::new(Duration::MAX)
+            .with_query_threshold("TestQuery42", Duration::ZERO)
+            .with_explain_connection(conn.clone()),
+    ));
+
+    TestQuery42::query(&conn, &1).await.unwrap();
+    TestQuery36::query(&conn, &1).await.unwrap();
+
+    // The plan is explained in the background.
+    let mut plans = Vec::new();
+    for _ in 0..100 {
+        plans = observer.plans.lock().unwrap().clone();
+        if !plans.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    set_slow_query_config(None);
+    assert_eq!(plans.len(), 1);
+    assert!(plans[0].as_ref().unwrap().contains("foo"), "{:?}", plans);
+
+    assert!(conn.explain("SELECT * FROM missing").await.is_err());
+}
+
+pub async fn test_slow_query_log(conn: Connection) {
+    let logged = Arc::new(Mutex::new(Vec::new()));
+    let log = SlowQueryLog::new(Duration::ZERO, {
+        let logged = logged.clone();
+        move |query| {
+            assert!(query.duration > query.telemetry.threshold);
+            logged.lock().unwrap().push((
+                query.name.to_owned(),
+                query.comment.map(str::to_owned),
+                query.telemetry.rows,
+            ));
+        }
+    });
+    let slow_conn = conn.clone().with_slow_query_log(log);
+    assert!(conn.slow_query_log().is_none());
+    assert_eq!(
+        slow_conn.slow_query_log().map(SlowQueryLog::threshold),
+        Some(Duration::ZERO)
+    );
+
+    TestQuery36::query(&slow_conn, &1).await.unwrap();
+    TestQuery36::commented_query(&slow_conn, "from the test", &1)
+        .await
+        .unwrap();
+    // Queries on other connections and in transactions are not logged.
+    TestQuery36::query(&conn, &1).await.unwrap();
+    let transaction = slow_conn.start_transaction().await.unwrap();
+    let (transaction, _) = TestQuery36::query_with_transaction(transaction, &1)
+        .await
+        .unwrap();
+    transaction.rollback().await.unwrap();
+
+    let rows = TestQuery36::query(&conn, &1).await.unwrap().len() as u64;
+    assert_eq!(
+        *logged.lock().unwrap(),
+        vec![
+            ("TestQuery36".to_owned(), None, Some(rows)),
+            (
+                "TestQuery36".to_owned(),
+                Some("from the test".to_owned()),
+                Some(rows)
+            ),
+        ]
+    );
+
+    // Queries faster than the threshold are not logged.
+    let fast_conn = conn.with_slow_query_log(SlowQueryLog::new(Duration::MAX, |query| {
+        panic!("{} logged as slow", query.name)
+    }));
+    TestQuery36::query(&fast_conn, &1).await.unwrap();
+}
+
+pub async fn test_connection_metrics(conn: Connection) {
+    let labeled = conn.clone().with_metrics_label("test");
+    assert!(conn.metrics().is_none());
+    assert_eq!(
+        labeled.metrics().map(ConnectionMetrics::label),
+        Some("test")
+    );
+
+    TestQuery36::query(&labeled, &1).await.unwrap();
+    let transaction = labeled.start_transaction().await.unwrap();
+    assert_eq!(
+        transaction.metrics().map(ConnectionMetrics::label),
+        Some("test")
+    );
+    let (transaction, _) = TestQuery36::query_with_transaction(transaction, &1)
+        .await
+        .unwrap();
+    assert_eq!(
+        transaction.metrics().map(ConnectionMetrics::label),
+        Some("test")
+    );
+    transaction.commit().await.unwrap();
+
+    let transaction = conn.start_transaction().await.unwrap();
+    assert!(transaction.metrics().is_none());
+    transaction.rollback().await.unwrap();
+
+    let connections = SqlConnections::new_single(conn).with_metrics_label("db");
+    assert_eq!(
+        connections
+            .read_master_connection
+            .metrics()
+            .map(ConnectionMetrics::label),
+        Some("db.read_master")
+    );
+}
+
+pub async fn test_probe_capabilities(conn: Connection) {
+    assert!(conn.capabilities().is_none());
+    let capabilities = conn.probe_capabilities().await.unwrap();
+    assert!(capabilities.ctes, "{:?}", capabilities);
+    if let Connection::Sqlite(..) = conn {
+        assert_eq!(capabilities.kind, ServerKind::Sqlite);
+    }
+
+    let conn = conn.with_probed_capabilities().await.unwrap();
+    assert_eq!(conn.capabilities(), Some(&capabilities));
+}
+
+pub async fn test_values_with_list(conn: Connection) {
+    TestQuery41::query(&conn, &[(&10, &1), (&20, &2), (&30, &3)])
+        .await
+        .unwrap();
+
+    // Only the conflicting rows in the list are updated.
+    TestQuery43::query(&conn, &[(&1, &11), (&2, &21), (&4, &41)], &[1, 4])
+        .await
+        .unwrap();
+    for (id, x) in [(1, 11), (2, 20), (3, 30), (4, 41)] {
+        assert_eq!(TestQuery36::query(&conn, &id).await.unwrap(), vec![(x,)]);
+    }
+
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, _) =
+        TestQuery43::query_with_transaction(transaction, &[(&2, &22), (&3, &32)], &[3])
+            .await
+            .unwrap();
+    transaction.commit().await.unwrap();
+    assert_eq!(TestQuery36::query(&conn, &2).await.unwrap(), vec![(20,)]);
+    assert_eq!(TestQuery36::query(&conn, &3).await.unwrap(), vec![(32,)]);
+
+    let rendered = TestQuery43::render(&[(&5, &50)], &[5, 6]);
+    assert!(rendered.mysql.contains("IN (5, 6)"), "{}", rendered.mysql);
+    assert!(
+        rendered.sqlite.contains("IN (:ids0, :ids1)"),
+        "{}",
+        rendered.sqlite
+    );
+}
+
+pub async fn test_explain(conn: Connection) {
+    let plan = TestQuery36::explain(&conn, &1).await.unwrap();
+    assert!(plan.uses_index(PRIMARY_KEY), "{:?}", plan);
+    assert!(!plan.has_full_scan(), "{:?}", plan);
+
+    let plan = TestQuery42::explain(&conn, &1).await.unwrap();
+    assert!(plan.scans_table("foo"), "{:?}", plan);
+
+    let plan = TestQuery5::explain(&conn, &[1, 2]).await.unwrap();
+    assert!(plan.uses_index(PRIMARY_KEY), "{:?}", plan);
+
+    TestQuery3::explain(&conn, &[(&1,)]).await.unwrap();
+}
+
+pub async fn test_insert_or_update(conn: Connection) {
+    TestQuery28::query(&conn, &[(&1, &10), (&2, &20)])
+        .await
+        .unwrap();
+    TestQuery28::query(&conn, &[(&2, &21), (&3, &30)])
+        .await
+        .unwrap();
+    TestQuery29::query(&conn, &1, &11).await.unwrap();
+
+    assert_eq!(
+        TestQuery5::query(&conn, &[1, 2, 3]).await.unwrap(),
+        vec![(11,), (21,), (30,)]
+    );
+}
+
+pub async fn test_write_returning(conn: Connection) {
+    let res = TestQuery30::query(&conn, &10).await.unwrap();
+    assert_eq!(res.rows(), &[(1,)]);
+
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, res) = TestQuery30::query_with_transaction(transaction, &20)
+        .await
+        .unwrap();
+    transaction.commit().await.unwrap();
+    assert_eq!(res.into_rows(), vec![(2,)]);
+
+    let res = TestQuery31::query(&conn, &30, &[1, 2, 3]).await.unwrap();
+    assert_eq!(res.affected_rows(), 2);
+    let mut rows = res.into_rows();
+    rows.sort();
+    assert_eq!(rows, vec![(1, 30), (2, 30)]);
+}
+
+pub fn test_render() {
+    assert_eq!(
+        TestQuery9::render(&5, &[1, 2]),
+        RenderedQuery {
+            mysql: "UPDATE foo SET x = 5 WHERE id IN (1, 2)".to_owned(),
+            sqlite: "UPDATE foo SET x = :x WHERE id IN (:ids0, :ids1)".to_owned(),
+        }
+    );
+    // Lists are padded to share prepared statements on sqlite.
+    assert_eq!(
+        TestQuery9::render(&5, &[1, 2, 3]).sqlite,
+        "UPDATE foo SET x = :x WHERE id IN (:ids0, :ids1, :ids2, :ids3)"
+    );
+    assert_eq!(
+        TestQuery3::render(&[(&1,), (&2,)]),
+        RenderedQuery {
+            mysql: "INSERT INTO foo (x) VALUES (1), (2)".to_owned(),
+            sqlite: "INSERT INTO foo (x) VALUES (:x)".to_owned(),
+        }
+    );
+    assert_eq!(
+        TestQuery29::render(&1, &2),
+        RenderedQuery {
+            mysql: "INSERT INTO foo (id, x) VALUES (1, 2) ON DUPLICATE KEY UPDATE x = VALUES(x)"
+                .to_owned(),
+            sqlite:
+                "INSERT INTO foo (id, x) VALUES (:id, :x) ON CONFLICT DO UPDATE SET x = excluded.x"
+                    .to_owned(),
+        }
+    );
+    assert_eq!(
+        TestQuery12::render(&"it's".to_owned()).mysql,
+        r"SELECT x FROM foo WHERE test = 'it\'s'"
+    );
+    assert_eq!(
+        TestQuery22::render(&10, Some(&2)),
+        RenderedQuery {
+            mysql: "SELECT id FROM foo WHERE x = 10 AND id >= 2 ORDER BY id".to_owned(),
+            sqlite: "SELECT id FROM foo WHERE x = :x AND id >= :min_id ORDER BY id".to_owned(),
+        }
+    );
+    assert_eq!(
+        TestQuery22::render(&10, None).sqlite,
+        "SELECT id FROM foo WHERE x = :x  ORDER BY id"
+    );
+}
+
+pub async fn test_schema_variants(conn: Connection) {
+    assert_eq!(conn.schema_variant(), None);
+    assert_eq!(TestQuery32::query(&conn, &1).await.unwrap(), vec![(3,)]);
+    assert_eq!(TestQuery32::render(&1).mysql, "SELECT 1 + 1");
+
+    let transaction = conn.start_transaction().await.unwrap();
+    assert_eq!(transaction.schema_variant(), None);
+    let (transaction, res) = TestQuery33::query_with_transaction(transaction, &[(&5,)])
+        .await
+        .unwrap();
+    let id = res.last_insert_id().unwrap();
+    let (transaction, res) = TestQuery34::query_with_transaction(transaction, &6, &[id])
+        .await
+        .unwrap();
+    assert_eq!(res.affected_rows(), 1);
+    transaction.commit().await.unwrap();
+    assert_eq!(
+        TestQuery4::query(&conn, &id, &id).await.unwrap(),
+        vec![(6,)]
+    );
+}
+
+pub async fn test_cas_update(conn: Connection) {
+    let res = TestQuery3::query(&conn, &[(&1,)]).await.unwrap();
+    let id = res.last_insert_id().unwrap();
+    let read_x = |id| {
+        let conn = &conn;
+        move || async move {
+            let rows = TestQuery36::query(conn, &id).await?;
+            Ok(rows.into_iter().next().map(|(x,)| x))
+        }
+    };
+
+    let outcome = cas_update(TestQuery35::query(&conn, &id, &1), read_x(id)).await;
+    assert_eq!(outcome.unwrap(), CasOutcome::Updated);
+    let outcome = cas_update(TestQuery35::query(&conn, &id, &1), read_x(id)).await;
+    assert_eq!(
+        outcome.unwrap(),
+        CasOutcome::Conflict {
+            current_version: Some(2)
+        }
+    );
+    let outcome = cas_update(TestQuery35::query(&conn, &(id + 1), &1), read_x(id + 1)).await;
+    assert_eq!(
+        outcome.unwrap(),
+        CasOutcome::Conflict {
+            current_version: None
+        }
+    );
+
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, outcome) = cas_update_with_transaction(
+        transaction,
+        |transaction| TestQuery35::query_with_transaction(transaction, &id, &2),
+        |transaction| async move {
+            let (transaction, rows) = TestQuery36::query_with_transaction(transaction, &id).await?;
+            Ok((transaction, rows.into_iter().next().map(|(x,)| x)))
+        },
+    )
+    .await
+    .unwrap();
+    assert!(outcome.is_updated());
+    transaction.commit().await.unwrap();
+    assert_eq!(TestQuery36::query(&conn, &id).await.unwrap(), vec![(3,)]);
+}
+
+pub async fn test_maybe_fragments(conn: Connection) {
+    let res = TestQuery3::query(&conn, &[(&10,), (&10,), (&20,)])
+        .await
+        .unwrap();
+    assert_eq!(res.affected_rows(), 3);
+
+    assert_eq!(
+        TestQuery22::query(&conn, &10, None).await.unwrap(),
+        vec![(1,), (2,)]
+    );
+    assert_eq!(
+        TestQuery22::query(&conn, &10, Some(&2)).await.unwrap(),
+        vec![(2,)]
+    );
+    let streamed: Vec<_> = TestQuery22::query_stream(&conn, &10, Some(&2))
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(streamed, vec![(2,)]);
+
+    let res = TestQuery23::query(&conn, &30, &[1, 2, 3], Some(&10))
+        .await
+        .unwrap();
+    assert_eq!(res.affected_rows(), 2);
+    assert_eq!(
+        TestQuery22::query(&conn, &30, None).await.unwrap(),
+        vec![(1,), (2,)]
+    );
+
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, res) = TestQuery23::query_with_transaction(transaction, &40, &[2, 3], None)
+        .await
+        .unwrap();
+    assert_eq!(res.affected_rows(), 2);
+    let (transaction, res) = TestQuery22::query_with_transaction(transaction, &40, Some(&3))
+        .await
+        .unwrap();
+    assert_eq!(res, vec![(3,)]);
+    transaction.rollback().await.unwrap();
+}
+
+pub async fn test_read_paged(conn: Connection) {
+    let res = TestQuery3::query(&conn, &[(&1,), (&2,), (&1,), (&1,), (&1,)])
+        .await
+        .unwrap();
+    assert_eq!(res.affected_rows(), 5);
+
+    let page = TestQuery37::next_page(&conn, None, 2, &1).await.unwrap();
+    assert_eq!(page.rows(), &[(1, 1), (3, 1)]);
+    assert_eq!(page.next_cursor(), Some(&3));
+    let page = TestQuery37::next_page(&conn, page.next_cursor(), 2, &1)
+        .await
+        .unwrap();
+    assert_eq!(page.rows(), &[(4, 1), (5, 1)]);
+    let page = TestQuery37::next_page(&conn, page.next_cursor(), 2, &1)
+        .await
+        .unwrap();
+    assert_eq!(page.into_parts(), (vec![], None));
+
+    let page = TestQuery37::next_page(&conn, None, 2, &2).await.unwrap();
+    assert_eq!(page.into_parts(), (vec![(2, 2)], None));
+
+    let rows = TestQuery44::query(&conn, &1, &Limit::from(2), &Offset::from(1))
+        .await
+        .unwrap();
+    assert_eq!(rows, vec![(3,), (4,)]);
+    // Limits above the bound are lowered rather than bound as negative
+    // values on sqlite.
+    let rows = TestQuery44::query(&conn, &1, &Limit::from(u64::MAX), &Offset::from(3))
+        .await
+        .unwrap();
+    assert_eq!(rows, vec![(5,)]);
+}
+
+pub async fn test_sql_error(conn: Connection) {
+    TestQuery41::query(&conn, &[(&1, &1)]).await.unwrap();
+
+    let err = TestQuery41::query(&conn, &[(&2, &1)]).await.unwrap_err();
+    assert_eq!(err.kind(), SqlErrorKind::DuplicateKey);
+    assert!(err.is_duplicate_key());
+    assert!(err.backend_code().is_some());
+    assert_eq!(err.error_code(), Some(sql::codes::SQL_DUPLICATE_KEY));
+
+    let err = TestQuery27::query(&conn).await.unwrap_err();
+    assert_eq!(err.kind(), SqlErrorKind::Syntax);
+
+    // The kind survives the conversion into an anyhow error.
+    let err = Error::from(err);
+    let code = err.chain().find_map(sql::codes::classify);
+    assert_eq!(code, Some(sql::codes::SQL_SYNTAX));
+}
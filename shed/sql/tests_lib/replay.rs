@@ -0,0 +1,528 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Recording the MySQL traffic of tests run against a live server, to replay
+//! it in hermetic tests without a server.
+//!
+//! [MysqlRecorder] is a proxy between the clients and a server recording the
+//! packets they exchange, and [MysqlReplayer] a fake server answering the
+//! clients with the recorded packets. Both listen on a local port, which an
+//! [OssConnection](sql::OssConnection) connects to as to a server, e.g. with
+//! `mysql://user:password@{addr}/db`, so that the tests run the same code as
+//! with a live server.
+//!
+//! A request is answered with the responses recorded for the same request,
+//! in the order they were recorded, the last one being repeated once they
+//! are exhausted, so the tests must run the same queries with the same
+//! parameters as when they were recorded. The credentials aren't checked
+//! when replaying. As the packets are recorded as they are, TLS and
+//! compression must be disabled.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use sql::anyhow::bail;
+use sql::anyhow::Context;
+use sql::anyhow::Error;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+
+/// A MySQL packet, with its header.
+type Packet = Vec<u8>;
+
+const COM_QUIT: u8 = 0x01;
+const COM_STMT_SEND_LONG_DATA: u8 = 0x18;
+const COM_STMT_CLOSE: u8 = 0x19;
+/// MySQL error returned for the requests that weren't recorded.
+const ER_UNKNOWN_ERROR: u16 = 1105;
+
+/// The packets exchanged by clients and a MySQL server, as recorded by
+/// [MysqlRecorder].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Recording {
+    /// Packets sent by the server when a client connects.
+    greeting: Vec<Packet>,
+    /// Responses of the server to each packet of a client authenticating.
+    handshake: Vec<Vec<Packet>>,
+    /// Requests of the clients and the responses of the server, in order.
+    exchanges: Vec<(Packet, Vec<Packet>)>,
+}
+
+impl Recording {
+    /// Number of requests recorded.
+    pub fn len(&self) -> usize {
+        self.exchanges.len()
+    }
+
+    /// Whether no request was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.exchanges.is_empty()
+    }
+
+    /// Save the recording to a text file, with a line per request.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_string())
+            .with_context(|| format!("Failed to save recording to {}", path.display()))
+    }
+
+    /// Load a recording saved with [Recording::save].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let recording = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to load recording from {}", path.display()))?;
+        Self::parse(&recording)
+    }
+
+    /// Parse a recording formatted with [fmt::Display].
+    pub fn parse(recording: &str) -> Result<Self, Error> {
+        let mut parsed = Self::default();
+        for (index, line) in recording.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            let kind = words.next();
+            let packets = words
+                .map(decode)
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("Invalid packet on line {}", index + 1))?;
+            match kind {
+                None => {}
+                Some("greeting") => parsed.greeting.extend(packets),
+                Some("handshake") => parsed.handshake.push(packets),
+                Some("exchange") => {
+                    let mut packets = packets.into_iter();
+                    match packets.next() {
+                        Some(request) => parsed.exchanges.push((request, packets.collect())),
+                        None => bail!("Missing request on line {}", index + 1),
+                    }
+                }
+                Some(kind) => bail!("Unknown line {:?} on line {}", kind, index + 1),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut write_line = |kind: &str, packets: &[&Packet]| {
+            write!(f, "{}", kind)?;
+            for packet in packets {
+                write!(f, " {}", encode(packet))?;
+            }
+            writeln!(f)
+        };
+        for packet in &self.greeting {
+            write_line("greeting", &[packet])?;
+        }
+        for response in &self.handshake {
+            write_line("handshake", &response.iter().collect::<Vec<_>>())?;
+        }
+        for (request, response) in &self.exchanges {
+            let packets: Vec<_> = std::iter::once(request).chain(response).collect();
+            write_line("exchange", &packets)?;
+        }
+        Ok(())
+    }
+}
+
+fn encode(packet: &[u8]) -> String {
+    packet.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode(hex: &str) -> Result<Packet, Error> {
+    if !hex.is_ascii() || hex.len() % 2 == 1 {
+        bail!("Invalid hex string {:?}", hex);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+        .collect()
+}
+
+/// Read a packet, or `None` once the stream is closed.
+async fn read_packet(stream: &mut (impl AsyncRead + Unpin)) -> Result<Option<Packet>, Error> {
+    let mut header = [0; 4];
+    match stream.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+    let mut packet = header.to_vec();
+    packet.resize(4 + len, 0);
+    stream.read_exact(&mut packet[4..]).await?;
+    Ok(Some(packet))
+}
+
+/// Requests start a new sequence of packets, while the packets of a client
+/// authenticating continue the sequence of the greeting.
+fn is_request(packet: &[u8]) -> bool {
+    packet[3] == 0
+}
+
+/// Proxy recording the packets exchanged by the clients connecting to it and
+/// a MySQL server.
+pub struct MysqlRecorder {
+    addr: SocketAddr,
+    recording: Arc<Mutex<Recording>>,
+    listener: JoinHandle<()>,
+}
+
+impl MysqlRecorder {
+    /// Start listening on a local port, forwarding the connections to the
+    /// server at `upstream`, e.g. `localhost:3306`.
+    pub async fn start(upstream: &str) -> Result<Self, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let recording = Arc::new(Mutex::new(Recording::default()));
+        let upstream = upstream.to_owned();
+        let listener = tokio::spawn({
+            let recording = recording.clone();
+            async move {
+                while let Ok((client, _)) = listener.accept().await {
+                    tokio::spawn(record_connection(
+                        client,
+                        upstream.clone(),
+                        recording.clone(),
+                    ));
+                }
+            }
+        });
+        Ok(Self {
+            addr,
+            recording,
+            listener,
+        })
+    }
+
+    /// The address the clients connect to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The packets recorded so far.
+    pub fn recording(&self) -> Recording {
+        self.recording.lock().expect("poisoned lock").clone()
+    }
+}
+
+impl Drop for MysqlRecorder {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+/// Where the packets sent by the server on a connection are recorded.
+#[derive(Clone, Copy)]
+enum Responses {
+    Greeting,
+    Handshake(usize),
+    Exchange(usize),
+}
+
+/// The packets of a connection until it authenticates, added to the
+/// recording with its first request unless another connection already was.
+struct RecordedConnection {
+    greeting: Vec<Packet>,
+    handshake: Vec<Vec<Packet>>,
+    responses: Responses,
+}
+
+impl RecordedConnection {
+    fn request(&mut self, packet: &[u8], recording: &Mutex<Recording>) {
+        let mut recording = recording.lock().expect("poisoned lock");
+        if !is_request(packet) {
+            self.handshake.push(Vec::new());
+            self.responses = Responses::Handshake(self.handshake.len() - 1);
+            return;
+        }
+        if !matches!(self.responses, Responses::Exchange(_)) && recording.greeting.is_empty() {
+            recording.greeting = self.greeting.clone();
+            recording.handshake = self.handshake.clone();
+        }
+        recording.exchanges.push((packet.to_vec(), Vec::new()));
+        self.responses = Responses::Exchange(recording.exchanges.len() - 1);
+    }
+
+    fn response(&mut self, packet: &[u8], recording: &Mutex<Recording>) {
+        match self.responses {
+            Responses::Greeting => self.greeting.push(packet.to_vec()),
+            Responses::Handshake(index) => self.handshake[index].push(packet.to_vec()),
+            Responses::Exchange(index) => {
+                let mut recording = recording.lock().expect("poisoned lock");
+                recording.exchanges[index].1.push(packet.to_vec());
+            }
+        }
+    }
+}
+
+async fn record_connection(
+    client: TcpStream,
+    upstream: String,
+    recording: Arc<Mutex<Recording>>,
+) -> Result<(), Error> {
+    let server = TcpStream::connect(&upstream).await?;
+    let (mut client_read, mut client_write) = client.into_split();
+    let (mut server_read, mut server_write) = server.into_split();
+    let connection = Mutex::new(RecordedConnection {
+        greeting: Vec::new(),
+        handshake: Vec::new(),
+        responses: Responses::Greeting,
+    });
+
+    // The packets are recorded before they are forwarded, so that the
+    // responses to a request are recorded after it.
+    let requests = async {
+        while let Some(packet) = read_packet(&mut client_read).await? {
+            connection
+                .lock()
+                .expect("poisoned lock")
+                .request(&packet, &recording);
+            server_write.write_all(&packet).await?;
+        }
+        server_write.shutdown().await?;
+        Ok::<_, Error>(())
+    };
+    let responses = async {
+        while let Some(packet) = read_packet(&mut server_read).await? {
+            connection
+                .lock()
+                .expect("poisoned lock")
+                .response(&packet, &recording);
+            client_write.write_all(&packet).await?;
+        }
+        client_write.shutdown().await?;
+        Ok::<_, Error>(())
+    };
+    tokio::try_join!(requests, responses)?;
+    Ok(())
+}
+
+/// Fake MySQL server answering the clients connecting to it with the
+/// packets of a [Recording].
+pub struct MysqlReplayer {
+    addr: SocketAddr,
+    listener: JoinHandle<()>,
+}
+
+impl MysqlReplayer {
+    /// Start listening on a local port, replaying the recording.
+    pub async fn start(recording: Recording) -> Result<Self, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(ReplayState::new(recording)));
+        let listener = tokio::spawn(async move {
+            while let Ok((client, _)) = listener.accept().await {
+                tokio::spawn(replay_connection(client, state.clone()));
+            }
+        });
+        Ok(Self { addr, listener })
+    }
+
+    /// Same as [MysqlReplayer::start], with a recording saved with
+    /// [Recording::save].
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::start(Recording::load(path)?).await
+    }
+
+    /// The address the clients connect to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for MysqlReplayer {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+struct ReplayState {
+    greeting: Vec<Packet>,
+    handshake: Vec<Vec<Packet>>,
+    /// The responses recorded for each request, by payload, and the index
+    /// of the next one to replay.
+    responses: HashMap<Vec<u8>, (Vec<Vec<Packet>>, usize)>,
+}
+
+impl ReplayState {
+    fn new(recording: Recording) -> Self {
+        let mut responses: HashMap<_, (Vec<_>, _)> = HashMap::new();
+        for (request, response) in recording.exchanges {
+            let payload = request[4..].to_vec();
+            responses.entry(payload).or_default().0.push(response);
+        }
+        Self {
+            greeting: recording.greeting,
+            handshake: recording.handshake,
+            responses,
+        }
+    }
+
+    fn respond(&mut self, request: &[u8]) -> Vec<Packet> {
+        let payload = &request[4..];
+        match self.responses.get_mut(payload) {
+            Some((responses, next)) => {
+                let response = responses[(*next).min(responses.len() - 1)].clone();
+                *next += 1;
+                response
+            }
+            None if matches!(
+                payload.first(),
+                Some(&(COM_QUIT | COM_STMT_SEND_LONG_DATA | COM_STMT_CLOSE))
+            ) =>
+            {
+                Vec::new()
+            }
+            None => vec![not_recorded()],
+        }
+    }
+}
+
+/// An error packet answering a request that wasn't recorded.
+fn not_recorded() -> Packet {
+    let mut payload = vec![0xff];
+    payload.extend_from_slice(&ER_UNKNOWN_ERROR.to_le_bytes());
+    payload.extend_from_slice(b"#HY000No response was recorded for the request");
+    let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+    packet.push(1);
+    packet.extend(payload);
+    packet
+}
+
+async fn replay_connection(
+    mut client: TcpStream,
+    state: Arc<Mutex<ReplayState>>,
+) -> Result<(), Error> {
+    let (greeting, handshake) = {
+        let state = state.lock().expect("poisoned lock");
+        (state.greeting.clone(), state.handshake.clone())
+    };
+    for packet in greeting {
+        client.write_all(&packet).await?;
+    }
+    let mut handshake = handshake.into_iter();
+    while let Some(request) = read_packet(&mut client).await? {
+        let response = if is_request(&request) {
+            state.lock().expect("poisoned lock").respond(&request)
+        } else {
+            match handshake.next() {
+                Some(response) => response,
+                None => bail!("The client authenticated differently than recorded"),
+            }
+        };
+        for packet in response {
+            client.write_all(&packet).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicU8;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    fn packet(seq: u8, payload: &[u8]) -> Packet {
+        let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+        packet.push(seq);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// Server greeting the clients, accepting any credentials, and answering
+    /// each request with its payload followed by the number of requests
+    /// answered before.
+    async fn start_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let answered = Arc::new(AtomicU8::new(0));
+        tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                let answered = answered.clone();
+                tokio::spawn(async move {
+                    client.write_all(&packet(0, b"hello")).await?;
+                    while let Some(request) = read_packet(&mut client).await? {
+                        let response = if is_request(&request) {
+                            let mut payload = request[4..].to_vec();
+                            payload.push(answered.fetch_add(1, Ordering::SeqCst));
+                            packet(1, &payload)
+                        } else {
+                            packet(request[3] + 1, b"ok")
+                        };
+                        client.write_all(&response).await?;
+                    }
+                    Ok::<_, Error>(())
+                });
+            }
+        });
+        addr
+    }
+
+    /// Connect, authenticate and send the requests, returning the payloads
+    /// of the responses.
+    async fn run_client(addr: SocketAddr, requests: &[&[u8]]) -> Vec<Vec<u8>> {
+        let mut server = TcpStream::connect(addr).await.unwrap();
+        let greeting = read_packet(&mut server).await.unwrap().unwrap();
+        assert_eq!(&greeting[4..], b"hello");
+        server.write_all(&packet(1, b"login")).await.unwrap();
+        let ok = read_packet(&mut server).await.unwrap().unwrap();
+        assert_eq!(&ok[4..], b"ok");
+
+        let mut responses = Vec::new();
+        for request in requests {
+            server.write_all(&packet(0, request)).await.unwrap();
+            let response = read_packet(&mut server).await.unwrap().unwrap();
+            responses.push(response[4..].to_vec());
+        }
+        responses
+    }
+
+    #[tokio::test]
+    async fn test_record_replay() {
+        let server = start_server().await;
+        let recorder = MysqlRecorder::start(&server.to_string()).await.unwrap();
+        let requests: &[&[u8]] = &[b"a", b"bc", b"a"];
+        let recorded = run_client(recorder.addr(), requests).await;
+        assert_eq!(
+            recorded,
+            [b"a\x00".to_vec(), b"bc\x01".to_vec(), b"a\x02".to_vec()]
+        );
+
+        let recording = recorder.recording();
+        assert_eq!(recording.len(), 3);
+        assert_eq!(Recording::parse(&recording.to_string()).unwrap(), recording);
+
+        let replayer = MysqlReplayer::start(recording).await.unwrap();
+        assert_eq!(run_client(replayer.addr(), requests).await, recorded);
+        // The last response is repeated once they are exhausted.
+        let replayed = run_client(replayer.addr(), &[b"a", b"x"]).await;
+        assert_eq!(replayed[0], b"a\x02");
+        assert_eq!(replayed[1][0], 0xff);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(Recording::parse("greeting 0x").is_err());
+        assert!(Recording::parse("exchange").is_err());
+        assert!(Recording::parse("unknown 00").is_err());
+        assert_eq!(Recording::parse("\n").unwrap(), Recording::default());
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Explicit flushing of the stats, so that the values recorded since the last
+//! periodic aggregation aren't lost when the process exits, e.g. for
+//! short-lived jobs that exit before the first aggregation.
+//!
+//! [flush] aggregates the thread local stats of every thread, which also
+//! merges their histograms, and then runs the hooks registered with
+//! [register_flush_hook], e.g. by exporters draining their queues.
+//! [flush_on_shutdown] registers [flush] to run when `#[fbinit::main]`
+//! returns.
+
+use std::sync::Mutex;
+
+use fbinit::FacebookInit;
+
+type FlushHook = Box<dyn Fn() + Send + Sync>;
+
+static FLUSH_HOOKS: Mutex<Vec<FlushHook>> = Mutex::new(Vec::new());
+
+/// Register a hook run by every [flush] after the stats are aggregated, e.g.
+/// to drain the queue of an exporter.
+pub fn register_flush_hook(hook: impl Fn() + Send + Sync + 'static) {
+    FLUSH_HOOKS
+        .lock()
+        .expect("poisoned lock")
+        .push(Box::new(hook));
+}
+
+/// Aggregate the thread local stats of every thread, like the periodic
+/// aggregation does, and run the flush hooks, in the order they were
+/// registered.
+pub fn flush() {
+    crate::thread_local_aggregator::aggregate();
+    for hook in &*FLUSH_HOOKS.lock().expect("poisoned lock") {
+        hook();
+    }
+}
+
+/// Flush the stats when the process shuts down, see [fbinit::shutdown].
+/// Should be called once, e.g. at the start of main.
+pub fn flush_on_shutdown(fb: FacebookInit) {
+    fbinit::shutdown::register_hook(fb, flush);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::sync::LazyLock;
+    use std::time::Duration;
+
+    use perthread::ThreadMap;
+    use stats_traits::stat_types::BoxHistogram;
+    use stats_traits::stat_types::BoxLocalCounter;
+    use stats_traits::stat_types::BoxLocalHistogram;
+    use stats_traits::stat_types::BoxLocalTimeseries;
+    use stats_traits::stats_manager::AggregationType;
+    use stats_traits::stats_manager::BoxStatsManager;
+    use stats_traits::stats_manager::BucketConfig;
+    use stats_traits::stats_manager::StatsManager;
+
+    use super::*;
+    use crate::noop_stats::Noop;
+    use crate::thread_local_aggregator::create_map;
+
+    static AGGREGATED: AtomicUsize = AtomicUsize::new(0);
+    static FLUSHED: AtomicUsize = AtomicUsize::new(0);
+
+    struct CountingManager;
+
+    impl StatsManager for CountingManager {
+        fn aggregate(&self) {
+            AGGREGATED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn create_counter(&self, name: &str) -> BoxLocalCounter {
+            Noop.create_counter(name)
+        }
+
+        fn create_timeseries(
+            &self,
+            name: &str,
+            aggregation_types: &[AggregationType],
+            intervals: &[Duration],
+        ) -> BoxLocalTimeseries {
+            Noop.create_timeseries(name, aggregation_types, intervals)
+        }
+
+        fn create_histogram(
+            &self,
+            name: &str,
+            aggregation_types: &[AggregationType],
+            conf: BucketConfig,
+            percentiles: &[u8],
+        ) -> BoxLocalHistogram {
+            Noop.create_histogram(name, aggregation_types, conf, percentiles)
+        }
+
+        fn create_quantile_stat(
+            &self,
+            name: &str,
+            aggregation_types: &[AggregationType],
+            percentiles: &[f32],
+            intervals: &[Duration],
+        ) -> BoxHistogram {
+            Noop.create_quantile_stat(name, aggregation_types, percentiles, intervals)
+        }
+    }
+
+    #[test]
+    fn test_flush() {
+        static STATS_MAP: LazyLock<Arc<ThreadMap<BoxStatsManager>>> = LazyLock::new(create_map);
+        let _stats = STATS_MAP.register(Box::new(CountingManager));
+        register_flush_hook(|| {
+            // The stats are aggregated before the hooks run.
+            assert!(AGGREGATED.load(Ordering::SeqCst) > 0);
+            FLUSHED.fetch_add(1, Ordering::SeqCst);
+        });
+
+        flush();
+        assert_eq!(AGGREGATED.load(Ordering::SeqCst), 1);
+        assert_eq!(FLUSHED.load(Ordering::SeqCst), 1);
+    }
+}
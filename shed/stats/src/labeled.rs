@@ -0,0 +1,395 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Provides counters with labels, so that the dimensions of a stat don't have
+//! to be encoded in its name. Every distinct set of label values of a counter
+//! is a separate series, which can be read on its own or summed across some of
+//! the labels, and exported either in the Prometheus text format or as
+//! ODS-style flattened names.
+//!
+//! The number of label sets of a counter is bounded, values for label sets
+//! beyond the limit are accounted in a single overflow label set whose values
+//! are all [OVERFLOW_LABEL_VALUE], so that an unbounded label (e.g. a user id)
+//! can't make the memory used grow indefinitely.
+//!
+//! ```
+//! use stats::prelude::*;
+//!
+//! stats::counter!("requests", method = "GET", code = 200).increment_value(1);
+//! ```
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Write;
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+
+use stats_traits::stat_types::Counter;
+
+/// The default maximum number of label sets of a single counter.
+pub const DEFAULT_MAX_LABEL_SETS: usize = 1000;
+
+/// The value of every label of the label set in which values of a counter are
+/// accounted once it has reached the maximum number of label sets.
+pub const OVERFLOW_LABEL_VALUE: &str = "__overflow__";
+
+static GLOBAL_REGISTRY: LazyLock<LabeledRegistry> =
+    LazyLock::new(|| LabeledRegistry::new(DEFAULT_MAX_LABEL_SETS));
+
+/// Get or create the counter `name` with the given labels in the global
+/// registry, e.g. `counter!("requests", method = "GET", code = 200)`. Label
+/// values may be of any type implementing [Display](std::fmt::Display).
+#[macro_export]
+macro_rules! counter {
+    ($name:expr $(, $label:ident = $value:expr )* $(,)?) => {
+        $crate::labeled::LabeledRegistry::global().counter(
+            $name,
+            &[$( (stringify!($label), &$value as &dyn ::std::fmt::Display) ),*],
+        )
+    };
+}
+
+/// The labels of a series of a counter, sorted by label name.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LabelSet(Vec<(String, String)>);
+
+impl LabelSet {
+    /// Create a label set from pairs of label names and values.
+    pub fn new<K, V>(labels: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let mut labels: Vec<_> = labels
+            .into_iter()
+            .map(|(name, value)| (name.into(), value.into()))
+            .collect();
+        labels.sort();
+        Self(labels)
+    }
+
+    /// Return the value of the label `name`, if the set has it.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(label, _)| label == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Iterate over the pairs of label names and values, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    fn overflow(&self) -> Self {
+        Self(
+            self.0
+                .iter()
+                .map(|(name, _)| (name.clone(), OVERFLOW_LABEL_VALUE.to_owned()))
+                .collect(),
+        )
+    }
+
+    fn project(&self, names: &[&str]) -> Self {
+        Self(
+            self.0
+                .iter()
+                .filter(|(name, _)| names.contains(&name.as_str()))
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+/// Formats the label set in the Prometheus syntax, e.g. `{code="200",method="GET"}`,
+/// or as nothing if it's empty.
+impl Display for LabelSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        write!(f, "{{")?;
+        for (i, (name, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}=\"{}\"", name, escape_prometheus_value(value))?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// A series of a labeled counter, returned by [LabeledRegistry::counter] or
+/// the `counter!` macro. Cloning it is cheap and the clones share the value.
+#[derive(Clone, Debug)]
+pub struct LabeledCounter {
+    value: Arc<AtomicI64>,
+}
+
+impl LabeledCounter {
+    /// Return the current value of the series.
+    pub fn get_value(&self) -> i64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+impl Counter for LabeledCounter {
+    fn increment_value(&self, value: i64) {
+        self.value.fetch_add(value, Ordering::Relaxed);
+    }
+}
+
+/// Registry of labeled counters, bounding the number of label sets of each.
+/// Most code should use the global one through the `counter!` macro.
+pub struct LabeledRegistry {
+    max_label_sets: AtomicUsize,
+    counters: Mutex<BTreeMap<String, HashMap<LabelSet, Arc<AtomicI64>>>>,
+}
+
+impl LabeledRegistry {
+    /// Create a registry allowing at most `max_label_sets` label sets per
+    /// counter, not counting the overflow one.
+    pub fn new(max_label_sets: usize) -> Self {
+        Self {
+            max_label_sets: AtomicUsize::new(max_label_sets),
+            counters: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Return the global registry, used by the `counter!` macro.
+    pub fn global() -> &'static Self {
+        &GLOBAL_REGISTRY
+    }
+
+    /// Change the maximum number of label sets per counter. Label sets
+    /// already created are kept even if there are more than the new maximum.
+    pub fn set_max_label_sets(&self, max_label_sets: usize) {
+        self.max_label_sets.store(max_label_sets, Ordering::Relaxed);
+    }
+
+    /// Get or create the series of the counter `name` with the given labels.
+    /// If the counter already has the maximum number of label sets and this
+    /// one is new, the overflow series of the counter is returned instead.
+    pub fn counter(&self, name: &str, labels: &[(&str, &dyn Display)]) -> LabeledCounter {
+        let labels = LabelSet::new(
+            labels
+                .iter()
+                .map(|(label, value)| (*label, value.to_string())),
+        );
+        let mut counters = self.counters.lock().expect("poisoned lock");
+        let series = counters.entry(name.to_owned()).or_default();
+        let value = match series.get(&labels) {
+            Some(value) => value.clone(),
+            None => {
+                let overflow = labels.overflow();
+                let labels = if series.len() - usize::from(series.contains_key(&overflow))
+                    >= self.max_label_sets.load(Ordering::Relaxed)
+                {
+                    overflow
+                } else {
+                    labels
+                };
+                series.entry(labels).or_default().clone()
+            }
+        };
+        LabeledCounter { value }
+    }
+
+    /// Return the values of all the series of the counter `name`, sorted by
+    /// label set.
+    pub fn series(&self, name: &str) -> Vec<(LabelSet, i64)> {
+        self.aggregate_with(name, |labels| labels.clone())
+    }
+
+    /// Return the values of the counter `name` summed across all labels but
+    /// the ones in `by`, sorted by the label sets made of the labels in `by`.
+    /// For example aggregating `requests` by `["code"]` sums the values of all
+    /// the methods for each code.
+    pub fn aggregate(&self, name: &str, by: &[&str]) -> Vec<(LabelSet, i64)> {
+        self.aggregate_with(name, |labels| labels.project(by))
+    }
+
+    fn aggregate_with(
+        &self,
+        name: &str,
+        key: impl Fn(&LabelSet) -> LabelSet,
+    ) -> Vec<(LabelSet, i64)> {
+        let counters = self.counters.lock().expect("poisoned lock");
+        let mut aggregated = BTreeMap::new();
+        if let Some(series) = counters.get(name) {
+            for (labels, value) in series {
+                *aggregated.entry(key(labels)).or_default() += value.load(Ordering::Relaxed);
+            }
+        }
+        aggregated.into_iter().collect()
+    }
+
+    /// Export all the series in the Prometheus text format. Characters not
+    /// allowed in Prometheus metric names, like `.`, are replaced with `_`.
+    pub fn export_prometheus(&self) -> String {
+        let counters = self.counters.lock().expect("poisoned lock");
+        let mut out = String::new();
+        for (name, series) in counters.iter() {
+            let name = prometheus_name(name);
+            let mut series: Vec<_> = series.iter().collect();
+            series.sort_by(|a, b| a.0.cmp(b.0));
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (labels, value) in series {
+                let _ = writeln!(out, "{}{} {}", name, labels, value.load(Ordering::Relaxed));
+            }
+        }
+        out
+    }
+
+    /// Export all the series with ODS-style flattened names, made of the
+    /// counter name followed by the label names and values sorted by label
+    /// name, e.g. `requests.code.200.method.GET`. Dots in label values are
+    /// replaced with `_` so that they can't be confused with separators.
+    pub fn export_flattened(&self) -> Vec<(String, i64)> {
+        let counters = self.counters.lock().expect("poisoned lock");
+        let mut out: Vec<_> = counters
+            .iter()
+            .flat_map(|(name, series)| {
+                series.iter().map(move |(labels, value)| {
+                    let mut key = name.clone();
+                    for (label, label_value) in labels.iter() {
+                        let _ = write!(key, ".{}.{}", label, label_value.replace('.', "_"));
+                    }
+                    (key, value.load(Ordering::Relaxed))
+                })
+            })
+            .collect();
+        out.sort();
+        out
+    }
+}
+
+fn prometheus_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == ':' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert(0, '_');
+    }
+    out
+}
+
+fn escape_prometheus_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn increment(registry: &LabeledRegistry, method: &str, code: u32, value: i64) {
+        registry
+            .counter("http.requests", &[("method", &method), ("code", &code)])
+            .increment_value(value);
+    }
+
+    #[test]
+    fn test_series_and_aggregation() {
+        let registry = LabeledRegistry::new(10);
+        increment(&registry, "GET", 200, 3);
+        increment(&registry, "GET", 404, 1);
+        increment(&registry, "POST", 200, 2);
+        increment(&registry, "GET", 200, 1);
+
+        assert_eq!(
+            registry.series("http.requests"),
+            vec![
+                (LabelSet::new([("code", "200"), ("method", "GET")]), 4),
+                (LabelSet::new([("code", "200"), ("method", "POST")]), 2),
+                (LabelSet::new([("code", "404"), ("method", "GET")]), 1),
+            ]
+        );
+        assert_eq!(
+            registry.aggregate("http.requests", &["method"]),
+            vec![
+                (LabelSet::new([("method", "GET")]), 5),
+                (LabelSet::new([("method", "POST")]), 2),
+            ]
+        );
+        assert_eq!(
+            registry.aggregate("http.requests", &[]),
+            vec![(LabelSet::default(), 7)]
+        );
+        assert_eq!(registry.series("missing"), vec![]);
+    }
+
+    #[test]
+    fn test_cardinality_limit() {
+        let registry = LabeledRegistry::new(2);
+        for code in 0..5 {
+            increment(&registry, "GET", code, 1);
+        }
+        increment(&registry, "GET", 1, 1);
+
+        let series = registry.series("http.requests");
+        assert_eq!(series.len(), 3);
+        let overflow = LabelSet::new([
+            ("code", OVERFLOW_LABEL_VALUE),
+            ("method", OVERFLOW_LABEL_VALUE),
+        ]);
+        assert!(series.contains(&(overflow, 3)));
+        assert!(series.contains(&(LabelSet::new([("code", "1"), ("method", "GET")]), 2)));
+    }
+
+    #[test]
+    fn test_export() {
+        let registry = LabeledRegistry::new(10);
+        increment(&registry, "GET", 200, 3);
+        increment(&registry, "a.\"b\"", 500, 1);
+        registry.counter("0.up", &[]).increment_value(1);
+
+        assert_eq!(
+            registry.export_prometheus(),
+            "# TYPE _0_up counter\n\
+             _0_up 1\n\
+             # TYPE http_requests counter\n\
+             http_requests{code=\"200\",method=\"GET\"} 3\n\
+             http_requests{code=\"500\",method=\"a.\\\"b\\\"\"} 1\n"
+        );
+        assert_eq!(
+            registry.export_flattened(),
+            vec![
+                ("0.up".to_owned(), 1),
+                ("http.requests.code.200.method.GET".to_owned(), 3),
+                ("http.requests.code.500.method.a_\"b\"".to_owned(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_macro() {
+        let counter = crate::counter!("test.labeled.macro", kind = "a", n = 1);
+        counter.increment_value(2);
+        crate::counter!("test.labeled.macro", n = 1, kind = "a").increment_value(1);
+        assert_eq!(counter.get_value(), 3);
+    }
+}
@@ -9,13 +9,18 @@
 
 //! Provides a macro `define_stats!` for creation of stats. This crate requires the caller to
 //! schedule aggregation of stats by calling schedule_stats_aggregation and executing the returned
-//! future.
+//! future. The stats recorded since the last aggregation can be flushed with
+//! [flush()], e.g. when the process shuts down, see [flush_on_shutdown].
 
 #![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
 
+pub mod flush;
+pub mod labeled;
 pub mod macros;
 mod noop_stats;
+pub mod state_timer;
 pub mod thread_local_aggregator;
+pub mod threads;
 
 pub mod prelude {
     //! A "prelude" of `stats` crate.
@@ -49,6 +54,9 @@ use stats_traits::stat_types::BoxSingletonCounter;
 use stats_traits::stats_manager::BoxStatsManager;
 use stats_traits::stats_manager::StatsManagerFactory;
 
+pub use self::flush::flush;
+pub use self::flush::flush_on_shutdown;
+pub use self::flush::register_flush_hook;
 pub use self::thread_local_aggregator::schedule_stats_aggregation_preview;
 
 static STATS_MANAGER_FACTORY: RwLock<Option<Box<dyn StatsManagerFactory + Send + Sync>>> =
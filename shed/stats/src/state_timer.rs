@@ -0,0 +1,233 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Provides [StateTimer], which tracks the time spent by something in each of
+//! the states of an enum, e.g. the lifecycle of a connection or the phases of
+//! a job, along with the transitions between the states.
+//!
+//! The time spent in a state is exported once the state is left, or the
+//! timer dropped, as the `state_timer.<timer>.<state>.time_ms` timeseries,
+//! and each transition as the `state_timer.<timer>.<from>.<to>.transitions`
+//! timeseries, states being named by their [Display] implementation.
+//!
+//! ```
+//! use stats::state_timer::StateTimer;
+//!
+//! #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+//! enum ConnectionState {
+//!     Connecting,
+//!     Idle,
+//!     Busy,
+//! }
+//!
+//! impl std::fmt::Display for ConnectionState {
+//!     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+//!         std::fmt::Debug::fmt(self, f)
+//!     }
+//! }
+//!
+//! let mut timer = StateTimer::new("connection", ConnectionState::Connecting);
+//! timer.transition(ConnectionState::Idle);
+//! timer.transition(ConnectionState::Busy);
+//! assert_eq!(
+//!     timer.transitions(&ConnectionState::Idle, &ConnectionState::Busy),
+//!     1
+//! );
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::prelude::*;
+
+define_stats! {
+    prefix = "state_timer";
+    time_ms: dynamic_timeseries("{}.{}.time_ms", (timer: String, state: String); Sum),
+    transitions: dynamic_timeseries(
+        "{}.{}.{}.transitions",
+        (timer: String, from: String, to: String);
+        Rate,
+        Sum
+    ),
+}
+
+/// Tracks the cumulative time spent in each state and the number of
+/// transitions between states, see the [module](self) documentation.
+///
+/// The timer is always in exactly one state, so that no time goes
+/// unaccounted for between transitions.
+#[derive(Debug)]
+pub struct StateTimer<T: Eq + Hash + Display> {
+    name: String,
+    state: T,
+    entered_at: Instant,
+    time_in_state: HashMap<T, Duration>,
+    transitions: HashMap<(T, T), u64>,
+}
+
+impl<T: Clone + Eq + Hash + Display> StateTimer<T> {
+    /// Create a timer named `name` in the stats, entering `initial` now.
+    pub fn new(name: impl Into<String>, initial: T) -> Self {
+        Self::new_at(name, initial, Instant::now())
+    }
+
+    /// Create a timer named `name` in the stats, having entered `initial` at
+    /// `entered_at`, e.g. when the timer is created after the fact.
+    pub fn new_at(name: impl Into<String>, initial: T, entered_at: Instant) -> Self {
+        Self {
+            name: name.into(),
+            state: initial,
+            entered_at,
+            time_in_state: HashMap::new(),
+            transitions: HashMap::new(),
+        }
+    }
+
+    /// Return the current state.
+    pub fn state(&self) -> &T {
+        &self.state
+    }
+
+    /// Return how long the timer has been in the current state.
+    pub fn time_in_current_state(&self) -> Duration {
+        Instant::now().saturating_duration_since(self.entered_at)
+    }
+
+    /// Enter `state` now, returning how long the timer was in the previous
+    /// state, or `None` if it is already in `state`, in which case the time
+    /// keeps running for it and no transition is counted.
+    pub fn transition(&mut self, state: T) -> Option<Duration> {
+        self.transition_at(state, Instant::now())
+    }
+
+    /// Enter `state` at `now`, see [Self::transition]. An instant before the
+    /// previous transition counts as no time spent in the previous state.
+    pub fn transition_at(&mut self, state: T, now: Instant) -> Option<Duration> {
+        if state == self.state {
+            return None;
+        }
+        let elapsed = self.leave(now);
+        STATS::transitions.add_value(
+            1,
+            (self.name.clone(), self.state.to_string(), state.to_string()),
+        );
+        let previous = std::mem::replace(&mut self.state, state.clone());
+        *self.transitions.entry((previous, state)).or_default() += 1;
+        self.entered_at = now;
+        Some(elapsed)
+    }
+
+    /// Return the cumulative time spent in `state`, including the time spent
+    /// in it so far if it is the current state.
+    pub fn time_in(&self, state: &T) -> Duration {
+        let time = self.time_in_state.get(state).copied().unwrap_or_default();
+        if *state == self.state {
+            time + self.time_in_current_state()
+        } else {
+            time
+        }
+    }
+
+    /// Return the number of transitions from `from` to `to`.
+    pub fn transitions(&self, from: &T, to: &T) -> u64 {
+        self.transitions
+            .get(&(from.clone(), to.clone()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Return the total number of transitions.
+    pub fn total_transitions(&self) -> u64 {
+        self.transitions.values().sum()
+    }
+
+    /// Account the time spent in the current state up to `now`, returning it.
+    fn leave(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.entered_at);
+        *self.time_in_state.entry(self.state.clone()).or_default() += elapsed;
+        STATS::time_ms.add_value(
+            elapsed.as_millis() as i64,
+            (self.name.clone(), self.state.to_string()),
+        );
+        elapsed
+    }
+}
+
+impl<T: Eq + Hash + Display> Drop for StateTimer<T> {
+    fn drop(&mut self) {
+        // The time spent in the last state is exported, but there is nothing
+        // left to read the cumulative times from.
+        let elapsed = Instant::now().saturating_duration_since(self.entered_at);
+        STATS::time_ms.add_value(
+            elapsed.as_millis() as i64,
+            (self.name.clone(), self.state.to_string()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    enum Phase {
+        Queued,
+        Running,
+        Done,
+    }
+
+    impl Display for Phase {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            std::fmt::Debug::fmt(self, f)
+        }
+    }
+
+    #[test]
+    fn test_state_timer() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut timer = StateTimer::new_at("job", Phase::Queued, start);
+
+        assert_eq!(
+            timer.transition_at(Phase::Running, at(10)),
+            Some(Duration::from_millis(10))
+        );
+        assert_eq!(timer.transition_at(Phase::Running, at(20)), None);
+        assert_eq!(
+            timer.transition_at(Phase::Queued, at(30)),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(
+            timer.transition_at(Phase::Running, at(35)),
+            Some(Duration::from_millis(5))
+        );
+        assert_eq!(
+            timer.transition_at(Phase::Done, at(45)),
+            Some(Duration::from_millis(10))
+        );
+
+        assert_eq!(timer.state(), &Phase::Done);
+        assert_eq!(timer.time_in(&Phase::Queued), Duration::from_millis(15));
+        assert_eq!(timer.time_in(&Phase::Running), Duration::from_millis(30));
+        assert!(timer.time_in(&Phase::Done) >= timer.time_in_current_state());
+        assert_eq!(timer.transitions(&Phase::Queued, &Phase::Running), 2);
+        assert_eq!(timer.transitions(&Phase::Running, &Phase::Queued), 1);
+        assert_eq!(timer.transitions(&Phase::Queued, &Phase::Done), 0);
+        assert_eq!(timer.total_transitions(), 4);
+
+        // A transition in the past accounts no time.
+        assert_eq!(
+            timer.transition_at(Phase::Queued, at(0)),
+            Some(Duration::ZERO)
+        );
+    }
+}
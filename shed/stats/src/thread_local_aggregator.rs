@@ -73,6 +73,12 @@ impl StatsAggregator {
     }
 }
 
+/// Aggregates the stats of every ThreadMap now, rather than waiting for the
+/// periodic aggregation
+pub(crate) fn aggregate() {
+    STATS_AGGREGATOR.aggregate();
+}
+
 /// Creates the ThreadMap and registers it for periodic calls for aggregation of stats
 pub fn create_map() -> Arc<ThreadMap<BoxStatsManager>> {
     let map = ThreadMap::default();
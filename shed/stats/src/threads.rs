@@ -0,0 +1,153 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Exports the threads registered with [perthread::registry] as gauges, for
+//! admin endpoints to diagnose runaway helper threads: the number of live
+//! registered threads, and the age and CPU time of each of them, in the same
+//! formats as the [labeled](crate::labeled) counters.
+
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use perthread::registry::registered_threads;
+use perthread::registry::ThreadSnapshot;
+
+use crate::labeled::LabelSet;
+
+const UNNAMED: &str = "unnamed";
+
+/// A gauge of a single registered thread.
+struct ThreadGauge {
+    name: &'static str,
+    value: fn(&ThreadSnapshot, SystemTime) -> Option<i64>,
+}
+
+const THREAD_GAUGES: &[ThreadGauge] = &[
+    ThreadGauge {
+        name: "age_s",
+        value: |thread, now| {
+            let age = now.duration_since(thread.registered_at).unwrap_or_default();
+            Some(age.as_secs() as i64)
+        },
+    },
+    ThreadGauge {
+        name: "cpu_time_ms",
+        value: |thread, _now| Some(thread.cpu_time?.as_millis() as i64),
+    },
+];
+
+/// The id of the thread used in the exported stats, preferring the id given
+/// by the operating system as it matches the one shown by tools like `top`.
+fn thread_id(thread: &ThreadSnapshot) -> String {
+    match thread.os_id {
+        Some(os_id) => os_id.to_string(),
+        None => format!("{:?}", thread.id)
+            .trim_start_matches("ThreadId(")
+            .trim_end_matches(')')
+            .to_owned(),
+    }
+}
+
+fn sorted_threads() -> Vec<ThreadSnapshot> {
+    let mut threads = registered_threads();
+    threads.sort_by(|a, b| (&a.name, a.registered_at).cmp(&(&b.name, b.registered_at)));
+    threads
+}
+
+/// Export the registered threads in the Prometheus text format, as the
+/// `threads_registered` gauge and the `thread_age_s` and `thread_cpu_time_ms`
+/// gauges labeled with the name and id of each thread.
+pub fn export_prometheus() -> String {
+    let threads = sorted_threads();
+    let now = SystemTime::now();
+    let mut out = String::new();
+    let _ = writeln!(out, "# TYPE threads_registered gauge");
+    let _ = writeln!(out, "threads_registered {}", threads.len());
+    for gauge in THREAD_GAUGES {
+        let _ = writeln!(out, "# TYPE thread_{} gauge", gauge.name);
+        for thread in &threads {
+            if let Some(value) = (gauge.value)(thread, now) {
+                let labels = LabelSet::new([
+                    ("name", thread.name.as_deref().unwrap_or(UNNAMED).to_owned()),
+                    ("id", thread_id(thread)),
+                ]);
+                let _ = writeln!(out, "thread_{}{} {}", gauge.name, labels, value);
+            }
+        }
+    }
+    out
+}
+
+/// Export the registered threads with ODS-style flattened names, as
+/// `threads.registered` and e.g. `threads.<name>.<id>.cpu_time_ms`. Dots in
+/// thread names are replaced with `_`.
+pub fn export_flattened() -> Vec<(String, i64)> {
+    let threads = sorted_threads();
+    let now = SystemTime::now();
+    let mut out = vec![("threads.registered".to_owned(), threads.len() as i64)];
+    for thread in &threads {
+        let name = thread.name.as_deref().unwrap_or(UNNAMED).replace('.', "_");
+        let id = thread_id(thread);
+        for gauge in THREAD_GAUGES {
+            if let Some(value) = (gauge.value)(thread, now) {
+                out.push((format!("threads.{}.{}.{}", name, id, gauge.name), value));
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::sync_channel;
+
+    use perthread::registry::register_current_thread;
+
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        let (registered_sender, registered_receiver) = sync_channel(0);
+        let (exit_sender, exit_receiver) = sync_channel(0);
+        let thread = std::thread::Builder::new()
+            .name("stats.test".to_owned())
+            .spawn(move || {
+                register_current_thread();
+                registered_sender.send(()).unwrap();
+                exit_receiver.recv().unwrap();
+            })
+            .unwrap();
+        registered_receiver.recv().unwrap();
+
+        let snapshot = registered_threads()
+            .into_iter()
+            .find(|thread| thread.name.as_deref() == Some("stats.test"))
+            .unwrap();
+        let id = thread_id(&snapshot);
+
+        let prometheus = export_prometheus();
+        assert!(prometheus.contains("# TYPE threads_registered gauge\n"));
+        assert!(prometheus.contains(&format!(
+            "thread_age_s{{id=\"{}\",name=\"stats.test\"}} 0\n",
+            id
+        )));
+
+        let flattened = export_flattened();
+        assert!(flattened[0].0 == "threads.registered" && flattened[0].1 >= 1);
+        assert!(flattened.contains(&(format!("threads.stats_test.{}.age_s", id), 0)));
+        if cfg!(target_os = "linux") {
+            let cpu_time = format!("threads.stats_test.{}.cpu_time_ms", id);
+            assert!(flattened.iter().any(|(key, _)| *key == cpu_time));
+        }
+
+        exit_sender.send(()).unwrap();
+        thread.join().unwrap();
+        assert!(!export_prometheus().contains("stats.test"));
+    }
+}
@@ -66,6 +66,10 @@ impl fmt::Display for GenContext {
     }
 }
 
+/// Callback rewriting the content of a generated file, given its path
+/// relative to the output dir.
+type PostProcessor = Box<dyn Fn(&Path, String) -> Result<String>>;
+
 /// Builder for thrift compiler wrapper.
 pub struct Config {
     thrift_bin: Option<OsString>,
@@ -78,6 +82,8 @@ pub struct Config {
     options: Option<String>,
     include_srcs: Vec<PathBuf>,
     extra_srcs: Vec<PathBuf>,
+    rustfmt: bool,
+    post_processors: Vec<PostProcessor>,
 }
 
 impl Config {
@@ -98,6 +104,8 @@ impl Config {
             options: None,
             include_srcs: vec![],
             extra_srcs: vec![],
+            rustfmt: false,
+            post_processors: vec![],
         })
     }
 
@@ -174,6 +182,27 @@ impl Config {
         self
     }
 
+    /// Set whether the generated `lib.rs` and `mod.rs` files are formatted
+    /// with rustfmt, after any post-processing. The rustfmt binary is taken
+    /// from the RUSTFMT environment variable if set, or from the PATH.
+    pub fn rustfmt(&mut self, value: bool) -> &mut Self {
+        self.rustfmt = value;
+        self
+    }
+
+    /// Add a callback rewriting the content of the generated `lib.rs` and
+    /// `mod.rs` files, e.g. to add attributes or extra items to them. It is
+    /// called with the path of each file relative to the output dir and its
+    /// content, and returns the new content. Callbacks are called in the
+    /// order they were added.
+    pub fn post_process(
+        &mut self,
+        value: impl Fn(&Path, String) -> Result<String> + 'static,
+    ) -> &mut Self {
+        self.post_processors.push(Box::new(value));
+        self
+    }
+
     /// Transform a relative path so leading "../"'s are replaced with "_t".
     pub fn remap_to_out_dir(&self, path: &Path) -> PathBuf {
         let mut rem = path;
@@ -276,6 +305,8 @@ impl Config {
                     fs::rename(out.join("mock.rs"), out.join("lib.rs"))?;
                 }
             }
+
+            self.post_process_generated(&[PathBuf::from("lib.rs")])?;
         } else {
             match self.gen_context {
                 GenContext::Types => {
@@ -360,6 +391,59 @@ impl Config {
                     .join("\n")
             );
             fs::write(out.join("lib.rs"), lib)?;
+
+            let generated = input
+                .iter()
+                .map(|(name, _file)| Path::new(name).join("mod.rs"))
+                .chain([PathBuf::from("lib.rs")])
+                .collect::<Vec<_>>();
+            self.post_process_generated(&generated)?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply the post-processing callbacks and rustfmt, if enabled, to the
+    /// given generated files, relative to the output dir.
+    fn post_process_generated(&self, files: &[PathBuf]) -> Result<()> {
+        let out = &self.out_dir;
+
+        if !self.post_processors.is_empty() {
+            for file in files {
+                let path = out.join(file);
+                let mut content = fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                for post_processor in &self.post_processors {
+                    content = post_processor(file, content)
+                        .with_context(|| format!("Failed to post-process {}", path.display()))?;
+                }
+                fs::write(&path, content)?;
+            }
+        }
+
+        if self.rustfmt {
+            println!("cargo:rerun-if-env-changed=RUSTFMT");
+            let rustfmt_bin = env::var_os("RUSTFMT").unwrap_or_else(|| "rustfmt".into());
+
+            let mut cmd = Command::new(&rustfmt_bin);
+            cmd.arg("--edition").arg("2021");
+            cmd.args(files.iter().map(|file| out.join(file)));
+
+            let output = cmd.output().with_context(|| {
+                format!(
+                    "Failed to run rustfmt. Is '{}' executable?",
+                    rustfmt_bin.to_string_lossy()
+                )
+            })?;
+            ensure!(
+                output.status.success(),
+                format!(
+                    "Command '{:#?}' failed! Stdout:\n{}\nStderr:\n{}",
+                    cmd,
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr),
+                )
+            );
         }
 
         Ok(())
@@ -30,6 +30,10 @@ struct Compiler {
     #[arg(long = "context", short = 'g', default_value_t = GenContext::Types)]
     gen_context: GenContext,
 
+    /// Format the generated files with rustfmt
+    #[arg(long)]
+    rustfmt: bool,
+
     /// Paths to .thrift files
     input: Vec<PathBuf>,
 }
@@ -38,10 +42,11 @@ fn main() -> Result<()> {
     let args = Compiler::parse();
 
     let out = args.out.map_or_else(env::current_dir, Result::Ok)?;
-    let compiler = if args.use_environment {
+    let mut compiler = if args.use_environment {
         Config::from_env(args.gen_context)?
     } else {
         Config::new(args.gen_context, None, out)?
     };
+    compiler.rustfmt(args.rustfmt);
     compiler.run(args.input)
 }